@@ -8,7 +8,8 @@ export RUSTUP_HOME=/ci/cache/rustup
 export CARGO_HOME=/ci/cache/cargo
 export CARGO_TARGET_DIR=/ci/cache/target
 
-cargo test --manifest-path ./embassy-futures/Cargo.toml
+cargo test --manifest-path ./embassy-executor/Cargo.toml --features alloc
+cargo test --manifest-path ./embassy-futures/Cargo.toml --features future-set
 cargo test --manifest-path ./embassy-sync/Cargo.toml
 cargo test --manifest-path ./embassy-embedded-hal/Cargo.toml
 cargo test --manifest-path ./embassy-hal-internal/Cargo.toml
@@ -18,6 +19,14 @@ cargo test --manifest-path ./embassy-time-driver/Cargo.toml
 cargo test --manifest-path ./embassy-boot/Cargo.toml
 cargo test --manifest-path ./embassy-boot/Cargo.toml --features ed25519-dalek
 cargo test --manifest-path ./embassy-boot/Cargo.toml --features ed25519-salty
+cargo test --manifest-path ./embassy-boot/Cargo.toml --features encryption
+cargo test --manifest-path ./embassy-boot/Cargo.toml --features encryption,ed25519-dalek
+cargo test --manifest-path ./embassy-boot/Cargo.toml --features metadata
+cargo test --manifest-path ./embassy-boot-tool/Cargo.toml
+cargo test --manifest-path ./embassy-boot-tool/Cargo.toml --no-default-features --features ed25519-salty
+cargo test --manifest-path ./embassy-boot-net/Cargo.toml --features coap
+cargo test --manifest-path ./embassy-boot-net/Cargo.toml --features coap,ed25519-dalek
+cargo test --manifest-path ./embassy-usb-dfu/Cargo.toml --features dfu
 
 cargo test --manifest-path ./embassy-nrf/Cargo.toml --no-default-features --features nrf52840,time-driver-rtc1,gpiote
 
@@ -26,5 +35,7 @@ cargo test --manifest-path ./embassy-rp/Cargo.toml --no-default-features --featu
 cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --features stm32f429vg,exti,time-driver-any,exti
 cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --features stm32f732ze,exti,time-driver-any,exti
 cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --features stm32f769ni,exti,time-driver-any,exti
+cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --features stm32wl55jc-cm4,exti,time-driver-any
 
+cargo test --manifest-path ./embassy-net/Cargo.toml --features sntp,proto-ipv4,medium-ip
 cargo test --manifest-path ./embassy-net-adin1110/Cargo.toml
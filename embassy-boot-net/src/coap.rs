@@ -0,0 +1,743 @@
+//! Firmware downloads over CoAP.
+//!
+//! Images are downloaded with block-wise transfers (RFC 7959), using confirmable `GET` requests
+//! with a `Block2` option. Requests are retransmitted with an exponential back-off as specified by
+//! RFC 7252, and a download resumes at the block following the last one received.
+use embassy_boot::FirmwareUpdater;
+use embassy_net::udp::{RecvError, UdpSocket};
+use embassy_net::IpEndpoint;
+use embassy_time::{with_deadline, Duration, Instant};
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::{BufSink, Error, ImageWriter, Sink};
+
+/// Number of retransmissions of a request before it times out.
+const MAX_RETRANSMIT: u32 = 4;
+/// Maximum length of a request.
+const REQUEST_LEN: usize = 128;
+/// Maximum length of the header and options of a response.
+const HEAD_LEN: usize = 64;
+/// Largest block size of block-wise transfers.
+const MAX_BLOCK_SIZE: usize = 1024;
+
+const VERSION: u8 = 1;
+const TYPE_CON: u8 = 0;
+const TYPE_ACK: u8 = 2;
+const TYPE_RST: u8 = 3;
+const CODE_EMPTY: u8 = 0x00;
+const CODE_GET: u8 = 0x01;
+const CODE_CONTENT: u8 = 0x45;
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_BLOCK2: u16 = 23;
+const PAYLOAD_MARKER: u8 = 0xFF;
+
+/// Transport of CoAP messages.
+///
+/// Implement this trait to use another transport than plain UDP, such as DTLS.
+pub trait Datagram {
+    /// Send a datagram to the server.
+    async fn send(&mut self, data: &[u8]) -> Result<(), Error>;
+
+    /// Receive a datagram from the server, and return its length.
+    async fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+}
+
+/// Plain UDP datagrams exchanged with a server.
+pub struct UdpDatagram<'a> {
+    socket: UdpSocket<'a>,
+    remote: IpEndpoint,
+}
+
+impl<'a> UdpDatagram<'a> {
+    /// Exchange datagrams with the `remote` endpoint, using a bound `socket`.
+    ///
+    /// Datagrams from other endpoints are ignored.
+    pub fn new<T: Into<IpEndpoint>>(socket: UdpSocket<'a>, remote: T) -> Self {
+        Self {
+            socket,
+            remote: remote.into(),
+        }
+    }
+}
+
+impl Datagram for UdpDatagram<'_> {
+    async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.socket.send_to(data, self.remote).await.map_err(|e| {
+            warn!("send error: {:?}", e);
+            Error::Network
+        })
+    }
+
+    async fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            match self.socket.recv_from(buf).await {
+                Ok((n, meta)) if meta.endpoint == self.remote => return Ok(n),
+                Ok(_) => {}
+                Err(RecvError::Truncated) => warn!("datagram too large"),
+            }
+        }
+    }
+}
+
+/// CoAP client downloading firmware images.
+pub struct CoapClient<T: Datagram> {
+    transport: T,
+    message_id: u16,
+    token: u32,
+    block_size: usize,
+    ack_timeout: Duration,
+}
+
+impl<T: Datagram> CoapClient<T> {
+    /// Create a client sending requests with the given transport.
+    ///
+    /// The `random_seed` is used for the first message ID and token, which should differ
+    /// across reboots.
+    pub fn new(transport: T, random_seed: u64) -> Self {
+        Self {
+            transport,
+            message_id: random_seed as u16,
+            token: (random_seed >> 16) as u32,
+            block_size: MAX_BLOCK_SIZE,
+            ack_timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Set the block size requested from the server, 1024 bytes by default.
+    ///
+    /// The size must be a power of two from 16 to 1024 bytes. The server may choose a smaller size.
+    pub fn set_block_size(&mut self, block_size: usize) {
+        assert!(block_size.is_power_of_two() && (16..=MAX_BLOCK_SIZE).contains(&block_size));
+        self.block_size = block_size;
+    }
+
+    /// Set the time to wait for a response before the first retransmission, 2 seconds by default.
+    ///
+    /// The time doubles with each retransmission.
+    pub fn set_ack_timeout(&mut self, timeout: Duration) {
+        self.ack_timeout = timeout;
+    }
+
+    /// Download the image at `path`, such as `fw/app.bin`.
+    ///
+    /// The download starts at the offset of `image`, so that calling this again after an error
+    /// resumes it.
+    pub async fn download<DFU: NorFlash, STATE: NorFlash>(
+        &mut self,
+        path: &str,
+        image: &mut ImageWriter<'_, '_, DFU, STATE>,
+    ) -> Result<(), Error> {
+        self.fetch(path, image).await
+    }
+
+    /// Download a small resource, such as a signature, and return its length.
+    pub async fn get(&mut self, path: &str, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut sink = BufSink { buf, len: 0 };
+        self.fetch(path, &mut sink).await?;
+        Ok(sink.len)
+    }
+
+    /// Download the image at `image_path` and its signature at `signature_path`, and mark the
+    /// image to be swapped on next boot if it is valid.
+    ///
+    /// The image is written through the `buffer`, see [`ImageWriter::new`].
+    #[cfg(feature = "_verify")]
+    pub async fn update<DFU: NorFlash, STATE: NorFlash>(
+        &mut self,
+        image_path: &str,
+        signature_path: &str,
+        public_key: &[u8; 32],
+        updater: &mut FirmwareUpdater<'_, DFU, STATE>,
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let mut signature = [0; 64];
+        if self.get(signature_path, &mut signature).await? != signature.len() {
+            return Err(Error::Protocol);
+        }
+
+        let mut image = ImageWriter::new(updater, buffer);
+        self.download(image_path, &mut image).await?;
+        image.verify_and_mark_updated(public_key, &signature).await
+    }
+
+    /// Download the image at `image_path`, and mark it to be swapped on next boot.
+    ///
+    /// The image is written through the `buffer`, see [`ImageWriter::new`].
+    #[cfg(not(feature = "_verify"))]
+    pub async fn update<DFU: NorFlash, STATE: NorFlash>(
+        &mut self,
+        image_path: &str,
+        updater: &mut FirmwareUpdater<'_, DFU, STATE>,
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let mut image = ImageWriter::new(updater, buffer);
+        self.download(image_path, &mut image).await?;
+        image.mark_updated().await
+    }
+
+    async fn fetch<S: Sink>(&mut self, path: &str, sink: &mut S) -> Result<(), Error> {
+        let mut buf = [0; HEAD_LEN + MAX_BLOCK_SIZE];
+        let mut size = self.block_size;
+        loop {
+            let offset = sink.offset();
+            // Resume with smaller blocks if the server chose a smaller size than requested.
+            while offset & (size - 1) != 0 {
+                size /= 2;
+            }
+            if size < 16 {
+                return Err(Error::Protocol);
+            }
+
+            let block = Block::new((offset / size) as u32, false, size);
+            let len = self.request(path, block, &mut buf).await?;
+            let response = Message::parse(&buf[..len])?;
+            if response.code != CODE_CONTENT {
+                return Err(Error::Status(status(response.code)));
+            }
+
+            let Some(block) = response.block2()? else {
+                // The server sent the whole resource in one response.
+                if offset != 0 {
+                    return Err(Error::Protocol);
+                }
+                return sink.write(response.payload).await;
+            };
+            if block.size() > size
+                || block.num as usize * block.size() != offset
+                || (block.more && response.payload.len() != block.size())
+            {
+                return Err(Error::Protocol);
+            }
+            sink.write(response.payload).await?;
+            if !block.more {
+                return Ok(());
+            }
+            size = block.size();
+        }
+    }
+
+    /// Send a request for a block of `path`, and return the length of the response in `buf`.
+    async fn request(&mut self, path: &str, block: Block, buf: &mut [u8]) -> Result<usize, Error> {
+        self.message_id = self.message_id.wrapping_add(1);
+        self.token = self.token.wrapping_add(1);
+        let id = self.message_id;
+        let token = self.token.to_be_bytes();
+
+        let mut request = [0; REQUEST_LEN];
+        let request_len = encode_request(&mut request, id, &token, path, block)?;
+
+        let mut timeout = self.ack_timeout;
+        // Whether the server acknowledged the request, and will send a separate response.
+        let mut acked = false;
+        for _ in 0..=MAX_RETRANSMIT {
+            if !acked {
+                self.transport.send(&request[..request_len]).await?;
+            }
+            let deadline = Instant::now() + timeout;
+            timeout *= 2;
+
+            while let Ok(len) = with_deadline(deadline, self.transport.recv(buf)).await {
+                let len = len?;
+                let Ok(response) = Message::parse(&buf[..len]) else {
+                    continue;
+                };
+                match response.kind {
+                    TYPE_ACK | TYPE_RST if response.id != id => continue,
+                    TYPE_RST => return Err(Error::Protocol),
+                    TYPE_ACK if response.code == CODE_EMPTY => {
+                        acked = true;
+                        continue;
+                    }
+                    _ if response.code == CODE_EMPTY || response.token != token => continue,
+                    _ => {}
+                }
+                let (kind, response_id) = (response.kind, response.id);
+                if kind == TYPE_CON {
+                    self.transport.send(&encode_ack(response_id)).await?;
+                }
+                return Ok(len);
+            }
+        }
+        Err(Error::Timeout)
+    }
+}
+
+/// Status of a response code, such as 404 for 4.04.
+fn status(code: u8) -> u16 {
+    (code >> 5) as u16 * 100 + (code & 0x1F) as u16
+}
+
+/// Value of a `Block1` or `Block2` option.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Block {
+    num: u32,
+    more: bool,
+    szx: u8,
+}
+
+impl Block {
+    fn new(num: u32, more: bool, size: usize) -> Self {
+        Self {
+            num,
+            more,
+            szx: (size.trailing_zeros() - 4) as u8,
+        }
+    }
+
+    fn size(&self) -> usize {
+        16 << self.szx
+    }
+
+    fn decode(value: &[u8]) -> Result<Self, Error> {
+        if value.len() > 3 {
+            return Err(Error::Protocol);
+        }
+        let value = value.iter().fold(0u32, |acc, byte| acc << 8 | *byte as u32);
+        let szx = (value & 0x7) as u8;
+        // Size exponent 7 is reserved.
+        if szx == 7 {
+            return Err(Error::Protocol);
+        }
+        Ok(Self {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx,
+        })
+    }
+
+    fn encode(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+}
+
+/// CoAP message.
+#[derive(Debug)]
+struct Message<'a> {
+    kind: u8,
+    code: u8,
+    id: u16,
+    token: &'a [u8],
+    options: &'a [u8],
+    payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let (header, rest) = data.split_first_chunk::<4>().ok_or(Error::Protocol)?;
+        let token_len = (header[0] & 0xF) as usize;
+        if header[0] >> 6 != VERSION || token_len > 8 || rest.len() < token_len {
+            return Err(Error::Protocol);
+        }
+        let (token, rest) = rest.split_at(token_len);
+
+        // Find the end of the options, which also validates them.
+        let mut options = Options::new(rest);
+        for option in &mut options {
+            option?;
+        }
+        let options_len = rest.len() - options.data.len();
+        let payload = match options.data {
+            [PAYLOAD_MARKER, payload @ ..] => payload,
+            _ => &[],
+        };
+        Ok(Self {
+            kind: (header[0] >> 4) & 0x3,
+            code: header[1],
+            id: u16::from_be_bytes([header[2], header[3]]),
+            token,
+            options: &rest[..options_len],
+            payload,
+        })
+    }
+
+    fn block2(&self) -> Result<Option<Block>, Error> {
+        for option in Options::new(self.options) {
+            let (number, value) = option?;
+            if number == OPTION_BLOCK2 {
+                return Block::decode(value).map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Iterator over encoded options, returning their number and value.
+struct Options<'a> {
+    data: &'a [u8],
+    number: u16,
+}
+
+impl<'a> Options<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, number: 0 }
+    }
+
+    fn parse_option(&mut self) -> Result<(u16, &'a [u8]), Error> {
+        let (&first, mut rest) = self.data.split_first().ok_or(Error::Protocol)?;
+        let delta = extended(first >> 4, &mut rest)?;
+        let len = extended(first & 0xF, &mut rest)? as usize;
+        self.number = self.number.checked_add(delta).ok_or(Error::Protocol)?;
+        if rest.len() < len {
+            return Err(Error::Protocol);
+        }
+        let (value, rest) = rest.split_at(len);
+        self.data = rest;
+        Ok((self.number, value))
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = Result<(u16, &'a [u8]), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if matches!(self.data, [] | [PAYLOAD_MARKER, ..]) {
+            return None;
+        }
+        let result = self.parse_option();
+        if result.is_err() {
+            self.data = &[];
+        }
+        Some(result)
+    }
+}
+
+/// Decode the extended delta or length of an option.
+fn extended(nibble: u8, rest: &mut &[u8]) -> Result<u16, Error> {
+    match nibble {
+        0..=12 => Ok(nibble as u16),
+        13 => {
+            let (byte, tail) = rest.split_first().ok_or(Error::Protocol)?;
+            *rest = tail;
+            Ok(*byte as u16 + 13)
+        }
+        14 => {
+            let (bytes, tail) = rest.split_first_chunk::<2>().ok_or(Error::Protocol)?;
+            *rest = tail;
+            u16::from_be_bytes(*bytes).checked_add(269).ok_or(Error::Protocol)
+        }
+        _ => Err(Error::Protocol),
+    }
+}
+
+/// Encode a confirmable `GET` request for a block of `path`, and return its length.
+fn encode_request(buf: &mut [u8], id: u16, token: &[u8; 4], path: &str, block: Block) -> Result<usize, Error> {
+    let mut writer = Writer { buf, pos: 0 };
+    writer.push(&[VERSION << 6 | TYPE_CON << 4 | token.len() as u8, CODE_GET])?;
+    writer.push(&id.to_be_bytes())?;
+    writer.push(token)?;
+
+    let mut number = 0;
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        writer.option(OPTION_URI_PATH - number, segment.as_bytes())?;
+        number = OPTION_URI_PATH;
+    }
+    // Option values are sent without leading zeros, and block values have at most 3 bytes.
+    let value = block.encode().to_be_bytes();
+    let zeros = value.iter().take_while(|byte| **byte == 0).count();
+    if zeros == 0 {
+        return Err(Error::TooLarge);
+    }
+    writer.option(OPTION_BLOCK2 - number, &value[zeros..])?;
+    Ok(writer.pos)
+}
+
+/// Encode an empty acknowledgement.
+fn encode_ack(id: u16) -> [u8; 4] {
+    let id = id.to_be_bytes();
+    [VERSION << 6 | TYPE_ACK << 4, CODE_EMPTY, id[0], id[1]]
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn push(&mut self, data: &[u8]) -> Result<(), Error> {
+        let dest = self
+            .buf
+            .get_mut(self.pos..self.pos + data.len())
+            .ok_or(Error::TooLarge)?;
+        dest.copy_from_slice(data);
+        self.pos += data.len();
+        Ok(())
+    }
+
+    fn option(&mut self, delta: u16, value: &[u8]) -> Result<(), Error> {
+        let len = u16::try_from(value.len()).map_err(|_| Error::TooLarge)?;
+        let mut header = [0; 5];
+        let mut header_len = 1;
+        let mut nibble = |value: u16| match value {
+            0..=12 => value as u8,
+            13..=268 => {
+                header[header_len] = (value - 13) as u8;
+                header_len += 1;
+                13
+            }
+            _ => {
+                header[header_len..header_len + 2].copy_from_slice(&(value - 269).to_be_bytes());
+                header_len += 2;
+                14
+            }
+        };
+        let first = nibble(delta) << 4 | nibble(len);
+        header[0] = first;
+        self.push(&header[..header_len])?;
+        self.push(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use embassy_boot::{FirmwareUpdaterConfig, State};
+    use embassy_embedded_hal::flash::sim_flash::{self, SimFlash};
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// CoAP server serving `resources`, which loses the requests listed in `lost`.
+    struct Server<'a> {
+        resources: &'a [(&'a str, &'a [u8])],
+        max_block_size: usize,
+        lost: &'a [usize],
+        separate: bool,
+        requests: usize,
+        responses: VecDeque<Vec<u8>>,
+        message_id: u16,
+    }
+
+    impl<'a> Server<'a> {
+        fn new(resources: &'a [(&'a str, &'a [u8])]) -> Self {
+            Self {
+                resources,
+                max_block_size: MAX_BLOCK_SIZE,
+                lost: &[],
+                separate: false,
+                requests: 0,
+                responses: VecDeque::new(),
+                message_id: 0,
+            }
+        }
+    }
+
+    fn encode_response(kind: u8, id: u16, token: &[u8], code: u8, block: Option<Block>, payload: &[u8]) -> Vec<u8> {
+        let mut buf = [0; HEAD_LEN + MAX_BLOCK_SIZE];
+        let mut writer = Writer { buf: &mut buf, pos: 0 };
+        writer
+            .push(&[VERSION << 6 | kind << 4 | token.len() as u8, code])
+            .unwrap();
+        writer.push(&id.to_be_bytes()).unwrap();
+        writer.push(token).unwrap();
+        if let Some(block) = block {
+            let value = block.encode().to_be_bytes();
+            writer.option(OPTION_BLOCK2, &value[1..]).unwrap();
+        }
+        if !payload.is_empty() {
+            writer.push(&[PAYLOAD_MARKER]).unwrap();
+            writer.push(payload).unwrap();
+        }
+        let len = writer.pos;
+        buf[..len].to_vec()
+    }
+
+    impl Datagram for Server<'_> {
+        async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+            let request = Message::parse(data).unwrap();
+            if request.kind == TYPE_ACK {
+                return Ok(());
+            }
+            self.requests += 1;
+            if self.lost.contains(&(self.requests - 1)) {
+                return Ok(());
+            }
+
+            let path: Vec<_> = Options::new(request.options)
+                .map(Result::unwrap)
+                .filter(|(number, _)| *number == OPTION_URI_PATH)
+                .map(|(_, value)| core::str::from_utf8(value).unwrap())
+                .collect();
+            let (code, block, payload) = match self.resources.iter().find(|(p, _)| *p == path.join("/")) {
+                None => (0x84, None, &[][..]),
+                Some((_, body)) => {
+                    let block = request.block2().unwrap().unwrap();
+                    let size = block.size().min(self.max_block_size);
+                    let start = (block.num as usize * block.size()).min(body.len());
+                    let end = (start + size).min(body.len());
+                    let block = Block::new((start / size) as u32, end < body.len(), size);
+                    (CODE_CONTENT, Some(block), &body[start..end])
+                }
+            };
+            let (kind, id) = if self.separate {
+                self.responses.push_back(encode_ack(request.id).to_vec());
+                self.message_id += 1;
+                (TYPE_CON, self.message_id)
+            } else {
+                (TYPE_ACK, request.id)
+            };
+            self.responses
+                .push_back(encode_response(kind, id, request.token, code, block, payload));
+            Ok(())
+        }
+
+        async fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            match self.responses.pop_front() {
+                Some(response) => {
+                    buf[..response.len()].copy_from_slice(&response);
+                    Ok(response.len())
+                }
+                None => core::future::pending().await,
+            }
+        }
+    }
+
+    fn download(server: &mut Server, path: &str, dfu: &mut SimFlash<4, 4096>) -> Result<usize, Error> {
+        let mut state = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+        let mut aligned = [0; 4];
+        let mut updater = FirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state: &mut state }, &mut aligned);
+        let mut buffer = [0; 512];
+        let mut image = ImageWriter::new(&mut updater, &mut buffer);
+
+        let mut client = CoapClient::new(server, 1);
+        client.set_ack_timeout(Duration::from_millis(1));
+        block_on(client.download(path, &mut image))?;
+        Ok(block_on(image.finish())? as usize)
+    }
+
+    impl<T: Datagram> Datagram for &mut T {
+        async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+            T::send(self, data).await
+        }
+
+        async fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            T::recv(self, buf).await
+        }
+    }
+
+    #[test]
+    fn can_encode_request() {
+        let mut buf = [0; REQUEST_LEN];
+        let block = Block::new(2, false, 1024);
+        let len = encode_request(&mut buf, 0x1234, &[1, 2, 3, 4], "/fw/app.bin", block).unwrap();
+        let mut expected = vec![0x44, CODE_GET, 0x12, 0x34, 1, 2, 3, 4, 0xB2];
+        expected.extend_from_slice(b"fw");
+        expected.push(0x07);
+        expected.extend_from_slice(b"app.bin");
+        expected.extend_from_slice(&[0xC1, 0x26]);
+        assert_eq!(&expected[..], &buf[..len]);
+
+        let request = Message::parse(&buf[..len]).unwrap();
+        assert_eq!(TYPE_CON, request.kind);
+        assert_eq!(0x1234, request.id);
+        assert_eq!(&[1, 2, 3, 4], request.token);
+        assert_eq!(Some(block), request.block2().unwrap());
+
+        let len = encode_request(&mut buf, 0, &[0; 4], "a", Block::new(0, false, 16)).unwrap();
+        assert_eq!(
+            Some(Block::new(0, false, 16)),
+            Message::parse(&buf[..len]).unwrap().block2().unwrap()
+        );
+    }
+
+    #[test]
+    fn can_parse_extended_options() {
+        let mut buf = [0; 512];
+        let mut writer = Writer { buf: &mut buf, pos: 0 };
+        writer.option(300, &[0xAB; 14]).unwrap();
+        writer.option(20, &[0xCD; 300]).unwrap();
+        writer.push(&[PAYLOAD_MARKER, 1, 2]).unwrap();
+        let len = writer.pos;
+
+        let mut options = Options::new(&buf[..len]);
+        assert_eq!((300, &[0xAB; 14][..]), options.next().unwrap().unwrap());
+        assert_eq!((320, &[0xCD; 300][..]), options.next().unwrap().unwrap());
+        assert!(options.next().is_none());
+        assert_eq!(&[PAYLOAD_MARKER, 1, 2], options.data);
+
+        assert!(Options::new(&[0xF0]).next().unwrap().is_err());
+        assert!(Options::new(&[0x12, 0]).next().unwrap().is_err());
+        assert!(Message::parse(&[0x48, 0x45, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn can_download_image() {
+        let image: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let resources = [("fw/app.bin", &image[..])];
+        let mut server = Server::new(&resources);
+        server.max_block_size = 256;
+        server.lost = &[1, 4, 5];
+        let mut dfu = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+
+        assert_eq!(image.len(), download(&mut server, "fw/app.bin", &mut dfu).unwrap());
+        assert_eq!(&image[..], &dfu.contents()[..image.len()]);
+        assert_eq!(12 + 3, server.requests);
+    }
+
+    #[test]
+    fn can_receive_separate_responses() {
+        let image: Vec<u8> = (0..3000).map(|i| (i * 3) as u8).collect();
+        let resources = [("fw/app.bin", &image[..])];
+        let mut server = Server::new(&resources);
+        server.separate = true;
+        let mut dfu = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+
+        assert_eq!(image.len(), download(&mut server, "/fw/app.bin", &mut dfu).unwrap());
+        assert_eq!(&image[..], &dfu.contents()[..image.len()]);
+        assert_eq!(3, server.requests);
+    }
+
+    #[test]
+    fn fails_without_response() {
+        let resources = [("fw/app.bin", &[0xAA; 100][..])];
+        let mut server = Server::new(&resources);
+        server.lost = &[0, 1, 2, 3, 4, 5];
+        let mut dfu = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+
+        assert!(matches!(
+            download(&mut server, "fw/app.bin", &mut dfu),
+            Err(Error::Timeout)
+        ));
+        assert_eq!(MAX_RETRANSMIT as usize + 1, server.requests);
+        assert!(matches!(
+            download(&mut Server::new(&resources), "fw/missing.bin", &mut dfu),
+            Err(Error::Status(404))
+        ));
+    }
+
+    #[test]
+    fn can_update() {
+        let image: Vec<u8> = (0..5000).map(|i| (i * 7) as u8).collect();
+        #[cfg(feature = "_verify")]
+        let (public_key, signature) = {
+            use ed25519_dalek::{Digest, Sha512, Signer, SigningKey};
+            let key = SigningKey::generate(&mut rand::rngs::OsRng);
+            let signature = key.sign(&Sha512::digest(&image));
+            (key.verifying_key().to_bytes(), signature.to_bytes())
+        };
+        let resources = [("fw.bin", &image[..])];
+        #[cfg(feature = "_verify")]
+        let resources = [resources[0], ("fw.bin.sig", &signature[..])];
+        let mut server = Server::new(&resources);
+
+        let mut dfu = SimFlash::<4, 4096>::new(2 * 4096, sim_flash::Config::default());
+        let mut state = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+        let mut aligned = [0; 4];
+        let mut updater = FirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: &mut dfu,
+                state: &mut state,
+            },
+            &mut aligned,
+        );
+        let mut buffer = [0; 1024];
+        let mut client = CoapClient::new(&mut server, 1);
+
+        #[cfg(feature = "_verify")]
+        block_on(client.update("fw.bin", "fw.bin.sig", &public_key, &mut updater, &mut buffer)).unwrap();
+        #[cfg(not(feature = "_verify"))]
+        block_on(client.update("fw.bin", &mut updater, &mut buffer)).unwrap();
+        assert_eq!(State::Swap, block_on(updater.get_state()).unwrap());
+        assert_eq!(&image[..], &dfu.contents()[..image.len()]);
+    }
+}
@@ -0,0 +1,731 @@
+//! Firmware downloads over HTTP.
+//!
+//! Images are downloaded with `GET` requests, using a `Range` header to resume interrupted
+//! downloads. A new connection is made for each request, with `Connection: close`.
+use embassy_boot::FirmwareUpdater;
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpEndpoint, Stack};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::{Read, Write};
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::{BufSink, Error, ImageWriter, Sink};
+
+/// Maximum length of the status line and headers of a response.
+const HEAD_LEN: usize = 1024;
+
+/// Transport of HTTP requests.
+///
+/// Implement this trait to use another transport than plain TCP, such as a TLS connection over a
+/// [`TcpSocket`] for HTTPS.
+pub trait Connect {
+    /// Connection to the server.
+    type Connection<'a>: Read + Write
+    where
+        Self: 'a;
+
+    /// Open a new connection to the server.
+    async fn connect(&mut self) -> Result<Self::Connection<'_>, Error>;
+}
+
+/// Plain TCP connections to a server.
+///
+/// Connections are made with a single [`TcpSocket`], which is reset before each new connection.
+pub struct TcpConnect<'a> {
+    socket: TcpSocket<'a>,
+    remote: IpEndpoint,
+}
+
+impl<'a> TcpConnect<'a> {
+    /// Create connections to the `remote` endpoint, using the given socket buffers.
+    pub fn new<D: Driver, T: Into<IpEndpoint>>(
+        stack: &'a Stack<D>,
+        remote: T,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        Self {
+            socket,
+            remote: remote.into(),
+        }
+    }
+
+    /// Set the timeout of connections, 10 seconds by default.
+    ///
+    /// A connection is dropped if no data is received for this duration, and the download resumes
+    /// with a new connection.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.socket.set_timeout(Some(timeout));
+    }
+}
+
+impl<'a> Connect for TcpConnect<'a> {
+    type Connection<'c> = &'c mut TcpSocket<'a> where Self: 'c;
+
+    async fn connect(&mut self) -> Result<&mut TcpSocket<'a>, Error> {
+        // The previous connection is kept until now, so that it is closed gracefully.
+        self.socket.close();
+        let _ = self.socket.flush().await;
+        self.socket.abort();
+        let _ = self.socket.flush().await;
+        if let Err(e) = self.socket.connect(self.remote).await {
+            warn!("connect error: {:?}", e);
+            return Err(Error::Network);
+        }
+        Ok(&mut self.socket)
+    }
+}
+
+/// HTTP client downloading firmware images.
+pub struct HttpClient<'a, C: Connect> {
+    connect: C,
+    host: &'a str,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl<'a, C: Connect> HttpClient<'a, C> {
+    /// Create a client sending requests to `host` with the given transport.
+    ///
+    /// The `host` is sent in the `Host` header, and should include the port if it is not the
+    /// default one, such as `192.168.1.1:8080`.
+    pub fn new(connect: C, host: &'a str) -> Self {
+        Self {
+            connect,
+            host,
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+
+    /// Set how many times a request is retried before a download fails, 3 by default.
+    ///
+    /// Retries are counted from the last time data was received, so that long downloads over
+    /// unreliable networks can complete.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Set the delay before a request is retried, 1 second by default.
+    pub fn set_retry_delay(&mut self, delay: Duration) {
+        self.retry_delay = delay;
+    }
+
+    /// Download the image at `path`.
+    ///
+    /// The download starts at the offset of `image`, so that calling this again after an error
+    /// resumes it.
+    pub async fn download<DFU: NorFlash, STATE: NorFlash>(
+        &mut self,
+        path: &str,
+        image: &mut ImageWriter<'_, '_, DFU, STATE>,
+    ) -> Result<(), Error> {
+        self.fetch_resumed(path, image).await
+    }
+
+    /// Download a small resource, such as a signature, and return its length.
+    pub async fn get(&mut self, path: &str, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut sink = BufSink { buf, len: 0 };
+        self.fetch_resumed(path, &mut sink).await?;
+        Ok(sink.len)
+    }
+
+    /// Download the image at `image_path` and its signature at `signature_path`, and mark the
+    /// image to be swapped on next boot if it is valid.
+    ///
+    /// The image is written through the `buffer`, see [`ImageWriter::new`].
+    #[cfg(feature = "_verify")]
+    pub async fn update<DFU: NorFlash, STATE: NorFlash>(
+        &mut self,
+        image_path: &str,
+        signature_path: &str,
+        public_key: &[u8; 32],
+        updater: &mut FirmwareUpdater<'_, DFU, STATE>,
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let mut signature = [0; 64];
+        if self.get(signature_path, &mut signature).await? != signature.len() {
+            return Err(Error::Protocol);
+        }
+
+        let mut image = ImageWriter::new(updater, buffer);
+        self.download(image_path, &mut image).await?;
+        image.verify_and_mark_updated(public_key, &signature).await
+    }
+
+    /// Download the image at `image_path`, and mark it to be swapped on next boot.
+    ///
+    /// The image is written through the `buffer`, see [`ImageWriter::new`].
+    #[cfg(not(feature = "_verify"))]
+    pub async fn update<DFU: NorFlash, STATE: NorFlash>(
+        &mut self,
+        image_path: &str,
+        updater: &mut FirmwareUpdater<'_, DFU, STATE>,
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let mut image = ImageWriter::new(updater, buffer);
+        self.download(image_path, &mut image).await?;
+        image.mark_updated().await
+    }
+
+    async fn fetch_resumed<S: Sink>(&mut self, path: &str, sink: &mut S) -> Result<(), Error> {
+        let mut failures = 0;
+        loop {
+            let offset = sink.offset();
+            match self.fetch(path, sink).await {
+                Err(e @ (Error::Network | Error::Timeout)) => {
+                    failures = if sink.offset() > offset { 0 } else { failures + 1 };
+                    if failures > self.retries {
+                        return Err(e);
+                    }
+                    warn!("download interrupted at {}, resuming", sink.offset());
+                    Timer::after(self.retry_delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn fetch<S: Sink>(&mut self, path: &str, sink: &mut S) -> Result<(), Error> {
+        let offset = sink.offset();
+        let mut conn = self.connect.connect().await?;
+        write_request(&mut conn, self.host, path, offset)
+            .await
+            .map_err(|_| Error::Network)?;
+
+        let mut buf = [0; HEAD_LEN];
+        let mut len = 0;
+        let head_len = loop {
+            if let Some(pos) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            if len == buf.len() {
+                return Err(Error::TooLarge);
+            }
+            match conn.read(&mut buf[len..]).await {
+                Ok(0) | Err(_) => return Err(Error::Network),
+                Ok(n) => len += n,
+            }
+        };
+
+        let head = Head::parse(&buf[..head_len])?;
+        if head.chunked {
+            return Err(Error::Protocol);
+        }
+        // Servers without range support send the whole resource, so skip what was received.
+        let (mut skip, mut remaining) = match head.status {
+            200 if head.content_length.is_some_and(|len| len < offset) => return Err(Error::Protocol),
+            200 => (offset, head.content_length.map(|len| len - offset)),
+            206 if head.content_range.is_some_and(|range| range.start == Some(offset)) => (0, head.content_length),
+            206 => return Err(Error::Protocol),
+            // The download was already complete.
+            416 if head.content_range.is_some_and(|range| range.total == Some(offset)) => return Ok(()),
+            status => return Err(Error::Status(status)),
+        };
+
+        let mut start = head_len;
+        loop {
+            let mut data = &buf[start..len];
+            let n = data.len().min(skip);
+            data = &data[n..];
+            skip -= n;
+            if let Some(remaining) = remaining.as_mut() {
+                data = &data[..data.len().min(*remaining)];
+                *remaining -= data.len();
+            }
+            sink.write(data).await?;
+            if remaining == Some(0) {
+                return Ok(());
+            }
+
+            start = 0;
+            len = match conn.read(&mut buf).await {
+                Ok(0) if remaining.is_none() => return Ok(()),
+                Ok(0) | Err(_) => return Err(Error::Network),
+                Ok(n) => n,
+            };
+        }
+    }
+}
+
+async fn write_request<W: Write>(conn: &mut W, host: &str, path: &str, offset: usize) -> Result<(), W::Error> {
+    let mut digits = [0; 20];
+    let parts: [&[u8]; 7] = [
+        b"GET ",
+        path.as_bytes(),
+        b" HTTP/1.1\r\nHost: ",
+        host.as_bytes(),
+        b"\r\nRange: bytes=",
+        format_usize(offset, &mut digits),
+        b"-\r\nConnection: close\r\n\r\n",
+    ];
+    for part in parts {
+        conn.write_all(part).await?;
+    }
+    conn.flush().await
+}
+
+fn format_usize(mut value: usize, digits: &mut [u8; 20]) -> &[u8] {
+    let mut pos = digits.len();
+    loop {
+        pos -= 1;
+        digits[pos] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            return &digits[pos..];
+        }
+    }
+}
+
+/// Status line and headers of a response.
+#[derive(Debug, PartialEq)]
+struct Head {
+    status: u16,
+    content_length: Option<usize>,
+    content_range: Option<ContentRange>,
+    chunked: bool,
+}
+
+/// `Content-Range` header, without the end of the range.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct ContentRange {
+    start: Option<usize>,
+    total: Option<usize>,
+}
+
+impl Head {
+    fn parse(head: &[u8]) -> Result<Self, Error> {
+        let head = core::str::from_utf8(head).map_err(|_| Error::Protocol)?;
+        let mut lines = head.split("\r\n");
+
+        let mut status_line = lines.next().ok_or(Error::Protocol)?.split(' ');
+        if !status_line.next().is_some_and(|version| version.starts_with("HTTP/1.")) {
+            return Err(Error::Protocol);
+        }
+        let status = parse(status_line.next())?;
+
+        let mut result = Self {
+            status,
+            content_length: None,
+            content_range: None,
+            chunked: false,
+        };
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':').ok_or(Error::Protocol)?;
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                result.content_length = Some(parse(Some(value))?);
+            } else if name.eq_ignore_ascii_case("content-range") {
+                result.content_range = Some(ContentRange::parse(value)?);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                result.chunked = !value.eq_ignore_ascii_case("identity");
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl ContentRange {
+    fn parse(value: &str) -> Result<Self, Error> {
+        let (range, total) = value
+            .strip_prefix("bytes ")
+            .and_then(|value| value.split_once('/'))
+            .ok_or(Error::Protocol)?;
+        let start = match range {
+            "*" => None,
+            range => Some(parse(range.split_once('-').map(|(start, _)| start))?),
+        };
+        let total = match total {
+            "*" => None,
+            total => Some(parse(Some(total))?),
+        };
+        Ok(Self { start, total })
+    }
+}
+
+fn parse<T: core::str::FromStr>(value: Option<&str>) -> Result<T, Error> {
+    value.and_then(|value| value.parse().ok()).ok_or(Error::Protocol)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::task::Waker;
+    use std::collections::VecDeque;
+
+    use embassy_boot::{FirmwareUpdaterConfig, State};
+    use embassy_embedded_hal::flash::sim_flash::{self, SimFlash};
+    use embassy_futures::select::{select3, Either3};
+    use embassy_net::driver::{Capabilities, HardwareAddress, LinkState};
+    use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+    use embedded_io_async::{ErrorKind, ErrorType};
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// HTTP server serving `resources`, which drops connections after `drop_after` bytes.
+    struct Server<'a> {
+        resources: &'a [(&'a str, &'a [u8])],
+        ignore_range: bool,
+        drop_after: Option<usize>,
+        connections: Cell<usize>,
+    }
+
+    impl<'a> Server<'a> {
+        fn new(resources: &'a [(&'a str, &'a [u8])]) -> Self {
+            Self {
+                resources,
+                ignore_range: false,
+                drop_after: None,
+                connections: Cell::new(0),
+            }
+        }
+
+        /// Response to a request, truncated if the connection is dropped.
+        fn respond(&self, request: &[u8]) -> Vec<u8> {
+            self.connections.set(self.connections.get() + 1);
+            let request = core::str::from_utf8(request).unwrap();
+            let path = request.split(' ').nth(1).unwrap();
+            let offset: usize = request
+                .split("\r\n")
+                .find_map(|line| line.strip_prefix("Range: bytes="))
+                .map_or(0, |range| range.trim_end_matches('-').parse().unwrap());
+
+            let mut response = match self.resources.iter().find(|(p, _)| *p == path) {
+                None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                Some((_, body)) if self.ignore_range => {
+                    let mut response =
+                        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+                    response.extend_from_slice(body);
+                    response
+                }
+                Some((_, body)) if offset >= body.len() => format!(
+                    "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\n\r\n",
+                    body.len()
+                )
+                .into_bytes(),
+                Some((_, body)) => {
+                    let mut response = format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                        offset,
+                        body.len() - 1,
+                        body.len(),
+                        body.len() - offset
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&body[offset..]);
+                    response
+                }
+            };
+            if let Some(drop_after) = self.drop_after {
+                let head_len = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+                let skipped = if self.ignore_range { offset } else { 0 };
+                response.truncate(head_len + skipped + drop_after);
+            }
+            response
+        }
+    }
+
+    /// Connections to a [`Server`] in memory.
+    struct MemConnect<'a>(&'a Server<'a>);
+
+    struct MemConnection<'a> {
+        server: &'a Server<'a>,
+        request: Vec<u8>,
+        response: Option<Vec<u8>>,
+        pos: usize,
+    }
+
+    impl Connect for MemConnect<'_> {
+        type Connection<'c> = MemConnection<'c> where Self: 'c;
+
+        async fn connect(&mut self) -> Result<MemConnection<'_>, Error> {
+            Ok(MemConnection {
+                server: self.0,
+                request: Vec::new(),
+                response: None,
+                pos: 0,
+            })
+        }
+    }
+
+    impl ErrorType for MemConnection<'_> {
+        type Error = ErrorKind;
+    }
+
+    impl Read for MemConnection<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            let response = self.response.get_or_insert_with(|| self.server.respond(&self.request));
+            let n = buf.len().min(response.len() - self.pos).min(100);
+            buf[..n].copy_from_slice(&response[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for MemConnection<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+            self.request.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn download(server: &Server, path: &str, dfu: &mut SimFlash<4, 4096>) -> Result<usize, Error> {
+        let mut state = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+        let mut aligned = [0; 4];
+        let mut updater = FirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state: &mut state }, &mut aligned);
+        let mut buffer = [0; 512];
+        let mut image = ImageWriter::new(&mut updater, &mut buffer);
+
+        let mut client = HttpClient::new(MemConnect(server), "localhost");
+        client.set_retry_delay(Duration::from_millis(0));
+        block_on(client.download(path, &mut image))?;
+        Ok(block_on(image.finish())? as usize)
+    }
+
+    #[test]
+    fn can_parse_head() {
+        let head =
+            Head::parse(b"HTTP/1.1 206 Partial Content\r\nContent-Length: 10\r\ncontent-range: bytes 5-14/15\r\n\r\n");
+        assert_eq!(
+            Head {
+                status: 206,
+                content_length: Some(10),
+                content_range: Some(ContentRange {
+                    start: Some(5),
+                    total: Some(15)
+                }),
+                chunked: false,
+            },
+            head.unwrap()
+        );
+
+        let head = Head::parse(b"HTTP/1.0 416 Range Not Satisfiable\r\nContent-Range: bytes */15\r\n\r\n").unwrap();
+        assert_eq!(None, head.content_range.unwrap().start);
+        assert!(
+            Head::parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n")
+                .unwrap()
+                .chunked
+        );
+        assert!(Head::parse(b"HTTP/1.1 200 OK\r\nContent-Length: x\r\n\r\n").is_err());
+        assert!(Head::parse(b"SSH-2.0\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn can_download_image() {
+        let image: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let resources = [("/fw.bin", &image[..])];
+        let server = Server::new(&resources);
+        let mut dfu = SimFlash::<4, 4096>::new(2 * 4096, sim_flash::Config::default());
+
+        assert_eq!(image.len(), download(&server, "/fw.bin", &mut dfu).unwrap());
+        assert_eq!(&image[..], &dfu.contents()[..image.len()]);
+        assert_eq!(1, server.connections.get());
+    }
+
+    #[test]
+    fn can_resume_download() {
+        let image: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let resources = [("/fw.bin", &image[..])];
+        for ignore_range in [false, true] {
+            let mut server = Server::new(&resources);
+            server.ignore_range = ignore_range;
+            server.drop_after = Some(1200);
+            let mut dfu = SimFlash::<4, 4096>::new(2 * 4096, sim_flash::Config::default());
+
+            assert_eq!(image.len(), download(&server, "/fw.bin", &mut dfu).unwrap());
+            assert_eq!(&image[..], &dfu.contents()[..image.len()]);
+            assert_eq!(5, server.connections.get());
+        }
+    }
+
+    #[test]
+    fn fails_without_progress() {
+        let resources = [("/fw.bin", &[0xAA; 100][..])];
+        let mut server = Server::new(&resources);
+        server.drop_after = Some(0);
+        let mut dfu = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+
+        assert!(matches!(download(&server, "/fw.bin", &mut dfu), Err(Error::Network)));
+        assert_eq!(4, server.connections.get());
+        assert!(matches!(
+            download(&Server::new(&resources), "/missing.bin", &mut dfu),
+            Err(Error::Status(404))
+        ));
+    }
+
+    #[derive(Default)]
+    struct Loopback {
+        packets: VecDeque<Vec<u8>>,
+        waker: Option<Waker>,
+    }
+
+    struct RxToken(Vec<u8>);
+
+    struct TxToken<'a>(&'a mut Loopback);
+
+    impl embassy_net::driver::RxToken for RxToken {
+        fn consume<R, F: FnOnce(&mut [u8]) -> R>(mut self, f: F) -> R {
+            f(&mut self.0)
+        }
+    }
+
+    impl embassy_net::driver::TxToken for TxToken<'_> {
+        fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+            let mut packet = vec![0; len];
+            let result = f(&mut packet);
+            self.0.packets.push_back(packet);
+            if let Some(waker) = self.0.waker.take() {
+                waker.wake();
+            }
+            result
+        }
+    }
+
+    /// Driver sending packets back to the stack, so that it can connect to itself.
+    impl Driver for Loopback {
+        type RxToken<'a> = RxToken;
+        type TxToken<'a> = TxToken<'a>;
+
+        fn receive(&mut self, cx: &mut core::task::Context) -> Option<(RxToken, TxToken<'_>)> {
+            match self.packets.pop_front() {
+                Some(packet) => Some((RxToken(packet), TxToken(self))),
+                None => {
+                    self.waker = Some(cx.waker().clone());
+                    None
+                }
+            }
+        }
+
+        fn transmit(&mut self, _cx: &mut core::task::Context) -> Option<TxToken<'_>> {
+            Some(TxToken(self))
+        }
+
+        fn link_state(&mut self, _cx: &mut core::task::Context) -> LinkState {
+            LinkState::Up
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            let mut capabilities = Capabilities::default();
+            capabilities.max_transmission_unit = 1500;
+            capabilities
+        }
+
+        fn hardware_address(&self) -> HardwareAddress {
+            HardwareAddress::Ip
+        }
+    }
+
+    async fn serve(stack: &Stack<Loopback>, server: &Server<'_>) -> ! {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        loop {
+            let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+            socket.accept(8080).await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buf = [0; 256];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            socket.write_all(&server.respond(&request)).await.unwrap();
+            socket.close();
+            socket.flush().await.unwrap();
+        }
+    }
+
+    #[test]
+    fn can_update_over_tcp() {
+        let image: Vec<u8> = (0..10000).map(|i| (i * 7) as u8).collect();
+        #[cfg(feature = "_verify")]
+        let (public_key, signature) = {
+            use ed25519_dalek::{Digest, Sha512, Signer, SigningKey};
+            let key = SigningKey::generate(&mut rand::rngs::OsRng);
+            let signature = key.sign(&Sha512::digest(&image));
+            (key.verifying_key().to_bytes(), signature.to_bytes())
+        };
+        #[cfg(not(feature = "_verify"))]
+        let signature = [0; 64];
+        let resources = [("/fw.bin", &image[..]), ("/fw.bin.sig", &signature[..])];
+        let mut server = Server::new(&resources);
+        server.drop_after = Some(3000);
+
+        let config = Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24),
+            gateway: None,
+            dns_servers: heapless::Vec::new(),
+        });
+        let resources = Box::leak(Box::new(StackResources::<2>::new()));
+        let stack = Stack::new(Loopback::default(), config, resources, 1);
+
+        let mut dfu = SimFlash::<4, 4096>::new(3 * 4096, sim_flash::Config::default());
+        let mut state = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+        let mut aligned = [0; 4];
+        let mut updater = FirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: &mut dfu,
+                state: &mut state,
+            },
+            &mut aligned,
+        );
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let connect = TcpConnect::new(
+            &stack,
+            (Ipv4Address::new(10, 0, 0, 1), 8080),
+            &mut rx_buffer,
+            &mut tx_buffer,
+        );
+        let mut client = HttpClient::new(connect, "10.0.0.1:8080");
+        client.set_retry_delay(Duration::from_millis(10));
+        let mut buffer = [0; 1024];
+
+        let update = async {
+            #[cfg(feature = "_verify")]
+            client
+                .update("/fw.bin", "/fw.bin.sig", &public_key, &mut updater, &mut buffer)
+                .await?;
+            #[cfg(not(feature = "_verify"))]
+            client.update("/fw.bin", &mut updater, &mut buffer).await?;
+            updater.get_state().await.map_err(Error::from)
+        };
+        match block_on(select3(stack.run(), serve(&stack, &server), update)) {
+            Either3::Third(state) => assert_eq!(State::Swap, state.unwrap()),
+            _ => unreachable!(),
+        }
+        assert_eq!(&image[..], &dfu.contents()[..image.len()]);
+        assert!(server.connections.get() >= 4);
+    }
+
+    #[cfg(feature = "_verify")]
+    #[test]
+    fn rejects_bad_signature() {
+        let image = [0xAA; 1000];
+        let signature = [0; 64];
+        let resources = [("/fw.bin", &image[..]), ("/fw.bin.sig", &signature[..])];
+        let server = Server::new(&resources);
+
+        let mut dfu = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+        let mut state = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+        let mut aligned = [0; 4];
+        let mut updater = FirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: &mut dfu,
+                state: &mut state,
+            },
+            &mut aligned,
+        );
+        let mut buffer = [0; 512];
+        let mut client = HttpClient::new(MemConnect(&server), "localhost");
+        let public_key = [0x42; 32];
+        assert!(matches!(
+            block_on(client.update("/fw.bin", "/fw.bin.sig", &public_key, &mut updater, &mut buffer)),
+            Err(Error::Updater(_))
+        ));
+        assert_eq!(State::Boot, block_on(updater.get_state()).unwrap());
+    }
+}
@@ -0,0 +1,135 @@
+use embassy_boot::FirmwareUpdater;
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::{Error, Sink};
+
+/// Writer of a downloaded image to the DFU partition.
+///
+/// Received bytes are buffered until a whole buffer can be written, so that transfers of any size
+/// can be written to the flash. The writer tracks how many bytes were received, which is where an
+/// interrupted download resumes with the same writer.
+pub struct ImageWriter<'a, 'd, DFU: NorFlash, STATE: NorFlash> {
+    updater: &'a mut FirmwareUpdater<'d, DFU, STATE>,
+    buffer: &'a mut [u8],
+    buffered: usize,
+    written: usize,
+}
+
+impl<'a, 'd, DFU: NorFlash, STATE: NorFlash> ImageWriter<'a, 'd, DFU, STATE> {
+    /// Create a writer of a new image.
+    ///
+    /// The `buffer` length must be a non-zero multiple of the write size of the DFU partition.
+    /// Larger buffers result in fewer flash writes.
+    pub fn new(updater: &'a mut FirmwareUpdater<'d, DFU, STATE>, buffer: &'a mut [u8]) -> Self {
+        assert!(!buffer.is_empty());
+        assert_eq!(0, buffer.len() % DFU::WRITE_SIZE);
+        Self {
+            updater,
+            buffer,
+            buffered: 0,
+            written: 0,
+        }
+    }
+
+    /// Number of bytes of the image received so far.
+    pub fn offset(&self) -> usize {
+        self.written + self.buffered
+    }
+
+    /// Append bytes to the image.
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let n = data.len().min(self.buffer.len() - self.buffered);
+            self.buffer[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+
+            if self.buffered == self.buffer.len() {
+                self.updater.write_firmware(self.written, self.buffer).await?;
+                self.written += self.buffered;
+                self.buffered = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the buffered end of the image, padded to the write size, and return the image length.
+    pub(crate) async fn finish(&mut self) -> Result<u32, Error> {
+        let len = self.offset();
+        if self.buffered > 0 {
+            let padded = self.buffered.next_multiple_of(DFU::WRITE_SIZE);
+            self.buffer[self.buffered..padded].fill(0xFF);
+            self.updater
+                .write_firmware(self.written, &self.buffer[..padded])
+                .await?;
+            self.written += self.buffered;
+            self.buffered = 0;
+        }
+        u32::try_from(len).map_err(|_| Error::TooLarge)
+    }
+
+    /// Verify the image with the given public key and signature, and mark it to be swapped on
+    /// next boot if it is valid.
+    ///
+    /// The signature is expected to be of a SHA-512 digest of the image, as with
+    /// [`FirmwareUpdater::verify_and_mark_updated`].
+    #[cfg(feature = "_verify")]
+    pub async fn verify_and_mark_updated(mut self, public_key: &[u8; 32], signature: &[u8; 64]) -> Result<(), Error> {
+        let len = self.finish().await?;
+        self.updater.verify_and_mark_updated(public_key, signature, len).await?;
+        Ok(())
+    }
+
+    /// Mark the image to be swapped on next boot.
+    #[cfg(not(feature = "_verify"))]
+    pub async fn mark_updated(mut self) -> Result<(), Error> {
+        self.finish().await?;
+        self.updater.mark_updated().await?;
+        Ok(())
+    }
+}
+
+impl<DFU: NorFlash, STATE: NorFlash> Sink for ImageWriter<'_, '_, DFU, STATE> {
+    fn offset(&self) -> usize {
+        ImageWriter::offset(self)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        ImageWriter::write(self, data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_boot::FirmwareUpdaterConfig;
+    use embassy_embedded_hal::flash::sim_flash::{self, SimFlash};
+    use futures::executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn can_write_image() {
+        let mut dfu = SimFlash::<4, 4096>::new(2 * 4096, sim_flash::Config::default());
+        let mut state = SimFlash::<4, 4096>::new(4096, sim_flash::Config::default());
+        let image: [u8; 5003] = core::array::from_fn(|i| i as u8);
+
+        let mut aligned = [0; 4];
+        let mut updater = FirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: &mut dfu,
+                state: &mut state,
+            },
+            &mut aligned,
+        );
+        let mut buffer = [0; 1024];
+        let mut writer = ImageWriter::new(&mut updater, &mut buffer);
+        for chunk in image.chunks(333) {
+            block_on(writer.write(chunk)).unwrap();
+        }
+        assert_eq!(image.len(), writer.offset());
+        assert_eq!(image.len() as u32, block_on(writer.finish()).unwrap());
+
+        assert_eq!(&image[..], &dfu.contents()[..image.len()]);
+        assert_eq!(0xFF, dfu.contents()[image.len()]);
+    }
+}
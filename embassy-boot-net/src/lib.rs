@@ -0,0 +1,69 @@
+#![cfg_attr(not(test), no_std)]
+#![allow(async_fn_in_trait)]
+#![warn(missing_docs)]
+#![doc = include_str!("../README.md")]
+mod fmt;
+
+#[cfg(feature = "coap")]
+pub mod coap;
+#[cfg(feature = "http")]
+pub mod http;
+mod image;
+
+use embassy_boot::FirmwareUpdaterError;
+pub use image::ImageWriter;
+
+/// Errors of network firmware updates.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The connection to the server failed or was lost.
+    Network,
+    /// The server did not respond in time.
+    Timeout,
+    /// The server responded with an error status, such as 404 for HTTP or 4.04 for CoAP.
+    Status(u16),
+    /// The response is malformed or uses an unsupported feature.
+    Protocol,
+    /// A request or response does not fit its buffer.
+    TooLarge,
+    /// Error from the firmware updater.
+    Updater(FirmwareUpdaterError),
+}
+
+impl From<FirmwareUpdaterError> for Error {
+    fn from(e: FirmwareUpdaterError) -> Self {
+        Error::Updater(e)
+    }
+}
+
+/// Destination of downloaded bytes, which tracks the offset to resume from.
+pub(crate) trait Sink {
+    /// Number of bytes received so far.
+    fn offset(&self) -> usize;
+
+    /// Append received bytes.
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error>;
+}
+
+/// Sink of small resources such as signatures, kept in memory.
+pub(crate) struct BufSink<'a> {
+    pub(crate) buf: &'a mut [u8],
+    pub(crate) len: usize,
+}
+
+impl Sink for BufSink<'_> {
+    fn offset(&self) -> usize {
+        self.len
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let dest = self
+            .buf
+            .get_mut(self.len..self.len + data.len())
+            .ok_or(Error::TooLarge)?;
+        dest.copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! In-memory flash used to run the updater of the target on the host.
+
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Erase size of [`RamFlash`].
+pub const ERASE_SIZE: usize = 4096;
+
+/// Erased flash of `capacity` bytes, rounded up to whole sectors.
+pub struct RamFlash<const WRITE_SIZE: usize> {
+    mem: Vec<u8>,
+}
+
+impl<const WRITE_SIZE: usize> RamFlash<WRITE_SIZE> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            mem: vec![0xFF; capacity.max(1).next_multiple_of(ERASE_SIZE)],
+        }
+    }
+}
+
+impl<const WRITE_SIZE: usize> ErrorType for RamFlash<WRITE_SIZE> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<const WRITE_SIZE: usize> ReadNorFlash for RamFlash<WRITE_SIZE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.mem[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.mem.len()
+    }
+}
+
+impl<const WRITE_SIZE: usize> NorFlash for RamFlash<WRITE_SIZE> {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        self.mem[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        self.mem[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
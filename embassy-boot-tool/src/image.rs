@@ -0,0 +1,177 @@
+//! Firmware image loading.
+
+use anyhow::{bail, Context};
+use embassy_boot::{ImageMetadata, Version};
+use object::elf::PT_LOAD;
+use object::read::elf::{FileHeader, ProgramHeader};
+use object::{FileKind, ReadRef};
+
+/// Load an image from an ELF file or a raw binary.
+///
+/// ELF files are converted to a binary holding their loadable segments, placed at their physical
+/// address like `objcopy -O binary` does, with gaps filled with erased bytes.
+pub fn load(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match FileKind::parse(data) {
+        Ok(FileKind::Elf32) => elf_to_bin::<object::elf::FileHeader32<object::Endianness>>(data),
+        Ok(FileKind::Elf64) => elf_to_bin::<object::elf::FileHeader64<object::Endianness>>(data),
+        _ => Ok(data.to_vec()),
+    }
+}
+
+fn elf_to_bin<Elf: FileHeader<Endian = object::Endianness>>(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let header = Elf::parse(data).context("invalid ELF header")?;
+    let endian = header.endian().context("invalid ELF endianness")?;
+
+    let mut segments = Vec::new();
+    for segment in header
+        .program_headers(endian, data)
+        .context("invalid ELF program headers")?
+    {
+        if segment.p_type(endian) != PT_LOAD || segment.p_filesz(endian).into() == 0 {
+            continue;
+        }
+        let address = segment.p_paddr(endian).into();
+        let bytes = segment
+            .data(endian, data)
+            .ok()
+            .and_then(|bytes| bytes.read_bytes_at(0, segment.p_filesz(endian).into()).ok())
+            .context("invalid ELF segment")?;
+        segments.push((address, bytes));
+    }
+    segments.sort_by_key(|(address, _)| *address);
+
+    let Some(&(base, _)) = segments.first() else {
+        bail!("ELF file has no loadable segments");
+    };
+    let mut image = Vec::new();
+    for (address, bytes) in segments {
+        let offset = usize::try_from(address - base)?;
+        if offset < image.len() {
+            bail!("ELF segments overlap at address 0x{:x}", address);
+        }
+        image.resize(offset, 0xFF);
+        image.extend_from_slice(bytes);
+    }
+    Ok(image)
+}
+
+/// Append metadata to an image, so that it is covered by the signature.
+pub fn append_metadata(
+    image: &mut Vec<u8>,
+    version: Version,
+    hardware_id: u32,
+    security_counter: u32,
+) -> anyhow::Result<()> {
+    let metadata = ImageMetadata {
+        version,
+        hardware_id,
+        image_len: u32::try_from(image.len()).context("image too large")?,
+        security_counter,
+    };
+    image.extend_from_slice(&metadata.to_bytes());
+    Ok(())
+}
+
+/// Parse a version such as `1.2.3`.
+pub fn parse_version(version: &str) -> anyhow::Result<Version> {
+    let parts: Vec<_> = version.split('.').collect();
+    let [major, minor, patch] = parts[..] else {
+        bail!("version must be major.minor.patch: {}", version);
+    };
+    Ok(Version {
+        major: major.parse().context("invalid major version")?,
+        minor: minor.parse().context("invalid minor version")?,
+        patch: patch.parse().context("invalid patch version")?,
+    })
+}
+
+/// Parse a number, decimal or hexadecimal with a `0x` prefix.
+pub fn parse_u32(value: &str) -> anyhow::Result<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => Ok(u32::from_str_radix(hex, 16)?),
+        None => Ok(value.parse()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_boot::IMAGE_METADATA_LEN;
+
+    use super::*;
+
+    /// Build a little endian ELF32 file with a loadable segment for each `(paddr, data)`.
+    fn elf32(segments: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut elf = vec![0; 52];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 1; // ELFCLASS32
+        elf[5] = 1; // ELFDATA2LSB
+        elf[6] = 1; // EV_CURRENT
+        elf[16..18].copy_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        elf[18..20].copy_from_slice(&40u16.to_le_bytes()); // EM_ARM
+        elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+        elf[28..32].copy_from_slice(&52u32.to_le_bytes()); // e_phoff
+        elf[40..42].copy_from_slice(&52u16.to_le_bytes()); // e_ehsize
+        elf[42..44].copy_from_slice(&32u16.to_le_bytes()); // e_phentsize
+        elf[44..46].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+        let mut offset = 52 + 32 * segments.len() as u32;
+        for (paddr, data) in segments {
+            let vaddr = paddr | 0x1000_0000;
+            for word in [
+                PT_LOAD,
+                offset,
+                vaddr,
+                *paddr,
+                data.len() as u32,
+                data.len() as u32,
+                5,
+                4,
+            ] {
+                elf.extend_from_slice(&word.to_le_bytes());
+            }
+            offset += data.len() as u32;
+        }
+        for (_, data) in segments {
+            elf.extend_from_slice(data);
+        }
+        elf
+    }
+
+    #[test]
+    fn can_load_elf() {
+        let elf = elf32(&[(0x0800_0010, &[3; 4]), (0x0800_0000, &[1; 8])]);
+        let mut expected = vec![1; 8];
+        expected.extend_from_slice(&[0xFF; 8]);
+        expected.extend_from_slice(&[3; 4]);
+        assert_eq!(expected, load(&elf).unwrap());
+
+        assert!(load(&elf32(&[])).is_err());
+        assert!(load(&elf32(&[(0x0800_0000, &[1; 8]), (0x0800_0004, &[2; 4])])).is_err());
+    }
+
+    #[test]
+    fn can_load_bin() {
+        assert_eq!(vec![1, 2, 3], load(&[1, 2, 3]).unwrap());
+    }
+
+    #[test]
+    fn can_append_metadata() {
+        let mut image = vec![0xAA; 100];
+        let version = parse_version("1.2.3").unwrap();
+        append_metadata(&mut image, version, 0x1234, 5).unwrap();
+        assert_eq!(100 + IMAGE_METADATA_LEN, image.len());
+
+        let metadata = ImageMetadata::from_bytes(image[100..].try_into().unwrap()).unwrap();
+        assert_eq!(version, metadata.version);
+        assert_eq!(0x1234, metadata.hardware_id);
+        assert_eq!(100, metadata.image_len);
+        assert_eq!(5, metadata.security_counter);
+    }
+
+    #[test]
+    fn can_parse_arguments() {
+        assert_eq!(0x1234, parse_u32("0x1234").unwrap());
+        assert_eq!(1234, parse_u32("1234").unwrap());
+        assert!(parse_version("1.2").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Host tool packaging and signing firmware images for embassy-boot.
+//!
+//! Images are signed for `FirmwareUpdater::verify_and_mark_updated`, and verified by running the
+//! firmware updater of embassy-boot on the host.
+#![warn(missing_docs)]
+
+#[cfg(not(any(feature = "ed25519-dalek", feature = "ed25519-salty")))]
+compile_error!("one of the ed25519-dalek or ed25519-salty features must be enabled");
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+
+mod flash;
+mod image;
+mod sign;
+mod verify;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a signing key pair.
+    Keygen {
+        /// Path of the secret key, kept private.
+        #[arg(long)]
+        secret_key: PathBuf,
+        /// Path of the public key, built into the application.
+        #[arg(long)]
+        public_key: PathBuf,
+    },
+    /// Package and sign an image.
+    Sign {
+        /// Path of the secret key.
+        #[arg(long)]
+        secret_key: PathBuf,
+        /// ELF file or raw binary of the firmware.
+        input: PathBuf,
+        /// Path of the packaged image, to be written to the DFU partition.
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Path of the signature. Defaults to the output path with a `.sig` extension.
+        #[arg(long)]
+        signature: Option<PathBuf>,
+        /// Append image metadata with this version, such as `1.2.3`.
+        #[arg(long)]
+        version: Option<String>,
+        /// Hardware identifier of the image metadata.
+        #[arg(long, default_value = "0", requires = "version")]
+        hardware_id: String,
+        /// Security counter of the image metadata.
+        #[arg(long, default_value = "0", requires = "version")]
+        security_counter: String,
+    },
+    /// Verify a packaged image.
+    Verify {
+        /// Path of the public key.
+        #[arg(long)]
+        public_key: PathBuf,
+        /// Path of the signature.
+        #[arg(long)]
+        signature: PathBuf,
+        /// Packaged image.
+        image: PathBuf,
+    },
+    /// Build and sign the manifest of a multi-image update.
+    Manifest {
+        /// Path of the secret key.
+        #[arg(long)]
+        secret_key: PathBuf,
+        /// Path of the manifest.
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Path of the signature. Defaults to the output path with a `.sig` extension.
+        #[arg(long)]
+        signature: Option<PathBuf>,
+        /// Packaged image of each slot, such as `0=app.bin`.
+        #[arg(required = true)]
+        images: Vec<String>,
+    },
+    /// Verify the manifest of a multi-image update, and its images.
+    VerifyManifest {
+        /// Path of the public key.
+        #[arg(long)]
+        public_key: PathBuf,
+        /// Path of the signature.
+        #[arg(long)]
+        signature: PathBuf,
+        /// Path of the manifest.
+        #[arg(long)]
+        manifest: PathBuf,
+        /// Packaged image of each slot, such as `0=app.bin`.
+        #[arg(required = true)]
+        images: Vec<String>,
+    },
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Command::Keygen { secret_key, public_key } => {
+            let key = sign::generate_key();
+            fs::write(&secret_key, key.to_bytes()).with_context(|| format!("writing {}", secret_key.display()))?;
+            fs::write(&public_key, key.verifying_key().to_bytes())
+                .with_context(|| format!("writing {}", public_key.display()))?;
+        }
+        Command::Sign {
+            secret_key,
+            input,
+            output,
+            signature,
+            version,
+            hardware_id,
+            security_counter,
+        } => {
+            let key = sign::signing_key(&read(&secret_key)?)?;
+            let mut image = image::load(&read(&input)?)?;
+            if let Some(version) = version {
+                let version = image::parse_version(&version)?;
+                let hardware_id = image::parse_u32(&hardware_id).context("invalid hardware id")?;
+                let security_counter = image::parse_u32(&security_counter).context("invalid security counter")?;
+                image::append_metadata(&mut image, version, hardware_id, security_counter)?;
+            }
+            let signature = signature.unwrap_or_else(|| sig_path(&output));
+            write(&output, &image)?;
+            write(&signature, &sign::sign(&key, &image))?;
+            println!("Signed image of {} bytes", image.len());
+        }
+        Command::Verify {
+            public_key,
+            signature,
+            image,
+        } => {
+            let image = read(&image)?;
+            let metadata = verify::verify_image(&read_key(&public_key)?, &read_signature(&signature)?, &image)?;
+            println!("Verified image of {} bytes", image.len());
+            if let Some(metadata) = metadata {
+                let version = metadata.version;
+                println!(
+                    "Version {}.{}.{}, hardware id 0x{:08x}, security counter {}",
+                    version.major, version.minor, version.patch, metadata.hardware_id, metadata.security_counter
+                );
+            }
+        }
+        Command::Manifest {
+            secret_key,
+            output,
+            signature,
+            images,
+        } => {
+            let key = sign::signing_key(&read(&secret_key)?)?;
+            let manifest = sign::manifest(&read_images(&images)?)?;
+            let signature = signature.unwrap_or_else(|| sig_path(&output));
+            write(&output, &manifest)?;
+            write(&signature, &sign::sign(&key, &manifest))?;
+            println!("Signed manifest of {} images", images.len());
+        }
+        Command::VerifyManifest {
+            public_key,
+            signature,
+            manifest,
+            images,
+        } => {
+            verify::verify_manifest(
+                &read_key(&public_key)?,
+                &read_signature(&signature)?,
+                &read(&manifest)?,
+                &read_images(&images)?,
+            )?;
+            println!("Verified manifest of {} images", images.len());
+        }
+    }
+    Ok(())
+}
+
+fn read(path: &Path) -> anyhow::Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("reading {}", path.display()))
+}
+
+fn write(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    fs::write(path, data).with_context(|| format!("writing {}", path.display()))
+}
+
+fn read_key(path: &Path) -> anyhow::Result<[u8; 32]> {
+    read(path)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("public key must be 32 bytes: {}", path.display()))
+}
+
+fn read_signature(path: &Path) -> anyhow::Result<[u8; 64]> {
+    read(path)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("signature must be 64 bytes: {}", path.display()))
+}
+
+/// Read the images given as `slot=path`.
+fn read_images(images: &[String]) -> anyhow::Result<Vec<(u32, Vec<u8>)>> {
+    images
+        .iter()
+        .map(|arg| {
+            let Some((slot, path)) = arg.split_once('=') else {
+                bail!("image must be given as slot=path: {}", arg);
+            };
+            let slot = slot.parse().with_context(|| format!("invalid slot: {}", slot))?;
+            Ok((slot, read(Path::new(path))?))
+        })
+        .collect()
+}
+
+fn sig_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".sig");
+    path.into()
+}
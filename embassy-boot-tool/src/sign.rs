@@ -0,0 +1,62 @@
+//! Keys and signatures.
+
+use anyhow::{bail, Context};
+use ed25519_dalek::{Digest, Sha512, Signer, SigningKey, SECRET_KEY_LENGTH};
+use embassy_boot::{Manifest, ManifestEntry};
+
+/// Generate a new signing key.
+pub fn generate_key() -> SigningKey {
+    SigningKey::generate(&mut rand::rngs::OsRng)
+}
+
+/// Parse a signing key, stored as its 32 raw bytes.
+pub fn signing_key(bytes: &[u8]) -> anyhow::Result<SigningKey> {
+    let bytes: &[u8; SECRET_KEY_LENGTH] = bytes
+        .try_into()
+        .with_context(|| format!("secret key must be {} bytes", SECRET_KEY_LENGTH))?;
+    Ok(SigningKey::from_bytes(bytes))
+}
+
+/// Sign an image or a manifest.
+///
+/// embassy-boot verifies a signature of the SHA-512 digest of the data, rather than of the data
+/// itself, so that the data can be hashed from flash in chunks.
+pub fn sign(key: &SigningKey, data: &[u8]) -> [u8; 64] {
+    let digest = Sha512::digest(data);
+    key.sign(&digest).to_bytes()
+}
+
+/// Build the manifest of a multi-image update from the image of each slot.
+pub fn manifest(images: &[(u32, Vec<u8>)]) -> anyhow::Result<Vec<u8>> {
+    let mut manifest = Manifest::header(images.len() as u32).to_vec();
+    for (slot, image) in images {
+        let entry = ManifestEntry {
+            slot: *slot,
+            image_len: u32::try_from(image.len()).context("image too large")?,
+            hash: Sha512::digest(image).into(),
+        };
+        manifest.extend_from_slice(&entry.to_bytes());
+    }
+    if let Err(e) = Manifest::parse(&manifest) {
+        bail!("invalid manifest: {:?}", e);
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_signing_key() {
+        let key = generate_key();
+        assert_eq!(key.to_bytes(), signing_key(&key.to_bytes()).unwrap().to_bytes());
+        assert!(signing_key(&[0; 31]).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_slots() {
+        assert!(manifest(&[(0, vec![1]), (1, vec![2])]).is_ok());
+        assert!(manifest(&[(1, vec![1]), (1, vec![2])]).is_err());
+    }
+}
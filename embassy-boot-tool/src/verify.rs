@@ -0,0 +1,157 @@
+//! Verification with the firmware updater of the target.
+//!
+//! Images are written to an in-memory DFU partition and verified by
+//! [`BlockingFirmwareUpdater::verify_and_mark_updated`], so that they are hashed and checked by the
+//! same code as on the target, with the signature feature selected for this tool.
+
+use anyhow::{anyhow, bail};
+use embassy_boot::{
+    BlockingFirmwareUpdater, BlockingMultiFirmwareUpdater, FirmwareUpdaterConfig, ImageMetadata, Manifest,
+    MultiFirmwareUpdaterConfig, State, MAX_SLOTS,
+};
+
+use crate::flash::{RamFlash, ERASE_SIZE};
+
+/// Write size of the state partition.
+const STATE_WRITE_SIZE: usize = 4;
+
+/// Verify the signature of an image, and return its metadata if it has any.
+pub fn verify_image(
+    public_key: &[u8; 32],
+    signature: &[u8; 64],
+    image: &[u8],
+) -> anyhow::Result<Option<ImageMetadata>> {
+    let update_len = u32::try_from(image.len())?;
+    let config = FirmwareUpdaterConfig {
+        dfu: RamFlash::<1>::new(image.len()),
+        state: RamFlash::<STATE_WRITE_SIZE>::new(ERASE_SIZE),
+    };
+    let mut aligned = [0; STATE_WRITE_SIZE];
+    let mut updater = BlockingFirmwareUpdater::new(config, &mut aligned);
+
+    updater.write_firmware(0, image).map_err(|e| anyhow!("{:?}", e))?;
+    updater
+        .verify_and_mark_updated(public_key, signature, update_len)
+        .map_err(|e| anyhow!("verification failed: {:?}", e))?;
+    if updater.get_state().map_err(|e| anyhow!("{:?}", e))? != State::Swap {
+        bail!("image was not marked updated");
+    }
+    Ok(updater.read_metadata(update_len).ok())
+}
+
+/// Verify the signature of a manifest, and the image of each slot it lists.
+pub fn verify_manifest(
+    public_key: &[u8; 32],
+    signature: &[u8; 64],
+    manifest: &[u8],
+    images: &[(u32, Vec<u8>)],
+) -> anyhow::Result<()> {
+    let image = |slot: usize| images.iter().find(|(s, _)| *s as usize == slot).map(|(_, image)| image);
+
+    // The updater only hashes the length given in the manifest, so check that the images match it.
+    let entries: Vec<_> = match Manifest::parse(manifest) {
+        Ok(manifest) => manifest.entries().collect(),
+        Err(e) => bail!("invalid manifest: {:?}", e),
+    };
+    for entry in entries.iter() {
+        match image(entry.slot as usize) {
+            Some(image) if image.len() == entry.image_len as usize => {}
+            Some(image) => bail!(
+                "image of slot {} is {} bytes, the manifest lists {} bytes",
+                entry.slot,
+                image.len(),
+                entry.image_len
+            ),
+            None => bail!("missing image of slot {}", entry.slot),
+        }
+    }
+    if let Some((slot, _)) = images.iter().find(|(slot, _)| !entries.iter().any(|e| e.slot == *slot)) {
+        bail!("slot {} is not in the manifest", slot);
+    }
+
+    let config = MultiFirmwareUpdaterConfig {
+        dfu: core::array::from_fn::<_, MAX_SLOTS, _>(|slot| RamFlash::<1>::new(image(slot).map_or(0, Vec::len))),
+        state: RamFlash::<STATE_WRITE_SIZE>::new(ERASE_SIZE),
+    };
+    let mut aligned = [0; STATE_WRITE_SIZE];
+    let mut updater = BlockingMultiFirmwareUpdater::new(config, &mut aligned);
+
+    for (slot, image) in images {
+        updater
+            .write_firmware(*slot as usize, 0, image)
+            .map_err(|e| anyhow!("{:?}", e))?;
+    }
+    updater
+        .verify_and_mark_updated(public_key, signature, manifest)
+        .map_err(|e| anyhow!("verification failed: {:?}", e))?;
+    if updater.get_state().map_err(|e| anyhow!("{:?}", e))? != State::Swap {
+        bail!("images were not marked updated");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_boot::Version;
+
+    use super::*;
+    use crate::image::append_metadata;
+    use crate::sign::{generate_key, manifest, sign};
+
+    #[test]
+    fn can_verify_image() {
+        let key = generate_key();
+        let public_key = key.verifying_key().to_bytes();
+        let image = vec![0xAA; 3 * ERASE_SIZE + 5];
+        let signature = sign(&key, &image);
+
+        assert_eq!(None, verify_image(&public_key, &signature, &image).unwrap());
+
+        let mut tampered = image.clone();
+        tampered[ERASE_SIZE] ^= 1;
+        assert!(verify_image(&public_key, &signature, &tampered).is_err());
+
+        let other_key = generate_key().verifying_key().to_bytes();
+        assert!(verify_image(&other_key, &signature, &image).is_err());
+    }
+
+    #[test]
+    fn can_verify_image_with_metadata() {
+        let key = generate_key();
+        let version = Version {
+            major: 1,
+            minor: 2,
+            patch: 3,
+        };
+        let mut image = vec![0x55; 1000];
+        append_metadata(&mut image, version, 0x1234, 7).unwrap();
+        let signature = sign(&key, &image);
+
+        let metadata = verify_image(&key.verifying_key().to_bytes(), &signature, &image)
+            .unwrap()
+            .unwrap();
+        assert_eq!(version, metadata.version);
+        assert_eq!(1000, metadata.image_len);
+        assert_eq!(7, metadata.security_counter);
+    }
+
+    #[test]
+    fn can_verify_manifest() {
+        let key = generate_key();
+        let public_key = key.verifying_key().to_bytes();
+        let images = [(0, vec![0xAA; 5000]), (3, vec![0x55; 100])];
+        let manifest = manifest(&images).unwrap();
+        let signature = sign(&key, &manifest);
+
+        verify_manifest(&public_key, &signature, &manifest, &images).unwrap();
+
+        let mut tampered = images.clone();
+        tampered[1].1[0] = 0;
+        assert!(verify_manifest(&public_key, &signature, &manifest, &tampered).is_err());
+        assert!(verify_manifest(&public_key, &signature, &manifest, &images[..1]).is_err());
+
+        let mut longer = images.clone();
+        longer[1].1.push(0);
+        assert!(verify_manifest(&public_key, &signature, &manifest, &longer).is_err());
+    }
+}
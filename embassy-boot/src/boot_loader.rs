@@ -4,8 +4,14 @@ use embassy_embedded_hal::flash::partition::BlockingPartition;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
+#[cfg(feature = "encryption")]
+use zeroize::Zeroize;
 
-use crate::{State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
+use crate::compress::{DecompressError, Decompressor, COMPRESSED_MAGIC};
+#[cfg(feature = "encryption")]
+use crate::encryption::{nonce_offset, DeviceKey, ImageCipher, NONCE_LEN, PREVIOUS_IMAGE_POSITION};
+use crate::metadata::state_progress_len;
+use crate::{AlignedBuffer, State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
 
 /// Errors returned by bootloader
 #[derive(PartialEq, Eq, Debug)]
@@ -14,6 +20,11 @@ pub enum BootError {
     Flash(NorFlashErrorKind),
     /// Invalid bootloader magic
     BadMagic,
+    /// Compressed image errors.
+    Decompress(DecompressError),
+    /// The update is encrypted or compressed, which is not supported by the
+    /// [`MultiBootLoader`](crate::MultiBootLoader).
+    Unsupported,
 }
 
 #[cfg(feature = "defmt")]
@@ -22,6 +33,8 @@ impl defmt::Format for BootError {
         match self {
             BootError::Flash(_) => defmt::write!(fmt, "BootError::Flash(_)"),
             BootError::BadMagic => defmt::write!(fmt, "BootError::BadMagic"),
+            BootError::Decompress(e) => defmt::write!(fmt, "BootError::Decompress({})", e),
+            BootError::Unsupported => defmt::write!(fmt, "BootError::Unsupported"),
         }
     }
 }
@@ -138,13 +151,25 @@ pub struct BootLoader<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> {
     /// | Range    | Description                                                                      |
     /// | 0..1     | Magic indicating bootloader state. BOOT_MAGIC means boot, SWAP_MAGIC means swap. |
     /// | 1..2     | Progress validity. ERASE_VALUE means valid, !ERASE_VALUE means invalid.          |
+    /// |          | SWAP_MAGIC with invalid progress means reverted.                                 |
     /// | 2..2 + N | Progress index used while swapping or reverting      
+    /// | 2 + N..  | Boot attempts of the swapped image, one per word
+    ///
+    /// With the `metadata` feature, if the state partition spans several erase sectors, its last
+    /// sector holds the image metadata log of the firmware updater, and is never erased by the
+    /// bootloader.
     state: STATE,
+    max_boot_attempts: u8,
+    /// Offset of the progress in words, after the progress of the previous slots of a
+    /// [`MultiBootLoader`](crate::MultiBootLoader).
+    progress_offset: usize,
+    #[cfg(feature = "encryption")]
+    cipher: Option<ImageCipher>,
 }
 
 impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, STATE> {
     /// Get the page size which is the "unit of operation" within the bootloader.
-    const PAGE_SIZE: u32 = if ACTIVE::ERASE_SIZE > DFU::ERASE_SIZE {
+    pub(crate) const PAGE_SIZE: u32 = if ACTIVE::ERASE_SIZE > DFU::ERASE_SIZE {
         ACTIVE::ERASE_SIZE as u32
     } else {
         DFU::ERASE_SIZE as u32
@@ -159,6 +184,38 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
             active: config.active,
             dfu: config.dfu,
             state: config.state,
+            max_boot_attempts: 1,
+            progress_offset: 0,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+        }
+    }
+
+    /// Set the number of times a swapped image is booted before it is reverted, unless the
+    /// application marks it booted. Defaults to 1, reverting the image on the first reset.
+    pub fn with_max_boot_attempts(mut self, max_boot_attempts: u8) -> Self {
+        assert!(max_boot_attempts > 0);
+        self.max_boot_attempts = max_boot_attempts;
+        self
+    }
+
+    /// Create a bootloader for one slot of a multi-image update, with its progress starting
+    /// `progress_offset` words further in the state partition.
+    pub(crate) fn new_slot(
+        active: ACTIVE,
+        dfu: DFU,
+        state: STATE,
+        max_boot_attempts: u8,
+        progress_offset: usize,
+    ) -> Self {
+        Self {
+            active,
+            dfu,
+            state,
+            max_boot_attempts,
+            progress_offset,
+            #[cfg(feature = "encryption")]
+            cipher: None,
         }
     }
 
@@ -234,7 +291,40 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
     /// |    Active |            3 |      1 |      2 |      3 |      - |
     /// |       DFU |            3 |      4 |      5 |      6 |      3 |
     ///
+    /// ## BOOT ATTEMPTS
+    ///
+    /// The swapped image is booted up to `max_boot_attempts` times, see
+    /// [`with_max_boot_attempts`](Self::with_max_boot_attempts), before being reverted, each
+    /// attempt being counted in the state partition. Once reverted,
+    /// [`State::Reverted`] is returned, and reported to the application until it marks the boot
+    /// successful.
+    ///
     pub fn prepare_boot(&mut self, aligned_buf: &mut [u8]) -> Result<State, BootError> {
+        self.prepare_boot_with_window(aligned_buf, &mut [])
+    }
+
+    /// Perform necessary boot preparations like swapping images, with support for compressed
+    /// images kept in the DFU partition.
+    ///
+    /// This works like [`prepare_boot`](Self::prepare_boot), except when the DFU partition holds a
+    /// compressed image (starting with [`COMPRESSED_MAGIC`]): the image is then decompressed into the
+    /// active partition while swapping, using `window` as the decompression window (see [`Decompressor`]).
+    ///
+    /// ## INSTALLING
+    ///
+    /// A compressed image is installed like an image is swapped, page by page starting from the
+    /// last one: each active page is copied to the next DFU page, then replaced by the matching page
+    /// of the decompressed image. The previous image thus ends up in the DFU partition as after a
+    /// swap, progress is recorded the same way, and [`State::Swap`] is returned. The image is
+    /// reverted unless the application marks it booted.
+    ///
+    /// Each page is decompressed from the start of the compressed data, which stays in the DFU
+    /// pages that have not been overwritten yet: installing takes time quadratic in the image size.
+    /// This requires the first `k` pages of the image to be compressed in at most `k` pages, which
+    /// is checked before the active partition is touched, along with the whole compressed data.
+    /// If the image cannot be installed, the update is dropped by setting the state to boot, so that
+    /// the current image keeps booting, and the [`BootError::Decompress`] error is returned.
+    pub fn prepare_boot_with_window(&mut self, aligned_buf: &mut [u8], window: &mut [u8]) -> Result<State, BootError> {
         const {
             core::assert!(Self::PAGE_SIZE % ACTIVE::WRITE_SIZE as u32 == 0);
             core::assert!(Self::PAGE_SIZE % ACTIVE::ERASE_SIZE as u32 == 0);
@@ -250,6 +340,10 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
 
         // Ensure our partitions are able to handle boot operations
         assert_partitions(&self.active, &self.dfu, &self.state, Self::PAGE_SIZE);
+        if self.max_boot_attempts > 1 {
+            let attempts_end = self.boot_attempts_index() + self.max_boot_attempts as usize - 1;
+            assert!(attempts_end <= self.progress_len() / STATE::WRITE_SIZE);
+        }
 
         // Copy contents from partition N to active
         let state = self.read_state(aligned_buf)?;
@@ -259,31 +353,220 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
             // since the app has failed to mark boot as successful
             //
             if !self.is_swapped(aligned_buf)? {
-                trace!("Swapping");
-                self.swap(aligned_buf)?;
-                trace!("Swapping done");
+                if !self.is_encrypted() && self.is_compressed()? {
+                    if self.current_progress(aligned_buf)? == 0 {
+                        if let Err(BootError::Decompress(e)) = self.decompress(window, aligned_buf, None) {
+                            trace!("Dropping update that cannot be installed");
+                            self.set_magic(BOOT_MAGIC, aligned_buf)?;
+                            return Err(BootError::Decompress(e));
+                        }
+                    }
+
+                    trace!("Installing");
+                    self.install(aligned_buf, window)?;
+                    trace!("Installing done");
+                } else {
+                    trace!("Swapping");
+                    self.swap(aligned_buf)?;
+                    trace!("Swapping done");
+                }
             } else {
+                let attempts = self.boot_attempts(aligned_buf)?;
+                if attempts + 1 < self.max_boot_attempts as usize {
+                    trace!("Boot attempt {}", attempts + 2);
+                    self.update_boot_attempts(attempts, aligned_buf)?;
+                    return Ok(State::Swap);
+                }
+
                 trace!("Reverting");
                 self.revert(aligned_buf)?;
+                self.invalidate_progress(aligned_buf)?;
+                return Ok(State::Reverted);
+            }
+        }
+        Ok(state)
+    }
 
-                let state_word = &mut aligned_buf[..STATE::WRITE_SIZE];
+    /// Perform necessary boot preparations like swapping images, with an encrypted DFU partition.
+    ///
+    /// This works like [`prepare_boot`](Self::prepare_boot), except that the DFU partition holds
+    /// images encrypted with the key from `key` (see [`ImageCipher`]), and the nonce written by
+    /// `mark_updated_encrypted` of the firmware updater. Pages are decrypted while swapping them
+    /// into the active partition, and the previous image is encrypted while swapping it into the
+    /// DFU partition, so that it can be reverted.
+    ///
+    /// The nonce is kept at the end of the progress in the state partition, before the word
+    /// holding the slots of a multi-image update. The state partition must have room for both.
+    #[cfg(feature = "encryption")]
+    pub fn prepare_boot_encrypted(
+        &mut self,
+        aligned_buf: &mut [u8],
+        key: &mut impl DeviceKey,
+    ) -> Result<State, BootError> {
+        let nonce_offset = nonce_offset(self.progress_len(), STATE::WRITE_SIZE);
+        let attempts_end = self.boot_attempts_index() + self.max_boot_attempts as usize - 1;
+        assert!(attempts_end <= nonce_offset / STATE::WRITE_SIZE);
+
+        let nonce_buf = &mut aligned_buf[..NONCE_LEN.next_multiple_of(STATE::WRITE_SIZE)];
+        self.state.read(nonce_offset as u32, nonce_buf)?;
+        let nonce = unwrap!(nonce_buf[..NONCE_LEN].try_into());
+
+        let mut device_key = key.key();
+        self.cipher = Some(ImageCipher::new(&device_key, &nonce));
+        device_key.zeroize();
+        let state = self.prepare_boot(aligned_buf);
+        self.cipher = None;
+        state
+    }
 
-                // Invalidate progress
-                state_word.fill(!STATE_ERASE_VALUE);
-                self.state.write(STATE::WRITE_SIZE as u32, state_word)?;
+    #[cfg(feature = "encryption")]
+    fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
 
-                // Clear magic and progress
-                self.state.erase(0, self.state.capacity() as u32)?;
+    #[cfg(not(feature = "encryption"))]
+    fn is_encrypted(&self) -> bool {
+        false
+    }
 
-                // Set magic
-                state_word.fill(BOOT_MAGIC);
-                self.state.write(0, state_word)?;
+    // Encrypt or decrypt data copied between the active and DFU partitions. The new image is at the
+    // same offset in both partitions, while the previous one is one page further in DFU.
+    #[allow(unused_variables)]
+    fn apply_keystream(&self, active_offset: u32, dfu_offset: u32, data: &mut [u8]) {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let position = if dfu_offset == active_offset {
+                active_offset as u64
+            } else {
+                PREVIOUS_IMAGE_POSITION + active_offset as u64
+            };
+            cipher.apply_keystream(position, data);
+        }
+    }
+
+    pub(crate) fn set_magic(&mut self, magic: u8, aligned_buf: &mut [u8]) -> Result<(), BootError> {
+        self.invalidate_progress(aligned_buf)?;
+        let state_word = &mut aligned_buf[..STATE::WRITE_SIZE];
+
+        // Clear magic and progress
+        self.state.erase(0, self.progress_len() as u32)?;
+
+        // Set magic
+        state_word.fill(magic);
+        self.state.write(0, state_word)?;
+        Ok(())
+    }
+
+    // Invalidate progress, unless already done before losing power. With the swap magic still set,
+    // this marks the update reverted in a single write, see `read_state`.
+    pub(crate) fn invalidate_progress(&mut self, aligned_buf: &mut [u8]) -> Result<(), BootError> {
+        let state_word = &mut aligned_buf[..STATE::WRITE_SIZE];
+        self.state.read(STATE::WRITE_SIZE as u32, state_word)?;
+        if !state_word.iter().any(|&b| b != STATE_ERASE_VALUE) {
+            state_word.fill(!STATE_ERASE_VALUE);
+            self.state.write(STATE::WRITE_SIZE as u32, state_word)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_compressed(&mut self) -> Result<bool, BootError> {
+        let mut magic = AlignedBuffer([0; 32]);
+        assert_eq!(0, magic.0.len() % DFU::READ_SIZE);
+        self.dfu.read(0, &mut magic.0)?;
+        Ok(magic.0.starts_with(&COMPRESSED_MAGIC))
+    }
+
+    // Decompress the image in DFU from its start. With `page`, the output of that page is written to
+    // the erased active page. Without, the image is only checked: it must fit in the active partition,
+    // and its first `k` pages must come from at most `k` pages of DFU, so that installing never
+    // overwrites compressed data that is still needed.
+    fn decompress(&mut self, window: &mut [u8], aligned_buf: &mut [u8], page: Option<u32>) -> Result<(), BootError> {
+        let page_size = Self::PAGE_SIZE as usize;
+        let mut decompressor = Decompressor::new(window);
+        let mut input = AlignedBuffer([0; 32]);
+        let mut input_offset = 0;
+        let mut consumed = 0;
+        let mut produced = 0;
+        let mut fill = 0;
+
+        loop {
+            if input_offset >= self.dfu.capacity() {
+                return Err(BootError::Decompress(DecompressError::Truncated));
+            }
+            self.dfu.read(input_offset as u32, &mut input.0)?;
+            input_offset += input.0.len();
+
+            let mut data = &input.0[..];
+            loop {
+                let (n, m) = decompressor
+                    .decode(data, &mut aligned_buf[fill..])
+                    .map_err(BootError::Decompress)?;
+                data = &data[n..];
+                consumed += n;
+                fill += m;
+
+                let Some(image_len) = decompressor.image_len() else {
+                    break;
+                };
+                if image_len as usize > self.active.capacity() {
+                    return Err(BootError::Decompress(DecompressError::Overrun));
+                }
+                if page.is_some_and(|page| image_len <= page * Self::PAGE_SIZE) {
+                    return Ok(());
+                }
+                if fill < aligned_buf.len() && !decompressor.is_done() {
+                    break;
+                }
+
+                // The buffer is a part of a page, as its length divides the page size.
+                let offset = produced;
+                produced += fill;
+                if consumed > produced.next_multiple_of(page_size).max(page_size) {
+                    return Err(BootError::Decompress(DecompressError::Expanding));
+                }
+                if let Some(page) = page {
+                    if offset / page_size == page as usize {
+                        let len = fill.next_multiple_of(ACTIVE::WRITE_SIZE);
+                        aligned_buf[fill..len].fill(STATE_ERASE_VALUE);
+                        self.active.write(offset as u32, &aligned_buf[..len])?;
+                    }
+                    if produced >= (page as usize + 1) * page_size {
+                        return Ok(());
+                    }
+                }
+                fill = 0;
+
+                if decompressor.is_done() {
+                    return Ok(());
+                }
             }
         }
-        Ok(state)
     }
 
-    fn is_swapped(&mut self, aligned_buf: &mut [u8]) -> Result<bool, BootError> {
+    fn install(&mut self, aligned_buf: &mut [u8], window: &mut [u8]) -> Result<(), BootError> {
+        let page_count = self.active.capacity() as u32 / Self::PAGE_SIZE;
+        for page_num in 0..page_count {
+            let progress_index = (page_num * 2) as usize;
+            let page = page_count - 1 - page_num;
+
+            // Copy active page to the 'next' DFU page, as when swapping. The compressed data it held
+            // produces the following pages, which are already installed.
+            let active_offset = page * Self::PAGE_SIZE;
+            let dfu_to_offset = (page + 1) * Self::PAGE_SIZE;
+            self.copy_page_once_to_dfu(progress_index, active_offset, dfu_to_offset, aligned_buf)?;
+
+            // Decompress the page into the active page
+            if self.current_progress(aligned_buf)? <= progress_index + 1 {
+                self.active.erase(active_offset, active_offset + Self::PAGE_SIZE)?;
+                self.decompress(window, aligned_buf, Some(page))?;
+                self.update_progress(progress_index + 1, aligned_buf)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn is_swapped(&mut self, aligned_buf: &mut [u8]) -> Result<bool, BootError> {
         let page_count = self.active.capacity() / Self::PAGE_SIZE as usize;
         let progress = self.current_progress(aligned_buf)?;
 
@@ -292,7 +575,7 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
 
     fn current_progress(&mut self, aligned_buf: &mut [u8]) -> Result<usize, BootError> {
         let write_size = STATE::WRITE_SIZE as u32;
-        let max_index = ((self.state.capacity() - STATE::WRITE_SIZE) / STATE::WRITE_SIZE) - 2;
+        let max_index = ((self.progress_len() - STATE::WRITE_SIZE) / STATE::WRITE_SIZE) - 2 - self.progress_offset;
         let state_word = &mut aligned_buf[..write_size as usize];
 
         self.state.read(write_size, state_word)?;
@@ -302,7 +585,8 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
         }
 
         for index in 0..max_index {
-            self.state.read((2 + index) as u32 * write_size, state_word)?;
+            self.state
+                .read((2 + self.progress_offset + index) as u32 * write_size, state_word)?;
 
             if state_word.iter().any(|&b| b == STATE_ERASE_VALUE) {
                 return Ok(index);
@@ -311,11 +595,45 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
         Ok(max_index)
     }
 
+    // The boot attempts follow the swap and revert progress.
+    pub(crate) fn boot_attempts_index(&self) -> usize {
+        2 + self.progress_offset + 4 * (self.active.capacity() / Self::PAGE_SIZE as usize)
+    }
+
+    pub(crate) fn boot_attempts(&mut self, aligned_buf: &mut [u8]) -> Result<usize, BootError> {
+        let write_size = STATE::WRITE_SIZE;
+        let index = self.boot_attempts_index();
+        let state_word = &mut aligned_buf[..write_size];
+
+        for attempt in 0..self.max_boot_attempts as usize - 1 {
+            self.state.read(((index + attempt) * write_size) as u32, state_word)?;
+            if state_word.iter().any(|&b| b == STATE_ERASE_VALUE) {
+                return Ok(attempt);
+            }
+        }
+        Ok(self.max_boot_attempts as usize - 1)
+    }
+
+    pub(crate) fn update_boot_attempts(&mut self, attempt: usize, aligned_buf: &mut [u8]) -> Result<(), BootError> {
+        let write_size = STATE::WRITE_SIZE;
+        let state_word = &mut aligned_buf[..write_size];
+        state_word.fill(!STATE_ERASE_VALUE);
+        self.state
+            .write(((self.boot_attempts_index() + attempt) * write_size) as u32, state_word)?;
+        Ok(())
+    }
+
+    pub(crate) fn progress_len(&self) -> usize {
+        state_progress_len(self.state.capacity(), STATE::ERASE_SIZE)
+    }
+
     fn update_progress(&mut self, progress_index: usize, aligned_buf: &mut [u8]) -> Result<(), BootError> {
         let state_word = &mut aligned_buf[..STATE::WRITE_SIZE];
         state_word.fill(!STATE_ERASE_VALUE);
-        self.state
-            .write((2 + progress_index) as u32 * STATE::WRITE_SIZE as u32, state_word)?;
+        self.state.write(
+            (2 + self.progress_offset + progress_index) as u32 * STATE::WRITE_SIZE as u32,
+            state_word,
+        )?;
         Ok(())
     }
 
@@ -333,6 +651,11 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
 
             for offset_in_page in (0..page_size).step_by(aligned_buf.len()) {
                 self.dfu.read(from_offset + offset_in_page as u32, aligned_buf)?;
+                self.apply_keystream(
+                    to_offset + offset_in_page as u32,
+                    from_offset + offset_in_page as u32,
+                    aligned_buf,
+                );
                 self.active.write(to_offset + offset_in_page as u32, aligned_buf)?;
             }
 
@@ -355,6 +678,11 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
 
             for offset_in_page in (0..page_size).step_by(aligned_buf.len()) {
                 self.active.read(from_offset + offset_in_page as u32, aligned_buf)?;
+                self.apply_keystream(
+                    from_offset + offset_in_page as u32,
+                    to_offset + offset_in_page as u32,
+                    aligned_buf,
+                );
                 self.dfu.write(to_offset + offset_in_page as u32, aligned_buf)?;
             }
 
@@ -363,7 +691,7 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
         Ok(())
     }
 
-    fn swap(&mut self, aligned_buf: &mut [u8]) -> Result<(), BootError> {
+    pub(crate) fn swap(&mut self, aligned_buf: &mut [u8]) -> Result<(), BootError> {
         let page_count = self.active.capacity() as u32 / Self::PAGE_SIZE;
         for page_num in 0..page_count {
             let progress_index = (page_num * 2) as usize;
@@ -384,7 +712,7 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
         Ok(())
     }
 
-    fn revert(&mut self, aligned_buf: &mut [u8]) -> Result<(), BootError> {
+    pub(crate) fn revert(&mut self, aligned_buf: &mut [u8]) -> Result<(), BootError> {
         let page_count = self.active.capacity() as u32 / Self::PAGE_SIZE;
         for page_num in 0..page_count {
             let progress_index = (page_count * 2 + page_num * 2) as usize;
@@ -403,12 +731,19 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
         Ok(())
     }
 
-    fn read_state(&mut self, aligned_buf: &mut [u8]) -> Result<State, BootError> {
+    pub(crate) fn read_state(&mut self, aligned_buf: &mut [u8]) -> Result<State, BootError> {
         let state_word = &mut aligned_buf[..STATE::WRITE_SIZE];
         self.state.read(0, state_word)?;
 
         if !state_word.iter().any(|&b| b != SWAP_MAGIC) {
-            Ok(State::Swap)
+            // The progress is invalidated once the update is reverted, the magic is kept so that
+            // the state is never lost on power loss.
+            self.state.read(STATE::WRITE_SIZE as u32, state_word)?;
+            if state_word.iter().any(|&b| b != STATE_ERASE_VALUE) {
+                Ok(State::Reverted)
+            } else {
+                Ok(State::Swap)
+            }
         } else if !state_word.iter().any(|&b| b != DFU_DETACH_MAGIC) {
             Ok(State::DfuDetach)
         } else {
@@ -417,7 +752,7 @@ impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash> BootLoader<ACTIVE, DFU, S
     }
 }
 
-fn assert_partitions<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash>(
+pub(crate) fn assert_partitions<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash>(
     active: &ACTIVE,
     dfu: &DFU,
     state: &STATE,
@@ -427,7 +762,8 @@ fn assert_partitions<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash>(
     assert_eq!(dfu.capacity() as u32 % page_size, 0);
     // DFU partition has to be bigger than ACTIVE partition to handle swap algorithm
     assert!(dfu.capacity() as u32 - active.capacity() as u32 >= page_size);
-    assert!(2 + 2 * (active.capacity() as u32 / page_size) <= state.capacity() as u32 / STATE::WRITE_SIZE as u32);
+    let progress_len = state_progress_len(state.capacity(), STATE::ERASE_SIZE);
+    assert!(2 + 2 * (active.capacity() as u32 / page_size) <= progress_len as u32 / STATE::WRITE_SIZE as u32);
 }
 
 #[cfg(test)]
@@ -0,0 +1,482 @@
+//! Compressed firmware images.
+
+/// Magic bytes at the start of a compressed image.
+pub const COMPRESSED_MAGIC: [u8; 4] = *b"EBCZ";
+
+const HEADER_LEN: usize = 16;
+
+const MIN_WINDOW_BITS: u8 = 4;
+const MAX_WINDOW_BITS: u8 = 15;
+const MIN_LOOKAHEAD_BITS: u8 = 3;
+
+/// Errors returned when decompressing an image.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecompressError {
+    /// The image does not start with [`COMPRESSED_MAGIC`].
+    BadMagic,
+    /// The window or lookahead size is not supported, or the window buffer is too small.
+    UnsupportedParameters,
+    /// The compressed data produces more data than announced, or than fits in the target.
+    Overrun,
+    /// The compressed data ended before the whole image was produced.
+    Truncated,
+    /// Part of the compressed data is larger than the image it produces, so that the bootloader
+    /// cannot install the image from a compressed DFU partition.
+    Expanding,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DecodeState {
+    Header,
+    Tag,
+    Literal,
+    Index,
+    Count { index: u16 },
+    Copy { index: u16, count: u16 },
+    Done,
+}
+
+/// Streaming decompressor.
+///
+/// Compressed images use the LZSS encoding of [heatshrink](https://github.com/atomicobject/heatshrink),
+/// so that they can be decompressed with constant memory: back-references only reach into a window
+/// of the last `2^window_bits` output bytes, which the caller provides as a buffer. The compressed
+/// data is preceded by a header:
+///
+/// | Field          | Size            | Description                                               |
+/// |----------------|-----------------|-----------------------------------------------------------|
+/// | magic          | 4               | [`COMPRESSED_MAGIC`]                                      |
+/// | window_bits    | 1               | Window size `W`, between 4 and 15                         |
+/// | lookahead_bits | 1               | Lookahead size `L`, at least 3 and lower than `W`         |
+/// | reserved       | 2               | Zero                                                      |
+/// | compressed_len | 4               | Length of the compressed data, little endian              |
+/// | image_len      | 4               | Length of the decompressed image, little endian           |
+/// | data           | compressed_len  | heatshrink compressed data                                |
+///
+/// The data is a stream of bits, most significant bit first. A `1` bit is followed by an 8-bit
+/// literal byte, and a `0` bit by a back-reference: a `W`-bit offset and an `L`-bit length, both
+/// stored minus 1. This is the output of `heatshrink -e -w W -l L`.
+pub struct Decompressor<'w> {
+    window: &'w mut [u8],
+    state: DecodeState,
+    header: [u8; HEADER_LEN],
+    header_len: usize,
+    window_bits: u8,
+    lookahead_bits: u8,
+    compressed_len: u32,
+    image_len: u32,
+    consumed: u32,
+    produced: u32,
+    bits: u32,
+    bit_count: u8,
+}
+
+impl<'w> Decompressor<'w> {
+    /// Create a new decompressor, expecting the start of a compressed image.
+    ///
+    /// The length of `window` must be at least `2^window_bits` of the image.
+    pub fn new(window: &'w mut [u8]) -> Self {
+        Self {
+            window,
+            state: DecodeState::Header,
+            header: [0; HEADER_LEN],
+            header_len: 0,
+            window_bits: 0,
+            lookahead_bits: 0,
+            compressed_len: 0,
+            image_len: 0,
+            consumed: 0,
+            produced: 0,
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Length of the decompressed image, once the header has been decoded.
+    pub fn image_len(&self) -> Option<u32> {
+        match self.state {
+            DecodeState::Header => None,
+            _ => Some(self.image_len),
+        }
+    }
+
+    /// Whether the whole image has been produced.
+    pub fn is_done(&self) -> bool {
+        self.state == DecodeState::Done
+    }
+
+    /// Decompress the next part of the image.
+    ///
+    /// Returns the number of bytes consumed from `input` and written to `output`. Decompression
+    /// stops when `input` is exhausted, `output` is full, or the whole image has been produced.
+    /// Input following the end of the compressed data is not consumed.
+    pub fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize), DecompressError> {
+        let mut consumed = 0;
+        let mut produced = 0;
+
+        loop {
+            match self.state {
+                DecodeState::Header => {
+                    let n = core::cmp::min(HEADER_LEN - self.header_len, input.len());
+                    self.header[self.header_len..self.header_len + n].copy_from_slice(&input[..n]);
+                    self.header_len += n;
+                    consumed += n;
+
+                    if self.header_len < HEADER_LEN {
+                        break;
+                    }
+                    self.decode_header()?;
+                }
+                DecodeState::Tag => {
+                    if produced == output.len() {
+                        break;
+                    }
+                    match self.take_bits(1, input, &mut consumed)? {
+                        Some(1) => self.state = DecodeState::Literal,
+                        Some(_) => self.state = DecodeState::Index,
+                        None => break,
+                    }
+                }
+                DecodeState::Literal => {
+                    if produced == output.len() {
+                        break;
+                    }
+                    match self.take_bits(8, input, &mut consumed)? {
+                        Some(byte) => {
+                            output[produced] = byte as u8;
+                            produced += 1;
+                            self.push(byte as u8);
+                            self.state = DecodeState::Tag;
+                        }
+                        None => break,
+                    }
+                }
+                DecodeState::Index => match self.take_bits(self.window_bits, input, &mut consumed)? {
+                    Some(index) => self.state = DecodeState::Count { index: index + 1 },
+                    None => break,
+                },
+                DecodeState::Count { index } => match self.take_bits(self.lookahead_bits, input, &mut consumed)? {
+                    Some(count) => {
+                        let count = count + 1;
+                        if count as u32 > self.image_len - self.produced {
+                            return Err(DecompressError::Overrun);
+                        }
+                        self.state = DecodeState::Copy { index, count };
+                    }
+                    None => break,
+                },
+                DecodeState::Copy { index, count } => {
+                    let n = core::cmp::min(count as usize, output.len() - produced);
+                    if n == 0 {
+                        break;
+                    }
+                    for _ in 0..n {
+                        let byte = self.window[self.produced.wrapping_sub(index as u32) as usize & self.mask()];
+                        output[produced] = byte;
+                        produced += 1;
+                        self.push(byte);
+                    }
+                    self.state = match count - n as u16 {
+                        0 => DecodeState::Tag,
+                        count => DecodeState::Copy { index, count },
+                    };
+                }
+                DecodeState::Done => break,
+            }
+
+            if self.state == DecodeState::Tag && self.produced == self.image_len {
+                self.state = DecodeState::Done;
+            }
+        }
+
+        Ok((consumed, produced))
+    }
+
+    fn decode_header(&mut self) -> Result<(), DecompressError> {
+        let header = self.header;
+        let word = |i: usize| u32::from_le_bytes(unwrap!(header[4 * i..4 * i + 4].try_into()));
+
+        if header[..4] != COMPRESSED_MAGIC {
+            return Err(DecompressError::BadMagic);
+        }
+
+        let (window_bits, lookahead_bits) = (header[4], header[5]);
+        if !(MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits)
+            || !(MIN_LOOKAHEAD_BITS..window_bits).contains(&lookahead_bits)
+            || self.window.len() < 1 << window_bits
+        {
+            return Err(DecompressError::UnsupportedParameters);
+        }
+
+        self.window_bits = window_bits;
+        self.lookahead_bits = lookahead_bits;
+        self.compressed_len = word(2);
+        self.image_len = word(3);
+        // heatshrink treats the window as zero-filled before the start of the image.
+        self.window[..1 << window_bits].fill(0);
+        self.state = DecodeState::Tag;
+        Ok(())
+    }
+
+    fn take_bits(&mut self, count: u8, input: &[u8], consumed: &mut usize) -> Result<Option<u16>, DecompressError> {
+        while self.bit_count < count {
+            if *consumed == input.len() {
+                return Ok(None);
+            }
+            if self.consumed == self.compressed_len {
+                return Err(DecompressError::Truncated);
+            }
+            self.bits = (self.bits << 8) | input[*consumed] as u32;
+            self.bit_count += 8;
+            self.consumed += 1;
+            *consumed += 1;
+        }
+
+        self.bit_count -= count;
+        Ok(Some(((self.bits >> self.bit_count) & ((1 << count) - 1)) as u16))
+    }
+
+    fn push(&mut self, byte: u8) {
+        let mask = self.mask();
+        self.window[self.produced as usize & mask] = byte;
+        self.produced += 1;
+    }
+
+    fn mask(&self) -> usize {
+        (1 << self.window_bits) - 1
+    }
+}
+
+/// State of a compressed image being written by a firmware updater.
+///
+/// The decompressed image is staged in `buf` and written to the DFU partition whenever it is full.
+/// The length of `buf` must be a multiple of the DFU partition's `WRITE_SIZE`, and it must follow
+/// the alignment rules of the DFU partition.
+pub struct Decompression<'b> {
+    pub(crate) decompressor: Decompressor<'b>,
+    pub(crate) buf: &'b mut [u8],
+    pub(crate) fill: usize,
+    pub(crate) offset: usize,
+}
+
+impl<'b> Decompression<'b> {
+    /// Create a new decompression state, with a window for the decompressor and a buffer for
+    /// staging the decompressed image.
+    pub fn new(window: &'b mut [u8], buf: &'b mut [u8]) -> Self {
+        assert!(!buf.is_empty());
+        Self {
+            decompressor: Decompressor::new(window),
+            buf,
+            fill: 0,
+            offset: 0,
+        }
+    }
+
+    /// Length of the decompressed image, once the header has been received.
+    pub fn image_len(&self) -> Option<u32> {
+        self.decompressor.image_len()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    struct BitWriter {
+        data: Vec<u8>,
+        bits: u8,
+    }
+
+    impl BitWriter {
+        fn push(&mut self, value: u16, count: u8) {
+            for i in (0..count).rev() {
+                if self.bits == 0 {
+                    self.data.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                *self.data.last_mut().unwrap() |= bit << (7 - self.bits);
+                self.bits = (self.bits + 1) % 8;
+            }
+        }
+    }
+
+    /// Compress `image` with a greedy search over the window.
+    pub(crate) fn compress(image: &[u8], window_bits: u8, lookahead_bits: u8) -> Vec<u8> {
+        let mut writer = BitWriter {
+            data: Vec::new(),
+            bits: 0,
+        };
+
+        let mut pos = 0;
+        while pos < image.len() {
+            let max_len = core::cmp::min(1 << lookahead_bits, image.len() - pos);
+            let mut best = (0, 0);
+            for offset in 1..=core::cmp::min(1 << window_bits, pos) {
+                let len = (0..max_len)
+                    .take_while(|&i| image[pos - offset + i] == image[pos + i])
+                    .count();
+                if len > best.1 {
+                    best = (offset, len);
+                }
+            }
+
+            if best.1 > 1 {
+                writer.push(0, 1);
+                writer.push(best.0 as u16 - 1, window_bits);
+                writer.push(best.1 as u16 - 1, lookahead_bits);
+                pos += best.1;
+            } else {
+                writer.push(1, 1);
+                writer.push(image[pos] as u16, 8);
+                pos += 1;
+            }
+        }
+
+        let mut compressed = Vec::new();
+        compressed.extend_from_slice(&COMPRESSED_MAGIC);
+        compressed.extend_from_slice(&[window_bits, lookahead_bits, 0, 0]);
+        compressed.extend_from_slice(&(writer.data.len() as u32).to_le_bytes());
+        compressed.extend_from_slice(&(image.len() as u32).to_le_bytes());
+        compressed.extend_from_slice(&writer.data);
+        compressed
+    }
+
+    /// Build an image with both repeated and unique sections.
+    pub(crate) fn test_image(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| match (i / 64) % 3 {
+                0 => (i % 7) as u8,
+                1 => (i * 31 / 5) as u8,
+                _ => 0xAA,
+            })
+            .collect()
+    }
+
+    fn decompress(
+        compressed: &[u8],
+        window: &mut [u8],
+        chunk_size: usize,
+        output_size: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        let mut decompressor = Decompressor::new(window);
+        let mut image = Vec::new();
+        let mut output = std::vec![0; output_size];
+        for mut chunk in compressed.chunks(chunk_size) {
+            loop {
+                let (n, m) = decompressor.decode(chunk, &mut output)?;
+                chunk = &chunk[n..];
+                image.extend_from_slice(&output[..m]);
+                if n == 0 && m == 0 {
+                    break;
+                }
+            }
+            if !chunk.is_empty() {
+                return Err(DecompressError::Overrun);
+            }
+        }
+        if !decompressor.is_done() {
+            return Err(DecompressError::Truncated);
+        }
+        Ok(image)
+    }
+
+    #[test]
+    fn can_decompress() {
+        let image = test_image(2000);
+        let mut window = [0; 256];
+        for (window_bits, lookahead_bits) in [(8, 4), (6, 3), (5, 4)] {
+            let compressed = compress(&image, window_bits, lookahead_bits);
+            assert!(compressed.len() < image.len());
+            for chunk_size in [1, 3, 16, 4096] {
+                for output_size in [1, 5, 4096] {
+                    assert_eq!(
+                        image,
+                        decompress(&compressed, &mut window, chunk_size, output_size).unwrap()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn can_decompress_empty_image() {
+        let compressed = compress(&[], 8, 4);
+        assert_eq!(
+            Vec::<u8>::new(),
+            decompress(&compressed, &mut [0; 256], 16, 16).unwrap()
+        );
+    }
+
+    #[test]
+    fn can_reference_before_start() {
+        // A back-reference before the start of the image reads zeros, as with heatshrink.
+        let mut compressed = Vec::new();
+        compressed.extend_from_slice(&COMPRESSED_MAGIC);
+        compressed.extend_from_slice(&[8, 4, 0, 0]);
+        compressed.extend_from_slice(&3u32.to_le_bytes());
+        compressed.extend_from_slice(&5u32.to_le_bytes());
+        // Offset 4 and length 4, then the literal 0x11.
+        compressed.extend_from_slice(&[0b0000_0001, 0b1001_1100, 0b0100_0100]);
+        assert_eq!(
+            std::vec![0, 0, 0, 0, 0x11],
+            decompress(&compressed, &mut [0; 256], 16, 16).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        let image = test_image(300);
+        let mut window = [0; 256];
+
+        let mut compressed = compress(&image, 8, 4);
+        compressed[0] = b'X';
+        assert_eq!(
+            Err(DecompressError::BadMagic),
+            decompress(&compressed, &mut window, 16, 16)
+        );
+
+        let compressed = compress(&image, 8, 4);
+        assert_eq!(
+            Err(DecompressError::UnsupportedParameters),
+            decompress(&compressed, &mut window[..128], 16, 16)
+        );
+
+        let mut compressed = compress(&image, 8, 4);
+        compressed[5] = 8;
+        assert_eq!(
+            Err(DecompressError::UnsupportedParameters),
+            decompress(&compressed, &mut window, 16, 16)
+        );
+
+        let compressed = compress(&image, 8, 4);
+        assert_eq!(
+            Err(DecompressError::Truncated),
+            decompress(&compressed[..compressed.len() - 1], &mut window, 16, 16)
+        );
+
+        let mut compressed = compress(&image, 8, 4);
+        let len = compressed.len() as u32 - 17;
+        compressed[8..12].copy_from_slice(&len.to_le_bytes());
+        compressed.pop();
+        assert_eq!(
+            Err(DecompressError::Truncated),
+            decompress(&compressed, &mut window, 16, 16)
+        );
+
+        let mut compressed = compress(&image, 8, 4);
+        compressed.push(0);
+        assert_eq!(
+            Err(DecompressError::Overrun),
+            decompress(&compressed, &mut window, 16, 16)
+        );
+
+        let mut compressed = compress(&image, 8, 4);
+        compressed[12..16].copy_from_slice(&(image.len() as u32 - 1).to_le_bytes());
+        assert!(decompress(&compressed, &mut window, 16, 16).is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! Delta (differential) firmware updates.
+
+/// Magic bytes at the start of a patch.
+pub const PATCH_MAGIC: [u8; 4] = *b"EBDP";
+
+/// Length of the SHA-512 digests of the source and target images in the patch header.
+pub const PATCH_DIGEST_LEN: usize = 64;
+
+const HEADER_LEN: usize = 12 + 2 * PATCH_DIGEST_LEN;
+const BLOCK_HEADER_LEN: usize = 12;
+
+/// Errors returned when applying a patch.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PatchError {
+    /// The patch does not start with [`PATCH_MAGIC`].
+    BadMagic,
+    /// The patch refers to data outside of the source image.
+    SourceOutOfBounds,
+    /// The patch produces more data than announced, or data follows its end.
+    Overrun,
+    /// The patch ended before the whole image was produced.
+    Truncated,
+    /// The source image does not match the digest in the patch header, the patch was made for another image.
+    SourceMismatch,
+    /// The produced image does not match the digest in the patch header.
+    TargetMismatch,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DecodeState {
+    Header,
+    Block,
+    Diff { remaining: u32, extra_len: u32, seek: i32 },
+    Extra { remaining: u32, seek: i32 },
+    Done,
+}
+
+/// An output operation produced by [`PatchDecoder::decode`].
+#[derive(PartialEq, Eq, Debug)]
+pub enum PatchOp<'a> {
+    /// Output the source bytes starting at `source_offset`, each added (wrapping) to the
+    /// corresponding byte of `data`.
+    Diff {
+        /// Offset of the source bytes.
+        source_offset: u32,
+        /// Diff bytes.
+        data: &'a [u8],
+    },
+    /// Output `data` as is.
+    Extra(&'a [u8]),
+}
+
+/// Streaming patch decoder.
+///
+/// A patch describes the new firmware image in terms of the image currently in the ACTIVE
+/// partition, so that only the differences have to be transferred. The format is similar to
+/// bsdiff, but laid out so that it can be applied while streaming, with constant memory:
+///
+/// | Field       | Size       | Description                                                     |
+/// |-------------|------------|-----------------------------------------------------------------|
+/// | magic       | 4          | [`PATCH_MAGIC`]                                                 |
+/// | target_len  | 4          | Length of the new image, little endian                          |
+/// | source_len  | 4          | Length of the source image, little endian                       |
+/// | source_hash | 64         | SHA-512 digest of the source image                              |
+/// | target_hash | 64         | SHA-512 digest of the new image                                 |
+/// | blocks      | ...        | Blocks, until `target_len` bytes have been produced             |
+///
+/// Each block has the following format:
+///
+/// | Field       | Size       | Description                                                     |
+/// |-------------|------------|-----------------------------------------------------------------|
+/// | diff_len    | 4          | Number of diff bytes, little endian                             |
+/// | extra_len   | 4          | Number of extra bytes, little endian                            |
+/// | seek        | 4          | Signed adjustment of the source position, little endian         |
+/// | diff        | diff_len   | Added (wrapping) to the source bytes at the source position     |
+/// | extra       | extra_len  | Copied as is                                                    |
+///
+/// The source position starts at 0, advances with the diff bytes, and is adjusted by `seek` at
+/// the end of each block. Diff bytes may only refer to the first `source_len` bytes of the source.
+///
+/// The decoder does not access the source or target images itself; it turns the patch into a
+/// sequence of [`PatchOp`]s, which is applied by [`FirmwareUpdater::write_firmware_patch`](crate::FirmwareUpdater::write_firmware_patch)
+/// and [`BlockingFirmwareUpdater::write_firmware_patch`](crate::BlockingFirmwareUpdater::write_firmware_patch).
+/// The updaters check the source image against `source_hash` before applying the patch, and the new
+/// image against `target_hash` once it has been written.
+pub struct PatchDecoder {
+    state: DecodeState,
+    header: [u8; HEADER_LEN],
+    header_len: usize,
+    target_len: u32,
+    source_len: u32,
+    source_digest: [u8; PATCH_DIGEST_LEN],
+    target_digest: [u8; PATCH_DIGEST_LEN],
+    produced: u32,
+    source_pos: u32,
+}
+
+impl Default for PatchDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatchDecoder {
+    /// Create a new decoder, expecting the start of a patch.
+    pub const fn new() -> Self {
+        Self {
+            state: DecodeState::Header,
+            header: [0; HEADER_LEN],
+            header_len: 0,
+            target_len: 0,
+            source_len: 0,
+            source_digest: [0; PATCH_DIGEST_LEN],
+            target_digest: [0; PATCH_DIGEST_LEN],
+            produced: 0,
+            source_pos: 0,
+        }
+    }
+
+    /// Length of the new image, once the patch header has been decoded.
+    pub fn target_len(&self) -> Option<u32> {
+        match self.state {
+            DecodeState::Header => None,
+            _ => Some(self.target_len),
+        }
+    }
+
+    /// Length of the source image, once the patch header has been decoded.
+    pub fn source_len(&self) -> Option<u32> {
+        self.target_len().map(|_| self.source_len)
+    }
+
+    /// SHA-512 digest of the source image, once the patch header has been decoded.
+    pub fn source_digest(&self) -> Option<&[u8; PATCH_DIGEST_LEN]> {
+        self.target_len().map(|_| &self.source_digest)
+    }
+
+    /// SHA-512 digest of the new image, once the patch header has been decoded.
+    pub fn target_digest(&self) -> Option<&[u8; PATCH_DIGEST_LEN]> {
+        self.target_len().map(|_| &self.target_digest)
+    }
+
+    /// Whether the whole image has been produced.
+    pub fn is_done(&self) -> bool {
+        self.state == DecodeState::Done
+    }
+
+    /// Decode the next part of the patch.
+    ///
+    /// Returns the number of bytes consumed from `input`, and the output operation they
+    /// describe, if any. The operation produces at most `max_len` bytes of output.
+    pub fn decode<'a>(&mut self, input: &'a [u8], max_len: usize) -> Result<(usize, Option<PatchOp<'a>>), PatchError> {
+        let (n, op) = match self.state {
+            DecodeState::Header | DecodeState::Block => {
+                let len = match self.state {
+                    DecodeState::Header => HEADER_LEN,
+                    _ => BLOCK_HEADER_LEN,
+                };
+                let n = core::cmp::min(len - self.header_len, input.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&input[..n]);
+                self.header_len += n;
+
+                if self.header_len == len {
+                    self.header_len = 0;
+                    self.decode_header()?;
+                }
+                (n, None)
+            }
+            DecodeState::Diff {
+                remaining,
+                extra_len,
+                seek,
+            } => {
+                let n = min3(remaining as usize, input.len(), max_len);
+                let op = PatchOp::Diff {
+                    source_offset: self.source_pos,
+                    data: &input[..n],
+                };
+                self.source_pos = self
+                    .source_pos
+                    .checked_add(n as u32)
+                    .filter(|&pos| pos <= self.source_len)
+                    .ok_or(PatchError::SourceOutOfBounds)?;
+                self.produced += n as u32;
+                self.state = DecodeState::Diff {
+                    remaining: remaining - n as u32,
+                    extra_len,
+                    seek,
+                };
+                (n, Some(op))
+            }
+            DecodeState::Extra { remaining, seek } => {
+                let n = min3(remaining as usize, input.len(), max_len);
+                self.produced += n as u32;
+                self.state = DecodeState::Extra {
+                    remaining: remaining - n as u32,
+                    seek,
+                };
+                (n, Some(PatchOp::Extra(&input[..n])))
+            }
+            DecodeState::Done => {
+                if !input.is_empty() {
+                    return Err(PatchError::Overrun);
+                }
+                (0, None)
+            }
+        };
+
+        self.settle()?;
+        Ok((n, op))
+    }
+
+    fn decode_header(&mut self) -> Result<(), PatchError> {
+        let header = self.header;
+        let word = |i: usize| u32::from_le_bytes(unwrap!(header[4 * i..4 * i + 4].try_into()));
+
+        if self.state == DecodeState::Header {
+            if header[..4] != PATCH_MAGIC {
+                return Err(PatchError::BadMagic);
+            }
+            self.target_len = word(1);
+            self.source_len = word(2);
+            self.source_digest.copy_from_slice(&header[12..12 + PATCH_DIGEST_LEN]);
+            self.target_digest
+                .copy_from_slice(&header[12 + PATCH_DIGEST_LEN..12 + 2 * PATCH_DIGEST_LEN]);
+            self.state = DecodeState::Block;
+            if self.target_len == 0 {
+                self.state = DecodeState::Done;
+            }
+        } else {
+            let (diff_len, extra_len, seek) = (word(0), word(1), word(2) as i32);
+            let remaining = self.target_len - self.produced;
+            if diff_len > remaining || extra_len > remaining - diff_len {
+                return Err(PatchError::Overrun);
+            }
+            self.state = DecodeState::Diff {
+                remaining: diff_len,
+                extra_len,
+                seek,
+            };
+        }
+        Ok(())
+    }
+
+    // Move past finished diff and extra sections.
+    fn settle(&mut self) -> Result<(), PatchError> {
+        loop {
+            match self.state {
+                DecodeState::Diff {
+                    remaining: 0,
+                    extra_len,
+                    seek,
+                } => {
+                    self.state = DecodeState::Extra {
+                        remaining: extra_len,
+                        seek,
+                    }
+                }
+                DecodeState::Extra { remaining: 0, seek } => {
+                    self.source_pos = self
+                        .source_pos
+                        .checked_add_signed(seek)
+                        .ok_or(PatchError::SourceOutOfBounds)?;
+                    self.state = if self.produced == self.target_len {
+                        DecodeState::Done
+                    } else {
+                        DecodeState::Block
+                    };
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+fn min3(a: usize, b: usize, c: usize) -> usize {
+    core::cmp::min(core::cmp::min(a, b), c)
+}
+
+/// State of a patch being applied by a firmware updater.
+///
+/// The new image is staged in `buf` and written to the DFU partition whenever it is full. The
+/// length of `buf` must be a multiple of the DFU partition's `WRITE_SIZE`, and it must follow the
+/// alignment rules of both the ACTIVE partition being read from and the DFU partition being
+/// written to.
+pub struct Patch<'b> {
+    pub(crate) decoder: PatchDecoder,
+    pub(crate) buf: &'b mut [u8],
+    pub(crate) fill: usize,
+    pub(crate) offset: usize,
+    pub(crate) source_checked: bool,
+}
+
+impl<'b> Patch<'b> {
+    /// Create a new patch state, with a buffer for staging the new image.
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        assert!(!buf.is_empty());
+        Self {
+            decoder: PatchDecoder::new(),
+            buf,
+            fill: 0,
+            offset: 0,
+            source_checked: false,
+        }
+    }
+
+    /// Length of the new image, once the patch header has been received.
+    pub fn target_len(&self) -> Option<u32> {
+        self.decoder.target_len()
+    }
+}
+
+pub(crate) fn apply_diff(buf: &mut [u8], diff: &[u8]) {
+    for (b, d) in buf.iter_mut().zip(diff) {
+        *b = b.wrapping_add(*d);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use sha2::{Digest, Sha512};
+
+    use super::*;
+
+    /// Build a patch turning `source` into `target` from `(diff, extra, seek)` blocks.
+    pub(crate) fn build_patch(source: &[u8], target: &[u8], blocks: &[(&[u8], &[u8], i32)]) -> Vec<u8> {
+        let mut patch = Vec::new();
+        patch.extend_from_slice(&PATCH_MAGIC);
+        patch.extend_from_slice(&(target.len() as u32).to_le_bytes());
+        patch.extend_from_slice(&(source.len() as u32).to_le_bytes());
+        patch.extend_from_slice(&Sha512::digest(source));
+        patch.extend_from_slice(&Sha512::digest(target));
+        for (diff, extra, seek) in blocks {
+            patch.extend_from_slice(&(diff.len() as u32).to_le_bytes());
+            patch.extend_from_slice(&(extra.len() as u32).to_le_bytes());
+            patch.extend_from_slice(&seek.to_le_bytes());
+            patch.extend_from_slice(diff);
+            patch.extend_from_slice(extra);
+        }
+        patch
+    }
+
+    /// Build a patch turning `source` into `target`, diffing the common prefix and appending the
+    /// rest as extra bytes.
+    pub(crate) fn naive_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let common = core::cmp::min(source.len(), target.len());
+        let diff: Vec<u8> = source[..common]
+            .iter()
+            .zip(&target[..common])
+            .map(|(s, t)| t.wrapping_sub(*s))
+            .collect();
+        build_patch(source, target, &[(&diff, &target[common..], 0)])
+    }
+
+    fn apply(source: &[u8], patch: &[u8], chunk_size: usize, max_len: usize) -> Result<Vec<u8>, PatchError> {
+        let mut decoder = PatchDecoder::new();
+        let mut output = Vec::new();
+        for mut chunk in patch.chunks(chunk_size) {
+            while !chunk.is_empty() {
+                let (n, op) = decoder.decode(chunk, max_len)?;
+                chunk = &chunk[n..];
+                match op {
+                    Some(PatchOp::Diff { source_offset, data }) => {
+                        let start = source_offset as usize;
+                        let mut buf = source
+                            .get(start..start + data.len())
+                            .ok_or(PatchError::SourceOutOfBounds)?
+                            .to_vec();
+                        apply_diff(&mut buf, data);
+                        output.extend_from_slice(&buf);
+                    }
+                    Some(PatchOp::Extra(data)) => output.extend_from_slice(data),
+                    None => {}
+                }
+            }
+        }
+        if !decoder.is_done() {
+            return Err(PatchError::Truncated);
+        }
+        Ok(output)
+    }
+
+    #[test]
+    fn can_apply_naive_patch() {
+        let source: Vec<u8> = (0..200u8).collect();
+        let mut target = source.clone();
+        target[17] = 0xAA;
+        target.extend_from_slice(b"appended");
+
+        let patch = naive_patch(&source, &target);
+        for chunk_size in [1, 3, 16, 1024] {
+            for max_len in [1, 7, 4096] {
+                assert_eq!(target, apply(&source, &patch, chunk_size, max_len).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn can_apply_seeking_patch() {
+        let source = b"0123456789abcdef";
+        // "89ab" + "XY" + "0123" + "cdef"
+        let patch = build_patch(
+            source,
+            b"89abXY0123cdef",
+            &[
+                (&[], b"", 8),
+                (&[0; 4], b"XY", -12),
+                (&[0; 4], b"", 8),
+                (&[0; 4], b"", 0),
+            ],
+        );
+        assert_eq!(b"89abXY0123cdef".as_slice(), apply(source, &patch, 5, 3).unwrap());
+    }
+
+    #[test]
+    fn can_apply_empty_sections() {
+        let source = b"abc";
+        let patch = build_patch(source, b"xcd", &[(&[], b"x", 0), (&[], b"", 1), (&[1, 1], b"", 0)]);
+        assert_eq!(b"xcd".as_slice(), apply(source, &patch, 2, 16).unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_patches() {
+        let source = b"abc";
+
+        let mut patch = naive_patch(source, b"abd");
+        patch[0] = b'X';
+        assert_eq!(Err(PatchError::BadMagic), apply(source, &patch, 16, 16));
+
+        let patch = naive_patch(source, b"abd");
+        assert_eq!(
+            Err(PatchError::Truncated),
+            apply(source, &patch[..patch.len() - 1], 16, 16)
+        );
+
+        let mut patch = naive_patch(source, b"abd");
+        patch.push(0);
+        assert_eq!(Err(PatchError::Overrun), apply(source, &patch, 16, 16));
+
+        let patch = build_patch(source, b"ab", &[(&[0; 3], b"", 0)]);
+        assert_eq!(Err(PatchError::Overrun), apply(source, &patch, 16, 16));
+
+        let patch = build_patch(source, b"ab", &[(&[], b"", -1)]);
+        assert_eq!(Err(PatchError::SourceOutOfBounds), apply(source, &patch, 16, 16));
+
+        let patch = build_patch(&source[..2], b"abc", &[(&[0; 3], b"", 0)]);
+        assert_eq!(Err(PatchError::SourceOutOfBounds), apply(source, &patch, 16, 16));
+    }
+}
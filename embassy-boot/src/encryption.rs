@@ -0,0 +1,100 @@
+//! Image encryption.
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::ChaCha20;
+use zeroize::Zeroize;
+
+use crate::multi_boot_loader::slots_offset;
+
+/// Length of the device key.
+pub const KEY_LEN: usize = 32;
+
+/// Length of the nonce of an image.
+pub const NONCE_LEN: usize = 12;
+
+/// Keystream position of the previous image while it is kept in the DFU partition.
+///
+/// The previous image is encrypted with the key and nonce of the new image, so it uses a distinct
+/// part of the keystream.
+pub(crate) const PREVIOUS_IMAGE_POSITION: u64 = 1 << 37;
+
+/// Source of the device key decrypting images, such as a secure element or OTP memory.
+pub trait DeviceKey {
+    /// Get the device key.
+    fn key(&mut self) -> [u8; KEY_LEN];
+}
+
+impl DeviceKey for [u8; KEY_LEN] {
+    fn key(&mut self) -> [u8; KEY_LEN] {
+        *self
+    }
+}
+
+/// ChaCha20 cipher of an image.
+///
+/// Images are encrypted with the device key and a nonce, starting at keystream position 0. The
+/// nonce must be unique to each image encrypted with a key. The copy of the key is zeroized when
+/// the cipher is dropped.
+pub struct ImageCipher {
+    key: [u8; KEY_LEN],
+    nonce: [u8; NONCE_LEN],
+}
+
+impl ImageCipher {
+    /// Create a cipher from a device key and the nonce of an image.
+    pub fn new(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN]) -> Self {
+        Self {
+            key: *key,
+            nonce: *nonce,
+        }
+    }
+
+    /// Encrypt or decrypt `data`, located at `offset` in the image.
+    pub fn apply_keystream(&self, offset: u64, data: &mut [u8]) {
+        let mut cipher = ChaCha20::new(&self.key.into(), &self.nonce.into());
+        cipher.seek(offset);
+        cipher.apply_keystream(data);
+    }
+}
+
+impl Drop for ImageCipher {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// Offset in the state partition of the nonce of the image to swap, at the end of the progress
+/// before the slots of a multi-image update.
+pub(crate) fn nonce_offset(progress_len: usize, write_size: usize) -> usize {
+    slots_offset(progress_len, write_size) - NONCE_LEN.next_multiple_of(write_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_apply_keystream_in_chunks() {
+        let cipher = ImageCipher::new(&[1; KEY_LEN], &[2; NONCE_LEN]);
+        let plaintext = [0x55; 300];
+
+        let mut ciphertext = plaintext;
+        cipher.apply_keystream(0, &mut ciphertext);
+        assert_ne!(plaintext, ciphertext);
+
+        let mut decrypted = ciphertext;
+        for (i, chunk) in decrypted.chunks_mut(7).enumerate() {
+            cipher.apply_keystream(i as u64 * 7, chunk);
+        }
+        assert_eq!(plaintext, decrypted);
+    }
+
+    #[test]
+    fn previous_image_uses_another_keystream() {
+        let cipher = ImageCipher::new(&[1; KEY_LEN], &[2; NONCE_LEN]);
+        let mut new = [0; 64];
+        let mut previous = [0; 64];
+        cipher.apply_keystream(0, &mut new);
+        cipher.apply_keystream(PREVIOUS_IMAGE_POSITION, &mut previous);
+        assert_ne!(new, previous);
+    }
+}
@@ -5,8 +5,20 @@ use embassy_embedded_hal::flash::partition::Partition;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embedded_storage_async::nor_flash::NorFlash;
 
-use super::FirmwareUpdaterConfig;
-use crate::{FirmwareUpdaterError, State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
+#[cfg(feature = "_verify")]
+use super::{verify_signature, Sha512};
+use super::{FirmwareUpdaterConfig, MultiFirmwareUpdaterConfig};
+use crate::compress::{DecompressError, Decompression, Decompressor};
+use crate::delta::{apply_diff, Patch, PatchError, PatchOp};
+#[cfg(feature = "encryption")]
+use crate::encryption::{nonce_offset, NONCE_LEN};
+use crate::manifest::{Manifest, ManifestError};
+use crate::metadata::{
+    encode_record, record_slot_size, state_progress_len, ImageMetadata, MetadataError, RecordKind, Records,
+    IMAGE_METADATA_LEN, RECORD_COMMIT, RECORD_LEN,
+};
+use crate::multi_boot_loader::slots_offset;
+use crate::{AlignedBuffer, FirmwareUpdaterError, State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
 
 /// FirmwareUpdater is an application API for interacting with the BootLoader without the ability to
 /// 'mess up' the internal bootloader state
@@ -14,6 +26,8 @@ pub struct FirmwareUpdater<'d, DFU: NorFlash, STATE: NorFlash> {
     dfu: DFU,
     state: FirmwareState<'d, STATE>,
     last_erased_dfu_sector_index: Option<usize>,
+    #[cfg(feature = "metadata")]
+    validated: bool,
 }
 
 #[cfg(target_os = "none")]
@@ -58,6 +72,8 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdater<'d, DFU, STATE> {
             dfu: config.dfu,
             state: FirmwareState::new(config.state, aligned),
             last_erased_dfu_sector_index: None,
+            #[cfg(feature = "metadata")]
+            validated: false,
         }
     }
 
@@ -81,63 +97,140 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdater<'d, DFU, STATE> {
     ///
     /// If no signature feature is set then this method will always return a
     /// signature error.
+    ///
+    /// With the `metadata` feature, the update must have been validated with
+    /// [`validate_image`](Self::validate_image) first.
     #[cfg(feature = "_verify")]
     pub async fn verify_and_mark_updated(
         &mut self,
-        _public_key: &[u8; 32],
-        _signature: &[u8; 64],
-        _update_len: u32,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+        update_len: u32,
     ) -> Result<(), FirmwareUpdaterError> {
-        assert!(_update_len <= self.dfu.capacity() as u32);
+        assert!(update_len <= self.dfu.capacity() as u32);
 
         self.state.verify_booted().await?;
+        self.check_validated()?;
 
-        #[cfg(feature = "ed25519-dalek")]
-        {
-            use ed25519_dalek::{Signature, SignatureError, Verifier, VerifyingKey};
+        let mut message = [0; 64];
+        let mut chunk_buf = [0; 2];
+        self.hash::<Sha512>(update_len, &mut chunk_buf, &mut message).await?;
+        verify_signature(public_key, signature, &message)?;
 
-            use crate::digest_adapters::ed25519_dalek::Sha512;
+        self.state.mark_updated().await
+    }
 
-            let into_signature_error = |e: SignatureError| FirmwareUpdaterError::Signature(e.into());
+    /// Verify a compressed image kept in DFU given a public key, and mark it to be decompressed
+    /// into the active partition on next boot if verify succeeds.
+    ///
+    /// The signature is expected to have been generated from a SHA-512 digest of the decompressed
+    /// image, as with [`verify_and_mark_updated`](Self::verify_and_mark_updated). The `window`
+    /// must be large enough for the image, see [`Decompressor`].
+    #[cfg(feature = "_verify")]
+    pub async fn verify_compressed_and_mark_updated(
+        &mut self,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+        window: &mut [u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        self.state.verify_booted().await?;
 
-            let public_key = VerifyingKey::from_bytes(_public_key).map_err(into_signature_error)?;
-            let signature = Signature::from_bytes(_signature);
+        let mut message = [0; 64];
+        let mut chunk_buf = [0; 32];
+        self.hash_compressed::<Sha512>(window, &mut chunk_buf, &mut message)
+            .await?;
+        verify_signature(public_key, signature, &message)?;
 
-            let mut chunk_buf = [0; 2];
-            let mut message = [0; 64];
-            self.hash::<Sha512>(_update_len, &mut chunk_buf, &mut message).await?;
+        self.state.mark_updated().await
+    }
 
-            public_key.verify(&message, &signature).map_err(into_signature_error)?
-        }
-        #[cfg(feature = "ed25519-salty")]
-        {
-            use salty::{PublicKey, Signature};
+    /// Verify an encrypted update in DFU given a public key, and mark it to be decrypted into the
+    /// active partition on next boot if verify succeeds.
+    ///
+    /// The signature is expected to have been generated from a SHA-512 digest of the encrypted
+    /// image followed by its `nonce`, so that both are authenticated before the bootloader decrypts
+    /// the image. The bootloader must then swap it with `BootLoader::prepare_boot_encrypted`.
+    #[cfg(all(feature = "encryption", feature = "_verify"))]
+    pub async fn verify_and_mark_updated_encrypted(
+        &mut self,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+        update_len: u32,
+        nonce: &[u8; NONCE_LEN],
+    ) -> Result<(), FirmwareUpdaterError> {
+        assert!(update_len <= self.dfu.capacity() as u32);
 
-            use crate::digest_adapters::salty::Sha512;
+        self.state.verify_booted().await?;
 
-            fn into_signature_error<E>(_: E) -> FirmwareUpdaterError {
-                FirmwareUpdaterError::Signature(signature::Error::default())
-            }
+        let mut digest = Sha512::new();
+        let mut chunk_buf = [0; 32];
+        self.update_digest(&mut digest, update_len, &mut chunk_buf).await?;
+        digest.update(nonce);
+        let mut message = [0; 64];
+        message.copy_from_slice(digest.finalize().as_slice());
+        verify_signature(public_key, signature, &message)?;
 
-            let public_key = PublicKey::try_from(_public_key).map_err(into_signature_error)?;
-            let signature = Signature::try_from(_signature).map_err(into_signature_error)?;
+        let offset = nonce_offset(self.state.progress_len(), STATE::WRITE_SIZE);
+        self.state.write_magic(SWAP_MAGIC, offset, nonce).await
+    }
 
-            let mut message = [0; 64];
-            let mut chunk_buf = [0; 2];
-            self.hash::<Sha512>(_update_len, &mut chunk_buf, &mut message).await?;
+    /// Read the metadata of the update in DFU.
+    ///
+    /// The metadata is expected at the end of the update, see [`ImageMetadata`].
+    pub async fn read_metadata(&mut self, update_len: u32) -> Result<ImageMetadata, FirmwareUpdaterError> {
+        assert!(update_len <= self.dfu.capacity() as u32);
 
-            let r = public_key.verify(&message, &signature);
-            trace!(
-                "Verifying with public key {}, signature {} and message {} yields ok: {}",
-                public_key.to_bytes(),
-                signature.to_bytes(),
-                message,
-                r.is_ok()
-            );
-            r.map_err(into_signature_error)?
+        let len = IMAGE_METADATA_LEN as u32;
+        if update_len < len {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::BadMagic));
         }
 
-        self.state.mark_updated().await
+        // Read from an aligned offset, as the update length may be unaligned.
+        let offset = update_len - len;
+        let start = offset - offset % len;
+        let read_len = if start == offset { len } else { 2 * len };
+        let mut buf = AlignedBuffer([0; 2 * IMAGE_METADATA_LEN]);
+        self.dfu.read(start, &mut buf.0[..read_len as usize]).await?;
+
+        let bytes = unwrap!(buf.0[(offset - start) as usize..][..IMAGE_METADATA_LEN].try_into());
+        let metadata = ImageMetadata::from_bytes(bytes).map_err(FirmwareUpdaterError::Metadata)?;
+        if metadata.image_len != offset {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::BadLength));
+        }
+        Ok(metadata)
+    }
+
+    /// Validate the metadata of the update in DFU, and record it as the pending image.
+    ///
+    /// The update is rejected if it is built for another hardware than `hardware_id`, or if its
+    /// security counter is lower than the one of the active image. With the `metadata` feature,
+    /// this must be called after writing the update and before marking it updated, e.g. with
+    /// [`verify_and_mark_updated`](Self::verify_and_mark_updated) which also authenticates the
+    /// metadata when a signature feature is enabled.
+    ///
+    /// The metadata is recorded in the state partition, which requires the `metadata` feature and
+    /// a state partition spanning at least two erase sectors, see [`FirmwareState`](crate::FirmwareState).
+    pub async fn validate_image(
+        &mut self,
+        update_len: u32,
+        hardware_id: u32,
+    ) -> Result<ImageMetadata, FirmwareUpdaterError> {
+        self.state.verify_booted().await?;
+
+        let metadata = self.read_metadata(update_len).await?;
+        if metadata.hardware_id != hardware_id {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::WrongHardware));
+        }
+        if metadata.security_counter < self.state.security_counter().await? {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::Downgrade));
+        }
+
+        self.state.set_pending(&metadata).await?;
+        #[cfg(feature = "metadata")]
+        {
+            self.validated = true;
+        }
+        Ok(metadata)
     }
 
     /// Verify the update in DFU with any digest.
@@ -147,22 +240,115 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdater<'d, DFU, STATE> {
         chunk_buf: &mut [u8],
         output: &mut [u8],
     ) -> Result<(), FirmwareUpdaterError> {
+        let digest = self.digest::<D>(update_len, chunk_buf).await?;
+        output.copy_from_slice(digest.as_slice());
+        Ok(())
+    }
+
+    /// Verify the update in DFU against an expected digest.
+    ///
+    /// This is typically used to check an image produced by [`write_firmware_patch`](Self::write_firmware_patch)
+    /// before marking it updated.
+    pub async fn verify_hash<D: Digest>(
+        &mut self,
+        update_len: u32,
+        chunk_buf: &mut [u8],
+        expected: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        let digest = self.digest::<D>(update_len, chunk_buf).await?;
+        if digest.as_slice() == expected {
+            Ok(())
+        } else {
+            Err(FirmwareUpdaterError::Digest)
+        }
+    }
+
+    /// Hash the decompressed contents of a compressed image kept in DFU with any digest.
+    ///
+    /// The `window` must be large enough for the image, see [`Decompressor`].
+    pub async fn hash_compressed<D: Digest>(
+        &mut self,
+        window: &mut [u8],
+        chunk_buf: &mut [u8],
+        output: &mut [u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        let mut decompressor = Decompressor::new(window);
         let mut digest = D::new();
+        let mut decompressed = [0; 32];
+        let mut offset = 0;
+        while !decompressor.is_done() {
+            if offset >= self.dfu.capacity() {
+                return Err(FirmwareUpdaterError::Decompress(DecompressError::Truncated));
+            }
+            self.dfu.read(offset as u32, chunk_buf).await?;
+            offset += chunk_buf.len();
+
+            let mut data = &chunk_buf[..];
+            loop {
+                let (n, m) = decompressor
+                    .decode(data, &mut decompressed)
+                    .map_err(FirmwareUpdaterError::Decompress)?;
+                data = &data[n..];
+                digest.update(&decompressed[..m]);
+                if m < decompressed.len() {
+                    break;
+                }
+            }
+        }
+        output.copy_from_slice(digest.finalize().as_slice());
+        Ok(())
+    }
+
+    async fn digest<D: Digest>(
+        &mut self,
+        update_len: u32,
+        chunk_buf: &mut [u8],
+    ) -> Result<digest::Output<D>, FirmwareUpdaterError> {
+        let mut digest = D::new();
+        self.update_digest(&mut digest, update_len, chunk_buf).await?;
+        Ok(digest.finalize())
+    }
+
+    async fn update_digest<D: Digest>(
+        &mut self,
+        digest: &mut D,
+        update_len: u32,
+        chunk_buf: &mut [u8],
+    ) -> Result<(), FirmwareUpdaterError> {
         for offset in (0..update_len).step_by(chunk_buf.len()) {
             self.dfu.read(offset, chunk_buf).await?;
             let len = core::cmp::min((update_len - offset) as usize, chunk_buf.len());
             digest.update(&chunk_buf[..len]);
         }
-        output.copy_from_slice(digest.finalize().as_slice());
         Ok(())
     }
 
     /// Mark to trigger firmware swap on next boot.
+    ///
+    /// With the `metadata` feature, the update must have been validated with
+    /// [`validate_image`](Self::validate_image) first.
     #[cfg(not(feature = "_verify"))]
     pub async fn mark_updated(&mut self) -> Result<(), FirmwareUpdaterError> {
+        self.check_validated()?;
         self.state.mark_updated().await
     }
 
+    // With the `metadata` feature, an update must be validated before being marked updated, so
+    // that the hardware and security counter checks can't be skipped.
+    fn check_validated(&self) -> Result<(), FirmwareUpdaterError> {
+        #[cfg(feature = "metadata")]
+        if !self.validated {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::NotValidated));
+        }
+        Ok(())
+    }
+
+    /// Mark to trigger firmware swap on next boot, for an image encrypted with `nonce`.
+    #[cfg(all(feature = "encryption", not(feature = "_verify")))]
+    pub async fn mark_updated_encrypted(&mut self, nonce: &[u8; NONCE_LEN]) -> Result<(), FirmwareUpdaterError> {
+        self.state.mark_updated_encrypted(nonce).await
+    }
+
     /// Mark to trigger USB DFU on next boot.
     pub async fn mark_dfu(&mut self) -> Result<(), FirmwareUpdaterError> {
         self.state.verify_booted().await?;
@@ -202,6 +388,10 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdater<'d, DFU, STATE> {
     pub async fn write_firmware(&mut self, offset: usize, data: &[u8]) -> Result<(), FirmwareUpdaterError> {
         // Make sure we are running a booted firmware to avoid reverting to a bad state.
         self.state.verify_booted().await?;
+        #[cfg(feature = "metadata")]
+        {
+            self.validated = false;
+        }
 
         // Initialize variables to keep track of the remaining data and the current offset.
         let mut remaining_data = data;
@@ -240,6 +430,161 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdater<'d, DFU, STATE> {
         Ok(())
     }
 
+    /// Writes a chunk of a delta patch to the device.
+    ///
+    /// The patch is applied against the image in the `active` partition, and the resulting image is
+    /// written to the DFU partition. The patch can be split in chunks of any size, which must be
+    /// written in order. See [`PatchDecoder`](crate::PatchDecoder) for the patch format.
+    ///
+    /// Once the whole patch has been written, call [`finish_firmware_patch`](Self::finish_firmware_patch).
+    pub async fn write_firmware_patch<ACTIVE: embedded_storage_async::nor_flash::ReadNorFlash>(
+        &mut self,
+        patch: &mut Patch<'_>,
+        active: &mut ACTIVE,
+        data: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        assert_eq!(0, patch.buf.len() % DFU::WRITE_SIZE);
+
+        let mut data = data;
+        while !data.is_empty() {
+            let (n, op) = patch
+                .decoder
+                .decode(data, patch.buf.len() - patch.fill)
+                .map_err(FirmwareUpdaterError::Patch)?;
+            data = &data[n..];
+
+            if !patch.source_checked && patch.decoder.target_len().is_some() {
+                // The header has just been decoded, so the buffer is free to read the source image.
+                let source_len = unwrap!(patch.decoder.source_len()) as usize;
+                if source_len > active.capacity() {
+                    return Err(FirmwareUpdaterError::Patch(PatchError::SourceOutOfBounds));
+                }
+                let mut digest = sha2::Sha512::new();
+                for offset in (0..source_len).step_by(patch.buf.len()) {
+                    let len = core::cmp::min(source_len - offset, patch.buf.len());
+                    read_unaligned(active, offset as u32, &mut patch.buf[..len]).await?;
+                    digest.update(&patch.buf[..len]);
+                }
+                if digest.finalize().as_slice() != unwrap!(patch.decoder.source_digest()) {
+                    return Err(FirmwareUpdaterError::Patch(PatchError::SourceMismatch));
+                }
+                patch.source_checked = true;
+            }
+
+            match op {
+                Some(PatchOp::Diff { source_offset, data }) => {
+                    if source_offset as usize + data.len() > active.capacity() {
+                        return Err(FirmwareUpdaterError::Patch(PatchError::SourceOutOfBounds));
+                    }
+                    let out = &mut patch.buf[patch.fill..patch.fill + data.len()];
+                    read_unaligned(active, source_offset, out).await?;
+                    apply_diff(out, data);
+                    patch.fill += data.len();
+                }
+                Some(PatchOp::Extra(data)) => {
+                    patch.buf[patch.fill..patch.fill + data.len()].copy_from_slice(data);
+                    patch.fill += data.len();
+                }
+                None => {}
+            }
+
+            if patch.fill == patch.buf.len() {
+                self.write_firmware(patch.offset, patch.buf).await?;
+                patch.offset += patch.fill;
+                patch.fill = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish applying a delta patch, writing the rest of the new image to the DFU partition.
+    ///
+    /// The new image is read back and checked against the digest in the patch header. Returns the
+    /// length of the new image, which can then be marked updated.
+    pub async fn finish_firmware_patch(&mut self, patch: &mut Patch<'_>) -> Result<u32, FirmwareUpdaterError> {
+        if !patch.decoder.is_done() {
+            return Err(FirmwareUpdaterError::Patch(PatchError::Truncated));
+        }
+
+        if patch.fill > 0 {
+            let len = patch.fill.next_multiple_of(DFU::WRITE_SIZE);
+            patch.buf[patch.fill..len].fill(STATE_ERASE_VALUE);
+            self.write_firmware(patch.offset, &patch.buf[..len]).await?;
+            patch.offset += patch.fill;
+            patch.fill = 0;
+        }
+
+        let target_len = unwrap!(patch.decoder.target_len());
+        let digest = self.digest::<sha2::Sha512>(target_len, patch.buf).await?;
+        if digest.as_slice() != unwrap!(patch.decoder.target_digest()) {
+            return Err(FirmwareUpdaterError::Patch(PatchError::TargetMismatch));
+        }
+
+        Ok(target_len)
+    }
+
+    /// Writes a chunk of a compressed image to the device.
+    ///
+    /// The image is decompressed while it is written to the DFU partition. It can be split in chunks
+    /// of any size, which must be written in order. See [`Decompressor`] for the image format.
+    ///
+    /// Once the whole image has been written, call [`finish_firmware_compressed`](Self::finish_firmware_compressed).
+    pub async fn write_firmware_compressed(
+        &mut self,
+        decompression: &mut Decompression<'_>,
+        data: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        assert_eq!(0, decompression.buf.len() % DFU::WRITE_SIZE);
+
+        let mut data = data;
+        loop {
+            let (n, m) = decompression
+                .decompressor
+                .decode(data, &mut decompression.buf[decompression.fill..])
+                .map_err(FirmwareUpdaterError::Decompress)?;
+            data = &data[n..];
+            decompression.fill += m;
+
+            if decompression.fill < decompression.buf.len() {
+                break;
+            }
+            self.write_firmware(decompression.offset, decompression.buf).await?;
+            decompression.offset += decompression.fill;
+            decompression.fill = 0;
+        }
+
+        if !data.is_empty() {
+            return Err(FirmwareUpdaterError::Decompress(DecompressError::Overrun));
+        }
+        Ok(())
+    }
+
+    /// Finish writing a compressed image, writing the rest of the decompressed image to the DFU
+    /// partition.
+    ///
+    /// Returns the length of the decompressed image, which can then be verified and marked updated
+    /// like an uncompressed one.
+    pub async fn finish_firmware_compressed(
+        &mut self,
+        decompression: &mut Decompression<'_>,
+    ) -> Result<u32, FirmwareUpdaterError> {
+        if !decompression.decompressor.is_done() {
+            return Err(FirmwareUpdaterError::Decompress(DecompressError::Truncated));
+        }
+
+        if decompression.fill > 0 {
+            let len = decompression.fill.next_multiple_of(DFU::WRITE_SIZE);
+            decompression.buf[decompression.fill..len].fill(STATE_ERASE_VALUE);
+            self.write_firmware(decompression.offset, &decompression.buf[..len])
+                .await?;
+            decompression.offset += decompression.fill;
+            decompression.fill = 0;
+        }
+
+        Ok(unwrap!(decompression.decompressor.image_len()))
+    }
+
     /// Prepare for an incoming DFU update by erasing the entire DFU area and
     /// returning its `Partition`.
     ///
@@ -247,12 +592,175 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdater<'d, DFU, STATE> {
     /// exchange for added complexity.
     pub async fn prepare_update(&mut self) -> Result<&mut DFU, FirmwareUpdaterError> {
         self.state.verify_booted().await?;
+        #[cfg(feature = "metadata")]
+        {
+            self.validated = false;
+        }
         self.dfu.erase(0, self.dfu.capacity() as u32).await?;
 
         Ok(&mut self.dfu)
     }
 }
 
+/// Read `out` from `flash` at any offset and length, going through a bounce buffer aligned to the
+/// flash `READ_SIZE` where needed.
+async fn read_unaligned<F: embedded_storage_async::nor_flash::ReadNorFlash>(
+    flash: &mut F,
+    offset: u32,
+    out: &mut [u8],
+) -> Result<(), FirmwareUpdaterError> {
+    if offset as usize % F::READ_SIZE == 0 && out.len() % F::READ_SIZE == 0 {
+        flash.read(offset, out).await?;
+        return Ok(());
+    }
+
+    let mut bounce = AlignedBuffer([0; 32]);
+    assert_eq!(0, bounce.0.len() % F::READ_SIZE);
+    let mut offset = offset as usize;
+    let mut out = out;
+    while !out.is_empty() {
+        let start = offset - offset % F::READ_SIZE;
+        let skip = offset - start;
+        let len = core::cmp::min((skip + out.len()).next_multiple_of(F::READ_SIZE), bounce.0.len());
+        flash.read(start as u32, &mut bounce.0[..len]).await?;
+
+        let n = core::cmp::min(len - skip, out.len());
+        out[..n].copy_from_slice(&bounce.0[skip..skip + n]);
+        out = &mut out[n..];
+        offset += n;
+    }
+    Ok(())
+}
+
+/// Multi-image firmware updater, updating the slots of a
+/// [`MultiBootLoader`](crate::MultiBootLoader) together.
+///
+/// The images are written to the DFU partition of their slot, and marked updated together with a
+/// [`Manifest`] listing them, so that a single signature covers all of them.
+pub struct MultiFirmwareUpdater<'d, DFU: NorFlash, STATE: NorFlash, const N: usize> {
+    dfu: [DFU; N],
+    state: FirmwareState<'d, STATE>,
+    last_erased_dfu_sector_index: [Option<usize>; N],
+}
+
+impl<'d, DFU: NorFlash, STATE: NorFlash, const N: usize> MultiFirmwareUpdater<'d, DFU, STATE, N> {
+    /// Create a multi-image firmware updater instance with partition ranges for the update and state partitions.
+    ///
+    /// # Safety
+    ///
+    /// The `aligned` buffer must follow the same rules as for [`FirmwareUpdater::new`].
+    pub fn new(config: MultiFirmwareUpdaterConfig<DFU, STATE, N>, aligned: &'d mut [u8]) -> Self {
+        Self {
+            dfu: config.dfu,
+            state: FirmwareState::new(config.state, aligned),
+            last_erased_dfu_sector_index: [None; N],
+        }
+    }
+
+    /// Obtain the current state.
+    pub async fn get_state(&mut self) -> Result<State, FirmwareUpdaterError> {
+        self.state.get_state().await
+    }
+
+    /// Write data to the DFU partition of a slot, see [`FirmwareUpdater::write_firmware`].
+    pub async fn write_firmware(
+        &mut self,
+        slot: usize,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        let mut updater = self.slot(slot);
+        let result = updater.write_firmware(offset, data).await;
+        let last_erased_dfu_sector_index = updater.last_erased_dfu_sector_index;
+        self.last_erased_dfu_sector_index[slot] = last_erased_dfu_sector_index;
+        result
+    }
+
+    /// Verify the update in the DFU partition of a slot with any digest.
+    pub async fn hash<D: Digest>(
+        &mut self,
+        slot: usize,
+        update_len: u32,
+        chunk_buf: &mut [u8],
+        output: &mut [u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        self.slot(slot).hash::<D>(update_len, chunk_buf, output).await
+    }
+
+    /// Verify a manifest given a public key, and the images it lists. If there is an error then
+    /// DO NOT proceed with updating the firmware.
+    ///
+    /// Mark the slots of the manifest to be swapped together on next boot if verify succeeds.
+    ///
+    /// The signature is expected to have been generated from a SHA-512 digest of the manifest,
+    /// which holds the SHA-512 digest of each image.
+    #[cfg(feature = "_verify")]
+    pub async fn verify_and_mark_updated(
+        &mut self,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+        manifest: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        self.state.verify_booted().await?;
+
+        let mut message = [0; 64];
+        message.copy_from_slice(&Sha512::digest(manifest));
+        verify_signature(public_key, signature, &message)?;
+
+        let manifest = self.parse_manifest(manifest)?;
+        for entry in manifest.entries() {
+            let mut chunk_buf = [0; 2];
+            let digest = self
+                .slot(entry.slot as usize)
+                .digest::<Sha512>(entry.image_len, &mut chunk_buf)
+                .await?;
+            if digest.as_slice() != entry.hash {
+                return Err(FirmwareUpdaterError::Digest);
+            }
+        }
+
+        self.state.mark_updated_slots(manifest.slots()).await
+    }
+
+    /// Mark the slots of a manifest to be swapped together on next boot.
+    #[cfg(not(feature = "_verify"))]
+    pub async fn mark_updated(&mut self, manifest: &[u8]) -> Result<(), FirmwareUpdaterError> {
+        let manifest = self.parse_manifest(manifest)?;
+        self.state.mark_updated_slots(manifest.slots()).await
+    }
+
+    /// Mark firmware boot successful and stop rollback on reset.
+    pub async fn mark_booted(&mut self) -> Result<(), FirmwareUpdaterError> {
+        self.state.mark_booted().await
+    }
+
+    // Parse a manifest, and check that its images fit in their slot.
+    fn parse_manifest<'m>(&self, manifest: &'m [u8]) -> Result<Manifest<'m>, FirmwareUpdaterError> {
+        let manifest = Manifest::parse(manifest).map_err(FirmwareUpdaterError::Manifest)?;
+        for entry in manifest.entries() {
+            let dfu = self
+                .dfu
+                .get(entry.slot as usize)
+                .ok_or(FirmwareUpdaterError::Manifest(ManifestError::BadSlot))?;
+            if entry.image_len as usize > dfu.capacity() {
+                return Err(FirmwareUpdaterError::Manifest(ManifestError::BadLength));
+            }
+        }
+        Ok(manifest)
+    }
+
+    // Single image updater of a slot.
+    fn slot(&mut self, slot: usize) -> FirmwareUpdater<'_, &mut DFU, &mut STATE> {
+        FirmwareUpdater {
+            dfu: &mut self.dfu[slot],
+            state: FirmwareState::new(&mut self.state.state, self.state.aligned),
+            last_erased_dfu_sector_index: self.last_erased_dfu_sector_index[slot],
+            #[cfg(feature = "metadata")]
+            validated: false,
+        }
+    }
+}
+
 /// Manages the state partition of the firmware update.
 ///
 /// Can be used standalone for more fine grained control, or as part of the updater.
@@ -285,7 +793,7 @@ impl<'d, STATE: NorFlash> FirmwareState<'d, STATE> {
 
     // Make sure we are running a booted firmware to avoid reverting to a bad state.
     async fn verify_booted(&mut self) -> Result<(), FirmwareUpdaterError> {
-        if self.get_state().await? == State::Boot {
+        if matches!(self.get_state().await?, State::Boot | State::Reverted) {
             Ok(())
         } else {
             Err(FirmwareUpdaterError::BadState)
@@ -297,11 +805,28 @@ impl<'d, STATE: NorFlash> FirmwareState<'d, STATE> {
     /// This is useful to check if the bootloader has just done a swap, in order
     /// to do verifications and self-tests of the new image before calling
     /// `mark_booted`.
+    ///
+    /// After the bootloader has reverted an update that was not marked booted, this
+    /// reports [`State::Reverted`] until `mark_booted` is called.
     pub async fn get_state(&mut self) -> Result<State, FirmwareUpdaterError> {
         self.state.read(0, &mut self.aligned).await?;
 
         if !self.aligned.iter().any(|&b| b != SWAP_MAGIC) {
-            Ok(State::Swap)
+            // The bootloader invalidates the progress once it has reverted the update
+            if STATE::READ_SIZE <= 2 * STATE::WRITE_SIZE {
+                self.state.read(STATE::WRITE_SIZE as u32, self.aligned).await?;
+            } else {
+                self.aligned.rotate_left(STATE::WRITE_SIZE);
+            }
+
+            if self.aligned[..STATE::WRITE_SIZE]
+                .iter()
+                .any(|&b| b != STATE_ERASE_VALUE)
+            {
+                Ok(State::Reverted)
+            } else {
+                Ok(State::Swap)
+            }
         } else {
             Ok(State::Boot)
         }
@@ -312,20 +837,174 @@ impl<'d, STATE: NorFlash> FirmwareState<'d, STATE> {
         self.set_magic(SWAP_MAGIC).await
     }
 
+    /// Mark to trigger firmware swap on next boot, for an image encrypted with `nonce`.
+    ///
+    /// The bootloader must then swap it with `BootLoader::prepare_boot_encrypted`.
+    #[cfg(all(feature = "encryption", not(feature = "_verify")))]
+    pub async fn mark_updated_encrypted(&mut self, nonce: &[u8; NONCE_LEN]) -> Result<(), FirmwareUpdaterError> {
+        let offset = nonce_offset(self.progress_len(), STATE::WRITE_SIZE);
+        self.write_magic(SWAP_MAGIC, offset, nonce).await
+    }
+
+    /// Mark to trigger a swap of some slots of a [`MultiBootLoader`](crate::MultiBootLoader) on next
+    /// boot, with bit `n` of `slots` set for slot `n`.
+    pub async fn mark_updated_slots(&mut self, slots: u32) -> Result<(), FirmwareUpdaterError> {
+        let offset = slots_offset(self.progress_len(), STATE::WRITE_SIZE);
+        self.write_magic(SWAP_MAGIC, offset, &slots.to_le_bytes()).await
+    }
+
     /// Mark to trigger USB DFU on next boot.
     pub async fn mark_dfu(&mut self) -> Result<(), FirmwareUpdaterError> {
         self.set_magic(DFU_DETACH_MAGIC).await
     }
 
     /// Mark firmware boot successful and stop rollback on reset.
+    ///
+    /// After a swap, this also makes the pending image metadata the active one.
     pub async fn mark_booted(&mut self) -> Result<(), FirmwareUpdaterError> {
-        self.set_magic(BOOT_MAGIC).await
+        let swapped = self.get_state().await? == State::Swap;
+        self.set_magic(BOOT_MAGIC).await?;
+
+        if swapped && self.metadata_range().is_some() {
+            if let Some(pending) = self.read_records().await?.pending {
+                self.append_record(RecordKind::Active, &pending).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Obtain the metadata of the active image.
+    ///
+    /// This is the metadata of the last image validated with [`FirmwareUpdater::validate_image`](crate::FirmwareUpdater::validate_image)
+    /// and marked booted after a swap, or `None` if there is none yet.
+    pub async fn active_metadata(&mut self) -> Result<Option<ImageMetadata>, FirmwareUpdaterError> {
+        Ok(self.read_records().await?.active)
+    }
+
+    /// Obtain the metadata of the pending image, which will be swapped in on next boot, or has
+    /// been swapped in but not marked booted yet.
+    pub async fn pending_metadata(&mut self) -> Result<Option<ImageMetadata>, FirmwareUpdaterError> {
+        if self.get_state().await? != State::Swap {
+            return Ok(None);
+        }
+        Ok(self.read_records().await?.pending)
+    }
+
+    /// Obtain the security counter of the active image.
+    pub async fn security_counter(&mut self) -> Result<u32, FirmwareUpdaterError> {
+        Ok(self
+            .active_metadata()
+            .await?
+            .map_or(0, |metadata| metadata.security_counter))
+    }
+
+    pub(crate) async fn set_pending(&mut self, metadata: &ImageMetadata) -> Result<(), FirmwareUpdaterError> {
+        self.append_record(RecordKind::Pending, metadata).await
+    }
+
+    fn metadata_range(&self) -> Option<(u32, u32)> {
+        let capacity = self.state.capacity();
+        let start = state_progress_len(capacity, STATE::ERASE_SIZE);
+        (start < capacity).then_some((start as u32, capacity as u32))
+    }
+
+    async fn read_records(&mut self) -> Result<Records, FirmwareUpdaterError> {
+        let (start, end) = self
+            .metadata_range()
+            .ok_or(FirmwareUpdaterError::Metadata(MetadataError::NoStorage))?;
+        let write_size = STATE::WRITE_SIZE;
+        let slot_size = record_slot_size(write_size);
+
+        let mut records = Records::default();
+        for offset in (start..end - slot_size as u32 + 1).step_by(slot_size) {
+            let mut record = [STATE_ERASE_VALUE; RECORD_LEN];
+            let mut erased = true;
+            let mut committed = false;
+            for pos in (0..slot_size).step_by(write_size) {
+                self.state.read(offset + pos as u32, &mut self.aligned).await?;
+                erased &= self.aligned.iter().all(|&b| b == STATE_ERASE_VALUE);
+                if pos < RECORD_LEN {
+                    let len = core::cmp::min(write_size, RECORD_LEN - pos);
+                    record[pos..pos + len].copy_from_slice(&self.aligned[..len]);
+                } else {
+                    committed = self.aligned.iter().all(|&b| b == RECORD_COMMIT);
+                }
+            }
+
+            if erased {
+                records.next = Some(offset);
+                break;
+            }
+            if committed {
+                records.apply(&record);
+            }
+        }
+        Ok(records)
+    }
+
+    async fn append_record(&mut self, kind: RecordKind, metadata: &ImageMetadata) -> Result<(), FirmwareUpdaterError> {
+        let records = self.read_records().await?;
+        let offset = match records.next {
+            Some(offset) => offset,
+            None => {
+                // The log is full, start over from the active record.
+                let (start, end) = unwrap!(self.metadata_range());
+                self.state.erase(start, end).await?;
+                match (kind, records.active) {
+                    (RecordKind::Pending, Some(active)) => {
+                        self.write_record(start, RecordKind::Active, &active).await?;
+                        start + record_slot_size(STATE::WRITE_SIZE) as u32
+                    }
+                    _ => start,
+                }
+            }
+        };
+        self.write_record(offset, kind, metadata).await
+    }
+
+    async fn write_record(
+        &mut self,
+        offset: u32,
+        kind: RecordKind,
+        metadata: &ImageMetadata,
+    ) -> Result<(), FirmwareUpdaterError> {
+        let record = encode_record(kind, metadata);
+        let write_size = STATE::WRITE_SIZE;
+        for pos in (0..RECORD_LEN).step_by(write_size) {
+            let len = core::cmp::min(write_size, RECORD_LEN - pos);
+            self.aligned.fill(STATE_ERASE_VALUE);
+            self.aligned[..len].copy_from_slice(&record[pos..pos + len]);
+            self.state.write(offset + pos as u32, &self.aligned).await?;
+        }
+
+        // Commit the record once it is complete.
+        self.aligned.fill(RECORD_COMMIT);
+        self.state
+            .write(offset + RECORD_LEN.next_multiple_of(write_size) as u32, &self.aligned)
+            .await?;
+        Ok(())
+    }
+
+    fn progress_len(&self) -> usize {
+        state_progress_len(self.state.capacity(), STATE::ERASE_SIZE)
     }
 
     async fn set_magic(&mut self, magic: u8) -> Result<(), FirmwareUpdaterError> {
+        self.write_magic(magic, 0, &[]).await
+    }
+
+    // Write the magic, preceded by a trailer at `trailer_offset` in the progress: the nonce of an
+    // encrypted image, or the slots of a multi-image update. This does nothing if the magic is
+    // already set, and there is no trailer.
+    async fn write_magic(
+        &mut self,
+        magic: u8,
+        trailer_offset: usize,
+        trailer: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
         self.state.read(0, &mut self.aligned).await?;
 
-        if self.aligned[..STATE::WRITE_SIZE].iter().any(|&b| b != magic) {
+        if !trailer.is_empty() || self.aligned[..STATE::WRITE_SIZE].iter().any(|&b| b != magic) {
             // Read progress validity
             if STATE::READ_SIZE <= 2 * STATE::WRITE_SIZE {
                 self.state.read(STATE::WRITE_SIZE as u32, &mut self.aligned).await?;
@@ -347,7 +1026,20 @@ impl<'d, STATE: NorFlash> FirmwareState<'d, STATE> {
             }
 
             // Clear magic and progress
-            self.state.erase(0, self.state.capacity() as u32).await?;
+            let progress_len = self.progress_len();
+            self.state.erase(0, progress_len as u32).await?;
+
+            // Set trailer
+            for (i, chunk) in trailer.chunks(STATE::WRITE_SIZE).enumerate() {
+                self.aligned.fill(STATE_ERASE_VALUE);
+                self.aligned[..chunk.len()].copy_from_slice(chunk);
+                self.state
+                    .write(
+                        (trailer_offset + i * STATE::WRITE_SIZE) as u32,
+                        &self.aligned[..STATE::WRITE_SIZE],
+                    )
+                    .await?;
+            }
 
             // Set magic
             self.aligned.fill(magic);
@@ -459,4 +1151,125 @@ mod tests {
 
         assert_eq!(Sha1::digest(update).as_slice(), hash);
     }
+
+    #[test]
+    fn can_apply_patch() {
+        let flash = Mutex::<NoopRawMutex, _>::new(MemFlash::<131072, 1024, 8>::default());
+        let state = Partition::new(&flash, 0, 4096);
+        let mut active = Partition::new(&flash, 4096, 61440);
+        let dfu = Partition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let original = [0x55; 4096];
+        let mut update = [0x55; 2048];
+        update[1000..1024].fill(0xAA);
+        block_on(active.write(0, &original)).unwrap();
+
+        let patch = crate::delta::tests::naive_patch(&original, &update);
+
+        let mut updater = FirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        let mut buf = [0; 1024];
+        let mut state = Patch::new(&mut buf);
+        for chunk in patch.chunks(100) {
+            block_on(updater.write_firmware_patch(&mut state, &mut active, chunk)).unwrap();
+        }
+        let len = block_on(updater.finish_firmware_patch(&mut state)).unwrap();
+
+        let mut chunk_buf = [0; 128];
+        block_on(updater.verify_hash::<Sha1>(len, &mut chunk_buf, Sha1::digest(update).as_slice())).unwrap();
+    }
+
+    #[test]
+    fn can_apply_patch_with_read_size() {
+        let flash = Mutex::<NoopRawMutex, _>::new(MemFlash::<131072, 1024, 8, 4>::default());
+        let state = Partition::new(&flash, 0, 4096);
+        let mut active = Partition::new(&flash, 4096, 61440);
+        let dfu = Partition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let mut original = [0; 1008];
+        for (i, b) in original.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        block_on(active.write(0, &original)).unwrap();
+        let original = &original[..1001];
+
+        // Diffs at source offsets and of lengths which aren't multiples of the read size.
+        let mut update = [0; 98];
+        update[..3].copy_from_slice(&original[..3]);
+        update[3..8].copy_from_slice(b"extra");
+        update[8..45].copy_from_slice(&original[10..47]);
+        update[8..45].iter_mut().for_each(|b| *b = b.wrapping_add(1));
+        update[45..].copy_from_slice(&original[948..]);
+        let patch = crate::delta::tests::build_patch(
+            original,
+            &update,
+            &[(&[0; 3], b"extra", 7), (&[1; 37], b"", 901), (&[0; 53], b"", 0)],
+        );
+
+        let mut updater = FirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        let mut buf = [0; 64];
+        let mut state = Patch::new(&mut buf);
+        for chunk in patch.chunks(7) {
+            block_on(updater.write_firmware_patch(&mut state, &mut active, chunk)).unwrap();
+        }
+        let len = block_on(updater.finish_firmware_patch(&mut state)).unwrap();
+        assert_eq!(update.len() as u32, len);
+
+        let mut chunk_buf = [0; 128];
+        block_on(updater.verify_hash::<Sha1>(len, &mut chunk_buf, Sha1::digest(update).as_slice())).unwrap();
+    }
+
+    #[test]
+    fn can_write_compressed_firmware() {
+        let flash = Mutex::<NoopRawMutex, _>::new(MemFlash::<131072, 1024, 8>::default());
+        let state = Partition::new(&flash, 0, 4096);
+        let dfu = Partition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let update = crate::compress::tests::test_image(3000);
+        let compressed = crate::compress::tests::compress(&update, 8, 4);
+
+        let mut updater = FirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        let mut window = [0; 256];
+        let mut buf = [0; 1024];
+        let mut decompression = Decompression::new(&mut window, &mut buf);
+        for chunk in compressed.chunks(100) {
+            block_on(updater.write_firmware_compressed(&mut decompression, chunk)).unwrap();
+        }
+        let len = block_on(updater.finish_firmware_compressed(&mut decompression)).unwrap();
+        assert_eq!(update.len() as u32, len);
+
+        let mut chunk_buf = [0; 128];
+        block_on(updater.verify_hash::<Sha1>(len, &mut chunk_buf, Sha1::digest(&update).as_slice())).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "metadata")]
+    fn can_validate_image() {
+        let flash = Mutex::<NoopRawMutex, _>::new(MemFlash::<131072, 1024, 8>::default());
+        let mut aligned = [0; 8];
+
+        let image = crate::metadata::tests::with_metadata(&[0x42; 1000], 0xAB, 5);
+        let mut updater = FirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: Partition::new(&flash, 65536, 65536),
+                state: Partition::new(&flash, 0, 4096),
+            },
+            &mut aligned,
+        );
+        block_on(updater.write_firmware(0, &image)).unwrap();
+        assert!(matches!(
+            block_on(updater.validate_image(image.len() as u32, 0xCD)),
+            Err(FirmwareUpdaterError::Metadata(MetadataError::WrongHardware))
+        ));
+        let metadata = block_on(updater.validate_image(image.len() as u32, 0xAB)).unwrap();
+
+        let mut state = FirmwareState::new(Partition::new(&flash, 0, 4096), &mut aligned);
+        block_on(state.mark_updated()).unwrap();
+        assert_eq!(Some(metadata), block_on(state.pending_metadata()).unwrap());
+        block_on(state.mark_booted()).unwrap();
+        assert_eq!(Some(metadata), block_on(state.active_metadata()).unwrap());
+        assert_eq!(5, block_on(state.security_counter()).unwrap());
+    }
 }
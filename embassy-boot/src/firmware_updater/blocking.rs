@@ -5,8 +5,20 @@ use embassy_embedded_hal::flash::partition::BlockingPartition;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embedded_storage::nor_flash::NorFlash;
 
-use super::FirmwareUpdaterConfig;
-use crate::{FirmwareUpdaterError, State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
+#[cfg(feature = "_verify")]
+use super::{verify_signature, Sha512};
+use super::{FirmwareUpdaterConfig, MultiFirmwareUpdaterConfig};
+use crate::compress::{DecompressError, Decompression, Decompressor};
+use crate::delta::{apply_diff, Patch, PatchError, PatchOp};
+#[cfg(feature = "encryption")]
+use crate::encryption::{nonce_offset, NONCE_LEN};
+use crate::manifest::{Manifest, ManifestError};
+use crate::metadata::{
+    encode_record, record_slot_size, state_progress_len, ImageMetadata, MetadataError, RecordKind, Records,
+    IMAGE_METADATA_LEN, RECORD_COMMIT, RECORD_LEN,
+};
+use crate::multi_boot_loader::slots_offset;
+use crate::{AlignedBuffer, FirmwareUpdaterError, State, BOOT_MAGIC, DFU_DETACH_MAGIC, STATE_ERASE_VALUE, SWAP_MAGIC};
 
 /// Blocking FirmwareUpdater is an application API for interacting with the BootLoader without the ability to
 /// 'mess up' the internal bootloader state
@@ -14,6 +26,8 @@ pub struct BlockingFirmwareUpdater<'d, DFU: NorFlash, STATE: NorFlash> {
     dfu: DFU,
     state: BlockingFirmwareState<'d, STATE>,
     last_erased_dfu_sector_index: Option<usize>,
+    #[cfg(feature = "metadata")]
+    validated: bool,
 }
 
 #[cfg(target_os = "none")]
@@ -93,6 +107,8 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> BlockingFirmwareUpdater<'d, DFU, STATE>
             dfu: config.dfu,
             state: BlockingFirmwareState::new(config.state, aligned),
             last_erased_dfu_sector_index: None,
+            #[cfg(feature = "metadata")]
+            validated: false,
         }
     }
 
@@ -116,63 +132,135 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> BlockingFirmwareUpdater<'d, DFU, STATE>
     ///
     /// If no signature feature is set then this method will always return a
     /// signature error.
+    ///
+    /// With the `metadata` feature, the update must have been validated with
+    /// [`validate_image`](Self::validate_image) first.
     #[cfg(feature = "_verify")]
     pub fn verify_and_mark_updated(
         &mut self,
-        _public_key: &[u8; 32],
-        _signature: &[u8; 64],
-        _update_len: u32,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+        update_len: u32,
     ) -> Result<(), FirmwareUpdaterError> {
-        assert!(_update_len <= self.dfu.capacity() as u32);
+        assert!(update_len <= self.dfu.capacity() as u32);
 
         self.state.verify_booted()?;
+        self.check_validated()?;
 
-        #[cfg(feature = "ed25519-dalek")]
-        {
-            use ed25519_dalek::{Signature, SignatureError, Verifier, VerifyingKey};
+        let mut message = [0; 64];
+        let mut chunk_buf = [0; 2];
+        self.hash::<Sha512>(update_len, &mut chunk_buf, &mut message)?;
+        verify_signature(public_key, signature, &message)?;
 
-            use crate::digest_adapters::ed25519_dalek::Sha512;
+        self.state.mark_updated()
+    }
 
-            let into_signature_error = |e: SignatureError| FirmwareUpdaterError::Signature(e.into());
+    /// Verify a compressed image kept in DFU given a public key, and mark it to be decompressed
+    /// into the active partition on next boot if verify succeeds.
+    ///
+    /// The signature is expected to have been generated from a SHA-512 digest of the decompressed
+    /// image, as with [`verify_and_mark_updated`](Self::verify_and_mark_updated). The `window`
+    /// must be large enough for the image, see [`Decompressor`].
+    #[cfg(feature = "_verify")]
+    pub fn verify_compressed_and_mark_updated(
+        &mut self,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+        window: &mut [u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        self.state.verify_booted()?;
 
-            let public_key = VerifyingKey::from_bytes(_public_key).map_err(into_signature_error)?;
-            let signature = Signature::from_bytes(_signature);
+        let mut message = [0; 64];
+        let mut chunk_buf = [0; 32];
+        self.hash_compressed::<Sha512>(window, &mut chunk_buf, &mut message)?;
+        verify_signature(public_key, signature, &message)?;
 
-            let mut message = [0; 64];
-            let mut chunk_buf = [0; 2];
-            self.hash::<Sha512>(_update_len, &mut chunk_buf, &mut message)?;
+        self.state.mark_updated()
+    }
 
-            public_key.verify(&message, &signature).map_err(into_signature_error)?
-        }
-        #[cfg(feature = "ed25519-salty")]
-        {
-            use salty::{PublicKey, Signature};
+    /// Verify an encrypted update in DFU given a public key, and mark it to be decrypted into the
+    /// active partition on next boot if verify succeeds.
+    ///
+    /// The signature is expected to have been generated from a SHA-512 digest of the encrypted
+    /// image followed by its `nonce`, so that both are authenticated before the bootloader decrypts
+    /// the image. The bootloader must then swap it with `BootLoader::prepare_boot_encrypted`.
+    #[cfg(all(feature = "encryption", feature = "_verify"))]
+    pub fn verify_and_mark_updated_encrypted(
+        &mut self,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+        update_len: u32,
+        nonce: &[u8; NONCE_LEN],
+    ) -> Result<(), FirmwareUpdaterError> {
+        assert!(update_len <= self.dfu.capacity() as u32);
 
-            use crate::digest_adapters::salty::Sha512;
+        self.state.verify_booted()?;
 
-            fn into_signature_error<E>(_: E) -> FirmwareUpdaterError {
-                FirmwareUpdaterError::Signature(signature::Error::default())
-            }
+        let mut digest = Sha512::new();
+        let mut chunk_buf = [0; 32];
+        self.update_digest(&mut digest, update_len, &mut chunk_buf)?;
+        digest.update(nonce);
+        let mut message = [0; 64];
+        message.copy_from_slice(digest.finalize().as_slice());
+        verify_signature(public_key, signature, &message)?;
 
-            let public_key = PublicKey::try_from(_public_key).map_err(into_signature_error)?;
-            let signature = Signature::try_from(_signature).map_err(into_signature_error)?;
+        let offset = nonce_offset(self.state.progress_len(), STATE::WRITE_SIZE);
+        self.state.write_magic(SWAP_MAGIC, offset, nonce)
+    }
 
-            let mut message = [0; 64];
-            let mut chunk_buf = [0; 2];
-            self.hash::<Sha512>(_update_len, &mut chunk_buf, &mut message)?;
-
-            let r = public_key.verify(&message, &signature);
-            trace!(
-                "Verifying with public key {}, signature {} and message {} yields ok: {}",
-                public_key.to_bytes(),
-                signature.to_bytes(),
-                message,
-                r.is_ok()
-            );
-            r.map_err(into_signature_error)?
+    /// Read the metadata of the update in DFU.
+    ///
+    /// The metadata is expected at the end of the update, see [`ImageMetadata`].
+    pub fn read_metadata(&mut self, update_len: u32) -> Result<ImageMetadata, FirmwareUpdaterError> {
+        assert!(update_len <= self.dfu.capacity() as u32);
+
+        let len = IMAGE_METADATA_LEN as u32;
+        if update_len < len {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::BadMagic));
         }
 
-        self.state.mark_updated()
+        // Read from an aligned offset, as the update length may be unaligned.
+        let offset = update_len - len;
+        let start = offset - offset % len;
+        let read_len = if start == offset { len } else { 2 * len };
+        let mut buf = AlignedBuffer([0; 2 * IMAGE_METADATA_LEN]);
+        self.dfu.read(start, &mut buf.0[..read_len as usize])?;
+
+        let bytes = unwrap!(buf.0[(offset - start) as usize..][..IMAGE_METADATA_LEN].try_into());
+        let metadata = ImageMetadata::from_bytes(bytes).map_err(FirmwareUpdaterError::Metadata)?;
+        if metadata.image_len != offset {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::BadLength));
+        }
+        Ok(metadata)
+    }
+
+    /// Validate the metadata of the update in DFU, and record it as the pending image.
+    ///
+    /// The update is rejected if it is built for another hardware than `hardware_id`, or if its
+    /// security counter is lower than the one of the active image. With the `metadata` feature,
+    /// this must be called after writing the update and before marking it updated, e.g. with
+    /// [`verify_and_mark_updated`](Self::verify_and_mark_updated) which also authenticates the
+    /// metadata when a signature feature is enabled.
+    ///
+    /// The metadata is recorded in the state partition, which requires the `metadata` feature and
+    /// a state partition spanning at least two erase sectors, see [`FirmwareState`](crate::FirmwareState).
+    pub fn validate_image(&mut self, update_len: u32, hardware_id: u32) -> Result<ImageMetadata, FirmwareUpdaterError> {
+        self.state.verify_booted()?;
+
+        let metadata = self.read_metadata(update_len)?;
+        if metadata.hardware_id != hardware_id {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::WrongHardware));
+        }
+        if metadata.security_counter < self.state.security_counter()? {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::Downgrade));
+        }
+
+        self.state.set_pending(&metadata)?;
+        #[cfg(feature = "metadata")]
+        {
+            self.validated = true;
+        }
+        Ok(metadata)
     }
 
     /// Verify the update in DFU with any digest.
@@ -182,22 +270,115 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> BlockingFirmwareUpdater<'d, DFU, STATE>
         chunk_buf: &mut [u8],
         output: &mut [u8],
     ) -> Result<(), FirmwareUpdaterError> {
+        let digest = self.digest::<D>(update_len, chunk_buf)?;
+        output.copy_from_slice(digest.as_slice());
+        Ok(())
+    }
+
+    /// Verify the update in DFU against an expected digest.
+    ///
+    /// This is typically used to check an image produced by [`write_firmware_patch`](Self::write_firmware_patch)
+    /// before marking it updated.
+    pub fn verify_hash<D: Digest>(
+        &mut self,
+        update_len: u32,
+        chunk_buf: &mut [u8],
+        expected: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        let digest = self.digest::<D>(update_len, chunk_buf)?;
+        if digest.as_slice() == expected {
+            Ok(())
+        } else {
+            Err(FirmwareUpdaterError::Digest)
+        }
+    }
+
+    /// Hash the decompressed contents of a compressed image kept in DFU with any digest.
+    ///
+    /// The `window` must be large enough for the image, see [`Decompressor`].
+    pub fn hash_compressed<D: Digest>(
+        &mut self,
+        window: &mut [u8],
+        chunk_buf: &mut [u8],
+        output: &mut [u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        let mut decompressor = Decompressor::new(window);
         let mut digest = D::new();
+        let mut decompressed = [0; 32];
+        let mut offset = 0;
+        while !decompressor.is_done() {
+            if offset >= self.dfu.capacity() {
+                return Err(FirmwareUpdaterError::Decompress(DecompressError::Truncated));
+            }
+            self.dfu.read(offset as u32, chunk_buf)?;
+            offset += chunk_buf.len();
+
+            let mut data = &chunk_buf[..];
+            loop {
+                let (n, m) = decompressor
+                    .decode(data, &mut decompressed)
+                    .map_err(FirmwareUpdaterError::Decompress)?;
+                data = &data[n..];
+                digest.update(&decompressed[..m]);
+                if m < decompressed.len() {
+                    break;
+                }
+            }
+        }
+        output.copy_from_slice(digest.finalize().as_slice());
+        Ok(())
+    }
+
+    fn digest<D: Digest>(
+        &mut self,
+        update_len: u32,
+        chunk_buf: &mut [u8],
+    ) -> Result<digest::Output<D>, FirmwareUpdaterError> {
+        let mut digest = D::new();
+        self.update_digest(&mut digest, update_len, chunk_buf)?;
+        Ok(digest.finalize())
+    }
+
+    fn update_digest<D: Digest>(
+        &mut self,
+        digest: &mut D,
+        update_len: u32,
+        chunk_buf: &mut [u8],
+    ) -> Result<(), FirmwareUpdaterError> {
         for offset in (0..update_len).step_by(chunk_buf.len()) {
             self.dfu.read(offset, chunk_buf)?;
             let len = core::cmp::min((update_len - offset) as usize, chunk_buf.len());
             digest.update(&chunk_buf[..len]);
         }
-        output.copy_from_slice(digest.finalize().as_slice());
         Ok(())
     }
 
     /// Mark to trigger firmware swap on next boot.
+    ///
+    /// With the `metadata` feature, the update must have been validated with
+    /// [`validate_image`](Self::validate_image) first.
     #[cfg(not(feature = "_verify"))]
     pub fn mark_updated(&mut self) -> Result<(), FirmwareUpdaterError> {
+        self.check_validated()?;
         self.state.mark_updated()
     }
 
+    // With the `metadata` feature, an update must be validated before being marked updated, so
+    // that the hardware and security counter checks can't be skipped.
+    fn check_validated(&self) -> Result<(), FirmwareUpdaterError> {
+        #[cfg(feature = "metadata")]
+        if !self.validated {
+            return Err(FirmwareUpdaterError::Metadata(MetadataError::NotValidated));
+        }
+        Ok(())
+    }
+
+    /// Mark to trigger firmware swap on next boot, for an image encrypted with `nonce`.
+    #[cfg(all(feature = "encryption", not(feature = "_verify")))]
+    pub fn mark_updated_encrypted(&mut self, nonce: &[u8; NONCE_LEN]) -> Result<(), FirmwareUpdaterError> {
+        self.state.mark_updated_encrypted(nonce)
+    }
+
     /// Mark to trigger USB DFU device on next boot.
     pub fn mark_dfu(&mut self) -> Result<(), FirmwareUpdaterError> {
         self.state.verify_booted()?;
@@ -237,6 +418,10 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> BlockingFirmwareUpdater<'d, DFU, STATE>
     pub fn write_firmware(&mut self, offset: usize, data: &[u8]) -> Result<(), FirmwareUpdaterError> {
         // Make sure we are running a booted firmware to avoid reverting to a bad state.
         self.state.verify_booted()?;
+        #[cfg(feature = "metadata")]
+        {
+            self.validated = false;
+        }
 
         // Initialize variables to keep track of the remaining data and the current offset.
         let mut remaining_data = data;
@@ -275,6 +460,160 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> BlockingFirmwareUpdater<'d, DFU, STATE>
         Ok(())
     }
 
+    /// Writes a chunk of a delta patch to the device.
+    ///
+    /// The patch is applied against the image in the `active` partition, and the resulting image is
+    /// written to the DFU partition. The patch can be split in chunks of any size, which must be
+    /// written in order. See [`PatchDecoder`](crate::PatchDecoder) for the patch format.
+    ///
+    /// Once the whole patch has been written, call [`finish_firmware_patch`](Self::finish_firmware_patch).
+    pub fn write_firmware_patch<ACTIVE: embedded_storage::nor_flash::ReadNorFlash>(
+        &mut self,
+        patch: &mut Patch<'_>,
+        active: &mut ACTIVE,
+        data: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        assert_eq!(0, patch.buf.len() % DFU::WRITE_SIZE);
+
+        let mut data = data;
+        while !data.is_empty() {
+            let (n, op) = patch
+                .decoder
+                .decode(data, patch.buf.len() - patch.fill)
+                .map_err(FirmwareUpdaterError::Patch)?;
+            data = &data[n..];
+
+            if !patch.source_checked && patch.decoder.target_len().is_some() {
+                // The header has just been decoded, so the buffer is free to read the source image.
+                let source_len = unwrap!(patch.decoder.source_len()) as usize;
+                if source_len > active.capacity() {
+                    return Err(FirmwareUpdaterError::Patch(PatchError::SourceOutOfBounds));
+                }
+                let mut digest = sha2::Sha512::new();
+                for offset in (0..source_len).step_by(patch.buf.len()) {
+                    let len = core::cmp::min(source_len - offset, patch.buf.len());
+                    read_unaligned(active, offset as u32, &mut patch.buf[..len])?;
+                    digest.update(&patch.buf[..len]);
+                }
+                if digest.finalize().as_slice() != unwrap!(patch.decoder.source_digest()) {
+                    return Err(FirmwareUpdaterError::Patch(PatchError::SourceMismatch));
+                }
+                patch.source_checked = true;
+            }
+
+            match op {
+                Some(PatchOp::Diff { source_offset, data }) => {
+                    if source_offset as usize + data.len() > active.capacity() {
+                        return Err(FirmwareUpdaterError::Patch(PatchError::SourceOutOfBounds));
+                    }
+                    let out = &mut patch.buf[patch.fill..patch.fill + data.len()];
+                    read_unaligned(active, source_offset, out)?;
+                    apply_diff(out, data);
+                    patch.fill += data.len();
+                }
+                Some(PatchOp::Extra(data)) => {
+                    patch.buf[patch.fill..patch.fill + data.len()].copy_from_slice(data);
+                    patch.fill += data.len();
+                }
+                None => {}
+            }
+
+            if patch.fill == patch.buf.len() {
+                self.write_firmware(patch.offset, patch.buf)?;
+                patch.offset += patch.fill;
+                patch.fill = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish applying a delta patch, writing the rest of the new image to the DFU partition.
+    ///
+    /// The new image is read back and checked against the digest in the patch header. Returns the
+    /// length of the new image, which can then be marked updated.
+    pub fn finish_firmware_patch(&mut self, patch: &mut Patch<'_>) -> Result<u32, FirmwareUpdaterError> {
+        if !patch.decoder.is_done() {
+            return Err(FirmwareUpdaterError::Patch(PatchError::Truncated));
+        }
+
+        if patch.fill > 0 {
+            let len = patch.fill.next_multiple_of(DFU::WRITE_SIZE);
+            patch.buf[patch.fill..len].fill(STATE_ERASE_VALUE);
+            self.write_firmware(patch.offset, &patch.buf[..len])?;
+            patch.offset += patch.fill;
+            patch.fill = 0;
+        }
+
+        let target_len = unwrap!(patch.decoder.target_len());
+        let digest = self.digest::<sha2::Sha512>(target_len, patch.buf)?;
+        if digest.as_slice() != unwrap!(patch.decoder.target_digest()) {
+            return Err(FirmwareUpdaterError::Patch(PatchError::TargetMismatch));
+        }
+
+        Ok(target_len)
+    }
+
+    /// Writes a chunk of a compressed image to the device.
+    ///
+    /// The image is decompressed while it is written to the DFU partition. It can be split in chunks
+    /// of any size, which must be written in order. See [`Decompressor`] for the image format.
+    ///
+    /// Once the whole image has been written, call [`finish_firmware_compressed`](Self::finish_firmware_compressed).
+    pub fn write_firmware_compressed(
+        &mut self,
+        decompression: &mut Decompression<'_>,
+        data: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        assert_eq!(0, decompression.buf.len() % DFU::WRITE_SIZE);
+
+        let mut data = data;
+        loop {
+            let (n, m) = decompression
+                .decompressor
+                .decode(data, &mut decompression.buf[decompression.fill..])
+                .map_err(FirmwareUpdaterError::Decompress)?;
+            data = &data[n..];
+            decompression.fill += m;
+
+            if decompression.fill < decompression.buf.len() {
+                break;
+            }
+            self.write_firmware(decompression.offset, decompression.buf)?;
+            decompression.offset += decompression.fill;
+            decompression.fill = 0;
+        }
+
+        if !data.is_empty() {
+            return Err(FirmwareUpdaterError::Decompress(DecompressError::Overrun));
+        }
+        Ok(())
+    }
+
+    /// Finish writing a compressed image, writing the rest of the decompressed image to the DFU
+    /// partition.
+    ///
+    /// Returns the length of the decompressed image, which can then be verified and marked updated
+    /// like an uncompressed one.
+    pub fn finish_firmware_compressed(
+        &mut self,
+        decompression: &mut Decompression<'_>,
+    ) -> Result<u32, FirmwareUpdaterError> {
+        if !decompression.decompressor.is_done() {
+            return Err(FirmwareUpdaterError::Decompress(DecompressError::Truncated));
+        }
+
+        if decompression.fill > 0 {
+            let len = decompression.fill.next_multiple_of(DFU::WRITE_SIZE);
+            decompression.buf[decompression.fill..len].fill(STATE_ERASE_VALUE);
+            self.write_firmware(decompression.offset, &decompression.buf[..len])?;
+            decompression.offset += decompression.fill;
+            decompression.fill = 0;
+        }
+
+        Ok(unwrap!(decompression.decompressor.image_len()))
+    }
+
     /// Prepare for an incoming DFU update by erasing the entire DFU area and
     /// returning its `Partition`.
     ///
@@ -282,12 +621,169 @@ impl<'d, DFU: NorFlash, STATE: NorFlash> BlockingFirmwareUpdater<'d, DFU, STATE>
     /// exchange for added complexity.
     pub fn prepare_update(&mut self) -> Result<&mut DFU, FirmwareUpdaterError> {
         self.state.verify_booted()?;
+        #[cfg(feature = "metadata")]
+        {
+            self.validated = false;
+        }
         self.dfu.erase(0, self.dfu.capacity() as u32)?;
 
         Ok(&mut self.dfu)
     }
 }
 
+/// Read `out` from `flash` at any offset and length, going through a bounce buffer aligned to the
+/// flash `READ_SIZE` where needed.
+fn read_unaligned<F: embedded_storage::nor_flash::ReadNorFlash>(
+    flash: &mut F,
+    offset: u32,
+    out: &mut [u8],
+) -> Result<(), FirmwareUpdaterError> {
+    if offset as usize % F::READ_SIZE == 0 && out.len() % F::READ_SIZE == 0 {
+        flash.read(offset, out)?;
+        return Ok(());
+    }
+
+    let mut bounce = AlignedBuffer([0; 32]);
+    assert_eq!(0, bounce.0.len() % F::READ_SIZE);
+    let mut offset = offset as usize;
+    let mut out = out;
+    while !out.is_empty() {
+        let start = offset - offset % F::READ_SIZE;
+        let skip = offset - start;
+        let len = core::cmp::min((skip + out.len()).next_multiple_of(F::READ_SIZE), bounce.0.len());
+        flash.read(start as u32, &mut bounce.0[..len])?;
+
+        let n = core::cmp::min(len - skip, out.len());
+        out[..n].copy_from_slice(&bounce.0[skip..skip + n]);
+        out = &mut out[n..];
+        offset += n;
+    }
+    Ok(())
+}
+
+/// Blocking multi-image firmware updater, updating the slots of a
+/// [`MultiBootLoader`](crate::MultiBootLoader) together.
+///
+/// The images are written to the DFU partition of their slot, and marked updated together with a
+/// [`Manifest`] listing them, so that a single signature covers all of them.
+pub struct BlockingMultiFirmwareUpdater<'d, DFU: NorFlash, STATE: NorFlash, const N: usize> {
+    dfu: [DFU; N],
+    state: BlockingFirmwareState<'d, STATE>,
+    last_erased_dfu_sector_index: [Option<usize>; N],
+}
+
+impl<'d, DFU: NorFlash, STATE: NorFlash, const N: usize> BlockingMultiFirmwareUpdater<'d, DFU, STATE, N> {
+    /// Create a multi-image firmware updater instance with partition ranges for the update and state partitions.
+    ///
+    /// # Safety
+    ///
+    /// The `aligned` buffer must follow the same rules as for [`BlockingFirmwareUpdater::new`].
+    pub fn new(config: MultiFirmwareUpdaterConfig<DFU, STATE, N>, aligned: &'d mut [u8]) -> Self {
+        Self {
+            dfu: config.dfu,
+            state: BlockingFirmwareState::new(config.state, aligned),
+            last_erased_dfu_sector_index: [None; N],
+        }
+    }
+
+    /// Obtain the current state.
+    pub fn get_state(&mut self) -> Result<State, FirmwareUpdaterError> {
+        self.state.get_state()
+    }
+
+    /// Write data to the DFU partition of a slot, see [`BlockingFirmwareUpdater::write_firmware`].
+    pub fn write_firmware(&mut self, slot: usize, offset: usize, data: &[u8]) -> Result<(), FirmwareUpdaterError> {
+        let mut updater = self.slot(slot);
+        let result = updater.write_firmware(offset, data);
+        let last_erased_dfu_sector_index = updater.last_erased_dfu_sector_index;
+        self.last_erased_dfu_sector_index[slot] = last_erased_dfu_sector_index;
+        result
+    }
+
+    /// Verify the update in the DFU partition of a slot with any digest.
+    pub fn hash<D: Digest>(
+        &mut self,
+        slot: usize,
+        update_len: u32,
+        chunk_buf: &mut [u8],
+        output: &mut [u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        self.slot(slot).hash::<D>(update_len, chunk_buf, output)
+    }
+
+    /// Verify a manifest given a public key, and the images it lists. If there is an error then
+    /// DO NOT proceed with updating the firmware.
+    ///
+    /// Mark the slots of the manifest to be swapped together on next boot if verify succeeds.
+    ///
+    /// The signature is expected to have been generated from a SHA-512 digest of the manifest,
+    /// which holds the SHA-512 digest of each image.
+    #[cfg(feature = "_verify")]
+    pub fn verify_and_mark_updated(
+        &mut self,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+        manifest: &[u8],
+    ) -> Result<(), FirmwareUpdaterError> {
+        self.state.verify_booted()?;
+
+        let mut message = [0; 64];
+        message.copy_from_slice(&Sha512::digest(manifest));
+        verify_signature(public_key, signature, &message)?;
+
+        let manifest = self.parse_manifest(manifest)?;
+        for entry in manifest.entries() {
+            let mut chunk_buf = [0; 2];
+            let digest = self
+                .slot(entry.slot as usize)
+                .digest::<Sha512>(entry.image_len, &mut chunk_buf)?;
+            if digest.as_slice() != entry.hash {
+                return Err(FirmwareUpdaterError::Digest);
+            }
+        }
+
+        self.state.mark_updated_slots(manifest.slots())
+    }
+
+    /// Mark the slots of a manifest to be swapped together on next boot.
+    #[cfg(not(feature = "_verify"))]
+    pub fn mark_updated(&mut self, manifest: &[u8]) -> Result<(), FirmwareUpdaterError> {
+        let manifest = self.parse_manifest(manifest)?;
+        self.state.mark_updated_slots(manifest.slots())
+    }
+
+    /// Mark firmware boot successful and stop rollback on reset.
+    pub fn mark_booted(&mut self) -> Result<(), FirmwareUpdaterError> {
+        self.state.mark_booted()
+    }
+
+    // Parse a manifest, and check that its images fit in their slot.
+    fn parse_manifest<'m>(&self, manifest: &'m [u8]) -> Result<Manifest<'m>, FirmwareUpdaterError> {
+        let manifest = Manifest::parse(manifest).map_err(FirmwareUpdaterError::Manifest)?;
+        for entry in manifest.entries() {
+            let dfu = self
+                .dfu
+                .get(entry.slot as usize)
+                .ok_or(FirmwareUpdaterError::Manifest(ManifestError::BadSlot))?;
+            if entry.image_len as usize > dfu.capacity() {
+                return Err(FirmwareUpdaterError::Manifest(ManifestError::BadLength));
+            }
+        }
+        Ok(manifest)
+    }
+
+    // Single image updater of a slot.
+    fn slot(&mut self, slot: usize) -> BlockingFirmwareUpdater<'_, &mut DFU, &mut STATE> {
+        BlockingFirmwareUpdater {
+            dfu: &mut self.dfu[slot],
+            state: BlockingFirmwareState::new(&mut self.state.state, self.state.aligned),
+            last_erased_dfu_sector_index: self.last_erased_dfu_sector_index[slot],
+            #[cfg(feature = "metadata")]
+            validated: false,
+        }
+    }
+}
+
 /// Manages the state partition of the firmware update.
 ///
 /// Can be used standalone for more fine grained control, or as part of the updater.
@@ -320,7 +816,7 @@ impl<'d, STATE: NorFlash> BlockingFirmwareState<'d, STATE> {
 
     // Make sure we are running a booted firmware to avoid reverting to a bad state.
     fn verify_booted(&mut self) -> Result<(), FirmwareUpdaterError> {
-        if self.get_state()? == State::Boot || self.get_state()? == State::DfuDetach {
+        if matches!(self.get_state()?, State::Boot | State::DfuDetach | State::Reverted) {
             Ok(())
         } else {
             Err(FirmwareUpdaterError::BadState)
@@ -332,11 +828,28 @@ impl<'d, STATE: NorFlash> BlockingFirmwareState<'d, STATE> {
     /// This is useful to check if the bootloader has just done a swap, in order
     /// to do verifications and self-tests of the new image before calling
     /// `mark_booted`.
+    ///
+    /// After the bootloader has reverted an update that was not marked booted, this
+    /// reports [`State::Reverted`] until `mark_booted` is called.
     pub fn get_state(&mut self) -> Result<State, FirmwareUpdaterError> {
         self.state.read(0, &mut self.aligned)?;
 
         if !self.aligned.iter().any(|&b| b != SWAP_MAGIC) {
-            Ok(State::Swap)
+            // The bootloader invalidates the progress once it has reverted the update
+            if STATE::READ_SIZE <= 2 * STATE::WRITE_SIZE {
+                self.state.read(STATE::WRITE_SIZE as u32, self.aligned)?;
+            } else {
+                self.aligned.rotate_left(STATE::WRITE_SIZE);
+            }
+
+            if self.aligned[..STATE::WRITE_SIZE]
+                .iter()
+                .any(|&b| b != STATE_ERASE_VALUE)
+            {
+                Ok(State::Reverted)
+            } else {
+                Ok(State::Swap)
+            }
         } else if !self.aligned.iter().any(|&b| b != DFU_DETACH_MAGIC) {
             Ok(State::DfuDetach)
         } else {
@@ -349,20 +862,165 @@ impl<'d, STATE: NorFlash> BlockingFirmwareState<'d, STATE> {
         self.set_magic(SWAP_MAGIC)
     }
 
+    /// Mark to trigger firmware swap on next boot, for an image encrypted with `nonce`.
+    ///
+    /// The bootloader must then swap it with `BootLoader::prepare_boot_encrypted`.
+    #[cfg(all(feature = "encryption", not(feature = "_verify")))]
+    pub fn mark_updated_encrypted(&mut self, nonce: &[u8; NONCE_LEN]) -> Result<(), FirmwareUpdaterError> {
+        let offset = nonce_offset(self.progress_len(), STATE::WRITE_SIZE);
+        self.write_magic(SWAP_MAGIC, offset, nonce)
+    }
+
+    /// Mark to trigger a swap of some slots of a [`MultiBootLoader`](crate::MultiBootLoader) on next
+    /// boot, with bit `n` of `slots` set for slot `n`.
+    pub fn mark_updated_slots(&mut self, slots: u32) -> Result<(), FirmwareUpdaterError> {
+        let offset = slots_offset(self.progress_len(), STATE::WRITE_SIZE);
+        self.write_magic(SWAP_MAGIC, offset, &slots.to_le_bytes())
+    }
+
     /// Mark to trigger USB DFU on next boot.
     pub fn mark_dfu(&mut self) -> Result<(), FirmwareUpdaterError> {
         self.set_magic(DFU_DETACH_MAGIC)
     }
 
     /// Mark firmware boot successful and stop rollback on reset.
+    ///
+    /// After a swap, this also makes the pending image metadata the active one.
     pub fn mark_booted(&mut self) -> Result<(), FirmwareUpdaterError> {
-        self.set_magic(BOOT_MAGIC)
+        let swapped = self.get_state()? == State::Swap;
+        self.set_magic(BOOT_MAGIC)?;
+
+        if swapped && self.metadata_range().is_some() {
+            if let Some(pending) = self.read_records()?.pending {
+                self.append_record(RecordKind::Active, &pending)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Obtain the metadata of the active image.
+    ///
+    /// This is the metadata of the last image validated with [`BlockingFirmwareUpdater::validate_image`](crate::BlockingFirmwareUpdater::validate_image)
+    /// and marked booted after a swap, or `None` if there is none yet.
+    pub fn active_metadata(&mut self) -> Result<Option<ImageMetadata>, FirmwareUpdaterError> {
+        Ok(self.read_records()?.active)
+    }
+
+    /// Obtain the metadata of the pending image, which will be swapped in on next boot, or has
+    /// been swapped in but not marked booted yet.
+    pub fn pending_metadata(&mut self) -> Result<Option<ImageMetadata>, FirmwareUpdaterError> {
+        if self.get_state()? != State::Swap {
+            return Ok(None);
+        }
+        Ok(self.read_records()?.pending)
+    }
+
+    /// Obtain the security counter of the active image.
+    pub fn security_counter(&mut self) -> Result<u32, FirmwareUpdaterError> {
+        Ok(self.active_metadata()?.map_or(0, |metadata| metadata.security_counter))
+    }
+
+    pub(crate) fn set_pending(&mut self, metadata: &ImageMetadata) -> Result<(), FirmwareUpdaterError> {
+        self.append_record(RecordKind::Pending, metadata)
+    }
+
+    fn metadata_range(&self) -> Option<(u32, u32)> {
+        let capacity = self.state.capacity();
+        let start = state_progress_len(capacity, STATE::ERASE_SIZE);
+        (start < capacity).then_some((start as u32, capacity as u32))
+    }
+
+    fn read_records(&mut self) -> Result<Records, FirmwareUpdaterError> {
+        let (start, end) = self
+            .metadata_range()
+            .ok_or(FirmwareUpdaterError::Metadata(MetadataError::NoStorage))?;
+        let write_size = STATE::WRITE_SIZE;
+        let slot_size = record_slot_size(write_size);
+
+        let mut records = Records::default();
+        for offset in (start..end - slot_size as u32 + 1).step_by(slot_size) {
+            let mut record = [STATE_ERASE_VALUE; RECORD_LEN];
+            let mut erased = true;
+            let mut committed = false;
+            for pos in (0..slot_size).step_by(write_size) {
+                self.state.read(offset + pos as u32, &mut self.aligned)?;
+                erased &= self.aligned.iter().all(|&b| b == STATE_ERASE_VALUE);
+                if pos < RECORD_LEN {
+                    let len = core::cmp::min(write_size, RECORD_LEN - pos);
+                    record[pos..pos + len].copy_from_slice(&self.aligned[..len]);
+                } else {
+                    committed = self.aligned.iter().all(|&b| b == RECORD_COMMIT);
+                }
+            }
+
+            if erased {
+                records.next = Some(offset);
+                break;
+            }
+            if committed {
+                records.apply(&record);
+            }
+        }
+        Ok(records)
+    }
+
+    fn append_record(&mut self, kind: RecordKind, metadata: &ImageMetadata) -> Result<(), FirmwareUpdaterError> {
+        let records = self.read_records()?;
+        let offset = match records.next {
+            Some(offset) => offset,
+            None => {
+                // The log is full, start over from the active record.
+                let (start, end) = unwrap!(self.metadata_range());
+                self.state.erase(start, end)?;
+                match (kind, records.active) {
+                    (RecordKind::Pending, Some(active)) => {
+                        self.write_record(start, RecordKind::Active, &active)?;
+                        start + record_slot_size(STATE::WRITE_SIZE) as u32
+                    }
+                    _ => start,
+                }
+            }
+        };
+        self.write_record(offset, kind, metadata)
+    }
+
+    fn write_record(
+        &mut self,
+        offset: u32,
+        kind: RecordKind,
+        metadata: &ImageMetadata,
+    ) -> Result<(), FirmwareUpdaterError> {
+        let record = encode_record(kind, metadata);
+        let write_size = STATE::WRITE_SIZE;
+        for pos in (0..RECORD_LEN).step_by(write_size) {
+            let len = core::cmp::min(write_size, RECORD_LEN - pos);
+            self.aligned.fill(STATE_ERASE_VALUE);
+            self.aligned[..len].copy_from_slice(&record[pos..pos + len]);
+            self.state.write(offset + pos as u32, &self.aligned)?;
+        }
+
+        // Commit the record once it is complete.
+        self.aligned.fill(RECORD_COMMIT);
+        self.state
+            .write(offset + RECORD_LEN.next_multiple_of(write_size) as u32, &self.aligned)?;
+        Ok(())
+    }
+
+    fn progress_len(&self) -> usize {
+        state_progress_len(self.state.capacity(), STATE::ERASE_SIZE)
     }
 
     fn set_magic(&mut self, magic: u8) -> Result<(), FirmwareUpdaterError> {
+        self.write_magic(magic, 0, &[])
+    }
+
+    // Write the magic, preceded by a trailer at `trailer_offset` in the progress: the nonce of an
+    // encrypted image, or the slots of a multi-image update. This does nothing if the magic is
+    // already set, and there is no trailer.
+    fn write_magic(&mut self, magic: u8, trailer_offset: usize, trailer: &[u8]) -> Result<(), FirmwareUpdaterError> {
         self.state.read(0, &mut self.aligned)?;
 
-        if self.aligned.iter().any(|&b| b != magic) {
+        if !trailer.is_empty() || self.aligned.iter().any(|&b| b != magic) {
             // Read progress validity
             self.state.read(STATE::WRITE_SIZE as u32, &mut self.aligned)?;
 
@@ -375,7 +1033,16 @@ impl<'d, STATE: NorFlash> BlockingFirmwareState<'d, STATE> {
             }
 
             // Clear magic and progress
-            self.state.erase(0, self.state.capacity() as u32)?;
+            let progress_len = self.progress_len();
+            self.state.erase(0, progress_len as u32)?;
+
+            // Set trailer
+            for (i, chunk) in trailer.chunks(STATE::WRITE_SIZE).enumerate() {
+                self.aligned.fill(STATE_ERASE_VALUE);
+                self.aligned[..chunk.len()].copy_from_slice(chunk);
+                self.state
+                    .write((trailer_offset + i * STATE::WRITE_SIZE) as u32, &self.aligned)?;
+            }
 
             // Set magic
             self.aligned.fill(magic);
@@ -496,4 +1163,304 @@ mod tests {
 
         assert_eq!(Sha1::digest(update).as_slice(), hash);
     }
+
+    #[test]
+    fn can_apply_patch() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8>::default()));
+        let state = BlockingPartition::new(&flash, 0, 4096);
+        let mut active = BlockingPartition::new(&flash, 4096, 61440);
+        let dfu = BlockingPartition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let mut original = [0; 8192];
+        for (i, b) in original.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        let mut update = [0; 9000];
+        update[..8192].copy_from_slice(&original);
+        update[100..110].fill(0x42);
+        update[8192..].fill(0x24);
+        active.write(0, &original).unwrap();
+
+        let patch = crate::delta::tests::naive_patch(&original, &update);
+
+        let mut updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        let mut buf = [0; 256];
+        let mut state = Patch::new(&mut buf);
+        for chunk in patch.chunks(333) {
+            updater.write_firmware_patch(&mut state, &mut active, chunk).unwrap();
+        }
+        let len = updater.finish_firmware_patch(&mut state).unwrap();
+        assert_eq!(update.len() as u32, len);
+
+        let mut chunk_buf = [0; 128];
+        updater
+            .verify_hash::<Sha1>(len, &mut chunk_buf, Sha1::digest(update).as_slice())
+            .unwrap();
+        assert!(matches!(
+            updater.verify_hash::<Sha1>(len, &mut chunk_buf, Sha1::digest(original).as_slice()),
+            Err(FirmwareUpdaterError::Digest)
+        ));
+    }
+
+    #[test]
+    fn can_apply_patch_with_read_size() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8, 4>::default()));
+        let state = BlockingPartition::new(&flash, 0, 4096);
+        let mut active = BlockingPartition::new(&flash, 4096, 61440);
+        let dfu = BlockingPartition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let mut original = [0; 1008];
+        for (i, b) in original.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        active.write(0, &original).unwrap();
+        let original = &original[..1001];
+
+        // Diffs at source offsets and of lengths which aren't multiples of the read size.
+        let mut update = [0; 98];
+        update[..3].copy_from_slice(&original[..3]);
+        update[3..8].copy_from_slice(b"extra");
+        update[8..45].copy_from_slice(&original[10..47]);
+        update[8..45].iter_mut().for_each(|b| *b = b.wrapping_add(1));
+        update[45..].copy_from_slice(&original[948..]);
+        let patch = crate::delta::tests::build_patch(
+            original,
+            &update,
+            &[(&[0; 3], b"extra", 7), (&[1; 37], b"", 901), (&[0; 53], b"", 0)],
+        );
+
+        let mut updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        let mut buf = [0; 64];
+        let mut state = Patch::new(&mut buf);
+        for chunk in patch.chunks(7) {
+            updater.write_firmware_patch(&mut state, &mut active, chunk).unwrap();
+        }
+        let len = updater.finish_firmware_patch(&mut state).unwrap();
+        assert_eq!(update.len() as u32, len);
+
+        let mut chunk_buf = [0; 128];
+        updater
+            .verify_hash::<Sha1>(len, &mut chunk_buf, Sha1::digest(update).as_slice())
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_patches() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8>::default()));
+        let state = BlockingPartition::new(&flash, 0, 4096);
+        let mut active = BlockingPartition::new(&flash, 4096, 61440);
+        let dfu = BlockingPartition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+        active.write(0, &[0; 64]).unwrap();
+
+        let mut updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        let mut buf = [0; 64];
+
+        let patch = crate::delta::tests::naive_patch(&[0; 64], &[1; 128]);
+        let mut state = Patch::new(&mut buf);
+        updater
+            .write_firmware_patch(&mut state, &mut active, &patch[..patch.len() - 1])
+            .unwrap();
+        assert!(matches!(
+            updater.finish_firmware_patch(&mut state),
+            Err(FirmwareUpdaterError::Patch(PatchError::Truncated))
+        ));
+
+        // Made against another image
+        let patch = crate::delta::tests::naive_patch(&[2; 64], &[1; 128]);
+        let mut state = Patch::new(&mut buf);
+        assert!(matches!(
+            updater.write_firmware_patch(&mut state, &mut active, &patch),
+            Err(FirmwareUpdaterError::Patch(PatchError::SourceMismatch))
+        ));
+
+        // Producing another image than announced
+        let mut patch = crate::delta::tests::naive_patch(&[0; 64], &[1; 128]);
+        patch[100] ^= 1;
+        updater.prepare_update().unwrap();
+        let mut state = Patch::new(&mut buf);
+        updater.write_firmware_patch(&mut state, &mut active, &patch).unwrap();
+        assert!(matches!(
+            updater.finish_firmware_patch(&mut state),
+            Err(FirmwareUpdaterError::Patch(PatchError::TargetMismatch))
+        ));
+    }
+
+    #[test]
+    fn can_write_compressed_firmware() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8>::default()));
+        let state = BlockingPartition::new(&flash, 0, 4096);
+        let dfu = BlockingPartition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let update = crate::compress::tests::test_image(9000);
+        let compressed = crate::compress::tests::compress(&update, 8, 4);
+
+        let mut updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        let mut window = [0; 256];
+        let mut buf = [0; 256];
+        let mut decompression = Decompression::new(&mut window, &mut buf);
+        for chunk in compressed.chunks(333) {
+            updater.write_firmware_compressed(&mut decompression, chunk).unwrap();
+        }
+        let len = updater.finish_firmware_compressed(&mut decompression).unwrap();
+        assert_eq!(update.len() as u32, len);
+
+        let mut chunk_buf = [0; 128];
+        updater
+            .verify_hash::<Sha1>(len, &mut chunk_buf, Sha1::digest(&update).as_slice())
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_data_after_compressed_firmware() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8>::default()));
+        let state = BlockingPartition::new(&flash, 0, 4096);
+        let dfu = BlockingPartition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let mut compressed = crate::compress::tests::compress(&[0x42; 100], 8, 4);
+        compressed.push(0);
+
+        let mut updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        let mut window = [0; 256];
+        let mut buf = [0; 64];
+        let mut decompression = Decompression::new(&mut window, &mut buf);
+        assert!(matches!(
+            updater.write_firmware_compressed(&mut decompression, &compressed),
+            Err(FirmwareUpdaterError::Decompress(DecompressError::Overrun))
+        ));
+    }
+
+    #[test]
+    fn can_hash_compressed_firmware() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8>::default()));
+        let state = BlockingPartition::new(&flash, 0, 4096);
+        let dfu = BlockingPartition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let update = crate::compress::tests::test_image(5000);
+        let mut compressed = crate::compress::tests::compress(&update, 8, 4);
+        compressed.resize(compressed.len().next_multiple_of(8), STATE_ERASE_VALUE);
+
+        let mut updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        updater.write_firmware(0, &compressed).unwrap();
+
+        let mut window = [0; 256];
+        let mut chunk_buf = [0; 32];
+        let mut hash = [0; 20];
+        updater
+            .hash_compressed::<Sha1>(&mut window, &mut chunk_buf, &mut hash)
+            .unwrap();
+        assert_eq!(Sha1::digest(&update).as_slice(), hash);
+    }
+
+    #[test]
+    #[cfg(feature = "metadata")]
+    fn can_validate_image() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8>::default()));
+        let mut aligned = [0; 8];
+
+        let image = crate::metadata::tests::with_metadata(&[0x42; 1001], 0xAB, 5);
+        let mut padded = image.clone();
+        padded.resize(image.len().next_multiple_of(8), STATE_ERASE_VALUE);
+
+        let mut updater = BlockingFirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: BlockingPartition::new(&flash, 65536, 65536),
+                state: BlockingPartition::new(&flash, 0, 4096),
+            },
+            &mut aligned,
+        );
+        updater.write_firmware(0, &padded).unwrap();
+        assert!(matches!(
+            updater.read_metadata(padded.len() as u32),
+            Err(FirmwareUpdaterError::Metadata(MetadataError::BadMagic))
+        ));
+        assert!(matches!(
+            updater.validate_image(image.len() as u32, 0xCD),
+            Err(FirmwareUpdaterError::Metadata(MetadataError::WrongHardware))
+        ));
+        let metadata = updater.validate_image(image.len() as u32, 0xAB).unwrap();
+        assert_eq!(1001, metadata.image_len);
+        assert_eq!(5, metadata.security_counter);
+
+        let mut state = BlockingFirmwareState::new(BlockingPartition::new(&flash, 0, 4096), &mut aligned);
+        assert_eq!(None, state.pending_metadata().unwrap());
+        state.mark_updated().unwrap();
+        assert_eq!(Some(metadata), state.pending_metadata().unwrap());
+        assert_eq!(None, state.active_metadata().unwrap());
+        assert_eq!(0, state.security_counter().unwrap());
+
+        // Marking the swapped image booted makes it active.
+        state.mark_booted().unwrap();
+        assert_eq!(None, state.pending_metadata().unwrap());
+        assert_eq!(Some(metadata), state.active_metadata().unwrap());
+        assert_eq!(5, state.security_counter().unwrap());
+
+        let image = crate::metadata::tests::with_metadata(&[0x24; 1000], 0xAB, 4);
+        let mut updater = BlockingFirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: BlockingPartition::new(&flash, 65536, 65536),
+                state: BlockingPartition::new(&flash, 0, 4096),
+            },
+            &mut aligned,
+        );
+        updater.write_firmware(0, &image).unwrap();
+        assert!(matches!(
+            updater.validate_image(image.len() as u32, 0xAB),
+            Err(FirmwareUpdaterError::Metadata(MetadataError::Downgrade))
+        ));
+        // Nor can the older image be marked updated without passing validation.
+        #[cfg(not(feature = "_verify"))]
+        assert!(matches!(
+            updater.mark_updated(),
+            Err(FirmwareUpdaterError::Metadata(MetadataError::NotValidated))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "metadata")]
+    fn can_record_many_images() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8>::default()));
+        let mut aligned = [0; 8];
+
+        // The metadata log fills up and starts over several times.
+        for counter in 0..50 {
+            let image = crate::metadata::tests::with_metadata(&[counter as u8; 1000], 0xAB, counter);
+            let mut updater = BlockingFirmwareUpdater::new(
+                FirmwareUpdaterConfig {
+                    dfu: BlockingPartition::new(&flash, 65536, 65536),
+                    state: BlockingPartition::new(&flash, 0, 4096),
+                },
+                &mut aligned,
+            );
+            updater.write_firmware(0, &image).unwrap();
+            updater.validate_image(image.len() as u32, 0xAB).unwrap();
+
+            let mut state = BlockingFirmwareState::new(BlockingPartition::new(&flash, 0, 4096), &mut aligned);
+            assert_eq!(counter.saturating_sub(1), state.security_counter().unwrap());
+            state.mark_updated().unwrap();
+            state.mark_booted().unwrap();
+            assert_eq!(counter, state.security_counter().unwrap());
+        }
+    }
+
+    #[test]
+    fn rejects_metadata_without_storage() {
+        let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(MemFlash::<131072, 1024, 8>::default()));
+        let state = BlockingPartition::new(&flash, 0, 1024);
+        let dfu = BlockingPartition::new(&flash, 65536, 65536);
+        let mut aligned = [0; 8];
+
+        let image = crate::metadata::tests::with_metadata(&[0x42; 1000], 0xAB, 5);
+        let mut updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut aligned);
+        updater.write_firmware(0, &image).unwrap();
+        assert!(matches!(
+            updater.validate_image(image.len() as u32, 0xAB),
+            Err(FirmwareUpdaterError::Metadata(MetadataError::NoStorage))
+        ));
+    }
 }
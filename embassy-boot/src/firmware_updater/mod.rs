@@ -1,10 +1,16 @@
 mod asynch;
 mod blocking;
 
-pub use asynch::{FirmwareState, FirmwareUpdater};
-pub use blocking::{BlockingFirmwareState, BlockingFirmwareUpdater};
+pub use asynch::{FirmwareState, FirmwareUpdater, MultiFirmwareUpdater};
+pub use blocking::{BlockingFirmwareState, BlockingFirmwareUpdater, BlockingMultiFirmwareUpdater};
 use embedded_storage::nor_flash::{NorFlashError, NorFlashErrorKind};
 
+#[cfg(feature = "ed25519-dalek")]
+use crate::digest_adapters::ed25519_dalek::Sha512;
+#[cfg(all(feature = "ed25519-salty", not(feature = "ed25519-dalek")))]
+use crate::digest_adapters::salty::Sha512;
+use crate::{DecompressError, ManifestError, MetadataError, PatchError};
+
 /// Firmware updater flash configuration holding the two flashes used by the updater
 ///
 /// If only a single flash is actually used, then that flash should be partitioned into two partitions before use.
@@ -17,6 +23,15 @@ pub struct FirmwareUpdaterConfig<DFU, STATE> {
     pub state: STATE,
 }
 
+/// Multi-image firmware updater flash configuration, holding the DFU partition of each slot of a
+/// [`MultiBootLoader`](crate::MultiBootLoader), and the state partition.
+pub struct MultiFirmwareUpdaterConfig<DFU, STATE, const N: usize> {
+    /// The dfu flash partitions, in slot order
+    pub dfu: [DFU; N],
+    /// The state flash partition
+    pub state: STATE,
+}
+
 /// Errors returned by FirmwareUpdater
 #[derive(Debug)]
 pub enum FirmwareUpdaterError {
@@ -26,6 +41,16 @@ pub enum FirmwareUpdaterError {
     Signature(signature::Error),
     /// Bad state.
     BadState,
+    /// Digest of the update does not match the expected digest.
+    Digest,
+    /// Delta patch errors.
+    Patch(PatchError),
+    /// Compressed image errors.
+    Decompress(DecompressError),
+    /// Image metadata errors.
+    Metadata(MetadataError),
+    /// Multi-image manifest errors.
+    Manifest(ManifestError),
 }
 
 #[cfg(feature = "defmt")]
@@ -35,6 +60,11 @@ impl defmt::Format for FirmwareUpdaterError {
             FirmwareUpdaterError::Flash(_) => defmt::write!(fmt, "FirmwareUpdaterError::Flash(_)"),
             FirmwareUpdaterError::Signature(_) => defmt::write!(fmt, "FirmwareUpdaterError::Signature(_)"),
             FirmwareUpdaterError::BadState => defmt::write!(fmt, "FirmwareUpdaterError::BadState"),
+            FirmwareUpdaterError::Digest => defmt::write!(fmt, "FirmwareUpdaterError::Digest"),
+            FirmwareUpdaterError::Patch(e) => defmt::write!(fmt, "FirmwareUpdaterError::Patch({})", e),
+            FirmwareUpdaterError::Decompress(e) => defmt::write!(fmt, "FirmwareUpdaterError::Decompress({})", e),
+            FirmwareUpdaterError::Metadata(e) => defmt::write!(fmt, "FirmwareUpdaterError::Metadata({})", e),
+            FirmwareUpdaterError::Manifest(e) => defmt::write!(fmt, "FirmwareUpdaterError::Manifest({})", e),
         }
     }
 }
@@ -47,3 +77,46 @@ where
         FirmwareUpdaterError::Flash(error.kind())
     }
 }
+
+/// Verify a signature of the SHA-512 digest of an image.
+#[cfg(feature = "_verify")]
+fn verify_signature(
+    public_key: &[u8; 32],
+    signature: &[u8; 64],
+    message: &[u8; 64],
+) -> Result<(), FirmwareUpdaterError> {
+    #[cfg(feature = "ed25519-dalek")]
+    {
+        use ed25519_dalek::{Signature, SignatureError, Verifier, VerifyingKey};
+
+        let into_signature_error = |e: SignatureError| FirmwareUpdaterError::Signature(e.into());
+
+        let public_key = VerifyingKey::from_bytes(public_key).map_err(into_signature_error)?;
+        let signature = Signature::from_bytes(signature);
+
+        public_key.verify(message, &signature).map_err(into_signature_error)?
+    }
+    #[cfg(feature = "ed25519-salty")]
+    {
+        use salty::{PublicKey, Signature};
+
+        fn into_signature_error<E>(_: E) -> FirmwareUpdaterError {
+            FirmwareUpdaterError::Signature(signature::Error::default())
+        }
+
+        let public_key = PublicKey::try_from(public_key).map_err(into_signature_error)?;
+        let signature = Signature::try_from(signature).map_err(into_signature_error)?;
+
+        let r = public_key.verify(message, &signature);
+        trace!(
+            "Verifying with public key {}, signature {} and message {} yields ok: {}",
+            public_key.to_bytes(),
+            signature.to_bytes(),
+            message,
+            r.is_ok()
+        );
+        r.map_err(into_signature_error)?
+    }
+
+    Ok(())
+}
@@ -5,10 +5,17 @@
 mod fmt;
 
 mod boot_loader;
+mod compress;
+mod delta;
 mod digest_adapters;
+#[cfg(feature = "encryption")]
+mod encryption;
 mod firmware_updater;
+mod manifest;
 #[cfg(test)]
 mod mem_flash;
+mod metadata;
+mod multi_boot_loader;
 #[cfg(test)]
 mod test_flash;
 
@@ -16,10 +23,19 @@ mod test_flash;
 // TODO: Use the value provided by NorFlash when available
 pub(crate) const STATE_ERASE_VALUE: u8 = 0xFF;
 pub use boot_loader::{BootError, BootLoader, BootLoaderConfig};
+pub use compress::{DecompressError, Decompression, Decompressor, COMPRESSED_MAGIC};
+pub use delta::{Patch, PatchDecoder, PatchError, PatchOp, PATCH_DIGEST_LEN, PATCH_MAGIC};
+#[cfg(feature = "encryption")]
+pub use encryption::{DeviceKey, ImageCipher, KEY_LEN, NONCE_LEN};
 pub use firmware_updater::{
-    BlockingFirmwareState, BlockingFirmwareUpdater, FirmwareState, FirmwareUpdater, FirmwareUpdaterConfig,
-    FirmwareUpdaterError,
+    BlockingFirmwareState, BlockingFirmwareUpdater, BlockingMultiFirmwareUpdater, FirmwareState, FirmwareUpdater,
+    FirmwareUpdaterConfig, FirmwareUpdaterError, MultiFirmwareUpdater, MultiFirmwareUpdaterConfig,
 };
+pub use manifest::{
+    Manifest, ManifestEntry, ManifestError, MANIFEST_ENTRY_LEN, MANIFEST_HEADER_LEN, MANIFEST_MAGIC, MAX_SLOTS,
+};
+pub use metadata::{ImageMetadata, MetadataError, Version, IMAGE_METADATA_LEN, IMAGE_METADATA_MAGIC};
+pub use multi_boot_loader::{BootSlot, MultiBootLoader, MultiBootLoaderConfig};
 
 pub(crate) const BOOT_MAGIC: u8 = 0xD0;
 pub(crate) const SWAP_MAGIC: u8 = 0xF0;
@@ -35,6 +51,8 @@ pub enum State {
     Swap,
     /// Application has received a request to reboot into DFU mode to apply an update.
     DfuDetach,
+    /// Bootloader has reverted to the previous image, as the swapped one was not marked booted.
+    Reverted,
 }
 
 /// Buffer aligned to 32 byte boundary, largest known alignment requirement for embassy-boot.
@@ -57,14 +75,21 @@ impl<const N: usize> AsMut<[u8]> for AlignedBuffer<N> {
 mod tests {
     #![allow(unused_imports)]
 
+    use core::cell::RefCell;
+
+    use embassy_embedded_hal::flash::partition::BlockingPartition;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
     use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
     use embedded_storage_async::nor_flash::NorFlash as AsyncNorFlash;
     use futures::executor::block_on;
 
     use super::*;
     use crate::boot_loader::BootLoaderConfig;
-    use crate::firmware_updater::FirmwareUpdaterConfig;
+    use crate::firmware_updater::{FirmwareUpdaterConfig, MultiFirmwareUpdaterConfig};
     use crate::mem_flash::MemFlash;
+    #[cfg(not(feature = "_verify"))]
+    use crate::test_flash::PowerLossFlash;
     use crate::test_flash::{AsyncTestFlash, BlockingTestFlash};
 
     /*
@@ -103,7 +128,7 @@ mod tests {
     }
 
     #[test]
-    #[cfg(not(feature = "_verify"))]
+    #[cfg(not(any(feature = "_verify", feature = "metadata")))]
     fn test_swap_state() {
         const FIRMWARE_SIZE: usize = 57344;
         let flash = AsyncTestFlash::new(BootLoaderConfig {
@@ -151,7 +176,7 @@ mod tests {
         assert_eq!(ORIGINAL, read_buf);
 
         // Running again should cause a revert
-        assert_eq!(State::Swap, bootloader.prepare_boot(&mut page).unwrap());
+        assert_eq!(State::Reverted, bootloader.prepare_boot(&mut page).unwrap());
 
         let mut read_buf = [0; FIRMWARE_SIZE];
         flash.active().read(0, &mut read_buf).unwrap();
@@ -181,7 +206,7 @@ mod tests {
     }
 
     #[test]
-    #[cfg(not(feature = "_verify"))]
+    #[cfg(not(any(feature = "_verify", feature = "metadata")))]
     fn test_swap_state_active_page_biggest() {
         const FIRMWARE_SIZE: usize = 12288;
         let flash = AsyncTestFlash::new(BootLoaderConfig {
@@ -226,7 +251,7 @@ mod tests {
     }
 
     #[test]
-    #[cfg(not(feature = "_verify"))]
+    #[cfg(not(any(feature = "_verify", feature = "metadata")))]
     fn test_swap_state_dfu_page_biggest() {
         const FIRMWARE_SIZE: usize = 12288;
         let flash = AsyncTestFlash::new(BootLoaderConfig {
@@ -270,7 +295,789 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature = "_verify")]
+    #[cfg(feature = "metadata")]
+    fn test_metadata_survives_swap() {
+        const FIRMWARE_SIZE: usize = 16384;
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<FIRMWARE_SIZE, 4096, 4>::default(),
+            dfu: MemFlash::<20480, 4096, 4>::default(),
+            state: MemFlash::<8192, 4096, 4>::default(),
+        });
+
+        let mut aligned = [0; 4];
+        let mut page = [0; 4096];
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+
+        for (counter, booted) in [(1, true), (2, false)] {
+            let image = metadata::tests::with_metadata(&[counter as u8; 4096], 0xAB, counter);
+            let mut updater = BlockingFirmwareUpdater::new(
+                FirmwareUpdaterConfig {
+                    dfu: flash.dfu(),
+                    state: flash.state(),
+                },
+                &mut aligned,
+            );
+            updater.write_firmware(0, &image).unwrap();
+            updater.validate_image(image.len() as u32, 0xAB).unwrap();
+
+            let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+            state.mark_updated().unwrap();
+            assert_eq!(State::Swap, bootloader.prepare_boot(&mut page).unwrap());
+
+            let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+            assert_eq!(counter, state.pending_metadata().unwrap().unwrap().security_counter);
+            if booted {
+                state.mark_booted().unwrap();
+            }
+        }
+
+        // The second image was reverted, the first one is still active.
+        assert_eq!(State::Reverted, bootloader.prepare_boot(&mut page).unwrap());
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        assert_eq!(State::Reverted, state.get_state().unwrap());
+        assert_eq!(None, state.pending_metadata().unwrap());
+        assert_eq!(1, state.security_counter().unwrap());
+
+        let mut read_buf = [0; 4096];
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!([1; 4096], read_buf);
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_boot_attempts() {
+        assert!(!boot_attempts(None));
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_boot_attempts_power_loss() {
+        // Lose power once at every write and erase, until the sequence completes without loss.
+        let mut power_loss_after = 0;
+        while boot_attempts(Some(power_loss_after)) {
+            power_loss_after += 1;
+        }
+        assert!(power_loss_after > 0);
+    }
+
+    /// Boot an update that is never marked booted, losing power after `power_loss_after` writes and
+    /// erases. Returns whether power was lost.
+    #[cfg(not(feature = "_verify"))]
+    fn boot_attempts(power_loss_after: Option<usize>) -> bool {
+        const ORIGINAL: [u8; 3072] = [0x55; 3072];
+        const UPDATE: [u8; 3072] = [0xAA; 3072];
+
+        let sim = PowerLossFlash::new(8192);
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: sim.partition(0, 3072),
+            dfu: sim.partition(3072, 4096),
+            state: sim.partition(7168, 1024),
+        });
+        let mut aligned = [0; 4];
+
+        flash.active().write(0, &ORIGINAL).unwrap();
+        flash.dfu().write(0, &UPDATE).unwrap();
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        state.mark_updated().unwrap();
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        })
+        .with_max_boot_attempts(3);
+        let mut page = [0; 1024];
+        let mut lost = false;
+        sim.set_power_loss(power_loss_after);
+
+        // Reboot until the bootloader completes, power being back for good after a loss.
+        let mut boot = || loop {
+            match bootloader.prepare_boot(&mut page) {
+                Ok(state) => break state,
+                Err(e) => {
+                    // Anything but a power loss is a bug.
+                    assert!(sim.power_on(), "{:?}", e);
+                    lost = true;
+                }
+            }
+        };
+
+        // Losing power while completing the swap or counting an attempt leaves it done but the
+        // update not booted, which costs one of its attempts.
+        let mut read_buf = [0; 3072];
+        let mut swaps = 0;
+        let mut state = boot();
+        while state == State::Swap {
+            flash.active().read(0, &mut read_buf).unwrap();
+            assert_eq!(UPDATE, read_buf);
+            swaps += 1;
+            state = boot();
+        }
+        for state in [state, boot()] {
+            assert_eq!(State::Reverted, state);
+            flash.active().read(0, &mut read_buf).unwrap();
+            assert_eq!(ORIGINAL, read_buf);
+        }
+        assert!(swaps == 3 || lost && swaps == 2);
+
+        sim.set_power_loss(None);
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        assert_eq!(State::Reverted, state.get_state().unwrap());
+        state.mark_booted().unwrap();
+        assert_eq!(State::Boot, state.get_state().unwrap());
+        assert_eq!(State::Boot, bootloader.prepare_boot(&mut page).unwrap());
+        lost
+    }
+
+    #[test]
+    #[cfg(all(feature = "encryption", not(feature = "_verify")))]
+    fn test_encrypted_swap() {
+        assert!(!encrypted_swap(None));
+    }
+
+    #[test]
+    #[cfg(all(feature = "encryption", not(feature = "_verify")))]
+    fn test_encrypted_swap_power_loss() {
+        // Lose power once at every write and erase, until the swap and revert complete without loss.
+        let mut power_loss_after = 0;
+        while encrypted_swap(Some(power_loss_after)) {
+            power_loss_after += 1;
+        }
+        assert!(power_loss_after > 0);
+    }
+
+    /// Swap and revert an encrypted update, losing power after `power_loss_after` writes and erases.
+    /// Returns whether power was lost.
+    #[cfg(all(feature = "encryption", not(feature = "_verify")))]
+    fn encrypted_swap(power_loss_after: Option<usize>) -> bool {
+        use crate::encryption::PREVIOUS_IMAGE_POSITION;
+
+        const KEY: [u8; KEY_LEN] = [0x42; KEY_LEN];
+        const NONCE: [u8; NONCE_LEN] = [0x24; NONCE_LEN];
+        const ORIGINAL: [u8; 3072] = [0x55; 3072];
+        const UPDATE: [u8; 3072] = [0xAA; 3072];
+
+        let sim = PowerLossFlash::new(8192);
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: sim.partition(0, 3072),
+            dfu: sim.partition(3072, 4096),
+            state: sim.partition(7168, 1024),
+        });
+        let cipher = ImageCipher::new(&KEY, &NONCE);
+        let mut aligned = [0; 4];
+
+        let mut encrypted = UPDATE;
+        cipher.apply_keystream(0, &mut encrypted);
+        flash.active().write(0, &ORIGINAL).unwrap();
+        flash.dfu().write(0, &encrypted).unwrap();
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        state.mark_updated_encrypted(&NONCE).unwrap();
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+        let mut page = [0; 1024];
+        let mut key = KEY;
+        let mut lost = false;
+        sim.set_power_loss(power_loss_after);
+
+        let mut boot = || loop {
+            match bootloader.prepare_boot_encrypted(&mut page, &mut key) {
+                Ok(state) => break state,
+                Err(e) => {
+                    // Anything but a power loss is a bug.
+                    assert!(sim.power_on(), "{:?}", e);
+                    lost = true;
+                }
+            }
+        };
+
+        // Losing power while completing the swap leaves it done but the update not booted, so that
+        // it is reverted right away.
+        let mut read_buf = [0; 3072];
+        let mut state = boot();
+        let swapped = state == State::Swap;
+        if swapped {
+            flash.active().read(0, &mut read_buf).unwrap();
+            assert_eq!(UPDATE, read_buf);
+            // The previous image is kept encrypted
+            flash.dfu().read(1024, &mut read_buf).unwrap();
+            assert_ne!(ORIGINAL, read_buf);
+            cipher.apply_keystream(PREVIOUS_IMAGE_POSITION, &mut read_buf);
+            assert_eq!(ORIGINAL, read_buf);
+            state = boot();
+        }
+
+        assert_eq!(State::Reverted, state);
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(ORIGINAL, read_buf);
+        flash.dfu().read(0, &mut read_buf).unwrap();
+        assert_eq!(encrypted, read_buf);
+        assert!(swapped || lost);
+        lost
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_boot_attempts_marked_booted() {
+        const ORIGINAL: [u8; 3072] = [0x55; 3072];
+        const UPDATE: [u8; 3072] = [0xAA; 3072];
+
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<3072, 1024, 4>::default(),
+            dfu: MemFlash::<4096, 1024, 4>::default(),
+            state: MemFlash::<1024, 1024, 4>::default(),
+        });
+        let mut aligned = [0; 4];
+
+        flash.active().write(0, &ORIGINAL).unwrap();
+        flash.dfu().write(0, &UPDATE).unwrap();
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        state.mark_updated().unwrap();
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        })
+        .with_max_boot_attempts(3);
+        let mut page = [0; 1024];
+        assert_eq!(State::Swap, bootloader.prepare_boot(&mut page).unwrap());
+        assert_eq!(State::Swap, bootloader.prepare_boot(&mut page).unwrap());
+
+        // The second attempt succeeds.
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        assert_eq!(State::Swap, state.get_state().unwrap());
+        state.mark_booted().unwrap();
+        assert_eq!(State::Boot, bootloader.prepare_boot(&mut page).unwrap());
+
+        let mut read_buf = [0; 3072];
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(UPDATE, read_buf);
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "_verify", feature = "metadata")))]
+    fn test_install_compressed() {
+        const FIRMWARE_SIZE: usize = 16384;
+        let flash = AsyncTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<FIRMWARE_SIZE, 4096, 4>::default(),
+            dfu: MemFlash::<20480, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+
+        const ORIGINAL: [u8; FIRMWARE_SIZE] = [0x55; FIRMWARE_SIZE];
+        let update = compress::tests::test_image(10001);
+        let mut compressed = compress::tests::compress(&update, 8, 4);
+        compressed.resize(compressed.len().next_multiple_of(4), STATE_ERASE_VALUE);
+        let mut aligned = [0; 4];
+
+        block_on(flash.active().erase(0, ORIGINAL.len() as u32)).unwrap();
+        block_on(flash.active().write(0, &ORIGINAL)).unwrap();
+
+        let mut updater = FirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: flash.dfu(),
+                state: flash.state(),
+            },
+            &mut aligned,
+        );
+        block_on(updater.write_firmware(0, &compressed)).unwrap();
+        block_on(updater.mark_updated()).unwrap();
+
+        let flash = flash.into_blocking();
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+
+        // Without a window, the image cannot be installed: the update is dropped, and the active
+        // partition is untouched.
+        let mut page = [0; 1024];
+        assert_eq!(
+            Err(BootError::Decompress(DecompressError::UnsupportedParameters)),
+            bootloader.prepare_boot(&mut page)
+        );
+        let mut read_buf = [0; FIRMWARE_SIZE];
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(ORIGINAL, read_buf);
+        assert_eq!(State::Boot, bootloader.prepare_boot(&mut page).unwrap());
+
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        state.mark_updated().unwrap();
+        let mut window = [0; 256];
+        assert_eq!(
+            State::Swap,
+            bootloader.prepare_boot_with_window(&mut page, &mut window).unwrap()
+        );
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(update, read_buf[..update.len()]);
+        // The previous image is kept for reverting, as after a swap
+        flash.dfu().read(4096, &mut read_buf).unwrap();
+        assert_eq!(ORIGINAL, read_buf);
+
+        // Not marked booted, so the previous image is reverted.
+        assert_eq!(
+            State::Reverted,
+            bootloader.prepare_boot_with_window(&mut page, &mut window).unwrap()
+        );
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(ORIGINAL, read_buf);
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_install_compressed_marked_booted() {
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<8192, 4096, 4>::default(),
+            dfu: MemFlash::<12288, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+        let update = compress::tests::test_image(5000);
+        let mut compressed = compress::tests::compress(&update, 8, 4);
+        compressed.resize(compressed.len().next_multiple_of(4), STATE_ERASE_VALUE);
+        flash.dfu().write(0, &compressed).unwrap();
+        let mut aligned = [0; 4];
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        state.mark_updated().unwrap();
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+        let mut page = [0; 4096];
+        let mut window = [0; 256];
+        assert_eq!(
+            State::Swap,
+            bootloader.prepare_boot_with_window(&mut page, &mut window).unwrap()
+        );
+
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        state.mark_booted().unwrap();
+        assert_eq!(
+            State::Boot,
+            bootloader.prepare_boot_with_window(&mut page, &mut window).unwrap()
+        );
+        let mut read_buf = [0; 8192];
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(update, read_buf[..update.len()]);
+        assert!(read_buf[update.len()..].iter().all(|&b| b == STATE_ERASE_VALUE));
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_install_compressed_power_loss() {
+        // Lose power once at every write and erase, until the install and revert complete without loss.
+        let mut power_loss_after = 0;
+        while install_compressed(Some(power_loss_after)) {
+            power_loss_after += 1;
+        }
+        assert!(power_loss_after > 0);
+    }
+
+    /// Install a compressed update and revert it, losing power after `power_loss_after` writes and
+    /// erases. Returns whether power was lost.
+    #[cfg(not(feature = "_verify"))]
+    fn install_compressed(power_loss_after: Option<usize>) -> bool {
+        const ORIGINAL: [u8; 3072] = [0x55; 3072];
+
+        let sim = PowerLossFlash::new(8192);
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: sim.partition(0, 3072),
+            dfu: sim.partition(3072, 4096),
+            state: sim.partition(7168, 1024),
+        });
+        let update = compress::tests::test_image(3000);
+        let mut compressed = compress::tests::compress(&update, 8, 4);
+        compressed.resize(compressed.len().next_multiple_of(4), STATE_ERASE_VALUE);
+        let mut aligned = [0; 4];
+
+        flash.active().write(0, &ORIGINAL).unwrap();
+        flash.dfu().write(0, &compressed).unwrap();
+        let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+        state.mark_updated().unwrap();
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+        let mut page = [0; 1024];
+        let mut window = [0; 256];
+        let mut lost = false;
+        sim.set_power_loss(power_loss_after);
+
+        let mut boot = || loop {
+            match bootloader.prepare_boot_with_window(&mut page, &mut window) {
+                Ok(state) => break state,
+                Err(e) => {
+                    // Anything but a power loss is a bug.
+                    assert!(sim.power_on(), "{:?}", e);
+                    lost = true;
+                }
+            }
+        };
+
+        // Losing power while completing the install leaves it done but the update not booted, so
+        // that it is reverted right away.
+        let mut read_buf = [0; 3072];
+        let mut state = boot();
+        let installed = state == State::Swap;
+        if installed {
+            flash.active().read(0, &mut read_buf).unwrap();
+            assert_eq!(update, read_buf[..update.len()]);
+            state = boot();
+        }
+
+        assert_eq!(State::Reverted, state);
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(ORIGINAL, read_buf);
+        assert!(installed || lost);
+        lost
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_install_compressed_corrupt() {
+        const ORIGINAL: [u8; 8192] = [0x55; 8192];
+        let image = compress::tests::test_image(8000);
+
+        // Data ending early, and an incompressible image which would overwrite the compressed data
+        // it still needs while installing.
+        let mut truncated = compress::tests::compress(&image, 8, 4);
+        let len = truncated.len() - 16 - 8;
+        truncated[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        let mut noise = [0; 8000];
+        let mut x = 0x2545_f491u32;
+        for b in noise.iter_mut() {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            *b = x as u8;
+        }
+        let incompressible = compress::tests::compress(&noise, 8, 4);
+
+        for (mut compressed, error) in [
+            (truncated, DecompressError::Truncated),
+            (incompressible, DecompressError::Expanding),
+        ] {
+            let flash = BlockingTestFlash::new(BootLoaderConfig {
+                active: MemFlash::<8192, 4096, 4>::default(),
+                dfu: MemFlash::<12288, 4096, 4>::default(),
+                state: MemFlash::<4096, 4096, 4>::default(),
+            });
+            compressed.resize(compressed.len().next_multiple_of(4), STATE_ERASE_VALUE);
+            flash.active().write(0, &ORIGINAL).unwrap();
+            flash.dfu().write(0, &compressed).unwrap();
+            let mut aligned = [0; 4];
+            let mut state = BlockingFirmwareState::new(flash.state(), &mut aligned);
+            state.mark_updated().unwrap();
+
+            let mut bootloader = BootLoader::new(BootLoaderConfig {
+                active: flash.active(),
+                dfu: flash.dfu(),
+                state: flash.state(),
+            });
+            let mut page = [0; 4096];
+            let mut window = [0; 256];
+            assert_eq!(
+                Err(BootError::Decompress(error)),
+                bootloader.prepare_boot_with_window(&mut page, &mut window)
+            );
+
+            // The update is dropped, and the original image boots.
+            assert_eq!(
+                State::Boot,
+                bootloader.prepare_boot_with_window(&mut page, &mut window).unwrap()
+            );
+            let mut read_buf = [0; 8192];
+            flash.active().read(0, &mut read_buf).unwrap();
+            assert_eq!(ORIGINAL, read_buf);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_install_compressed_too_large() {
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<8192, 4096, 4>::default(),
+            dfu: MemFlash::<12288, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+
+        let mut compressed = compress::tests::compress(&[0x42; 8193], 8, 4);
+        compressed.resize(compressed.len().next_multiple_of(4), STATE_ERASE_VALUE);
+        flash.dfu().write(0, &compressed).unwrap();
+        flash.state().write(0, &[SWAP_MAGIC; 4]).unwrap();
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+
+        let mut page = [0; 4096];
+        let mut window = [0; 256];
+        assert_eq!(
+            Err(BootError::Decompress(DecompressError::Overrun)),
+            bootloader.prepare_boot_with_window(&mut page, &mut window)
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_multi_swap() {
+        assert!(!multi_swap(None));
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_multi_swap_power_loss() {
+        // Lose power once at every write and erase, until the swap and revert complete without loss.
+        let mut power_loss_after = 0;
+        while multi_swap(Some(power_loss_after)) {
+            power_loss_after += 1;
+        }
+        assert!(power_loss_after > 0);
+    }
+
+    #[cfg(any(not(feature = "_verify"), feature = "ed25519-dalek"))]
+    fn partition<F: NorFlash>(flash: &Mutex<NoopRawMutex, RefCell<F>>) -> BlockingPartition<'_, NoopRawMutex, F> {
+        BlockingPartition::new(flash, 0, flash.lock(|f| f.borrow().capacity()) as u32)
+    }
+
+    /// Swap and revert two slots together, losing power after `power_loss_after` writes and
+    /// erases. Returns whether power was lost.
+    #[cfg(not(feature = "_verify"))]
+    fn multi_swap(power_loss_after: Option<usize>) -> bool {
+        const ORIGINAL: [[u8; 2048]; 2] = [[0x11; 2048], [0x22; 2048]];
+        const UPDATE: [[u8; 2048]; 2] = [[0xAA; 2048], [0xBB; 2048]];
+
+        let sim = PowerLossFlash::new(11264);
+        let active = [0, 2048].map(|offset| Mutex::new(RefCell::new(sim.partition(offset, 2048))));
+        let dfu = [4096, 7168].map(|offset| Mutex::new(RefCell::new(sim.partition(offset, 3072))));
+        let state = Mutex::new(RefCell::new(sim.partition(10240, 1024)));
+        let mut aligned = [0; 4];
+
+        let mut updater = BlockingMultiFirmwareUpdater::new(
+            MultiFirmwareUpdaterConfig {
+                dfu: [partition(&dfu[0]), partition(&dfu[1])],
+                state: partition(&state),
+            },
+            &mut aligned,
+        );
+        for slot in 0..2 {
+            partition(&active[slot]).write(0, &ORIGINAL[slot]).unwrap();
+            updater.write_firmware(slot, 0, &UPDATE[slot]).unwrap();
+        }
+        let manifest = manifest::tests::manifest(&[(0, &UPDATE[0]), (1, &UPDATE[1])]);
+        updater.mark_updated(&manifest).unwrap();
+
+        let mut bootloader = MultiBootLoader::new(MultiBootLoaderConfig {
+            slots: [0, 1].map(|slot| BootSlot {
+                active: partition(&active[slot]),
+                dfu: partition(&dfu[slot]),
+            }),
+            state: partition(&state),
+        });
+        let mut page = [0; 1024];
+        let mut lost = false;
+        sim.set_power_loss(power_loss_after);
+
+        let mut boot = || loop {
+            match bootloader.prepare_boot(&mut page) {
+                Ok(state) => break state,
+                Err(e) => {
+                    // Anything but a power loss is a bug.
+                    assert!(sim.power_on(), "{:?}", e);
+                    lost = true;
+                }
+            }
+        };
+
+        // Losing power while completing the swap leaves it done but the update not booted, so that
+        // it is reverted right away.
+        let mut read_buf = [0; 2048];
+        let mut state = boot();
+        let swapped = state == State::Swap;
+        if swapped {
+            for slot in 0..2 {
+                partition(&active[slot]).read(0, &mut read_buf).unwrap();
+                assert_eq!(UPDATE[slot], read_buf);
+            }
+            state = boot();
+        }
+
+        assert_eq!(State::Reverted, state);
+        for slot in 0..2 {
+            partition(&active[slot]).read(0, &mut read_buf).unwrap();
+            assert_eq!(ORIGINAL[slot], read_buf);
+        }
+        assert!(swapped || lost);
+        lost
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_multi_swap_some_slots() {
+        const ORIGINAL: [u8; 2048] = [0x11; 2048];
+        const UPDATE: [u8; 2048] = [0xAA; 2048];
+
+        let active = [(); 2].map(|_| Mutex::new(RefCell::new(MemFlash::<2048, 1024, 4>::new(0x11))));
+        let dfu = [(); 2].map(|_| Mutex::new(RefCell::new(MemFlash::<3072, 1024, 4>::default())));
+        let state = Mutex::new(RefCell::new(MemFlash::<1024, 1024, 4>::default()));
+        let mut aligned = [0; 4];
+
+        // Only update the second slot
+        let mut updater = BlockingMultiFirmwareUpdater::new(
+            MultiFirmwareUpdaterConfig {
+                dfu: [partition(&dfu[0]), partition(&dfu[1])],
+                state: partition(&state),
+            },
+            &mut aligned,
+        );
+        updater.write_firmware(1, 0, &UPDATE).unwrap();
+        updater
+            .mark_updated(&manifest::tests::manifest(&[(1, &UPDATE)]))
+            .unwrap();
+        assert!(matches!(
+            updater.mark_updated(&manifest::tests::manifest(&[(2, &UPDATE)])),
+            Err(FirmwareUpdaterError::Manifest(ManifestError::BadSlot))
+        ));
+
+        let mut bootloader = MultiBootLoader::new(MultiBootLoaderConfig {
+            slots: [0, 1].map(|slot| BootSlot {
+                active: partition(&active[slot]),
+                dfu: partition(&dfu[slot]),
+            }),
+            state: partition(&state),
+        });
+        let mut page = [0; 1024];
+        assert_eq!(State::Swap, bootloader.prepare_boot(&mut page).unwrap());
+
+        updater.mark_booted().unwrap();
+        assert_eq!(State::Boot, bootloader.prepare_boot(&mut page).unwrap());
+
+        let mut read_buf = [0; 2048];
+        partition(&active[0]).read(0, &mut read_buf).unwrap();
+        assert_eq!(ORIGINAL, read_buf);
+        partition(&active[1]).read(0, &mut read_buf).unwrap();
+        assert_eq!(UPDATE, read_buf);
+    }
+
+    #[test]
+    #[cfg(not(feature = "_verify"))]
+    fn test_multi_rejects_unsupported_updates() {
+        const ORIGINAL: [u8; 2048] = [0x11; 2048];
+
+        let active = [(); 2].map(|_| Mutex::new(RefCell::new(MemFlash::<2048, 1024, 4>::new(0x11))));
+        let dfu = [(); 2].map(|_| Mutex::new(RefCell::new(MemFlash::<3072, 1024, 4>::default())));
+        let state = Mutex::new(RefCell::new(MemFlash::<1024, 1024, 4>::default()));
+        let mut aligned = [0; 4];
+
+        let mut bootloader = MultiBootLoader::new(MultiBootLoaderConfig {
+            slots: [0, 1].map(|slot| BootSlot {
+                active: partition(&active[slot]),
+                dfu: partition(&dfu[slot]),
+            }),
+            state: partition(&state),
+        });
+        let mut page = [0; 1024];
+
+        // A compressed image in a slot
+        let mut compressed = compress::tests::compress(&compress::tests::test_image(1000), 8, 4);
+        compressed.resize(compressed.len().next_multiple_of(4), STATE_ERASE_VALUE);
+        let mut updater = BlockingMultiFirmwareUpdater::new(
+            MultiFirmwareUpdaterConfig {
+                dfu: [partition(&dfu[0]), partition(&dfu[1])],
+                state: partition(&state),
+            },
+            &mut aligned,
+        );
+        updater.write_firmware(1, 0, &compressed).unwrap();
+        updater
+            .mark_updated(&manifest::tests::manifest(&[(1, &compressed)]))
+            .unwrap();
+        assert_eq!(Err(BootError::Unsupported), bootloader.prepare_boot(&mut page));
+        assert_eq!(State::Boot, bootloader.prepare_boot(&mut page).unwrap());
+
+        // An encrypted update, whose nonce is not mistaken for the slots
+        #[cfg(feature = "encryption")]
+        {
+            partition(&dfu[1]).erase(0, 3072).unwrap();
+            let mut state = BlockingFirmwareState::new(partition(&state), &mut aligned);
+            state.mark_updated_encrypted(&[0; NONCE_LEN]).unwrap();
+            assert_eq!(Err(BootError::Unsupported), bootloader.prepare_boot(&mut page));
+            assert_eq!(State::Boot, bootloader.prepare_boot(&mut page).unwrap());
+        }
+
+        let mut read_buf = [0; 2048];
+        for active in active.iter() {
+            partition(active).read(0, &mut read_buf).unwrap();
+            assert_eq!(ORIGINAL, read_buf);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ed25519-dalek")]
+    fn test_multi_verify() {
+        use ed25519_dalek::{Digest, Sha512, Signer, SigningKey};
+        use rand::rngs::OsRng;
+
+        const UPDATE: [[u8; 2048]; 2] = [[0xAA; 2048], [0xBB; 2048]];
+
+        let keypair = SigningKey::generate(&mut OsRng {});
+        let manifest = manifest::tests::manifest(&[(0, &UPDATE[0]), (1, &UPDATE[1])]);
+        let signature = keypair.sign(&Sha512::digest(&manifest));
+        let public_key = keypair.verifying_key().to_bytes();
+
+        let dfu = [(); 2].map(|_| Mutex::new(RefCell::new(MemFlash::<3072, 1024, 4>::default())));
+        let state = Mutex::new(RefCell::new(MemFlash::<1024, 1024, 4>::default()));
+        let mut aligned = [0; 4];
+        let mut updater = BlockingMultiFirmwareUpdater::new(
+            MultiFirmwareUpdaterConfig {
+                dfu: [partition(&dfu[0]), partition(&dfu[1])],
+                state: partition(&state),
+            },
+            &mut aligned,
+        );
+
+        // The second image does not match the manifest
+        updater.write_firmware(0, 0, &UPDATE[0]).unwrap();
+        updater.write_firmware(1, 0, &UPDATE[0]).unwrap();
+        assert!(matches!(
+            updater.verify_and_mark_updated(&public_key, &signature.to_bytes(), &manifest),
+            Err(FirmwareUpdaterError::Digest)
+        ));
+
+        let mut updater = BlockingMultiFirmwareUpdater::new(
+            MultiFirmwareUpdaterConfig {
+                dfu: [partition(&dfu[0]), partition(&dfu[1])],
+                state: partition(&state),
+            },
+            &mut aligned,
+        );
+        updater.write_firmware(1, 0, &UPDATE[1]).unwrap();
+        assert!(matches!(
+            updater.verify_and_mark_updated(&public_key, &[0; 64], &manifest),
+            Err(FirmwareUpdaterError::Signature(_))
+        ));
+        updater
+            .verify_and_mark_updated(&public_key, &signature.to_bytes(), &manifest)
+            .unwrap();
+        assert_eq!(State::Swap, updater.get_state().unwrap());
+    }
+
+    #[test]
+    #[cfg(all(feature = "_verify", not(feature = "metadata")))]
     fn test_verify() {
         // The following key setup is based on:
         // https://docs.rs/ed25519-dalek/latest/ed25519_dalek/#example
@@ -320,4 +1127,130 @@ mod tests {
         ))
         .is_ok());
     }
+
+    #[test]
+    #[cfg(feature = "_verify")]
+    fn test_verify_compressed() {
+        use ed25519_dalek::{Digest, Sha512, Signature, Signer, SigningKey};
+        use rand::rngs::OsRng;
+
+        let mut csprng = OsRng {};
+        let keypair = SigningKey::generate(&mut csprng);
+
+        let firmware = compress::tests::test_image(1000);
+        let mut digest = Sha512::new();
+        digest.update(&firmware);
+        let message = digest.finalize();
+        let signature: Signature = keypair.sign(&message);
+
+        let public_key = keypair.verifying_key();
+
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<0, 0, 0>::default(),
+            dfu: MemFlash::<4096, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+
+        let mut compressed = compress::tests::compress(&firmware, 8, 4);
+        compressed.resize(compressed.len().next_multiple_of(4), STATE_ERASE_VALUE);
+        flash.dfu().write(0, &compressed).unwrap();
+
+        let flash = flash.into_async();
+        let mut aligned = [0; 4];
+        let mut updater = FirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: flash.dfu(),
+                state: flash.state(),
+            },
+            &mut aligned,
+        );
+
+        let mut window = [0; 256];
+        assert!(block_on(updater.verify_compressed_and_mark_updated(
+            &public_key.to_bytes(),
+            &signature.to_bytes(),
+            &mut window,
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    #[cfg(all(feature = "encryption", feature = "_verify"))]
+    fn test_verify_encrypted() {
+        use ed25519_dalek::{Digest, Sha512, Signature, Signer, SigningKey};
+        use rand::rngs::OsRng;
+
+        const KEY: [u8; KEY_LEN] = [0x42; KEY_LEN];
+        const NONCE: [u8; NONCE_LEN] = [0x24; NONCE_LEN];
+        const ORIGINAL: [u8; 4096] = [0x55; 4096];
+        const UPDATE: [u8; 4096] = [0xAA; 4096];
+
+        let mut csprng = OsRng {};
+        let keypair = SigningKey::generate(&mut csprng);
+
+        let mut encrypted = UPDATE;
+        ImageCipher::new(&KEY, &NONCE).apply_keystream(0, &mut encrypted);
+        let mut digest = Sha512::new();
+        digest.update(encrypted);
+        digest.update(NONCE);
+        let message = digest.finalize();
+        let signature: Signature = keypair.sign(&message);
+
+        let public_key = keypair.verifying_key();
+
+        let flash = BlockingTestFlash::new(BootLoaderConfig {
+            active: MemFlash::<4096, 4096, 4>::default(),
+            dfu: MemFlash::<8192, 4096, 4>::default(),
+            state: MemFlash::<4096, 4096, 4>::default(),
+        });
+        flash.active().write(0, &ORIGINAL).unwrap();
+        flash.dfu().write(0, &encrypted).unwrap();
+
+        let mut aligned = [0; 4];
+        let mut updater = BlockingFirmwareUpdater::new(
+            FirmwareUpdaterConfig {
+                dfu: flash.dfu(),
+                state: flash.state(),
+            },
+            &mut aligned,
+        );
+
+        // The nonce is covered by the signature
+        assert!(matches!(
+            updater.verify_and_mark_updated_encrypted(
+                &public_key.to_bytes(),
+                &signature.to_bytes(),
+                encrypted.len() as u32,
+                &[0x25; NONCE_LEN],
+            ),
+            Err(FirmwareUpdaterError::Signature(_))
+        ));
+        assert_eq!(State::Boot, updater.get_state().unwrap());
+
+        updater
+            .verify_and_mark_updated_encrypted(
+                &public_key.to_bytes(),
+                &signature.to_bytes(),
+                encrypted.len() as u32,
+                &NONCE,
+            )
+            .unwrap();
+        assert_eq!(State::Swap, updater.get_state().unwrap());
+
+        let mut bootloader = BootLoader::new(BootLoaderConfig {
+            active: flash.active(),
+            dfu: flash.dfu(),
+            state: flash.state(),
+        });
+        let mut page = [0; 4096];
+        let mut key = KEY;
+        assert_eq!(
+            State::Swap,
+            bootloader.prepare_boot_encrypted(&mut page, &mut key).unwrap()
+        );
+
+        let mut read_buf = [0; 4096];
+        flash.active().read(0, &mut read_buf).unwrap();
+        assert_eq!(UPDATE, read_buf);
+    }
 }
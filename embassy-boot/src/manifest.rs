@@ -0,0 +1,179 @@
+//! Manifest of a multi-image update.
+
+/// Magic bytes at the start of a manifest.
+pub const MANIFEST_MAGIC: [u8; 4] = *b"EBMF";
+
+/// Length of the manifest header.
+pub const MANIFEST_HEADER_LEN: usize = 8;
+
+/// Length of a manifest entry.
+pub const MANIFEST_ENTRY_LEN: usize = 72;
+
+/// Maximum number of image slots.
+pub const MAX_SLOTS: usize = 32;
+
+/// Errors returned when parsing or checking a manifest.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ManifestError {
+    /// The manifest does not start with [`MANIFEST_MAGIC`].
+    BadMagic,
+    /// The manifest length does not match its number of entries, or an image does not fit its slot.
+    BadLength,
+    /// An entry refers to a slot that does not exist, or that is already updated by another entry.
+    BadSlot,
+}
+
+/// Entry of a manifest, describing the image of one slot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ManifestEntry {
+    /// Index of the slot.
+    pub slot: u32,
+    /// Length of the image.
+    pub image_len: u32,
+    /// SHA-512 digest of the image.
+    pub hash: [u8; 64],
+}
+
+impl ManifestEntry {
+    /// Parse an entry.
+    pub fn from_bytes(bytes: &[u8; MANIFEST_ENTRY_LEN]) -> Self {
+        Self {
+            slot: u32::from_le_bytes(unwrap!(bytes[0..4].try_into())),
+            image_len: u32::from_le_bytes(unwrap!(bytes[4..8].try_into())),
+            hash: unwrap!(bytes[8..].try_into()),
+        }
+    }
+
+    /// Serialize an entry.
+    pub fn to_bytes(&self) -> [u8; MANIFEST_ENTRY_LEN] {
+        let mut bytes = [0; MANIFEST_ENTRY_LEN];
+        bytes[0..4].copy_from_slice(&self.slot.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.image_len.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.hash);
+        bytes
+    }
+}
+
+/// Manifest of a multi-image update.
+///
+/// The manifest lists the images updated together, and is signed as a whole instead of each
+/// image. It has the following format:
+///
+/// | Field   | Size                   | Description                                  |
+/// |---------|------------------------|----------------------------------------------|
+/// | magic   | 4                      | [`MANIFEST_MAGIC`]                           |
+/// | count   | 4                      | Number of entries, little endian             |
+/// | entries | 72 * count             | [`ManifestEntry`] for each updated slot      |
+///
+/// An entry holds the slot index and the image length, both little endian, followed by the
+/// SHA-512 digest of the image.
+pub struct Manifest<'a> {
+    entries: &'a [u8],
+}
+
+impl<'a> Manifest<'a> {
+    /// Parse a manifest.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ManifestError> {
+        if bytes.len() < MANIFEST_HEADER_LEN {
+            return Err(ManifestError::BadLength);
+        }
+        if bytes[..4] != MANIFEST_MAGIC {
+            return Err(ManifestError::BadMagic);
+        }
+        let count = u32::from_le_bytes(unwrap!(bytes[4..8].try_into())) as usize;
+        let entries = &bytes[MANIFEST_HEADER_LEN..];
+        if count.checked_mul(MANIFEST_ENTRY_LEN) != Some(entries.len()) {
+            return Err(ManifestError::BadLength);
+        }
+
+        let manifest = Self { entries };
+        let mut slots = 0u32;
+        for entry in manifest.entries() {
+            let bit = 1u32.checked_shl(entry.slot).ok_or(ManifestError::BadSlot)?;
+            if slots & bit != 0 {
+                return Err(ManifestError::BadSlot);
+            }
+            slots |= bit;
+        }
+        Ok(manifest)
+    }
+
+    /// Iterate over the entries.
+    pub fn entries(&self) -> impl Iterator<Item = ManifestEntry> + 'a {
+        self.entries
+            .chunks_exact(MANIFEST_ENTRY_LEN)
+            .map(|entry| ManifestEntry::from_bytes(unwrap!(entry.try_into())))
+    }
+
+    /// Get the updated slots, as a mask with bit `n` set for slot `n`.
+    pub fn slots(&self) -> u32 {
+        self.entries().fold(0, |slots, entry| slots | 1 << entry.slot)
+    }
+
+    /// Serialize the header of a manifest with `count` entries, to be followed by the entries.
+    pub fn header(count: u32) -> [u8; MANIFEST_HEADER_LEN] {
+        let mut header = [0; MANIFEST_HEADER_LEN];
+        header[..4].copy_from_slice(&MANIFEST_MAGIC);
+        header[4..].copy_from_slice(&count.to_le_bytes());
+        header
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Build a manifest for images of slots.
+    pub(crate) fn manifest(images: &[(u32, &[u8])]) -> Vec<u8> {
+        use ed25519_dalek::{Digest, Sha512};
+
+        let mut manifest = Manifest::header(images.len() as u32).to_vec();
+        for (slot, image) in images {
+            let entry = ManifestEntry {
+                slot: *slot,
+                image_len: image.len() as u32,
+                hash: Sha512::digest(image).into(),
+            };
+            manifest.extend_from_slice(&entry.to_bytes());
+        }
+        manifest
+    }
+
+    #[test]
+    fn can_parse_manifest() {
+        let bytes = manifest(&[(0, &[1; 16]), (2, &[2; 8])]);
+        let manifest = Manifest::parse(&bytes).unwrap();
+        assert_eq!(0b101, manifest.slots());
+
+        let entries: Vec<_> = manifest.entries().collect();
+        assert_eq!(2, entries.len());
+        assert_eq!(2, entries[1].slot);
+        assert_eq!(8, entries[1].image_len);
+    }
+
+    #[test]
+    fn rejects_bad_manifests() {
+        let mut bytes = manifest(&[(0, &[1; 16])]);
+        assert_eq!(
+            Err(ManifestError::BadLength),
+            Manifest::parse(&bytes[..bytes.len() - 1]).map(|_| ())
+        );
+        assert_eq!(
+            Err(ManifestError::BadSlot),
+            Manifest::parse(&manifest(&[(1, &[]), (1, &[])])).map(|_| ())
+        );
+        assert_eq!(
+            Err(ManifestError::BadSlot),
+            Manifest::parse(&manifest(&[(32, &[])])).map(|_| ())
+        );
+
+        bytes[0] = b'X';
+        assert_eq!(Err(ManifestError::BadMagic), Manifest::parse(&bytes).map(|_| ()));
+    }
+}
@@ -5,7 +5,7 @@ use core::ops::{Bound, Range, RangeBounds};
 use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
 use embedded_storage_async::nor_flash::{NorFlash as AsyncNorFlash, ReadNorFlash as AsyncReadNorFlash};
 
-pub struct MemFlash<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> {
+pub struct MemFlash<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize, const READ_SIZE: usize = 1> {
     pub mem: [u8; SIZE],
     pub pending_write_successes: Option<usize>,
 }
@@ -13,7 +13,9 @@ pub struct MemFlash<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE
 #[derive(Debug)]
 pub struct MemFlashError;
 
-impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE> {
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize, const READ_SIZE: usize>
+    MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE, READ_SIZE>
+{
     pub const fn new(fill: u8) -> Self {
         Self {
             mem: [fill; SIZE],
@@ -35,6 +37,8 @@ impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> MemFla
 
     fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), MemFlashError> {
         let len = bytes.len();
+        assert!(len % READ_SIZE == 0);
+        assert!(offset as usize % READ_SIZE == 0);
         bytes.copy_from_slice(&self.mem[offset as usize..offset as usize + len]);
         Ok(())
     }
@@ -91,16 +95,16 @@ impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> MemFla
     }
 }
 
-impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> Default
-    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize, const READ_SIZE: usize> Default
+    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE, READ_SIZE>
 {
     fn default() -> Self {
         Self::new(0xFF)
     }
 }
 
-impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> ErrorType
-    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize, const READ_SIZE: usize> ErrorType
+    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE, READ_SIZE>
 {
     type Error = MemFlashError;
 }
@@ -111,10 +115,10 @@ impl NorFlashError for MemFlashError {
     }
 }
 
-impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> ReadNorFlash
-    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize, const READ_SIZE: usize> ReadNorFlash
+    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE, READ_SIZE>
 {
-    const READ_SIZE: usize = 1;
+    const READ_SIZE: usize = READ_SIZE;
 
     fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
         self.read(offset, bytes)
@@ -125,8 +129,8 @@ impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> ReadNo
     }
 }
 
-impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> NorFlash
-    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize, const READ_SIZE: usize> NorFlash
+    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE, READ_SIZE>
 {
     const WRITE_SIZE: usize = WRITE_SIZE;
     const ERASE_SIZE: usize = ERASE_SIZE;
@@ -140,10 +144,10 @@ impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> NorFla
     }
 }
 
-impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> AsyncReadNorFlash
-    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize, const READ_SIZE: usize> AsyncReadNorFlash
+    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE, READ_SIZE>
 {
-    const READ_SIZE: usize = 1;
+    const READ_SIZE: usize = READ_SIZE;
 
     async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
         self.read(offset, bytes)
@@ -154,8 +158,8 @@ impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> AsyncR
     }
 }
 
-impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize> AsyncNorFlash
-    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE>
+impl<const SIZE: usize, const ERASE_SIZE: usize, const WRITE_SIZE: usize, const READ_SIZE: usize> AsyncNorFlash
+    for MemFlash<SIZE, ERASE_SIZE, WRITE_SIZE, READ_SIZE>
 {
     const WRITE_SIZE: usize = WRITE_SIZE;
     const ERASE_SIZE: usize = ERASE_SIZE;
@@ -0,0 +1,230 @@
+//! Image metadata.
+
+use crate::STATE_ERASE_VALUE;
+
+/// Magic bytes at the start of the image metadata.
+pub const IMAGE_METADATA_MAGIC: [u8; 4] = *b"EBIM";
+
+/// Length of the image metadata trailer.
+pub const IMAGE_METADATA_LEN: usize = 32;
+
+/// Errors returned when validating image metadata.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MetadataError {
+    /// The image does not end with metadata starting with [`IMAGE_METADATA_MAGIC`].
+    BadMagic,
+    /// The image length in the metadata does not match the length of the image.
+    BadLength,
+    /// The image is built for another hardware.
+    WrongHardware,
+    /// The security counter of the image is lower than the one of the active image.
+    Downgrade,
+    /// The update was marked updated without being validated first, see
+    /// [`FirmwareUpdater::validate_image`](crate::FirmwareUpdater::validate_image).
+    NotValidated,
+    /// The `metadata` feature is disabled, or the state partition has no room for metadata, see
+    /// [`FirmwareState`](crate::FirmwareState).
+    NoStorage,
+}
+
+/// Semantic version of an image.
+///
+/// Versions are ordered by major, minor and patch number.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    /// Major version.
+    pub major: u16,
+    /// Minor version.
+    pub minor: u16,
+    /// Patch version.
+    pub patch: u16,
+}
+
+/// Metadata of a firmware image.
+///
+/// The metadata is appended to the image as a trailer, so that it is covered by the image
+/// signature, and does not move the vector table at the start of the image:
+///
+/// | Field            | Size | Description                                                     |
+/// |------------------|------|-----------------------------------------------------------------|
+/// | magic            | 4    | [`IMAGE_METADATA_MAGIC`]                                        |
+/// | major            | 2    | Major version, little endian                                    |
+/// | minor            | 2    | Minor version, little endian                                    |
+/// | patch            | 2    | Patch version, little endian                                    |
+/// | reserved         | 2    | Zero                                                            |
+/// | hardware_id      | 4    | Identifier of the target hardware, little endian                |
+/// | image_len        | 4    | Length of the image preceding the metadata, little endian       |
+/// | security_counter | 4    | Monotonic security counter, little endian                       |
+/// | reserved         | 8    | Zero                                                            |
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageMetadata {
+    /// Version of the image.
+    pub version: Version,
+    /// Identifier of the target hardware.
+    pub hardware_id: u32,
+    /// Length of the image, excluding the metadata.
+    pub image_len: u32,
+    /// Security counter. Images with a security counter lower than the one of the active image
+    /// are rejected.
+    pub security_counter: u32,
+}
+
+impl ImageMetadata {
+    /// Parse metadata.
+    pub fn from_bytes(bytes: &[u8; IMAGE_METADATA_LEN]) -> Result<Self, MetadataError> {
+        let half = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let word = |i: usize| u32::from_le_bytes(unwrap!(bytes[i..i + 4].try_into()));
+
+        if bytes[..4] != IMAGE_METADATA_MAGIC {
+            return Err(MetadataError::BadMagic);
+        }
+        Ok(Self {
+            version: Version {
+                major: half(4),
+                minor: half(6),
+                patch: half(8),
+            },
+            hardware_id: word(12),
+            image_len: word(16),
+            security_counter: word(20),
+        })
+    }
+
+    /// Serialize metadata.
+    pub fn to_bytes(&self) -> [u8; IMAGE_METADATA_LEN] {
+        let mut bytes = [0; IMAGE_METADATA_LEN];
+        bytes[..4].copy_from_slice(&IMAGE_METADATA_MAGIC);
+        bytes[4..6].copy_from_slice(&self.version.major.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.version.minor.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.version.patch.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.hardware_id.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.image_len.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.security_counter.to_le_bytes());
+        bytes
+    }
+}
+
+/// Kind of a metadata record in the state partition.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum RecordKind {
+    Active = 0x01,
+    Pending = 0x02,
+}
+
+pub(crate) const RECORD_LEN: usize = 4 + IMAGE_METADATA_LEN;
+pub(crate) const RECORD_COMMIT: u8 = !STATE_ERASE_VALUE;
+
+/// Length of the part of the state partition holding the magic and the swap progress.
+///
+/// With the `metadata` feature, if the state partition spans several erase sectors, its last
+/// sector is kept for the metadata log and never erased along with the magic.
+pub(crate) fn state_progress_len(capacity: usize, erase_size: usize) -> usize {
+    if cfg!(feature = "metadata") && capacity >= 2 * erase_size {
+        capacity - erase_size
+    } else {
+        capacity
+    }
+}
+
+/// Size of a record slot in the metadata log: the record, padded to `write_size`, followed by a
+/// commit word written once the record is complete.
+pub(crate) fn record_slot_size(write_size: usize) -> usize {
+    RECORD_LEN.next_multiple_of(write_size) + write_size
+}
+
+pub(crate) fn encode_record(kind: RecordKind, metadata: &ImageMetadata) -> [u8; RECORD_LEN] {
+    let mut record = [0; RECORD_LEN];
+    record[0] = kind as u8;
+    record[4..].copy_from_slice(&metadata.to_bytes());
+    record
+}
+
+/// Latest records of the metadata log.
+#[derive(Default)]
+pub(crate) struct Records {
+    pub active: Option<ImageMetadata>,
+    pub pending: Option<ImageMetadata>,
+    /// Offset of the next free slot, if any.
+    pub next: Option<u32>,
+}
+
+impl Records {
+    pub fn apply(&mut self, record: &[u8; RECORD_LEN]) {
+        let Ok(metadata) = ImageMetadata::from_bytes(unwrap!(record[4..].try_into())) else {
+            return;
+        };
+        if record[0] == RecordKind::Active as u8 {
+            self.active = Some(metadata);
+            self.pending = None;
+        } else if record[0] == RecordKind::Pending as u8 {
+            self.pending = Some(metadata);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    pub(crate) fn metadata(major: u16, hardware_id: u32, image_len: u32, security_counter: u32) -> ImageMetadata {
+        ImageMetadata {
+            version: Version {
+                major,
+                minor: 2,
+                patch: 3,
+            },
+            hardware_id,
+            image_len,
+            security_counter,
+        }
+    }
+
+    /// Append metadata to `firmware`.
+    pub(crate) fn with_metadata(firmware: &[u8], hardware_id: u32, security_counter: u32) -> Vec<u8> {
+        let mut image = firmware.to_vec();
+        image.extend_from_slice(&metadata(1, hardware_id, firmware.len() as u32, security_counter).to_bytes());
+        image
+    }
+
+    #[test]
+    fn can_parse_metadata() {
+        let metadata = metadata(1, 0x1234_5678, 4096, 7);
+        let bytes = metadata.to_bytes();
+        assert_eq!(Ok(metadata), ImageMetadata::from_bytes(&bytes));
+
+        let mut bytes = bytes;
+        bytes[0] = b'X';
+        assert_eq!(Err(MetadataError::BadMagic), ImageMetadata::from_bytes(&bytes));
+    }
+
+    #[test]
+    fn versions_are_ordered() {
+        let version = |major, minor, patch| Version { major, minor, patch };
+        assert!(version(1, 2, 3) < version(1, 2, 4));
+        assert!(version(1, 2, 3) < version(1, 3, 0));
+        assert!(version(1, 9, 9) < version(2, 0, 0));
+    }
+
+    #[test]
+    fn applies_records() {
+        let mut records = Records::default();
+        records.apply(&encode_record(RecordKind::Pending, &metadata(1, 0, 0, 1)));
+        assert_eq!(None, records.active);
+        assert_eq!(Some(metadata(1, 0, 0, 1)), records.pending);
+
+        records.apply(&encode_record(RecordKind::Active, &metadata(2, 0, 0, 2)));
+        assert_eq!(Some(metadata(2, 0, 0, 2)), records.active);
+        assert_eq!(None, records.pending);
+
+        // Incomplete records are ignored.
+        records.apply(&[STATE_ERASE_VALUE; RECORD_LEN]);
+        assert_eq!(Some(metadata(2, 0, 0, 2)), records.active);
+    }
+}
@@ -0,0 +1,238 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::boot_loader::assert_partitions;
+#[cfg(feature = "encryption")]
+use crate::encryption::{nonce_offset, NONCE_LEN};
+use crate::manifest::MAX_SLOTS;
+#[cfg(feature = "encryption")]
+use crate::STATE_ERASE_VALUE;
+use crate::{BootError, BootLoader, State, BOOT_MAGIC};
+
+/// Image slot of a [`MultiBootLoader`], holding an image and its update.
+pub struct BootSlot<ACTIVE, DFU> {
+    /// Flash type used for the active partition - the partition which the image is run from.
+    pub active: ACTIVE,
+    /// Flash type used for the dfu partition - the partition which the update is written to.
+    pub dfu: DFU,
+}
+
+/// Create a [`BootSlot`] from a flash and the address symbols defined in the linker file with
+/// `prefix`, such as `__bootloader_wifi` for `__bootloader_wifi_active_start`,
+/// `__bootloader_wifi_active_end`, `__bootloader_wifi_dfu_start` and `__bootloader_wifi_dfu_end`.
+///
+/// The `__bootloader` prefix gives the slot of the partitions used by
+/// [`BootLoaderConfig::from_linkerfile_blocking`](crate::BootLoaderConfig::from_linkerfile_blocking).
+///
+/// # Example
+/// ```ignore
+/// let layout = Flash::new_blocking(p.FLASH).into_blocking_regions();
+/// let flash = Mutex::new(RefCell::new(layout.bank1_region));
+///
+/// let config = MultiBootLoaderConfig {
+///     slots: [
+///         boot_slot_from_linkerfile!(&flash, &flash, "__bootloader"),
+///         boot_slot_from_linkerfile!(&flash, &flash, "__bootloader_wifi"),
+///     ],
+///     state: BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash).state,
+/// };
+/// ```
+#[macro_export]
+macro_rules! boot_slot_from_linkerfile {
+    ($active_flash:expr, $dfu_flash:expr, $prefix:literal) => {{
+        extern "C" {
+            #[link_name = concat!($prefix, "_active_start")]
+            static ACTIVE_START: u32;
+            #[link_name = concat!($prefix, "_active_end")]
+            static ACTIVE_END: u32;
+            #[link_name = concat!($prefix, "_dfu_start")]
+            static DFU_START: u32;
+            #[link_name = concat!($prefix, "_dfu_end")]
+            static DFU_END: u32;
+        }
+
+        let active = unsafe {
+            let start = &ACTIVE_START as *const u32 as u32;
+            let end = &ACTIVE_END as *const u32 as u32;
+            ::embassy_embedded_hal::flash::partition::BlockingPartition::new($active_flash, start, end - start)
+        };
+        let dfu = unsafe {
+            let start = &DFU_START as *const u32 as u32;
+            let end = &DFU_END as *const u32 as u32;
+            ::embassy_embedded_hal::flash::partition::BlockingPartition::new($dfu_flash, start, end - start)
+        };
+        $crate::BootSlot { active, dfu }
+    }};
+}
+
+/// Multi-image bootloader configuration.
+pub struct MultiBootLoaderConfig<ACTIVE, DFU, STATE, const N: usize> {
+    /// Image slots. The first one is usually the application.
+    pub slots: [BootSlot<ACTIVE, DFU>; N],
+    /// Flash type used for the state partition, shared by all slots.
+    pub state: STATE,
+}
+
+/// Bootloader updating several images together, such as an application and the firmware of a
+/// co-processor.
+///
+/// Each slot is swapped as with [`BootLoader`], and the slots share a single state partition, so
+/// that the images are updated together, and reverted together unless the application marks the
+/// boot successful. Only the slots in the update, see
+/// [`Manifest`](crate::Manifest), are swapped. All slots are swapped if the update was marked
+/// without slots, with `mark_updated`.
+///
+/// The progress of the slots follows each other in the state partition, which must have room for
+/// all of them.
+///
+/// Encrypted and compressed updates are not supported: they are dropped, and
+/// [`BootError::Unsupported`] is returned.
+pub struct MultiBootLoader<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash, const N: usize> {
+    slots: [BootSlot<ACTIVE, DFU>; N],
+    state: STATE,
+    max_boot_attempts: u8,
+}
+
+impl<ACTIVE: NorFlash, DFU: NorFlash, STATE: NorFlash, const N: usize> MultiBootLoader<ACTIVE, DFU, STATE, N> {
+    /// Create a new instance of a multi-image bootloader with the flash partitions.
+    ///
+    /// The partitions of each slot follow the same rules as for [`BootLoader::new`].
+    pub fn new(config: MultiBootLoaderConfig<ACTIVE, DFU, STATE, N>) -> Self {
+        assert!(N > 0 && N <= MAX_SLOTS);
+        Self {
+            slots: config.slots,
+            state: config.state,
+            max_boot_attempts: 1,
+        }
+    }
+
+    /// Set the number of times the swapped images are booted before they are reverted, unless the
+    /// application marks them booted. Defaults to 1, reverting the images on the first reset.
+    pub fn with_max_boot_attempts(mut self, max_boot_attempts: u8) -> Self {
+        assert!(max_boot_attempts > 0);
+        self.max_boot_attempts = max_boot_attempts;
+        self
+    }
+
+    /// Perform necessary boot preparations like swapping images.
+    ///
+    /// This works like [`BootLoader::prepare_boot`], for all the slots in the update. The images
+    /// are swapped one slot after the other, and reverted the same way.
+    pub fn prepare_boot(&mut self, aligned_buf: &mut [u8]) -> Result<State, BootError> {
+        // Ensure we have enough progress pages to store copy progress
+        assert_eq!(
+            0,
+            BootLoader::<ACTIVE, DFU, STATE>::PAGE_SIZE % aligned_buf.len() as u32
+        );
+        assert!(aligned_buf.len() >= STATE::WRITE_SIZE);
+        assert_eq!(0, aligned_buf.len() % ACTIVE::WRITE_SIZE);
+        assert_eq!(0, aligned_buf.len() % DFU::WRITE_SIZE);
+
+        // Ensure our partitions are able to handle boot operations
+        for slot in self.slots.iter() {
+            assert_partitions(
+                &slot.active,
+                &slot.dfu,
+                &self.state,
+                BootLoader::<ACTIVE, DFU, STATE>::PAGE_SIZE,
+            );
+        }
+        let attempts_end = self.slot(N - 1).boot_attempts_index() + self.max_boot_attempts as usize - 1;
+        let slots_offset = slots_offset(self.slot(0).progress_len(), STATE::WRITE_SIZE);
+        #[cfg(feature = "encryption")]
+        assert!(attempts_end <= nonce_offset(self.slot(0).progress_len(), STATE::WRITE_SIZE) / STATE::WRITE_SIZE);
+        assert!(attempts_end <= slots_offset / STATE::WRITE_SIZE);
+
+        let state = self.slot(0).read_state(aligned_buf)?;
+        if state != State::Swap {
+            return Ok(state);
+        }
+
+        let slots = &mut aligned_buf[..4usize.next_multiple_of(STATE::WRITE_SIZE)];
+        self.state.read(slots_offset as u32, slots)?;
+        let slots = u32::from_le_bytes(unwrap!(slots[..4].try_into()));
+        let updated = (0..N).filter(|&i| slots & (1 << i) != 0);
+
+        let mut swapped = true;
+        for i in updated.clone() {
+            swapped &= self.slot(i).is_swapped(aligned_buf)?;
+        }
+        if !swapped {
+            if self.is_unsupported(updated.clone(), aligned_buf)? {
+                trace!("Dropping update that cannot be swapped");
+                self.slot(0).set_magic(BOOT_MAGIC, aligned_buf)?;
+                return Err(BootError::Unsupported);
+            }
+
+            for i in updated {
+                trace!("Swapping slot {}", i);
+                self.slot(i).swap(aligned_buf)?;
+            }
+            trace!("Swapping done");
+            return Ok(State::Swap);
+        }
+
+        // The boot attempts follow the progress of the last slot.
+        let attempts = self.slot(N - 1).boot_attempts(aligned_buf)?;
+        if attempts + 1 < self.max_boot_attempts as usize {
+            trace!("Boot attempt {}", attempts + 2);
+            self.slot(N - 1).update_boot_attempts(attempts, aligned_buf)?;
+            return Ok(State::Swap);
+        }
+
+        for i in updated {
+            trace!("Reverting slot {}", i);
+            self.slot(i).revert(aligned_buf)?;
+        }
+        self.slot(0).invalidate_progress(aligned_buf)?;
+        trace!("Reverting done");
+        Ok(State::Reverted)
+    }
+
+    fn slot(&mut self, index: usize) -> BootLoader<&mut ACTIVE, &mut DFU, &mut STATE> {
+        let page_size = BootLoader::<ACTIVE, DFU, STATE>::PAGE_SIZE as usize;
+        let progress_offset = self.slots[..index]
+            .iter()
+            .map(|slot| 4 * (slot.active.capacity() / page_size))
+            .sum();
+        let slot = &mut self.slots[index];
+        BootLoader::new_slot(
+            &mut slot.active,
+            &mut slot.dfu,
+            &mut self.state,
+            self.max_boot_attempts,
+            progress_offset,
+        )
+    }
+
+    // Encrypted updates, marked with a nonce, and compressed images are only supported by the
+    // single image bootloader. The DFU partitions are not modified before the swap is complete, so
+    // this gives the same result when resuming a swap.
+    #[allow(unused_variables)]
+    fn is_unsupported(
+        &mut self,
+        updated: impl Iterator<Item = usize>,
+        aligned_buf: &mut [u8],
+    ) -> Result<bool, BootError> {
+        #[cfg(feature = "encryption")]
+        {
+            let offset = nonce_offset(self.slot(0).progress_len(), STATE::WRITE_SIZE);
+            let nonce_buf = &mut aligned_buf[..NONCE_LEN.next_multiple_of(STATE::WRITE_SIZE)];
+            self.state.read(offset as u32, nonce_buf)?;
+            if nonce_buf.iter().any(|&b| b != STATE_ERASE_VALUE) {
+                return Ok(true);
+            }
+        }
+
+        for i in updated {
+            if self.slot(i).is_compressed()? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Offset in the state partition of the slots of a multi-image update, at the end of the progress.
+pub(crate) fn slots_offset(progress_len: usize, write_size: usize) -> usize {
+    progress_len - 4usize.next_multiple_of(write_size)
+}
@@ -57,6 +57,7 @@ where
     DFU: NorFlash + embedded_storage_async::nor_flash::NorFlash,
     STATE: NorFlash + embedded_storage_async::nor_flash::NorFlash,
 {
+    #[cfg_attr(feature = "metadata", allow(dead_code))]
     pub fn into_async(self) -> super::AsyncTestFlash<ACTIVE, DFU, STATE> {
         let config = BootLoaderConfig {
             active: self.active.into_inner().into_inner(),
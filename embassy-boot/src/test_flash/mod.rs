@@ -1,5 +1,12 @@
+// Without a signature feature, only the swap tests use async flashes, and they don't run with the
+// `metadata` feature.
+#[cfg_attr(feature = "metadata", allow(dead_code))]
 mod asynch;
 mod blocking;
+#[cfg(not(feature = "_verify"))]
+mod power_loss;
 
 pub(crate) use asynch::AsyncTestFlash;
 pub(crate) use blocking::BlockingTestFlash;
+#[cfg(not(feature = "_verify"))]
+pub(crate) use power_loss::PowerLossFlash;
@@ -0,0 +1,40 @@
+use core::cell::RefCell;
+
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_embedded_hal::flash::sim_flash::{Config, SimFlash};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+type Flash = SimFlash<4, 1024>;
+
+/// A single [`SimFlash`] split into partitions, so that one power loss hits all of them.
+pub struct PowerLossFlash {
+    flash: Mutex<NoopRawMutex, RefCell<Flash>>,
+}
+
+impl PowerLossFlash {
+    pub fn new(size: usize) -> Self {
+        Self {
+            flash: Mutex::new(RefCell::new(Flash::new(size, Config::default()))),
+        }
+    }
+
+    pub fn partition(&self, offset: u32, size: u32) -> BlockingPartition<'_, NoopRawMutex, Flash> {
+        BlockingPartition::new(&self.flash, offset, size)
+    }
+
+    /// Lose power after `operations` more word writes and page erases.
+    pub fn set_power_loss(&self, operations: Option<usize>) {
+        self.flash.lock(|f| f.borrow_mut().set_power_loss(operations));
+    }
+
+    /// Restore power after a power loss, returning whether power was lost.
+    pub fn power_on(&self) -> bool {
+        self.flash.lock(|f| {
+            let mut f = f.borrow_mut();
+            let lost = !f.is_powered();
+            f.power_on();
+            lost
+        })
+    }
+}
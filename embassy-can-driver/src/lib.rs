@@ -0,0 +1,213 @@
+#![no_std]
+#![allow(async_fn_in_trait)]
+#![warn(missing_docs)]
+#![doc = include_str!("../README.md")]
+
+pub use embedded_can;
+pub use embedded_can::{ErrorKind, ExtendedId, Id, StandardId};
+
+/// Fault confinement state of the CAN controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusState {
+    /// Error active, normal operation. The controller signals protocol errors with active error
+    /// frames.
+    ErrorActive,
+    /// Error passive. An error counter exceeded 127, and the controller signals protocol errors
+    /// with passive error frames only.
+    ErrorPassive,
+    /// Bus off. The transmit error counter exceeded 255, and the controller no longer takes part
+    /// in bus traffic.
+    BusOff,
+}
+
+/// Acceptance filter.
+///
+/// A frame passes the filter if it has the same ID format, and its ID equals the filter ID in all
+/// bits set in the mask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Filter {
+    id: u32,
+    mask: u32,
+    extended: bool,
+}
+
+impl Filter {
+    /// Filters that accept all frames, standard and extended.
+    pub const ACCEPT_ALL: [Filter; 2] = [Self::accept_all_standard(), Self::accept_all_extended()];
+
+    /// Filter for standard IDs, matching the bits set in `mask`.
+    pub fn new_standard(id: StandardId, mask: u16) -> Self {
+        Self {
+            id: id.as_raw() as u32,
+            mask: (mask & 0x7FF) as u32,
+            extended: false,
+        }
+    }
+
+    /// Filter for extended IDs, matching the bits set in `mask`.
+    pub fn new_extended(id: ExtendedId, mask: u32) -> Self {
+        Self {
+            id: id.as_raw(),
+            mask: mask & 0x1FFF_FFFF,
+            extended: true,
+        }
+    }
+
+    /// Filter accepting a single ID.
+    pub fn exact(id: Id) -> Self {
+        match id {
+            Id::Standard(id) => Self::new_standard(id, u16::MAX),
+            Id::Extended(id) => Self::new_extended(id, u32::MAX),
+        }
+    }
+
+    /// Filter accepting all standard frames.
+    pub const fn accept_all_standard() -> Self {
+        Self {
+            id: 0,
+            mask: 0,
+            extended: false,
+        }
+    }
+
+    /// Filter accepting all extended frames.
+    pub const fn accept_all_extended() -> Self {
+        Self {
+            id: 0,
+            mask: 0,
+            extended: true,
+        }
+    }
+
+    /// Filter ID.
+    pub fn id(&self) -> Id {
+        match self.extended {
+            false => Id::Standard(StandardId::new(self.id as u16).unwrap()),
+            true => Id::Extended(ExtendedId::new(self.id).unwrap()),
+        }
+    }
+
+    /// Raw filter ID.
+    pub const fn raw_id(&self) -> u32 {
+        self.id
+    }
+
+    /// Mask of the ID bits that must match.
+    pub const fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Whether the filter applies to extended IDs.
+    pub const fn is_extended(&self) -> bool {
+        self.extended
+    }
+
+    /// Checks whether a frame with the given ID passes the filter.
+    pub fn matches(&self, id: Id) -> bool {
+        let (raw, extended) = match id {
+            Id::Standard(id) => (id.as_raw() as u32, false),
+            Id::Extended(id) => (id.as_raw(), true),
+        };
+        extended == self.extended && (raw ^ self.id) & self.mask == 0
+    }
+}
+
+/// Error returned by [`Can::set_filters`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum FilterError {
+    /// There are more filters than the controller supports.
+    TooManyFilters,
+    /// The controller failed to apply the filters.
+    Rejected,
+}
+
+/// CAN FD frame.
+///
+/// Implementations use the same type for classic and FD frames received on a CAN FD bus.
+pub trait FdFrame: embedded_can::Frame {
+    /// Create a new CAN FD data frame.
+    ///
+    /// Returns `None` if the ID is invalid, or `data` is longer than 64 bytes or has a length that
+    /// can't be encoded in a DLC.
+    fn new_fd(id: impl Into<Id>, data: &[u8], bit_rate_switching: bool) -> Option<Self>;
+
+    /// Whether this is a CAN FD frame, as opposed to a classic frame.
+    fn is_fd(&self) -> bool;
+
+    /// Whether the data phase of this frame is sent at the data bit rate.
+    fn bit_rate_switching(&self) -> bool;
+}
+
+/// Async CAN controller.
+pub trait Can {
+    /// Frame type.
+    type Frame: embedded_can::Frame;
+    /// Error type.
+    type Error: embedded_can::Error;
+
+    /// Queue a frame for transmission, waiting for space in the transmit queue.
+    async fn transmit(&mut self, frame: &Self::Frame) -> Result<(), Self::Error>;
+
+    /// Receive a frame.
+    ///
+    /// Bus errors are returned as they are detected. The controller keeps running, so receiving
+    /// can continue after an error.
+    async fn receive(&mut self) -> Result<Self::Frame, Self::Error>;
+
+    /// Replace the acceptance filters.
+    ///
+    /// Only frames passing at least one filter are received, so an empty list rejects all frames.
+    fn set_filters(&mut self, filters: &[Filter]) -> Result<(), FilterError>;
+
+    /// Current fault confinement state.
+    fn bus_state(&self) -> BusState;
+}
+
+/// Async CAN FD controller.
+pub trait CanFd: Can {
+    /// Frame type, for both classic and FD frames.
+    type FdFrame: FdFrame;
+
+    /// Queue a classic or FD frame for transmission, waiting for space in the transmit queue.
+    async fn transmit_fd(&mut self, frame: &Self::FdFrame) -> Result<(), Self::Error>;
+
+    /// Receive a classic or FD frame.
+    async fn receive_fd(&mut self) -> Result<Self::FdFrame, Self::Error>;
+}
+
+impl<T: Can + ?Sized> Can for &mut T {
+    type Frame = T::Frame;
+    type Error = T::Error;
+
+    async fn transmit(&mut self, frame: &Self::Frame) -> Result<(), Self::Error> {
+        T::transmit(self, frame).await
+    }
+
+    async fn receive(&mut self) -> Result<Self::Frame, Self::Error> {
+        T::receive(self).await
+    }
+
+    fn set_filters(&mut self, filters: &[Filter]) -> Result<(), FilterError> {
+        T::set_filters(self, filters)
+    }
+
+    fn bus_state(&self) -> BusState {
+        T::bus_state(self)
+    }
+}
+
+impl<T: CanFd + ?Sized> CanFd for &mut T {
+    type FdFrame = T::FdFrame;
+
+    async fn transmit_fd(&mut self, frame: &Self::FdFrame) -> Result<(), Self::Error> {
+        T::transmit_fd(self, frame).await
+    }
+
+    async fn receive_fd(&mut self) -> Result<Self::FdFrame, Self::Error> {
+        T::receive_fd(self).await
+    }
+}
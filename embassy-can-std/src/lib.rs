@@ -0,0 +1,151 @@
+#![warn(missing_docs)]
+#![doc = include_str!("../README.md")]
+
+use std::fmt;
+
+pub use embassy_can_driver as driver;
+use embassy_can_driver::embedded_can::{self, ErrorKind, Id};
+
+#[cfg(target_os = "linux")]
+mod socketcan;
+mod virtual_bus;
+
+#[cfg(target_os = "linux")]
+pub use socketcan::SocketCan;
+pub use virtual_bus::{VirtualBus, VirtualCan};
+
+/// Maximum data length of a CAN FD frame.
+const FD_MAX_LEN: usize = 64;
+
+/// Classic or CAN FD frame.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    id: Id,
+    data: [u8; FD_MAX_LEN],
+    len: u8,
+    remote: bool,
+    fd: bool,
+    brs: bool,
+}
+
+impl Frame {
+    fn new_raw(id: Id, data: &[u8], fd: bool, brs: bool) -> Option<Self> {
+        let valid = match fd {
+            false => data.len() <= 8,
+            true => matches!(data.len(), 0..=8 | 12 | 16 | 20 | 24 | 32 | 48 | 64),
+        };
+        if !valid {
+            return None;
+        }
+        let mut frame = Self {
+            id,
+            data: [0; FD_MAX_LEN],
+            len: data.len() as u8,
+            remote: false,
+            fd,
+            brs: fd && brs,
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Frame");
+        s.field("id", &self.id);
+        match self.remote {
+            true => s.field("remote_len", &self.len),
+            false => s.field("data", &embedded_can::Frame::data(self)),
+        };
+        s.field("fd", &self.fd).field("brs", &self.brs).finish()
+    }
+}
+
+impl embedded_can::Frame for Frame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        Self::new_raw(id.into(), data, false, false)
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        let mut frame = Self::new_raw(id.into(), &[], false, false)?;
+        if dlc > 8 {
+            return None;
+        }
+        frame.len = dlc as u8;
+        frame.remote = true;
+        Some(frame)
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self.id, Id::Extended(_))
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    fn dlc(&self) -> usize {
+        self.len as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        match self.remote {
+            true => &[],
+            false => &self.data[..self.len as usize],
+        }
+    }
+}
+
+impl embassy_can_driver::FdFrame for Frame {
+    fn new_fd(id: impl Into<Id>, data: &[u8], bit_rate_switching: bool) -> Option<Self> {
+        Self::new_raw(id.into(), data, true, bit_rate_switching)
+    }
+
+    fn is_fd(&self) -> bool {
+        self.fd
+    }
+
+    fn bit_rate_switching(&self) -> bool {
+        self.brs
+    }
+}
+
+/// Error.
+#[derive(Debug)]
+pub enum Error {
+    /// I/O error of the underlying socket.
+    Io(std::io::Error),
+    /// Error reported by the CAN controller.
+    Bus(ErrorKind),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Bus(kind) => write!(f, "bus error: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl embedded_can::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Io(_) => ErrorKind::Other,
+            Self::Bus(kind) => *kind,
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use async_io::{Async, Timer};
+use embassy_can_driver::embedded_can::{ErrorKind, ExtendedId, Id, StandardId};
+use embassy_can_driver::{BusState, Can, CanFd, Filter, FilterError};
+use log::*;
+
+use crate::{Error, Frame, FD_MAX_LEN};
+
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CAN_SFF_MASK: u32 = 0x0000_07FF;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+const CAN_ERR_MASK: u32 = 0x1FFF_FFFF;
+
+const CANFD_BRS: u8 = 0x01;
+const CANFD_FDF: u8 = 0x04;
+
+/// Size of a classic frame on the socket.
+const CAN_MTU: usize = 16;
+/// Size of a CAN FD frame on the socket.
+const CANFD_MTU: usize = 72;
+
+/// Maximum number of filters accepted by the kernel.
+const CAN_RAW_FILTER_MAX: usize = 512;
+
+// Error frame classes, in the ID.
+const CAN_ERR_TX_TIMEOUT: u32 = 0x001;
+const CAN_ERR_CRTL: u32 = 0x004;
+const CAN_ERR_PROT: u32 = 0x008;
+const CAN_ERR_TRX: u32 = 0x010;
+const CAN_ERR_ACK: u32 = 0x020;
+const CAN_ERR_BUSOFF: u32 = 0x040;
+const CAN_ERR_BUSERROR: u32 = 0x080;
+const CAN_ERR_RESTARTED: u32 = 0x100;
+
+// Controller status, in data[1].
+const CAN_ERR_CRTL_RX_OVERFLOW: u8 = 0x01;
+const CAN_ERR_CRTL_TX_OVERFLOW: u8 = 0x02;
+const CAN_ERR_CRTL_RX_PASSIVE: u8 = 0x10;
+const CAN_ERR_CRTL_TX_PASSIVE: u8 = 0x20;
+const CAN_ERR_CRTL_ACTIVE: u8 = 0x40;
+
+// Protocol error type, in data[2], and location, in data[3].
+const CAN_ERR_PROT_BIT: u8 = 0x01;
+const CAN_ERR_PROT_FORM: u8 = 0x02;
+const CAN_ERR_PROT_STUFF: u8 = 0x04;
+const CAN_ERR_PROT_BIT0: u8 = 0x08;
+const CAN_ERR_PROT_BIT1: u8 = 0x10;
+const CAN_ERR_PROT_LOC_CRC_SEQ: u8 = 0x08;
+
+/// `struct canfd_frame`, whose first 16 bytes are layout compatible with `struct can_frame`.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct canfd_frame {
+    can_id: u32,
+    len: u8,
+    flags: u8,
+    res0: u8,
+    res1: u8,
+    data: [u8; FD_MAX_LEN],
+}
+
+const _: () = assert!(std::mem::size_of::<canfd_frame>() == CANFD_MTU);
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct can_filter {
+    can_id: u32,
+    can_mask: u32,
+}
+
+struct Socket {
+    fd: libc::c_int,
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl Socket {
+    fn open(ifname: &str) -> io::Result<Self> {
+        let name = CString::new(ifname).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let fd = unsafe { libc::socket(libc::AF_CAN, libc::SOCK_RAW | libc::SOCK_NONBLOCK, libc::CAN_RAW) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = Self { fd };
+
+        let mut addr: libc::sockaddr_can = unsafe { std::mem::zeroed() };
+        addr.can_family = libc::AF_CAN as libc::sa_family_t;
+        addr.can_ifindex = ifindex as libc::c_int;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_can as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(socket)
+    }
+
+    fn set_option<T: ?Sized>(&self, name: libc::c_int, value: &T) -> io::Result<()> {
+        let res = unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_CAN_RAW,
+                name,
+                value as *const T as *const libc::c_void,
+                std::mem::size_of_val(value) as libc::socklen_t,
+            )
+        };
+        match res {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn send(&self, frame: &canfd_frame, len: usize) -> io::Result<()> {
+        let res = unsafe { libc::write(self.fd, frame as *const canfd_frame as *const libc::c_void, len) };
+        match res {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn recv(&self, frame: &mut canfd_frame) -> io::Result<usize> {
+        let res = unsafe {
+            libc::read(
+                self.fd,
+                frame as *mut canfd_frame as *mut libc::c_void,
+                std::mem::size_of::<canfd_frame>(),
+            )
+        };
+        match res {
+            -1 => Err(io::Error::last_os_error()),
+            n => Ok(n as usize),
+        }
+    }
+}
+
+/// Linux SocketCAN interface.
+///
+/// The bit rate of a real interface is configured outside of the program, e.g. with
+/// `ip link set can0 type can bitrate 500000`.
+pub struct SocketCan {
+    socket: Async<Socket>,
+    state: BusState,
+}
+
+impl SocketCan {
+    /// Open a classic CAN interface, e.g. `can0` or `vcan0`.
+    pub fn open(ifname: &str) -> io::Result<Self> {
+        Self::new(Socket::open(ifname)?)
+    }
+
+    /// Open a CAN FD interface, which can also send and receive classic frames.
+    pub fn open_fd(ifname: &str) -> io::Result<Self> {
+        let socket = Socket::open(ifname)?;
+        socket.set_option(libc::CAN_RAW_FD_FRAMES, &1 as &libc::c_int)?;
+        Self::new(socket)
+    }
+
+    fn new(socket: Socket) -> io::Result<Self> {
+        let err_mask = CAN_ERR_TX_TIMEOUT
+            | CAN_ERR_CRTL
+            | CAN_ERR_PROT
+            | CAN_ERR_TRX
+            | CAN_ERR_ACK
+            | CAN_ERR_BUSOFF
+            | CAN_ERR_BUSERROR
+            | CAN_ERR_RESTARTED;
+        socket.set_option(libc::CAN_RAW_ERR_FILTER, &err_mask)?;
+        Ok(Self {
+            socket: Async::new(socket)?,
+            state: BusState::ErrorActive,
+        })
+    }
+
+    async fn send(&mut self, frame: &Frame) -> Result<(), Error> {
+        let mut raw = canfd_frame {
+            can_id: match frame.id {
+                Id::Standard(id) => id.as_raw() as u32,
+                Id::Extended(id) => id.as_raw() | CAN_EFF_FLAG,
+            },
+            len: frame.len,
+            flags: 0,
+            res0: 0,
+            res1: 0,
+            data: frame.data,
+        };
+        if frame.remote {
+            raw.can_id |= CAN_RTR_FLAG;
+        }
+        let len = match frame.fd {
+            true => {
+                raw.flags = CANFD_FDF | if frame.brs { CANFD_BRS } else { 0 };
+                CANFD_MTU
+            }
+            false => CAN_MTU,
+        };
+
+        loop {
+            match self.socket.write_with(|s| s.send(&raw, len)).await {
+                // The kernel returns ENOBUFS instead of blocking when the interface queue is full.
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => Timer::after(Duration::from_millis(1)).await,
+                res => return Ok(res?),
+            };
+        }
+    }
+
+    async fn recv(&mut self) -> Result<Frame, Error> {
+        loop {
+            let mut raw = canfd_frame {
+                can_id: 0,
+                len: 0,
+                flags: 0,
+                res0: 0,
+                res1: 0,
+                data: [0; FD_MAX_LEN],
+            };
+            let n = self.socket.read_with(|s| s.recv(&mut raw)).await?;
+            if n != CAN_MTU && n != CANFD_MTU {
+                warn!("unexpected frame size {}", n);
+                continue;
+            }
+
+            if raw.can_id & CAN_ERR_FLAG != 0 {
+                match self.decode_error(&raw) {
+                    Some(kind) => return Err(Error::Bus(kind)),
+                    None => continue,
+                }
+            }
+
+            let id = match raw.can_id & CAN_EFF_FLAG {
+                0 => StandardId::new((raw.can_id & CAN_SFF_MASK) as u16).map(Id::Standard),
+                _ => ExtendedId::new(raw.can_id & CAN_EFF_MASK).map(Id::Extended),
+            };
+            let fd = n == CANFD_MTU;
+            let len = raw.len.min(if fd { 64 } else { 8 });
+            return Ok(Frame {
+                id: id.unwrap(),
+                data: raw.data,
+                len,
+                remote: raw.can_id & CAN_RTR_FLAG != 0,
+                fd,
+                brs: fd && raw.flags & CANFD_BRS != 0,
+            });
+        }
+    }
+
+    /// Update the bus state from an error frame, returning the error to report if any.
+    fn decode_error(&mut self, raw: &canfd_frame) -> Option<ErrorKind> {
+        let class = raw.can_id & CAN_ERR_MASK;
+        let ctrl = raw.data[1];
+        if class & CAN_ERR_RESTARTED != 0 {
+            self.state = BusState::ErrorActive;
+        }
+        if class & CAN_ERR_CRTL != 0 {
+            if ctrl & (CAN_ERR_CRTL_RX_PASSIVE | CAN_ERR_CRTL_TX_PASSIVE) != 0 {
+                self.state = BusState::ErrorPassive;
+            } else if ctrl & CAN_ERR_CRTL_ACTIVE != 0 {
+                self.state = BusState::ErrorActive;
+            }
+        }
+        if class & CAN_ERR_BUSOFF != 0 {
+            self.state = BusState::BusOff;
+        }
+
+        if class & CAN_ERR_PROT != 0 {
+            let kind = raw.data[2];
+            return Some(
+                if kind & (CAN_ERR_PROT_BIT | CAN_ERR_PROT_BIT0 | CAN_ERR_PROT_BIT1) != 0 {
+                    ErrorKind::Bit
+                } else if kind & CAN_ERR_PROT_FORM != 0 {
+                    ErrorKind::Form
+                } else if kind & CAN_ERR_PROT_STUFF != 0 {
+                    ErrorKind::Stuff
+                } else if raw.data[3] == CAN_ERR_PROT_LOC_CRC_SEQ {
+                    ErrorKind::Crc
+                } else {
+                    ErrorKind::Other
+                },
+            );
+        }
+        if class & CAN_ERR_ACK != 0 {
+            return Some(ErrorKind::Acknowledge);
+        }
+        if class & CAN_ERR_CRTL != 0 && ctrl & (CAN_ERR_CRTL_RX_OVERFLOW | CAN_ERR_CRTL_TX_OVERFLOW) != 0 {
+            return Some(ErrorKind::Overrun);
+        }
+        if class & (CAN_ERR_TX_TIMEOUT | CAN_ERR_TRX | CAN_ERR_BUSOFF | CAN_ERR_BUSERROR) != 0 {
+            return Some(ErrorKind::Other);
+        }
+        None
+    }
+}
+
+impl Can for SocketCan {
+    type Frame = Frame;
+    type Error = Error;
+
+    async fn transmit(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.send(frame).await
+    }
+
+    async fn receive(&mut self) -> Result<Frame, Error> {
+        self.recv().await
+    }
+
+    fn set_filters(&mut self, filters: &[Filter]) -> Result<(), FilterError> {
+        if filters.len() > CAN_RAW_FILTER_MAX {
+            return Err(FilterError::TooManyFilters);
+        }
+        let filters: Vec<can_filter> = filters
+            .iter()
+            .map(|f| match f.is_extended() {
+                false => can_filter {
+                    can_id: f.raw_id(),
+                    can_mask: f.mask() | CAN_EFF_FLAG,
+                },
+                true => can_filter {
+                    can_id: f.raw_id() | CAN_EFF_FLAG,
+                    can_mask: f.mask() | CAN_EFF_FLAG,
+                },
+            })
+            .collect();
+        self.socket
+            .get_ref()
+            .set_option(libc::CAN_RAW_FILTER, filters.as_slice())
+            .map_err(|e| {
+                warn!("failed to set CAN filters: {}", e);
+                FilterError::Rejected
+            })
+    }
+
+    fn bus_state(&self) -> BusState {
+        self.state
+    }
+}
+
+impl CanFd for SocketCan {
+    type FdFrame = Frame;
+
+    async fn transmit_fd(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.send(frame).await
+    }
+
+    async fn receive_fd(&mut self) -> Result<Frame, Error> {
+        self.recv().await
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+use embassy_can_driver::embedded_can::{ErrorKind, Frame as _};
+use embassy_can_driver::{BusState, Can, CanFd, Filter, FilterError};
+
+use crate::{Error, Frame};
+
+struct Node {
+    id: usize,
+    queue: VecDeque<Result<Frame, ErrorKind>>,
+    filters: Vec<Filter>,
+    waker: Option<Waker>,
+}
+
+impl Node {
+    fn push(&mut self, item: Result<Frame, ErrorKind>) {
+        self.queue.push_back(item);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct Bus {
+    nodes: Vec<Node>,
+    next_id: usize,
+    state: BusState,
+}
+
+/// In-memory CAN bus.
+///
+/// Every frame transmitted by a node is delivered to all other nodes whose filters accept it.
+/// Transmission never blocks and there's no arbitration, so frames are received in the order they
+/// were transmitted.
+///
+/// Cloning returns a handle to the same bus.
+#[derive(Clone)]
+pub struct VirtualBus {
+    bus: Arc<Mutex<Bus>>,
+}
+
+impl Default for VirtualBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualBus {
+    /// Create a new bus with no nodes.
+    pub fn new() -> Self {
+        Self {
+            bus: Arc::new(Mutex::new(Bus {
+                nodes: Vec::new(),
+                next_id: 0,
+                state: BusState::ErrorActive,
+            })),
+        }
+    }
+
+    /// Connect a new node to the bus. It accepts all frames until its filters are set.
+    pub fn node(&self) -> VirtualCan {
+        let mut bus = self.bus.lock().unwrap();
+        let id = bus.next_id;
+        bus.next_id += 1;
+        bus.nodes.push(Node {
+            id,
+            queue: VecDeque::new(),
+            filters: Filter::ACCEPT_ALL.to_vec(),
+            waker: None,
+        });
+        VirtualCan {
+            bus: self.bus.clone(),
+            id,
+        }
+    }
+
+    /// Report a bus error to all nodes, which receive it as an error.
+    pub fn inject_error(&self, kind: ErrorKind) {
+        let mut bus = self.bus.lock().unwrap();
+        for node in &mut bus.nodes {
+            node.push(Err(kind));
+        }
+    }
+
+    /// Set the state reported by [`Can::bus_state`] on all nodes.
+    pub fn set_bus_state(&self, state: BusState) {
+        self.bus.lock().unwrap().state = state;
+    }
+}
+
+/// Node of a [`VirtualBus`].
+pub struct VirtualCan {
+    bus: Arc<Mutex<Bus>>,
+    id: usize,
+}
+
+impl VirtualCan {
+    fn with_node<R>(&self, f: impl FnOnce(&mut Node) -> R) -> R {
+        let mut bus = self.bus.lock().unwrap();
+        let node = bus.nodes.iter_mut().find(|n| n.id == self.id).unwrap();
+        f(node)
+    }
+
+    fn send(&self, frame: &Frame) {
+        let mut bus = self.bus.lock().unwrap();
+        for node in bus.nodes.iter_mut().filter(|n| n.id != self.id) {
+            if node.filters.iter().any(|f| f.matches(frame.id())) {
+                node.push(Ok(*frame));
+            }
+        }
+    }
+
+    async fn recv(&self) -> Result<Frame, Error> {
+        poll_fn(|cx| {
+            self.with_node(|node| match node.queue.pop_front() {
+                Some(item) => Poll::Ready(item.map_err(Error::Bus)),
+                None => {
+                    node.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl Drop for VirtualCan {
+    fn drop(&mut self) {
+        let mut bus = self.bus.lock().unwrap();
+        bus.nodes.retain(|n| n.id != self.id);
+    }
+}
+
+impl Can for VirtualCan {
+    type Frame = Frame;
+    type Error = Error;
+
+    async fn transmit(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.send(frame);
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Frame, Error> {
+        self.recv().await
+    }
+
+    fn set_filters(&mut self, filters: &[Filter]) -> Result<(), FilterError> {
+        self.with_node(|node| node.filters = filters.to_vec());
+        Ok(())
+    }
+
+    fn bus_state(&self) -> BusState {
+        self.bus.lock().unwrap().state
+    }
+}
+
+impl CanFd for VirtualCan {
+    type FdFrame = Frame;
+
+    async fn transmit_fd(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.send(frame);
+        Ok(())
+    }
+
+    async fn receive_fd(&mut self) -> Result<Frame, Error> {
+        self.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_can_driver::embedded_can::{ExtendedId, StandardId};
+    use embassy_can_driver::FdFrame as _;
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    fn std_id(raw: u16) -> StandardId {
+        StandardId::new(raw).unwrap()
+    }
+
+    #[test]
+    fn transmit_receive() {
+        let bus = VirtualBus::new();
+        let mut a = bus.node();
+        let mut b = bus.node();
+        let mut c = bus.node();
+
+        let frame = Frame::new(std_id(0x123), &[1, 2, 3]).unwrap();
+        block_on(a.transmit(&frame)).unwrap();
+
+        assert_eq!(block_on(b.receive()).unwrap(), frame);
+        assert_eq!(block_on(c.receive()).unwrap(), frame);
+        // The sender doesn't receive its own frames.
+        b.with_node(|n| assert!(n.queue.is_empty()));
+        a.with_node(|n| assert!(n.queue.is_empty()));
+
+        let fd_frame = Frame::new_fd(std_id(0x124), &[0x55; 64], true).unwrap();
+        block_on(b.transmit_fd(&fd_frame)).unwrap();
+        let rx = block_on(a.receive_fd()).unwrap();
+        assert!(rx.is_fd() && rx.bit_rate_switching());
+        assert_eq!(rx.data(), &[0x55; 64]);
+    }
+
+    #[test]
+    fn filters() {
+        let bus = VirtualBus::new();
+        let mut a = bus.node();
+        let mut b = bus.node();
+        b.set_filters(&[
+            Filter::new_standard(std_id(0x100), 0x700),
+            Filter::exact(ExtendedId::new(0x1234567).unwrap().into()),
+        ])
+        .unwrap();
+
+        for frame in [
+            Frame::new(std_id(0x0FF), &[]).unwrap(),
+            Frame::new(std_id(0x1AB), &[1]).unwrap(),
+            Frame::new(ExtendedId::new(0x100).unwrap(), &[2]).unwrap(),
+            Frame::new(ExtendedId::new(0x1234567).unwrap(), &[3]).unwrap(),
+        ] {
+            block_on(a.transmit(&frame)).unwrap();
+        }
+        assert_eq!(block_on(b.receive()).unwrap().data(), &[1]);
+        assert_eq!(block_on(b.receive()).unwrap().data(), &[3]);
+        b.with_node(|n| assert!(n.queue.is_empty()));
+
+        b.set_filters(&[]).unwrap();
+        block_on(a.transmit(&Frame::new(std_id(0x100), &[]).unwrap())).unwrap();
+        b.with_node(|n| assert!(n.queue.is_empty()));
+    }
+
+    #[test]
+    fn errors() {
+        let bus = VirtualBus::new();
+        let mut a = bus.node();
+        bus.inject_error(ErrorKind::Crc);
+        assert!(matches!(block_on(a.receive()), Err(Error::Bus(ErrorKind::Crc))));
+
+        assert_eq!(a.bus_state(), BusState::ErrorActive);
+        bus.set_bus_state(BusState::BusOff);
+        assert_eq!(a.bus_state(), BusState::BusOff);
+    }
+
+    #[test]
+    fn frame_lengths() {
+        assert!(Frame::new(std_id(1), &[0; 9]).is_none());
+        assert!(Frame::new_fd(std_id(1), &[0; 9], false).is_none());
+        assert!(Frame::new_fd(std_id(1), &[0; 12], false).is_some());
+        let remote = Frame::new_remote(std_id(1), 4).unwrap();
+        assert_eq!((remote.dlc(), remote.data()), (4, &[][..]));
+    }
+}
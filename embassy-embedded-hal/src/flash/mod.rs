@@ -4,5 +4,7 @@ mod concat_flash;
 #[cfg(test)]
 pub(crate) mod mem_flash;
 pub mod partition;
+#[cfg(any(feature = "std", test))]
+pub mod sim_flash;
 
 pub use concat_flash::ConcatFlash;
@@ -0,0 +1,453 @@
+//! Simulated NOR flash with fault injection, for crash-consistency tests on the host.
+//!
+//! [`SimFlash`] keeps its contents in memory and follows the rules of real NOR flash more strictly than a plain
+//! buffer: accesses must be aligned, programming only clears bits, and a word may only be programmed once per
+//! erase unless the flash is [`MultiwriteNorFlash`].
+//!
+//! Faults are injected deterministically from a seed:
+//! - Power loss after a number of operations. Each programmed word and each erased page is one operation, and
+//!   the interrupted one is left partially done: a word with only some of its bits programmed, or a page with
+//!   only some of its bits erased. The flash then fails every access until [`SimFlash::power_on`] is called.
+//! - Bit flips, either stored with [`SimFlash::flip_bit`] or on random reads with [`Config::read_disturb`].
+//! - Wear-out, where erasing a page fails once it exceeds [`Config::erase_cycles`].
+//!
+//! A crash-consistency test runs an operation once to count its flash operations with
+//! [`SimFlash::operations`], then reruns it with power lost at every one of them, checking what the recovery
+//! code finds after [`SimFlash::power_on`].
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, MultiwriteNorFlash, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+use embedded_storage_async::nor_flash::{
+    MultiwriteNorFlash as AsyncMultiwriteNorFlash, NorFlash as AsyncNorFlash, ReadNorFlash as AsyncReadNorFlash,
+};
+
+/// Fault configuration of a [`SimFlash`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Config {
+    /// Number of erases a page endures. Erasing a page which has been erased this many times fails and leaves it
+    /// partially erased. `None` disables wear-out.
+    pub erase_cycles: Option<u32>,
+    /// One in `read_disturb` reads returns one flipped bit. The stored contents are not changed. 0 disables.
+    pub read_disturb: u32,
+    /// Seed of the random partial states and bit flips.
+    pub seed: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            erase_cycles: None,
+            read_disturb: 0,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
+
+/// Simulated NOR flash with power-loss, bit-flip and wear-out injection.
+///
+/// With `MULTIWRITE` set, a word can be programmed several times per erase, each write clearing more bits, and
+/// the flash implements [`MultiwriteNorFlash`]. Otherwise programming a word twice per erase fails.
+///
+/// Faults are reported as [`NorFlashErrorKind::Other`].
+pub struct SimFlash<const WRITE_SIZE: usize, const ERASE_SIZE: usize, const MULTIWRITE: bool = false> {
+    mem: Vec<u8>,
+    programmed: Vec<bool>,
+    erase_counts: Vec<u32>,
+    config: Config,
+    rng: u64,
+    power_loss: Option<usize>,
+    powered: bool,
+    operations: usize,
+}
+
+impl<const WRITE_SIZE: usize, const ERASE_SIZE: usize, const MULTIWRITE: bool>
+    SimFlash<WRITE_SIZE, ERASE_SIZE, MULTIWRITE>
+{
+    /// Create an erased flash of `size` bytes.
+    pub fn new(size: usize, config: Config) -> Self {
+        assert!(WRITE_SIZE > 0);
+        assert_eq!(0, ERASE_SIZE % WRITE_SIZE);
+        assert_eq!(0, size % ERASE_SIZE);
+        Self {
+            mem: vec![0xFF; size],
+            programmed: vec![false; size / WRITE_SIZE],
+            erase_counts: vec![0; size / ERASE_SIZE],
+            rng: config.seed | 1,
+            config,
+            power_loss: None,
+            powered: true,
+            operations: 0,
+        }
+    }
+
+    /// Contents of the flash.
+    pub fn contents(&self) -> &[u8] {
+        &self.mem
+    }
+
+    /// Lose power after `operations` more operations, during the next one. `None` cancels a pending power loss.
+    pub fn set_power_loss(&mut self, operations: Option<usize>) {
+        self.power_loss = operations;
+    }
+
+    /// Restore power after a power loss, as on reboot. The contents are kept as they were left.
+    pub fn power_on(&mut self) {
+        self.powered = true;
+    }
+
+    /// Whether the flash is powered, i.e. no injected power loss happened since the last [`Self::power_on`].
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Number of operations completed so far: words programmed and pages erased.
+    pub fn operations(&self) -> usize {
+        self.operations
+    }
+
+    /// Number of times each page has been erased, including interrupted erases.
+    pub fn erase_counts(&self) -> &[u32] {
+        &self.erase_counts
+    }
+
+    /// Flip a stored bit, as a retention error would.
+    pub fn flip_bit(&mut self, offset: u32, bit: u8) {
+        self.mem[offset as usize] ^= 1 << (bit % 8);
+    }
+
+    fn random(&mut self) -> u64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn lose_power(&mut self) -> bool {
+        match self.power_loss {
+            Some(0) => {
+                self.power_loss = None;
+                self.powered = false;
+                true
+            }
+            Some(n) => {
+                self.power_loss = Some(n - 1);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn check_powered(&self) -> Result<(), NorFlashErrorKind> {
+        match self.powered {
+            true => Ok(()),
+            false => Err(NorFlashErrorKind::Other),
+        }
+    }
+
+    /// Leave a page with a random part of its bits erased.
+    fn erase_partially(&mut self, page: usize) {
+        for i in page * ERASE_SIZE..(page + 1) * ERASE_SIZE {
+            self.mem[i] |= self.random() as u8;
+        }
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        self.check_powered()?;
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.mem[offset..offset + bytes.len()]);
+
+        let disturb = self.config.read_disturb as u64;
+        if disturb > 0 && !bytes.is_empty() && self.random() < u64::MAX / disturb {
+            let bit = self.random() as usize % (bytes.len() * 8);
+            bytes[bit / 8] ^= 1 << (bit % 8);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        self.check_powered()?;
+        check_write(self, offset, bytes.len())?;
+
+        for (i, word) in bytes.chunks(WRITE_SIZE).enumerate() {
+            let start = offset as usize + i * WRITE_SIZE;
+            let index = start / WRITE_SIZE;
+            if !MULTIWRITE && self.programmed[index] {
+                return Err(NorFlashErrorKind::Other);
+            }
+
+            let power_lost = self.lose_power();
+            for (j, byte) in word.iter().enumerate() {
+                // Only some of the bits to clear are programmed by an interrupted write
+                let mask = match power_lost {
+                    true => self.random() as u8,
+                    false => 0,
+                };
+                self.mem[start + j] &= byte | mask;
+            }
+            self.programmed[index] = true;
+
+            if power_lost {
+                return Err(NorFlashErrorKind::Other);
+            }
+            self.operations += 1;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        self.check_powered()?;
+        check_erase(self, from, to)?;
+
+        for page in from as usize / ERASE_SIZE..to as usize / ERASE_SIZE {
+            if self
+                .config
+                .erase_cycles
+                .is_some_and(|cycles| self.erase_counts[page] >= cycles)
+            {
+                self.erase_partially(page);
+                return Err(NorFlashErrorKind::Other);
+            }
+
+            self.erase_counts[page] += 1;
+            if self.lose_power() {
+                self.erase_partially(page);
+                return Err(NorFlashErrorKind::Other);
+            }
+
+            self.mem[page * ERASE_SIZE..(page + 1) * ERASE_SIZE].fill(0xFF);
+            self.programmed[page * ERASE_SIZE / WRITE_SIZE..(page + 1) * ERASE_SIZE / WRITE_SIZE].fill(false);
+            self.operations += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<const WRITE_SIZE: usize, const ERASE_SIZE: usize, const MULTIWRITE: bool> ErrorType
+    for SimFlash<WRITE_SIZE, ERASE_SIZE, MULTIWRITE>
+{
+    type Error = NorFlashErrorKind;
+}
+
+impl<const WRITE_SIZE: usize, const ERASE_SIZE: usize, const MULTIWRITE: bool> ReadNorFlash
+    for SimFlash<WRITE_SIZE, ERASE_SIZE, MULTIWRITE>
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.mem.len()
+    }
+}
+
+impl<const WRITE_SIZE: usize, const ERASE_SIZE: usize, const MULTIWRITE: bool> NorFlash
+    for SimFlash<WRITE_SIZE, ERASE_SIZE, MULTIWRITE>
+{
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write(offset, bytes)
+    }
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.erase(from, to)
+    }
+}
+
+impl<const WRITE_SIZE: usize, const ERASE_SIZE: usize> MultiwriteNorFlash for SimFlash<WRITE_SIZE, ERASE_SIZE, true> {}
+
+impl<const WRITE_SIZE: usize, const ERASE_SIZE: usize, const MULTIWRITE: bool> AsyncReadNorFlash
+    for SimFlash<WRITE_SIZE, ERASE_SIZE, MULTIWRITE>
+{
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.mem.len()
+    }
+}
+
+impl<const WRITE_SIZE: usize, const ERASE_SIZE: usize, const MULTIWRITE: bool> AsyncNorFlash
+    for SimFlash<WRITE_SIZE, ERASE_SIZE, MULTIWRITE>
+{
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write(offset, bytes)
+    }
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.erase(from, to)
+    }
+}
+
+impl<const WRITE_SIZE: usize, const ERASE_SIZE: usize> AsyncMultiwriteNorFlash
+    for SimFlash<WRITE_SIZE, ERASE_SIZE, true>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enforces_alignment() {
+        let mut flash = SimFlash::<4, 256>::new(1024, Config::default());
+
+        assert_eq!(
+            Err(NorFlashErrorKind::NotAligned),
+            NorFlash::write(&mut flash, 2, &[0; 4])
+        );
+        assert_eq!(
+            Err(NorFlashErrorKind::NotAligned),
+            NorFlash::write(&mut flash, 0, &[0; 6])
+        );
+        assert_eq!(Err(NorFlashErrorKind::NotAligned), NorFlash::erase(&mut flash, 0, 128));
+        assert_eq!(
+            Err(NorFlashErrorKind::OutOfBounds),
+            NorFlash::erase(&mut flash, 0, 2048)
+        );
+        assert_eq!(
+            Err(NorFlashErrorKind::OutOfBounds),
+            NorFlash::write(&mut flash, 1024, &[0; 4])
+        );
+    }
+
+    #[test]
+    fn enforces_write_once() {
+        let mut flash = SimFlash::<4, 256>::new(1024, Config::default());
+        NorFlash::write(&mut flash, 0, &[0xF0; 4]).unwrap();
+        assert_eq!(
+            Err(NorFlashErrorKind::Other),
+            NorFlash::write(&mut flash, 0, &[0x0F; 4])
+        );
+
+        NorFlash::erase(&mut flash, 0, 256).unwrap();
+        NorFlash::write(&mut flash, 0, &[0x0F; 4]).unwrap();
+        assert_eq!([0x0F; 4], flash.contents()[..4]);
+
+        let mut flash = SimFlash::<4, 256, true>::new(1024, Config::default());
+        NorFlash::write(&mut flash, 0, &[0xF0; 4]).unwrap();
+        NorFlash::write(&mut flash, 0, &[0x3C; 4]).unwrap();
+        assert_eq!([0x30; 4], flash.contents()[..4]);
+    }
+
+    #[test]
+    fn loses_power_during_write() {
+        let mut flash = SimFlash::<4, 256>::new(1024, Config::default());
+        flash.set_power_loss(Some(2));
+
+        assert_eq!(Err(NorFlashErrorKind::Other), NorFlash::write(&mut flash, 0, &[0; 16]));
+        assert!(!flash.is_powered());
+        assert_eq!(2, flash.operations());
+        assert_eq!([0; 8], flash.contents()[..8]);
+        assert_eq!([0xFF; 4], flash.contents()[12..16]);
+
+        // The interrupted word cannot be programmed again, and nothing works until power is back
+        let mut buf = [0; 4];
+        assert_eq!(
+            Err(NorFlashErrorKind::Other),
+            ReadNorFlash::read(&mut flash, 0, &mut buf)
+        );
+        flash.power_on();
+        assert_eq!(Err(NorFlashErrorKind::Other), NorFlash::write(&mut flash, 8, &[0; 4]));
+        NorFlash::write(&mut flash, 12, &[0; 4]).unwrap();
+    }
+
+    #[test]
+    fn loses_power_during_erase() {
+        let mut flash = SimFlash::<4, 256>::new(1024, Config::default());
+        NorFlash::write(&mut flash, 0, &[0; 1024]).unwrap();
+        flash.set_power_loss(Some(1));
+
+        assert_eq!(Err(NorFlashErrorKind::Other), NorFlash::erase(&mut flash, 0, 1024));
+        assert_eq!([1, 1, 0, 0], flash.erase_counts());
+        assert!(flash.contents()[..256].iter().all(|&b| b == 0xFF));
+        let page = &flash.contents()[256..512];
+        assert!(page.iter().any(|&b| b != 0xFF) && page.iter().any(|&b| b != 0));
+        assert!(flash.contents()[512..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn wears_out() {
+        let config = Config {
+            erase_cycles: Some(3),
+            ..Default::default()
+        };
+        let mut flash = SimFlash::<4, 256>::new(512, config);
+
+        for _ in 0..3 {
+            NorFlash::erase(&mut flash, 0, 256).unwrap();
+        }
+        assert_eq!(Err(NorFlashErrorKind::Other), NorFlash::erase(&mut flash, 0, 512));
+        NorFlash::erase(&mut flash, 256, 512).unwrap();
+        assert_eq!([3, 1], flash.erase_counts());
+    }
+
+    #[test]
+    fn flips_bits() {
+        let mut flash = SimFlash::<4, 256>::new(256, Config::default());
+        flash.flip_bit(5, 3);
+        assert_eq!(0xF7, flash.contents()[5]);
+
+        let config = Config {
+            read_disturb: 2,
+            ..Default::default()
+        };
+        let mut flash = SimFlash::<4, 256>::new(256, config);
+        let mut flipped = 0;
+        for _ in 0..100 {
+            let mut buf = [0; 16];
+            ReadNorFlash::read(&mut flash, 0, &mut buf).unwrap();
+            flipped += buf.iter().map(|b| b.count_zeros()).sum::<u32>();
+        }
+        assert!(flipped > 10 && flipped < 90);
+        assert!(flash.contents().iter().all(|&b| b == 0xFF));
+    }
+
+    #[futures_test::test]
+    async fn survives_power_loss_at_every_operation() {
+        // Write a record, then a commit marker: after any power loss, a committed record must be intact
+        async fn update(flash: &mut SimFlash<4, 256>) -> Result<(), NorFlashErrorKind> {
+            AsyncNorFlash::erase(flash, 0, 512).await?;
+            AsyncNorFlash::write(flash, 256, &[0xAB; 64]).await?;
+            AsyncNorFlash::write(flash, 0, &[0x00; 4]).await
+        }
+
+        let mut flash = SimFlash::<4, 256>::new(512, Config::default());
+        update(&mut flash).await.unwrap();
+        let operations = flash.operations();
+
+        for n in 0..operations {
+            let mut flash = SimFlash::<4, 256>::new(512, Config::default());
+            flash.set_power_loss(Some(n));
+            assert!(update(&mut flash).await.is_err());
+            flash.power_on();
+
+            let mut marker = [0; 4];
+            AsyncReadNorFlash::read(&mut flash, 0, &mut marker).await.unwrap();
+            let mut record = [0; 64];
+            AsyncReadNorFlash::read(&mut flash, 256, &mut record).await.unwrap();
+            if marker == [0; 4] {
+                assert_eq!([0xAB; 64], record);
+            }
+            assert_eq!(n == operations - 1, marker != [0xFF; 4]);
+        }
+    }
+}
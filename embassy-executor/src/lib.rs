@@ -26,6 +26,9 @@ macro_rules! check_at_most_one {
 }
 check_at_most_one!("arch-avr", "arch-cortex-m", "arch-riscv32", "arch-std", "arch-wasm",);
 
+#[cfg(all(feature = "alloc", feature = "turbowakers"))]
+compile_error!("The `alloc` feature is not supported with `turbowakers`.");
+
 #[cfg(feature = "_arch")]
 #[cfg_attr(feature = "arch-avr", path = "arch/avr.rs")]
 #[cfg_attr(feature = "arch-cortex-m", path = "arch/cortex_m.rs")]
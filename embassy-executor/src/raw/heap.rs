@@ -0,0 +1,147 @@
+//! Heap-allocated tasks.
+//!
+//! A heap task is a regular [`TaskStorage`] allocated on the global allocator instead of in a
+//! `static`. Since it must be freed once it's no longer referenced, heap tasks keep a reference count
+//! in their header. References are held by:
+//!
+//! - The task itself while it's spawned (i.e. while its future hasn't completed).
+//! - The executor run queue while the task is enqueued in it.
+//! - The executor timer queue while the task is enqueued in it.
+//! - Every `Waker` for the task that has been cloned out of the task's `Context`.
+//!
+//! The memory is freed when the last reference is released. Statically allocated tasks don't track
+//! references at all.
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, handle_alloc_error};
+use core::alloc::Layout;
+use core::future::Future;
+
+use super::{AvailableTask, TaskHeader, TaskRef, TaskStorage};
+use crate::SpawnToken;
+
+#[cfg(target_has_atomic = "ptr")]
+pub(crate) struct RefCount {
+    count: core::sync::atomic::AtomicUsize,
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl RefCount {
+    pub const fn new() -> Self {
+        Self {
+            count: core::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    fn set(&self, val: usize) {
+        self.count.store(val, core::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn increment(&self) {
+        self.count.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Decrement the count. Return true if it reached zero.
+    fn decrement(&self) -> bool {
+        use core::sync::atomic::{fence, Ordering};
+
+        if self.count.fetch_sub(1, Ordering::Release) != 1 {
+            return false;
+        }
+        // Make sure all accesses to the task by other references happen before it's freed.
+        fence(Ordering::Acquire);
+        true
+    }
+}
+
+#[cfg(not(target_has_atomic = "ptr"))]
+pub(crate) struct RefCount {
+    count: critical_section::Mutex<core::cell::Cell<usize>>,
+}
+
+#[cfg(not(target_has_atomic = "ptr"))]
+impl RefCount {
+    pub const fn new() -> Self {
+        Self {
+            count: critical_section::Mutex::new(core::cell::Cell::new(0)),
+        }
+    }
+
+    fn set(&self, val: usize) {
+        critical_section::with(|cs| self.count.borrow(cs).set(val));
+    }
+
+    fn increment(&self) {
+        critical_section::with(|cs| {
+            let c = self.count.borrow(cs);
+            c.set(c.get() + 1);
+        });
+    }
+
+    /// Decrement the count. Return true if it reached zero.
+    fn decrement(&self) -> bool {
+        critical_section::with(|cs| {
+            let c = self.count.borrow(cs);
+            c.set(c.get() - 1);
+            c.get() == 0
+        })
+    }
+}
+
+impl TaskHeader {
+    /// Take a reference to the task, if it's heap-allocated.
+    #[inline(always)]
+    pub(crate) fn retain(&self) {
+        if unsafe { self.dealloc_fn.get() }.is_some() {
+            self.refs.increment();
+        }
+    }
+
+    /// Release a reference to the task, freeing it if it was the last one.
+    ///
+    /// # Safety
+    ///
+    /// The caller must own a reference previously taken with [`TaskHeader::retain`], and must not
+    /// access the task after this call.
+    #[inline(always)]
+    pub(crate) unsafe fn release(p: TaskRef) {
+        let header = p.header();
+        if let Some(dealloc_fn) = header.dealloc_fn.get() {
+            if header.refs.decrement() {
+                dealloc_fn(p);
+            }
+        }
+    }
+}
+
+unsafe fn dealloc_task<F: Future + 'static>(p: TaskRef) {
+    // The future has already been dropped when the task finished, and `TaskStorage`
+    // has no drop glue, so only the memory needs to be freed.
+    dealloc(p.as_ptr() as *mut u8, Layout::new::<TaskStorage<F>>());
+}
+
+/// Allocate a task on the heap and initialize it to run `future`.
+///
+/// The task is freed when it has finished running and no references to it remain.
+pub(crate) fn spawn_boxed<F: Future + 'static>(future: F) -> SpawnToken<F> {
+    let layout = Layout::new::<TaskStorage<F>>();
+    let task: &'static TaskStorage<F> = unsafe {
+        let ptr = alloc(layout) as *mut TaskStorage<F>;
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        ptr.write(TaskStorage::new());
+        &*ptr
+    };
+
+    // A freshly allocated task can't be already spawned.
+    let task = unwrap!(AvailableTask::claim(task));
+    unsafe {
+        // The task holds a reference to itself while spawned. It is released in `TaskStorage::poll`
+        // when the future completes.
+        task.task.raw.refs.set(1);
+        task.task.raw.dealloc_fn.set(Some(dealloc_task::<F>));
+    }
+    task.initialize(move || future)
+}
@@ -16,6 +16,8 @@ mod run_queue;
 #[cfg_attr(not(target_has_atomic = "8"), path = "state_critical_section.rs")]
 mod state;
 
+#[cfg(feature = "alloc")]
+mod heap;
 #[cfg(feature = "integrated-timers")]
 mod timer_queue;
 pub(crate) mod util;
@@ -34,6 +36,8 @@ use embassy_time_driver::AlarmHandle;
 #[cfg(feature = "rtos-trace")]
 use rtos_trace::trace;
 
+#[cfg(feature = "alloc")]
+pub(crate) use self::heap::spawn_boxed;
 use self::run_queue::{RunQueue, RunQueueItem};
 use self::state::State;
 use self::util::{SyncUnsafeCell, UninitCell};
@@ -51,6 +55,13 @@ pub(crate) struct TaskHeader {
     pub(crate) expires_at: SyncUnsafeCell<u64>,
     #[cfg(feature = "integrated-timers")]
     pub(crate) timer_queue_item: timer_queue::TimerQueueItem,
+
+    /// Number of references to a heap-allocated task. Unused for static tasks.
+    #[cfg(feature = "alloc")]
+    pub(crate) refs: heap::RefCount,
+    /// Frees a heap-allocated task. `None` for static tasks.
+    #[cfg(feature = "alloc")]
+    dealloc_fn: SyncUnsafeCell<Option<unsafe fn(TaskRef)>>,
 }
 
 /// This is essentially a `&'static TaskStorage<F>` where the type of the future has been erased.
@@ -124,6 +135,11 @@ impl<F: Future + 'static> TaskStorage<F> {
                 expires_at: SyncUnsafeCell::new(0),
                 #[cfg(feature = "integrated-timers")]
                 timer_queue_item: timer_queue::TimerQueueItem::new(),
+
+                #[cfg(feature = "alloc")]
+                refs: heap::RefCount::new(),
+                #[cfg(feature = "alloc")]
+                dealloc_fn: SyncUnsafeCell::new(None),
             },
             future: UninitCell::uninit(),
         }
@@ -163,6 +179,11 @@ impl<F: Future + 'static> TaskStorage<F> {
 
                 #[cfg(feature = "integrated-timers")]
                 this.raw.expires_at.set(u64::MAX);
+
+                // Release the reference held by the task while spawned. This never frees
+                // the task here, because the run queue still holds a reference while polling.
+                #[cfg(feature = "alloc")]
+                TaskHeader::release(p);
             }
             Poll::Pending => {}
         }
@@ -350,6 +371,9 @@ impl SyncExecutor {
         #[cfg(feature = "rtos-trace")]
         trace::task_ready_begin(task.as_ptr() as u32);
 
+        #[cfg(feature = "alloc")]
+        task.header().retain();
+
         if self.run_queue.enqueue(task) {
             self.pender.pend();
         }
@@ -395,6 +419,8 @@ impl SyncExecutor {
                     //   - While task is being polled, it gets woken. It gets placed in the queue.
                     //   - Task poll finishes, returning done=true
                     //   - RUNNING bit is cleared, but the task is already in the queue.
+                    #[cfg(feature = "alloc")]
+                    TaskHeader::release(p);
                     return;
                 }
 
@@ -410,6 +436,11 @@ impl SyncExecutor {
                 // Enqueue or update into timer_queue
                 #[cfg(feature = "integrated-timers")]
                 self.timer_queue.update(p);
+
+                // Release the reference held by the run queue. This frees the task
+                // if it has finished and nothing else references it.
+                #[cfg(feature = "alloc")]
+                TaskHeader::release(p);
             });
 
             #[cfg(feature = "integrated-timers")]
@@ -557,6 +588,9 @@ pub fn wake_task_no_pend(task: TaskRef) {
     if header.state.run_enqueue() {
         // We have just marked the task as scheduled, so enqueue it.
         unsafe {
+            #[cfg(feature = "alloc")]
+            header.retain();
+
             let executor = header.executor.get().unwrap_unchecked();
             executor.run_queue.enqueue(task);
         }
@@ -30,6 +30,9 @@ impl TimerQueue {
         let task = p.header();
         if task.expires_at.get() != u64::MAX {
             if task.state.timer_enqueue() {
+                #[cfg(feature = "alloc")]
+                task.retain();
+
                 task.timer_queue_item.next.set(self.head.get());
                 self.head.set(Some(p));
             }
@@ -70,6 +73,9 @@ impl TimerQueue {
                 // Remove it
                 prev.set(task.timer_queue_item.next.get());
                 task.state.timer_dequeue();
+
+                #[cfg(feature = "alloc")]
+                super::TaskHeader::release(p);
             }
         }
     }
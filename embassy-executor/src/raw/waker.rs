@@ -2,18 +2,30 @@ use core::task::{RawWaker, RawWakerVTable, Waker};
 
 use super::{wake_task, TaskHeader, TaskRef};
 
-static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
 
 unsafe fn clone(p: *const ()) -> RawWaker {
+    // Cloned wakers keep heap-allocated tasks alive.
+    #[cfg(feature = "alloc")]
+    TaskRef::from_ptr(p as *const TaskHeader).header().retain();
+
     RawWaker::new(p, &VTABLE)
 }
 
 unsafe fn wake(p: *const ()) {
+    wake_by_ref(p);
+    drop(p);
+}
+
+unsafe fn wake_by_ref(p: *const ()) {
     wake_task(TaskRef::from_ptr(p as *const TaskHeader))
 }
 
-unsafe fn drop(_: *const ()) {
-    // nop
+#[allow(unused_variables)]
+unsafe fn drop(p: *const ()) {
+    // nop for static tasks
+    #[cfg(feature = "alloc")]
+    TaskHeader::release(TaskRef::from_ptr(p as *const TaskHeader));
 }
 
 pub(crate) unsafe fn from_task(p: TaskRef) -> Waker {
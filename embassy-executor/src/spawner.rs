@@ -1,4 +1,6 @@
 use core::future::poll_fn;
+#[cfg(feature = "alloc")]
+use core::future::Future;
 use core::marker::PhantomData;
 use core::mem;
 use core::task::Poll;
@@ -115,6 +117,19 @@ impl Spawner {
         }
     }
 
+    /// Spawn a future into an executor, allocating its task on the heap.
+    ///
+    /// Unlike [`Spawner::spawn()`], this doesn't need a task function or a statically
+    /// allocated task pool: any number of tasks can be spawned this way, as long as
+    /// the global allocator has memory for them. The task's memory is freed when the
+    /// future completes and no wakers for it remain.
+    ///
+    /// Allocation failures are handled by the global allocator's error handler.
+    #[cfg(feature = "alloc")]
+    pub fn spawn_boxed<F: Future + 'static>(&self, future: F) {
+        unwrap!(self.spawn(raw::spawn_boxed(future)));
+    }
+
     // Used by the `embassy_executor_macros::main!` macro to throw an error when spawn
     // fails. This is here to allow conditional use of `defmt::unwrap!`
     // without introducing a `defmt` feature in the `embassy_executor_macros` package,
@@ -194,4 +209,12 @@ impl SendSpawner {
     pub fn must_spawn<S: Send>(&self, token: SpawnToken<S>) {
         unwrap!(self.spawn(token));
     }
+
+    /// Spawn a `Send` future into an executor, allocating its task on the heap.
+    ///
+    /// See [`Spawner::spawn_boxed()`] for details.
+    #[cfg(feature = "alloc")]
+    pub fn spawn_boxed<F: Future + Send + 'static>(&self, future: F) {
+        unwrap!(self.spawn(raw::spawn_boxed(future)));
+    }
 }
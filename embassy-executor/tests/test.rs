@@ -149,3 +149,101 @@ fn executor_task_cfg_args() {
         let (_, _, _) = (a, b, c);
     }
 }
+
+#[cfg(feature = "alloc")]
+#[test]
+fn executor_task_boxed() {
+    struct DropTrace(Trace);
+    impl Drop for DropTrace {
+        fn drop(&mut self) {
+            self.0.push("drop future")
+        }
+    }
+
+    let (executor, trace) = setup();
+    let t = DropTrace(trace.clone());
+    executor.spawner().spawn_boxed(async move {
+        t.0.push("poll boxed");
+    });
+    let t = DropTrace(trace.clone());
+    executor.spawner().spawn_boxed(async move {
+        t.0.push("poll boxed");
+    });
+
+    unsafe { executor.poll() };
+    unsafe { executor.poll() };
+
+    assert_eq!(
+        trace.get(),
+        &[
+            "pend",        // spawning a task pends the executor
+            "poll boxed",  //
+            "drop future", // the future is dropped as soon as it completes
+            "poll boxed",  //
+            "drop future", //
+        ]
+    )
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn executor_task_boxed_outlived_by_waker() {
+    let (executor, trace) = setup();
+    let waker = Arc::new(Mutex::new(None));
+
+    let w = waker.clone();
+    let t = trace.clone();
+    executor.spawner().spawn_boxed(async move {
+        poll_fn(|cx| {
+            t.push("poll boxed");
+            *w.lock().unwrap() = Some(cx.waker().clone());
+            Poll::Ready(())
+        })
+        .await
+    });
+
+    unsafe { executor.poll() };
+
+    // The task is finished, but the waker keeps its memory alive: waking it must be a no-op.
+    let waker = waker.lock().unwrap().take().unwrap();
+    waker.wake_by_ref();
+    waker.wake();
+    unsafe { executor.poll() };
+
+    assert_eq!(
+        trace.get(),
+        &[
+            "pend",       // spawning a task pends the executor
+            "poll boxed", // poll only once.
+        ]
+    )
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn executor_task_boxed_woken_while_finishing() {
+    let (executor, trace) = setup();
+
+    let t = trace.clone();
+    executor.spawner().spawn_boxed(async move {
+        poll_fn(|cx| {
+            t.push("poll boxed");
+            // Leaves the task in the run queue after it finishes.
+            cx.waker().wake_by_ref();
+            Poll::Ready(())
+        })
+        .await
+    });
+
+    unsafe { executor.poll() };
+    unsafe { executor.poll() };
+
+    assert_eq!(
+        trace.get(),
+        &[
+            "pend",       // spawning a task pends the executor
+            "poll boxed", //
+            "pend",       // task self-wakes
+        ]
+    )
+}
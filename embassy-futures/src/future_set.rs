@@ -0,0 +1,427 @@
+//! Fixed-capacity set of futures that yields their outputs as they complete.
+//!
+//! Unlike [`select_slice`](crate::select::select_slice), a [`FutureSet`] keeps track of which futures
+//! have been woken, and only polls those. Futures can be pushed while others are still running,
+//! and the remaining futures keep running when one of them completes.
+//!
+//! Each future gets its own waker. Since wakers may outlive the set (for example, when a future
+//! registers its waker somewhere and is then dropped), their state lives in a separate
+//! [`FutureSetWakers`], which must be `'static`.
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use critical_section::Mutex;
+
+struct SlotState {
+    /// The future in this slot must be polled.
+    woken: bool,
+    /// Waker of the task polling the set. Taken when the slot is woken.
+    waker: Option<Waker>,
+}
+
+struct Slot {
+    state: Mutex<RefCell<SlotState>>,
+}
+
+impl Slot {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: Self = Self {
+        state: Mutex::new(RefCell::new(SlotState {
+            woken: false,
+            waker: None,
+        })),
+    };
+
+    fn wake(&self) {
+        let waker = critical_section::with(|cs| {
+            let mut s = self.state.borrow_ref_mut(cs);
+            s.woken = true;
+            s.waker.take()
+        });
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Clear the woken flag, returning whether it was set.
+    fn take_woken(&self) -> bool {
+        critical_section::with(|cs| core::mem::replace(&mut self.state.borrow_ref_mut(cs).woken, false))
+    }
+
+    /// Mark the slot as woken without waking the task. Used when a new future is pushed.
+    fn set_woken(&self) {
+        critical_section::with(|cs| self.state.borrow_ref_mut(cs).woken = true)
+    }
+
+    /// Register the waker of the task polling the set.
+    fn register(&self, waker: &Waker) {
+        critical_section::with(|cs| {
+            let mut s = self.state.borrow_ref_mut(cs);
+            match &s.waker {
+                Some(w) if w.will_wake(waker) => {}
+                _ => s.waker = Some(waker.clone()),
+            }
+        })
+    }
+
+    /// Replace the registered waker, if any. Slots that have already been woken keep no waker.
+    fn replace_registered(&self, waker: &Waker) {
+        critical_section::with(|cs| {
+            let mut s = self.state.borrow_ref_mut(cs);
+            if s.waker.is_some() {
+                s.waker = Some(waker.clone());
+            }
+        })
+    }
+
+    fn waker(&'static self) -> Waker {
+        unsafe { Waker::from_raw(RawWaker::new(self as *const Slot as *const (), &VTABLE)) }
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |p| RawWaker::new(p, &VTABLE),
+    |p| unsafe { &*(p as *const Slot) }.wake(),
+    |p| unsafe { &*(p as *const Slot) }.wake(),
+    |_| {},
+);
+
+/// Waker storage for a [`FutureSet`] of capacity `N`.
+///
+/// This must be placed in a `static`. It can be reused by another [`FutureSet`] once the previous one
+/// has been dropped, but not by two sets at the same time.
+///
+/// ```
+/// use embassy_futures::future_set::FutureSetWakers;
+///
+/// static WAKERS: FutureSetWakers<8> = FutureSetWakers::new();
+/// ```
+pub struct FutureSetWakers<const N: usize> {
+    in_use: Mutex<RefCell<bool>>,
+    slots: [Slot; N],
+}
+
+impl<const N: usize> FutureSetWakers<N> {
+    /// Create a new `FutureSetWakers`.
+    pub const fn new() -> Self {
+        Self {
+            in_use: Mutex::new(RefCell::new(false)),
+            slots: [Slot::NEW; N],
+        }
+    }
+}
+
+impl<const N: usize> Default for FutureSetWakers<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fixed-capacity set of futures, yielding their outputs as they complete.
+///
+/// Push futures with [`FutureSet::push`], and await [`FutureSet::next`] to get the output of the
+/// next future that completes. Only futures that have been woken are polled.
+///
+/// The set must be pinned to be used, for example with [`core::pin::pin!`].
+///
+/// # Examples
+///
+/// ```
+/// # embassy_futures::block_on(async {
+/// use core::pin::pin;
+///
+/// use embassy_futures::future_set::{FutureSet, FutureSetWakers};
+///
+/// static WAKERS: FutureSetWakers<4> = FutureSetWakers::new();
+///
+/// async fn work(n: u32) -> u32 {
+///     for _ in 0..n {
+///         embassy_futures::yield_now().await;
+///     }
+///     n
+/// }
+///
+/// let mut set = pin!(FutureSet::new(&WAKERS));
+/// set.as_mut().push(work(3)).ok().unwrap();
+/// set.as_mut().push(work(1)).ok().unwrap();
+///
+/// let (output, _) = set.as_mut().next().await;
+/// assert_eq!(output, 1);
+///
+/// set.as_mut().push(work(5)).ok().unwrap();
+/// assert_eq!(set.as_mut().next().await.0, 3);
+/// assert_eq!(set.as_mut().next().await.0, 5);
+/// assert!(set.is_empty());
+/// # });
+/// ```
+pub struct FutureSet<F: Future, const N: usize> {
+    futures: [Option<F>; N],
+    wakers: &'static FutureSetWakers<N>,
+    /// Waker of the task that last polled the set.
+    parent: Option<Waker>,
+    len: usize,
+}
+
+impl<F: Future, const N: usize> FutureSet<F, N> {
+    /// Create a new, empty `FutureSet` using the given waker storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `wakers` is in use by another `FutureSet`.
+    pub fn new(wakers: &'static FutureSetWakers<N>) -> Self {
+        let was_in_use = critical_section::with(|cs| wakers.in_use.replace(cs, true));
+        if was_in_use {
+            panic!("FutureSetWakers is already in use by another FutureSet");
+        }
+        Self {
+            futures: core::array::from_fn(|_| None),
+            wakers,
+            parent: None,
+            len: 0,
+        }
+    }
+
+    /// Number of futures in the set.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the set contains no futures.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether the set is full.
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Maximum number of futures in the set.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Add a future to the set.
+    ///
+    /// Returns the index of the future in the set, which is returned along with its output by
+    /// [`FutureSet::next`] and can be passed to [`FutureSet::remove`]. If the set is full, the
+    /// future is given back.
+    pub fn push(self: Pin<&mut Self>, future: F) -> Result<usize, F> {
+        // safety: the futures are not moved out of the set until they're dropped.
+        let this = unsafe { self.get_unchecked_mut() };
+        let Some(index) = this.futures.iter().position(Option::is_none) else {
+            return Err(future);
+        };
+        this.futures[index] = Some(future);
+        this.len += 1;
+        // The future hasn't been polled yet.
+        this.wakers.slots[index].set_woken();
+        Ok(index)
+    }
+
+    /// Remove and drop the future at `index`, returning whether there was one.
+    pub fn remove(self: Pin<&mut Self>, index: usize) -> bool {
+        // safety: the future is dropped in place.
+        let this = unsafe { self.get_unchecked_mut() };
+        match this.futures.get_mut(index) {
+            Some(f @ Some(_)) => {
+                *f = None;
+                this.len -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Wait for the next future in the set to complete.
+    ///
+    /// Returns the output of the future and its index. The future is removed from the set.
+    ///
+    /// If the set is empty, the returned future will be pending until a future is pushed and completes.
+    pub fn next(self: Pin<&mut Self>) -> Next<'_, F, N> {
+        Next { set: self }
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<(F::Output, usize)> {
+        // safety: the futures are pinned in place, they are not moved until they're dropped.
+        let this = unsafe { self.get_unchecked_mut() };
+
+        // If the set is polled from another task, wakeups must go to the new one.
+        let parent_changed = !matches!(&this.parent, Some(w) if w.will_wake(cx.waker()));
+        if parent_changed {
+            this.parent = Some(cx.waker().clone());
+            for (slot, f) in this.wakers.slots.iter().zip(this.futures.iter()) {
+                if f.is_some() {
+                    slot.replace_registered(cx.waker());
+                }
+            }
+        }
+
+        for (index, (slot, f)) in this.wakers.slots.iter().zip(this.futures.iter_mut()).enumerate() {
+            let Some(fut) = f else {
+                continue;
+            };
+            if !slot.take_woken() {
+                continue;
+            }
+
+            // Register before polling, so wakes that happen while polling aren't lost.
+            slot.register(cx.waker());
+            let waker = slot.waker();
+            let mut slot_cx = Context::from_waker(&waker);
+            if let Poll::Ready(output) = unsafe { Pin::new_unchecked(fut) }.poll(&mut slot_cx) {
+                *f = None;
+                this.len -= 1;
+                return Poll::Ready((output, index));
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<F: Future, const N: usize> Drop for FutureSet<F, N> {
+    fn drop(&mut self) {
+        // Drop the futures before releasing the wakers, so they can't be woken by a new set.
+        for f in self.futures.iter_mut() {
+            *f = None;
+        }
+        critical_section::with(|cs| {
+            for slot in self.wakers.slots.iter() {
+                let mut s = slot.state.borrow_ref_mut(cs);
+                s.woken = false;
+                s.waker = None;
+            }
+            self.wakers.in_use.replace(cs, false);
+        });
+    }
+}
+
+/// Future for the [`FutureSet::next`] method.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Next<'a, F: Future, const N: usize> {
+    set: Pin<&'a mut FutureSet<F, N>>,
+}
+
+impl<'a, F: Future, const N: usize> Future for Next<'a, F, N> {
+    type Output = (F::Output, usize);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.set.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::future::poll_fn;
+    use core::pin::pin;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex as StdMutex};
+    use std::task::Wake;
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn poll<T: Future>(fut: T, cx: &mut Context<'_>) -> Poll<T::Output> {
+        pin!(fut).poll(cx)
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// A future that records each poll, and completes when its waker is woken.
+    fn tracked(
+        id: usize,
+        polls: Arc<StdMutex<Vec<usize>>>,
+        wakers: Arc<StdMutex<Vec<Option<Waker>>>>,
+    ) -> impl Future<Output = usize> {
+        let mut pending = true;
+        poll_fn(move |cx| {
+            polls.lock().unwrap().push(id);
+            if pending {
+                pending = false;
+                wakers.lock().unwrap()[id] = Some(cx.waker().clone());
+                Poll::Pending
+            } else {
+                Poll::Ready(id)
+            }
+        })
+    }
+
+    #[test]
+    fn test_only_woken_futures_are_polled() {
+        static WAKERS: FutureSetWakers<4> = FutureSetWakers::new();
+
+        let parent = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let parent_waker = Waker::from(parent.clone());
+        let mut cx = Context::from_waker(&parent_waker);
+
+        let polls = Arc::new(StdMutex::new(Vec::new()));
+        let wakers = Arc::new(StdMutex::new(std::vec![None, None, None]));
+
+        let mut set = pin!(FutureSet::new(&WAKERS));
+        for id in 0..3 {
+            assert_eq!(
+                set.as_mut().push(tracked(id, polls.clone(), wakers.clone())).ok(),
+                Some(id)
+            );
+        }
+
+        // All futures are polled once.
+        assert!(poll(set.as_mut().next(), &mut cx).is_pending());
+        assert_eq!(*polls.lock().unwrap(), [0, 1, 2]);
+
+        // Nothing is woken, nothing is polled.
+        assert!(poll(set.as_mut().next(), &mut cx).is_pending());
+        assert_eq!(polls.lock().unwrap().len(), 3);
+
+        // Wake future 1. The parent is woken, and only future 1 is polled.
+        wakers.lock().unwrap()[1].take().unwrap().wake();
+        assert_eq!(parent.0.load(Ordering::Relaxed), 1);
+        assert_eq!(poll(set.as_mut().next(), &mut cx), Poll::Ready((1, 1)));
+        assert_eq!(*polls.lock().unwrap(), [0, 1, 2, 1]);
+        assert_eq!(set.len(), 2);
+
+        // Freed slots are reused.
+        assert_eq!(
+            set.as_mut().push(tracked(1, polls.clone(), wakers.clone())).ok(),
+            Some(1)
+        );
+        assert!(poll(set.as_mut().next(), &mut cx).is_pending());
+        assert_eq!(*polls.lock().unwrap(), [0, 1, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_full() {
+        static WAKERS: FutureSetWakers<1> = FutureSetWakers::new();
+
+        let mut set = pin!(FutureSet::new(&WAKERS));
+        assert_eq!(set.as_mut().push(core::future::ready(1)).ok(), Some(0));
+        assert!(set.is_full());
+        assert!(set.as_mut().push(core::future::ready(2)).is_err());
+        assert!(set.as_mut().remove(0));
+        assert!(!set.as_mut().remove(0));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn test_wakers_reuse() {
+        static WAKERS: FutureSetWakers<1> = FutureSetWakers::new();
+
+        let set = FutureSet::<core::future::Ready<()>, 1>::new(&WAKERS);
+        drop(set);
+        let _set = FutureSet::<core::future::Ready<()>, 1>::new(&WAKERS);
+        let res = std::panic::catch_unwind(|| FutureSet::<core::future::Ready<()>, 1>::new(&WAKERS).len());
+        assert!(res.is_err());
+    }
+}
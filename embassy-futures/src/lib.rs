@@ -8,6 +8,8 @@ pub(crate) mod fmt;
 mod block_on;
 mod yield_now;
 
+#[cfg(feature = "future-set")]
+pub mod future_set;
 pub mod join;
 pub mod select;
 
@@ -17,6 +17,8 @@ mod device;
 pub mod dns;
 #[cfg(feature = "raw")]
 pub mod raw;
+#[cfg(feature = "sntp")]
+pub mod sntp;
 #[cfg(feature = "tcp")]
 pub mod tcp;
 mod time;
@@ -0,0 +1,778 @@
+//! SNTP client (RFC 4330).
+//!
+//! [`SntpClient`] queries NTP servers for the current time and feeds the results into an
+//! [`embassy_time::WallClock`], so applications can get UTC time without an RTC.
+//!
+//! Each query opens a temporary UDP socket, so the [`StackResources`](crate::StackResources) passed
+//! to the stack must have room for one more socket.
+
+use embassy_time::{with_timeout, Duration, Instant, Timer, UtcTime, WallClock};
+
+use crate::dns::DnsQueryType;
+use crate::udp::{PacketMetadata, UdpSocket};
+use crate::{Driver, IpAddress, IpEndpoint, Stack};
+
+/// The well-known NTP server port.
+pub const NTP_PORT: u16 = 123;
+
+/// Length of an NTP packet without extension fields or authenticator.
+const PACKET_LEN: usize = 48;
+
+/// Seconds between the NTP epoch (1900-01-01) and the Unix epoch (1970-01-01).
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const VERSION: u8 = 4;
+const LEAP_ALARM: u8 = 3;
+const MAX_STRATUM: u8 = 15;
+
+/// Maximum number of servers in [`Config::servers`].
+pub const MAX_SERVERS: usize = 32;
+
+/// Errors returned by [`SntpClient`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// All configured servers have refused service with a kiss-of-death packet, or none are configured.
+    NoServer,
+    /// The server name could not be resolved.
+    Dns,
+    /// The request could not be sent.
+    Send,
+    /// No valid response was received in time.
+    Timeout,
+    /// The server is not synchronized, so its time can't be trusted.
+    Unsynchronized,
+    /// The server sent a kiss-of-death packet.
+    KissOfDeath(KissCode),
+}
+
+/// Kiss code of a kiss-of-death packet, sent by servers to control client behavior.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KissCode {
+    /// Access denied. The client must stop sending requests to this server.
+    Deny,
+    /// Access restricted. The client must stop sending requests to this server.
+    Restrict,
+    /// Rate exceeded. The client must reduce its polling rate.
+    Rate,
+    /// Any other kiss code. The four ASCII characters of the code are included.
+    Other([u8; 4]),
+}
+
+impl KissCode {
+    fn from_bytes(code: [u8; 4]) -> Self {
+        match &code {
+            b"DENY" => Self::Deny,
+            b"RSTR" => Self::Restrict,
+            b"RATE" => Self::Rate,
+            _ => Self::Other(code),
+        }
+    }
+}
+
+/// SNTP client configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// NTP servers, as hostnames or IP address literals. Servers are used in order: the client
+    /// switches to the next one when the current one fails or refuses service.
+    ///
+    /// At most [`MAX_SERVERS`] servers can be configured.
+    pub servers: &'a [&'a str],
+    /// Server port. This is almost always 123.
+    pub server_port: u16,
+    /// How long to wait for a response.
+    pub timeout: Duration,
+    /// Interval between successful synchronizations. It is doubled, up to `max_poll_interval`, every
+    /// time a server responds with a `RATE` kiss-of-death packet.
+    pub poll_interval: Duration,
+    /// Upper bound for the poll interval.
+    pub max_poll_interval: Duration,
+    /// Interval between attempts after a failed synchronization.
+    pub retry_interval: Duration,
+}
+
+impl Default for Config<'_> {
+    fn default() -> Self {
+        Self {
+            servers: &["pool.ntp.org"],
+            server_port: NTP_PORT,
+            timeout: Duration::from_secs(5),
+            poll_interval: Duration::from_secs(1024),
+            max_poll_interval: Duration::from_secs(36 * 60 * 60),
+            retry_interval: Duration::from_secs(16),
+        }
+    }
+}
+
+/// Result of a successful SNTP query.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Sample {
+    /// Address of the server that responded.
+    pub server: IpAddress,
+    /// Stratum of the server.
+    pub stratum: u8,
+    /// Instant the response was received.
+    pub instant: Instant,
+    /// UTC time at `instant`, compensated for half the round-trip delay.
+    pub utc: UtcTime,
+    /// Round-trip delay of the query, excluding server processing time.
+    pub round_trip_delay: Duration,
+}
+
+/// Fields of a server response used by the client.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Response {
+    stratum: u8,
+    /// Server receive timestamp (T2).
+    receive: u64,
+    /// Server transmit timestamp (T3).
+    transmit: u64,
+}
+
+/// Serialize a client request. `transmit` is echoed back by the server in the originate
+/// timestamp of its response, so it can be used to match responses to requests.
+fn encode_request(transmit: u64) -> [u8; PACKET_LEN] {
+    let mut buf = [0; PACKET_LEN];
+    buf[0] = (VERSION << 3) | MODE_CLIENT;
+    buf[40..48].copy_from_slice(&transmit.to_be_bytes());
+    buf
+}
+
+/// Parse a server response to the request with transmit timestamp `originate`.
+///
+/// Returns `Ok(None)` for packets that aren't a response to our request.
+fn parse_response(buf: &[u8], originate: u64) -> Result<Option<Response>, Error> {
+    if buf.len() < PACKET_LEN {
+        return Ok(None);
+    }
+
+    let read_u64 = |offset: usize| u64::from_be_bytes(unwrap!(buf[offset..offset + 8].try_into()));
+
+    let leap = buf[0] >> 6;
+    let version = (buf[0] >> 3) & 0x7;
+    let mode = buf[0] & 0x7;
+    let stratum = buf[1];
+
+    if mode != MODE_SERVER || !(1..=4).contains(&version) || read_u64(24) != originate {
+        return Ok(None);
+    }
+
+    if stratum == 0 {
+        return Err(Error::KissOfDeath(KissCode::from_bytes(
+            unwrap!(buf[12..16].try_into()),
+        )));
+    }
+
+    let transmit = read_u64(40);
+    if leap == LEAP_ALARM || stratum > MAX_STRATUM || transmit == 0 {
+        return Err(Error::Unsynchronized);
+    }
+
+    Ok(Some(Response {
+        stratum,
+        receive: read_u64(32),
+        transmit,
+    }))
+}
+
+/// Convert an NTP timestamp to UTC.
+///
+/// Timestamps with the most significant bit cleared are taken to be in NTP era 1 (starting in 2036),
+/// as recommended by RFC 4330 section 3.
+fn ntp_to_utc(timestamp: u64) -> UtcTime {
+    let mut secs = timestamp >> 32;
+    if secs & 0x8000_0000 == 0 {
+        secs += 1 << 32;
+    }
+    let micros = ((timestamp & 0xFFFF_FFFF) * 1_000_000) >> 32;
+    UtcTime::from_unix_micros((secs - NTP_UNIX_OFFSET_SECS) * 1_000_000 + micros)
+}
+
+/// Convert a difference between NTP timestamps to microseconds.
+fn ntp_diff_micros(later: u64, earlier: u64) -> u64 {
+    ((later.wrapping_sub(earlier) as u128 * 1_000_000) >> 32) as u64
+}
+
+/// Compute the time at `t4`, from a request sent at `t1` and received by the server at `t2`,
+/// whose response was sent at `t3` and received at `t4`.
+///
+/// The local clock is the monotonic `Instant` clock, so rather than the RFC 4330 clock offset this
+/// computes the UTC time at `t4` directly: `T3 + d / 2`, where `d = (T4 - T1) - (T3 - T2)` is the
+/// round-trip delay. The offset to a local wall clock is then `(T3 + d / 2) - T4`, which is equal to
+/// RFC 4330's `((T2 - T1) + (T3 - T4)) / 2`.
+fn compute(t1: Instant, t2: u64, t3: u64, t4: Instant) -> (UtcTime, Duration) {
+    let processing = ntp_diff_micros(t3, t2);
+    let delay = t4.saturating_duration_since(t1).as_micros().saturating_sub(processing);
+    let utc = ntp_to_utc(t3) + Duration::from_micros(delay / 2);
+    (utc, Duration::from_micros(delay))
+}
+
+/// SNTP client.
+///
+/// Use [`SntpClient::query`] to query the current time once, or [`SntpClient::run`] to keep a
+/// [`WallClock`] synchronized.
+pub struct SntpClient<'a, D: Driver + 'static> {
+    stack: &'a Stack<D>,
+    config: Config<'a>,
+    /// Index of the server currently in use.
+    server: usize,
+    /// Bitmask of servers that refused service.
+    denied: u32,
+    poll_interval: Duration,
+}
+
+impl<'a, D: Driver + 'static> SntpClient<'a, D> {
+    /// Create a new SNTP client.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than [`MAX_SERVERS`] servers are configured.
+    pub fn new(stack: &'a Stack<D>, config: Config<'a>) -> Self {
+        assert!(config.servers.len() <= MAX_SERVERS);
+        let poll_interval = config.poll_interval;
+        Self {
+            stack,
+            config,
+            server: 0,
+            denied: 0,
+            poll_interval,
+        }
+    }
+
+    /// Current interval between successful synchronizations.
+    ///
+    /// This is [`Config::poll_interval`], unless servers asked the client to slow down.
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Switch to the next server that hasn't refused service.
+    fn next_server(&mut self) {
+        let n = self.config.servers.len();
+        for i in 1..=n {
+            let server = (self.server + i) % n;
+            if self.denied & (1 << server) == 0 {
+                self.server = server;
+                return;
+            }
+        }
+    }
+
+    /// Query the current server once.
+    ///
+    /// On failure, the client switches to the next server for the following query. Servers that
+    /// respond with a `DENY` or `RSTR` kiss-of-death packet are never queried again.
+    pub async fn query(&mut self) -> Result<Sample, Error> {
+        let n = self.config.servers.len();
+        if n == 0 || self.denied == (1u64 << n).wrapping_sub(1) as u32 {
+            return Err(Error::NoServer);
+        }
+        if self.denied & (1 << self.server) != 0 {
+            self.next_server();
+        }
+
+        let res = self.query_server(self.config.servers[self.server]).await;
+        match res {
+            Ok(_) => {}
+            Err(Error::KissOfDeath(KissCode::Deny | KissCode::Restrict)) => {
+                warn!("sntp: server {} refused service", self.server);
+                self.denied |= 1 << self.server;
+                self.next_server();
+            }
+            Err(Error::KissOfDeath(KissCode::Rate)) => {
+                self.poll_interval = (self.poll_interval * 2).min(self.config.max_poll_interval);
+                debug!(
+                    "sntp: rate exceeded, poll interval is now {} s",
+                    self.poll_interval.as_secs()
+                );
+            }
+            Err(_) => self.next_server(),
+        }
+        res
+    }
+
+    async fn query_server(&self, name: &str) -> Result<Sample, Error> {
+        #[cfg(feature = "proto-ipv4")]
+        let qtype = DnsQueryType::A;
+        #[cfg(not(feature = "proto-ipv4"))]
+        let qtype = DnsQueryType::Aaaa;
+
+        let addrs = self.stack.dns_query(name, qtype).await.map_err(|_| Error::Dns)?;
+        let addr = *addrs.first().ok_or(Error::Dns)?;
+        let endpoint = IpEndpoint::new(addr, self.config.server_port);
+
+        let mut rx_meta = [PacketMetadata::EMPTY; 2];
+        let mut rx_buffer = [0; 2 * PACKET_LEN];
+        let mut tx_meta = [PacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0; PACKET_LEN];
+        let mut socket = UdpSocket::new(self.stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+        socket.bind(0).map_err(|_| Error::Send)?;
+
+        // The transmit timestamp only needs to be unique, it is not interpreted by the server.
+        let t1 = Instant::now();
+        let originate = t1.as_ticks();
+        socket
+            .send_to(&encode_request(originate), endpoint)
+            .await
+            .map_err(|_| Error::Send)?;
+
+        let (response, t4) = with_timeout(self.config.timeout, async {
+            let mut buf = [0; PACKET_LEN];
+            loop {
+                let Ok((len, meta)) = socket.recv_from(&mut buf).await else {
+                    // Truncated packet, can't be a valid response.
+                    continue;
+                };
+                let t4 = Instant::now();
+                if meta.endpoint != endpoint {
+                    continue;
+                }
+                if let Some(response) = parse_response(&buf[..len], originate)? {
+                    return Ok((response, t4));
+                }
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)??;
+
+        let (utc, round_trip_delay) = compute(t1, response.receive, response.transmit, t4);
+        Ok(Sample {
+            server: addr,
+            stratum: response.stratum,
+            instant: t4,
+            utc,
+            round_trip_delay,
+        })
+    }
+
+    /// Keep `clock` synchronized, forever.
+    ///
+    /// The clock is synchronized every [`poll_interval`](Self::poll_interval). Failed queries are
+    /// retried every [`Config::retry_interval`], rotating through the configured servers.
+    pub async fn run(&mut self, clock: &WallClock) -> ! {
+        loop {
+            let delay = match self.query().await {
+                Ok(sample) => {
+                    match clock.synchronize(sample.instant, sample.utc) {
+                        Some(offset) => debug!("sntp: synchronized, offset {} us", offset),
+                        None => info!("sntp: clock set"),
+                    }
+                    self.poll_interval
+                }
+                Err(Error::NoServer) => {
+                    warn!("sntp: no usable server");
+                    self.config.max_poll_interval
+                }
+                Err(Error::KissOfDeath(KissCode::Rate)) => self.poll_interval,
+                Err(e) => {
+                    debug!("sntp: query failed: {:?}", e);
+                    self.config.retry_interval
+                }
+            };
+            Timer::after(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-01T00:00:00Z
+    const UNIX_2024: u64 = 1_704_067_200;
+
+    fn ntp(unix_secs: u64, micros: u64) -> u64 {
+        // Only exact for multiples of 15625 us.
+        ((unix_secs + NTP_UNIX_OFFSET_SECS) << 32) | ((micros << 32) / 1_000_000)
+    }
+
+    fn response(leap: u8, stratum: u8, originate: u64, receive: u64, transmit: u64) -> [u8; PACKET_LEN] {
+        let mut buf = [0; PACKET_LEN];
+        buf[0] = (leap << 6) | (VERSION << 3) | MODE_SERVER;
+        buf[1] = stratum;
+        buf[12..16].copy_from_slice(b"GPS\0");
+        buf[24..32].copy_from_slice(&originate.to_be_bytes());
+        buf[32..40].copy_from_slice(&receive.to_be_bytes());
+        buf[40..48].copy_from_slice(&transmit.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let buf = encode_request(0x0102030405060708);
+        assert_eq!(buf[0], 0x23);
+        assert!(buf[1..40].iter().all(|&b| b == 0));
+        assert_eq!(buf[40..48], [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let buf = response(0, 2, 42, ntp(UNIX_2024, 0), ntp(UNIX_2024, 100));
+        assert_eq!(
+            parse_response(&buf, 42),
+            Ok(Some(Response {
+                stratum: 2,
+                receive: ntp(UNIX_2024, 0),
+                transmit: ntp(UNIX_2024, 100),
+            }))
+        );
+
+        // Not a response to our request.
+        assert_eq!(parse_response(&buf, 43), Ok(None));
+        assert_eq!(parse_response(&buf[..47], 42), Ok(None));
+        let mut client = buf;
+        client[0] = (VERSION << 3) | MODE_CLIENT;
+        assert_eq!(parse_response(&client, 42), Ok(None));
+
+        // Unsynchronized server.
+        let buf = response(LEAP_ALARM, 2, 42, ntp(UNIX_2024, 0), ntp(UNIX_2024, 100));
+        assert_eq!(parse_response(&buf, 42), Err(Error::Unsynchronized));
+        let buf = response(0, 16, 42, ntp(UNIX_2024, 0), ntp(UNIX_2024, 100));
+        assert_eq!(parse_response(&buf, 42), Err(Error::Unsynchronized));
+    }
+
+    #[test]
+    fn test_parse_kiss_of_death() {
+        let mut buf = response(LEAP_ALARM, 0, 42, 0, 0);
+        for (code, kiss) in [
+            (b"DENY", KissCode::Deny),
+            (b"RSTR", KissCode::Restrict),
+            (b"RATE", KissCode::Rate),
+            (b"INIT", KissCode::Other(*b"INIT")),
+        ] {
+            buf[12..16].copy_from_slice(code);
+            assert_eq!(parse_response(&buf, 42), Err(Error::KissOfDeath(kiss)));
+        }
+    }
+
+    #[test]
+    fn test_ntp_to_utc() {
+        assert_eq!(
+            ntp_to_utc(ntp(UNIX_2024, 250_000)),
+            UtcTime::from_unix_micros(UNIX_2024 * 1_000_000 + 250_000)
+        );
+        // NTP era 1 starts on 2036-02-07T06:28:16Z.
+        assert_eq!(ntp_to_utc(0), UtcTime::from_unix_secs(2_085_978_496));
+        assert_eq!(ntp_to_utc(10 << 32), UtcTime::from_unix_secs(2_085_978_506));
+    }
+
+    #[test]
+    fn test_compute() {
+        // Request sent at 10s since boot, response received 35.625ms later.
+        // The server took 15.625ms to respond, so the one-way delay is 10ms.
+        let t1 = Instant::from_secs(10);
+        let t4 = t1 + Duration::from_micros(35_625);
+        let t2 = ntp(UNIX_2024, 0);
+        let t3 = ntp(UNIX_2024, 15_625);
+
+        let (utc, delay) = compute(t1, t2, t3, t4);
+        assert_eq!(delay, Duration::from_millis(20));
+        assert_eq!(utc, UtcTime::from_unix_micros(UNIX_2024 * 1_000_000 + 25_625));
+    }
+
+    /// Queries through a stack, to servers simulated by its driver.
+    #[cfg(all(feature = "proto-ipv4", feature = "medium-ip"))]
+    mod stack {
+        extern crate std;
+
+        use core::cell::RefCell;
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Waker};
+        use std::boxed::Box;
+        use std::collections::VecDeque;
+        use std::rc::Rc;
+        use std::vec;
+        use std::vec::Vec;
+
+        use embassy_futures::select::{select, Either};
+        use futures::executor::block_on;
+        use smoltcp::phy::ChecksumCapabilities;
+        use smoltcp::wire::{IpProtocol, Ipv4Packet, Ipv4Repr, UdpPacket, UdpRepr};
+
+        use super::*;
+        use crate::driver::{Capabilities, HardwareAddress, LinkState};
+        use crate::{Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+
+        /// Reply of a simulated server to a request.
+        #[derive(Clone, Copy)]
+        enum Reply {
+            Time,
+            Kiss(&'static [u8; 4]),
+            Drop,
+        }
+
+        /// Driver answering NTP requests itself, as servers with scripted replies would.
+        struct Servers {
+            /// Replies of each server, in order. Servers stop replying once they run out.
+            replies: Vec<(Ipv4Address, VecDeque<Reply>)>,
+            /// Servers that received a request, in order.
+            requests: Rc<RefCell<Vec<Ipv4Address>>>,
+            packets: VecDeque<Vec<u8>>,
+            waker: Option<Waker>,
+        }
+
+        impl Servers {
+            fn respond(&mut self, packet: &[u8]) {
+                let caps = ChecksumCapabilities::default();
+                let ip = Ipv4Packet::new_checked(packet).unwrap();
+                let ip_repr = Ipv4Repr::parse(&ip, &caps).unwrap();
+                let (client, server) = (IpAddress::Ipv4(ip_repr.src_addr), IpAddress::Ipv4(ip_repr.dst_addr));
+                let udp = UdpPacket::new_checked(ip.payload()).unwrap();
+                let udp_repr = UdpRepr::parse(&udp, &client, &server, &caps).unwrap();
+                assert_eq!(NTP_PORT, udp_repr.dst_port);
+                let request = udp.payload();
+                assert_eq!(PACKET_LEN, request.len());
+                let originate = u64::from_be_bytes(request[40..48].try_into().unwrap());
+
+                self.requests.borrow_mut().push(ip_repr.dst_addr);
+                let reply = self
+                    .replies
+                    .iter_mut()
+                    .find(|(addr, _)| *addr == ip_repr.dst_addr)
+                    .and_then(|(_, replies)| replies.pop_front())
+                    .unwrap_or(Reply::Drop);
+                let payload = match reply {
+                    Reply::Time => response(0, 2, originate, ntp(UNIX_2024, 0), ntp(UNIX_2024, 15_625)),
+                    Reply::Kiss(code) => {
+                        let mut buf = response(LEAP_ALARM, 0, originate, 0, 0);
+                        buf[12..16].copy_from_slice(code);
+                        buf
+                    }
+                    Reply::Drop => return,
+                };
+
+                let ip_repr = Ipv4Repr {
+                    src_addr: ip_repr.dst_addr,
+                    dst_addr: ip_repr.src_addr,
+                    next_header: IpProtocol::Udp,
+                    payload_len: 8 + PACKET_LEN,
+                    hop_limit: 64,
+                };
+                let udp_repr = UdpRepr {
+                    src_port: udp_repr.dst_port,
+                    dst_port: udp_repr.src_port,
+                };
+                let mut buf = vec![0; ip_repr.buffer_len() + ip_repr.payload_len];
+                let mut ip = Ipv4Packet::new_unchecked(&mut buf[..]);
+                ip_repr.emit(&mut ip, &caps);
+                udp_repr.emit(
+                    &mut UdpPacket::new_unchecked(ip.payload_mut()),
+                    &server,
+                    &client,
+                    PACKET_LEN,
+                    |buf| buf.copy_from_slice(&payload),
+                    &caps,
+                );
+                self.packets.push_back(buf);
+                if let Some(waker) = self.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+
+        struct RxToken(Vec<u8>);
+
+        struct TxToken<'a>(&'a mut Servers);
+
+        impl crate::driver::RxToken for RxToken {
+            fn consume<R, F: FnOnce(&mut [u8]) -> R>(mut self, f: F) -> R {
+                f(&mut self.0)
+            }
+        }
+
+        impl crate::driver::TxToken for TxToken<'_> {
+            fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+                let mut packet = vec![0; len];
+                let result = f(&mut packet);
+                self.0.respond(&packet);
+                result
+            }
+        }
+
+        impl Driver for Servers {
+            type RxToken<'a> = RxToken;
+            type TxToken<'a> = TxToken<'a>;
+
+            fn receive(&mut self, cx: &mut Context) -> Option<(RxToken, TxToken<'_>)> {
+                match self.packets.pop_front() {
+                    Some(packet) => Some((RxToken(packet), TxToken(self))),
+                    None => {
+                        self.waker = Some(cx.waker().clone());
+                        None
+                    }
+                }
+            }
+
+            fn transmit(&mut self, _cx: &mut Context) -> Option<TxToken<'_>> {
+                Some(TxToken(self))
+            }
+
+            fn link_state(&mut self, _cx: &mut Context) -> LinkState {
+                LinkState::Up
+            }
+
+            fn capabilities(&self) -> Capabilities {
+                let mut capabilities = Capabilities::default();
+                capabilities.max_transmission_unit = 1500;
+                capabilities
+            }
+
+            fn hardware_address(&self) -> HardwareAddress {
+                HardwareAddress::Ip
+            }
+        }
+
+        fn server(n: u8) -> Ipv4Address {
+            Ipv4Address::new(10, 0, 0, n)
+        }
+
+        /// Run `queries` with a client of `servers`, which reply with `replies`. Returns the servers that
+        /// received a request, in order.
+        fn run<const N: usize>(
+            servers: &[&str],
+            replies: [(u8, &[Reply]); N],
+            queries: impl for<'c> FnOnce(&'c mut SntpClient<'_, Servers>) -> Pin<Box<dyn Future<Output = ()> + 'c>>,
+        ) -> Vec<Ipv4Address> {
+            let requests = Rc::new(RefCell::new(Vec::new()));
+            let driver = Servers {
+                replies: replies
+                    .iter()
+                    .map(|(n, replies)| (server(*n), replies.iter().copied().collect()))
+                    .collect(),
+                requests: requests.clone(),
+                packets: VecDeque::new(),
+                waker: None,
+            };
+            let config = crate::Config::ipv4_static(StaticConfigV4 {
+                address: Ipv4Cidr::new(server(1), 24),
+                gateway: None,
+                dns_servers: heapless::Vec::new(),
+            });
+            let resources = Box::leak(Box::new(StackResources::<2>::new()));
+            let stack = Stack::new(driver, config, resources, 1);
+
+            let mut client = SntpClient::new(
+                &stack,
+                Config {
+                    servers,
+                    timeout: Duration::from_millis(50),
+                    poll_interval: Duration::from_secs(64),
+                    max_poll_interval: Duration::from_secs(192),
+                    ..Default::default()
+                },
+            );
+            match block_on(select(stack.run(), queries(&mut client))) {
+                Either::First(never) => never,
+                Either::Second(()) => {}
+            }
+            requests.take()
+        }
+
+        #[test]
+        fn test_query() {
+            let requests = run(&["10.0.0.2"], [(2, &[Reply::Time])], |client| {
+                Box::pin(async move {
+                    let start = Instant::now();
+                    let sample = client.query().await.unwrap();
+                    assert_eq!(sample.server, IpAddress::Ipv4(server(2)));
+                    assert_eq!(sample.stratum, 2);
+                    assert!(sample.instant >= start && sample.instant <= Instant::now());
+                    assert!(sample.round_trip_delay <= sample.instant - start);
+
+                    // The server transmitted its response at T3, half the round trip before it was received.
+                    let t3 = UtcTime::from_unix_micros(UNIX_2024 * 1_000_000 + 15_625);
+                    assert_eq!(sample.utc, t3 + sample.round_trip_delay / 2);
+                })
+            });
+            assert_eq!(requests, [server(2)]);
+        }
+
+        #[test]
+        fn test_server_rotation() {
+            let requests = run(
+                &["10.0.0.2", "10.0.0.3", "10.0.0.4"],
+                [
+                    (2, &[Reply::Drop, Reply::Time]),
+                    (3, &[Reply::Kiss(b"DENY")]),
+                    (4, &[Reply::Time, Reply::Kiss(b"INIT"), Reply::Time]),
+                ],
+                |client| {
+                    Box::pin(async move {
+                        // Timeouts and unknown kiss codes move on to the next server.
+                        assert_eq!(client.query().await, Err(Error::Timeout));
+                        assert_eq!(client.query().await, Err(Error::KissOfDeath(KissCode::Deny)));
+                        assert_eq!(client.query().await.unwrap().server, IpAddress::Ipv4(server(4)));
+                        assert_eq!(
+                            client.query().await.unwrap_err(),
+                            Error::KissOfDeath(KissCode::Other(*b"INIT"))
+                        );
+                        // The server that denied service is skipped.
+                        assert_eq!(client.query().await.unwrap().server, IpAddress::Ipv4(server(2)));
+                        assert_eq!(client.query().await, Err(Error::Timeout));
+                        assert_eq!(client.query().await.unwrap().server, IpAddress::Ipv4(server(4)));
+                    })
+                },
+            );
+            assert_eq!(
+                requests,
+                [
+                    server(2),
+                    server(3),
+                    server(4),
+                    server(4),
+                    server(2),
+                    server(2),
+                    server(4)
+                ]
+            );
+        }
+
+        #[test]
+        fn test_no_server() {
+            let requests = run(
+                &["10.0.0.2", "10.0.0.3"],
+                [(2, &[Reply::Kiss(b"RSTR")]), (3, &[Reply::Kiss(b"DENY")])],
+                |client| {
+                    Box::pin(async move {
+                        assert_eq!(client.query().await, Err(Error::KissOfDeath(KissCode::Restrict)));
+                        assert_eq!(client.query().await, Err(Error::KissOfDeath(KissCode::Deny)));
+                        assert_eq!(client.query().await, Err(Error::NoServer));
+                    })
+                },
+            );
+            assert_eq!(requests, [server(2), server(3)]);
+
+            let requests = run(&[], [], |client| {
+                Box::pin(async move {
+                    assert_eq!(client.query().await, Err(Error::NoServer));
+                })
+            });
+            assert!(requests.is_empty());
+        }
+
+        #[test]
+        fn test_rate_backoff() {
+            let requests = run(
+                &["10.0.0.2", "10.0.0.3"],
+                [(2, &[Reply::Kiss(b"RATE"), Reply::Kiss(b"RATE"), Reply::Time])],
+                |client| {
+                    Box::pin(async move {
+                        // The client slows down, up to the maximum poll interval, but keeps the server.
+                        assert_eq!(client.query().await, Err(Error::KissOfDeath(KissCode::Rate)));
+                        assert_eq!(client.poll_interval(), Duration::from_secs(128));
+                        assert_eq!(client.query().await, Err(Error::KissOfDeath(KissCode::Rate)));
+                        assert_eq!(client.poll_interval(), Duration::from_secs(192));
+                        client.query().await.unwrap();
+                        assert_eq!(client.poll_interval(), Duration::from_secs(192));
+                    })
+                },
+            );
+            assert_eq!(requests, [server(2), server(2), server(2)]);
+        }
+    }
+}
@@ -310,6 +310,8 @@ impl<'d, U: UarteInstance, T: TimerInstance> BufferedUarte<'d, U, T> {
         unsafe { U::Interrupt::enable() };
 
         U::state().tx_rx_refcount.store(2, Ordering::Relaxed);
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
 
         Self { tx, rx }
     }
@@ -412,6 +414,8 @@ impl<'d, U: UarteInstance> BufferedUarteTx<'d, U> {
         unsafe { U::Interrupt::enable() };
 
         U::state().tx_rx_refcount.store(1, Ordering::Relaxed);
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
 
         this
     }
@@ -610,6 +614,8 @@ impl<'d, U: UarteInstance, T: TimerInstance> BufferedUarteRx<'d, U, T> {
         unsafe { U::Interrupt::enable() };
 
         U::state().tx_rx_refcount.store(1, Ordering::Relaxed);
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
 
         this
     }
@@ -73,6 +73,8 @@ pub mod buffered_uarte;
 pub mod gpio;
 #[cfg(feature = "gpiote")]
 pub mod gpiote;
+#[cfg(feature = "low-power")]
+pub mod low_power;
 
 // TODO: tested on other chips
 #[cfg(not(any(feature = "_nrf91", feature = "_nrf5340-app")))]
@@ -657,6 +659,17 @@ pub fn init(config: config::Config) -> Peripherals {
     #[cfg(feature = "_time-driver")]
     time_driver::init(config.time_interrupt_priority);
 
+    // init low-power executor. The HFXO can only be stopped when idle if the LFCLK isn't
+    // synthesized from it.
+    #[cfg(feature = "low-power")]
+    {
+        #[cfg(not(any(feature = "_nrf5340", feature = "_nrf91")))]
+        let lfclk_synth = matches!(config.lfclk_source, config::LfclkSource::Synthesized);
+        #[cfg(any(feature = "_nrf5340", feature = "_nrf91"))]
+        let lfclk_synth = false;
+        low_power::init(matches!(config.hfclk_source, config::HfclkSource::ExternalXtal) && !lfclk_synth);
+    }
+
     // Disable UARTE (enabled by default for some reason)
     #[cfg(feature = "_nrf91")]
     unsafe {
@@ -0,0 +1,255 @@
+//! Low-power support.
+//!
+//! nRF chips already enter the System ON idle state with `WFE`, and wake up from the RTC used by
+//! the time driver. `embassy-nrf` provides a low-power executor, [`Executor`], which further
+//! reduces the idle current by:
+//!
+//!  * stopping the high-frequency crystal oscillator (HFXO) while idle, if it was started by
+//!    [`init`](crate::init) with [`HfclkSource::ExternalXtal`](crate::config::HfclkSource::ExternalXtal).
+//!    It is restarted before any interrupt handler runs.
+//!  * entering System OFF when idle with no timer pending, if enabled with [`set_system_off`].
+//!
+//! The executor tracks which peripherals are enabled; consequently, these states can only be
+//! entered if all UARTE, SPIM, TWIM and SAADC drivers have been `drop`'d and the radio is
+//! disabled. Other peripherals request the HFCLK on their own, and run from the internal
+//! oscillator while the HFXO is stopped.
+//!
+//! Since restarting the HFXO incurs a significant latency, it is only stopped when the next timer
+//! event is at least [`MIN_STOP_PAUSE`] in the future.
+//!
+//! System OFF can only be left through a reset, typically by a GPIO `DETECT` signal, so program
+//! execution starts over at wakeup. Pins waited on with the `gpiote` driver's
+//! [`Input::wait_for_low`](crate::gpio::Input::wait_for_low) and similar methods have their
+//! `SENSE` configured, and act as wakeup sources.
+//!
+//! Currently there is no macro analogous to `embassy_executor::main` for this executor;
+//! consequently one must define their entrypoint manually. This will typically look like
+//!
+//! ```rust,no_run
+//! use embassy_executor::Spawner;
+//! use embassy_nrf::gpio::{Input, Pull};
+//! use embassy_nrf::low_power::Executor;
+//!
+//! #[cortex_m_rt::entry]
+//! fn main() -> ! {
+//!     Executor::take().run(|spawner| {
+//!         unwrap!(spawner.spawn(async_main(spawner)));
+//!     });
+//! }
+//!
+//! #[embassy_executor::task]
+//! async fn async_main(spawner: Spawner) {
+//!     let mut config = embassy_nrf::config::Config::default();
+//!     config.hfclk_source = embassy_nrf::config::HfclkSource::ExternalXtal;
+//!     let p = embassy_nrf::init(config);
+//!
+//!     // power off until the button is pressed...
+//!     embassy_nrf::low_power::set_system_off(true);
+//!     let mut button = Input::new(p.P0_11, Pull::Up);
+//!     button.wait_for_low().await;
+//! }
+//! ```
+
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+
+use cortex_m::peripheral::SCB;
+use embassy_executor::*;
+use embassy_time::Duration;
+
+use crate::{pac, time_driver};
+
+const THREAD_PENDER: usize = usize::MAX;
+
+/// The minimum pause time beyond which the executor will stop the HFXO.
+pub const MIN_STOP_PAUSE: Duration = Duration::from_millis(2);
+
+static mut EXECUTOR: Option<Executor> = None;
+
+static mut REFCOUNT: u32 = 0;
+
+static HFXO: AtomicBool = AtomicBool::new(false);
+static SYSTEM_OFF: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn init(hfxo: bool) {
+    HFXO.store(hfxo, Ordering::Relaxed);
+}
+
+pub(crate) fn on_enable() {
+    critical_section::with(|_| unsafe { REFCOUNT += 1 });
+}
+
+pub(crate) fn on_disable() {
+    critical_section::with(|_| unsafe { REFCOUNT -= 1 });
+}
+
+fn peripherals_idle() -> bool {
+    #[cfg(not(any(feature = "_nrf91", feature = "_nrf5340-app")))]
+    if crate::radio::state(unsafe { &*pac::RADIO::ptr() }) != pac::radio::state::STATE_A::DISABLED {
+        return false;
+    }
+    critical_section::with(|_| unsafe { REFCOUNT == 0 })
+}
+
+/// Allow or disallow entering System OFF when idle with no timer pending.
+///
+/// A wakeup source must be configured before enabling this, otherwise the chip will only wake
+/// up on reset.
+pub fn set_system_off(enabled: bool) {
+    SYSTEM_OFF.store(enabled, Ordering::Relaxed);
+}
+
+/// Get whether the chip is ready to enter the given low-power mode.
+///
+/// This will return false if some peripheral driver is in use that prevents entering the given
+/// mode.
+pub fn stop_ready(mode: StopMode) -> bool {
+    match mode {
+        StopMode::HfxoOff => peripherals_idle() && HFXO.load(Ordering::Relaxed),
+        StopMode::SystemOff => peripherals_idle() && SYSTEM_OFF.load(Ordering::Relaxed),
+    }
+}
+
+/// Available low-power modes.
+#[non_exhaustive]
+#[derive(PartialEq)]
+pub enum StopMode {
+    /// System ON idle, with the HFXO stopped.
+    HfxoOff,
+    /// System OFF.
+    SystemOff,
+}
+
+/// Enter System OFF.
+///
+/// All peripherals and RAM retention settings are left as they are. Execution starts over from
+/// reset when a wakeup source triggers.
+#[cfg(not(feature = "_nrf5340-net"))]
+pub fn system_off() -> ! {
+    trace!("low power: system off");
+
+    #[cfg(not(any(feature = "_nrf5340", feature = "_nrf91")))]
+    unsafe { &*pac::POWER::ptr() }
+        .systemoff
+        .write(|w| w.systemoff().enter());
+    #[cfg(any(feature = "_nrf5340-app", feature = "_nrf91"))]
+    unsafe { &*pac::REGULATORS::ptr() }
+        .systemoff
+        .write(|w| w.systemoff().enter());
+
+    // System OFF is emulated while a debugger is attached, and execution continues.
+    loop {
+        cortex_m::asm::wfe();
+    }
+}
+
+/// Thread mode executor, using WFE/SEV.
+///
+/// This is the simplest and most common kind of executor. It runs on
+/// thread mode (at the lowest priority level), and uses the `WFE` ARM instruction
+/// to sleep when it has no more work to do. When a task is woken, a `SEV` instruction
+/// is executed, to make the `WFE` exit from sleep and poll the task.
+///
+/// When idle, this executor additionally stops the HFXO or enters System OFF if the enabled
+/// peripherals and pending timers allow it. See the [module documentation](self) for details.
+pub struct Executor {
+    inner: raw::Executor,
+    not_send: PhantomData<*mut ()>,
+}
+
+impl Executor {
+    /// Create a new Executor.
+    pub fn take() -> &'static mut Self {
+        critical_section::with(|_| unsafe {
+            assert!(EXECUTOR.is_none());
+
+            // Let interrupts wake up WFE while they are masked, so that the HFXO can be
+            // restarted before their handlers run.
+            let mut scb: SCB = cortex_m::Peripherals::steal().SCB;
+            scb.set_sevonpend();
+
+            EXECUTOR = Some(Self {
+                inner: raw::Executor::new(THREAD_PENDER as *mut ()),
+                not_send: PhantomData,
+            });
+
+            EXECUTOR.as_mut().unwrap()
+        })
+    }
+
+    fn stop_mode(&self) -> Option<StopMode> {
+        if !peripherals_idle() {
+            return None;
+        }
+
+        let next_alarm = time_driver::next_alarm();
+        #[cfg(not(feature = "_nrf5340-net"))]
+        if next_alarm == u64::MAX && SYSTEM_OFF.load(Ordering::Relaxed) {
+            return Some(StopMode::SystemOff);
+        }
+
+        let now = time_driver::now();
+        if HFXO.load(Ordering::Relaxed) && next_alarm.saturating_sub(now) >= MIN_STOP_PAUSE.as_ticks() {
+            Some(StopMode::HfxoOff)
+        } else {
+            None
+        }
+    }
+
+    fn idle(&mut self) {
+        compiler_fence(Ordering::SeqCst);
+
+        // Interrupts stay masked until the HFXO is running again. Pending interrupts still wake
+        // up WFE, and run when unmasked.
+        cortex_m::interrupt::disable();
+
+        match self.stop_mode() {
+            #[cfg(not(feature = "_nrf5340-net"))]
+            Some(StopMode::SystemOff) => system_off(),
+            Some(StopMode::HfxoOff) => {
+                let r = unsafe { &*pac::CLOCK::ptr() };
+
+                trace!("low power: hfxo off");
+                r.tasks_hfclkstop.write(|w| unsafe { w.bits(1) });
+                cortex_m::asm::wfe();
+
+                r.events_hfclkstarted.write(|w| unsafe { w.bits(0) });
+                r.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+                while r.events_hfclkstarted.read().bits() == 0 {}
+                trace!("low power: resume");
+            }
+            _ => cortex_m::asm::wfe(),
+        }
+
+        unsafe { cortex_m::interrupt::enable() };
+    }
+
+    /// Run the executor.
+    ///
+    /// The `init` closure is called with a [`Spawner`] that spawns tasks on
+    /// this executor. Use it to spawn the initial task(s). After `init` returns,
+    /// the executor starts running the tasks.
+    ///
+    /// To spawn more tasks later, you may keep copies of the [`Spawner`] (it is `Copy`),
+    /// for example by passing it as an argument to the initial tasks.
+    ///
+    /// This function requires `&'static mut self`. This means you have to store the
+    /// Executor instance in a place where it'll live forever and grants you mutable
+    /// access. There's a few ways to do this:
+    ///
+    /// - a [StaticCell](https://docs.rs/static_cell/latest/static_cell/) (safe)
+    /// - a `static mut` (unsafe)
+    /// - a local variable in a function you know never returns (like `fn main() -> !`), upgrading its lifetime with `transmute`. (unsafe)
+    ///
+    /// This function never returns.
+    pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
+        init(unsafe { EXECUTOR.as_mut().unwrap() }.inner.spawner());
+
+        loop {
+            unsafe {
+                EXECUTOR.as_mut().unwrap().inner.poll();
+                self.idle();
+            };
+        }
+    }
+}
@@ -155,6 +155,8 @@ impl<'d, const N: usize> Saadc<'d, N> {
 
         // Configure channels
         r.enable.write(|w| w.enable().enabled());
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
         r.resolution.write(|w| w.val().variant(resolution.into()));
         r.oversample.write(|w| w.oversample().variant(oversample.into()));
 
@@ -481,6 +483,8 @@ impl<'d, const N: usize> Drop for Saadc<'d, N> {
     fn drop(&mut self) {
         let r = Self::regs();
         r.enable.write(|w| w.enable().disabled());
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_disable();
     }
 }
 
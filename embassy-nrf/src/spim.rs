@@ -211,6 +211,8 @@ impl<'d, T: Instance> Spim<'d, T> {
 
         // Enable SPIM instance.
         r.enable.write(|w| w.enable().enabled());
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
 
         let mut spim = Self { _p: spim };
 
@@ -475,6 +477,8 @@ impl<'d, T: Instance> Drop for Spim<'d, T> {
         // disable!
         let r = T::regs();
         r.enable.write(|w| w.enable().disabled());
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_disable();
 
         gpio::deconfigure_pin(r.psel.sck.read().bits());
         gpio::deconfigure_pin(r.psel.miso.read().bits());
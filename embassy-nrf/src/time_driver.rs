@@ -299,6 +299,21 @@ fn RTC1() {
     DRIVER.on_interrupt()
 }
 
+/// Timestamp of the earliest pending alarm, or `u64::MAX` if there is none.
+#[cfg(feature = "low-power")]
+pub(crate) fn next_alarm() -> u64 {
+    critical_section::with(|cs| {
+        let alarms = DRIVER.alarms.borrow(cs);
+        alarms.iter().map(|a| a.timestamp.get()).min().unwrap_or(u64::MAX)
+    })
+}
+
+/// Current timestamp, in ticks.
+#[cfg(feature = "low-power")]
+pub(crate) fn now() -> u64 {
+    DRIVER.now()
+}
+
 pub(crate) fn init(irq_prio: crate::interrupt::Priority) {
     DRIVER.init(irq_prio)
 }
@@ -166,6 +166,8 @@ impl<'d, T: Instance> Twim<'d, T> {
 
         // Enable TWIM instance.
         r.enable.write(|w| w.enable().enabled());
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
 
         let mut twim = Self { _p: twim };
 
@@ -719,6 +721,8 @@ impl<'a, T: Instance> Drop for Twim<'a, T> {
         // disable!
         let r = T::regs();
         r.enable.write(|w| w.enable().disabled());
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_disable();
 
         gpio::deconfigure_pin(r.psel.sda.read().bits());
         gpio::deconfigure_pin(r.psel.scl.read().bits());
@@ -225,6 +225,8 @@ impl<'d, T: Instance> Uarte<'d, T> {
 
         let s = T::state();
         s.tx_rx_refcount.store(2, Ordering::Relaxed);
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
 
         Self {
             tx: UarteTx {
@@ -372,6 +374,8 @@ impl<'d, T: Instance> UarteTx<'d, T> {
 
         let s = T::state();
         s.tx_rx_refcount.store(1, Ordering::Relaxed);
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
 
         Self { _p: uarte }
     }
@@ -571,6 +575,8 @@ impl<'d, T: Instance> UarteRx<'d, T> {
 
         let s = T::state();
         s.tx_rx_refcount.store(1, Ordering::Relaxed);
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_enable();
 
         Self { _p: uarte }
     }
@@ -945,6 +951,8 @@ pub(crate) fn drop_tx_rx(r: &pac::uarte0::RegisterBlock, s: &State) {
         // Finally we can disable, and we do so for the peripheral
         // i.e. not just rx concerns.
         r.enable.write(|w| w.enable().disabled());
+        #[cfg(feature = "low-power")]
+        crate::low_power::on_disable();
 
         gpio::deconfigure_pin(r.psel.rxd.read().bits());
         gpio::deconfigure_pin(r.psel.txd.read().bits());
@@ -23,12 +23,15 @@ mod float;
 pub mod gpio;
 pub mod i2c;
 pub mod i2c_slave;
+#[cfg(feature = "low-power")]
+pub mod low_power;
 pub mod multicore;
 pub mod pwm;
 mod reset;
 pub mod rom_data;
 pub mod rtc;
 pub mod spi;
+pub mod spi_slave;
 #[cfg(feature = "time-driver")]
 pub mod time_driver;
 pub mod uart;
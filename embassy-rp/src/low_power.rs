@@ -0,0 +1,278 @@
+//! Low-power support.
+//!
+//! The RP2040 has two low-power states beyond simply halting the processors with `WFE`:
+//!
+//!  * **Sleep**: both processors are in deep sleep, and only the clocks enabled in the
+//!    `CLOCKS.SLEEP_EN` registers keep running. Any interrupt wakes the system.
+//!  * **Dormant**: all oscillators are stopped. Only a GPIO dormant-wake event, or an RTC alarm if
+//!    `clk_rtc` is fed from an external clock, can wake the system.
+//!
+//! `embassy-rp` provides a low-power executor, [`Executor`], which uses knowledge of which
+//! peripherals are currently active to transparently and safely enter these states when idle.
+//!
+//! The executor determines which peripherals are active by their enable state in hardware;
+//! consequently, low-power states can only be entered while no UART, SPI, I2C, PWM, ADC, USB or PIO
+//! peripheral is enabled and no DMA transfer is in progress. GPIO, the RTC and the timer
+//! do not prevent entering low-power states.
+//!
+//! When idle with no active peripherals, and the next timer event is at least [`MIN_SLEEP_PAUSE`]
+//! in the future, the executor enters the sleep state. If `clk_sys` runs from `PLL_SYS` and `clk_ref`
+//! from the crystal oscillator, `clk_sys` is switched to `clk_ref` and `PLL_SYS` is powered down for
+//! the duration of the sleep. The clock tree is restored before any interrupt handler runs.
+//!
+//! The dormant state stops the timer, so it is only entered when enabled with [`set_dormant`] and no
+//! timer is pending at all. The clock tree is restored by [`clocks::dormant_sleep`] on wakeup. A
+//! wakeup source must be configured beforehand, typically with
+//! [`Input::dormant_wake`](crate::gpio::Input::dormant_wake).
+//!
+//! Currently there is no macro analogous to `embassy_executor::main` for this executor;
+//! consequently one must define their entrypoint manually. The executor only runs on core 0, and
+//! the sleep state is only entered while core 1 is also in deep sleep. This will typically look
+//! like
+//!
+//! ```rust,no_run
+//! use embassy_executor::Spawner;
+//! use embassy_rp::gpio::{DormantWakeConfig, Input, Pull};
+//! use embassy_rp::low_power::Executor;
+//!
+//! #[cortex_m_rt::entry]
+//! fn main() -> ! {
+//!     Executor::take().run(|spawner| {
+//!         unwrap!(spawner.spawn(async_main(spawner)));
+//!     });
+//! }
+//!
+//! #[embassy_executor::task]
+//! async fn async_main(spawner: Spawner) {
+//!     let p = embassy_rp::init(Default::default());
+//!
+//!     // wake up from dormant when the button is pressed...
+//!     let mut button = Input::new(p.PIN_15, Pull::Up);
+//!     let _wake = button.dormant_wake(DormantWakeConfig {
+//!         edge_low: true,
+//!         ..Default::default()
+//!     });
+//!     embassy_rp::low_power::set_dormant(true);
+//!
+//!     // your application here...
+//! }
+//! ```
+
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+
+use cortex_m::peripheral::SCB;
+use embassy_executor::*;
+use embassy_time::Duration;
+
+use crate::pac::clocks::vals::{ClkRefCtrlSrc, ClkRtcCtrlAuxsrc, ClkSysCtrlAuxsrc, ClkSysCtrlSrc};
+use crate::{clocks, pac, time_driver};
+
+const THREAD_PENDER: usize = usize::MAX;
+
+/// The minimum pause time beyond which the executor will enter the sleep state.
+pub const MIN_SLEEP_PAUSE: Duration = Duration::from_millis(1);
+
+static mut EXECUTOR: Option<Executor> = None;
+
+static DORMANT: AtomicBool = AtomicBool::new(false);
+
+/// Allow or disallow entering the dormant state when idle with no timer pending.
+///
+/// A dormant-wake source must be configured before enabling this, otherwise the chip will only
+/// wake up on reset.
+pub fn set_dormant(enabled: bool) {
+    DORMANT.store(enabled, Ordering::Relaxed);
+}
+
+/// Get whether the chip is ready to enter the given low-power mode.
+///
+/// This will return false if some peripheral is active that prevents entering the given mode.
+pub fn sleep_ready(mode: SleepMode) -> bool {
+    match mode {
+        SleepMode::Sleep => peripherals_idle(),
+        SleepMode::Dormant => peripherals_idle() && DORMANT.load(Ordering::Relaxed),
+    }
+}
+
+/// Available low-power modes.
+#[non_exhaustive]
+#[derive(PartialEq)]
+pub enum SleepMode {
+    /// Sleep, with most clocks gated.
+    Sleep,
+    /// Dormant, with all oscillators stopped.
+    Dormant,
+}
+
+fn peripherals_idle() -> bool {
+    let uart = [pac::UART0, pac::UART1].iter().any(|r| r.uartcr().read().uarten());
+    let spi = [pac::SPI0, pac::SPI1].iter().any(|r| r.sspcr1().read().sse());
+    let i2c = [pac::I2C0, pac::I2C1].iter().any(|r| r.ic_enable().read().enable());
+    let pio = [pac::PIO0, pac::PIO1].iter().any(|r| r.ctrl().read().sm_enable() != 0);
+    let pwm = pac::PWM.en().read().0 != 0;
+    let adc = pac::ADC.cs().read().en();
+    let usb = pac::USBCTRL_REGS.main_ctrl().read().controller_en();
+    let dma = (0..12).any(|n| pac::DMA.ch(n).ctrl_trig().read().busy());
+
+    !(uart || spi || i2c || pio || pwm || adc || usb || dma)
+}
+
+/// Thread mode executor, using WFE/SEV.
+///
+/// This is the simplest and most common kind of executor. It runs on
+/// thread mode (at the lowest priority level), and uses the `WFE` ARM instruction
+/// to sleep when it has no more work to do. When a task is woken, a `SEV` instruction
+/// is executed, to make the `WFE` exit from sleep and poll the task.
+///
+/// When idle, this executor additionally enters the sleep or dormant state if the active
+/// peripherals and pending timers allow it. See the [module documentation](self) for details.
+pub struct Executor {
+    inner: raw::Executor,
+    not_send: PhantomData<*mut ()>,
+    scb: SCB,
+}
+
+impl Executor {
+    /// Create a new Executor.
+    pub fn take() -> &'static mut Self {
+        critical_section::with(|_| unsafe {
+            assert!(EXECUTOR.is_none());
+
+            let mut scb = cortex_m::Peripherals::steal().SCB;
+            // Let interrupts wake up WFE while they are masked, so that the clock tree can be
+            // restored before their handlers run.
+            scb.set_sevonpend();
+
+            EXECUTOR = Some(Self {
+                inner: raw::Executor::new(THREAD_PENDER as *mut ()),
+                not_send: PhantomData,
+                scb,
+            });
+
+            EXECUTOR.as_mut().unwrap()
+        })
+    }
+
+    fn sleep_mode(&self) -> Option<SleepMode> {
+        if !peripherals_idle() {
+            return None;
+        }
+
+        let next_alarm = time_driver::next_alarm();
+        if next_alarm == u64::MAX && DORMANT.load(Ordering::Relaxed) {
+            return Some(SleepMode::Dormant);
+        }
+
+        let now = time_driver::now();
+        if next_alarm.saturating_sub(now) >= MIN_SLEEP_PAUSE.as_ticks() {
+            Some(SleepMode::Sleep)
+        } else {
+            None
+        }
+    }
+
+    fn sleep(&mut self) {
+        let c = pac::CLOCKS;
+
+        let sleep_en = (c.sleep_en0().read(), c.sleep_en1().read());
+        c.sleep_en0().write(|w| {
+            w.set_clk_sys_io(true);
+            w.set_clk_sys_pads(true);
+            w.set_clk_sys_rtc(true);
+            w.set_clk_rtc_rtc(true);
+        });
+        c.sleep_en1().write(|w| {
+            w.set_clk_sys_timer(true);
+            w.set_clk_sys_watchdog(true);
+        });
+
+        // Run clk_sys from the crystal while asleep, unless something else needs PLL_SYS.
+        let stop_pll_sys = c.clk_sys_ctrl().read().src() == ClkSysCtrlSrc::CLKSRC_CLK_SYS_AUX
+            && c.clk_sys_ctrl().read().auxsrc() == ClkSysCtrlAuxsrc::CLKSRC_PLL_SYS
+            && c.clk_ref_ctrl().read().src() == ClkRefCtrlSrc::XOSC_CLKSRC
+            && !(c.clk_rtc_ctrl().read().enable()
+                && c.clk_rtc_ctrl().read().auxsrc() == ClkRtcCtrlAuxsrc::CLKSRC_PLL_SYS);
+
+        if stop_pll_sys {
+            c.clk_sys_ctrl().modify(|w| w.set_src(ClkSysCtrlSrc::CLK_REF));
+            while c.clk_sys_selected().read() != 1 {}
+            pac::PLL_SYS.pwr().modify(|w| {
+                w.set_pd(true);
+                w.set_vcopd(true);
+            });
+        }
+
+        trace!("low power: sleep");
+        self.scb.set_sleepdeep();
+        cortex_m::asm::wfe();
+        self.scb.clear_sleepdeep();
+
+        if stop_pll_sys {
+            pac::PLL_SYS.pwr().modify(|w| {
+                w.set_pd(false);
+                w.set_vcopd(false);
+            });
+            while !pac::PLL_SYS.cs().read().lock() {}
+            c.clk_sys_ctrl()
+                .modify(|w| w.set_src(ClkSysCtrlSrc::CLKSRC_CLK_SYS_AUX));
+            while c.clk_sys_selected().read() != 2 {}
+        }
+
+        c.sleep_en0().write_value(sleep_en.0);
+        c.sleep_en1().write_value(sleep_en.1);
+        trace!("low power: resume");
+    }
+
+    fn idle(&mut self) {
+        compiler_fence(Ordering::SeqCst);
+
+        // Interrupts stay masked until the clock tree is restored. Pending interrupts still wake
+        // up WFE, and run when unmasked.
+        cortex_m::interrupt::disable();
+
+        match self.sleep_mode() {
+            Some(SleepMode::Sleep) => self.sleep(),
+            Some(SleepMode::Dormant) => {
+                trace!("low power: dormant");
+                clocks::dormant_sleep();
+                trace!("low power: resume");
+            }
+            None => {
+                trace!("low power: not ready to sleep");
+                cortex_m::asm::wfe();
+            }
+        }
+
+        unsafe { cortex_m::interrupt::enable() };
+    }
+
+    /// Run the executor.
+    ///
+    /// The `init` closure is called with a [`Spawner`] that spawns tasks on
+    /// this executor. Use it to spawn the initial task(s). After `init` returns,
+    /// the executor starts running the tasks.
+    ///
+    /// To spawn more tasks later, you may keep copies of the [`Spawner`] (it is `Copy`),
+    /// for example by passing it as an argument to the initial tasks.
+    ///
+    /// This function requires `&'static mut self`. This means you have to store the
+    /// Executor instance in a place where it'll live forever and grants you mutable
+    /// access. There's a few ways to do this:
+    ///
+    /// - a [StaticCell](https://docs.rs/static_cell/latest/static_cell/) (safe)
+    /// - a `static mut` (unsafe)
+    /// - a local variable in a function you know never returns (like `fn main() -> !`), upgrading its lifetime with `transmute`. (unsafe)
+    ///
+    /// This function never returns.
+    pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
+        init(unsafe { EXECUTOR.as_mut().unwrap() }.inner.spawner());
+
+        loop {
+            unsafe {
+                EXECUTOR.as_mut().unwrap().inner.poll();
+                self.idle();
+            };
+        }
+    }
+}
@@ -441,11 +441,12 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
 
 trait SealedMode {}
 
-trait SealedInstance {
+pub(crate) trait SealedInstance {
     const TX_DREQ: u8;
     const RX_DREQ: u8;
 
     fn regs(&self) -> pac::spi::Spi;
+    fn reset() -> pac::resets::regs::Peripherals;
 }
 
 /// Mode.
@@ -457,7 +458,7 @@ pub trait Mode: SealedMode {}
 pub trait Instance: SealedInstance {}
 
 macro_rules! impl_instance {
-    ($type:ident, $irq:ident, $tx_dreq:expr, $rx_dreq:expr) => {
+    ($type:ident, $irq:ident, $tx_dreq:expr, $rx_dreq:expr, $reset:ident) => {
         impl SealedInstance for peripherals::$type {
             const TX_DREQ: u8 = $tx_dreq;
             const RX_DREQ: u8 = $rx_dreq;
@@ -465,13 +466,19 @@ macro_rules! impl_instance {
             fn regs(&self) -> pac::spi::Spi {
                 pac::$type
             }
+
+            fn reset() -> pac::resets::regs::Peripherals {
+                let mut ret = pac::resets::regs::Peripherals::default();
+                ret.$reset(true);
+                ret
+            }
         }
         impl Instance for peripherals::$type {}
     };
 }
 
-impl_instance!(SPI0, Spi0, 16, 17);
-impl_instance!(SPI1, Spi1, 18, 19);
+impl_instance!(SPI0, Spi0, 16, 17, set_spi0);
+impl_instance!(SPI1, Spi1, 18, 19, set_spi1);
 
 /// CLK pin.
 pub trait ClkPin<T: Instance>: GpioPin {}
@@ -0,0 +1,338 @@
+//! SPI slave driver.
+//!
+//! The API mirrors `embassy_nrf::spis`: each call to [`SpiSlave::transfer`] (or one of its variants) arms
+//! DMA with the buffers for exactly one transaction, which ends when the host deasserts CS. The TX
+//! buffer is loaded before the host starts the transaction, so the first byte clocked out is the
+//! first byte of `write`.
+//!
+//! The SPI peripheral must run at least 12 times faster than the host's SCK, so `clk_peri` limits the
+//! bus speed.
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use embassy_embedded_hal::SetConfig;
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, PeripheralRef};
+pub use embedded_hal_02::spi::{Phase, Polarity};
+
+use crate::dma::{AnyChannel, Channel};
+use crate::gpio::{AnyPin, InputFuture, InterruptTrigger, SealedPin as _};
+use crate::spi::{ClkPin, CsPin, Instance, MisoPin, MosiPin};
+use crate::{pac, Peripheral};
+
+/// SPI slave errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    // No errors for now
+}
+
+/// SPI slave configuration.
+#[non_exhaustive]
+#[derive(Clone)]
+pub struct Config {
+    /// Phase.
+    ///
+    /// With [`Phase::CaptureOnFirstTransition`], the peripheral requires CS to be deasserted between
+    /// bytes, so every byte is a transaction of its own. Use [`Phase::CaptureOnSecondTransition`] for
+    /// multi-byte transactions.
+    pub phase: Phase,
+    /// Polarity.
+    pub polarity: Polarity,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            phase: Phase::CaptureOnSecondTransition,
+            polarity: Polarity::IdleLow,
+        }
+    }
+}
+
+/// SPI slave driver.
+pub struct SpiSlave<'d, T: Instance> {
+    inner: PeripheralRef<'d, T>,
+    clk: PeripheralRef<'d, AnyPin>,
+    mosi: Option<PeripheralRef<'d, AnyPin>>,
+    miso: Option<PeripheralRef<'d, AnyPin>>,
+    cs: PeripheralRef<'d, AnyPin>,
+    tx_dma: PeripheralRef<'d, AnyChannel>,
+    rx_dma: PeripheralRef<'d, AnyChannel>,
+    config: Config,
+    overflow: bool,
+    overread: bool,
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> SpiSlave<'d, T> {
+    /// Create a new SPI slave driver.
+    pub fn new(
+        inner: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T> + 'd> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T> + 'd> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T> + 'd> + 'd,
+        cs: impl Peripheral<P = impl CsPin<T> + 'd> + 'd,
+        tx_dma: impl Peripheral<P = impl Channel> + 'd,
+        rx_dma: impl Peripheral<P = impl Channel> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(clk, mosi, miso);
+        Self::new_inner(
+            inner,
+            clk.map_into(),
+            Some(mosi.map_into()),
+            Some(miso.map_into()),
+            cs,
+            tx_dma,
+            rx_dma,
+            config,
+        )
+    }
+
+    /// Create a new SPI slave driver, capable of TX only (MISO only).
+    pub fn new_txonly(
+        inner: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T> + 'd> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T> + 'd> + 'd,
+        cs: impl Peripheral<P = impl CsPin<T> + 'd> + 'd,
+        tx_dma: impl Peripheral<P = impl Channel> + 'd,
+        rx_dma: impl Peripheral<P = impl Channel> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(clk, miso);
+        Self::new_inner(
+            inner,
+            clk.map_into(),
+            None,
+            Some(miso.map_into()),
+            cs,
+            tx_dma,
+            rx_dma,
+            config,
+        )
+    }
+
+    /// Create a new SPI slave driver, capable of RX only (MOSI only).
+    pub fn new_rxonly(
+        inner: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T> + 'd> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T> + 'd> + 'd,
+        cs: impl Peripheral<P = impl CsPin<T> + 'd> + 'd,
+        tx_dma: impl Peripheral<P = impl Channel> + 'd,
+        rx_dma: impl Peripheral<P = impl Channel> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(clk, mosi);
+        Self::new_inner(
+            inner,
+            clk.map_into(),
+            Some(mosi.map_into()),
+            None,
+            cs,
+            tx_dma,
+            rx_dma,
+            config,
+        )
+    }
+
+    fn new_inner(
+        inner: impl Peripheral<P = T> + 'd,
+        clk: PeripheralRef<'d, AnyPin>,
+        mosi: Option<PeripheralRef<'d, AnyPin>>,
+        miso: Option<PeripheralRef<'d, AnyPin>>,
+        cs: impl Peripheral<P = impl CsPin<T> + 'd> + 'd,
+        tx_dma: impl Peripheral<P = impl Channel> + 'd,
+        rx_dma: impl Peripheral<P = impl Channel> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(inner, cs, tx_dma, rx_dma);
+
+        reset_and_configure::<T>(inner.regs(), &config);
+
+        clk.gpio().ctrl().write(|w| w.set_funcsel(1));
+        if let Some(pin) = &mosi {
+            pin.gpio().ctrl().write(|w| w.set_funcsel(1));
+        }
+        if let Some(pin) = &miso {
+            pin.gpio().ctrl().write(|w| w.set_funcsel(1));
+        }
+        cs.gpio().ctrl().write(|w| w.set_funcsel(1));
+
+        Self {
+            inner,
+            clk,
+            mosi,
+            miso,
+            cs: cs.map_into(),
+            tx_dma: tx_dma.map_into(),
+            rx_dma: rx_dma.map_into(),
+            config,
+            overflow: false,
+            overread: false,
+            phantom: PhantomData,
+        }
+    }
+
+    async fn transfer_inner(&mut self, rx: *mut [u8], tx: *const [u8]) -> Result<(usize, usize), Error> {
+        let p = self.inner.regs();
+        let cs_in = self.cs.sio_in();
+        let cs_bit = 1 << self.cs._pin();
+
+        // Don't join a transaction that is already in progress, since its start was missed.
+        {
+            let idle = InputFuture::new(self.cs.reborrow(), InterruptTrigger::EdgeHigh);
+            if cs_in.read() & cs_bit == 0 {
+                idle.await;
+            }
+        }
+        let end = InputFuture::new(self.cs.reborrow(), InterruptTrigger::EdgeHigh);
+
+        // Data left in the TX FIFO by the previous transaction can only be discarded by a reset.
+        reset_and_configure::<T>(p, &self.config);
+
+        let on_drop = OnDrop::new(|| {
+            p.cr1().write(|w| w.set_sse(false));
+        });
+
+        let rx_ch = self.rx_dma.regs();
+        let tx_ch = self.tx_dma.regs();
+        let rx_transfer = match rx.len() {
+            0 => None,
+            _ => Some(unsafe { crate::dma::read(&mut self.rx_dma, p.dr().as_ptr() as *const _, rx, T::RX_DREQ) }),
+        };
+        let tx_transfer = match tx.len() {
+            0 => None,
+            _ => Some(unsafe { crate::dma::write(&mut self.tx_dma, tx, p.dr().as_ptr() as *mut _, T::TX_DREQ) }),
+        };
+
+        p.cr1().write(|w| {
+            w.set_ms(true);
+            w.set_sse(true);
+        });
+
+        end.await;
+
+        // Let the DMA catch up with the last byte.
+        if rx_transfer.is_some() {
+            while rx_ch.ctrl_trig().read().busy() && p.sr().read().rne() {}
+        }
+
+        let rx_remaining = match rx_transfer {
+            Some(_) => rx_ch.trans_count().read() as usize,
+            None => 0,
+        };
+        let tx_remaining = match tx_transfer {
+            Some(_) => tx_ch.trans_count().read() as usize,
+            None => 0,
+        };
+        let n_rx = rx.len() - rx_remaining;
+        self.overflow = rx_remaining == 0 && (p.sr().read().rne() || p.ris().read().rorris());
+        let tx_empty = p.sr().read().tfe();
+
+        drop(rx_transfer);
+        drop(tx_transfer);
+        drop(on_drop);
+
+        compiler_fence(Ordering::SeqCst);
+
+        // Every byte received means one byte was sent. Past the end of `rx` that count is lost, and the
+        // TX side is used instead.
+        let n_tx = match self.overflow {
+            false => n_rx.min(tx.len()),
+            true => tx.len() - tx_remaining,
+        };
+        self.overread = match self.overflow {
+            false => n_rx > tx.len(),
+            true => tx_remaining == 0 && tx_empty,
+        };
+
+        Ok((n_rx, n_tx))
+    }
+
+    /// Reads data from the SPI bus without sending anything.
+    /// Returns number of bytes read.
+    pub async fn read(&mut self, data: &mut [u8]) -> Result<usize, Error> {
+        self.transfer_inner(data, &[]).await.map(|n| n.0)
+    }
+
+    /// Simultaneously sends and receives data.
+    /// Returns number of bytes transferred `(n_rx, n_tx)`.
+    ///
+    /// The transaction ends when the host deasserts CS. If the host clocks more bytes than fit in
+    /// `read`, `n_tx` may also count bytes that were loaded into the peripheral but not sent.
+    pub async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(usize, usize), Error> {
+        self.transfer_inner(read, write).await
+    }
+
+    /// Simultaneously sends and receives data. Places the received data into the same buffer.
+    /// Returns number of bytes transferred.
+    pub async fn transfer_in_place(&mut self, data: &mut [u8]) -> Result<usize, Error> {
+        self.transfer_inner(data, data).await.map(|n| n.0)
+    }
+
+    /// Sends data, discarding any received data.
+    /// Returns number of bytes written.
+    pub async fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.transfer_inner(&mut [], data).await.map(|n| n.1)
+    }
+
+    /// Checks if last transaction overread, i.e. the host clocked more bytes than there were in the
+    /// write buffer.
+    ///
+    /// What's sent on MISO after the end of the write buffer is undefined.
+    pub fn is_overread(&mut self) -> bool {
+        self.overread
+    }
+
+    /// Checks if last transaction overflowed, i.e. the host sent more bytes than fit in the read buffer.
+    pub fn is_overflow(&mut self) -> bool {
+        self.overflow
+    }
+}
+
+fn reset_and_configure<T: Instance>(p: pac::spi::Spi, config: &Config) {
+    let reset = T::reset();
+    crate::reset::reset(reset);
+    crate::reset::unreset_wait(reset);
+
+    // The slave still needs a prescaler, as clk_peri must be at least 12 times the bus clock.
+    p.cpsr().write(|w| w.set_cpsdvsr(2));
+    p.cr0().write(|w| {
+        w.set_dss(0b0111); // 8bit
+        w.set_spo(config.polarity == Polarity::IdleHigh);
+        w.set_sph(config.phase == Phase::CaptureOnSecondTransition);
+        w.set_scr(0);
+    });
+    p.dmacr().write(|reg| {
+        reg.set_rxdmae(true);
+        reg.set_txdmae(true);
+    });
+    // Slave mode must be selected while the peripheral is disabled.
+    p.cr1().write(|w| w.set_ms(true));
+}
+
+impl<'d, T: Instance> Drop for SpiSlave<'d, T> {
+    fn drop(&mut self) {
+        self.inner.regs().cr1().write(|w| w.set_sse(false));
+        self.clk.gpio().ctrl().write(|w| w.set_funcsel(31));
+        if let Some(pin) = &self.mosi {
+            pin.gpio().ctrl().write(|w| w.set_funcsel(31));
+        }
+        if let Some(pin) = &self.miso {
+            pin.gpio().ctrl().write(|w| w.set_funcsel(31));
+        }
+        self.cs.gpio().ctrl().write(|w| w.set_funcsel(31));
+    }
+}
+
+impl<'d, T: Instance> SetConfig for SpiSlave<'d, T> {
+    type Config = Config;
+    type ConfigError = ();
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), ()> {
+        self.config = config.clone();
+        reset_and_configure::<T>(self.inner.regs(), &self.config);
+        Ok(())
+    }
+}
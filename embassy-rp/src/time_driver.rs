@@ -18,7 +18,7 @@ unsafe impl Send for AlarmState {}
 
 const ALARM_COUNT: usize = 4;
 const DUMMY_ALARM: AlarmState = AlarmState {
-    timestamp: Cell::new(0),
+    timestamp: Cell::new(u64::MAX),
     callback: Cell::new(None),
 };
 
@@ -127,6 +127,26 @@ impl TimerDriver {
     }
 }
 
+/// Timestamp of the earliest pending alarm, or `u64::MAX` if there is none.
+#[cfg(feature = "low-power")]
+pub(crate) fn next_alarm() -> u64 {
+    let allocated = DRIVER.next_alarm.load(Ordering::Acquire) as usize;
+    critical_section::with(|cs| {
+        let alarms = DRIVER.alarms.borrow(cs);
+        alarms[..allocated]
+            .iter()
+            .map(|a| a.timestamp.get())
+            .min()
+            .unwrap_or(u64::MAX)
+    })
+}
+
+/// Current timestamp, in ticks.
+#[cfg(feature = "low-power")]
+pub(crate) fn now() -> u64 {
+    DRIVER.now()
+}
+
 /// safety: must be called exactly once at bootup
 pub unsafe fn init() {
     // init alarms
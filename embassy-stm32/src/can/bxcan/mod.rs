@@ -17,7 +17,7 @@ use self::registers::{Registers, RxFifo};
 pub use super::common::{BufferedCanReceiver, BufferedCanSender};
 use super::frame::{Envelope, Frame};
 use super::util;
-use crate::can::enums::{BusError, TryReadError};
+use crate::can::enums::{BusError, BusErrorMode, TryReadError};
 use crate::gpio::{AfType, OutputType, Pull, Speed};
 use crate::interrupt::typelevel::Interrupt;
 use crate::rcc::{self, RccPeripheral};
@@ -464,6 +464,74 @@ impl<'d> Can<'d> {
     pub fn modify_filters(&mut self) -> MasterFilters<'_> {
         unsafe { MasterFilters::new(self.info) }
     }
+
+    /// Get the current bus error mode.
+    pub fn bus_error_mode(&self) -> BusErrorMode {
+        bus_error_mode(self.info)
+    }
+}
+
+fn bus_error_mode(info: &Info) -> BusErrorMode {
+    let esr = info.regs.0.esr().read();
+    match (esr.boff(), esr.epvf()) {
+        (false, false) => BusErrorMode::ErrorActive,
+        (false, true) => BusErrorMode::ErrorPassive,
+        (true, _) => BusErrorMode::BusOff,
+    }
+}
+
+/// Programs the filter banks for [`embassy_can_driver::Can::set_filters`].
+///
+/// Like [`Can::modify_filters`], this configures the banks owned by the master peripheral.
+fn set_driver_filters(
+    info: &'static Info,
+    filters: &[embassy_can_driver::Filter],
+) -> Result<(), embassy_can_driver::FilterError> {
+    let mut banks = unsafe { MasterFilters::new(info) };
+    if filters.len() > banks.num_banks() as usize {
+        return Err(embassy_can_driver::FilterError::TooManyFilters);
+    }
+    banks.clear();
+    for (i, f) in filters.iter().enumerate() {
+        let config = match f.is_extended() {
+            false => filter::Mask32::frames_with_std_id(
+                unwrap!(StandardId::new(f.raw_id() as u16)),
+                unwrap!(StandardId::new(f.mask() as u16)),
+            ),
+            true => filter::Mask32::frames_with_ext_id(
+                unwrap!(ExtendedId::new(f.raw_id())),
+                unwrap!(ExtendedId::new(f.mask())),
+            ),
+        };
+        banks.enable_bank(i as u8, Fifo::Fifo0, config);
+    }
+    Ok(())
+}
+
+impl<'d> embassy_can_driver::Can for Can<'d> {
+    type Frame = Frame;
+    type Error = BusError;
+
+    async fn transmit(&mut self, frame: &Frame) -> Result<(), BusError> {
+        // Requeue lower-priority frames displaced from the mailboxes, so none are lost.
+        let mut displaced = self.write(frame).await.dequeued_frame().copied();
+        while let Some(frame) = displaced {
+            displaced = self.write(&frame).await.dequeued_frame().copied();
+        }
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Frame, BusError> {
+        self.read().await.map(|e| e.frame)
+    }
+
+    fn set_filters(&mut self, filters: &[embassy_can_driver::Filter]) -> Result<(), embassy_can_driver::FilterError> {
+        set_driver_filters(self.info, filters)
+    }
+
+    fn bus_state(&self) -> embassy_can_driver::BusState {
+        self.bus_error_mode().into()
+    }
 }
 
 /// Buffered CAN driver.
@@ -506,6 +574,30 @@ impl<'d, const TX_BUF_SIZE: usize, const RX_BUF_SIZE: usize> BufferedCan<'d, TX_
     }
 }
 
+impl<'d, const TX_BUF_SIZE: usize, const RX_BUF_SIZE: usize> embassy_can_driver::Can
+    for BufferedCan<'d, TX_BUF_SIZE, RX_BUF_SIZE>
+{
+    type Frame = Frame;
+    type Error = BusError;
+
+    async fn transmit(&mut self, frame: &Frame) -> Result<(), BusError> {
+        self.write(frame).await;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Frame, BusError> {
+        self.read().await.map(|e| e.frame)
+    }
+
+    fn set_filters(&mut self, filters: &[embassy_can_driver::Filter]) -> Result<(), embassy_can_driver::FilterError> {
+        set_driver_filters(self.rx.info, filters)
+    }
+
+    fn bus_state(&self) -> embassy_can_driver::BusState {
+        bus_error_mode(self.rx.info).into()
+    }
+}
+
 /// CAN driver, transmit half.
 pub struct CanTx<'d> {
     _phantom: PhantomData<&'d ()>,
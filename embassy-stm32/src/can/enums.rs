@@ -29,6 +29,19 @@ pub enum BusError {
     BusWarning,
 }
 
+impl embedded_can::Error for BusError {
+    fn kind(&self) -> embedded_can::ErrorKind {
+        match self {
+            Self::Stuff => embedded_can::ErrorKind::Stuff,
+            Self::Form => embedded_can::ErrorKind::Form,
+            Self::Acknowledge => embedded_can::ErrorKind::Acknowledge,
+            Self::BitRecessive | Self::BitDominant => embedded_can::ErrorKind::Bit,
+            Self::Crc => embedded_can::ErrorKind::Crc,
+            _ => embedded_can::ErrorKind::Other,
+        }
+    }
+}
+
 /// Bus error modes.
 ///
 /// Contrary to the `BusError` enum which also includes last-seen acute protocol
@@ -47,6 +60,16 @@ pub enum BusErrorMode {
     BusOff,
 }
 
+impl From<BusErrorMode> for embassy_can_driver::BusState {
+    fn from(mode: BusErrorMode) -> Self {
+        match mode {
+            BusErrorMode::ErrorActive => Self::ErrorActive,
+            BusErrorMode::ErrorPassive => Self::ErrorPassive,
+            BusErrorMode::BusOff => Self::BusOff,
+        }
+    }
+}
+
 /// Frame Create Errors
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
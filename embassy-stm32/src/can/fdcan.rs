@@ -918,6 +918,157 @@ impl Properties {
     }
 }
 
+impl Properties {
+    /// Programs the filter elements for [`embassy_can_driver::Can::set_filters`].
+    ///
+    /// A reject-all element follows the filters, since the global filter accepts non-matching frames
+    /// by default.
+    fn set_driver_filters(
+        &self,
+        filters: &[embassy_can_driver::Filter],
+    ) -> Result<(), embassy_can_driver::FilterError> {
+        let too_many = embassy_can_driver::FilterError::TooManyFilters;
+        let mut standard = [StandardFilter::disable(); STANDARD_FILTER_MAX as usize];
+        let mut extended = [ExtendedFilter::disable(); EXTENDED_FILTER_MAX as usize];
+        let (mut n_standard, mut n_extended) = (0, 0);
+        for f in filters {
+            match f.is_extended() {
+                false => {
+                    *standard.get_mut(n_standard).ok_or(too_many)? = StandardFilter {
+                        filter: FilterType::BitMask {
+                            filter: f.raw_id() as u16,
+                            mask: f.mask() as u16,
+                        },
+                        action: Action::StoreInFifo0,
+                    };
+                    n_standard += 1;
+                }
+                true => {
+                    *extended.get_mut(n_extended).ok_or(too_many)? = ExtendedFilter {
+                        filter: FilterType::BitMask {
+                            filter: f.raw_id(),
+                            mask: f.mask(),
+                        },
+                        action: Action::StoreInFifo0,
+                    };
+                    n_extended += 1;
+                }
+            }
+        }
+        *standard.get_mut(n_standard).ok_or(too_many)? = StandardFilter::reject_all();
+        *extended.get_mut(n_extended).ok_or(too_many)? = ExtendedFilter::reject_all();
+
+        self.set_standard_filters(&standard);
+        self.set_extended_filters(&extended);
+        Ok(())
+    }
+}
+
+impl<'d> embassy_can_driver::Can for Can<'d> {
+    type Frame = Frame;
+    type Error = BusError;
+
+    async fn transmit(&mut self, frame: &Frame) -> Result<(), BusError> {
+        // Requeue lower-priority frames displaced from the mailboxes, so none are lost.
+        let mut displaced = self.write(frame).await;
+        while let Some(frame) = displaced {
+            displaced = self.write(&frame).await;
+        }
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Frame, BusError> {
+        self.read().await.map(|e| e.frame)
+    }
+
+    fn set_filters(&mut self, filters: &[embassy_can_driver::Filter]) -> Result<(), embassy_can_driver::FilterError> {
+        self.properties.set_driver_filters(filters)
+    }
+
+    fn bus_state(&self) -> embassy_can_driver::BusState {
+        self.properties.bus_error_mode().into()
+    }
+}
+
+impl<'d> embassy_can_driver::CanFd for Can<'d> {
+    type FdFrame = FdFrame;
+
+    async fn transmit_fd(&mut self, frame: &FdFrame) -> Result<(), BusError> {
+        let mut displaced = self.write_fd(frame).await;
+        while let Some(frame) = displaced {
+            displaced = self.write_fd(&frame).await;
+        }
+        Ok(())
+    }
+
+    async fn receive_fd(&mut self) -> Result<FdFrame, BusError> {
+        self.read_fd().await.map(|e| e.frame)
+    }
+}
+
+impl<'d, const TX_BUF_SIZE: usize, const RX_BUF_SIZE: usize> embassy_can_driver::Can
+    for BufferedCan<'d, TX_BUF_SIZE, RX_BUF_SIZE>
+{
+    type Frame = Frame;
+    type Error = BusError;
+
+    async fn transmit(&mut self, frame: &Frame) -> Result<(), BusError> {
+        self.write(*frame).await;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Frame, BusError> {
+        self.read().await.map(|e| e.frame)
+    }
+
+    fn set_filters(&mut self, filters: &[embassy_can_driver::Filter]) -> Result<(), embassy_can_driver::FilterError> {
+        self.properties.set_driver_filters(filters)
+    }
+
+    fn bus_state(&self) -> embassy_can_driver::BusState {
+        self.properties.bus_error_mode().into()
+    }
+}
+
+impl<'d, const TX_BUF_SIZE: usize, const RX_BUF_SIZE: usize> embassy_can_driver::Can
+    for BufferedCanFd<'d, TX_BUF_SIZE, RX_BUF_SIZE>
+{
+    type Frame = FdFrame;
+    type Error = BusError;
+
+    async fn transmit(&mut self, frame: &FdFrame) -> Result<(), BusError> {
+        self.write(*frame).await;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<FdFrame, BusError> {
+        self.read().await.map(|e| e.frame)
+    }
+
+    fn set_filters(&mut self, filters: &[embassy_can_driver::Filter]) -> Result<(), embassy_can_driver::FilterError> {
+        self.properties.set_driver_filters(filters)
+    }
+
+    fn bus_state(&self) -> embassy_can_driver::BusState {
+        self.properties.bus_error_mode().into()
+    }
+}
+
+impl<'d, const TX_BUF_SIZE: usize, const RX_BUF_SIZE: usize> embassy_can_driver::CanFd
+    for BufferedCanFd<'d, TX_BUF_SIZE, RX_BUF_SIZE>
+{
+    type FdFrame = FdFrame;
+
+    async fn transmit_fd(&mut self, frame: &FdFrame) -> Result<(), BusError> {
+        self.write(*frame).await;
+        Ok(())
+    }
+
+    async fn receive_fd(&mut self) -> Result<FdFrame, BusError> {
+        self.read().await.map(|e| e.frame)
+    }
+}
+
 struct State {
     pub rx_mode: RxMode,
     pub tx_mode: TxMode,
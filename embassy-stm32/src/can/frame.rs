@@ -442,6 +442,21 @@ impl CanHeader for FdFrame {
     }
 }
 
+impl embassy_can_driver::FdFrame for FdFrame {
+    fn new_fd(id: impl Into<embedded_can::Id>, data: &[u8], bit_rate_switching: bool) -> Option<Self> {
+        let header = Header::new_fd(id.into(), data.len() as u8, false, bit_rate_switching);
+        FdFrame::new(header, data).ok()
+    }
+
+    fn is_fd(&self) -> bool {
+        self.can_header.fdcan()
+    }
+
+    fn bit_rate_switching(&self) -> bool {
+        self.can_header.bit_rate_switching()
+    }
+}
+
 /// Contains CAN FD frame and additional metadata.
 ///
 /// Timestamp is available if `time` feature is enabled.
@@ -0,0 +1,1095 @@
+//! ISO-TP (ISO 15765-2) transport protocol.
+//!
+//! ISO-TP carries messages of up to 4 GiB over CAN, by segmenting them into a first frame and
+//! consecutive frames, paced by flow control frames from the receiver. It's the transport used
+//! by UDS diagnostics and by most CAN bootloaders.
+//!
+//! [`IsoTp`] runs one connection, between a pair of CAN IDs, on top of any [`CanIo`]. Classic
+//! CAN and CAN FD frames of up to 64 bytes are supported, with normal, extended and mixed
+//! addressing.
+//!
+//! The driver is half-duplex: while sending, frames other than flow control are ignored, and
+//! while receiving, flow control frames are ignored. Frames with other IDs are always ignored,
+//! so it's best to configure the CAN filters to only accept the receive ID.
+
+use core::marker::PhantomData;
+
+use embassy_time::{with_timeout, Duration, Timer};
+use embedded_can::Id;
+
+use super::enums::{BusError, FrameCreateError};
+use super::frame::{CanHeader, FdData, Header};
+
+const PCI_SINGLE: u8 = 0x0;
+const PCI_FIRST: u8 = 0x1;
+const PCI_CONSECUTIVE: u8 = 0x2;
+const PCI_FLOW_CONTROL: u8 = 0x3;
+
+/// Padding byte for CAN FD frames, used if [`Config::padding`] is not set.
+const FD_PADDING: u8 = 0xCC;
+
+/// ISO-TP error.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// CAN bus error.
+    Bus(BusError),
+    /// A frame could not be created, e.g. because [`Config::tx_dl`] is larger than the frame type
+    /// allows.
+    Frame(FrameCreateError),
+    /// Timed out waiting for a flow control or consecutive frame.
+    Timeout,
+    /// The message to send is empty or too long.
+    InvalidLength,
+    /// The received message doesn't fit in the buffer. The sender was told with an overflow flow
+    /// control frame.
+    BufferTooSmall,
+    /// The receiver reported that the message is too long for it.
+    Overflow,
+    /// The receiver sent more wait flow control frames than [`Config::max_wait_frames`].
+    TooManyWaits,
+    /// A consecutive frame was received out of sequence.
+    WrongSequenceNumber,
+    /// A malformed frame was received.
+    InvalidFrame,
+}
+
+impl From<BusError> for Error {
+    fn from(e: BusError) -> Self {
+        Self::Bus(e)
+    }
+}
+
+impl From<FrameCreateError> for Error {
+    fn from(e: FrameCreateError) -> Self {
+        Self::Frame(e)
+    }
+}
+
+/// Addressing mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Addressing {
+    /// The CAN IDs alone identify the connection.
+    Normal,
+    /// The first data byte of each frame is a target address.
+    ///
+    /// Sent frames carry `target`, and only received frames carrying `source` are accepted.
+    Extended {
+        /// Address of the peer.
+        target: u8,
+        /// Own address.
+        source: u8,
+    },
+    /// The first data byte of each frame is an address extension, the same in both directions.
+    Mixed(u8),
+}
+
+impl Addressing {
+    fn tx_byte(&self) -> Option<u8> {
+        match *self {
+            Self::Normal => None,
+            Self::Extended { target, .. } => Some(target),
+            Self::Mixed(ae) => Some(ae),
+        }
+    }
+
+    fn rx_byte(&self) -> Option<u8> {
+        match *self {
+            Self::Normal => None,
+            Self::Extended { source, .. } => Some(source),
+            Self::Mixed(ae) => Some(ae),
+        }
+    }
+
+    /// Number of data bytes taken by the address.
+    fn len(&self) -> usize {
+        match self {
+            Self::Normal => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// Minimum separation time between consecutive frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StMin(u8);
+
+impl StMin {
+    /// No separation.
+    pub const ZERO: Self = Self(0);
+
+    /// Separation in milliseconds, from 0 to 127.
+    pub const fn from_millis(millis: u8) -> Self {
+        assert!(millis <= 0x7F);
+        Self(millis)
+    }
+
+    /// Separation in hundreds of microseconds, from 1 to 9.
+    pub const fn from_hundred_micros(n: u8) -> Self {
+        assert!(n >= 1 && n <= 9);
+        Self(0xF0 + n)
+    }
+
+    /// Create from the raw byte of a flow control frame.
+    pub const fn from_raw(raw: u8) -> Self {
+        Self(raw)
+    }
+
+    /// Raw byte, as sent in flow control frames.
+    pub const fn raw(&self) -> u8 {
+        self.0
+    }
+
+    /// Separation in microseconds.
+    ///
+    /// Reserved values are interpreted as the longest separation, 127 ms, as the standard requires.
+    pub const fn as_micros(&self) -> u32 {
+        match self.0 {
+            0x00..=0x7F => self.0 as u32 * 1000,
+            0xF1..=0xF9 => (self.0 - 0xF0) as u32 * 100,
+            _ => 127_000,
+        }
+    }
+}
+
+/// Flow status of a flow control frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum FlowStatus {
+    ContinueToSend = 0,
+    Wait = 1,
+    Overflow = 2,
+}
+
+/// ISO-TP connection configuration.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// ID of sent frames.
+    pub tx_id: Id,
+    /// ID of received frames.
+    pub rx_id: Id,
+    /// Addressing mode.
+    pub addressing: Addressing,
+    /// Data length of sent frames: 8 for classic CAN, or a valid CAN FD length up to 64.
+    ///
+    /// Frames longer than 8 bytes are sent as CAN FD frames, which requires [`FdFrame`](super::frame::FdFrame).
+    pub tx_dl: u8,
+    /// Use bit rate switching for CAN FD frames.
+    pub bit_rate_switching: bool,
+    /// Pad frames to 8 bytes with this byte.
+    ///
+    /// CAN FD frames longer than 8 bytes are always padded to a valid length, with `0xCC` if this
+    /// is not set.
+    pub padding: Option<u8>,
+    /// Number of consecutive frames the peer may send before waiting for another flow control
+    /// frame. 0 means no limit.
+    pub block_size: u8,
+    /// Separation time the peer must leave between consecutive frames.
+    pub st_min: StMin,
+    /// How long to wait for a flow control or consecutive frame (N_Bs and N_Cr).
+    pub timeout: Duration,
+    /// Maximum number of wait flow control frames accepted in a row.
+    pub max_wait_frames: u8,
+}
+
+impl Config {
+    /// Create a classic CAN configuration with normal addressing.
+    pub fn new(tx_id: impl Into<Id>, rx_id: impl Into<Id>) -> Self {
+        Self {
+            tx_id: tx_id.into(),
+            rx_id: rx_id.into(),
+            addressing: Addressing::Normal,
+            tx_dl: 8,
+            bit_rate_switching: false,
+            padding: Some(0xAA),
+            block_size: 0,
+            st_min: StMin::ZERO,
+            timeout: Duration::from_millis(1000),
+            max_wait_frames: 10,
+        }
+    }
+
+    /// Number of ISO-TP bytes in a sent frame.
+    fn tx_capacity(&self) -> usize {
+        self.tx_dl as usize - self.addressing.len()
+    }
+}
+
+/// Decoded protocol control information, with the data that follows.
+#[derive(Debug, PartialEq, Eq)]
+enum Pci<'a> {
+    Single(&'a [u8]),
+    First {
+        len: u32,
+        data: &'a [u8],
+    },
+    Consecutive {
+        sn: u8,
+        data: &'a [u8],
+    },
+    FlowControl {
+        status: FlowStatus,
+        block_size: u8,
+        st_min: StMin,
+    },
+}
+
+impl<'a> Pci<'a> {
+    /// Decode the data of a frame, without the address byte.
+    fn decode(data: &'a [u8]) -> Result<Self, Error> {
+        let b0 = *data.first().ok_or(Error::InvalidFrame)?;
+        match b0 >> 4 {
+            PCI_SINGLE => {
+                let (len, data) = match b0 & 0x0F {
+                    // Escape sequence, for CAN FD frames longer than 8 bytes.
+                    0 => (*data.get(1).ok_or(Error::InvalidFrame)? as usize, &data[2..]),
+                    len => (len as usize, &data[1..]),
+                };
+                match len {
+                    0 => Err(Error::InvalidFrame),
+                    _ => data.get(..len).map(Pci::Single).ok_or(Error::InvalidFrame),
+                }
+            }
+            PCI_FIRST => {
+                let b1 = *data.get(1).ok_or(Error::InvalidFrame)?;
+                match ((b0 & 0x0F) as u32) << 8 | b1 as u32 {
+                    // Escape sequence, for messages longer than 4095 bytes.
+                    0 => {
+                        let len = data.get(2..6).ok_or(Error::InvalidFrame)?;
+                        Ok(Pci::First {
+                            len: u32::from_be_bytes([len[0], len[1], len[2], len[3]]),
+                            data: &data[6..],
+                        })
+                    }
+                    len => Ok(Pci::First { len, data: &data[2..] }),
+                }
+            }
+            PCI_CONSECUTIVE => Ok(Pci::Consecutive {
+                sn: b0 & 0x0F,
+                data: &data[1..],
+            }),
+            PCI_FLOW_CONTROL => {
+                let status = match b0 & 0x0F {
+                    0 => FlowStatus::ContinueToSend,
+                    1 => FlowStatus::Wait,
+                    2 => FlowStatus::Overflow,
+                    _ => return Err(Error::InvalidFrame),
+                };
+                let params = data.get(1..3).ok_or(Error::InvalidFrame)?;
+                Ok(Pci::FlowControl {
+                    status,
+                    block_size: params[0],
+                    st_min: StMin(params[1]),
+                })
+            }
+            _ => Err(Error::InvalidFrame),
+        }
+    }
+}
+
+/// Data of a frame being built.
+struct FrameBuf {
+    buf: [u8; 64],
+    len: usize,
+}
+
+impl FrameBuf {
+    fn new(addressing: &Addressing) -> Self {
+        let mut this = Self { buf: [0; 64], len: 0 };
+        if let Some(b) = addressing.tx_byte() {
+            this.push(&[b]);
+        }
+        this
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buf[self.len..][..data.len()].copy_from_slice(data);
+        self.len += data.len();
+    }
+
+    fn pad(&mut self, padding: Option<u8>) {
+        let (len, padding) = match (self.len, padding) {
+            (0..=8, Some(p)) => (8, p),
+            (0..=8, None) => return,
+            (len, p) => (
+                (len..=64).find(|&l| FdData::is_valid_len(l)).unwrap_or(64),
+                p.unwrap_or(FD_PADDING),
+            ),
+        };
+        self.buf[self.len..len].fill(padding);
+        self.len = len;
+    }
+
+    fn flow_control(config: &Config, status: FlowStatus) -> Self {
+        let mut this = Self::new(&config.addressing);
+        this.push(&[
+            PCI_FLOW_CONTROL << 4 | status as u8,
+            config.block_size,
+            config.st_min.raw(),
+        ]);
+        this.pad(config.padding);
+        this
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// What the sender does next.
+#[derive(Debug, PartialEq, Eq)]
+enum TxStep {
+    /// Send the frame, after waiting for the separation time if given.
+    Frame(Option<StMin>),
+    /// Wait for a flow control frame.
+    WaitFlowControl,
+    /// The message was sent.
+    Done,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TxState {
+    Start,
+    WaitFlowControl,
+    /// Sending consecutive frames. `remaining` is the number of frames left in the block, if limited.
+    Sending {
+        remaining: Option<u8>,
+        first: bool,
+    },
+    Done,
+}
+
+/// Sender state machine, segmenting a message into frames.
+struct Sender<'a> {
+    config: &'a Config,
+    data: &'a [u8],
+    offset: usize,
+    sn: u8,
+    state: TxState,
+    st_min: StMin,
+    waits: u8,
+}
+
+impl<'a> Sender<'a> {
+    fn new(config: &'a Config, data: &'a [u8]) -> Result<Self, Error> {
+        if data.is_empty() || u32::try_from(data.len()).is_err() {
+            return Err(Error::InvalidLength);
+        }
+        Ok(Self {
+            config,
+            data,
+            offset: 0,
+            sn: 1,
+            state: TxState::Start,
+            st_min: StMin::ZERO,
+            waits: 0,
+        })
+    }
+
+    fn take(&mut self, max: usize) -> &'a [u8] {
+        let n = max.min(self.data.len() - self.offset);
+        let data = &self.data[self.offset..][..n];
+        self.offset += n;
+        data
+    }
+
+    /// Advance the state machine, building the next frame into `frame` if there is one.
+    fn next(&mut self, frame: &mut FrameBuf) -> TxStep {
+        let cap = self.config.tx_capacity();
+        let len = self.data.len();
+        let step = match self.state {
+            TxState::Start => {
+                if len < cap.min(8 - self.config.addressing.len()) {
+                    frame.push(&[PCI_SINGLE << 4 | len as u8]);
+                    frame.push(self.take(len));
+                    self.state = TxState::Done;
+                } else if len <= cap - 2 {
+                    frame.push(&[PCI_SINGLE << 4, len as u8]);
+                    frame.push(self.take(len));
+                    self.state = TxState::Done;
+                } else if len <= 0xFFF {
+                    frame.push(&[PCI_FIRST << 4 | (len >> 8) as u8, len as u8]);
+                    frame.push(self.take(cap - 2));
+                    self.state = TxState::WaitFlowControl;
+                } else {
+                    frame.push(&[PCI_FIRST << 4, 0]);
+                    frame.push(&(len as u32).to_be_bytes());
+                    frame.push(self.take(cap - 6));
+                    self.state = TxState::WaitFlowControl;
+                }
+                TxStep::Frame(None)
+            }
+            TxState::WaitFlowControl => return TxStep::WaitFlowControl,
+            TxState::Sending { remaining, first } => {
+                frame.push(&[PCI_CONSECUTIVE << 4 | self.sn]);
+                frame.push(self.take(cap - 1));
+                self.sn = (self.sn + 1) & 0x0F;
+                self.state = match remaining.map(|n| n - 1) {
+                    _ if self.offset == len => TxState::Done,
+                    Some(0) => TxState::WaitFlowControl,
+                    remaining => TxState::Sending {
+                        remaining,
+                        first: false,
+                    },
+                };
+                TxStep::Frame((!first).then_some(self.st_min))
+            }
+            TxState::Done => return TxStep::Done,
+        };
+        frame.pad(self.config.padding);
+        step
+    }
+
+    fn on_flow_control(&mut self, status: FlowStatus, block_size: u8, st_min: StMin) -> Result<(), Error> {
+        if self.state != TxState::WaitFlowControl {
+            return Ok(());
+        }
+        match status {
+            FlowStatus::ContinueToSend => {
+                self.waits = 0;
+                self.st_min = st_min;
+                self.state = TxState::Sending {
+                    remaining: (block_size != 0).then_some(block_size),
+                    first: true,
+                };
+                Ok(())
+            }
+            FlowStatus::Wait => match self.waits >= self.config.max_wait_frames {
+                true => Err(Error::TooManyWaits),
+                false => {
+                    self.waits += 1;
+                    Ok(())
+                }
+            },
+            FlowStatus::Overflow => Err(Error::Overflow),
+        }
+    }
+}
+
+/// What the receiver does next.
+#[derive(Debug, PartialEq, Eq)]
+enum RxStep {
+    /// Wait for the next frame.
+    Continue,
+    /// Send a flow control frame, then wait for the next frame.
+    FlowControl(FlowStatus),
+    /// The message of the given length was received.
+    Done(usize),
+}
+
+/// Receiver state machine, reassembling a message from frames.
+struct Receiver<'a, 'b> {
+    config: &'a Config,
+    buf: &'b mut [u8],
+    /// Length of the message being received, or `None` if waiting for a new message.
+    len: Option<usize>,
+    received: usize,
+    sn: u8,
+    block: u8,
+}
+
+impl<'a, 'b> Receiver<'a, 'b> {
+    fn new(config: &'a Config, buf: &'b mut [u8]) -> Self {
+        Self {
+            config,
+            buf,
+            len: None,
+            received: 0,
+            sn: 0,
+            block: 0,
+        }
+    }
+
+    fn is_receiving(&self) -> bool {
+        self.len.is_some()
+    }
+
+    fn on_pci(&mut self, pci: Pci<'_>) -> Result<RxStep, Error> {
+        match pci {
+            // A new message aborts the one being received.
+            Pci::Single(data) => {
+                self.len = None;
+                let buf = self.buf.get_mut(..data.len()).ok_or(Error::BufferTooSmall)?;
+                buf.copy_from_slice(data);
+                Ok(RxStep::Done(data.len()))
+            }
+            Pci::First { len, data } => {
+                let len = len as usize;
+                if len > self.buf.len() {
+                    self.len = None;
+                    return Ok(RxStep::FlowControl(FlowStatus::Overflow));
+                }
+                let n = data.len().min(len);
+                self.buf[..n].copy_from_slice(&data[..n]);
+                self.len = Some(len);
+                self.received = n;
+                self.sn = 1;
+                self.block = 0;
+                Ok(RxStep::FlowControl(FlowStatus::ContinueToSend))
+            }
+            Pci::Consecutive { sn, data } => {
+                let Some(len) = self.len else {
+                    return Ok(RxStep::Continue);
+                };
+                if sn != self.sn {
+                    self.len = None;
+                    return Err(Error::WrongSequenceNumber);
+                }
+                let n = data.len().min(len - self.received);
+                self.buf[self.received..][..n].copy_from_slice(&data[..n]);
+                self.received += n;
+                self.sn = (self.sn + 1) & 0x0F;
+
+                if self.received == len {
+                    self.len = None;
+                    return Ok(RxStep::Done(len));
+                }
+                if self.config.block_size == 0 {
+                    return Ok(RxStep::Continue);
+                }
+                self.block += 1;
+                match self.block == self.config.block_size {
+                    true => {
+                        self.block = 0;
+                        Ok(RxStep::FlowControl(FlowStatus::ContinueToSend))
+                    }
+                    false => Ok(RxStep::Continue),
+                }
+            }
+            Pci::FlowControl { .. } => Ok(RxStep::Continue),
+        }
+    }
+}
+
+/// Frame-level CAN interface used by [`IsoTp`].
+///
+/// `F` is the frame type: [`Frame`](super::Frame) for classic CAN, [`FdFrame`](super::frame::FdFrame) for CAN FD.
+pub trait CanIo<F> {
+    /// Transmit a frame.
+    async fn write(&mut self, frame: &F) -> Result<(), BusError>;
+    /// Receive a frame.
+    async fn read(&mut self) -> Result<F, BusError>;
+}
+
+impl<T: CanIo<F>, F> CanIo<F> for &mut T {
+    async fn write(&mut self, frame: &F) -> Result<(), BusError> {
+        T::write(self, frame).await
+    }
+
+    async fn read(&mut self) -> Result<F, BusError> {
+        T::read(self).await
+    }
+}
+
+/// ISO-TP connection.
+pub struct IsoTp<C, F> {
+    can: C,
+    config: Config,
+    phantom: PhantomData<F>,
+}
+
+impl<C, F> IsoTp<C, F>
+where
+    C: CanIo<F>,
+    F: embedded_can::Frame + CanHeader,
+{
+    /// Create a new ISO-TP connection over `can`.
+    pub fn new(can: C, config: Config) -> Self {
+        assert!(config.tx_dl == 8 || (config.tx_dl > 8 && FdData::is_valid_len(config.tx_dl as usize)));
+        Self {
+            can,
+            config,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Get the configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Release the CAN interface.
+    pub fn into_inner(self) -> C {
+        self.can
+    }
+
+    async fn write(&mut self, frame: &FrameBuf) -> Result<(), Error> {
+        let len = frame.len as u8;
+        let header = match self.config.tx_dl {
+            8 => Header::new(self.config.tx_id, len, false),
+            _ => Header::new_fd(self.config.tx_id, len, false, self.config.bit_rate_switching),
+        };
+        let frame = F::from_header(header, frame.data())?;
+        self.can.write(&frame).await?;
+        Ok(())
+    }
+
+    /// Read frames until one for this connection arrives.
+    async fn read(&mut self) -> Result<F, Error> {
+        loop {
+            let frame = self.can.read().await?;
+            if frame.id() != self.config.rx_id || frame.is_remote_frame() {
+                continue;
+            }
+            match self.config.addressing.rx_byte() {
+                Some(b) if frame.dlc() == 0 || frame.data()[0] != b => continue,
+                _ => return Ok(frame),
+            }
+        }
+    }
+
+    async fn read_timeout(&mut self) -> Result<F, Error> {
+        with_timeout(self.config.timeout, self.read())
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    fn data<'f>(&self, frame: &'f F) -> &'f [u8] {
+        let len = frame.dlc().min(frame.data().len());
+        &frame.data()[self.config.addressing.len()..len]
+    }
+
+    /// Send a message.
+    pub async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        let config = self.config;
+        let mut sender = Sender::new(&config, data)?;
+        loop {
+            let mut frame = FrameBuf::new(&config.addressing);
+            match sender.next(&mut frame) {
+                TxStep::Frame(st_min) => {
+                    if let Some(st_min) = st_min {
+                        Timer::after_micros(st_min.as_micros() as u64).await;
+                    }
+                    self.write(&frame).await?;
+                }
+                TxStep::WaitFlowControl => {
+                    let frame = self.read_timeout().await?;
+                    // Anything but flow control is ignored, and so are malformed frames.
+                    if let Ok(Pci::FlowControl {
+                        status,
+                        block_size,
+                        st_min,
+                    }) = Pci::decode(self.data(&frame))
+                    {
+                        sender.on_flow_control(status, block_size, st_min)?;
+                    }
+                }
+                TxStep::Done => return Ok(()),
+            }
+        }
+    }
+
+    /// Receive a message into `buf`, returning its length.
+    ///
+    /// Frames that are not part of a message are ignored, while waiting for the first frame.
+    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let config = self.config;
+        let mut receiver = Receiver::new(&config, buf);
+        loop {
+            let frame = match receiver.is_receiving() {
+                true => self.read_timeout().await?,
+                false => self.read().await?,
+            };
+            let pci = match Pci::decode(self.data(&frame)) {
+                Ok(pci) => pci,
+                // Malformed frames are ignored.
+                Err(_) => continue,
+            };
+            match receiver.on_pci(pci)? {
+                RxStep::Continue => {}
+                RxStep::FlowControl(status) => {
+                    self.write(&FrameBuf::flow_control(&config, status)).await?;
+                    if status == FlowStatus::Overflow {
+                        return Err(Error::BufferTooSmall);
+                    }
+                }
+                RxStep::Done(len) => return Ok(len),
+            }
+        }
+    }
+}
+
+#[cfg(can_bxcan)]
+mod impls {
+    use super::*;
+    use crate::can::{BufferedCan, Can, Frame};
+
+    impl<'d> CanIo<Frame> for Can<'d> {
+        async fn write(&mut self, frame: &Frame) -> Result<(), BusError> {
+            Can::write(self, frame).await;
+            Ok(())
+        }
+
+        async fn read(&mut self) -> Result<Frame, BusError> {
+            Can::read(self).await.map(|e| e.frame)
+        }
+    }
+
+    impl<'d, const TX_BUF_SIZE: usize, const RX_BUF_SIZE: usize> CanIo<Frame>
+        for BufferedCan<'d, TX_BUF_SIZE, RX_BUF_SIZE>
+    {
+        async fn write(&mut self, frame: &Frame) -> Result<(), BusError> {
+            BufferedCan::write(self, frame).await;
+            Ok(())
+        }
+
+        async fn read(&mut self) -> Result<Frame, BusError> {
+            BufferedCan::read(self).await.map(|e| e.frame)
+        }
+    }
+}
+
+#[cfg(any(can_fdcan_v1, can_fdcan_h7))]
+mod impls {
+    use super::*;
+    use crate::can::frame::FdFrame;
+    use crate::can::{BufferedCan, BufferedCanFd, Can, Frame};
+
+    impl<'d> CanIo<Frame> for Can<'d> {
+        async fn write(&mut self, frame: &Frame) -> Result<(), BusError> {
+            Can::write(self, frame).await;
+            Ok(())
+        }
+
+        async fn read(&mut self) -> Result<Frame, BusError> {
+            Can::read(self).await.map(|e| e.frame)
+        }
+    }
+
+    impl<'d> CanIo<FdFrame> for Can<'d> {
+        async fn write(&mut self, frame: &FdFrame) -> Result<(), BusError> {
+            Can::write_fd(self, frame).await;
+            Ok(())
+        }
+
+        async fn read(&mut self) -> Result<FdFrame, BusError> {
+            Can::read_fd(self).await.map(|e| e.frame)
+        }
+    }
+
+    impl<'d, const TX_BUF_SIZE: usize, const RX_BUF_SIZE: usize> CanIo<Frame>
+        for BufferedCan<'d, TX_BUF_SIZE, RX_BUF_SIZE>
+    {
+        async fn write(&mut self, frame: &Frame) -> Result<(), BusError> {
+            BufferedCan::write(self, *frame).await;
+            Ok(())
+        }
+
+        async fn read(&mut self) -> Result<Frame, BusError> {
+            BufferedCan::read(self).await.map(|e| e.frame)
+        }
+    }
+
+    impl<'d, const TX_BUF_SIZE: usize, const RX_BUF_SIZE: usize> CanIo<FdFrame>
+        for BufferedCanFd<'d, TX_BUF_SIZE, RX_BUF_SIZE>
+    {
+        async fn write(&mut self, frame: &FdFrame) -> Result<(), BusError> {
+            BufferedCanFd::write(self, *frame).await;
+            Ok(())
+        }
+
+        async fn read(&mut self) -> Result<FdFrame, BusError> {
+            BufferedCanFd::read(self).await.map(|e| e.frame)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a message through a sender and a receiver connected back to back, returning the
+    /// received message and the number of frames each side sent.
+    fn loopback(
+        tx_config: &Config,
+        rx_config: &Config,
+        msg: &[u8],
+        buf: &mut [u8],
+    ) -> (Result<usize, Error>, usize, usize) {
+        let mut sender = Sender::new(tx_config, msg).unwrap();
+        let mut receiver = Receiver::new(rx_config, buf);
+        let (mut tx_frames, mut rx_frames) = (0, 0);
+        loop {
+            let mut frame = FrameBuf::new(&tx_config.addressing);
+            match sender.next(&mut frame) {
+                TxStep::Frame(_) => {
+                    tx_frames += 1;
+                    assert!(frame.len <= tx_config.tx_dl as usize);
+                    assert!(FdData::is_valid_len(frame.len));
+                    assert_eq!(
+                        frame.data()[..rx_config.addressing.len()],
+                        frame.buf[..tx_config.addressing.len()]
+                    );
+                    let pci = Pci::decode(&frame.data()[rx_config.addressing.len()..]).unwrap();
+                    match receiver.on_pci(pci) {
+                        Ok(RxStep::Continue) => {}
+                        Ok(RxStep::Done(len)) => return (Ok(len), tx_frames, rx_frames),
+                        Ok(RxStep::FlowControl(status)) => {
+                            rx_frames += 1;
+                            let fc = FrameBuf::flow_control(rx_config, status);
+                            let Pci::FlowControl {
+                                status,
+                                block_size,
+                                st_min,
+                            } = Pci::decode(&fc.data()[rx_config.addressing.len()..]).unwrap()
+                            else {
+                                panic!("not a flow control frame");
+                            };
+                            if let Err(e) = sender.on_flow_control(status, block_size, st_min) {
+                                return (Err(e), tx_frames, rx_frames);
+                            }
+                        }
+                        Err(e) => return (Err(e), tx_frames, rx_frames),
+                    }
+                }
+                TxStep::WaitFlowControl => panic!("sender waits for flow control that was not sent"),
+                TxStep::Done => panic!("sender is done, receiver is not"),
+            }
+        }
+    }
+
+    fn message(len: usize) -> std::vec::Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    fn assert_loopback(config: &Config, len: usize, frames: usize, flow_controls: usize) {
+        let msg = message(len);
+        let mut buf = std::vec![0; len];
+        let (res, tx_frames, rx_frames) = loopback(config, config, &msg, &mut buf);
+        assert_eq!(res.unwrap(), len);
+        assert_eq!(buf, msg);
+        assert_eq!((tx_frames, rx_frames), (frames, flow_controls), "len {}", len);
+    }
+
+    #[test]
+    fn classic_lengths() {
+        let config = Config::new(
+            embedded_can::StandardId::new(0x7E0).unwrap(),
+            embedded_can::StandardId::new(0x7E8).unwrap(),
+        );
+        assert_loopback(&config, 1, 1, 0);
+        assert_loopback(&config, 7, 1, 0);
+        // 6 bytes in the first frame, then 7 per consecutive frame.
+        assert_loopback(&config, 8, 2, 1);
+        assert_loopback(&config, 13, 2, 1);
+        assert_loopback(&config, 14, 3, 1);
+        assert_loopback(&config, 4095, 1 + 4089usize.div_ceil(7), 1);
+        // Escaped length, with 2 bytes in the first frame.
+        assert_loopback(&config, 5000, 1 + 4998usize.div_ceil(7), 1);
+    }
+
+    #[test]
+    fn block_size() {
+        let mut config = Config::new(
+            embedded_can::StandardId::new(0x7E0).unwrap(),
+            embedded_can::StandardId::new(0x7E8).unwrap(),
+        );
+        config.block_size = 4;
+        // 6 + 10 * 7 bytes: 10 consecutive frames in blocks of 4, 4 and 2.
+        assert_loopback(&config, 76, 11, 3);
+        // 6 + 8 * 7 bytes: the last block is complete, no flow control after it.
+        assert_loopback(&config, 62, 9, 2);
+    }
+
+    #[test]
+    fn addressing() {
+        let mut config = Config::new(
+            embedded_can::StandardId::new(0x600).unwrap(),
+            embedded_can::StandardId::new(0x600).unwrap(),
+        );
+        config.addressing = Addressing::Mixed(0x42);
+        // 5 bytes in the first frame, then 6 per consecutive frame.
+        assert_loopback(&config, 6, 1, 0);
+        assert_loopback(&config, 7, 2, 1);
+        assert_loopback(&config, 100, 1 + 95usize.div_ceil(6), 1);
+
+        let tx_config = Config {
+            addressing: Addressing::Extended {
+                target: 0x10,
+                source: 0xF1,
+            },
+            ..config
+        };
+        let rx_config = Config {
+            addressing: Addressing::Extended {
+                target: 0xF1,
+                source: 0x10,
+            },
+            ..config
+        };
+        let msg = message(100);
+        let mut buf = [0; 100];
+        let (res, _, _) = loopback(&tx_config, &rx_config, &msg, &mut buf);
+        assert_eq!(res.unwrap(), 100);
+        assert_eq!(buf[..], msg[..]);
+
+        let mut frame = FrameBuf::new(&tx_config.addressing);
+        Sender::new(&tx_config, &msg[..3]).unwrap().next(&mut frame);
+        assert_eq!(frame.data(), &[0x10, 0x03, 0, 1, 2, 0xAA, 0xAA, 0xAA]);
+    }
+
+    #[test]
+    fn can_fd() {
+        let mut config = Config::new(
+            embedded_can::StandardId::new(0x7E0).unwrap(),
+            embedded_can::StandardId::new(0x7E8).unwrap(),
+        );
+        config.tx_dl = 64;
+        config.padding = None;
+        assert_loopback(&config, 7, 1, 0);
+        // Escaped single frame.
+        assert_loopback(&config, 8, 1, 0);
+        assert_loopback(&config, 62, 1, 0);
+        // 62 bytes in the first frame, then 63 per consecutive frame.
+        assert_loopback(&config, 63, 2, 1);
+        assert_loopback(&config, 1000, 1 + 938usize.div_ceil(63), 1);
+
+        // Short single frames are not padded, longer ones are padded to a valid length.
+        let mut frame = FrameBuf::new(&config.addressing);
+        Sender::new(&config, &[1, 2, 3]).unwrap().next(&mut frame);
+        assert_eq!(frame.data(), &[0x03, 1, 2, 3]);
+
+        let msg = message(9);
+        let mut frame = FrameBuf::new(&config.addressing);
+        Sender::new(&config, &msg).unwrap().next(&mut frame);
+        assert_eq!(frame.len, 12);
+        assert_eq!(frame.data()[..2], [0x00, 9]);
+        assert_eq!(frame.data()[11], FD_PADDING);
+    }
+
+    #[test]
+    fn overflow() {
+        let config = Config::new(
+            embedded_can::StandardId::new(0x7E0).unwrap(),
+            embedded_can::StandardId::new(0x7E8).unwrap(),
+        );
+        let msg = message(100);
+        let mut buf = [0; 50];
+        let (res, tx_frames, rx_frames) = loopback(&config, &config, &msg, &mut buf);
+        assert!(matches!(res, Err(Error::Overflow)));
+        assert_eq!((tx_frames, rx_frames), (1, 1));
+
+        let mut receiver = Receiver::new(&config, &mut buf[..2]);
+        assert!(matches!(
+            receiver.on_pci(Pci::Single(&[1, 2, 3])),
+            Err(Error::BufferTooSmall)
+        ));
+    }
+
+    #[test]
+    fn wait_frames() {
+        let mut config = Config::new(
+            embedded_can::StandardId::new(0x7E0).unwrap(),
+            embedded_can::StandardId::new(0x7E8).unwrap(),
+        );
+        check_wait_frames(&config);
+        config.max_wait_frames = u8::MAX;
+        check_wait_frames(&config);
+    }
+
+    fn check_wait_frames(config: &Config) {
+        let msg = message(100);
+        let mut sender = Sender::new(config, &msg).unwrap();
+        let mut frame = FrameBuf::new(&config.addressing);
+        assert_eq!(sender.next(&mut frame), TxStep::Frame(None));
+        for _ in 0..config.max_wait_frames {
+            sender.on_flow_control(FlowStatus::Wait, 0, StMin::ZERO).unwrap();
+            assert_eq!(sender.next(&mut frame), TxStep::WaitFlowControl);
+        }
+        assert!(matches!(
+            sender.on_flow_control(FlowStatus::Wait, 0, StMin::ZERO),
+            Err(Error::TooManyWaits)
+        ));
+    }
+
+    #[test]
+    fn separation_time() {
+        let config = Config::new(
+            embedded_can::StandardId::new(0x7E0).unwrap(),
+            embedded_can::StandardId::new(0x7E8).unwrap(),
+        );
+        let msg = message(30);
+        let mut sender = Sender::new(&config, &msg).unwrap();
+        let mut frame = FrameBuf::new(&config.addressing);
+        assert_eq!(sender.next(&mut frame), TxStep::Frame(None));
+        sender
+            .on_flow_control(FlowStatus::ContinueToSend, 0, StMin::from_millis(5))
+            .unwrap();
+        // No separation before the first consecutive frame of a block.
+        assert_eq!(sender.next(&mut frame), TxStep::Frame(None));
+        assert_eq!(sender.next(&mut frame), TxStep::Frame(Some(StMin::from_millis(5))));
+
+        assert_eq!(StMin::from_millis(5).as_micros(), 5000);
+        assert_eq!(StMin::from_hundred_micros(3).as_micros(), 300);
+        assert_eq!(StMin::from_raw(0x80).as_micros(), 127_000);
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        let config = Config::new(
+            embedded_can::StandardId::new(0x7E0).unwrap(),
+            embedded_can::StandardId::new(0x7E8).unwrap(),
+        );
+        let mut buf = [0; 100];
+        let mut receiver = Receiver::new(&config, &mut buf);
+        assert_eq!(
+            receiver
+                .on_pci(Pci::First {
+                    len: 100,
+                    data: &[0; 6]
+                })
+                .unwrap(),
+            RxStep::FlowControl(FlowStatus::ContinueToSend)
+        );
+        assert_eq!(
+            receiver.on_pci(Pci::Consecutive { sn: 1, data: &[0; 7] }).unwrap(),
+            RxStep::Continue
+        );
+        assert!(matches!(
+            receiver.on_pci(Pci::Consecutive { sn: 3, data: &[0; 7] }),
+            Err(Error::WrongSequenceNumber)
+        ));
+        // Consecutive frames outside of a message are ignored.
+        assert_eq!(
+            receiver.on_pci(Pci::Consecutive { sn: 4, data: &[0; 7] }).unwrap(),
+            RxStep::Continue
+        );
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(Pci::decode(&[0x03, 1, 2, 3, 0xAA]).unwrap(), Pci::Single(&[1, 2, 3]));
+        assert_eq!(Pci::decode(&[0x00, 2, 1, 2]).unwrap(), Pci::Single(&[1, 2]));
+        assert_eq!(
+            Pci::decode(&[0x11, 0x00, 1, 2, 3, 4, 5, 6]).unwrap(),
+            Pci::First {
+                len: 0x100,
+                data: &[1, 2, 3, 4, 5, 6]
+            }
+        );
+        assert_eq!(
+            Pci::decode(&[0x10, 0x00, 0x00, 0x01, 0x00, 0x00, 1, 2]).unwrap(),
+            Pci::First {
+                len: 0x10000,
+                data: &[1, 2]
+            }
+        );
+        assert_eq!(
+            Pci::decode(&[0x30, 8, 0xF5]).unwrap(),
+            Pci::FlowControl {
+                status: FlowStatus::ContinueToSend,
+                block_size: 8,
+                st_min: StMin::from_hundred_micros(5)
+            }
+        );
+        assert!(Pci::decode(&[]).is_err());
+        assert!(Pci::decode(&[0x05, 1, 2]).is_err());
+        assert!(Pci::decode(&[0x00]).is_err());
+        assert!(Pci::decode(&[0x33, 0, 0]).is_err());
+        assert!(Pci::decode(&[0x40]).is_err());
+    }
+}
@@ -9,6 +9,8 @@ pub use _version::*;
 mod common;
 pub mod enums;
 pub mod frame;
+#[cfg(feature = "time")]
+pub mod isotp;
 pub mod util;
 
 pub use frame::Frame;
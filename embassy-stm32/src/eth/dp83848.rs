@@ -0,0 +1,131 @@
+//! Texas Instruments DP83848 Ethernet PHY
+
+use core::task::Context;
+
+#[cfg(feature = "time")]
+use embassy_time::Duration;
+
+use super::mii::{self, LinkPoll};
+use super::{Duplex, LinkSpeed, LinkStatus, StationManagement, PHY};
+#[cfg(feature = "exti")]
+use crate::exti::ExtiInput;
+
+const REG_PHYSTS: u8 = 0x10;
+const REG_MICR: u8 = 0x11;
+const REG_MISR: u8 = 0x12;
+const REG_EDCR: u8 = 0x1D;
+
+const PHYSTS_LINK: u16 = 1 << 0;
+const PHYSTS_SPEED_10: u16 = 1 << 1;
+const PHYSTS_FULL_DUPLEX: u16 = 1 << 2;
+const PHYSTS_AN_COMPLETE: u16 = 1 << 4;
+
+const MICR_INT_OE: u16 = 1 << 0;
+const MICR_INTEN: u16 = 1 << 1;
+
+const MISR_ANC_INT_EN: u16 = 1 << 2;
+const MISR_LINK_INT_EN: u16 = 1 << 5;
+
+const EDCR_ED_PWR_STATE: u16 = 1 << 10;
+const EDCR_ED_AUTO_DOWN: u16 = 1 << 13;
+const EDCR_ED_AUTO_UP: u16 = 1 << 14;
+const EDCR_ED_EN: u16 = 1 << 15;
+
+/// DP83848 Ethernet PHY driver.
+///
+/// The link state is polled periodically, or only when the PWR_DOWN/INT pin is asserted if the
+/// driver is created with [`new_with_interrupt`](Self::new_with_interrupt).
+pub struct Dp83848 {
+    phy_addr: u8,
+    poll: LinkPoll,
+    link: Option<LinkStatus>,
+}
+
+impl Dp83848 {
+    /// Create the driver for the PHY at address `phy_addr`.
+    pub fn new(phy_addr: u8) -> Self {
+        Self {
+            phy_addr,
+            poll: LinkPoll::new(),
+            link: None,
+        }
+    }
+
+    /// Create the driver for the PHY at address `phy_addr`, with its PWR_DOWN/INT pin connected to
+    /// `int`. The pin is switched to interrupt output mode on initialization.
+    #[cfg(feature = "exti")]
+    pub fn new_with_interrupt(phy_addr: u8, int: ExtiInput<'static>) -> Self {
+        Self {
+            phy_addr,
+            poll: LinkPoll::new_interrupt(int),
+            link: None,
+        }
+    }
+
+    /// Set the link polling interval, when not using the interrupt pin.
+    #[cfg(feature = "time")]
+    pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.poll.set_poll_interval(poll_interval)
+    }
+
+    /// Enable or disable energy detect mode. When enabled, the PHY powers down its transceiver
+    /// while no energy is detected on the line, and powers it up again automatically.
+    pub fn set_energy_detect<S: StationManagement>(&mut self, sm: &mut S, enable: bool) {
+        let val = match enable {
+            true => EDCR_ED_EN | EDCR_ED_AUTO_UP | EDCR_ED_AUTO_DOWN,
+            false => 0,
+        };
+        sm.smi_write(self.phy_addr, REG_EDCR, val);
+    }
+
+    /// Whether energy is detected on the line. Only meaningful while energy detect mode is enabled.
+    pub fn energy_detected<S: StationManagement>(&mut self, sm: &mut S) -> bool {
+        sm.smi_read(self.phy_addr, REG_EDCR) & EDCR_ED_PWR_STATE != 0
+    }
+
+    fn read_link<S: StationManagement>(&mut self, sm: &mut S) -> Option<LinkStatus> {
+        let physts = sm.smi_read(self.phy_addr, REG_PHYSTS);
+        if physts & PHYSTS_LINK == 0 {
+            return None;
+        }
+        if physts & PHYSTS_AN_COMPLETE == 0 && sm.smi_read(self.phy_addr, mii::REG_BCR) & mii::BCR_AN != 0 {
+            return None;
+        }
+        let speed = match physts & PHYSTS_SPEED_10 {
+            0 => LinkSpeed::Mbps100,
+            _ => LinkSpeed::Mbps10,
+        };
+        let duplex = match physts & PHYSTS_FULL_DUPLEX {
+            0 => Duplex::Half,
+            _ => Duplex::Full,
+        };
+        Some(LinkStatus { speed, duplex })
+    }
+}
+
+unsafe impl PHY for Dp83848 {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        mii::reset(sm, self.phy_addr);
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        if self.poll.has_interrupt() {
+            sm.smi_write(self.phy_addr, REG_MISR, MISR_LINK_INT_EN | MISR_ANC_INT_EN);
+            sm.smi_write(self.phy_addr, REG_MICR, MICR_INTEN | MICR_INT_OE);
+        }
+
+        mii::start_autonegotiation(sm, self.phy_addr);
+        self.poll.reset();
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> Option<LinkStatus> {
+        if self.poll.poll(cx) {
+            if self.poll.has_interrupt() {
+                // Reading the status deasserts the interrupt pin.
+                sm.smi_read(self.phy_addr, REG_MISR);
+            }
+            self.link = self.read_link(sm);
+        }
+        self.link
+    }
+}
@@ -3,11 +3,10 @@
 use core::task::Context;
 
 #[cfg(feature = "time")]
-use embassy_time::{Duration, Timer};
-#[cfg(feature = "time")]
-use futures_util::FutureExt;
+use embassy_time::Duration;
 
-use super::{StationManagement, PHY};
+use super::mii::{self, LinkPoll};
+use super::{LinkStatus, StationManagement, PHY};
 
 #[allow(dead_code)]
 mod phy_consts {
@@ -43,10 +42,12 @@ mod phy_consts {
 use self::phy_consts::*;
 
 /// Generic SMI Ethernet PHY implementation
+///
+/// Only the standard registers are used, so the speed and duplex mode are derived from the
+/// auto-negotiation advertisements.
 pub struct GenericSMI {
     phy_addr: u8,
-    #[cfg(feature = "time")]
-    poll_interval: Duration,
+    poll: LinkPoll,
 }
 
 impl GenericSMI {
@@ -54,8 +55,7 @@ impl GenericSMI {
     pub fn new(phy_addr: u8) -> Self {
         Self {
             phy_addr,
-            #[cfg(feature = "time")]
-            poll_interval: Duration::from_millis(500),
+            poll: LinkPoll::new(),
         }
     }
 }
@@ -78,26 +78,9 @@ unsafe impl PHY for GenericSMI {
         );
     }
 
-    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool {
-        #[cfg(not(feature = "time"))]
-        cx.waker().wake_by_ref();
-
-        #[cfg(feature = "time")]
-        let _ = Timer::after(self.poll_interval).poll_unpin(cx);
-
-        let bsr = sm.smi_read(self.phy_addr, PHY_REG_BSR);
-
-        // No link without autonegotiate
-        if bsr & PHY_REG_BSR_ANDONE == 0 {
-            return false;
-        }
-        // No link if link is down
-        if bsr & PHY_REG_BSR_UP == 0 {
-            return false;
-        }
-
-        // Got link
-        true
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> Option<LinkStatus> {
+        self.poll.poll(cx);
+        mii::link_status(sm, self.phy_addr)
     }
 }
 
@@ -106,7 +89,7 @@ impl GenericSMI {
     /// Set the SMI polling interval.
     #[cfg(feature = "time")]
     pub fn set_poll_interval(&mut self, poll_interval: Duration) {
-        self.poll_interval = poll_interval
+        self.poll.set_poll_interval(poll_interval)
     }
 
     // Writes a value to an extended PHY register in MMD address space
@@ -0,0 +1,169 @@
+//! Microchip KSZ8081 Ethernet PHY
+
+use core::task::Context;
+
+#[cfg(feature = "time")]
+use embassy_time::Duration;
+
+use super::mii::{self, LinkPoll};
+use super::{CableStatus, CableTest, Duplex, LinkSpeed, LinkStatus, StationManagement, PHY};
+#[cfg(feature = "exti")]
+use crate::exti::ExtiInput;
+
+const REG_EXPANDED_CTRL: u8 = 0x18;
+const REG_ICSR: u8 = 0x1B;
+const REG_LINKMD: u8 = 0x1D;
+const REG_PHY_CTRL1: u8 = 0x1E;
+const REG_PHY_CTRL2: u8 = 0x1F;
+
+const EXPANDED_CTRL_EDPD_DISABLED: u16 = 1 << 11;
+
+const ICSR_LINK_UP_EN: u16 = 1 << 8;
+const ICSR_LINK_DOWN_EN: u16 = 1 << 10;
+
+const LINKMD_TEST_EN: u16 = 1 << 15;
+
+const PHY_CTRL1_ENERGY_DETECT: u16 = 1 << 4;
+
+const PHY_CTRL2_MDIX_DISABLE: u16 = 1 << 13;
+
+/// Number of status reads before a cable test is considered failed.
+const CABLE_TEST_POLLS: u32 = 100_000;
+
+/// KSZ8081 Ethernet PHY driver.
+///
+/// The link state is polled periodically, or only when the INTRP pin is asserted if the driver is
+/// created with [`new_with_interrupt`](Self::new_with_interrupt).
+pub struct Ksz8081 {
+    phy_addr: u8,
+    poll: LinkPoll,
+    link: Option<LinkStatus>,
+}
+
+impl Ksz8081 {
+    /// Create the driver for the PHY at address `phy_addr`.
+    pub fn new(phy_addr: u8) -> Self {
+        Self {
+            phy_addr,
+            poll: LinkPoll::new(),
+            link: None,
+        }
+    }
+
+    /// Create the driver for the PHY at address `phy_addr`, with its INTRP pin connected to `int`.
+    ///
+    /// The pin must be configured as active low, which is the default.
+    #[cfg(feature = "exti")]
+    pub fn new_with_interrupt(phy_addr: u8, int: ExtiInput<'static>) -> Self {
+        Self {
+            phy_addr,
+            poll: LinkPoll::new_interrupt(int),
+            link: None,
+        }
+    }
+
+    /// Set the link polling interval, when not using the interrupt pin.
+    #[cfg(feature = "time")]
+    pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.poll.set_poll_interval(poll_interval)
+    }
+
+    /// Enable or disable energy detect power-down. When enabled, the PHY powers down its
+    /// transceiver while no energy is detected on the line.
+    pub fn set_energy_detect_power_down<S: StationManagement>(&mut self, sm: &mut S, enable: bool) {
+        let mut ctrl = sm.smi_read(self.phy_addr, REG_EXPANDED_CTRL);
+        if enable {
+            ctrl &= !EXPANDED_CTRL_EDPD_DISABLED;
+        } else {
+            ctrl |= EXPANDED_CTRL_EDPD_DISABLED;
+        }
+        sm.smi_write(self.phy_addr, REG_EXPANDED_CTRL, ctrl);
+    }
+
+    /// Whether energy is detected on the line.
+    pub fn energy_detected<S: StationManagement>(&mut self, sm: &mut S) -> bool {
+        sm.smi_read(self.phy_addr, REG_PHY_CTRL1) & PHY_CTRL1_ENERGY_DETECT != 0
+    }
+
+    /// Run a LinkMD cable test.
+    ///
+    /// This blocks until the test completes. The link goes down during the test, and
+    /// auto-negotiation is restarted afterwards.
+    pub fn cable_test<S: StationManagement>(&mut self, sm: &mut S) -> CableTest {
+        let ctrl2 = sm.smi_read(self.phy_addr, REG_PHY_CTRL2);
+
+        // LinkMD requires 100BASE-TX with auto MDI/MDI-X disabled.
+        sm.smi_write(self.phy_addr, mii::REG_BCR, mii::BCR_100M | mii::BCR_FD);
+        sm.smi_write(self.phy_addr, REG_PHY_CTRL2, ctrl2 | PHY_CTRL2_MDIX_DISABLE);
+        sm.smi_write(self.phy_addr, REG_LINKMD, LINKMD_TEST_EN);
+
+        let mut result = CableTest {
+            status: CableStatus::Unknown,
+            fault_distance_m: None,
+        };
+        for _ in 0..CABLE_TEST_POLLS {
+            let linkmd = sm.smi_read(self.phy_addr, REG_LINKMD);
+            if linkmd & LINKMD_TEST_EN == 0 {
+                result.status = match (linkmd >> 13) & 0b11 {
+                    0b00 => CableStatus::Normal,
+                    0b01 => CableStatus::Open,
+                    0b10 => CableStatus::Short,
+                    _ => CableStatus::Unknown,
+                };
+                if matches!(result.status, CableStatus::Open | CableStatus::Short) {
+                    // Distance to fault is approximately 0.38 m per count, with an offset of 13.
+                    let count = linkmd & 0x1FF;
+                    result.fault_distance_m = Some(count.saturating_sub(13) * 38 / 100);
+                }
+                break;
+            }
+        }
+
+        sm.smi_write(self.phy_addr, REG_PHY_CTRL2, ctrl2);
+        mii::start_autonegotiation(sm, self.phy_addr);
+        self.poll.reset();
+
+        result
+    }
+
+    fn read_link<S: StationManagement>(&mut self, sm: &mut S) -> Option<LinkStatus> {
+        if !mii::link_up(sm, self.phy_addr) {
+            return None;
+        }
+        let (speed, duplex) = match sm.smi_read(self.phy_addr, REG_PHY_CTRL1) & 0b111 {
+            0b001 => (LinkSpeed::Mbps10, Duplex::Half),
+            0b010 => (LinkSpeed::Mbps100, Duplex::Half),
+            0b101 => (LinkSpeed::Mbps10, Duplex::Full),
+            0b110 => (LinkSpeed::Mbps100, Duplex::Full),
+            // Still negotiating
+            _ => return None,
+        };
+        Some(LinkStatus { speed, duplex })
+    }
+}
+
+unsafe impl PHY for Ksz8081 {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        mii::reset(sm, self.phy_addr);
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        if self.poll.has_interrupt() {
+            sm.smi_write(self.phy_addr, REG_ICSR, ICSR_LINK_UP_EN | ICSR_LINK_DOWN_EN);
+        }
+
+        mii::start_autonegotiation(sm, self.phy_addr);
+        self.poll.reset();
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> Option<LinkStatus> {
+        if self.poll.poll(cx) {
+            if self.poll.has_interrupt() {
+                // Reading the flags deasserts INTRP.
+                sm.smi_read(self.phy_addr, REG_ICSR);
+            }
+            self.link = self.read_link(sm);
+        }
+        self.link
+    }
+}
@@ -0,0 +1,188 @@
+//! Microchip LAN8742A Ethernet PHY
+
+use core::task::Context;
+
+#[cfg(feature = "time")]
+use embassy_time::Duration;
+
+use super::mii::{self, LinkPoll};
+use super::{CableStatus, CableTest, Duplex, LinkSpeed, LinkStatus, StationManagement, PHY};
+#[cfg(feature = "exti")]
+use crate::exti::ExtiInput;
+
+const REG_CTL: u8 = 0x0D;
+const REG_ADDAR: u8 = 0x0E;
+const REG_MCSR: u8 = 0x11;
+const REG_TDR_CSR: u8 = 0x19;
+const REG_SCSIR: u8 = 0x1B;
+const REG_ISFR: u8 = 0x1D;
+const REG_IMR: u8 = 0x1E;
+const REG_PSCSR: u8 = 0x1F;
+
+const MMD_WUCSR: u16 = 0x8010;
+
+const MCSR_ENERGYON: u16 = 1 << 1;
+const MCSR_EDPWRDOWN: u16 = 1 << 13;
+
+const TDR_EN: u16 = 1 << 15;
+const TDR_AD_FILTER_EN: u16 = 1 << 14;
+const TDR_DONE: u16 = 1 << 8;
+
+const SCSIR_AMDIXCTRL: u16 = 1 << 15;
+const SCSIR_CH_SELECT: u16 = 1 << 13;
+
+const INT_LINK_DOWN: u16 = 1 << 4;
+const INT_AN_COMPLETE: u16 = 1 << 6;
+
+const PSCSR_100M: u16 = 1 << 3;
+const PSCSR_FULL_DUPLEX: u16 = 1 << 4;
+
+/// Number of status reads before a cable test is considered failed.
+const CABLE_TEST_POLLS: u32 = 100_000;
+
+/// LAN8742A Ethernet PHY driver.
+///
+/// The link state is polled periodically, or only when the nINT pin is asserted if the driver is
+/// created with [`new_with_interrupt`](Self::new_with_interrupt).
+pub struct Lan8742a {
+    phy_addr: u8,
+    poll: LinkPoll,
+    link: Option<LinkStatus>,
+}
+
+impl Lan8742a {
+    /// Create the driver for the PHY at address `phy_addr`.
+    pub fn new(phy_addr: u8) -> Self {
+        Self {
+            phy_addr,
+            poll: LinkPoll::new(),
+            link: None,
+        }
+    }
+
+    /// Create the driver for the PHY at address `phy_addr`, with its nINT pin connected to `int`.
+    #[cfg(feature = "exti")]
+    pub fn new_with_interrupt(phy_addr: u8, int: ExtiInput<'static>) -> Self {
+        Self {
+            phy_addr,
+            poll: LinkPoll::new_interrupt(int),
+            link: None,
+        }
+    }
+
+    /// Set the link polling interval, when not using the interrupt pin.
+    #[cfg(feature = "time")]
+    pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.poll.set_poll_interval(poll_interval)
+    }
+
+    /// Enable or disable energy detect power-down. When enabled, the PHY powers down its
+    /// transceiver while no energy is detected on the line.
+    pub fn set_energy_detect_power_down<S: StationManagement>(&mut self, sm: &mut S, enable: bool) {
+        let mut mcsr = sm.smi_read(self.phy_addr, REG_MCSR);
+        if enable {
+            mcsr |= MCSR_EDPWRDOWN;
+        } else {
+            mcsr &= !MCSR_EDPWRDOWN;
+        }
+        sm.smi_write(self.phy_addr, REG_MCSR, mcsr);
+    }
+
+    /// Whether energy is detected on the line.
+    pub fn energy_detected<S: StationManagement>(&mut self, sm: &mut S) -> bool {
+        sm.smi_read(self.phy_addr, REG_MCSR) & MCSR_ENERGYON != 0
+    }
+
+    /// Run a time-domain reflectometry cable test.
+    ///
+    /// This blocks until the test completes. The link goes down during the test, and
+    /// auto-negotiation is restarted afterwards.
+    ///
+    /// The LAN8742A reports the fault position in uncalibrated units, so no distance is returned.
+    pub fn cable_test<S: StationManagement>(&mut self, sm: &mut S) -> CableTest {
+        let scsir = sm.smi_read(self.phy_addr, REG_SCSIR);
+
+        // TDR requires 100BASE-TX full duplex with a fixed MDI channel.
+        sm.smi_write(self.phy_addr, mii::REG_BCR, mii::BCR_100M | mii::BCR_FD);
+        sm.smi_write(self.phy_addr, REG_SCSIR, (scsir | SCSIR_AMDIXCTRL) & !SCSIR_CH_SELECT);
+        sm.smi_write(self.phy_addr, REG_TDR_CSR, TDR_EN | TDR_AD_FILTER_EN);
+
+        let mut status = CableStatus::Unknown;
+        for _ in 0..CABLE_TEST_POLLS {
+            let tdr = sm.smi_read(self.phy_addr, REG_TDR_CSR);
+            if tdr & TDR_DONE != 0 {
+                status = match (tdr >> 9) & 0b11 {
+                    0b01 => CableStatus::Short,
+                    0b10 => CableStatus::Open,
+                    0b11 => CableStatus::Normal,
+                    _ => CableStatus::Unknown,
+                };
+                break;
+            }
+        }
+
+        sm.smi_write(self.phy_addr, REG_TDR_CSR, 0);
+        sm.smi_write(self.phy_addr, REG_SCSIR, scsir);
+        mii::start_autonegotiation(sm, self.phy_addr);
+        self.poll.reset();
+
+        CableTest {
+            status,
+            fault_distance_m: None,
+        }
+    }
+
+    // Writes a value to an extended PHY register in MMD address space
+    fn smi_write_ext<S: StationManagement>(&mut self, sm: &mut S, reg_addr: u16, reg_data: u16) {
+        sm.smi_write(self.phy_addr, REG_CTL, 0x0003); // set address
+        sm.smi_write(self.phy_addr, REG_ADDAR, reg_addr);
+        sm.smi_write(self.phy_addr, REG_CTL, 0x4003); // set data
+        sm.smi_write(self.phy_addr, REG_ADDAR, reg_data);
+    }
+
+    fn read_link<S: StationManagement>(&mut self, sm: &mut S) -> Option<LinkStatus> {
+        if !mii::link_up(sm, self.phy_addr) {
+            return None;
+        }
+        let pscsr = sm.smi_read(self.phy_addr, REG_PSCSR);
+        let speed = match pscsr & PSCSR_100M {
+            0 => LinkSpeed::Mbps10,
+            _ => LinkSpeed::Mbps100,
+        };
+        let duplex = match pscsr & PSCSR_FULL_DUPLEX {
+            0 => Duplex::Half,
+            _ => Duplex::Full,
+        };
+        Some(LinkStatus { speed, duplex })
+    }
+}
+
+unsafe impl PHY for Lan8742a {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        mii::reset(sm, self.phy_addr);
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        // Clear WU CSR
+        self.smi_write_ext(sm, MMD_WUCSR, 0);
+
+        if self.poll.has_interrupt() {
+            sm.smi_write(self.phy_addr, REG_IMR, INT_LINK_DOWN | INT_AN_COMPLETE);
+            sm.smi_read(self.phy_addr, REG_ISFR);
+        }
+
+        mii::start_autonegotiation(sm, self.phy_addr);
+        self.poll.reset();
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> Option<LinkStatus> {
+        if self.poll.poll(cx) {
+            if self.poll.has_interrupt() {
+                // Reading the flags deasserts nINT.
+                sm.smi_read(self.phy_addr, REG_ISFR);
+            }
+            self.link = self.read_link(sm);
+        }
+        self.link
+    }
+}
@@ -0,0 +1,175 @@
+//! Standard MII management registers (IEEE 802.3 clause 22), shared by the PHY drivers.
+
+use core::task::Context;
+#[cfg(feature = "exti")]
+use core::task::Poll;
+
+#[cfg(feature = "time")]
+use embassy_time::{Duration, Timer};
+#[cfg(feature = "time")]
+use futures_util::FutureExt;
+
+use super::{Duplex, LinkSpeed, LinkStatus, StationManagement};
+#[cfg(feature = "exti")]
+use crate::exti::ExtiInput;
+
+pub(crate) const REG_BCR: u8 = 0x00;
+pub(crate) const REG_BSR: u8 = 0x01;
+pub(crate) const REG_ANAR: u8 = 0x04;
+pub(crate) const REG_ANLPAR: u8 = 0x05;
+
+pub(crate) const BCR_FD: u16 = 1 << 8;
+pub(crate) const BCR_ANRST: u16 = 1 << 9;
+pub(crate) const BCR_AN: u16 = 1 << 12;
+pub(crate) const BCR_100M: u16 = 1 << 13;
+pub(crate) const BCR_RESET: u16 = 1 << 15;
+
+pub(crate) const BSR_UP: u16 = 1 << 2;
+pub(crate) const BSR_ANDONE: u16 = 1 << 5;
+
+const AN_10HD: u16 = 1 << 5;
+const AN_10FD: u16 = 1 << 6;
+const AN_100HD: u16 = 1 << 7;
+const AN_100FD: u16 = 1 << 8;
+
+/// Reset the PHY and wait for it to come out of reset.
+pub(crate) fn reset<S: StationManagement>(sm: &mut S, phy_addr: u8) {
+    sm.smi_write(phy_addr, REG_BCR, BCR_RESET);
+    while sm.smi_read(phy_addr, REG_BCR) & BCR_RESET == BCR_RESET {}
+}
+
+/// Enable and restart auto-negotiation.
+pub(crate) fn start_autonegotiation<S: StationManagement>(sm: &mut S, phy_addr: u8) {
+    sm.smi_write(phy_addr, REG_BCR, BCR_AN | BCR_ANRST | BCR_100M);
+}
+
+/// Read whether the link is up. With auto-negotiation enabled, the link is only reported up once
+/// it has completed.
+pub(crate) fn link_up<S: StationManagement>(sm: &mut S, phy_addr: u8) -> bool {
+    // The link status bit latches low, so read again to get the current state.
+    sm.smi_read(phy_addr, REG_BSR);
+    let bsr = sm.smi_read(phy_addr, REG_BSR);
+    if bsr & BSR_UP == 0 {
+        return false;
+    }
+    let bcr = sm.smi_read(phy_addr, REG_BCR);
+    bcr & BCR_AN == 0 || bsr & BSR_ANDONE != 0
+}
+
+/// Read the link status from the standard registers only.
+///
+/// With auto-negotiation, the mode is the best one advertised by both link partners. Otherwise, it's
+/// the mode forced in the control register.
+pub(crate) fn link_status<S: StationManagement>(sm: &mut S, phy_addr: u8) -> Option<LinkStatus> {
+    if !link_up(sm, phy_addr) {
+        return None;
+    }
+
+    let bcr = sm.smi_read(phy_addr, REG_BCR);
+    if bcr & BCR_AN == 0 {
+        return Some(LinkStatus {
+            speed: if bcr & BCR_100M != 0 {
+                LinkSpeed::Mbps100
+            } else {
+                LinkSpeed::Mbps10
+            },
+            duplex: if bcr & BCR_FD != 0 { Duplex::Full } else { Duplex::Half },
+        });
+    }
+
+    let common = sm.smi_read(phy_addr, REG_ANAR) & sm.smi_read(phy_addr, REG_ANLPAR);
+    let (speed, duplex) = if common & AN_100FD != 0 {
+        (LinkSpeed::Mbps100, Duplex::Full)
+    } else if common & AN_100HD != 0 {
+        (LinkSpeed::Mbps100, Duplex::Half)
+    } else if common & AN_10FD != 0 {
+        (LinkSpeed::Mbps10, Duplex::Full)
+    } else if common & AN_10HD != 0 {
+        (LinkSpeed::Mbps10, Duplex::Half)
+    } else {
+        return None;
+    };
+    Some(LinkStatus { speed, duplex })
+}
+
+/// Decides when a PHY driver must query the link status, either periodically or when its
+/// interrupt pin is asserted.
+pub(crate) struct LinkPoll {
+    #[cfg(feature = "time")]
+    poll_interval: Duration,
+    #[cfg(feature = "exti")]
+    interrupt: Option<ExtiInput<'static>>,
+    #[cfg(feature = "exti")]
+    queried: bool,
+}
+
+impl LinkPoll {
+    pub(crate) fn new() -> Self {
+        Self {
+            #[cfg(feature = "time")]
+            poll_interval: Duration::from_millis(500),
+            #[cfg(feature = "exti")]
+            interrupt: None,
+            #[cfg(feature = "exti")]
+            queried: false,
+        }
+    }
+
+    /// Wait for the active-low PHY interrupt pin instead of polling.
+    #[cfg(feature = "exti")]
+    pub(crate) fn new_interrupt(pin: ExtiInput<'static>) -> Self {
+        let mut this = Self::new();
+        this.interrupt = Some(pin);
+        this
+    }
+
+    #[cfg(feature = "time")]
+    pub(crate) fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.poll_interval = poll_interval;
+    }
+
+    /// Whether an interrupt pin is used.
+    pub(crate) fn has_interrupt(&self) -> bool {
+        #[cfg(feature = "exti")]
+        if self.interrupt.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// Force a query on the next poll, e.g. after the PHY was reinitialized.
+    pub(crate) fn reset(&mut self) {
+        #[cfg(feature = "exti")]
+        {
+            self.queried = false;
+        }
+    }
+
+    /// Returns whether the driver must query the PHY, and arranges for `cx` to be woken when it
+    /// should be polled again.
+    ///
+    /// When using the interrupt pin, the driver must clear the interrupt in the PHY when this
+    /// returns `true`.
+    pub(crate) fn poll(&mut self, cx: &mut Context) -> bool {
+        #[cfg(feature = "exti")]
+        if let Some(pin) = &mut self.interrupt {
+            let first = !core::mem::replace(&mut self.queried, true);
+            return match pin.poll_low(cx) {
+                Poll::Ready(()) => {
+                    // Poll again after the interrupt was cleared, to rearm the EXTI line.
+                    cx.waker().wake_by_ref();
+                    true
+                }
+                Poll::Pending => first,
+            };
+        }
+
+        #[cfg(not(feature = "time"))]
+        cx.waker().wake_by_ref();
+
+        #[cfg(feature = "time")]
+        let _ = Timer::after(self.poll_interval).poll_unpin(cx);
+
+        true
+    }
+}
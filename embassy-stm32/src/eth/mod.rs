@@ -4,7 +4,13 @@
 #[cfg_attr(any(eth_v1a, eth_v1b, eth_v1c), path = "v1/mod.rs")]
 #[cfg_attr(eth_v2, path = "v2/mod.rs")]
 mod _version;
+pub mod dp83848;
 pub mod generic_smi;
+pub mod ksz8081;
+pub mod lan8742a;
+mod mii;
+#[cfg(not(eth_v1a))]
+pub mod ptp;
 
 use core::mem::MaybeUninit;
 use core::task::Context;
@@ -79,6 +85,8 @@ impl<'d, T: Instance, P: PHY> embassy_net_driver::Driver for Ethernet<'d, T, P>
 
     fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
         WAKER.register(cx.waker());
+        #[cfg(not(eth_v1a))]
+        self.tx.collect_timestamps();
         if self.rx.available().is_some() && self.tx.available().is_some() {
             Some((RxToken { rx: &mut self.rx }, TxToken { tx: &mut self.tx }))
         } else {
@@ -88,6 +96,8 @@ impl<'d, T: Instance, P: PHY> embassy_net_driver::Driver for Ethernet<'d, T, P>
 
     fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
         WAKER.register(cx.waker());
+        #[cfg(not(eth_v1a))]
+        self.tx.collect_timestamps();
         if self.tx.available().is_some() {
             Some(TxToken { tx: &mut self.tx })
         } else {
@@ -103,10 +113,17 @@ impl<'d, T: Instance, P: PHY> embassy_net_driver::Driver for Ethernet<'d, T, P>
     }
 
     fn link_state(&mut self, cx: &mut Context) -> LinkState {
-        if self.phy.poll_link(&mut self.station_management, cx) {
-            LinkState::Up
-        } else {
-            LinkState::Down
+        let status = self.phy.poll_link(&mut self.station_management, cx);
+        if let Some(status) = status {
+            if self.link_status != Some(status) {
+                Self::configure_link(status);
+            }
+        }
+        self.link_status = status;
+
+        match status {
+            Some(_) => LinkState::Up,
+            None => LinkState::Down,
         }
     }
 
@@ -115,6 +132,30 @@ impl<'d, T: Instance, P: PHY> embassy_net_driver::Driver for Ethernet<'d, T, P>
     }
 }
 
+impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
+    /// Link status last reported by the PHY, or `None` if the link is down.
+    pub fn link_status(&self) -> Option<LinkStatus> {
+        self.link_status
+    }
+
+    /// Get the PHY driver together with the station management interface, to use vendor-specific
+    /// PHY features such as cable diagnostics.
+    pub fn phy_mut(&mut self) -> (&mut P, &mut EthernetStationManagement<T>) {
+        (&mut self.phy, &mut self.station_management)
+    }
+
+    /// Enable IEEE 1588 timestamping of PTP messages, and start the PTP clock at zero.
+    ///
+    /// Returns a handle to the PTP clock, and to the timestamps of sent and received messages.
+    #[cfg(not(eth_v1a))]
+    pub fn enable_ptp(&mut self) -> ptp::PtpClock<T> {
+        let clock = ptp::PtpClock::init();
+        self.tx.enable_timestamping();
+        self.rx.enable_timestamping();
+        clock
+    }
+}
+
 /// `embassy-net` RX token.
 pub struct RxToken<'a, 'd> {
     rx: &'a mut RDesRing<'d>,
@@ -125,6 +166,11 @@ impl<'a, 'd> embassy_net_driver::RxToken for RxToken<'a, 'd> {
     where
         F: FnOnce(&mut [u8]) -> R,
     {
+        #[cfg(not(eth_v1a))]
+        if let Some(timestamp) = self.rx.timestamp() {
+            ptp::record_rx(unwrap!(self.rx.available()), timestamp);
+        }
+
         // NOTE(unwrap): we checked the queue wasn't full when creating the token.
         let pkt = unwrap!(self.rx.available());
         let r = f(pkt);
@@ -163,6 +209,60 @@ pub unsafe trait StationManagement {
     fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16);
 }
 
+/// Link speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkSpeed {
+    /// 10 Mbit/s.
+    Mbps10,
+    /// 100 Mbit/s.
+    Mbps100,
+}
+
+/// Duplex mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Duplex {
+    /// Half duplex.
+    Half,
+    /// Full duplex.
+    Full,
+}
+
+/// Negotiated parameters of an established link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkStatus {
+    /// Link speed.
+    pub speed: LinkSpeed,
+    /// Duplex mode.
+    pub duplex: Duplex,
+}
+
+/// Result of a PHY cable test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CableStatus {
+    /// The cable is correctly terminated.
+    Normal,
+    /// Open circuit.
+    Open,
+    /// Short circuit.
+    Short,
+    /// The PHY couldn't determine the cable state.
+    Unknown,
+}
+
+/// Result of a PHY cable test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CableTest {
+    /// Cable state.
+    pub status: CableStatus,
+    /// Approximate distance to the fault in meters, if the PHY reports it.
+    pub fault_distance_m: Option<u16>,
+}
+
 /// Traits for an Ethernet PHY
 ///
 /// # Safety
@@ -173,8 +273,12 @@ pub unsafe trait PHY {
     fn phy_reset<S: StationManagement>(&mut self, sm: &mut S);
     /// PHY initialisation.
     fn phy_init<S: StationManagement>(&mut self, sm: &mut S);
-    /// Poll link to see if it is up and FD with 100Mbps
-    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool;
+    /// Poll the link state.
+    ///
+    /// Returns the negotiated speed and duplex mode if the link is up, or `None` if it's down. The
+    /// MAC is reconfigured whenever the returned status changes. The waker in `cx` must be woken
+    /// when the link state may have changed.
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> Option<LinkStatus>;
 }
 
 trait SealedInstance {
@@ -208,3 +312,4 @@ pin_trait!(TXD1Pin, Instance);
 pin_trait!(TXD2Pin, Instance);
 pin_trait!(TXD3Pin, Instance);
 pin_trait!(TXEnPin, Instance);
+pin_trait!(PPSOutPin, Instance);
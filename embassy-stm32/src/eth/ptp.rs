@@ -0,0 +1,355 @@
+//! IEEE 1588 Precision Time Protocol (PTP) support.
+//!
+//! Once enabled with [`Ethernet::enable_ptp`](super::Ethernet::enable_ptp), the MAC timestamps
+//! PTPv2 messages sent and received over UDP (IPv4 or IPv6) or directly over Ethernet, using its
+//! system time, which can be read, set and disciplined through [`PtpClock`].
+//!
+//! Timestamps are matched to messages by their [`MessageId`], so they can be used with any
+//! `embassy-net` socket: after receiving a PTP message, parse its ID with [`MessageId::parse`] and
+//! look up its reception time with [`PtpClock::rx_timestamp`]. After sending an event message, wait
+//! for its transmission time with [`PtpClock::tx_timestamp`].
+//!
+//! Timestamping is not available on the STM32F107 Ethernet MAC.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::Poll;
+
+use embassy_hal_internal::{into_ref, PeripheralRef};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use super::{Instance, PPSOutPin};
+use crate::gpio::{AfType, AnyPin, OutputType, SealedPin as _, Speed};
+use crate::Peripheral;
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+/// Number of timestamps kept for each direction. Older timestamps are dropped when a new one
+/// arrives.
+const QUEUE_LEN: usize = 8;
+
+/// PTP system time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timestamp {
+    /// Seconds.
+    pub seconds: u32,
+    /// Nanoseconds, always less than 1 000 000 000.
+    pub nanoseconds: u32,
+}
+
+impl Timestamp {
+    /// Create a timestamp from a number of nanoseconds.
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self {
+            seconds: (nanos / NANOS_PER_SEC as u64) as u32,
+            nanoseconds: (nanos % NANOS_PER_SEC as u64) as u32,
+        }
+    }
+
+    /// Total number of nanoseconds.
+    pub const fn as_nanos(&self) -> u64 {
+        self.seconds as u64 * NANOS_PER_SEC as u64 + self.nanoseconds as u64
+    }
+}
+
+/// Identifies a PTP message, for matching it with its timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MessageId {
+    /// Message type, e.g. 0 for Sync or 1 for Delay_Req.
+    pub message_type: u8,
+    /// Sequence ID.
+    pub sequence_id: u16,
+    /// Identity of the sending port: clock identity followed by the port number.
+    pub source_port_identity: [u8; 10],
+}
+
+impl MessageId {
+    /// Parse the ID of a PTPv2 message, i.e. the payload of a UDP datagram or Ethernet frame.
+    ///
+    /// Returns `None` if `msg` is not a PTPv2 message.
+    pub fn parse(msg: &[u8]) -> Option<Self> {
+        if msg.len() < 34 || msg[1] & 0x0F != 2 {
+            return None;
+        }
+        Some(Self {
+            message_type: msg[0] & 0x0F,
+            sequence_id: u16::from_be_bytes([msg[30], msg[31]]),
+            source_port_identity: msg[20..30].try_into().unwrap(),
+        })
+    }
+
+    /// Parse the ID of a PTPv2 message carried in an Ethernet frame, directly or over UDP.
+    pub(crate) fn from_frame(frame: &[u8]) -> Option<Self> {
+        const ETHERTYPE_VLAN: u16 = 0x8100;
+        const ETHERTYPE_IPV4: u16 = 0x0800;
+        const ETHERTYPE_IPV6: u16 = 0x86DD;
+        const ETHERTYPE_PTP: u16 = 0x88F7;
+        const IP_PROTO_UDP: u8 = 17;
+
+        let mut offset = 12;
+        let mut ethertype = u16::from_be_bytes(frame.get(offset..offset + 2)?.try_into().unwrap());
+        offset += 2;
+        if ethertype == ETHERTYPE_VLAN {
+            offset += 2;
+            ethertype = u16::from_be_bytes(frame.get(offset..offset + 2)?.try_into().unwrap());
+            offset += 2;
+        }
+
+        let udp = match ethertype {
+            ETHERTYPE_PTP => return Self::parse(&frame[offset..]),
+            ETHERTYPE_IPV4 => {
+                let ihl = (*frame.get(offset)? & 0x0F) as usize * 4;
+                if *frame.get(offset + 9)? != IP_PROTO_UDP {
+                    return None;
+                }
+                offset + ihl
+            }
+            ETHERTYPE_IPV6 => {
+                // Extension headers are not supported.
+                if *frame.get(offset + 6)? != IP_PROTO_UDP {
+                    return None;
+                }
+                offset + 40
+            }
+            _ => return None,
+        };
+
+        let dst_port = u16::from_be_bytes(frame.get(udp + 2..udp + 4)?.try_into().unwrap());
+        if dst_port != 319 && dst_port != 320 {
+            return None;
+        }
+        Self::parse(frame.get(udp + 8..)?)
+    }
+}
+
+struct TimestampQueue {
+    entries: [Option<(MessageId, Timestamp)>; QUEUE_LEN],
+    next: usize,
+}
+
+impl TimestampQueue {
+    const fn new() -> Self {
+        Self {
+            entries: [None; QUEUE_LEN],
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, id: MessageId, timestamp: Timestamp) {
+        self.entries[self.next] = Some((id, timestamp));
+        self.next = (self.next + 1) % QUEUE_LEN;
+    }
+
+    fn take(&mut self, id: &MessageId) -> Option<Timestamp> {
+        let entry = self.entries.iter_mut().find(|e| matches!(e, Some((i, _)) if i == id))?;
+        entry.take().map(|(_, ts)| ts)
+    }
+}
+
+static RX_TIMESTAMPS: Mutex<CriticalSectionRawMutex, RefCell<TimestampQueue>> =
+    Mutex::new(RefCell::new(TimestampQueue::new()));
+static TX_TIMESTAMPS: Mutex<CriticalSectionRawMutex, RefCell<TimestampQueue>> =
+    Mutex::new(RefCell::new(TimestampQueue::new()));
+static TX_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Addend programmed for the nominal clock frequency.
+pub(crate) static BASE_ADDEND: AtomicU32 = AtomicU32::new(0);
+
+/// Record the reception timestamp of a frame, if it's a PTP message.
+pub(crate) fn record_rx(frame: &[u8], timestamp: Timestamp) {
+    if let Some(id) = MessageId::from_frame(frame) {
+        RX_TIMESTAMPS.lock(|q| q.borrow_mut().push(id, timestamp));
+    }
+}
+
+/// Record the transmission timestamp of a frame, if it's a PTP message.
+pub(crate) fn record_tx(frame: &[u8], timestamp: Timestamp) {
+    if let Some(id) = MessageId::from_frame(frame) {
+        TX_TIMESTAMPS.lock(|q| q.borrow_mut().push(id, timestamp));
+        TX_WAKER.wake();
+    }
+}
+
+/// Compute the sub-second increment in nanoseconds and the addend for the fine update method,
+/// clocking the system time at about half the PTP clock frequency.
+pub(crate) fn clock_params(ptp_clock_hz: u32) -> (u8, u32) {
+    let increment = (2 * NANOS_PER_SEC).div_ceil(ptp_clock_hz);
+    assert!(increment <= u8::MAX as u32, "PTP clock frequency too low");
+    let target_hz = NANOS_PER_SEC as u64 / increment as u64;
+    let addend = ((target_hz << 32) / ptp_clock_hz as u64) as u32;
+    (increment as u8, addend)
+}
+
+/// Split a signed offset in nanoseconds into its magnitude in seconds and nanoseconds, and whether
+/// it's negative.
+pub(crate) fn split_offset(offset_ns: i64) -> (u32, u32, bool) {
+    let abs = offset_ns.unsigned_abs();
+    let ts = Timestamp::from_nanos(abs);
+    (ts.seconds, ts.nanoseconds, offset_ns < 0)
+}
+
+/// Handle to the PTP system time of the Ethernet MAC.
+///
+/// Obtained from [`Ethernet::enable_ptp`](super::Ethernet::enable_ptp). It doesn't borrow the
+/// Ethernet driver, so it can be used while the driver is owned by the network stack.
+pub struct PtpClock<T: Instance> {
+    pub(crate) _peri: PhantomData<T>,
+}
+
+impl<T: Instance> Clone for PtpClock<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Instance> Copy for PtpClock<T> {}
+
+impl<T: Instance> PtpClock<T> {
+    /// Adjust the clock frequency by `ppb` parts per billion relative to its nominal frequency.
+    pub fn adjust_frequency(&self, ppb: i32) {
+        let base = BASE_ADDEND.load(Ordering::Relaxed) as i64;
+        let addend = base + base * ppb as i64 / NANOS_PER_SEC as i64;
+        self.set_addend(addend.clamp(0, u32::MAX as i64) as u32);
+    }
+
+    /// Take the reception timestamp of a message.
+    ///
+    /// Returns `None` if the message wasn't timestamped, or its timestamp was already taken or
+    /// dropped to make room for newer ones.
+    pub fn rx_timestamp(&self, id: &MessageId) -> Option<Timestamp> {
+        RX_TIMESTAMPS.lock(|q| q.borrow_mut().take(id))
+    }
+
+    /// Wait for the transmission timestamp of a message.
+    ///
+    /// This never completes if the message isn't sent, so use a timeout.
+    pub async fn tx_timestamp(&self, id: &MessageId) -> Timestamp {
+        poll_fn(|cx| {
+            TX_WAKER.register(cx.waker());
+            match TX_TIMESTAMPS.lock(|q| q.borrow_mut().take(id)) {
+                Some(ts) => Poll::Ready(ts),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// Pulse-per-second output.
+///
+/// Outputs a signal synchronized with the PTP system time on the PPS_OUT pin.
+pub struct PpsOutput<'d> {
+    pin: PeripheralRef<'d, AnyPin>,
+}
+
+impl<'d> PpsOutput<'d> {
+    /// Start the output on `pin`.
+    ///
+    /// The signal frequency is 2^`exponent` Hz, with a 50% duty cycle. An exponent of 0 outputs a
+    /// short pulse at the start of each second instead.
+    pub fn new<T: Instance>(
+        _clock: &PtpClock<T>,
+        pin: impl Peripheral<P = impl PPSOutPin<T>> + 'd,
+        exponent: u8,
+    ) -> Self {
+        assert!(exponent <= 15);
+        into_ref!(pin);
+
+        PtpClock::<T>::set_pps_frequency(exponent);
+        critical_section::with(|_| {
+            pin.set_as_af(pin.af_num(), AfType::output(OutputType::PushPull, Speed::VeryHigh));
+        });
+
+        Self { pin: pin.map_into() }
+    }
+}
+
+impl<'d> Drop for PpsOutput<'d> {
+    fn drop(&mut self) {
+        critical_section::with(|_| self.pin.set_as_disconnected());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ptp_message(message_type: u8, sequence_id: u16) -> [u8; 44] {
+        let mut msg = [0u8; 44];
+        msg[0] = message_type;
+        msg[1] = 2;
+        msg[20..30].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 0, 1]);
+        msg[30..32].copy_from_slice(&sequence_id.to_be_bytes());
+        msg
+    }
+
+    #[test]
+    fn parse_udp_ipv4() {
+        let msg = ptp_message(1, 0x1234);
+        let mut frame = [0u8; 14 + 20 + 8 + 44];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame[14] = 0x45;
+        frame[14 + 9] = 17;
+        frame[34 + 2..34 + 4].copy_from_slice(&319u16.to_be_bytes());
+        frame[42..].copy_from_slice(&msg);
+
+        let id = MessageId::from_frame(&frame).unwrap();
+        assert_eq!(id, MessageId::parse(&msg).unwrap());
+        assert_eq!((id.message_type, id.sequence_id), (1, 0x1234));
+
+        // Other UDP ports are ignored.
+        frame[34 + 2..34 + 4].copy_from_slice(&53u16.to_be_bytes());
+        assert_eq!(MessageId::from_frame(&frame), None);
+    }
+
+    #[test]
+    fn parse_ethernet_vlan() {
+        let msg = ptp_message(0, 7);
+        let mut frame = [0u8; 18 + 44];
+        frame[12..14].copy_from_slice(&[0x81, 0x00]);
+        frame[16..18].copy_from_slice(&[0x88, 0xF7]);
+        frame[18..].copy_from_slice(&msg);
+        assert_eq!(MessageId::from_frame(&frame).unwrap().sequence_id, 7);
+
+        // PTPv1 is not supported, nor are truncated frames.
+        frame[19] = 1;
+        assert_eq!(MessageId::from_frame(&frame), None);
+        assert_eq!(MessageId::from_frame(&frame[..30]), None);
+    }
+
+    #[test]
+    fn queue() {
+        let id = |seq| MessageId::parse(&ptp_message(0, seq)).unwrap();
+        let ts = |s| Timestamp {
+            seconds: s,
+            nanoseconds: 0,
+        };
+
+        let mut q = TimestampQueue::new();
+        for seq in 0..QUEUE_LEN as u16 + 2 {
+            q.push(id(seq), ts(seq as u32));
+        }
+        assert_eq!(q.take(&id(0)), None);
+        assert_eq!(q.take(&id(2)), Some(ts(2)));
+        assert_eq!(q.take(&id(2)), None);
+        assert_eq!(q.take(&id(QUEUE_LEN as u16 + 1)), Some(ts(QUEUE_LEN as u32 + 1)));
+    }
+
+    #[test]
+    fn clock() {
+        // 168 MHz: 12 ns increments at 83.33 MHz.
+        let (increment, addend) = clock_params(168_000_000);
+        assert_eq!(increment, 12);
+        assert_eq!(addend, ((83_333_333u64 << 32) / 168_000_000) as u32);
+
+        assert_eq!(split_offset(-1_500_000_000), (1, 500_000_000, true));
+        assert_eq!(split_offset(20), (0, 20, false));
+        assert_eq!(Timestamp::from_nanos(3_000_000_007).as_nanos(), 3_000_000_007);
+    }
+}
@@ -1,5 +1,7 @@
 // The v1c ethernet driver was ported to embassy from the awesome stm32-eth project (https://github.com/stm32-rs/stm32-eth).
 
+#[cfg(not(eth_v1a))]
+mod ptp;
 mod rx_desc;
 mod tx_desc;
 
@@ -55,6 +57,7 @@ pub struct Ethernet<'d, T: Instance, P: PHY> {
     pub(crate) phy: P,
     pub(crate) station_management: EthernetStationManagement<T>,
     pub(crate) mac_addr: [u8; 6],
+    pub(crate) link_status: Option<LinkStatus>,
 }
 
 #[cfg(eth_v1a)]
@@ -186,7 +189,9 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
         });
 
         dma.dmabmr().modify(|w| {
-            w.set_pbl(Pbl::PBL32) // programmable burst length - 32 ?
+            w.set_pbl(Pbl::PBL32); // programmable burst length - 32 ?
+            #[cfg(not(eth_v1a))]
+            w.set_edfe(true); // enhanced descriptors, needed for timestamps
         });
 
         // TODO MTU size setting not found for v1 ethernet, check if correct
@@ -228,6 +233,7 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
                 clock_range: clock_range,
             },
             mac_addr,
+            link_status: None,
             tx: TDesRing::new(&mut queue.tx_desc, &mut queue.tx_buf),
             rx: RDesRing::new(&mut queue.rx_desc, &mut queue.rx_buf),
         };
@@ -264,6 +270,20 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
 
         this
     }
+
+    /// Configure the MAC for the speed and duplex mode negotiated by the PHY.
+    pub(crate) fn configure_link(status: LinkStatus) {
+        T::regs().ethernet_mac().maccr().modify(|w| {
+            w.set_fes(match status.speed {
+                LinkSpeed::Mbps10 => Fes::FES10,
+                LinkSpeed::Mbps100 => Fes::FES100,
+            });
+            w.set_dm(match status.duplex {
+                Duplex::Half => Dm::HALFDUPLEX,
+                Duplex::Full => Dm::FULLDUPLEX,
+            });
+        });
+    }
 }
 
 /// Ethernet station management interface.
@@ -0,0 +1,99 @@
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering;
+
+use stm32_metapac::eth::vals::Tstim;
+
+use crate::eth::ptp::{self, PtpClock, Timestamp};
+use crate::eth::Instance;
+use crate::rcc::SealedRccPeripheral;
+
+impl<T: Instance> PtpClock<T> {
+    /// Start the system time at zero and enable timestamping.
+    pub(crate) fn init() -> Self {
+        let mac = T::regs().ethernet_mac();
+        let ptp = T::regs().ethernet_ptp();
+
+        // The timestamp trigger interrupt is not used.
+        mac.macimr().modify(|w| w.set_tstim(Tstim::MASKED));
+
+        ptp.ptptscr().write(|w| {
+            w.set_tse(true);
+            w.set_tsfcu(true); // fine update
+            w.set_tsssr(true); // sub-seconds in nanoseconds
+            w.set_tsptppsv2e(true);
+            w.set_tssptpoefe(true); // PTP over Ethernet
+            w.set_tssipv4fe(true);
+            w.set_tssipv6fe(true);
+            w.set_tscnt(0b01); // snapshot both Sync and Delay_Req
+        });
+
+        let (increment, addend) = ptp::clock_params(<T as SealedRccPeripheral>::frequency().0);
+        ptp.ptpssir().write(|w| w.set_stssi(increment));
+        ptp::BASE_ADDEND.store(addend, Ordering::Relaxed);
+
+        let this = Self { _peri: PhantomData };
+        this.set_addend(addend);
+        this.set_time(Timestamp::default());
+        this
+    }
+
+    /// Read the current time.
+    pub fn now(&self) -> Timestamp {
+        let ptp = T::regs().ethernet_ptp();
+        loop {
+            let seconds = ptp.ptptshr().read().sts();
+            let nanoseconds = ptp.ptptslr().read().stss();
+            // Retry if the seconds rolled over between the two reads.
+            if ptp.ptptshr().read().sts() == seconds {
+                return Timestamp { seconds, nanoseconds };
+            }
+        }
+    }
+
+    /// Set the current time.
+    pub fn set_time(&self, time: Timestamp) {
+        let ptp = T::regs().ethernet_ptp();
+        ptp.ptptshur().write(|w| w.set_tsus(time.seconds));
+        ptp.ptptslur().write(|w| w.set_tsuss(time.nanoseconds));
+        ptp.ptptscr().modify(|w| w.set_tssti(true));
+        while ptp.ptptscr().read().tssti() {}
+    }
+
+    /// Add a signed offset in nanoseconds to the current time, without stopping the clock.
+    pub fn adjust_time(&self, offset_ns: i64) {
+        let ptp = T::regs().ethernet_ptp();
+        let (seconds, mut nanoseconds, negative) = ptp::split_offset(offset_ns);
+        if negative && nanoseconds != 0 {
+            nanoseconds = 1_000_000_000 - nanoseconds;
+        }
+        ptp.ptptshur().write(|w| w.set_tsus(seconds));
+        ptp.ptptslur().write(|w| {
+            w.set_tsuss(nanoseconds);
+            w.set_tsupns(negative);
+        });
+        ptp.ptptscr().modify(|w| w.set_tsstu(true));
+        while ptp.ptptscr().read().tsstu() {}
+    }
+
+    /// Current frequency adjustment addend.
+    pub fn addend(&self) -> u32 {
+        T::regs().ethernet_ptp().ptptsar().read().tsa()
+    }
+
+    /// Set the frequency adjustment addend.
+    ///
+    /// The system time advances by the sub-second increment each time adding this value to a
+    /// 32-bit accumulator on every PTP clock cycle overflows it.
+    pub fn set_addend(&self, addend: u32) {
+        let ptp = T::regs().ethernet_ptp();
+        ptp.ptptsar().write(|w| w.set_tsa(addend));
+        ptp.ptptscr().modify(|w| w.set_ttsaru(true));
+        while ptp.ptptscr().read().ttsaru() {}
+    }
+
+    pub(crate) fn set_pps_frequency(exponent: u8) {
+        // PTPPPSCR is described as read-only in the PAC, write PPSFREQ directly.
+        let reg = T::regs().ethernet_ptp().ptpppscr().as_ptr() as *mut u32;
+        unsafe { reg.write_volatile(exponent as u32 & 0x0F) };
+    }
+}
@@ -15,6 +15,8 @@ mod rx_consts {
     pub const RXDESC_0_LS: u32 = 1 << 8;
     /// Error summary
     pub const RXDESC_0_ES: u32 = 1 << 15;
+    /// Timestamp valid
+    pub const RXDESC_0_TSV: u32 = 1 << 7;
     /// Frame length
     pub const RXDESC_0_FL_MASK: u32 = 0x3FFF;
     pub const RXDESC_0_FL_SHIFT: usize = 16;
@@ -29,6 +31,8 @@ mod rx_consts {
 use rx_consts::*;
 
 use super::Packet;
+#[cfg(not(eth_v1a))]
+use crate::eth::ptp::Timestamp;
 
 /// Receive Descriptor representation
 ///
@@ -36,12 +40,23 @@ use super::Packet;
 /// * rdes1: allocated buffer length
 /// * rdes2: data buffer address
 /// * rdes3: next descriptor address
+/// * rdes4: extended status (enhanced descriptors only)
+/// * rdes5: reserved (enhanced descriptors only)
+/// * rdes6, rdes7: receive timestamp (enhanced descriptors only)
 #[repr(C)]
 pub(crate) struct RDes {
     rdes0: VolatileCell<u32>,
     rdes1: VolatileCell<u32>,
     rdes2: VolatileCell<u32>,
     rdes3: VolatileCell<u32>,
+    #[cfg(not(eth_v1a))]
+    _rdes4: VolatileCell<u32>,
+    #[cfg(not(eth_v1a))]
+    _rdes5: VolatileCell<u32>,
+    #[cfg(not(eth_v1a))]
+    rdes6: VolatileCell<u32>,
+    #[cfg(not(eth_v1a))]
+    rdes7: VolatileCell<u32>,
 }
 
 impl RDes {
@@ -51,6 +66,14 @@ impl RDes {
             rdes1: VolatileCell::new(0),
             rdes2: VolatileCell::new(0),
             rdes3: VolatileCell::new(0),
+            #[cfg(not(eth_v1a))]
+            _rdes4: VolatileCell::new(0),
+            #[cfg(not(eth_v1a))]
+            _rdes5: VolatileCell::new(0),
+            #[cfg(not(eth_v1a))]
+            rdes6: VolatileCell::new(0),
+            #[cfg(not(eth_v1a))]
+            rdes7: VolatileCell::new(0),
         }
     }
 
@@ -134,6 +157,8 @@ pub(crate) struct RDesRing<'a> {
     descriptors: &'a mut [RDes],
     buffers: &'a mut [Packet<RX_BUFFER_SIZE>],
     index: usize,
+    #[cfg(not(eth_v1a))]
+    timestamping: bool,
 }
 
 impl<'a> RDesRing<'a> {
@@ -155,9 +180,17 @@ impl<'a> RDesRing<'a> {
             descriptors,
             buffers,
             index: 0,
+            #[cfg(not(eth_v1a))]
+            timestamping: false,
         }
     }
 
+    /// Enable reporting of reception timestamps.
+    #[cfg(not(eth_v1a))]
+    pub(crate) fn enable_timestamping(&mut self) {
+        self.timestamping = true;
+    }
+
     pub(crate) fn demand_poll(&self) {
         ETH.ethernet_dma().dmarpdr().write(|w| w.set_rpd(Rpd::POLL));
     }
@@ -214,6 +247,19 @@ impl<'a> RDesRing<'a> {
         return Some(&mut self.buffers[self.index].0[..len]);
     }
 
+    /// Reception timestamp of the packet previously returned by `available`, if any.
+    #[cfg(not(eth_v1a))]
+    pub(crate) fn timestamp(&self) -> Option<Timestamp> {
+        let descriptor = &self.descriptors[self.index];
+        if !self.timestamping || descriptor.rdes0.get() & RXDESC_0_TSV == 0 {
+            return None;
+        }
+        Some(Timestamp {
+            seconds: descriptor.rdes7.get(),
+            nanoseconds: descriptor.rdes6.get(),
+        })
+    }
+
     /// Pop the packet previously returned by `available`.
     pub(crate) fn pop_packet(&mut self) {
         let descriptor = &mut self.descriptors[self.index];
@@ -10,6 +10,10 @@ use crate::pac::ETH;
 mod tx_consts {
     pub const TXDESC_0_OWN: u32 = 1 << 31;
     pub const TXDESC_0_IOC: u32 = 1 << 30;
+    // Transmit timestamp enable
+    pub const TXDESC_0_TTSE: u32 = 1 << 25;
+    // Transmit timestamp status
+    pub const TXDESC_0_TTSS: u32 = 1 << 17;
     // First segment of frame
     pub const TXDESC_0_FS: u32 = 1 << 28;
     // Last segment of frame
@@ -28,6 +32,8 @@ mod tx_consts {
 use tx_consts::*;
 
 use super::Packet;
+#[cfg(not(eth_v1a))]
+use crate::eth::ptp::{self, MessageId, Timestamp};
 
 /// Transmit Descriptor representation
 ///
@@ -35,12 +41,22 @@ use super::Packet;
 /// * tdes1: buffer lengths
 /// * tdes2: data buffer address
 /// * tdes3: next descriptor address
+/// * tdes4, tdes5: reserved (enhanced descriptors only)
+/// * tdes6, tdes7: transmit timestamp (enhanced descriptors only)
 #[repr(C)]
 pub(crate) struct TDes {
     tdes0: VolatileCell<u32>,
     tdes1: VolatileCell<u32>,
     tdes2: VolatileCell<u32>,
     tdes3: VolatileCell<u32>,
+    #[cfg(not(eth_v1a))]
+    _tdes4: VolatileCell<u32>,
+    #[cfg(not(eth_v1a))]
+    _tdes5: VolatileCell<u32>,
+    #[cfg(not(eth_v1a))]
+    tdes6: VolatileCell<u32>,
+    #[cfg(not(eth_v1a))]
+    tdes7: VolatileCell<u32>,
 }
 
 impl TDes {
@@ -50,6 +66,14 @@ impl TDes {
             tdes1: VolatileCell::new(0),
             tdes2: VolatileCell::new(0),
             tdes3: VolatileCell::new(0),
+            #[cfg(not(eth_v1a))]
+            _tdes4: VolatileCell::new(0),
+            #[cfg(not(eth_v1a))]
+            _tdes5: VolatileCell::new(0),
+            #[cfg(not(eth_v1a))]
+            tdes6: VolatileCell::new(0),
+            #[cfg(not(eth_v1a))]
+            tdes7: VolatileCell::new(0),
         }
     }
 
@@ -107,6 +131,8 @@ pub(crate) struct TDesRing<'a> {
     descriptors: &'a mut [TDes],
     buffers: &'a mut [Packet<TX_BUFFER_SIZE>],
     index: usize,
+    #[cfg(not(eth_v1a))]
+    timestamping: bool,
 }
 
 impl<'a> TDesRing<'a> {
@@ -128,6 +154,33 @@ impl<'a> TDesRing<'a> {
             descriptors,
             buffers,
             index: 0,
+            #[cfg(not(eth_v1a))]
+            timestamping: false,
+        }
+    }
+
+    /// Enable timestamping of transmitted PTP messages.
+    #[cfg(not(eth_v1a))]
+    pub(crate) fn enable_timestamping(&mut self) {
+        self.timestamping = true;
+    }
+
+    /// Record the timestamps of PTP messages that have been transmitted.
+    #[cfg(not(eth_v1a))]
+    pub(crate) fn collect_timestamps(&mut self) {
+        for (descriptor, buffer) in self.descriptors.iter().zip(self.buffers.iter()) {
+            let tdes0 = descriptor.tdes0.get();
+            if tdes0 & (TXDESC_0_OWN | TXDESC_0_TTSE) != TXDESC_0_TTSE {
+                continue;
+            }
+            if tdes0 & TXDESC_0_TTSS != 0 {
+                let timestamp = Timestamp {
+                    seconds: descriptor.tdes7.get(),
+                    nanoseconds: descriptor.tdes6.get(),
+                };
+                ptp::record_tx(&buffer.0, timestamp);
+            }
+            descriptor.tdes0.set(tdes0 & !(TXDESC_0_TTSE | TXDESC_0_TTSS));
         }
     }
 
@@ -147,12 +200,23 @@ impl<'a> TDesRing<'a> {
 
     /// Transmit the packet written in a buffer returned by `available`.
     pub(crate) fn transmit(&mut self, len: usize) {
+        // Don't lose the timestamp of the previous packet in this descriptor.
+        #[cfg(not(eth_v1a))]
+        if self.timestamping {
+            self.collect_timestamps();
+        }
+
         let descriptor = &mut self.descriptors[self.index];
         assert!(descriptor.available());
 
         descriptor.set_buffer1(self.buffers[self.index].0.as_ptr());
         descriptor.set_buffer1_len(len);
 
+        #[cfg(not(eth_v1a))]
+        if self.timestamping && MessageId::from_frame(&self.buffers[self.index].0[..len]).is_some() {
+            descriptor.tdes0.set(descriptor.tdes0.get() | TXDESC_0_TTSE);
+        }
+
         descriptor.set_owned();
 
         // Ensure changes to the descriptor are committed before DMA engine sees tail pointer store.
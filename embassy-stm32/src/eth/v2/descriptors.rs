@@ -2,6 +2,7 @@ use core::sync::atomic::{fence, Ordering};
 
 use vcell::VolatileCell;
 
+use crate::eth::ptp::{self, MessageId, Timestamp};
 use crate::eth::{Packet, RX_BUFFER_SIZE, TX_BUFFER_SIZE};
 use crate::pac::ETH;
 
@@ -16,8 +17,13 @@ mod emac_consts {
     pub const EMAC_DES0_BUF1AP: u32 = 0xFFFF_FFFF;
 
     pub const EMAC_TDES2_IOC: u32 = 0x8000_0000;
+    pub const EMAC_TDES2_TTSE: u32 = 0x4000_0000;
     pub const EMAC_TDES2_B1L: u32 = 0x0000_3FFF;
 
+    pub const EMAC_TDES3_TTSS: u32 = 0x0002_0000;
+
+    pub const EMAC_RDES1_TSA: u32 = 0x0000_4000;
+
     pub const EMAC_RDES3_IOC: u32 = 0x4000_0000;
     pub const EMAC_RDES3_PL: u32 = 0x0000_7FFF;
     pub const EMAC_RDES3_BUF1V: u32 = 0x0100_0000;
@@ -25,10 +31,14 @@ mod emac_consts {
 }
 use emac_consts::*;
 
+/// Number of times to check for the context descriptor holding the timestamp of a received packet,
+/// which the DMA writes right after the packet's descriptor.
+const CONTEXT_DESCRIPTOR_POLLS: u32 = 100;
+
 /// Transmit Descriptor representation
 ///
-/// * tdes0: transmit buffer address
-/// * tdes1:
+/// * tdes0: transmit buffer address, or timestamp low after transmission
+/// * tdes1: timestamp high after transmission
 /// * tdes2: buffer lengths
 /// * tdes3: control and payload/frame length
 #[repr(C)]
@@ -59,6 +69,7 @@ pub(crate) struct TDesRing<'a> {
     descriptors: &'a mut [TDes],
     buffers: &'a mut [Packet<TX_BUFFER_SIZE>],
     index: usize,
+    timestamping: bool,
 }
 
 impl<'a> TDesRing<'a> {
@@ -82,6 +93,27 @@ impl<'a> TDesRing<'a> {
             descriptors,
             buffers,
             index: 0,
+            timestamping: false,
+        }
+    }
+
+    /// Enable timestamping of transmitted PTP messages.
+    pub(crate) fn enable_timestamping(&mut self) {
+        self.timestamping = true;
+    }
+
+    /// Record the timestamps of PTP messages that have been transmitted.
+    pub(crate) fn collect_timestamps(&mut self) {
+        for (td, buffer) in self.descriptors.iter().zip(self.buffers.iter()) {
+            let tdes3 = td.tdes3.get();
+            if tdes3 & (EMAC_DES3_OWN | EMAC_TDES3_TTSS) == EMAC_TDES3_TTSS {
+                let timestamp = Timestamp {
+                    seconds: td.tdes1.get(),
+                    nanoseconds: td.tdes0.get(),
+                };
+                ptp::record_tx(&buffer.0, timestamp);
+                td.tdes3.set(tdes3 & !EMAC_TDES3_TTSS);
+            }
         }
     }
 
@@ -101,13 +133,24 @@ impl<'a> TDesRing<'a> {
 
     /// Transmit the packet written in a buffer returned by `available`.
     pub(crate) fn transmit(&mut self, len: usize) {
+        // Don't lose the timestamp of the previous packet in this descriptor.
+        if self.timestamping {
+            self.collect_timestamps();
+        }
+
         let td = &mut self.descriptors[self.index];
         assert!(td.available());
         assert!(len as u32 <= EMAC_TDES2_B1L);
 
+        let buffer = &self.buffers[self.index].0;
+        let mut tdes2 = len as u32 & EMAC_TDES2_B1L | EMAC_TDES2_IOC;
+        if self.timestamping && MessageId::from_frame(&buffer[..len]).is_some() {
+            tdes2 |= EMAC_TDES2_TTSE;
+        }
+
         // Read format
-        td.tdes0.set(self.buffers[self.index].0.as_ptr() as u32);
-        td.tdes2.set(len as u32 & EMAC_TDES2_B1L | EMAC_TDES2_IOC);
+        td.tdes0.set(buffer.as_ptr() as u32);
+        td.tdes2.set(tdes2);
 
         // FD: Contains first buffer of packet
         // LD: Contains last buffer of packet
@@ -129,8 +172,8 @@ impl<'a> TDesRing<'a> {
 
 /// Receive Descriptor representation
 ///
-/// * rdes0: receive buffer address
-/// * rdes1:
+/// * rdes0: receive buffer address, or timestamp low in a context descriptor
+/// * rdes1: status, or timestamp high in a context descriptor
 /// * rdes2:
 /// * rdes3: OWN and Status
 #[repr(C)]
@@ -168,6 +211,12 @@ impl RDes {
         self.rdes3.get() & EMAC_DES3_OWN == 0 // Owned by us
     }
 
+    /// Return true if this RDes is a context descriptor
+    #[inline(always)]
+    fn is_context(&self) -> bool {
+        self.rdes3.get() & EMAC_DES3_CTXT != 0
+    }
+
     #[inline(always)]
     fn set_ready(&mut self, buf: *mut u8) {
         self.rdes0.set(buf as u32);
@@ -180,6 +229,9 @@ pub(crate) struct RDesRing<'a> {
     descriptors: &'a mut [RDes],
     buffers: &'a mut [Packet<RX_BUFFER_SIZE>],
     index: usize,
+    timestamping: bool,
+    /// Timestamp of the current packet, read from the context descriptor following it.
+    timestamp: Option<Timestamp>,
 }
 
 impl<'a> RDesRing<'a> {
@@ -201,9 +253,21 @@ impl<'a> RDesRing<'a> {
             descriptors,
             buffers,
             index: 0,
+            timestamping: false,
+            timestamp: None,
         }
     }
 
+    /// Enable reporting of reception timestamps.
+    pub(crate) fn enable_timestamping(&mut self) {
+        self.timestamping = true;
+    }
+
+    /// Reception timestamp of the packet previously returned by `available`, if any.
+    pub(crate) fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp.filter(|_| self.timestamping)
+    }
+
     /// Get a received packet if any, or None.
     pub(crate) fn available(&mut self) -> Option<&mut [u8]> {
         // Not sure if the contents of the write buffer on the M7 can affects reads, so we are using
@@ -220,7 +284,10 @@ impl<'a> RDesRing<'a> {
 
             // If packet is invalid, pop it and try again.
             if !descriptor.valid() {
-                warn!("invalid packet: {:08x}", descriptor.rdes0.get());
+                // A context descriptor whose packet was already popped.
+                if !descriptor.is_context() {
+                    warn!("invalid packet: {:08x}", descriptor.rdes0.get());
+                }
                 self.pop_packet();
                 continue;
             }
@@ -228,6 +295,19 @@ impl<'a> RDesRing<'a> {
             break;
         }
 
+        if self.timestamp.is_none() && self.descriptors[self.index].rdes1.get() & EMAC_RDES1_TSA != 0 {
+            let ctx = &self.descriptors[(self.index + 1) % self.descriptors.len()];
+            for _ in 0..CONTEXT_DESCRIPTOR_POLLS {
+                if ctx.available() && ctx.is_context() {
+                    self.timestamp = Some(Timestamp {
+                        seconds: ctx.rdes1.get(),
+                        nanoseconds: ctx.rdes0.get(),
+                    });
+                    break;
+                }
+            }
+        }
+
         let descriptor = &mut self.descriptors[self.index];
         let len = (descriptor.rdes3.get() & EMAC_RDES3_PKTLEN) as usize;
         return Some(&mut self.buffers[self.index].0[..len]);
@@ -235,6 +315,15 @@ impl<'a> RDesRing<'a> {
 
     /// Pop the packet previously returned by `available`.
     pub(crate) fn pop_packet(&mut self) {
+        self.pop_descriptor();
+
+        // Return the context descriptor holding the timestamp too.
+        if self.timestamp.take().is_some() {
+            self.pop_descriptor();
+        }
+    }
+
+    fn pop_descriptor(&mut self) {
         let rd = &mut self.descriptors[self.index];
         assert!(rd.available());
 
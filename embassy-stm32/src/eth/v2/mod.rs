@@ -1,4 +1,5 @@
 mod descriptors;
+mod ptp;
 
 use core::marker::PhantomData;
 use core::sync::atomic::{fence, Ordering};
@@ -44,6 +45,7 @@ pub struct Ethernet<'d, T: Instance, P: PHY> {
     pub(crate) phy: P,
     pub(crate) station_management: EthernetStationManagement<T>,
     pub(crate) mac_addr: [u8; 6],
+    pub(crate) link_status: Option<LinkStatus>,
 }
 
 /// Pins of ethernet driver.
@@ -261,6 +263,7 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
                 clock_range: clock_range,
             },
             mac_addr,
+            link_status: None,
         };
 
         fence(Ordering::SeqCst);
@@ -293,6 +296,14 @@ impl<'d, T: Instance, P: PHY> Ethernet<'d, T, P> {
 
         this
     }
+
+    /// Configure the MAC for the speed and duplex mode negotiated by the PHY.
+    pub(crate) fn configure_link(status: LinkStatus) {
+        T::regs().ethernet_mac().maccr().modify(|w| {
+            w.set_fes(status.speed == LinkSpeed::Mbps100);
+            w.set_dm(status.duplex == Duplex::Full);
+        });
+    }
 }
 
 /// Ethernet SMI driver.
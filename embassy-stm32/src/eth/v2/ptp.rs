@@ -0,0 +1,96 @@
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering;
+
+use crate::eth::ptp::{self, PtpClock, Timestamp};
+use crate::eth::Instance;
+use crate::rcc::SealedRccPeripheral;
+
+impl<T: Instance> PtpClock<T> {
+    /// Start the system time at zero and enable timestamping.
+    pub(crate) fn init() -> Self {
+        let mac = T::regs().ethernet_mac();
+
+        mac.mactscr().write(|w| {
+            w.set_tsena(true);
+            w.set_tscfupdt(true); // fine update
+            w.set_tsctrlssr(true); // sub-seconds in nanoseconds
+            w.set_tsver2ena(true);
+            w.set_tsipena(true); // PTP over Ethernet
+            w.set_tsipv4ena(true);
+            w.set_tsipv6ena(true);
+            w.set_snaptypsel(0b01); // snapshot all PTP messages
+        });
+
+        let (increment, addend) = ptp::clock_params(<T as SealedRccPeripheral>::frequency().0);
+        mac.macssir().write(|w| w.set_ssinc(increment));
+        ptp::BASE_ADDEND.store(addend, Ordering::Relaxed);
+
+        let this = Self { _peri: PhantomData };
+        this.set_addend(addend);
+        this.set_time(Timestamp::default());
+        this
+    }
+
+    /// Read the current time.
+    pub fn now(&self) -> Timestamp {
+        let mac = T::regs().ethernet_mac();
+        loop {
+            let seconds = mac.macstsr().read().tss();
+            let nanoseconds = mac.macstnr().read().tsss();
+            // Retry if the seconds rolled over between the two reads.
+            if mac.macstsr().read().tss() == seconds {
+                return Timestamp { seconds, nanoseconds };
+            }
+        }
+    }
+
+    /// Set the current time.
+    pub fn set_time(&self, time: Timestamp) {
+        let mac = T::regs().ethernet_mac();
+        mac.macstsur().write(|w| w.set_tss(time.seconds));
+        mac.macstnur().write(|w| w.set_tsss(time.nanoseconds));
+        mac.mactscr().modify(|w| w.set_tsinit(true));
+        while mac.mactscr().read().tsinit() {}
+    }
+
+    /// Add a signed offset in nanoseconds to the current time, without stopping the clock.
+    pub fn adjust_time(&self, offset_ns: i64) {
+        let mac = T::regs().ethernet_mac();
+        let (mut seconds, mut nanoseconds, negative) = ptp::split_offset(offset_ns);
+        if negative {
+            // Subtraction takes the two's complement of the seconds and the complement to one
+            // second of the nanoseconds.
+            seconds = seconds.wrapping_neg();
+            if nanoseconds != 0 {
+                nanoseconds = 1_000_000_000 - nanoseconds;
+            }
+        }
+        mac.macstsur().write(|w| w.set_tss(seconds));
+        mac.macstnur().write(|w| {
+            w.set_tsss(nanoseconds);
+            w.set_addsub(negative);
+        });
+        mac.mactscr().modify(|w| w.set_tsupdt(true));
+        while mac.mactscr().read().tsupdt() {}
+    }
+
+    /// Current frequency adjustment addend.
+    pub fn addend(&self) -> u32 {
+        T::regs().ethernet_mac().mactsar().read().tsar()
+    }
+
+    /// Set the frequency adjustment addend.
+    ///
+    /// The system time advances by the sub-second increment each time adding this value to a
+    /// 32-bit accumulator on every PTP clock cycle overflows it.
+    pub fn set_addend(&self, addend: u32) {
+        let mac = T::regs().ethernet_mac();
+        mac.mactsar().write(|w| w.set_tsar(addend));
+        mac.mactscr().modify(|w| w.set_tsaddreg(true));
+        while mac.mactscr().read().tsaddreg() {}
+    }
+
+    pub(crate) fn set_pps_frequency(exponent: u8) {
+        T::regs().ethernet_mac().macppscr().modify(|w| w.set_ppsctrl(exponent));
+    }
+}
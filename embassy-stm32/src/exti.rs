@@ -153,6 +153,22 @@ impl<'d> ExtiInput<'d> {
         fut.await
     }
 
+    /// Poll whether the pin is low, registering `cx` to be woken on the next falling edge.
+    ///
+    /// Unlike [`wait_for_low`](Self::wait_for_low), the interrupt stays armed after this returns,
+    /// so it can be used from poll-based interfaces that can't keep a future around.
+    #[cfg(eth)]
+    pub(crate) fn poll_low(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let pin = self.pin.pin.pin.pin();
+        EXTI_WAKERS[pin as usize].register(cx.waker());
+        core::mem::forget(ExtiInputFuture::new(pin, self.pin.pin.pin.port(), false, true));
+        if self.is_low() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
     /// Asynchronously wait until the pin sees a rising edge.
     ///
     /// If the pin is already high, it will wait for it to go low then back high.
@@ -227,13 +243,13 @@ impl<'d> embedded_hal_async::digital::Wait for ExtiInput<'d> {
 }
 
 #[must_use = "futures do nothing unless you `.await` or poll them"]
-struct ExtiInputFuture<'a> {
+pub(crate) struct ExtiInputFuture<'a> {
     pin: u8,
     phantom: PhantomData<&'a mut AnyPin>,
 }
 
 impl<'a> ExtiInputFuture<'a> {
-    fn new(pin: u8, port: u8, rising: bool, falling: bool) -> Self {
+    pub(crate) fn new(pin: u8, port: u8, rising: bool, falling: bool) -> Self {
         critical_section::with(|_| {
             let pin = pin as usize;
             exticr_regs().exticr(pin / 4).modify(|w| w.set_exti(pin % 4, port));
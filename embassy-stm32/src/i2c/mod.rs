@@ -4,6 +4,7 @@
 #[cfg_attr(i2c_v1, path = "v1.rs")]
 #[cfg_attr(any(i2c_v2, i2c_v3), path = "v2.rs")]
 mod _version;
+pub mod slave;
 
 use core::future::Future;
 use core::iter;
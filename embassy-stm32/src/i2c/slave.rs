@@ -0,0 +1,256 @@
+//! I2C slave (target) driver.
+//!
+//! The API mirrors `embassy_rp::i2c_slave`: wait for a command from the controller with
+//! [`I2cSlave::listen`], then answer reads with [`I2cSlave::respond_to_read`].
+//!
+//! With clock stretching enabled (the default), the bus is held while the driver waits for the
+//! application, so there's no hard deadline for calling [`I2cSlave::respond_to_read`] after
+//! [`I2cSlave::listen`] returns a read command.
+
+use embassy_hal_internal::{Peripheral, PeripheralRef};
+
+use super::{ErrorInterruptHandler, EventInterruptHandler, Info, Instance, RxDma, SclPin, SdaPin, State, TxDma};
+use crate::dma::ChannelAndRequest;
+use crate::gpio::{AnyPin, SealedPin as _};
+use crate::interrupt;
+use crate::interrupt::typelevel::Interrupt;
+use crate::rcc::SealedRccPeripheral;
+use crate::time::Hertz;
+
+/// I2C slave error.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// Bus error, i.e. a misplaced start or stop condition.
+    Bus,
+    /// Arbitration lost while transmitting.
+    Arbitration,
+    /// Overrun or underrun. This can only happen with clock stretching disabled.
+    Overrun,
+    /// User passed in a response buffer that was 0 length
+    InvalidResponseBufferLength,
+    /// The response buffer length was too short to contain the message
+    ///
+    /// The length parameter will always be the length of the buffer, and is
+    /// provided as a convenience for matching alongside `Command::Write`.
+    PartialWrite(usize),
+    /// The response buffer length was too short to contain the message
+    ///
+    /// The length parameter will always be the length of the buffer, and is
+    /// provided as a convenience for matching alongside `Command::GeneralCall`.
+    PartialGeneralCall(usize),
+}
+
+/// Received command
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// General Call
+    GeneralCall(usize),
+    /// Read
+    Read,
+    /// Write+read
+    WriteRead(usize),
+    /// Write
+    Write(usize),
+}
+
+/// Possible responses to responding to a read
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReadStatus {
+    /// Transaction Complete, controller naked our last byte
+    Done,
+    /// Transaction Incomplete, controller trying to read more bytes than were provided
+    NeedMoreBytes,
+    /// Transaction Complete, but controller stopped reading bytes before we ran out
+    LeftoverBytes(u16),
+}
+
+/// Own address of the slave.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Address {
+    /// 7-bit address.
+    SevenBit(u8),
+    /// 10-bit address.
+    TenBit(u16),
+}
+
+impl From<u8> for Address {
+    fn from(addr: u8) -> Self {
+        Self::SevenBit(addr)
+    }
+}
+
+impl Address {
+    fn is_valid(&self) -> bool {
+        match *self {
+            // Reserved addresses are 0b0000xxx and 0b1111xxx.
+            Self::SevenBit(addr) => (0x08..0x78).contains(&addr),
+            Self::TenBit(addr) => addr < 0x400,
+        }
+    }
+}
+
+/// Slave Configuration
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// Target Address
+    pub addr: Address,
+    /// Optional second 7-bit address the peripheral also responds to.
+    pub addr2: Option<u8>,
+    /// Control if the peripheral should ack to and report general calls.
+    pub general_call: bool,
+    /// Hold SCL low while waiting for the application to read or provide data.
+    ///
+    /// If disabled, the application must keep up with the bus, otherwise transfers fail with
+    /// [`Error::Overrun`].
+    pub clock_stretching: bool,
+    /// Enable internal pullup on SDA.
+    ///
+    /// Using external pullup resistors is recommended for I2C. If you do
+    /// have external pullups you should not enable this.
+    #[cfg(gpio_v2)]
+    pub sda_pullup: bool,
+    /// Enable internal pullup on SCL.
+    ///
+    /// Using external pullup resistors is recommended for I2C. If you do
+    /// have external pullups you should not enable this.
+    #[cfg(gpio_v2)]
+    pub scl_pullup: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: Address::SevenBit(0x55),
+            addr2: None,
+            general_call: true,
+            clock_stretching: true,
+            #[cfg(gpio_v2)]
+            sda_pullup: false,
+            #[cfg(gpio_v2)]
+            scl_pullup: false,
+        }
+    }
+}
+
+impl Config {
+    fn pin_config(&self) -> super::Config {
+        super::Config {
+            #[cfg(gpio_v2)]
+            sda_pullup: self.sda_pullup,
+            #[cfg(gpio_v2)]
+            scl_pullup: self.scl_pullup,
+            ..Default::default()
+        }
+    }
+}
+
+/// I2C slave driver.
+pub struct I2cSlave<'d> {
+    pub(super) info: &'static Info,
+    pub(super) state: &'static State,
+    pub(super) kernel_clock: Hertz,
+    scl: Option<PeripheralRef<'d, AnyPin>>,
+    sda: Option<PeripheralRef<'d, AnyPin>>,
+    pub(super) tx_dma: Option<ChannelAndRequest<'d>>,
+    pub(super) rx_dma: Option<ChannelAndRequest<'d>>,
+    pub(super) config: Config,
+    /// Address the controller used for the current or last transaction.
+    pub(super) address: u16,
+}
+
+impl<'d> I2cSlave<'d> {
+    /// Create a new I2C slave driver.
+    pub fn new<T: Instance>(
+        _peri: impl Peripheral<P = T> + 'd,
+        scl: impl Peripheral<P = impl SclPin<T>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::EventInterrupt, EventInterruptHandler<T>>
+            + interrupt::typelevel::Binding<T::ErrorInterrupt, ErrorInterruptHandler<T>>
+            + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        assert!(config.addr.is_valid());
+        assert!(config.addr2.map_or(true, |a| Address::SevenBit(a).is_valid()));
+
+        let pin_config = config.pin_config();
+        let mut this = Self {
+            info: T::info(),
+            state: T::state(),
+            kernel_clock: T::frequency(),
+            scl: new_pin!(scl, pin_config.scl_af()),
+            sda: new_pin!(sda, pin_config.sda_af()),
+            tx_dma: new_dma!(tx_dma),
+            rx_dma: new_dma!(rx_dma),
+            config,
+            address: 0,
+        };
+
+        this.info.rcc.enable_and_reset();
+        this.init();
+
+        T::EventInterrupt::unpend();
+        T::ErrorInterrupt::unpend();
+        unsafe { T::EventInterrupt::enable() };
+        unsafe { T::ErrorInterrupt::enable() };
+
+        this
+    }
+
+    /// Reset the i2c peripheral. If you cancel a respond_to_read, you may stall the bus.
+    /// You can recover the bus by calling this function, but doing so will almost certainly cause
+    /// an i/o error in the master.
+    pub fn reset(&mut self) {
+        self.init();
+    }
+
+    /// Address used by the controller in the last command.
+    ///
+    /// This is either [`Config::addr`], [`Config::addr2`], or 0 for a general call.
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    /// Respond to reads with the fill byte until the controller stops asking
+    pub async fn respond_till_stop(&mut self, fill: u8) -> Result<(), Error> {
+        // Send fill bytes a chunk at a time, to reduce interrupt noise.
+        // This does mean we'll almost certainly abort the write, but since these are fill bytes,
+        // we don't care.
+        let buff = [fill; 16];
+        loop {
+            match self.respond_to_read(&buff).await {
+                Ok(ReadStatus::NeedMoreBytes) => (),
+                Ok(_) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        }
+    }
+
+    /// Respond to a master read, then fill any remaining read bytes with `fill`
+    pub async fn respond_and_fill(&mut self, buffer: &[u8], fill: u8) -> Result<ReadStatus, Error> {
+        let resp_stat = self.respond_to_read(buffer).await?;
+
+        if resp_stat == ReadStatus::NeedMoreBytes {
+            self.respond_till_stop(fill).await?;
+            Ok(ReadStatus::Done)
+        } else {
+            Ok(resp_stat)
+        }
+    }
+}
+
+impl<'d> Drop for I2cSlave<'d> {
+    fn drop(&mut self) {
+        self.scl.as_ref().map(|x| x.set_as_disconnected());
+        self.sda.as_ref().map(|x| x.set_as_disconnected());
+
+        self.info.rcc.disable()
+    }
+}
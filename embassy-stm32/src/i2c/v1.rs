@@ -4,7 +4,8 @@
 //!
 //! All other devices (as of 2023-12-28) use [`v2`](super::v2) instead.
 
-use core::future::poll_fn;
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
 use core::task::Poll;
 
 use embassy_embedded_hal::SetConfig;
@@ -12,6 +13,7 @@ use embassy_futures::select::{select, Either};
 use embassy_hal_internal::drop::OnDrop;
 use embedded_hal_1::i2c::Operation;
 
+use super::slave::{self, Address, Command, I2cSlave, ReadStatus};
 use super::*;
 use crate::mode::Mode as PeriMode;
 use crate::pac::i2c;
@@ -700,6 +702,313 @@ impl<'d> I2c<'d, Async> {
     }
 }
 
+impl<'d> I2cSlave<'d> {
+    pub(super) fn init(&mut self) {
+        let regs = self.info.regs;
+        regs.cr1().modify(|reg| {
+            reg.set_pe(false);
+        });
+
+        // Same errata workaround as in master mode, see `I2c::init`.
+        regs.cr1().modify(|reg| {
+            reg.set_swrst(true);
+        });
+        regs.cr1().modify(|reg| {
+            reg.set_swrst(false);
+        });
+
+        // The peripheral clock frequency is needed for the data setup time, even in slave mode.
+        let timings = Timings::new(self.kernel_clock, Hertz(100_000));
+        regs.cr2().modify(|reg| {
+            reg.set_freq(timings.freq);
+        });
+
+        regs.oar1().write(|reg| {
+            // Bit 14 must be kept at 1 by software.
+            reg.0 = 1 << 14;
+            match self.config.addr {
+                Address::SevenBit(addr) => {
+                    reg.set_add((addr as u16) << 1);
+                    reg.set_addmode(i2c::vals::Addmode::BIT7);
+                }
+                Address::TenBit(addr) => {
+                    reg.set_add(addr);
+                    reg.set_addmode(i2c::vals::Addmode::BIT10);
+                }
+            }
+        });
+        regs.oar2().write(|reg| {
+            if let Some(addr) = self.config.addr2 {
+                reg.set_add2(addr);
+                reg.set_endual(i2c::vals::Endual::DUAL);
+            }
+        });
+
+        regs.cr1().modify(|reg| {
+            reg.set_engc(self.config.general_call);
+            reg.set_nostretch(!self.config.clock_stretching);
+            reg.set_pe(true);
+        });
+        // ACK can only be set while the peripheral is enabled.
+        regs.cr1().modify(|reg| {
+            reg.set_ack(true);
+        });
+    }
+
+    fn check_and_clear_slave_errors(info: &'static Info) -> Result<i2c::regs::Sr1, slave::Error> {
+        let sr1 = info.regs.sr1().read();
+
+        if sr1.berr() {
+            info.regs.sr1().write(|reg| {
+                reg.0 = !0;
+                reg.set_berr(false);
+            });
+            return Err(slave::Error::Bus);
+        }
+
+        if sr1.arlo() {
+            info.regs.sr1().write(|reg| {
+                reg.0 = !0;
+                reg.set_arlo(false);
+            });
+            return Err(slave::Error::Arbitration);
+        }
+
+        if sr1.ovr() {
+            info.regs.sr1().write(|reg| {
+                reg.0 = !0;
+                reg.set_ovr(false);
+            });
+            return Err(slave::Error::Overrun);
+        }
+
+        Ok(sr1)
+    }
+
+    #[inline]
+    fn enable_slave_interrupts(info: &'static Info) {
+        info.regs.cr2().modify(|w| {
+            w.set_iterren(true);
+            w.set_itevten(true);
+        });
+    }
+
+    fn clear_af(info: &'static Info) {
+        info.regs.sr1().write(|reg| {
+            reg.0 = !0;
+            reg.set_af(false);
+        });
+    }
+
+    /// Wait asynchronously for commands from an I2C master.
+    /// `buffer` is provided in case master does a 'write', 'write read', or 'general call' and is unused for 'read'.
+    ///
+    /// Written data is received using DMA. For 'read' and 'write read', SCL is held low until
+    /// [`respond_to_read`](Self::respond_to_read) is called.
+    pub async fn listen(&mut self, buffer: &mut [u8]) -> Result<Command, slave::Error> {
+        let info = self.info;
+        let state = self.state;
+        let regs = info.regs;
+
+        let _on_drop = OnDrop::new(|| {
+            regs.cr2().modify(|w| {
+                w.set_dmaen(false);
+                w.set_iterren(false);
+                w.set_itevten(false);
+            })
+        });
+
+        // ACK may have been disabled to stop an overflowing write.
+        regs.cr1().modify(|w| w.set_ack(true));
+
+        loop {
+            // Wait to be addressed. Flags left over from the end of the previous transaction are
+            // cleared.
+            let sr2 = poll_fn(|cx| {
+                state.waker.register(cx.waker());
+
+                match Self::check_and_clear_slave_errors(info) {
+                    Err(e) => Poll::Ready(Err(e)),
+                    Ok(sr1) => {
+                        if sr1.addr() {
+                            // Reading SR2 after SR1 clears ADDR.
+                            return Poll::Ready(Ok(regs.sr2().read()));
+                        }
+                        if sr1.stopf() {
+                            // Cleared by reading SR1, then writing CR1.
+                            regs.cr1().modify(|_| {});
+                        }
+                        if sr1.af() {
+                            Self::clear_af(info);
+                        }
+
+                        // When pending, (re-)enable interrupts to wake us up.
+                        Self::enable_slave_interrupts(info);
+                        Poll::Pending
+                    }
+                }
+            })
+            .await?;
+
+            let general_call = sr2.gencall();
+            self.address = match (general_call, sr2.dualf(), self.config.addr) {
+                (true, _, _) => 0,
+                (false, true, _) => self.config.addr2.unwrap_or(0) as u16,
+                (false, false, Address::SevenBit(addr)) => addr as u16,
+                (false, false, Address::TenBit(addr)) => addr,
+            };
+
+            if sr2.tra() {
+                // SCL is stretched until `respond_to_read` provides data.
+                return Ok(Command::Read);
+            }
+
+            let total_len = buffer.len();
+            let mut transfer = if total_len == 0 {
+                None
+            } else {
+                regs.cr2().modify(|w| {
+                    // Note: Do not enable the ITBUFEN bit in the I2C_CR2 register if DMA is used for
+                    // reception.
+                    w.set_itbufen(false);
+                    w.set_dmaen(true);
+                });
+                let src = regs.dr().as_ptr() as *mut u8;
+                Some(unsafe {
+                    self.rx_dma
+                        .as_mut()
+                        .unwrap()
+                        .read(src, &mut *buffer, Default::default())
+                })
+            };
+
+            let (len, read_next) = poll_fn(|cx| {
+                state.waker.register(cx.waker());
+
+                let sr1 = match Self::check_and_clear_slave_errors(info) {
+                    Err(e) => return Poll::Ready(Err(e)),
+                    Ok(sr1) => sr1,
+                };
+                let (dma_done, remaining) = match transfer.as_mut() {
+                    Some(t) => (
+                        Pin::new(&mut *t).poll(cx).is_ready(),
+                        t.get_remaining_transfers() as usize,
+                    ),
+                    None => (true, 0),
+                };
+                let len = total_len - remaining;
+
+                if dma_done && sr1.rxne() {
+                    // More data than fits in the buffer. NACK the next byte so the controller
+                    // stops, and drop the one that was already received.
+                    regs.cr1().modify(|w| w.set_ack(false));
+                    let _ = regs.dr().read();
+                    Poll::Ready(Err(match general_call {
+                        true => slave::Error::PartialGeneralCall(total_len),
+                        false => slave::Error::PartialWrite(total_len),
+                    }))
+                } else if sr1.stopf() {
+                    regs.cr1().modify(|_| {});
+                    Poll::Ready(Ok((len, false)))
+                } else if sr1.addr() && regs.sr2().read().tra() {
+                    // Repeated start for a read.
+                    Poll::Ready(Ok((len, true)))
+                } else {
+                    // A repeated start for another write clears ADDR above, and its data is
+                    // appended to this write.
+                    Self::enable_slave_interrupts(info);
+                    Poll::Pending
+                }
+            })
+            .await?;
+
+            drop(transfer);
+            regs.cr2().modify(|w| w.set_dmaen(false));
+
+            match (len, read_next) {
+                (_, true) => return Ok(Command::WriteRead(len)),
+                // Zero-length writes, e.g. from bus scans, aren't reported.
+                (0, false) => continue,
+                (_, false) if general_call => return Ok(Command::GeneralCall(len)),
+                (_, false) => return Ok(Command::Write(len)),
+            }
+        }
+    }
+
+    /// Respond to an I2C master READ command, asynchronously.
+    ///
+    /// The response is sent using DMA.
+    pub async fn respond_to_read(&mut self, buffer: &[u8]) -> Result<ReadStatus, slave::Error> {
+        if buffer.is_empty() {
+            return Err(slave::Error::InvalidResponseBufferLength);
+        }
+
+        let info = self.info;
+        let state = self.state;
+        let regs = info.regs;
+
+        let on_drop = OnDrop::new(|| {
+            regs.cr2().modify(|w| {
+                w.set_dmaen(false);
+                w.set_iterren(false);
+                w.set_itevten(false);
+            })
+        });
+
+        regs.cr2().modify(|w| {
+            w.set_itbufen(false);
+            w.set_dmaen(true);
+        });
+        let dst = regs.dr().as_ptr() as *mut u8;
+        let mut transfer = unsafe { self.tx_dma.as_mut().unwrap().write(buffer, dst, Default::default()) };
+
+        let mut stale_data = false;
+        let result = poll_fn(|cx| {
+            state.waker.register(cx.waker());
+
+            let sr1 = match Self::check_and_clear_slave_errors(info) {
+                Err(e) => return Poll::Ready(Err(e)),
+                Ok(sr1) => sr1,
+            };
+            let dma_done = Pin::new(&mut transfer).poll(cx).is_ready();
+
+            if sr1.af() || sr1.stopf() || sr1.addr() {
+                // The controller ended the read. Bytes that the DMA didn't copy yet, and the byte
+                // still waiting in DR, weren't sent.
+                regs.cr2().modify(|w| w.set_dmaen(false));
+                stale_data = !sr1.txe();
+                let leftover = transfer.get_remaining_transfers() + stale_data as u16;
+                if sr1.af() {
+                    Self::clear_af(info);
+                }
+
+                Poll::Ready(Ok(match leftover {
+                    0 => ReadStatus::Done,
+                    n => ReadStatus::LeftoverBytes(n),
+                }))
+            } else if dma_done && sr1.btf() {
+                // The last byte was acknowledged, and the controller is waiting for the next one.
+                Poll::Ready(Ok(ReadStatus::NeedMoreBytes))
+            } else {
+                // When pending, (re-)enable interrupts to wake us up.
+                Self::enable_slave_interrupts(info);
+                Poll::Pending
+            }
+        })
+        .await;
+
+        drop(transfer);
+        drop(on_drop);
+
+        if stale_data {
+            // DR can't be flushed, and would otherwise be sent at the start of the next read.
+            self.init();
+        }
+
+        result
+    }
+}
+
 enum Mode {
     Fast,
     Standard,
@@ -1,17 +1,20 @@
 use core::cmp;
-use core::future::poll_fn;
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
 use core::task::Poll;
 
 use embassy_embedded_hal::SetConfig;
 use embassy_hal_internal::drop::OnDrop;
 use embedded_hal_1::i2c::Operation;
 
+use super::slave::{self, Address, Command, I2cSlave, ReadStatus};
 use super::*;
 use crate::pac::i2c;
 
 pub(crate) unsafe fn on_interrupt<T: Instance>() {
     let regs = T::info().regs;
     let isr = regs.isr().read();
+    let cr1 = regs.cr1().read();
 
     if isr.tcr() || isr.tc() {
         T::state().waker.wake();
@@ -21,6 +24,22 @@ pub(crate) unsafe fn on_interrupt<T: Instance>() {
     critical_section::with(|_| {
         regs.cr1().modify(|w| w.set_tcie(false));
     });
+
+    // Slave mode events. The flags are handled by the slave driver, which re-enables the
+    // interrupts when it has to wait again.
+    if cr1.addrie() || cr1.stopie() || cr1.nackie() || cr1.errie() || cr1.rxie() || cr1.txie() {
+        critical_section::with(|_| {
+            regs.cr1().modify(|w| {
+                w.set_addrie(false);
+                w.set_stopie(false);
+                w.set_nackie(false);
+                w.set_errie(false);
+                w.set_rxie(false);
+                w.set_txie(false);
+            });
+        });
+        T::state().waker.wake();
+    }
 }
 
 impl<'d, M: Mode> I2c<'d, M> {
@@ -676,6 +695,278 @@ impl<'d> I2c<'d, Async> {
     }
 }
 
+impl<'d> I2cSlave<'d> {
+    pub(super) fn init(&mut self) {
+        let regs = self.info.regs;
+        regs.cr1().modify(|reg| reg.set_pe(false));
+
+        // The slave doesn't generate SCL, but the data setup and hold times still apply. Use the
+        // standard-mode ones, which are the longest and so also valid on faster buses.
+        let timings = Timings::new(self.kernel_clock, Hertz(100_000));
+        regs.timingr().write(|reg| {
+            reg.set_presc(timings.prescale);
+            reg.set_sdadel(timings.sdadel);
+            reg.set_scldel(timings.scldel);
+        });
+
+        // Own addresses can only be changed while they're disabled.
+        regs.oar1().write(|_| {});
+        regs.oar2().write(|_| {});
+        regs.oar1().write(|reg| {
+            match self.config.addr {
+                Address::SevenBit(addr) => {
+                    reg.set_oa1((addr as u16) << 1);
+                    reg.set_oa1mode(i2c::vals::Addmode::BIT7);
+                }
+                Address::TenBit(addr) => {
+                    reg.set_oa1(addr);
+                    reg.set_oa1mode(i2c::vals::Addmode::BIT10);
+                }
+            }
+            reg.set_oa1en(true);
+        });
+        if let Some(addr) = self.config.addr2 {
+            regs.oar2().write(|reg| {
+                reg.set_oa2(addr);
+                reg.set_oa2msk(i2c::vals::Oamsk::NOMASK);
+                reg.set_oa2en(true);
+            });
+        }
+
+        regs.cr1().modify(|reg| {
+            reg.set_anfoff(false);
+            reg.set_gcen(self.config.general_call);
+            reg.set_nostretch(!self.config.clock_stretching);
+            reg.set_sbc(false);
+            reg.set_pe(true);
+        });
+    }
+
+    fn check_and_clear_slave_errors(info: &'static Info) -> Result<i2c::regs::Isr, slave::Error> {
+        let isr = info.regs.isr().read();
+        if isr.berr() {
+            info.regs.icr().write(|reg| reg.set_berrcf(true));
+            return Err(slave::Error::Bus);
+        }
+        if isr.arlo() {
+            info.regs.icr().write(|reg| reg.set_arlocf(true));
+            return Err(slave::Error::Arbitration);
+        }
+        if isr.ovr() {
+            info.regs.icr().write(|reg| reg.set_ovrcf(true));
+            return Err(slave::Error::Overrun);
+        }
+        Ok(isr)
+    }
+
+    fn enable_slave_interrupts(info: &'static Info, rx: bool, tx: bool) {
+        info.regs.cr1().modify(|w| {
+            w.set_addrie(true);
+            w.set_stopie(true);
+            w.set_nackie(true);
+            w.set_errie(true);
+            w.set_rxie(rx);
+            w.set_txie(tx);
+        });
+    }
+
+    fn disable_slave_interrupts(info: &'static Info) {
+        info.regs.cr1().modify(|w| {
+            w.set_addrie(false);
+            w.set_stopie(false);
+            w.set_nackie(false);
+            w.set_errie(false);
+            w.set_rxie(false);
+            w.set_txie(false);
+        });
+    }
+
+    /// Wait asynchronously for commands from an I2C master.
+    /// `buffer` is provided in case master does a 'write', 'write read', or 'general call' and is unused for 'read'.
+    ///
+    /// Written data is received using DMA. For 'read' and 'write read', SCL is held low until
+    /// [`respond_to_read`](Self::respond_to_read) is called.
+    pub async fn listen(&mut self, buffer: &mut [u8]) -> Result<Command, slave::Error> {
+        let info = self.info;
+        let state = self.state;
+        let regs = info.regs;
+
+        let _on_drop = OnDrop::new(|| {
+            regs.cr1().modify(|w| w.set_rxdmaen(false));
+            Self::disable_slave_interrupts(info);
+        });
+
+        loop {
+            // Wait to be addressed. Flags left over from the end of the previous transaction are
+            // cleared, but a pending repeated start is kept.
+            let isr = poll_fn(|cx| {
+                state.waker.register(cx.waker());
+
+                let isr = Self::check_and_clear_slave_errors(info)?;
+                if isr.addr() {
+                    return Poll::Ready(Ok(isr));
+                }
+                if isr.stopf() {
+                    regs.icr().write(|w| w.set_stopcf(true));
+                }
+                if isr.nackf() {
+                    regs.icr().write(|w| w.set_nackcf(true));
+                }
+
+                Self::enable_slave_interrupts(info, false, false);
+                Poll::Pending
+            })
+            .await?;
+
+            let addcode = isr.addcode();
+            let general_call = addcode == 0;
+            self.address = match self.config.addr {
+                // 10-bit addresses are matched on the `0b11110xx` header.
+                Address::TenBit(addr) if addcode & 0x7c == 0x78 => addr,
+                _ => addcode as u16,
+            };
+
+            if isr.dir() == i2c::vals::Dir::READ {
+                // ADDR is left set, so SCL is stretched until `respond_to_read`.
+                return Ok(Command::Read);
+            }
+
+            let total_len = buffer.len();
+            let mut transfer = if total_len == 0 {
+                None
+            } else {
+                regs.cr1().modify(|w| w.set_rxdmaen(true));
+                let src = regs.rxdr().as_ptr() as *mut u8;
+                Some(unsafe {
+                    self.rx_dma
+                        .as_mut()
+                        .unwrap()
+                        .read(src, &mut *buffer, Default::default())
+                })
+            };
+
+            // Release SCL to start receiving.
+            regs.icr().write(|w| w.set_addrcf(true));
+
+            let (len, read_next) = poll_fn(|cx| {
+                state.waker.register(cx.waker());
+
+                let isr = Self::check_and_clear_slave_errors(info)?;
+                let (dma_done, remaining) = match transfer.as_mut() {
+                    Some(t) => (
+                        Pin::new(&mut *t).poll(cx).is_ready(),
+                        t.get_remaining_transfers() as usize,
+                    ),
+                    None => (true, 0),
+                };
+                let len = total_len - remaining;
+
+                if dma_done && isr.rxne() {
+                    // More data than fits in the buffer. NACK the next byte so the controller
+                    // stops, and drop the one that was already received.
+                    regs.cr2().modify(|w| w.set_nack(true));
+                    let _ = regs.rxdr().read();
+                    Poll::Ready(Err(match general_call {
+                        true => slave::Error::PartialGeneralCall(total_len),
+                        false => slave::Error::PartialWrite(total_len),
+                    }))
+                } else if isr.stopf() {
+                    regs.icr().write(|w| w.set_stopcf(true));
+                    Poll::Ready(Ok((len, false)))
+                } else if isr.addr() {
+                    // Repeated start. ADDR is left set for the next command.
+                    Poll::Ready(Ok((len, isr.dir() == i2c::vals::Dir::READ)))
+                } else {
+                    Self::enable_slave_interrupts(info, dma_done, false);
+                    Poll::Pending
+                }
+            })
+            .await?;
+
+            drop(transfer);
+            regs.cr1().modify(|w| w.set_rxdmaen(false));
+
+            match (len, read_next) {
+                (_, true) => return Ok(Command::WriteRead(len)),
+                // Zero-length writes, e.g. from bus scans, aren't reported.
+                (0, false) => continue,
+                (_, false) if general_call => return Ok(Command::GeneralCall(len)),
+                (_, false) => return Ok(Command::Write(len)),
+            }
+        }
+    }
+
+    /// Respond to an I2C master READ command, asynchronously.
+    ///
+    /// The response is sent using DMA. The peripheral buffers one byte ahead of the bus, so
+    /// [`ReadStatus::NeedMoreBytes`] is returned as soon as the last byte of `buffer` has been handed
+    /// to the hardware. If the controller then stops reading, the next call returns
+    /// [`ReadStatus::LeftoverBytes`] instead of sending anything.
+    pub async fn respond_to_read(&mut self, buffer: &[u8]) -> Result<ReadStatus, slave::Error> {
+        if buffer.is_empty() {
+            return Err(slave::Error::InvalidResponseBufferLength);
+        }
+
+        let info = self.info;
+        let state = self.state;
+        let regs = info.regs;
+
+        let on_drop = OnDrop::new(|| {
+            regs.cr1().modify(|w| w.set_txdmaen(false));
+            Self::disable_slave_interrupts(info);
+        });
+
+        // At the start of the read, flush data left over from a previous one.
+        let addressed = regs.isr().read().addr();
+        if addressed {
+            regs.isr().write(|w| w.set_txe(true));
+        }
+
+        regs.cr1().modify(|w| w.set_txdmaen(true));
+        let dst = regs.txdr().as_ptr() as *mut u8;
+        let mut transfer = unsafe { self.tx_dma.as_mut().unwrap().write(buffer, dst, Default::default()) };
+
+        if addressed {
+            // Release SCL to start sending.
+            regs.icr().write(|w| w.set_addrcf(true));
+        }
+
+        let result = poll_fn(|cx| {
+            state.waker.register(cx.waker());
+
+            let isr = Self::check_and_clear_slave_errors(info)?;
+            let dma_done = Pin::new(&mut transfer).poll(cx).is_ready();
+
+            if isr.nackf() || isr.stopf() || isr.addr() {
+                // The controller ended the read. Bytes that the DMA didn't copy yet, and the byte
+                // still waiting in TXDR, weren't sent.
+                regs.cr1().modify(|w| w.set_txdmaen(false));
+                let leftover = transfer.get_remaining_transfers() + if isr.txe() { 0 } else { 1 };
+                if isr.nackf() {
+                    regs.icr().write(|w| w.set_nackcf(true));
+                }
+                regs.isr().write(|w| w.set_txe(true));
+
+                Poll::Ready(Ok(match leftover {
+                    0 => ReadStatus::Done,
+                    n => ReadStatus::LeftoverBytes(n),
+                }))
+            } else if dma_done && isr.txis() {
+                Poll::Ready(Ok(ReadStatus::NeedMoreBytes))
+            } else {
+                Self::enable_slave_interrupts(info, false, dma_done);
+                Poll::Pending
+            }
+        })
+        .await;
+
+        drop(transfer);
+        drop(on_drop);
+
+        result
+    }
+}
+
 /// I2C Stop Configuration
 ///
 /// Peripheral options for generating the STOP condition
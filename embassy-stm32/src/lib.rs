@@ -107,6 +107,8 @@ pub mod sai;
 pub mod sdmmc;
 #[cfg(spi)]
 pub mod spi;
+#[cfg(stm32wl)]
+pub mod subghz;
 #[cfg(tsc)]
 pub mod tsc;
 #[cfg(ucpd)]
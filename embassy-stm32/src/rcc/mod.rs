@@ -14,6 +14,8 @@ use critical_section::CriticalSection;
 #[cfg(any(mco, mco1, mco2))]
 pub use mco::*;
 
+pub mod solver;
+
 #[cfg(crs)]
 mod hsi48;
 #[cfg(crs)]
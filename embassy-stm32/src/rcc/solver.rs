@@ -0,0 +1,784 @@
+//! PLL divider solver.
+//!
+//! Picking PLL dividers by hand is error-prone, and an invalid choice is only caught by the
+//! assertions in [`init`](crate::init), if at all. [`solve_pll`] searches the divider space of a
+//! family's PLL for a setting that meets the requested output frequencies, and [`solve_bus`] picks
+//! the AHB and APB prescalers. Both are `const fn`, so the result can be computed at compile time:
+//!
+//! ```rust,ignore
+//! use embassy_stm32::rcc::solver::{self, PllTargets, Target};
+//! use embassy_stm32::time::Hertz;
+//!
+//! const PLL: solver::PllSolution = match solver::solve_pll(
+//!     &solver::F4,
+//!     Hertz(8_000_000),
+//!     PllTargets {
+//!         p: Some(Target::exact(Hertz(168_000_000))),
+//!         q: Some(Target::within(Hertz(48_000_000), 2_500)),
+//!         r: None,
+//!     },
+//! ) {
+//!     Some(pll) => pll,
+//!     None => panic!("no PLL setting for these targets"),
+//! };
+//!
+//! config.rcc.pll = Some(Pll {
+//!     prediv: PllPreDiv::from_bits(PLL.prediv as u8),
+//!     // ...
+//! });
+//! ```
+//!
+//! The solver only deals with the numeric dividers, `prediv / mul / divp / divq / divr` map to the
+//! `PllPreDiv`, `PllMul`, `PllPDiv`, `PllQDiv` and `PllRDiv` values of the family's `Pll` config.
+//! Fractional multipliers are not used.
+
+use core::ops::RangeInclusive;
+
+use crate::time::Hertz;
+
+/// Allowed values of a PLL output divider.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Div {
+    /// The output doesn't exist.
+    None,
+    /// Any value in `min..=max`.
+    Range(u32, u32),
+    /// Any even value in `min..=max`.
+    Even(u32, u32),
+    /// One of the listed values.
+    List(&'static [u32]),
+}
+
+/// Limits of a PLL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PllLimits {
+    /// PLL input frequency, after the pre-divider.
+    pub input: RangeInclusive<Hertz>,
+    /// VCO frequency.
+    pub vco: RangeInclusive<Hertz>,
+    /// Pre-divider (`M`).
+    pub prediv: RangeInclusive<u32>,
+    /// Multiplier (`N`).
+    pub mul: RangeInclusive<u32>,
+    /// `P` output divider.
+    pub divp: Div,
+    /// `Q` output divider.
+    pub divq: Div,
+    /// `R` output divider.
+    pub divr: Div,
+    /// Maximum frequency of any output.
+    pub output_max: Hertz,
+}
+
+/// Limits of the system and bus clocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusLimits {
+    /// Maximum SYSCLK frequency.
+    pub sysclk: Hertz,
+    /// Maximum AHB clock frequency.
+    pub hclk: Hertz,
+    /// Maximum APB1 clock frequency.
+    pub pclk1: Hertz,
+    /// Maximum APB2 clock frequency.
+    pub pclk2: Hertz,
+}
+
+/// STM32F2 main PLL (RM0033).
+pub const F2: PllLimits = PllLimits {
+    input: Hertz(950_000)..=Hertz(2_100_000),
+    vco: Hertz(192_000_000)..=Hertz(432_000_000),
+    prediv: 2..=63,
+    mul: 192..=432,
+    divp: Div::Even(2, 8),
+    divq: Div::Range(2, 15),
+    divr: Div::None,
+    output_max: Hertz(120_000_000),
+};
+
+/// STM32F4 main PLL (RM0090, RM0390).
+///
+/// `R` only exists on some parts, and SYSCLK limits vary with the part.
+pub const F4: PllLimits = PllLimits {
+    input: Hertz(1_000_000)..=Hertz(2_100_000),
+    vco: Hertz(100_000_000)..=Hertz(432_000_000),
+    prediv: 2..=63,
+    mul: 50..=432,
+    divp: Div::Even(2, 8),
+    divq: Div::Range(2, 15),
+    divr: Div::Range(2, 7),
+    output_max: Hertz(180_000_000),
+};
+
+/// STM32F7 main PLL (RM0385, RM0410).
+pub const F7: PllLimits = PllLimits {
+    input: Hertz(1_000_000)..=Hertz(2_100_000),
+    vco: Hertz(100_000_000)..=Hertz(432_000_000),
+    prediv: 2..=63,
+    mul: 50..=432,
+    divp: Div::Even(2, 8),
+    divq: Div::Range(2, 15),
+    divr: Div::Range(2, 7),
+    output_max: Hertz(216_000_000),
+};
+
+/// STM32G4 PLL (RM0440).
+pub const G4: PllLimits = PllLimits {
+    input: Hertz(2_660_000)..=Hertz(16_000_000),
+    vco: Hertz(96_000_000)..=Hertz(344_000_000),
+    prediv: 1..=16,
+    mul: 8..=127,
+    divp: Div::Range(2, 31),
+    divq: Div::Even(2, 8),
+    divr: Div::Even(2, 8),
+    output_max: Hertz(170_000_000),
+};
+
+/// STM32L4 PLL (RM0351).
+pub const L4: PllLimits = PllLimits {
+    input: Hertz(4_000_000)..=Hertz(16_000_000),
+    vco: Hertz(64_000_000)..=Hertz(344_000_000),
+    prediv: 1..=8,
+    mul: 8..=86,
+    divp: Div::List(&[7, 17]),
+    divq: Div::Even(2, 8),
+    divr: Div::Even(2, 8),
+    output_max: Hertz(80_000_000),
+};
+
+/// STM32L4+ PLL (RM0432).
+pub const L4_PLUS: PllLimits = PllLimits {
+    input: Hertz(2_660_000)..=Hertz(16_000_000),
+    vco: Hertz(64_000_000)..=Hertz(344_000_000),
+    prediv: 1..=16,
+    mul: 8..=127,
+    divp: Div::Range(2, 31),
+    divq: Div::Even(2, 8),
+    divr: Div::Even(2, 8),
+    output_max: Hertz(120_000_000),
+};
+
+/// STM32WB main PLL (RM0434).
+pub const WB: PllLimits = PllLimits {
+    input: Hertz(2_660_000)..=Hertz(16_000_000),
+    vco: Hertz(96_000_000)..=Hertz(344_000_000),
+    prediv: 1..=8,
+    mul: 6..=127,
+    divp: Div::Range(2, 32),
+    divq: Div::Range(2, 8),
+    divr: Div::Range(2, 8),
+    output_max: Hertz(64_000_000),
+};
+
+/// STM32WL PLL (RM0453).
+pub const WL: PllLimits = PllLimits {
+    input: Hertz(2_660_000)..=Hertz(16_000_000),
+    vco: Hertz(96_000_000)..=Hertz(344_000_000),
+    prediv: 1..=8,
+    mul: 6..=127,
+    divp: Div::Range(2, 32),
+    divq: Div::Range(2, 8),
+    divr: Div::Range(2, 8),
+    output_max: Hertz(48_000_000),
+};
+
+/// STM32U5 PLL in voltage range 1 (RM0456).
+pub const U5: PllLimits = PllLimits {
+    input: Hertz(4_000_000)..=Hertz(16_000_000),
+    vco: Hertz(128_000_000)..=Hertz(544_000_000),
+    prediv: 1..=16,
+    mul: 4..=512,
+    divp: Div::Range(1, 128),
+    divq: Div::Range(1, 128),
+    divr: Div::Range(1, 128),
+    output_max: Hertz(208_000_000),
+};
+
+/// STM32H5 PLL in voltage scale 0, wide VCO range (RM0481).
+pub const H5: PllLimits = PllLimits {
+    input: Hertz(2_000_000)..=Hertz(16_000_000),
+    vco: Hertz(192_000_000)..=Hertz(836_000_000),
+    prediv: 1..=63,
+    mul: 4..=512,
+    divp: Div::Even(2, 128),
+    divq: Div::Range(1, 128),
+    divr: Div::Range(1, 128),
+    output_max: Hertz(250_000_000),
+};
+
+/// STM32H7 (RM0433) PLL1 in voltage scale 0, wide VCO range.
+pub const H7: PllLimits = PllLimits {
+    input: Hertz(2_000_000)..=Hertz(16_000_000),
+    vco: Hertz(192_000_000)..=Hertz(960_000_000),
+    prediv: 1..=63,
+    mul: 4..=512,
+    divp: Div::Even(2, 128),
+    divq: Div::Range(1, 128),
+    divr: Div::Range(1, 128),
+    output_max: Hertz(480_000_000),
+};
+
+/// STM32F2 bus limits.
+pub const F2_BUS: BusLimits = BusLimits {
+    sysclk: Hertz(120_000_000),
+    hclk: Hertz(120_000_000),
+    pclk1: Hertz(30_000_000),
+    pclk2: Hertz(60_000_000),
+};
+
+/// STM32F405/407/415/417 bus limits.
+pub const F40X_BUS: BusLimits = BusLimits {
+    sysclk: Hertz(168_000_000),
+    hclk: Hertz(168_000_000),
+    pclk1: Hertz(42_000_000),
+    pclk2: Hertz(84_000_000),
+};
+
+/// STM32F42x/43x/446/469/479 bus limits.
+pub const F42X_BUS: BusLimits = BusLimits {
+    sysclk: Hertz(180_000_000),
+    hclk: Hertz(180_000_000),
+    pclk1: Hertz(45_000_000),
+    pclk2: Hertz(90_000_000),
+};
+
+/// STM32F7 bus limits.
+pub const F7_BUS: BusLimits = BusLimits {
+    sysclk: Hertz(216_000_000),
+    hclk: Hertz(216_000_000),
+    pclk1: Hertz(54_000_000),
+    pclk2: Hertz(108_000_000),
+};
+
+/// STM32G4 bus limits.
+pub const G4_BUS: BusLimits = BusLimits {
+    sysclk: Hertz(170_000_000),
+    hclk: Hertz(170_000_000),
+    pclk1: Hertz(170_000_000),
+    pclk2: Hertz(170_000_000),
+};
+
+/// STM32L4 bus limits.
+pub const L4_BUS: BusLimits = BusLimits {
+    sysclk: Hertz(80_000_000),
+    hclk: Hertz(80_000_000),
+    pclk1: Hertz(80_000_000),
+    pclk2: Hertz(80_000_000),
+};
+
+/// STM32U5 bus limits in voltage range 1.
+pub const U5_BUS: BusLimits = BusLimits {
+    sysclk: Hertz(160_000_000),
+    hclk: Hertz(160_000_000),
+    pclk1: Hertz(160_000_000),
+    pclk2: Hertz(160_000_000),
+};
+
+/// A requested output frequency.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// Frequency.
+    pub freq: Hertz,
+    /// Maximum error, in parts per million.
+    pub max_error_ppm: u32,
+}
+
+impl Target {
+    /// Exactly `freq`.
+    pub const fn exact(freq: Hertz) -> Self {
+        Self { freq, max_error_ppm: 0 }
+    }
+
+    /// `freq`, within `max_error_ppm` parts per million.
+    ///
+    /// USB full speed requires 48 MHz within 2500 ppm, for example.
+    pub const fn within(freq: Hertz, max_error_ppm: u32) -> Self {
+        Self { freq, max_error_ppm }
+    }
+}
+
+/// Requested PLL outputs. Outputs set to `None` are left disabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PllTargets {
+    /// `P` output.
+    pub p: Option<Target>,
+    /// `Q` output.
+    pub q: Option<Target>,
+    /// `R` output.
+    pub r: Option<Target>,
+}
+
+/// PLL dividers found by [`solve_pll`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PllSolution {
+    /// Pre-divider (`M`).
+    pub prediv: u32,
+    /// Multiplier (`N`).
+    pub mul: u32,
+    /// `P` output divider, if requested.
+    pub divp: Option<u32>,
+    /// `Q` output divider, if requested.
+    pub divq: Option<u32>,
+    /// `R` output divider, if requested.
+    pub divr: Option<u32>,
+    /// VCO frequency, rounded to the nearest hertz.
+    pub vco: Hertz,
+}
+
+impl PllSolution {
+    /// Frequency of the `P` output.
+    pub const fn p(&self) -> Option<Hertz> {
+        output(self.vco, self.divp)
+    }
+
+    /// Frequency of the `Q` output.
+    pub const fn q(&self) -> Option<Hertz> {
+        output(self.vco, self.divq)
+    }
+
+    /// Frequency of the `R` output.
+    pub const fn r(&self) -> Option<Hertz> {
+        output(self.vco, self.divr)
+    }
+}
+
+const fn output(vco: Hertz, div: Option<u32>) -> Option<Hertz> {
+    match div {
+        Some(div) => Some(Hertz(vco.0 / div)),
+        None => None,
+    }
+}
+
+/// AHB and APB prescalers found by [`solve_bus`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusSolution {
+    /// AHB prescaler.
+    pub ahb: u32,
+    /// APB1 prescaler.
+    pub apb1: u32,
+    /// APB2 prescaler.
+    pub apb2: u32,
+}
+
+/// Error of `freq` relative to `target`, in parts per million.
+const fn error_ppm(freq: u64, target: u64) -> u64 {
+    freq.abs_diff(target) * 1_000_000 / target
+}
+
+const fn div_allowed(div: Div, value: u32) -> bool {
+    match div {
+        Div::None => false,
+        Div::Range(min, max) => min <= value && value <= max,
+        Div::Even(min, max) => min <= value && value <= max && value % 2 == 0,
+        Div::List(values) => {
+            let mut i = 0;
+            while i < values.len() {
+                if values[i] == value {
+                    return true;
+                }
+                i += 1;
+            }
+            false
+        }
+    }
+}
+
+/// Best divider of a VCO running at `vco / prediv` for `target`, returning the divider and its
+/// error in ppm. The VCO frequency is kept as a fraction, as the pre-divider needn't divide the
+/// source frequency.
+const fn best_div(div: Div, vco: u64, prediv: u64, target: Target, output_max: Hertz) -> Option<(u32, u64)> {
+    let target_freq = target.freq.0 as u64;
+    let ideal = (vco + target_freq * prediv / 2) / (target_freq * prediv);
+    let mut best: Option<(u32, u64)> = None;
+
+    // The closest divider is within two of the rounded ideal one, except for divider lists.
+    let (mut d, end) = match div {
+        Div::None => return None,
+        Div::List(_) => (1, 128),
+        _ => (if ideal > 2 { ideal as u32 - 2 } else { 1 }, ideal as u32 + 2),
+    };
+    while d <= end {
+        let scale = prediv * d as u64;
+        if div_allowed(div, d) && vco <= output_max.0 as u64 * scale {
+            let err = error_ppm(vco, target_freq * scale);
+            let better = match best {
+                Some((_, best_err)) => err < best_err,
+                None => true,
+            };
+            if err <= target.max_error_ppm as u64 && better {
+                best = Some((d, err));
+            }
+        }
+        d += 1;
+    }
+    best
+}
+
+/// Evaluates all requested outputs for a VCO running at `vco / prediv`, returning the dividers and
+/// total error.
+const fn evaluate(limits: &PllLimits, vco: u64, prediv: u64, targets: &PllTargets) -> Option<([Option<u32>; 3], u64)> {
+    let outputs = [
+        (targets.p, limits.divp),
+        (targets.q, limits.divq),
+        (targets.r, limits.divr),
+    ];
+    let mut divs = [None; 3];
+    let mut total = 0;
+    let mut i = 0;
+    while i < 3 {
+        if let Some(target) = outputs[i].0 {
+            match best_div(outputs[i].1, vco, prediv, target, limits.output_max) {
+                Some((div, err)) => {
+                    divs[i] = Some(div);
+                    total += err;
+                }
+                None => return None,
+            }
+        }
+        i += 1;
+    }
+    Some((divs, total))
+}
+
+/// Find PLL dividers producing the requested outputs from a `source` frequency.
+///
+/// Among the settings meeting every target, the one with the smallest total error is returned.
+/// Ties are broken by the smallest pre-divider, as a higher PLL input frequency gives lower
+/// jitter. Returns `None` if no setting meets the targets within the PLL's limits.
+pub const fn solve_pll(limits: &PllLimits, source: Hertz, targets: PllTargets) -> Option<PllSolution> {
+    // The multiplier is derived from the tightest target, then every other output is matched
+    // as closely as possible.
+    let outputs = [
+        (targets.p, limits.divp),
+        (targets.q, limits.divq),
+        (targets.r, limits.divr),
+    ];
+    let mut primary: Option<(Target, Div)> = None;
+    let mut i = 0;
+    while i < 3 {
+        if let Some(target) = outputs[i].0 {
+            let tighter = match primary {
+                Some((p, _)) => target.max_error_ppm < p.max_error_ppm,
+                None => true,
+            };
+            if tighter {
+                primary = Some((target, outputs[i].1));
+            }
+        }
+        i += 1;
+    }
+    let Some((primary, primary_div)) = primary else {
+        return None;
+    };
+
+    let mut best: Option<(PllSolution, u64)> = None;
+
+    // Frequencies after the pre-divider are compared as fractions over `m`, so that any
+    // pre-divider can be used with any source.
+    let source = source.0 as u64;
+    let mut m = *limits.prediv.start();
+    while m <= *limits.prediv.end() {
+        let m64 = m as u64;
+        if limits.input.start().0 as u64 * m64 <= source && source <= limits.input.end().0 as u64 * m64 {
+            let mut d = 1;
+            while d <= 128 {
+                if div_allowed(primary_div, d) {
+                    let ideal = primary.freq.0 as u64 * d as u64 * m64;
+                    let n = ((ideal + source / 2) / source) as u32;
+                    let vco = source * n as u64;
+                    let n_ok = *limits.mul.start() <= n && n <= *limits.mul.end();
+                    let vco_ok = limits.vco.start().0 as u64 * m64 <= vco && vco <= limits.vco.end().0 as u64 * m64;
+                    if n_ok && vco_ok {
+                        if let Some((divs, err)) = evaluate(limits, vco, m64, &targets) {
+                            let better = match best {
+                                Some((_, best_err)) => err < best_err,
+                                None => true,
+                            };
+                            if better {
+                                let solution = PllSolution {
+                                    prediv: m,
+                                    mul: n,
+                                    divp: divs[0],
+                                    divq: divs[1],
+                                    divr: divs[2],
+                                    vco: Hertz(((vco + m64 / 2) / m64) as u32),
+                                };
+                                best = Some((solution, err));
+                            }
+                        }
+                    }
+                }
+                d += 1;
+            }
+        }
+        m += 1;
+    }
+
+    match best {
+        Some((solution, _)) => Some(solution),
+        None => None,
+    }
+}
+
+const AHB_DIVS: [u32; 9] = [1, 2, 4, 8, 16, 64, 128, 256, 512];
+const APB_DIVS: [u32; 5] = [1, 2, 4, 8, 16];
+
+const fn smallest_div(divs: &[u32], freq: u32, max: Hertz) -> Option<u32> {
+    let mut i = 0;
+    while i < divs.len() {
+        if freq / divs[i] <= max.0 {
+            return Some(divs[i]);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Find the smallest AHB and APB prescalers keeping every bus within its limits.
+///
+/// Returns `None` if `sysclk` itself is out of range.
+pub const fn solve_bus(limits: &BusLimits, sysclk: Hertz) -> Option<BusSolution> {
+    if sysclk.0 > limits.sysclk.0 {
+        return None;
+    }
+    let Some(ahb) = smallest_div(&AHB_DIVS, sysclk.0, limits.hclk) else {
+        return None;
+    };
+    let hclk = sysclk.0 / ahb;
+    let Some(apb1) = smallest_div(&APB_DIVS, hclk, limits.pclk1) else {
+        return None;
+    };
+    let Some(apb2) = smallest_div(&APB_DIVS, hclk, limits.pclk2) else {
+        return None;
+    };
+    Some(BusSolution { ahb, apb1, apb2 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [(&str, &PllLimits); 11] = [
+        ("f2", &F2),
+        ("f4", &F4),
+        ("f7", &F7),
+        ("g4", &G4),
+        ("l4", &L4),
+        ("l4+", &L4_PLUS),
+        ("wb", &WB),
+        ("wl", &WL),
+        ("u5", &U5),
+        ("h5", &H5),
+        ("h7", &H7),
+    ];
+
+    fn check(limits: &PllLimits, source: Hertz, targets: &PllTargets, s: &PllSolution) {
+        assert!(limits.prediv.contains(&s.prediv));
+        assert!(limits.mul.contains(&s.mul));
+        // Frequencies after the pre-divider, times the pre-divider.
+        let prediv = s.prediv as u64;
+        let range = |r: &RangeInclusive<Hertz>| r.start().0 as u64 * prediv..=r.end().0 as u64 * prediv;
+        assert!(range(&limits.input).contains(&(source.0 as u64)));
+        let vco = source.0 as u64 * s.mul as u64;
+        assert!(range(&limits.vco).contains(&vco));
+        assert_eq!(s.vco.0 as u64, (vco + prediv / 2) / prediv);
+
+        for (target, div, limit) in [
+            (targets.p, s.divp, limits.divp),
+            (targets.q, s.divq, limits.divq),
+            (targets.r, s.divr, limits.divr),
+        ] {
+            match target {
+                Some(target) => {
+                    let div = div.unwrap();
+                    assert!(div_allowed(limit, div));
+                    let scale = prediv * div as u64;
+                    assert!(vco <= limits.output_max.0 as u64 * scale);
+                    assert!(error_ppm(vco, target.freq.0 as u64 * scale) <= target.max_error_ppm as u64);
+                }
+                None => assert_eq!(div, None),
+            }
+        }
+    }
+
+    #[test]
+    fn f4_168mhz_usb() {
+        let targets = PllTargets {
+            p: Some(Target::exact(Hertz(168_000_000))),
+            q: Some(Target::exact(Hertz(48_000_000))),
+            r: None,
+        };
+        let s = solve_pll(&F4, Hertz(8_000_000), targets).unwrap();
+        check(&F4, Hertz(8_000_000), &targets, &s);
+        assert_eq!((s.prediv, s.mul, s.divp, s.divq), (4, 168, Some(2), Some(7)));
+    }
+
+    #[test]
+    fn prediv_not_dividing_source() {
+        // Audio clocks from 25 and 26 MHz crystals need a pre-divider which doesn't divide the
+        // source, 25 MHz / 21 and 26 MHz / 9 here.
+        for (limits, source, targets) in [
+            (
+                &F4,
+                Hertz(25_000_000),
+                PllTargets {
+                    p: None,
+                    q: Some(Target::within(Hertz(49_152_000), 100)),
+                    r: None,
+                },
+            ),
+            (
+                &H7,
+                Hertz(26_000_000),
+                PllTargets {
+                    p: None,
+                    q: Some(Target::within(Hertz(12_288_000), 100)),
+                    r: None,
+                },
+            ),
+        ] {
+            let s = solve_pll(limits, source, targets).unwrap();
+            check(limits, source, &targets, &s);
+            assert_ne!(0, source.0 % s.prediv);
+        }
+    }
+
+    #[test]
+    fn const_eval() {
+        const PLL: Option<PllSolution> = solve_pll(
+            &G4,
+            Hertz(24_000_000),
+            PllTargets {
+                p: None,
+                q: None,
+                r: Some(Target::exact(Hertz(170_000_000))),
+            },
+        );
+        let s = PLL.unwrap();
+        check(
+            &G4,
+            Hertz(24_000_000),
+            &PllTargets {
+                p: None,
+                q: None,
+                r: Some(Target::exact(Hertz(170_000_000))),
+            },
+            &s,
+        );
+        assert_eq!(s.r(), Some(Hertz(170_000_000)));
+    }
+
+    #[test]
+    fn sai_audio() {
+        // 49.152 MHz (48 kHz * 1024) isn't reachable exactly with an integer multiplier.
+        let targets = PllTargets {
+            p: None,
+            q: Some(Target::within(Hertz(49_152_000), 1_000)),
+            r: None,
+        };
+        let s = solve_pll(&F4, Hertz(8_000_000), targets).unwrap();
+        check(&F4, Hertz(8_000_000), &targets, &s);
+
+        let targets = PllTargets {
+            q: Some(Target::exact(Hertz(49_152_000))),
+            ..targets
+        };
+        assert!(solve_pll(&F4, Hertz(8_000_000), targets).is_none());
+    }
+
+    #[test]
+    fn impossible() {
+        let targets = PllTargets {
+            p: Some(Target::exact(Hertz(500_000_000))),
+            q: None,
+            r: None,
+        };
+        for (_, limits) in ALL {
+            assert!(solve_pll(limits, Hertz(8_000_000), targets).is_none());
+        }
+        assert!(solve_pll(
+            &F4,
+            Hertz(8_000_000),
+            PllTargets {
+                p: None,
+                q: None,
+                r: None
+            }
+        )
+        .is_none());
+        assert!(solve_pll(
+            &F2,
+            Hertz(8_000_000),
+            PllTargets {
+                p: None,
+                q: None,
+                r: Some(Target::exact(Hertz(48_000_000)))
+            }
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn all_families() {
+        // Every family can produce its maximum output frequency, and USB where it's usually taken
+        // from the main PLL, from common crystals.
+        let usb = Target::exact(Hertz(48_000_000));
+        for (name, limits) in ALL {
+            for source in [8_000_000, 12_000_000, 16_000_000, 24_000_000] {
+                let targets = match name {
+                    "f2" | "f7" | "h7" => PllTargets {
+                        p: Some(Target::exact(limits.output_max)),
+                        q: Some(usb),
+                        r: None,
+                    },
+                    "f4" => PllTargets {
+                        p: Some(Target::exact(Hertz(168_000_000))),
+                        q: Some(usb),
+                        r: None,
+                    },
+                    "h5" => PllTargets {
+                        p: Some(Target::exact(limits.output_max)),
+                        q: None,
+                        r: None,
+                    },
+                    _ => PllTargets {
+                        p: None,
+                        q: None,
+                        r: Some(Target::exact(limits.output_max)),
+                    },
+                };
+                let s = solve_pll(limits, Hertz(source), targets)
+                    .unwrap_or_else(|| panic!("{} from {} Hz: no solution", name, source));
+                check(limits, Hertz(source), &targets, &s);
+            }
+        }
+    }
+
+    #[test]
+    fn bus() {
+        let b = solve_bus(&F40X_BUS, Hertz(168_000_000)).unwrap();
+        assert_eq!(
+            b,
+            BusSolution {
+                ahb: 1,
+                apb1: 4,
+                apb2: 2
+            }
+        );
+        let b = solve_bus(&F42X_BUS, Hertz(16_000_000)).unwrap();
+        assert_eq!(
+            b,
+            BusSolution {
+                ahb: 1,
+                apb1: 1,
+                apb2: 1
+            }
+        );
+        assert!(solve_bus(&L4_BUS, Hertz(120_000_000)).is_none());
+        for bus in [F2_BUS, F40X_BUS, F42X_BUS, F7_BUS, G4_BUS, L4_BUS, U5_BUS] {
+            let b = solve_bus(&bus, bus.sysclk).unwrap();
+            assert!(bus.sysclk.0 / b.ahb <= bus.hclk.0);
+            assert!(bus.sysclk.0 / b.ahb / b.apb1 <= bus.pclk1.0);
+            assert!(bus.sysclk.0 / b.ahb / b.apb2 <= bus.pclk2.0);
+        }
+    }
+}
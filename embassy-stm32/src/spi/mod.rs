@@ -17,6 +17,9 @@ use crate::rcc::{RccInfo, SealedRccPeripheral};
 use crate::time::Hertz;
 use crate::Peripheral;
 
+#[cfg(all(feature = "exti", any(spi_v1, spi_f1, spi_v2)))]
+pub mod slave;
+
 /// SPI error.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -0,0 +1,417 @@
+//! Serial Peripheral Interface in slave mode.
+//!
+//! The API mirrors `embassy_nrf::spis`: each call to [`SpiSlave::transfer`] (or one of its variants) arms
+//! DMA with the buffers for exactly one transaction, which ends when the host deasserts NSS. The TX
+//! buffer is loaded before the host starts the transaction, so the first byte clocked out is the
+//! first byte of `write`.
+//!
+//! NSS is handled by the SPI peripheral, and the driver additionally watches it through EXTI to
+//! detect the end of a transaction. This needs the `exti` feature, and the EXTI channel of the NSS pin.
+//!
+//! Only SPI versions 1 and 2 (all families except H5, H7, U5 and similar) are supported for now.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use embassy_embedded_hal::SetConfig;
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::{into_ref, PeripheralRef};
+pub use embedded_hal_02::spi::{Mode, Phase, Polarity, MODE_0, MODE_1, MODE_2, MODE_3};
+
+use super::{
+    set_rxdmaen, set_txdmaen, BitOrder, CsPin, Info, Instance, MisoPin, MosiPin, RegsExt, RxDma, SckPin, TxDma,
+};
+use crate::dma::ChannelAndRequest;
+use crate::exti::{Channel, ExtiInputFuture};
+use crate::gpio::{AfType, AnyPin, OutputType, Pull, SealedPin as _, Speed};
+use crate::pac::gpio::vals::Idr;
+use crate::pac::spi::{vals, Spi as Regs};
+use crate::Peripheral;
+
+/// SPI slave error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// TX buffer was too long.
+    TxBufferTooLong,
+    /// RX buffer was too long.
+    RxBufferTooLong,
+}
+
+/// SPI slave configuration.
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// SPI mode.
+    pub mode: Mode,
+    /// Bit order.
+    pub bit_order: BitOrder,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: MODE_0,
+            bit_order: BitOrder::MsbFirst,
+        }
+    }
+}
+
+impl Config {
+    fn raw_phase(&self) -> vals::Cpha {
+        match self.mode.phase {
+            Phase::CaptureOnSecondTransition => vals::Cpha::SECONDEDGE,
+            Phase::CaptureOnFirstTransition => vals::Cpha::FIRSTEDGE,
+        }
+    }
+
+    fn raw_polarity(&self) -> vals::Cpol {
+        match self.mode.polarity {
+            Polarity::IdleHigh => vals::Cpol::IDLEHIGH,
+            Polarity::IdleLow => vals::Cpol::IDLELOW,
+        }
+    }
+
+    fn raw_byte_order(&self) -> vals::Lsbfirst {
+        match self.bit_order {
+            BitOrder::LsbFirst => vals::Lsbfirst::LSBFIRST,
+            BitOrder::MsbFirst => vals::Lsbfirst::MSBFIRST,
+        }
+    }
+}
+
+/// SPI slave driver.
+pub struct SpiSlave<'d> {
+    info: &'static Info,
+    sck: PeripheralRef<'d, AnyPin>,
+    mosi: Option<PeripheralRef<'d, AnyPin>>,
+    miso: Option<PeripheralRef<'d, AnyPin>>,
+    cs: PeripheralRef<'d, AnyPin>,
+    tx_dma: ChannelAndRequest<'d>,
+    rx_dma: ChannelAndRequest<'d>,
+    config: Config,
+    overflow: bool,
+    overread: bool,
+}
+
+impl<'d> SpiSlave<'d> {
+    /// Create a new SPI slave driver.
+    ///
+    /// `cs_exti` must be the EXTI channel of the `cs` pin.
+    pub fn new<T: Instance, C: CsPin<T>>(
+        peri: impl Peripheral<P = T> + 'd,
+        sck: impl Peripheral<P = impl SckPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        cs: impl Peripheral<P = C> + 'd,
+        cs_exti: impl Peripheral<P = C::ExtiChannel> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        Self::new_inner(
+            peri,
+            new_pin!(sck, AfType::input(Pull::None)).unwrap(),
+            new_pin!(mosi, AfType::input(Pull::None)),
+            new_pin!(miso, AfType::output(OutputType::PushPull, Speed::VeryHigh)),
+            new_pin!(cs, AfType::input(Pull::None)).unwrap(),
+            cs_exti,
+            new_dma!(tx_dma).unwrap(),
+            new_dma!(rx_dma).unwrap(),
+            config,
+        )
+    }
+
+    /// Create a new SPI slave driver, capable of TX only (MISO only).
+    pub fn new_txonly<T: Instance, C: CsPin<T>>(
+        peri: impl Peripheral<P = T> + 'd,
+        sck: impl Peripheral<P = impl SckPin<T>> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        cs: impl Peripheral<P = C> + 'd,
+        cs_exti: impl Peripheral<P = C::ExtiChannel> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        Self::new_inner(
+            peri,
+            new_pin!(sck, AfType::input(Pull::None)).unwrap(),
+            None,
+            new_pin!(miso, AfType::output(OutputType::PushPull, Speed::VeryHigh)),
+            new_pin!(cs, AfType::input(Pull::None)).unwrap(),
+            cs_exti,
+            new_dma!(tx_dma).unwrap(),
+            new_dma!(rx_dma).unwrap(),
+            config,
+        )
+    }
+
+    /// Create a new SPI slave driver, capable of RX only (MOSI only).
+    pub fn new_rxonly<T: Instance, C: CsPin<T>>(
+        peri: impl Peripheral<P = T> + 'd,
+        sck: impl Peripheral<P = impl SckPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        cs: impl Peripheral<P = C> + 'd,
+        cs_exti: impl Peripheral<P = C::ExtiChannel> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        Self::new_inner(
+            peri,
+            new_pin!(sck, AfType::input(Pull::None)).unwrap(),
+            new_pin!(mosi, AfType::input(Pull::None)),
+            None,
+            new_pin!(cs, AfType::input(Pull::None)).unwrap(),
+            cs_exti,
+            new_dma!(tx_dma).unwrap(),
+            new_dma!(rx_dma).unwrap(),
+            config,
+        )
+    }
+
+    fn new_inner<T: Instance>(
+        _peri: impl Peripheral<P = T> + 'd,
+        sck: PeripheralRef<'d, AnyPin>,
+        mosi: Option<PeripheralRef<'d, AnyPin>>,
+        miso: Option<PeripheralRef<'d, AnyPin>>,
+        cs: PeripheralRef<'d, AnyPin>,
+        cs_exti: impl Peripheral<P = impl Channel> + 'd,
+        tx_dma: ChannelAndRequest<'d>,
+        rx_dma: ChannelAndRequest<'d>,
+        config: Config,
+    ) -> Self {
+        into_ref!(cs_exti);
+        // Needed if using AnyPin+AnyChannel.
+        assert_eq!(cs._pin(), cs_exti.number());
+
+        let mut this = Self {
+            info: T::info(),
+            sck,
+            mosi,
+            miso,
+            cs,
+            tx_dma,
+            rx_dma,
+            config,
+            overflow: false,
+            overread: false,
+        };
+        this.init();
+        this
+    }
+
+    /// Reset the peripheral and apply the configuration, leaving it disabled.
+    ///
+    /// Data queued in the TX FIFO can only be discarded by a peripheral reset, so this is done before
+    /// every transaction.
+    fn init(&mut self) {
+        let regs = self.info.regs;
+        let cpha = self.config.raw_phase();
+        let cpol = self.config.raw_polarity();
+        let lsbfirst = self.config.raw_byte_order();
+
+        self.info.rcc.disable();
+        self.info.rcc.enable_and_reset();
+
+        #[cfg(any(spi_v1, spi_f1))]
+        {
+            regs.cr2().modify(|w| {
+                w.set_ssoe(false);
+            });
+            regs.cr1().modify(|w| {
+                w.set_cpha(cpha);
+                w.set_cpol(cpol);
+
+                w.set_mstr(vals::Mstr::SLAVE);
+                w.set_lsbfirst(lsbfirst);
+                w.set_ssm(false);
+                w.set_crcen(false);
+                w.set_bidimode(vals::Bidimode::UNIDIRECTIONAL);
+                w.set_rxonly(vals::Rxonly::FULLDUPLEX);
+                w.set_dff(vals::Dff::BITS8);
+            });
+        }
+        #[cfg(spi_v2)]
+        {
+            regs.cr2().modify(|w| {
+                w.set_frxth(vals::Frxth::QUARTER);
+                w.set_ds(vals::Ds::BITS8);
+                w.set_ssoe(false);
+            });
+            regs.cr1().modify(|w| {
+                w.set_cpha(cpha);
+                w.set_cpol(cpol);
+
+                w.set_mstr(vals::Mstr::SLAVE);
+                w.set_lsbfirst(lsbfirst);
+                w.set_ssm(false);
+                w.set_crcen(false);
+                w.set_bidimode(vals::Bidimode::UNIDIRECTIONAL);
+            });
+        }
+    }
+
+    fn cs_is_low(&self) -> bool {
+        self.cs.block().idr().read().idr(self.cs._pin() as _) == Idr::LOW
+    }
+
+    fn cs_rising_edge(&self) -> ExtiInputFuture<'static> {
+        ExtiInputFuture::new(self.cs._pin(), self.cs._port(), true, false)
+    }
+
+    async fn async_inner(&mut self, rx: *mut [u8], tx: *const [u8]) -> Result<(usize, usize), Error> {
+        if tx.len() > 0xFFFF {
+            return Err(Error::TxBufferTooLong);
+        }
+        if rx.len() > 0xFFFF {
+            return Err(Error::RxBufferTooLong);
+        }
+
+        // Don't join a transaction that is already in progress, since its start was missed.
+        {
+            let idle = self.cs_rising_edge();
+            if self.cs_is_low() {
+                idle.await;
+            }
+        }
+        let end = self.cs_rising_edge();
+
+        self.init();
+
+        let regs = self.info.regs;
+        let on_drop = OnDrop::new(|| {
+            regs.cr1().modify(|w| w.set_spe(false));
+            set_txdmaen(regs, false);
+            set_rxdmaen(regs, false);
+        });
+
+        set_rxdmaen(regs, true);
+        let rx_src = regs.rx_ptr();
+        let mut rx_transfer = match rx.len() {
+            0 => None,
+            _ => Some(unsafe { self.rx_dma.read_raw(rx_src, rx, Default::default()) }),
+        };
+
+        let tx_dst = regs.tx_ptr();
+        let tx_transfer = match tx.len() {
+            0 => None,
+            _ => Some(unsafe { self.tx_dma.write_raw(tx, tx_dst, Default::default()) }),
+        };
+        set_txdmaen(regs, true);
+
+        regs.cr1().modify(|w| w.set_spe(true));
+
+        end.await;
+
+        // Let the DMA catch up with the last byte.
+        if let Some(t) = rx_transfer.as_mut() {
+            while t.is_running() && rx_pending(regs) {}
+        }
+
+        let rx_remaining = rx_transfer.as_ref().map_or(0, |t| t.get_remaining_transfers()) as usize;
+        let tx_remaining = tx_transfer.as_ref().map_or(0, |t| t.get_remaining_transfers()) as usize;
+        let n_rx = rx.len() - rx_remaining;
+        self.overflow = rx_remaining == 0 && (rx_pending(regs) || regs.sr().read().ovr());
+        let tx_queued = tx_queued(regs);
+
+        drop(rx_transfer);
+        drop(tx_transfer);
+        drop(on_drop);
+
+        compiler_fence(Ordering::SeqCst);
+
+        // Every byte received means one byte was sent. Past the end of `rx` that count is lost, and the
+        // TX side is used instead.
+        let n_tx = match self.overflow {
+            false => n_rx.min(tx.len()),
+            true => (tx.len() - tx_remaining).saturating_sub(tx_queued),
+        };
+        self.overread = match self.overflow {
+            false => n_rx > tx.len(),
+            true => tx_remaining == 0 && tx_queued == 0,
+        };
+
+        Ok((n_rx, n_tx))
+    }
+
+    /// Reads data from the SPI bus without sending anything.
+    /// Returns number of bytes read.
+    pub async fn read(&mut self, data: &mut [u8]) -> Result<usize, Error> {
+        self.async_inner(data, &[]).await.map(|n| n.0)
+    }
+
+    /// Simultaneously sends and receives data.
+    /// Returns number of bytes transferred `(n_rx, n_tx)`.
+    ///
+    /// The transaction ends when the host deasserts NSS. If the host clocks more bytes than fit in
+    /// `read`, `n_tx` may also count bytes that were loaded into the peripheral but not sent.
+    pub async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(usize, usize), Error> {
+        self.async_inner(read, write).await
+    }
+
+    /// Simultaneously sends and receives data. Places the received data into the same buffer.
+    /// Returns number of bytes transferred.
+    pub async fn transfer_in_place(&mut self, data: &mut [u8]) -> Result<usize, Error> {
+        self.async_inner(data, data).await.map(|n| n.0)
+    }
+
+    /// Sends data, discarding any received data.
+    /// Returns number of bytes written.
+    pub async fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.async_inner(&mut [], data).await.map(|n| n.1)
+    }
+
+    /// Checks if last transaction overread, i.e. the host clocked more bytes than there were in the
+    /// write buffer.
+    ///
+    /// What's sent on MISO after the end of the write buffer depends on the chip family.
+    pub fn is_overread(&mut self) -> bool {
+        self.overread
+    }
+
+    /// Checks if last transaction overflowed, i.e. the host sent more bytes than fit in the read buffer.
+    pub fn is_overflow(&mut self) -> bool {
+        self.overflow
+    }
+}
+
+impl<'d> Drop for SpiSlave<'d> {
+    fn drop(&mut self) {
+        self.sck.set_as_disconnected();
+        self.mosi.as_ref().map(|x| x.set_as_disconnected());
+        self.miso.as_ref().map(|x| x.set_as_disconnected());
+        self.cs.set_as_disconnected();
+
+        self.info.rcc.disable();
+    }
+}
+
+impl<'d> SetConfig for SpiSlave<'d> {
+    type Config = Config;
+    type ConfigError = ();
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), ()> {
+        self.config = *config;
+        self.init();
+        Ok(())
+    }
+}
+
+/// Whether the peripheral holds received bytes that DMA didn't read yet.
+fn rx_pending(regs: Regs) -> bool {
+    let sr = regs.sr().read();
+    #[cfg(any(spi_v1, spi_f1))]
+    return sr.rxne();
+    #[cfg(spi_v2)]
+    return sr.frlvl() != vals::Frlvl::EMPTY;
+}
+
+/// Number of bytes loaded into the peripheral but not sent yet.
+fn tx_queued(regs: Regs) -> usize {
+    let sr = regs.sr().read();
+    #[cfg(any(spi_v1, spi_f1))]
+    return (!sr.txe()) as usize;
+    // With 8-bit frames, each quarter of the FIFO is one byte.
+    #[cfg(spi_v2)]
+    return sr.ftlvl().to_bits() as usize;
+}
@@ -0,0 +1,1367 @@
+//! Radio command encoding and response decoding.
+//!
+//! Everything in here is plain data, independent of the hardware.
+
+/// Oscillator frequency of the radio, in Hz.
+const XTAL_FREQ: u64 = 32_000_000;
+
+/// Command opcodes.
+#[allow(missing_docs)]
+pub mod opcode {
+    pub const SET_SLEEP: u8 = 0x84;
+    pub const SET_STANDBY: u8 = 0x80;
+    pub const SET_FS: u8 = 0xC1;
+    pub const SET_TX: u8 = 0x83;
+    pub const SET_RX: u8 = 0x82;
+    pub const SET_STOP_RX_TIMER_ON_PREAMBLE: u8 = 0x9F;
+    pub const SET_CAD: u8 = 0xC5;
+    pub const SET_TX_CONTINUOUS_WAVE: u8 = 0xD1;
+    pub const SET_TX_CONTINUOUS_PREAMBLE: u8 = 0xD2;
+    pub const SET_PACKET_TYPE: u8 = 0x8A;
+    pub const GET_PACKET_TYPE: u8 = 0x11;
+    pub const SET_RF_FREQUENCY: u8 = 0x86;
+    pub const SET_TX_PARAMS: u8 = 0x8E;
+    pub const SET_PA_CONFIG: u8 = 0x95;
+    pub const SET_CAD_PARAMS: u8 = 0x88;
+    pub const SET_BUFFER_BASE_ADDRESS: u8 = 0x8F;
+    pub const SET_MODULATION_PARAMS: u8 = 0x8B;
+    pub const SET_PACKET_PARAMS: u8 = 0x8C;
+    pub const SET_LORA_SYMB_NUM_TIMEOUT: u8 = 0xA0;
+    pub const GET_STATUS: u8 = 0xC0;
+    pub const GET_RX_BUFFER_STATUS: u8 = 0x13;
+    pub const GET_PACKET_STATUS: u8 = 0x14;
+    pub const GET_RSSI_INST: u8 = 0x15;
+    pub const GET_STATS: u8 = 0x10;
+    pub const RESET_STATS: u8 = 0x00;
+    pub const SET_DIO_IRQ_PARAMS: u8 = 0x08;
+    pub const GET_IRQ_STATUS: u8 = 0x12;
+    pub const CLEAR_IRQ_STATUS: u8 = 0x02;
+    pub const CALIBRATE: u8 = 0x89;
+    pub const CALIBRATE_IMAGE: u8 = 0x98;
+    pub const SET_REGULATOR_MODE: u8 = 0x96;
+    pub const GET_ERROR: u8 = 0x17;
+    pub const CLR_ERROR: u8 = 0x07;
+    pub const SET_TCXO_MODE: u8 = 0x97;
+    pub const WRITE_REGISTER: u8 = 0x0D;
+    pub const READ_REGISTER: u8 = 0x1D;
+    pub const WRITE_BUFFER: u8 = 0x0E;
+    pub const READ_BUFFER: u8 = 0x1E;
+}
+
+/// Register addresses used by the driver.
+#[allow(missing_docs)]
+pub mod reg {
+    pub const GFSK_WHITENING_INIT: u16 = 0x06B8;
+    pub const GFSK_CRC_INIT: u16 = 0x06BC;
+    pub const GFSK_CRC_POLY: u16 = 0x06BE;
+    pub const GFSK_SYNC_WORD: u16 = 0x06C0;
+    pub const GFSK_NODE_ADDR: u16 = 0x06CD;
+    pub const GFSK_BROADCAST_ADDR: u16 = 0x06CE;
+    pub const LORA_SYNC_WORD: u16 = 0x0740;
+    pub const RX_GAIN: u16 = 0x08AC;
+    pub const PA_OCP: u16 = 0x08E7;
+}
+
+/// Radio operation timeout, in steps of 15.625 µs.
+///
+/// The timeout is a 24-bit value, so the longest timeout is a little over 262 seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timeout(u32);
+
+impl Timeout {
+    /// No timeout.
+    ///
+    /// For TX and CAD the operation runs until it's done, for RX the radio returns to standby after
+    /// receiving one packet.
+    pub const DISABLED: Self = Self(0);
+    /// RX stays enabled after receiving a packet, until it's stopped with another command.
+    pub const CONTINUOUS: Self = Self(0xFF_FFFF);
+    /// Longest timeout that doesn't have a special meaning.
+    pub const MAX: Self = Self(0xFF_FFFE);
+
+    /// Create a timeout from a raw number of 15.625 µs steps, saturating to [`Timeout::MAX`].
+    pub const fn from_steps_sat(steps: u32) -> Self {
+        if steps > Self::MAX.0 {
+            Self::MAX
+        } else {
+            Self(steps)
+        }
+    }
+
+    /// Create a timeout from milliseconds, saturating to [`Timeout::MAX`].
+    pub const fn from_millis_sat(millis: u32) -> Self {
+        Self::from_steps_sat(millis.saturating_mul(64))
+    }
+
+    /// Create a timeout from microseconds, rounding up and saturating to [`Timeout::MAX`].
+    pub const fn from_micros_sat(micros: u32) -> Self {
+        // 15.625 µs == 1000 / 64 µs
+        let steps = (micros as u64 * 64).div_ceil(1000);
+        if steps > Self::MAX.0 as u64 {
+            Self::MAX
+        } else {
+            Self(steps as u32)
+        }
+    }
+
+    /// Number of 15.625 µs steps.
+    pub const fn as_steps(&self) -> u32 {
+        self.0
+    }
+
+    const fn to_bytes(self) -> [u8; 3] {
+        [(self.0 >> 16) as u8, (self.0 >> 8) as u8, self.0 as u8]
+    }
+}
+
+/// Radio interrupt sources.
+///
+/// This is a bit set: combine sources with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Irq(u16);
+
+impl Irq {
+    /// Packet transmission completed.
+    pub const TX_DONE: Self = Self(1 << 0);
+    /// Packet received.
+    pub const RX_DONE: Self = Self(1 << 1);
+    /// Preamble detected.
+    pub const PREAMBLE_DETECTED: Self = Self(1 << 2);
+    /// Valid sync word detected (FSK).
+    pub const SYNC_WORD_VALID: Self = Self(1 << 3);
+    /// Valid header received (LoRa).
+    pub const HEADER_VALID: Self = Self(1 << 4);
+    /// Header with CRC error received (LoRa).
+    pub const HEADER_ERR: Self = Self(1 << 5);
+    /// Packet with CRC error received.
+    pub const CRC_ERR: Self = Self(1 << 6);
+    /// Channel activity detection finished.
+    pub const CAD_DONE: Self = Self(1 << 7);
+    /// Channel activity detected.
+    pub const CAD_DETECTED: Self = Self(1 << 8);
+    /// RX or TX timeout.
+    pub const TIMEOUT: Self = Self(1 << 9);
+    /// No interrupt sources.
+    pub const NONE: Self = Self(0);
+    /// All interrupt sources.
+    pub const ALL: Self = Self(0x03FF);
+
+    /// Create from raw bits. Unknown bits are dropped.
+    pub const fn from_bits(bits: u16) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    /// Raw bits.
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Whether all sources in `other` are set in `self`.
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether any source in `other` is set in `self`.
+    pub const fn intersects(&self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Whether no sources are set.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    const fn to_bytes(self) -> [u8; 2] {
+        self.0.to_be_bytes()
+    }
+}
+
+impl core::ops::BitOr for Irq {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl core::ops::BitOrAssign for Irq {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
+}
+
+/// Operating mode of the radio, as reported in [`Status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChipMode {
+    /// Standby, running from the RC 13 MHz oscillator.
+    StandbyRc,
+    /// Standby, running from the HSE32 oscillator.
+    StandbyHse,
+    /// Frequency synthesis.
+    Fs,
+    /// Receiving.
+    Rx,
+    /// Transmitting.
+    Tx,
+}
+
+/// Result of the last command, as reported in [`Status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CmdStatus {
+    /// A packet was received and is available in the buffer.
+    DataAvailable,
+    /// The command timed out.
+    Timeout,
+    /// The command was invalid, or its parameters were.
+    ProcessingError,
+    /// The command could not be executed.
+    ExecutionFailure,
+    /// Packet transmission completed.
+    TxDone,
+}
+
+/// Radio status byte, returned by every command that reads data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status(u8);
+
+impl Status {
+    /// Create from the raw status byte.
+    pub const fn from_raw(raw: u8) -> Self {
+        Self(raw)
+    }
+
+    /// Raw status byte.
+    pub const fn raw(&self) -> u8 {
+        self.0
+    }
+
+    /// Operating mode, or `None` if the mode field holds a reserved value.
+    pub const fn mode(&self) -> Option<ChipMode> {
+        match (self.0 >> 4) & 0b111 {
+            0x2 => Some(ChipMode::StandbyRc),
+            0x3 => Some(ChipMode::StandbyHse),
+            0x4 => Some(ChipMode::Fs),
+            0x5 => Some(ChipMode::Rx),
+            0x6 => Some(ChipMode::Tx),
+            _ => None,
+        }
+    }
+
+    /// Status of the last command, or `None` if there's nothing to report.
+    pub const fn cmd(&self) -> Option<CmdStatus> {
+        match (self.0 >> 1) & 0b111 {
+            0x2 => Some(CmdStatus::DataAvailable),
+            0x3 => Some(CmdStatus::Timeout),
+            0x4 => Some(CmdStatus::ProcessingError),
+            0x5 => Some(CmdStatus::ExecutionFailure),
+            0x6 => Some(CmdStatus::TxDone),
+            _ => None,
+        }
+    }
+}
+
+/// Sleep mode configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SleepConfig {
+    /// Retain the configuration while sleeping.
+    ///
+    /// With a cold start, the radio must be fully configured again after waking up.
+    pub warm_start: bool,
+    /// Wake up when the RTC wakeup timer fires.
+    pub rtc_wakeup: bool,
+}
+
+/// Clock used in standby mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum StandbyClock {
+    /// RC 13 MHz oscillator.
+    Rc = 0x00,
+    /// HSE32 oscillator.
+    Hse = 0x01,
+}
+
+/// Regulator supplying the radio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum RegulatorMode {
+    /// Linear regulator.
+    Ldo = 0x00,
+    /// Switched-mode regulator. Requires the SMPS inductor to be fitted.
+    Smps = 0x01,
+}
+
+/// Packet type, which selects the modem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum PacketType {
+    /// (G)FSK.
+    Fsk = 0x00,
+    /// LoRa.
+    LoRa = 0x01,
+}
+
+/// Supply voltage of the TCXO, provided by the radio on `PB0-VDDTCXO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum TcxoVoltage {
+    V1_6 = 0x00,
+    V1_7 = 0x01,
+    V1_8 = 0x02,
+    V2_2 = 0x03,
+    V2_4 = 0x04,
+    V2_7 = 0x05,
+    V3_0 = 0x06,
+    V3_3 = 0x07,
+}
+
+/// Blocks to calibrate with [`Command::Calibrate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration(u8);
+
+impl Calibration {
+    /// RC 64 kHz oscillator.
+    pub const RC64K: Self = Self(1 << 0);
+    /// RC 13 MHz oscillator.
+    pub const RC13M: Self = Self(1 << 1);
+    /// PLL.
+    pub const PLL: Self = Self(1 << 2);
+    /// ADC pulse.
+    pub const ADC_PULSE: Self = Self(1 << 3);
+    /// ADC bulk N.
+    pub const ADC_BULK_N: Self = Self(1 << 4);
+    /// ADC bulk P.
+    pub const ADC_BULK_P: Self = Self(1 << 5);
+    /// Image rejection.
+    pub const IMAGE: Self = Self(1 << 6);
+    /// All blocks.
+    pub const ALL: Self = Self(0x7F);
+}
+
+/// Frequency band for image calibration.
+///
+/// The band is given in units of 4 MHz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CalibrateImage(pub u8, pub u8);
+
+impl CalibrateImage {
+    /// 430 - 440 MHz.
+    pub const MHZ_430_440: Self = Self(0x6B, 0x6F);
+    /// 470 - 510 MHz.
+    pub const MHZ_470_510: Self = Self(0x75, 0x81);
+    /// 779 - 787 MHz.
+    pub const MHZ_779_787: Self = Self(0xC1, 0xC5);
+    /// 863 - 870 MHz.
+    pub const MHZ_863_870: Self = Self(0xD7, 0xDB);
+    /// 902 - 928 MHz.
+    pub const MHZ_902_928: Self = Self(0xE1, 0xE9);
+
+    /// Band containing the given RF frequency.
+    pub const fn for_frequency(hz: u32) -> Self {
+        if hz > 900_000_000 {
+            Self::MHZ_902_928
+        } else if hz > 850_000_000 {
+            Self::MHZ_863_870
+        } else if hz > 770_000_000 {
+            Self::MHZ_779_787
+        } else if hz > 460_000_000 {
+            Self::MHZ_470_510
+        } else {
+            Self::MHZ_430_440
+        }
+    }
+}
+
+/// Power amplifier selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum PaSel {
+    /// High power PA, up to +22 dBm, on `RFO_HP`.
+    Hp = 0x00,
+    /// Low power PA, up to +15 dBm, on `RFO_LP`.
+    Lp = 0x01,
+}
+
+/// Power amplifier configuration.
+///
+/// The constants are the optimal settings from the reference manual. They must be used with the
+/// power given in their documentation, see [`TxParams`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PaConfig {
+    /// PA duty cycle.
+    pub duty_cycle: u8,
+    /// Size of the high power PA. Only used with [`PaSel::Hp`].
+    pub hp_max: u8,
+    /// PA to use.
+    pub pa_sel: PaSel,
+}
+
+impl PaConfig {
+    /// +15 dBm on the LP PA, with a power of 14 dBm.
+    pub const LP_15: Self = Self::new(0x06, 0x00, PaSel::Lp);
+    /// +14 dBm on the LP PA, with a power of 14 dBm.
+    pub const LP_14: Self = Self::new(0x04, 0x00, PaSel::Lp);
+    /// +10 dBm on the LP PA, with a power of 13 dBm.
+    pub const LP_10: Self = Self::new(0x01, 0x00, PaSel::Lp);
+    /// +22 dBm on the HP PA, with a power of 22 dBm.
+    pub const HP_22: Self = Self::new(0x04, 0x07, PaSel::Hp);
+    /// +20 dBm on the HP PA, with a power of 22 dBm.
+    pub const HP_20: Self = Self::new(0x03, 0x05, PaSel::Hp);
+    /// +17 dBm on the HP PA, with a power of 22 dBm.
+    pub const HP_17: Self = Self::new(0x02, 0x03, PaSel::Hp);
+    /// +14 dBm on the HP PA, with a power of 22 dBm.
+    pub const HP_14: Self = Self::new(0x02, 0x02, PaSel::Hp);
+
+    /// Create a new PA configuration.
+    pub const fn new(duty_cycle: u8, hp_max: u8, pa_sel: PaSel) -> Self {
+        Self {
+            duty_cycle,
+            hp_max,
+            pa_sel,
+        }
+    }
+}
+
+/// PA ramp time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum RampTime {
+    Micros10 = 0x00,
+    Micros20 = 0x01,
+    Micros40 = 0x02,
+    Micros80 = 0x03,
+    Micros200 = 0x04,
+    Micros800 = 0x05,
+    Micros1700 = 0x06,
+    Micros3400 = 0x07,
+}
+
+/// Transmit parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TxParams {
+    /// Output power in dBm.
+    ///
+    /// -17 to +14 for the LP PA, -9 to +22 for the HP PA.
+    pub power: i8,
+    /// PA ramp time.
+    pub ramp_time: RampTime,
+}
+
+/// LoRa spreading factor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum SpreadingFactor {
+    Sf5 = 5,
+    Sf6 = 6,
+    Sf7 = 7,
+    Sf8 = 8,
+    Sf9 = 9,
+    Sf10 = 10,
+    Sf11 = 11,
+    Sf12 = 12,
+}
+
+/// LoRa bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum LoRaBandwidth {
+    Khz7 = 0x00,
+    Khz10 = 0x08,
+    Khz15 = 0x01,
+    Khz20 = 0x09,
+    Khz31 = 0x02,
+    Khz41 = 0x0A,
+    Khz62 = 0x03,
+    Khz125 = 0x04,
+    Khz250 = 0x05,
+    Khz500 = 0x06,
+}
+
+impl LoRaBandwidth {
+    /// Bandwidth in Hz.
+    pub const fn hz(&self) -> u32 {
+        match self {
+            Self::Khz7 => 7_810,
+            Self::Khz10 => 10_420,
+            Self::Khz15 => 15_630,
+            Self::Khz20 => 20_830,
+            Self::Khz31 => 31_250,
+            Self::Khz41 => 41_670,
+            Self::Khz62 => 62_500,
+            Self::Khz125 => 125_000,
+            Self::Khz250 => 250_000,
+            Self::Khz500 => 500_000,
+        }
+    }
+}
+
+/// LoRa coding rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum CodingRate {
+    Cr45 = 0x01,
+    Cr46 = 0x02,
+    Cr47 = 0x03,
+    Cr48 = 0x04,
+}
+
+/// LoRa modulation parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoRaModParams {
+    /// Spreading factor.
+    pub sf: SpreadingFactor,
+    /// Bandwidth.
+    pub bw: LoRaBandwidth,
+    /// Coding rate.
+    pub cr: CodingRate,
+    /// Low data rate optimization.
+    pub ldro: bool,
+}
+
+impl LoRaModParams {
+    /// Create new LoRa modulation parameters.
+    ///
+    /// Low data rate optimization is enabled if a symbol takes 16 ms or more, as recommended.
+    pub const fn new(sf: SpreadingFactor, bw: LoRaBandwidth, cr: CodingRate) -> Self {
+        // symbol time = 2^sf / bw
+        let ldro = (1u32 << sf as u8) * 1000 >= 16 * bw.hz();
+        Self { sf, bw, cr, ldro }
+    }
+
+    const fn to_bytes(self) -> [u8; 4] {
+        [self.sf as u8, self.bw as u8, self.cr as u8, self.ldro as u8]
+    }
+}
+
+/// LoRa header type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum LoRaHeader {
+    /// Variable length packets, with the length and coding rate sent in a header.
+    Explicit = 0x00,
+    /// Fixed length packets without a header.
+    Implicit = 0x01,
+}
+
+/// LoRa packet parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoRaPacketParams {
+    /// Preamble length in symbols.
+    pub preamble_len: u16,
+    /// Header type.
+    pub header: LoRaHeader,
+    /// Payload length. With an explicit header this is only used for TX.
+    pub payload_len: u8,
+    /// Append a CRC to the payload.
+    pub crc: bool,
+    /// Invert the I and Q signals.
+    pub invert_iq: bool,
+}
+
+impl LoRaPacketParams {
+    const fn to_bytes(self) -> [u8; 6] {
+        let [p0, p1] = self.preamble_len.to_be_bytes();
+        [
+            p0,
+            p1,
+            self.header as u8,
+            self.payload_len,
+            self.crc as u8,
+            self.invert_iq as u8,
+        ]
+    }
+}
+
+/// Gaussian filter applied to (G)FSK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum PulseShape {
+    /// No filter.
+    None = 0x00,
+    /// Gaussian BT 0.3.
+    Bt03 = 0x08,
+    /// Gaussian BT 0.5.
+    Bt05 = 0x09,
+    /// Gaussian BT 0.7.
+    Bt07 = 0x0A,
+    /// Gaussian BT 1.0.
+    Bt1 = 0x0B,
+}
+
+/// (G)FSK receiver bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum FskBandwidth {
+    Hz4800 = 0x1F,
+    Hz5800 = 0x17,
+    Hz7300 = 0x0F,
+    Hz9700 = 0x1E,
+    Hz11700 = 0x16,
+    Hz14600 = 0x0E,
+    Hz19500 = 0x1D,
+    Hz23400 = 0x15,
+    Hz29300 = 0x0D,
+    Hz39000 = 0x1C,
+    Hz46900 = 0x14,
+    Hz58600 = 0x0C,
+    Hz78200 = 0x1B,
+    Hz93800 = 0x13,
+    Hz117300 = 0x0B,
+    Hz156200 = 0x1A,
+    Hz187200 = 0x12,
+    Hz234300 = 0x0A,
+    Hz312000 = 0x19,
+    Hz373600 = 0x11,
+    Hz467000 = 0x09,
+}
+
+/// (G)FSK modulation parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FskModParams {
+    /// Bit rate in bits per second.
+    pub bitrate: u32,
+    /// Pulse shape.
+    pub pulse_shape: PulseShape,
+    /// Receiver bandwidth.
+    pub bandwidth: FskBandwidth,
+    /// Frequency deviation in Hz.
+    pub fdev: u32,
+}
+
+impl FskModParams {
+    const fn to_bytes(self) -> [u8; 8] {
+        let br = (32 * XTAL_FREQ / self.bitrate as u64) as u32;
+        let fdev = freq_to_reg(self.fdev);
+        [
+            (br >> 16) as u8,
+            (br >> 8) as u8,
+            br as u8,
+            self.pulse_shape as u8,
+            self.bandwidth as u8,
+            (fdev >> 16) as u8,
+            (fdev >> 8) as u8,
+            fdev as u8,
+        ]
+    }
+}
+
+/// Length of the preamble that must be received before the radio looks for the sync word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum PreambleDetection {
+    Disabled = 0x00,
+    Bits8 = 0x04,
+    Bits16 = 0x05,
+    Bits24 = 0x06,
+    Bits32 = 0x07,
+}
+
+/// (G)FSK address filtering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum AddrComp {
+    /// No filtering.
+    Disabled = 0x00,
+    /// Accept packets addressed to the node address.
+    Node = 0x01,
+    /// Accept packets addressed to the node or broadcast address.
+    NodeBroadcast = 0x02,
+}
+
+/// (G)FSK header type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum FskHeader {
+    /// Fixed length packets.
+    Fixed = 0x00,
+    /// Variable length packets, with the length sent in the first byte.
+    Variable = 0x01,
+}
+
+/// (G)FSK CRC type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum FskCrc {
+    None = 0x01,
+    Byte1 = 0x00,
+    Byte2 = 0x02,
+    Byte1Inv = 0x04,
+    Byte2Inv = 0x06,
+}
+
+/// (G)FSK packet parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FskPacketParams {
+    /// Preamble length in bits.
+    pub preamble_len: u16,
+    /// Preamble detection length.
+    pub preamble_detection: PreambleDetection,
+    /// Sync word length in bits, at most 64.
+    pub sync_word_len: u8,
+    /// Address filtering.
+    pub addr_comp: AddrComp,
+    /// Header type.
+    pub header: FskHeader,
+    /// Payload length. With a variable length header this is the maximum length for RX.
+    pub payload_len: u8,
+    /// CRC type.
+    pub crc: FskCrc,
+    /// Enable whitening.
+    pub whitening: bool,
+}
+
+impl FskPacketParams {
+    const fn to_bytes(self) -> [u8; 9] {
+        let [p0, p1] = self.preamble_len.to_be_bytes();
+        [
+            p0,
+            p1,
+            self.preamble_detection as u8,
+            self.sync_word_len,
+            self.addr_comp as u8,
+            self.header as u8,
+            self.payload_len,
+            self.crc as u8,
+            self.whitening as u8,
+        ]
+    }
+}
+
+/// Modulation parameters for either modem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ModulationParams {
+    /// LoRa.
+    LoRa(LoRaModParams),
+    /// (G)FSK.
+    Fsk(FskModParams),
+}
+
+/// Packet parameters for either modem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PacketParams {
+    /// LoRa.
+    LoRa(LoRaPacketParams),
+    /// (G)FSK.
+    Fsk(FskPacketParams),
+}
+
+impl PacketParams {
+    /// Same parameters, with a different payload length.
+    pub const fn with_payload_len(self, len: u8) -> Self {
+        match self {
+            Self::LoRa(mut p) => {
+                p.payload_len = len;
+                Self::LoRa(p)
+            }
+            Self::Fsk(mut p) => {
+                p.payload_len = len;
+                Self::Fsk(p)
+            }
+        }
+    }
+}
+
+/// Number of symbols used for channel activity detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+#[allow(missing_docs)]
+pub enum CadSymbols {
+    Symbols1 = 0x00,
+    Symbols2 = 0x01,
+    Symbols4 = 0x02,
+    Symbols8 = 0x03,
+    Symbols16 = 0x04,
+}
+
+/// What the radio does after channel activity detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum CadExit {
+    /// Go back to standby.
+    Standby = 0x00,
+    /// Receive if activity was detected, for up to [`CadParams::timeout`].
+    Rx = 0x01,
+}
+
+/// Channel activity detection parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CadParams {
+    /// Number of symbols.
+    pub symbols: CadSymbols,
+    /// Detection peak threshold. Depends on the spreading factor and bandwidth.
+    pub det_peak: u8,
+    /// Minimum detection threshold. 10 is recommended.
+    pub det_min: u8,
+    /// Exit mode.
+    pub exit: CadExit,
+    /// RX timeout with [`CadExit::Rx`].
+    pub timeout: Timeout,
+}
+
+impl CadParams {
+    /// Parameters recommended for the given spreading factor.
+    pub const fn for_sf(sf: SpreadingFactor) -> Self {
+        let (symbols, det_peak) = match sf {
+            SpreadingFactor::Sf5 | SpreadingFactor::Sf6 | SpreadingFactor::Sf7 | SpreadingFactor::Sf8 => {
+                (CadSymbols::Symbols2, 22)
+            }
+            SpreadingFactor::Sf9 => (CadSymbols::Symbols4, 23),
+            SpreadingFactor::Sf10 => (CadSymbols::Symbols4, 24),
+            SpreadingFactor::Sf11 => (CadSymbols::Symbols4, 25),
+            SpreadingFactor::Sf12 => (CadSymbols::Symbols4, 28),
+        };
+        Self {
+            symbols,
+            det_peak,
+            det_min: 10,
+            exit: CadExit::Standby,
+            timeout: Timeout::DISABLED,
+        }
+    }
+}
+
+/// Convert an RF frequency to the radio's representation.
+const fn freq_to_reg(hz: u32) -> u32 {
+    (((hz as u64) << 25) / XTAL_FREQ) as u32
+}
+
+/// A radio command without a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// Enter sleep mode.
+    SetSleep(SleepConfig),
+    /// Enter standby mode.
+    SetStandby(StandbyClock),
+    /// Enter frequency synthesis mode.
+    SetFs,
+    /// Start transmitting the packet in the buffer.
+    SetTx(Timeout),
+    /// Start receiving.
+    SetRx(Timeout),
+    /// Stop the RX timeout on preamble detection, instead of on sync word or header detection.
+    SetStopRxTimerOnPreamble(bool),
+    /// Start channel activity detection.
+    SetCad,
+    /// Transmit a continuous wave, for testing.
+    SetTxContinuousWave,
+    /// Transmit an infinite preamble, for testing.
+    SetTxContinuousPreamble,
+    /// Select the modem.
+    SetPacketType(PacketType),
+    /// Set the RF frequency in Hz.
+    SetRfFrequency(u32),
+    /// Set the TX power and ramp time.
+    SetTxParams(TxParams),
+    /// Configure the power amplifier.
+    SetPaConfig(PaConfig),
+    /// Configure channel activity detection.
+    SetCadParams(CadParams),
+    /// Set the offset of TX and RX data in the buffer.
+    SetBufferBaseAddress {
+        /// TX offset.
+        tx: u8,
+        /// RX offset.
+        rx: u8,
+    },
+    /// Set the modulation parameters.
+    SetModulationParams(ModulationParams),
+    /// Set the packet parameters.
+    SetPacketParams(PacketParams),
+    /// Set the number of symbols the LoRa modem waits for to validate a reception.
+    SetLoRaSymbNumTimeout(u8),
+    /// Reset the packet statistics.
+    ResetStats,
+    /// Enable interrupt sources, and route them to the interrupt lines.
+    ///
+    /// The radio interrupt of the MCU is triggered by any source routed to `irq1`, `irq2` or
+    /// `irq3`. Sources must be enabled in `mask` to be reported at all.
+    SetDioIrqParams {
+        /// Enabled sources.
+        mask: Irq,
+        /// Sources routed to interrupt line 1.
+        irq1: Irq,
+        /// Sources routed to interrupt line 2.
+        irq2: Irq,
+        /// Sources routed to interrupt line 3.
+        irq3: Irq,
+    },
+    /// Clear interrupt sources.
+    ClearIrqStatus(Irq),
+    /// Calibrate blocks of the radio.
+    Calibrate(Calibration),
+    /// Calibrate image rejection for a frequency band.
+    CalibrateImage(CalibrateImage),
+    /// Select the regulator.
+    SetRegulatorMode(RegulatorMode),
+    /// Clear device errors.
+    ClearDeviceErrors,
+    /// Power the TCXO from `PB0-VDDTCXO`, and wait for it to start up for `timeout`.
+    SetTcxoMode {
+        /// TCXO supply voltage.
+        voltage: TcxoVoltage,
+        /// Startup time.
+        timeout: Timeout,
+    },
+}
+
+impl Command {
+    /// Length of the longest encoded command.
+    pub const MAX_LEN: usize = 10;
+
+    /// Encode the command into `buf`, returning the encoded bytes.
+    pub fn encode<'b>(&self, buf: &'b mut [u8; Self::MAX_LEN]) -> &'b [u8] {
+        fn put<'b>(buf: &'b mut [u8; Command::MAX_LEN], opcode: u8, params: &[u8]) -> &'b [u8] {
+            buf[0] = opcode;
+            buf[1..][..params.len()].copy_from_slice(params);
+            &buf[..1 + params.len()]
+        }
+
+        match *self {
+            Self::SetSleep(cfg) => put(
+                buf,
+                opcode::SET_SLEEP,
+                &[(cfg.warm_start as u8) << 2 | cfg.rtc_wakeup as u8],
+            ),
+            Self::SetStandby(clk) => put(buf, opcode::SET_STANDBY, &[clk as u8]),
+            Self::SetFs => put(buf, opcode::SET_FS, &[]),
+            Self::SetTx(timeout) => put(buf, opcode::SET_TX, &timeout.to_bytes()),
+            Self::SetRx(timeout) => put(buf, opcode::SET_RX, &timeout.to_bytes()),
+            Self::SetStopRxTimerOnPreamble(en) => put(buf, opcode::SET_STOP_RX_TIMER_ON_PREAMBLE, &[en as u8]),
+            Self::SetCad => put(buf, opcode::SET_CAD, &[]),
+            Self::SetTxContinuousWave => put(buf, opcode::SET_TX_CONTINUOUS_WAVE, &[]),
+            Self::SetTxContinuousPreamble => put(buf, opcode::SET_TX_CONTINUOUS_PREAMBLE, &[]),
+            Self::SetPacketType(ty) => put(buf, opcode::SET_PACKET_TYPE, &[ty as u8]),
+            Self::SetRfFrequency(hz) => put(buf, opcode::SET_RF_FREQUENCY, &freq_to_reg(hz).to_be_bytes()),
+            Self::SetTxParams(p) => put(buf, opcode::SET_TX_PARAMS, &[p.power as u8, p.ramp_time as u8]),
+            Self::SetPaConfig(p) => put(
+                buf,
+                opcode::SET_PA_CONFIG,
+                &[p.duty_cycle, p.hp_max, p.pa_sel as u8, 0x01],
+            ),
+            Self::SetCadParams(p) => {
+                let [t0, t1, t2] = p.timeout.to_bytes();
+                put(
+                    buf,
+                    opcode::SET_CAD_PARAMS,
+                    &[p.symbols as u8, p.det_peak, p.det_min, p.exit as u8, t0, t1, t2],
+                )
+            }
+            Self::SetBufferBaseAddress { tx, rx } => put(buf, opcode::SET_BUFFER_BASE_ADDRESS, &[tx, rx]),
+            Self::SetModulationParams(ModulationParams::LoRa(p)) => {
+                put(buf, opcode::SET_MODULATION_PARAMS, &p.to_bytes())
+            }
+            Self::SetModulationParams(ModulationParams::Fsk(p)) => {
+                put(buf, opcode::SET_MODULATION_PARAMS, &p.to_bytes())
+            }
+            Self::SetPacketParams(PacketParams::LoRa(p)) => put(buf, opcode::SET_PACKET_PARAMS, &p.to_bytes()),
+            Self::SetPacketParams(PacketParams::Fsk(p)) => put(buf, opcode::SET_PACKET_PARAMS, &p.to_bytes()),
+            Self::SetLoRaSymbNumTimeout(n) => put(buf, opcode::SET_LORA_SYMB_NUM_TIMEOUT, &[n]),
+            Self::ResetStats => put(buf, opcode::RESET_STATS, &[0; 6]),
+            Self::SetDioIrqParams { mask, irq1, irq2, irq3 } => {
+                let [m0, m1] = mask.to_bytes();
+                let [a0, a1] = irq1.to_bytes();
+                let [b0, b1] = irq2.to_bytes();
+                let [c0, c1] = irq3.to_bytes();
+                put(buf, opcode::SET_DIO_IRQ_PARAMS, &[m0, m1, a0, a1, b0, b1, c0, c1])
+            }
+            Self::ClearIrqStatus(irq) => put(buf, opcode::CLEAR_IRQ_STATUS, &irq.to_bytes()),
+            Self::Calibrate(c) => put(buf, opcode::CALIBRATE, &[c.0]),
+            Self::CalibrateImage(CalibrateImage(f1, f2)) => put(buf, opcode::CALIBRATE_IMAGE, &[f1, f2]),
+            Self::SetRegulatorMode(mode) => put(buf, opcode::SET_REGULATOR_MODE, &[mode as u8]),
+            Self::ClearDeviceErrors => put(buf, opcode::CLR_ERROR, &[0, 0]),
+            Self::SetTcxoMode { voltage, timeout } => {
+                let [t0, t1, t2] = timeout.to_bytes();
+                put(buf, opcode::SET_TCXO_MODE, &[voltage as u8, t0, t1, t2])
+            }
+        }
+    }
+}
+
+/// Response to `GetIrqStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IrqStatus {
+    /// Radio status.
+    pub status: Status,
+    /// Pending interrupt sources.
+    pub irq: Irq,
+}
+
+impl IrqStatus {
+    /// Decode the bytes following the opcode.
+    pub const fn decode(buf: [u8; 3]) -> Self {
+        Self {
+            status: Status(buf[0]),
+            irq: Irq::from_bits(u16::from_be_bytes([buf[1], buf[2]])),
+        }
+    }
+}
+
+/// Response to `GetRxBufferStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxBufferStatus {
+    /// Radio status.
+    pub status: Status,
+    /// Length of the last received packet.
+    pub payload_len: u8,
+    /// Offset of the last received packet in the buffer.
+    pub offset: u8,
+}
+
+impl RxBufferStatus {
+    /// Decode the bytes following the opcode.
+    pub const fn decode(buf: [u8; 3]) -> Self {
+        Self {
+            status: Status(buf[0]),
+            payload_len: buf[1],
+            offset: buf[2],
+        }
+    }
+}
+
+/// Signal quality of a received LoRa packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoRaPacketStatus {
+    /// Average RSSI over the packet, in dBm.
+    pub rssi: i16,
+    /// SNR, in dB.
+    pub snr: i16,
+    /// RSSI of the de-spread signal, in dBm.
+    pub signal_rssi: i16,
+}
+
+impl LoRaPacketStatus {
+    /// Decode the bytes following the opcode and status.
+    pub const fn decode(buf: [u8; 3]) -> Self {
+        Self {
+            rssi: -(buf[0] as i16) / 2,
+            // The SNR is in steps of 0.25 dB, round to nearest.
+            snr: ((buf[1] as i8) as i16 + 2) >> 2,
+            signal_rssi: -(buf[2] as i16) / 2,
+        }
+    }
+}
+
+/// Signal quality and errors of a received (G)FSK packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FskPacketStatus {
+    /// Raw RX status. Bit 4 is set on CRC errors, bit 3 on length errors.
+    pub rx_status: u8,
+    /// RSSI when the sync word was detected, in dBm.
+    pub rssi_sync: i16,
+    /// Average RSSI over the packet, in dBm.
+    pub rssi_avg: i16,
+}
+
+impl FskPacketStatus {
+    /// Decode the bytes following the opcode and status.
+    pub const fn decode(buf: [u8; 3]) -> Self {
+        Self {
+            rx_status: buf[0],
+            rssi_sync: -(buf[1] as i16) / 2,
+            rssi_avg: -(buf[2] as i16) / 2,
+        }
+    }
+}
+
+/// Signal quality of a received packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PacketStatus {
+    /// LoRa.
+    LoRa(LoRaPacketStatus),
+    /// (G)FSK.
+    Fsk(FskPacketStatus),
+}
+
+/// Device errors, as returned by `GetDeviceErrors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceErrors(u16);
+
+impl DeviceErrors {
+    /// RC 64 kHz oscillator calibration failed.
+    pub const RC64K_CALIB: u16 = 1 << 0;
+    /// RC 13 MHz oscillator calibration failed.
+    pub const RC13M_CALIB: u16 = 1 << 1;
+    /// PLL calibration failed.
+    pub const PLL_CALIB: u16 = 1 << 2;
+    /// ADC calibration failed.
+    pub const ADC_CALIB: u16 = 1 << 3;
+    /// Image calibration failed.
+    pub const IMG_CALIB: u16 = 1 << 4;
+    /// HSE32 oscillator failed to start.
+    pub const XOSC_START: u16 = 1 << 5;
+    /// PLL failed to lock.
+    pub const PLL_LOCK: u16 = 1 << 6;
+    /// PA ramping failed.
+    pub const PA_RAMP: u16 = 1 << 8;
+
+    /// Decode the bytes following the opcode and status.
+    pub const fn decode(buf: [u8; 2]) -> Self {
+        Self(u16::from_be_bytes(buf))
+    }
+
+    /// Raw error bits.
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Whether any error is set.
+    pub const fn any(&self) -> bool {
+        self.0 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(cmd: Command) -> ([u8; Command::MAX_LEN], usize) {
+        let mut buf = [0; Command::MAX_LEN];
+        let len = cmd.encode(&mut buf).len();
+        (buf, len)
+    }
+
+    fn assert_encodes(cmd: Command, expected: &[u8]) {
+        let (buf, len) = encode(cmd);
+        assert_eq!(&buf[..len], expected);
+    }
+
+    #[test]
+    fn simple_commands() {
+        assert_encodes(Command::SetStandby(StandbyClock::Hse), &[0x80, 0x01]);
+        assert_encodes(Command::SetPacketType(PacketType::LoRa), &[0x8A, 0x01]);
+        assert_encodes(Command::SetRegulatorMode(RegulatorMode::Smps), &[0x96, 0x01]);
+        assert_encodes(
+            Command::SetBufferBaseAddress { tx: 0x00, rx: 0x80 },
+            &[0x8F, 0x00, 0x80],
+        );
+        assert_encodes(Command::SetCad, &[0xC5]);
+        assert_encodes(Command::Calibrate(Calibration::ALL), &[0x89, 0x7F]);
+        assert_encodes(
+            Command::SetSleep(SleepConfig {
+                warm_start: true,
+                rtc_wakeup: false,
+            }),
+            &[0x84, 0x04],
+        );
+    }
+
+    #[test]
+    fn timeouts() {
+        assert_encodes(Command::SetRx(Timeout::CONTINUOUS), &[0x82, 0xFF, 0xFF, 0xFF]);
+        assert_encodes(
+            Command::SetTx(Timeout::from_millis_sat(1000)),
+            &[0x83, 0x00, 0xFA, 0x00],
+        );
+        assert_eq!(Timeout::from_micros_sat(16).as_steps(), 2);
+        assert_eq!(Timeout::from_micros_sat(15_625).as_steps(), 1000);
+        assert_eq!(Timeout::from_millis_sat(u32::MAX), Timeout::MAX);
+        assert_eq!(Timeout::from_steps_sat(0xFF_FFFF), Timeout::MAX);
+    }
+
+    #[test]
+    fn rf_frequency() {
+        // 868 MHz * 2^25 / 32 MHz
+        assert_encodes(Command::SetRfFrequency(868_000_000), &[0x86, 0x36, 0x40, 0x00, 0x00]);
+        assert_encodes(Command::SetRfFrequency(915_000_000), &[0x86, 0x39, 0x30, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn pa_and_tx_params() {
+        assert_encodes(Command::SetPaConfig(PaConfig::HP_22), &[0x95, 0x04, 0x07, 0x00, 0x01]);
+        assert_encodes(
+            Command::SetTxParams(TxParams {
+                power: -9,
+                ramp_time: RampTime::Micros40,
+            }),
+            &[0x8E, 0xF7, 0x02],
+        );
+    }
+
+    #[test]
+    fn lora_params() {
+        let mod_params = LoRaModParams::new(SpreadingFactor::Sf7, LoRaBandwidth::Khz125, CodingRate::Cr45);
+        assert!(!mod_params.ldro);
+        assert_encodes(
+            Command::SetModulationParams(ModulationParams::LoRa(mod_params)),
+            &[0x8B, 0x07, 0x04, 0x01, 0x00],
+        );
+
+        let pkt_params = LoRaPacketParams {
+            preamble_len: 8,
+            header: LoRaHeader::Explicit,
+            payload_len: 0xFF,
+            crc: true,
+            invert_iq: false,
+        };
+        assert_encodes(
+            Command::SetPacketParams(PacketParams::LoRa(pkt_params).with_payload_len(12)),
+            &[0x8C, 0x00, 0x08, 0x00, 12, 0x01, 0x00],
+        );
+    }
+
+    #[test]
+    fn lora_ldro() {
+        let ldro = |sf, bw| LoRaModParams::new(sf, bw, CodingRate::Cr45).ldro;
+        assert!(!ldro(SpreadingFactor::Sf10, LoRaBandwidth::Khz125));
+        assert!(ldro(SpreadingFactor::Sf11, LoRaBandwidth::Khz125));
+        assert!(ldro(SpreadingFactor::Sf12, LoRaBandwidth::Khz250));
+        assert!(!ldro(SpreadingFactor::Sf12, LoRaBandwidth::Khz500));
+    }
+
+    #[test]
+    fn fsk_params() {
+        let mod_params = FskModParams {
+            bitrate: 50_000,
+            pulse_shape: PulseShape::Bt05,
+            bandwidth: FskBandwidth::Hz117300,
+            fdev: 25_000,
+        };
+        // br = 32 * 32 MHz / 50 kbps = 20480, fdev = 25 kHz * 2^25 / 32 MHz = 26214
+        assert_encodes(
+            Command::SetModulationParams(ModulationParams::Fsk(mod_params)),
+            &[0x8B, 0x00, 0x50, 0x00, 0x09, 0x0B, 0x00, 0x66, 0x66],
+        );
+
+        let pkt_params = FskPacketParams {
+            preamble_len: 32,
+            preamble_detection: PreambleDetection::Bits16,
+            sync_word_len: 24,
+            addr_comp: AddrComp::Disabled,
+            header: FskHeader::Variable,
+            payload_len: 255,
+            crc: FskCrc::Byte2Inv,
+            whitening: true,
+        };
+        assert_encodes(
+            Command::SetPacketParams(PacketParams::Fsk(pkt_params)),
+            &[0x8C, 0x00, 0x20, 0x05, 24, 0x00, 0x01, 255, 0x06, 0x01],
+        );
+    }
+
+    #[test]
+    fn irq_params() {
+        let irq = Irq::TX_DONE | Irq::RX_DONE | Irq::TIMEOUT;
+        assert_encodes(
+            Command::SetDioIrqParams {
+                mask: irq,
+                irq1: irq,
+                irq2: Irq::NONE,
+                irq3: Irq::NONE,
+            },
+            &[0x08, 0x02, 0x03, 0x02, 0x03, 0x00, 0x00, 0x00, 0x00],
+        );
+        assert_encodes(Command::ClearIrqStatus(Irq::ALL), &[0x02, 0x03, 0xFF]);
+    }
+
+    #[test]
+    fn cad_and_tcxo() {
+        assert_encodes(
+            Command::SetCadParams(CadParams::for_sf(SpreadingFactor::Sf9)),
+            &[0x88, 0x02, 23, 10, 0x00, 0x00, 0x00, 0x00],
+        );
+        assert_encodes(
+            Command::SetTcxoMode {
+                voltage: TcxoVoltage::V1_7,
+                timeout: Timeout::from_millis_sat(5),
+            },
+            &[0x97, 0x01, 0x00, 0x01, 0x40],
+        );
+        assert_eq!(CalibrateImage::for_frequency(868_000_000), CalibrateImage::MHZ_863_870);
+        assert_eq!(CalibrateImage::for_frequency(433_000_000), CalibrateImage::MHZ_430_440);
+    }
+
+    #[test]
+    fn status() {
+        // STBY_HSE32, data available
+        let status = Status::from_raw(0x34);
+        assert_eq!(status.mode(), Some(ChipMode::StandbyHse));
+        assert_eq!(status.cmd(), Some(CmdStatus::DataAvailable));
+
+        // TX, command processing error
+        let status = Status::from_raw(0x68);
+        assert_eq!(status.mode(), Some(ChipMode::Tx));
+        assert_eq!(status.cmd(), Some(CmdStatus::ProcessingError));
+
+        assert_eq!(Status::from_raw(0x00).mode(), None);
+        assert_eq!(Status::from_raw(0x00).cmd(), None);
+    }
+
+    #[test]
+    fn responses() {
+        let irq = IrqStatus::decode([0x52, 0x02, 0x42]);
+        assert_eq!(irq.status.mode(), Some(ChipMode::Rx));
+        assert!(irq.irq.contains(Irq::RX_DONE | Irq::CRC_ERR | Irq::TIMEOUT));
+        assert!(!irq.irq.intersects(Irq::TX_DONE));
+
+        // Reserved bits are dropped.
+        assert_eq!(IrqStatus::decode([0, 0xFC, 0x00]).irq, Irq::from_bits(0x0000));
+
+        let rx = RxBufferStatus::decode([0x24, 17, 0x80]);
+        assert_eq!(rx.payload_len, 17);
+        assert_eq!(rx.offset, 0x80);
+
+        let pkt = LoRaPacketStatus::decode([180, (-27i8) as u8, 182]);
+        assert_eq!(pkt.rssi, -90);
+        assert_eq!(pkt.snr, -7);
+        assert_eq!(pkt.signal_rssi, -91);
+        assert_eq!(LoRaPacketStatus::decode([0, 38, 0]).snr, 10);
+
+        let pkt = FskPacketStatus::decode([0x12, 100, 110]);
+        assert_eq!(pkt.rx_status, 0x12);
+        assert_eq!(pkt.rssi_sync, -50);
+        assert_eq!(pkt.rssi_avg, -55);
+
+        let errors = DeviceErrors::decode([0x00, 0x20]);
+        assert!(errors.any());
+        assert_eq!(errors.bits(), DeviceErrors::XOSC_START);
+    }
+}
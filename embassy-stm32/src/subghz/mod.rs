@@ -0,0 +1,565 @@
+//! Sub-GHz radio (SUBGHZ) of the STM32WL, supporting LoRa and (G)FSK.
+//!
+//! The radio is connected to the MCU through the internal SUBGHZSPI bus. Its chip select, busy line
+//! and reset are controlled through the PWR and RCC peripherals, and its interrupt line is wired to
+//! the `SUBGHZ_RADIO` interrupt, which must be bound to [`InterruptHandler`].
+//!
+//! Most boards route the radio's RF ports to the antenna through an external RF switch, which is
+//! controlled with GPIOs. Implement [`RfSwitch`] for your board to have the driver drive it.
+//!
+//! The high-level API handles one packet at a time:
+//!
+//! 1. Configure the modem with [`SubGhz::configure_lora`] or [`SubGhz::configure_fsk`].
+//! 2. Send packets with [`SubGhz::tx`], and receive them with [`SubGhz::rx`].
+//!
+//! The raw command set is available through [`SubGhz::command`] and friends, for anything the
+//! high-level API doesn't cover.
+//!
+//! Dropping a `tx`, `rx` or `cad` future doesn't stop the radio. The next operation puts it back in
+//! standby first, or call [`SubGhz::standby`] to stop it right away.
+
+mod cmd;
+
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+pub use cmd::*;
+use embassy_hal_internal::drop::OnDrop;
+use embassy_hal_internal::Peripheral;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::typelevel::Interrupt;
+use crate::mode::Async;
+use crate::pac::{EXTI, PWR, RCC};
+use crate::spi::{self, RxDma, Spi, TxDma};
+use crate::{interrupt, peripherals};
+
+/// EXTI line of the radio interrupt.
+const EXTI_LINE: usize = 44;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+static IRQ_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Radio interrupt handler.
+pub struct InterruptHandler {
+    _private: (),
+}
+
+impl interrupt::typelevel::Handler<interrupt::typelevel::SUBGHZ_RADIO> for InterruptHandler {
+    unsafe fn on_interrupt() {
+        // The interrupt line stays high until the IRQ status is cleared over SPI, which can only be
+        // done from the driver, so mask the interrupt until the driver waits for the next one.
+        interrupt::typelevel::SUBGHZ_RADIO::disable();
+        IRQ_PENDING.store(true, Ordering::Release);
+        WAKER.wake();
+    }
+}
+
+/// Control of the board's RF switch.
+///
+/// The driver calls these methods before every operation, and after it's done.
+pub trait RfSwitch {
+    /// Disconnect the antenna, the radio is idle.
+    fn set_off(&mut self);
+    /// Connect the antenna to the receiver.
+    fn set_rx(&mut self);
+    /// Connect the antenna to the given power amplifier.
+    fn set_tx(&mut self, pa: PaSel);
+}
+
+/// RF switch for boards without one, or where it's controlled by the application.
+pub struct NoRfSwitch;
+
+impl RfSwitch for NoRfSwitch {
+    fn set_off(&mut self) {}
+    fn set_rx(&mut self) {}
+    fn set_tx(&mut self, _pa: PaSel) {}
+}
+
+/// Radio configuration.
+#[non_exhaustive]
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Regulator supplying the radio.
+    pub regulator: RegulatorMode,
+    /// Supply voltage of the TCXO, if the radio is clocked by a TCXO powered from `PB0-VDDTCXO`.
+    ///
+    /// In that case, HSE must also be configured in bypass mode in the RCC configuration.
+    pub tcxo: Option<TcxoVoltage>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            regulator: RegulatorMode::Ldo,
+            tcxo: None,
+        }
+    }
+}
+
+/// LoRa modem configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoRaConfig {
+    /// RF frequency in Hz.
+    pub frequency: u32,
+    /// Modulation parameters.
+    pub modulation: LoRaModParams,
+    /// Packet parameters. The payload length is set by [`SubGhz::tx`] and [`SubGhz::rx`].
+    pub packet: LoRaPacketParams,
+    /// Sync word. Use `0x3444` for public networks, and `0x1424` for private ones.
+    pub sync_word: u16,
+    /// Power amplifier configuration.
+    pub pa: PaConfig,
+    /// Transmit parameters.
+    pub tx: TxParams,
+}
+
+/// (G)FSK modem configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FskConfig {
+    /// RF frequency in Hz.
+    pub frequency: u32,
+    /// Modulation parameters.
+    pub modulation: FskModParams,
+    /// Packet parameters. The payload length is set by [`SubGhz::tx`] and [`SubGhz::rx`].
+    pub packet: FskPacketParams,
+    /// Sync word. Only the first [`FskPacketParams::sync_word_len`] bits are used.
+    pub sync_word: [u8; 8],
+    /// Power amplifier configuration.
+    pub pa: PaConfig,
+    /// Transmit parameters.
+    pub tx: TxParams,
+}
+
+/// Radio error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// SPI error.
+    Spi(spi::Error),
+    /// No modem was configured.
+    NotConfigured,
+    /// The operation is not supported by the configured modem.
+    Unsupported,
+    /// The payload is longer than 255 bytes.
+    PayloadTooLong,
+    /// The received packet doesn't fit in the buffer.
+    BufferTooSmall,
+    /// The operation timed out.
+    Timeout,
+    /// A packet was received with a CRC error.
+    Crc,
+    /// A LoRa header was received with a CRC error.
+    Header,
+}
+
+impl From<spi::Error> for Error {
+    fn from(e: spi::Error) -> Self {
+        Self::Spi(e)
+    }
+}
+
+/// Sub-GHz radio driver.
+pub struct SubGhz<'d, RF: RfSwitch = NoRfSwitch> {
+    spi: Spi<'d, Async>,
+    rf_switch: RF,
+    packet: Option<PacketParams>,
+    pa_sel: PaSel,
+    sleeping: bool,
+}
+
+impl<'d, RF: RfSwitch> SubGhz<'d, RF> {
+    /// Create a new radio driver.
+    ///
+    /// This resets the radio, and leaves it in standby.
+    pub fn new(
+        peri: impl Peripheral<P = peripherals::SUBGHZSPI> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::SUBGHZ_RADIO, InterruptHandler> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<peripherals::SUBGHZSPI>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<peripherals::SUBGHZSPI>> + 'd,
+        rf_switch: RF,
+        config: Config,
+    ) -> Result<Self, Error> {
+        let mut this = Self {
+            spi: Spi::new_subghz(peri, tx_dma, rx_dma),
+            rf_switch,
+            packet: None,
+            pa_sel: PaSel::Lp,
+            sleeping: false,
+        };
+        this.rf_switch.set_off();
+
+        interrupt::typelevel::SUBGHZ_RADIO::disable();
+        #[cfg(exti_w)]
+        let exti = EXTI.cpu(crate::pac::CORE_INDEX);
+        #[cfg(not(exti_w))]
+        let exti = EXTI;
+        exti.imr(EXTI_LINE / 32).modify(|w| w.set_line(EXTI_LINE % 32, true));
+
+        deselect();
+        RCC.csr().modify(|w| w.set_rfrst(true));
+        RCC.csr().modify(|w| w.set_rfrst(false));
+        while RCC.csr().read().rfrstf() {}
+
+        this.blocking_command(Command::SetStandby(StandbyClock::Rc))?;
+        if let Some(voltage) = config.tcxo {
+            this.blocking_command(Command::SetTcxoMode {
+                voltage,
+                timeout: Timeout::from_millis_sat(5),
+            })?;
+            // Calibration at power-up failed without a clock, run it again now that the TCXO is on.
+            this.blocking_command(Command::Calibrate(Calibration::ALL))?;
+            this.blocking_command(Command::ClearDeviceErrors)?;
+        }
+        this.blocking_command(Command::SetRegulatorMode(config.regulator))?;
+
+        Ok(this)
+    }
+
+    fn blocking_command(&mut self, cmd: Command) -> Result<(), Error> {
+        let mut buf = [0; Command::MAX_LEN];
+        let bytes = cmd.encode(&mut buf);
+        self.wake()?;
+        wait_busy();
+        select();
+        let res = self.spi.blocking_write(bytes);
+        deselect();
+        Ok(res?)
+    }
+
+    /// Wake the radio up from sleep, which is done by selecting it.
+    fn wake(&mut self) -> Result<(), Error> {
+        if self.sleeping {
+            select();
+            let res = self.spi.blocking_write(&[opcode::GET_STATUS, 0]);
+            deselect();
+            res?;
+            self.sleeping = false;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, header: &[u8], data: &[u8]) -> Result<(), Error> {
+        self.wake()?;
+        wait_busy();
+        select();
+        let _nss = OnDrop::new(deselect);
+        self.spi.write(header).await?;
+        self.spi.write(data).await?;
+        Ok(())
+    }
+
+    async fn read(&mut self, header: &[u8], data: &mut [u8]) -> Result<(), Error> {
+        self.wake()?;
+        wait_busy();
+        select();
+        let _nss = OnDrop::new(deselect);
+        self.spi.write(header).await?;
+        if !data.is_empty() {
+            data.fill(0);
+            self.spi.transfer_in_place(data).await?;
+        }
+        Ok(())
+    }
+
+    /// Send a command to the radio.
+    pub async fn command(&mut self, cmd: Command) -> Result<(), Error> {
+        let mut buf = [0; Command::MAX_LEN];
+        self.write(cmd.encode(&mut buf), &[]).await?;
+        if let Command::SetSleep(cfg) = cmd {
+            self.sleeping = true;
+            if !cfg.warm_start {
+                self.packet = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the radio status.
+    pub async fn status(&mut self) -> Result<Status, Error> {
+        let mut buf = [0; 1];
+        self.read(&[opcode::GET_STATUS], &mut buf).await?;
+        Ok(Status::from_raw(buf[0]))
+    }
+
+    /// Read the pending interrupt sources.
+    pub async fn irq_status(&mut self) -> Result<IrqStatus, Error> {
+        let mut buf = [0; 3];
+        self.read(&[opcode::GET_IRQ_STATUS], &mut buf).await?;
+        Ok(IrqStatus::decode(buf))
+    }
+
+    /// Read the device errors.
+    pub async fn device_errors(&mut self) -> Result<DeviceErrors, Error> {
+        let mut buf = [0; 3];
+        self.read(&[opcode::GET_ERROR], &mut buf).await?;
+        Ok(DeviceErrors::decode([buf[1], buf[2]]))
+    }
+
+    /// Read the instantaneous RSSI while receiving, in dBm.
+    pub async fn rssi_inst(&mut self) -> Result<i16, Error> {
+        let mut buf = [0; 2];
+        self.read(&[opcode::GET_RSSI_INST], &mut buf).await?;
+        Ok(-(buf[1] as i16) / 2)
+    }
+
+    /// Write radio registers, starting at `addr`.
+    pub async fn write_register(&mut self, addr: u16, data: &[u8]) -> Result<(), Error> {
+        let [a0, a1] = addr.to_be_bytes();
+        self.write(&[opcode::WRITE_REGISTER, a0, a1], data).await
+    }
+
+    /// Read radio registers, starting at `addr`.
+    pub async fn read_register(&mut self, addr: u16, data: &mut [u8]) -> Result<(), Error> {
+        let [a0, a1] = addr.to_be_bytes();
+        // The byte after the address returns the status, not data.
+        self.read(&[opcode::READ_REGISTER, a0, a1, 0], data).await
+    }
+
+    /// Write to the radio's packet buffer, starting at `offset`.
+    pub async fn write_buffer(&mut self, offset: u8, data: &[u8]) -> Result<(), Error> {
+        self.write(&[opcode::WRITE_BUFFER, offset], data).await
+    }
+
+    /// Read from the radio's packet buffer, starting at `offset`.
+    pub async fn read_buffer(&mut self, offset: u8, data: &mut [u8]) -> Result<(), Error> {
+        self.read(&[opcode::READ_BUFFER, offset, 0], data).await
+    }
+
+    /// Stop any operation and put the radio in standby.
+    pub async fn standby(&mut self) -> Result<(), Error> {
+        self.command(Command::SetStandby(StandbyClock::Rc)).await?;
+        self.rf_switch.set_off();
+        Ok(())
+    }
+
+    /// Put the radio to sleep.
+    ///
+    /// It's woken up by the next command. With a cold start, the modem must be configured again.
+    pub async fn sleep(&mut self, config: SleepConfig) -> Result<(), Error> {
+        self.standby().await?;
+        self.command(Command::SetSleep(config)).await
+    }
+
+    /// Configure the LoRa modem.
+    pub async fn configure_lora(&mut self, config: &LoRaConfig) -> Result<(), Error> {
+        self.configure(
+            PacketType::LoRa,
+            config.frequency,
+            config.pa,
+            config.tx,
+            ModulationParams::LoRa(config.modulation),
+            PacketParams::LoRa(config.packet),
+        )
+        .await?;
+        self.write_register(reg::LORA_SYNC_WORD, &config.sync_word.to_be_bytes())
+            .await
+    }
+
+    /// Configure the (G)FSK modem.
+    pub async fn configure_fsk(&mut self, config: &FskConfig) -> Result<(), Error> {
+        self.configure(
+            PacketType::Fsk,
+            config.frequency,
+            config.pa,
+            config.tx,
+            ModulationParams::Fsk(config.modulation),
+            PacketParams::Fsk(config.packet),
+        )
+        .await?;
+        let len = (config.packet.sync_word_len as usize).div_ceil(8).min(8);
+        self.write_register(reg::GFSK_SYNC_WORD, &config.sync_word[..len]).await
+    }
+
+    async fn configure(
+        &mut self,
+        packet_type: PacketType,
+        frequency: u32,
+        pa: PaConfig,
+        tx: TxParams,
+        modulation: ModulationParams,
+        packet: PacketParams,
+    ) -> Result<(), Error> {
+        self.packet = None;
+        self.standby().await?;
+        self.command(Command::SetPacketType(packet_type)).await?;
+        self.command(Command::CalibrateImage(CalibrateImage::for_frequency(frequency)))
+            .await?;
+        self.command(Command::SetRfFrequency(frequency)).await?;
+        self.command(Command::SetPaConfig(pa)).await?;
+        self.command(Command::SetTxParams(tx)).await?;
+        self.command(Command::SetModulationParams(modulation)).await?;
+        self.command(Command::SetPacketParams(packet)).await?;
+        self.command(Command::SetBufferBaseAddress { tx: 0, rx: 0 }).await?;
+        self.packet = Some(packet);
+        self.pa_sel = pa.pa_sel;
+        Ok(())
+    }
+
+    /// Start an operation with `start`, and wait for one of `irqs`.
+    async fn run(&mut self, start: Command, irqs: Irq) -> Result<Irq, Error> {
+        self.command(Command::SetDioIrqParams {
+            mask: irqs,
+            irq1: irqs,
+            irq2: Irq::NONE,
+            irq3: Irq::NONE,
+        })
+        .await?;
+        self.command(Command::ClearIrqStatus(Irq::ALL)).await?;
+
+        IRQ_PENDING.store(false, Ordering::Relaxed);
+        interrupt::typelevel::SUBGHZ_RADIO::unpend();
+        unsafe { interrupt::typelevel::SUBGHZ_RADIO::enable() };
+
+        self.command(start).await?;
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            match IRQ_PENDING.swap(false, Ordering::AcqRel) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await;
+
+        let status = self.irq_status().await?;
+        self.command(Command::ClearIrqStatus(status.irq)).await?;
+        Ok(status.irq)
+    }
+
+    /// Transmit a packet, and wait until it's sent.
+    ///
+    /// With [`Timeout::DISABLED`], the radio transmits until the packet is sent.
+    pub async fn tx(&mut self, data: &[u8], timeout: Timeout) -> Result<(), Error> {
+        let packet = self.packet.ok_or(Error::NotConfigured)?;
+        let len = u8::try_from(data.len()).map_err(|_| Error::PayloadTooLong)?;
+
+        self.standby().await?;
+        self.write_buffer(0, data).await?;
+        self.command(Command::SetPacketParams(packet.with_payload_len(len)))
+            .await?;
+
+        self.rf_switch.set_tx(self.pa_sel);
+        let irq = self.run(Command::SetTx(timeout), Irq::TX_DONE | Irq::TIMEOUT).await;
+        self.rf_switch.set_off();
+
+        match irq?.contains(Irq::TX_DONE) {
+            true => Ok(()),
+            false => Err(Error::Timeout),
+        }
+    }
+
+    /// Receive a packet.
+    ///
+    /// Returns the length of the packet, and its signal quality. With [`Timeout::DISABLED`], the
+    /// radio waits for a packet indefinitely.
+    ///
+    /// With variable length packets, longer packets than `buf` are dropped by the (G)FSK modem,
+    /// and fail with [`Error::BufferTooSmall`] with LoRa. With fixed length packets or an implicit
+    /// LoRa header, the packet length is `buf.len()`.
+    pub async fn rx(&mut self, buf: &mut [u8], timeout: Timeout) -> Result<(usize, PacketStatus), Error> {
+        let packet = self.packet.ok_or(Error::NotConfigured)?;
+        let max_len = buf.len().min(u8::MAX as usize) as u8;
+
+        self.standby().await?;
+        self.command(Command::SetPacketParams(packet.with_payload_len(max_len)))
+            .await?;
+
+        self.rf_switch.set_rx();
+        let irq = self
+            .run(
+                Command::SetRx(timeout),
+                Irq::RX_DONE | Irq::TIMEOUT | Irq::CRC_ERR | Irq::HEADER_ERR,
+            )
+            .await;
+        // The radio stays in RX with a continuous timeout.
+        if timeout != Timeout::CONTINUOUS {
+            self.rf_switch.set_off();
+        }
+        let irq = irq?;
+
+        if irq.contains(Irq::TIMEOUT) {
+            return Err(Error::Timeout);
+        }
+        if irq.contains(Irq::HEADER_ERR) {
+            return Err(Error::Header);
+        }
+        if irq.contains(Irq::CRC_ERR) {
+            return Err(Error::Crc);
+        }
+
+        let rx = {
+            let mut buf = [0; 3];
+            self.read(&[opcode::GET_RX_BUFFER_STATUS], &mut buf).await?;
+            RxBufferStatus::decode(buf)
+        };
+        let len = rx.payload_len as usize;
+        if len > buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.read_buffer(rx.offset, &mut buf[..len]).await?;
+
+        let mut status = [0; 4];
+        self.read(&[opcode::GET_PACKET_STATUS], &mut status).await?;
+        let status = [status[1], status[2], status[3]];
+        let status = match packet {
+            PacketParams::LoRa(_) => PacketStatus::LoRa(LoRaPacketStatus::decode(status)),
+            PacketParams::Fsk(_) => PacketStatus::Fsk(FskPacketStatus::decode(status)),
+        };
+
+        Ok((len, status))
+    }
+
+    /// Run LoRa channel activity detection, returning whether activity was detected.
+    ///
+    /// The radio always returns to standby afterwards, [`CadParams::exit`] is ignored.
+    pub async fn cad(&mut self, params: CadParams) -> Result<bool, Error> {
+        match self.packet {
+            Some(PacketParams::LoRa(_)) => {}
+            Some(PacketParams::Fsk(_)) => return Err(Error::Unsupported),
+            None => return Err(Error::NotConfigured),
+        }
+
+        self.standby().await?;
+        self.command(Command::SetCadParams(CadParams {
+            exit: CadExit::Standby,
+            ..params
+        }))
+        .await?;
+
+        self.rf_switch.set_rx();
+        let irq = self.run(Command::SetCad, Irq::CAD_DONE | Irq::CAD_DETECTED).await;
+        self.rf_switch.set_off();
+
+        Ok(irq?.contains(Irq::CAD_DETECTED))
+    }
+}
+
+impl<'d, RF: RfSwitch> Drop for SubGhz<'d, RF> {
+    fn drop(&mut self) {
+        interrupt::typelevel::SUBGHZ_RADIO::disable();
+        let _ = self.blocking_command(Command::SetSleep(SleepConfig::default()));
+        self.rf_switch.set_off();
+    }
+}
+
+/// Select the radio, i.e. pull its NSS low.
+fn select() {
+    PWR.subghzspicr().modify(|w| w.set_nss(false));
+}
+
+fn deselect() {
+    PWR.subghzspicr().modify(|w| w.set_nss(true));
+}
+
+/// Wait until the radio is ready to accept a command.
+///
+/// Commands take at most a few hundred microseconds to process, except for calibration, which
+/// takes a few milliseconds, so this spins.
+fn wait_busy() {
+    while PWR.sr2().read().rfbusys() {}
+}
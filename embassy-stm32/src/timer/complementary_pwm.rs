@@ -5,7 +5,7 @@ use core::marker::PhantomData;
 use embassy_hal_internal::{into_ref, PeripheralRef};
 use stm32_metapac::timer::vals::Ckd;
 
-use super::low_level::{CountingMode, OutputPolarity, Timer};
+use super::low_level::{CountingMode, MasterMode, OutputPolarity, SlaveSync, Timer};
 use super::simple_pwm::{Ch1, Ch2, Ch3, Ch4, PwmPin};
 use super::{
     AdvancedInstance4Channel, Channel, Channel1ComplementaryPin, Channel2ComplementaryPin, Channel3ComplementaryPin,
@@ -147,6 +147,23 @@ impl<'d, T: AdvancedInstance4Channel> ComplementaryPwm<'d, T> {
         self.inner.set_dead_time_clock_division(ckd);
         self.inner.set_dead_time_value(value);
     }
+
+    /// Set the master mode, selecting the trigger output (TRGO) sent to slave timers.
+    pub fn set_master_mode(&mut self, mode: MasterMode) {
+        self.inner.set_master_mode(mode);
+    }
+
+    /// Set the slave synchronization mode.
+    ///
+    /// In [`SlaveSync::Trigger`] mode, the counter is stopped and reset, and only starts on the next
+    /// trigger edge.
+    pub fn set_slave_sync(&mut self, sync: SlaveSync) {
+        if let SlaveSync::Trigger(_) = sync {
+            self.inner.stop();
+            self.inner.reset();
+        }
+        self.inner.set_slave_sync(sync);
+    }
 }
 
 impl<'d, T: AdvancedInstance4Channel> embedded_hal_02::Pwm for ComplementaryPwm<'d, T> {
@@ -8,7 +8,7 @@
 
 use embassy_hal_internal::{into_ref, Peripheral, PeripheralRef};
 // Re-export useful enums
-pub use stm32_metapac::timer::vals::{FilterValue, Sms as SlaveMode, Ts as TriggerSource};
+pub use stm32_metapac::timer::vals::{FilterValue, Mms as MasterMode, Sms as SlaveMode, Ts as TriggerSource};
 
 use super::*;
 use crate::pac::timer::vals;
@@ -177,6 +177,24 @@ impl From<OutputPolarity> for bool {
     }
 }
 
+/// Slave synchronization mode.
+///
+/// This selects how a timer reacts to its trigger input (TRGI). Internal triggers (`ITRx`) connect
+/// the trigger output (TRGO) of another timer, selected with [`Timer::set_master_mode`]. Which
+/// timer is wired to which `ITRx` input is chip-specific, refer to the "TIMx internal trigger
+/// connection" table of the reference manual.
+#[derive(Clone, Copy)]
+pub enum SlaveSync {
+    /// Slave mode disabled, the counter runs on its own.
+    Disabled,
+    /// A rising edge of the trigger resets the counter and updates the registers.
+    Reset(TriggerSource),
+    /// The counter only runs while the trigger is high.
+    Gated(TriggerSource),
+    /// A rising edge of the trigger starts the counter.
+    Trigger(TriggerSource),
+}
+
 /// Low-level timer driver.
 pub struct Timer<'d, T: CoreInstance> {
     tim: PeripheralRef<'d, T>,
@@ -228,6 +246,28 @@ impl<'d, T: CoreInstance> Timer<'d, T> {
         self.regs_core().cnt().write(|r| r.set_cnt(0));
     }
 
+    /// Get whether the counter is running.
+    pub fn is_running(&self) -> bool {
+        self.regs_core().cr1().read().cen()
+    }
+
+    /// Enable/disable one-pulse mode.
+    ///
+    /// In one-pulse mode, the counter stops at the next update event.
+    pub fn set_one_pulse_mode(&self, enable: bool) {
+        self.regs_core().cr1().modify(|r| r.set_opm(enable));
+    }
+
+    /// Generate an update event, without setting the update interrupt flag.
+    ///
+    /// This reinitializes the counter and loads the preloaded registers.
+    pub fn generate_update_event(&self) {
+        let regs = self.regs_core();
+        regs.cr1().modify(|r| r.set_urs(vals::Urs::COUNTERONLY));
+        regs.egr().write(|r| r.set_ug(true));
+        regs.cr1().modify(|r| r.set_urs(vals::Urs::ANYEVENT));
+    }
+
     /// Set the frequency of how many times per second the timer counts up to the max value or down to 0.
     ///
     /// This means that in the default edge-aligned mode,
@@ -379,6 +419,11 @@ impl<'d, T: BasicInstance> Timer<'d, T> {
     pub fn regs_basic(&self) -> crate::pac::timer::TimBasic {
         unsafe { crate::pac::timer::TimBasic::from_ptr(T::regs()) }
     }
+
+    /// Set the master mode, selecting the trigger output (TRGO) sent to other timers.
+    pub fn set_master_mode(&self, mms: MasterMode) {
+        self.regs_basic().cr2().modify(|r| r.set_mms(mms));
+    }
 }
 
 impl<'d, T: GeneralInstance1Channel> Timer<'d, T> {
@@ -405,6 +450,18 @@ impl<'d, T: GeneralInstance1Channel> Timer<'d, T> {
             TimerBits::Bits32 => self.regs_gp32_unchecked().arr().read(),
         }
     }
+
+    /// Set max compare value, i.e. the auto-reload value in timer ticks.
+    pub fn set_max_compare_value(&self, ticks: u32) {
+        match T::BITS {
+            TimerBits::Bits16 => {
+                let arr = unwrap!(u16::try_from(ticks));
+                self.regs_1ch().arr().write(|r| r.set_arr(arr));
+            }
+            #[cfg(not(stm32l0))]
+            TimerBits::Bits32 => self.regs_gp32_unchecked().arr().write_value(ticks),
+        }
+    }
 }
 
 impl<'d, T: GeneralInstance2Channel> Timer<'d, T> {
@@ -601,6 +658,50 @@ impl<'d, T: GeneralInstance4Channel> Timer<'d, T> {
     pub fn set_trigger_source(&self, ts: TriggerSource) {
         self.regs_gp16().smcr().modify(|r| r.set_ts(ts));
     }
+
+    /// Set slave synchronization mode and trigger source.
+    pub fn set_slave_sync(&self, sync: SlaveSync) {
+        let (sms, ts) = match sync {
+            SlaveSync::Disabled => (SlaveMode::DISABLED, None),
+            SlaveSync::Reset(ts) => (SlaveMode::RESET_MODE, Some(ts)),
+            SlaveSync::Gated(ts) => (SlaveMode::GATED_MODE, Some(ts)),
+            SlaveSync::Trigger(ts) => (SlaveMode::TRIGGER_MODE, Some(ts)),
+        };
+
+        // The trigger source must only be changed while slave mode is disabled.
+        self.set_slave_mode(SlaveMode::DISABLED);
+        if let Some(ts) = ts {
+            self.set_trigger_source(ts);
+        }
+        self.set_slave_mode(sms);
+    }
+
+    /// Enable/disable master/slave mode.
+    ///
+    /// When enabled, the effect of the trigger input is delayed so that this timer and its slaves
+    /// are synchronized on the same trigger event.
+    pub fn set_master_slave_mode(&self, enable: bool) {
+        self.regs_gp16()
+            .smcr()
+            .modify(|r| r.set_msm(if enable { vals::Msm::SYNC } else { vals::Msm::NOSYNC }));
+    }
+
+    /// Configure DMA burst transfers through the `DMAR` register.
+    ///
+    /// `base` is the first register updated by each burst, as an offset in 32-bit words from `CR1`,
+    /// and `len` the number of consecutive registers updated by each burst.
+    pub fn set_dma_burst(&self, base: u8, len: u8) {
+        assert!((1..=18).contains(&len));
+        self.regs_gp16().dcr().modify(|r| {
+            r.set_dba(base);
+            r.set_dbl(len - 1);
+        });
+    }
+
+    /// Get the address of the DMA burst register, `DMAR`.
+    pub fn dma_burst_address(&self) -> *mut u16 {
+        self.regs_gp16().dmar().as_ptr() as *mut u16
+    }
 }
 
 #[cfg(not(stm32l0))]
@@ -0,0 +1,292 @@
+//! One-pulse mode driver.
+//!
+//! In one-pulse mode, the timer generates a single pulse on its output channels each time it is
+//! triggered, either by software or by an edge on its trigger input, after a programmable delay.
+//! This is useful for precisely timed pulses such as ultrasonic sensor triggers or solenoid
+//! kicks.
+
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use embassy_hal_internal::{into_ref, PeripheralRef};
+
+use super::low_level::{
+    CountingMode, InputCaptureMode, InputTISelection, MasterMode, OutputCompareMode, OutputPolarity, SlaveSync, Timer,
+    TriggerSource,
+};
+use super::simple_pwm::{Ch1, Ch2, Ch3, Ch4, PwmPin};
+use super::{Channel, Channel1Pin, Channel2Pin, CoreInstance, GeneralInstance4Channel, UpdateInterruptHandler};
+use crate::gpio::{AfType, AnyPin, Pull};
+use crate::interrupt::typelevel::{Binding, Interrupt};
+use crate::time::Hertz;
+use crate::Peripheral;
+
+/// Trigger pin wrapper.
+///
+/// This wraps a pin to make it usable as the trigger input of a one-pulse timer.
+pub struct TriggerPin<'d, T, C> {
+    _pin: PeripheralRef<'d, AnyPin>,
+    phantom: PhantomData<(T, C)>,
+}
+
+macro_rules! channel_impl {
+    ($new_chx:ident, $channel:ident, $pin_trait:ident) => {
+        impl<'d, T: GeneralInstance4Channel> TriggerPin<'d, T, $channel> {
+            #[doc = concat!("Create a new ", stringify!($channel), " trigger pin instance.")]
+            pub fn $new_chx(pin: impl Peripheral<P = impl $pin_trait<T>> + 'd, pull: Pull) -> Self {
+                into_ref!(pin);
+                pin.set_as_af(pin.af_num(), AfType::input(pull));
+                TriggerPin {
+                    _pin: pin.map_into(),
+                    phantom: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+channel_impl!(new_ch1, Ch1, Channel1Pin);
+channel_impl!(new_ch2, Ch2, Channel2Pin);
+
+/// Edge of the trigger input starting a pulse.
+#[derive(Clone, Copy)]
+pub enum TriggerEdge {
+    /// Rising edge.
+    Rising,
+    /// Falling edge.
+    Falling,
+}
+
+/// One-pulse mode driver.
+pub struct OnePulse<'d, T: GeneralInstance4Channel> {
+    inner: Timer<'d, T>,
+}
+
+impl<'d, T: GeneralInstance4Channel> OnePulse<'d, T> {
+    /// Create a new one-pulse driver, triggered by software with [`trigger`](Self::trigger).
+    ///
+    /// The delay and width of the pulse are counted in ticks of `freq`.
+    pub fn new(
+        tim: impl Peripheral<P = T> + 'd,
+        _ch1: Option<PwmPin<'d, T, Ch1>>,
+        _ch2: Option<PwmPin<'d, T, Ch2>>,
+        _ch3: Option<PwmPin<'d, T, Ch3>>,
+        _ch4: Option<PwmPin<'d, T, Ch4>>,
+        _irq: impl Binding<T::UpdateInterrupt, UpdateInterruptHandler<T>> + 'd,
+        freq: Hertz,
+    ) -> Self {
+        Self::new_inner(tim, freq)
+    }
+
+    /// Create a new one-pulse driver, triggered by an edge on the channel 1 pin.
+    ///
+    /// The delay and width of the pulse are counted in ticks of `freq`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_ch1_triggered(
+        tim: impl Peripheral<P = T> + 'd,
+        _trigger: TriggerPin<'d, T, Ch1>,
+        edge: TriggerEdge,
+        _ch2: Option<PwmPin<'d, T, Ch2>>,
+        _ch3: Option<PwmPin<'d, T, Ch3>>,
+        _ch4: Option<PwmPin<'d, T, Ch4>>,
+        _irq: impl Binding<T::UpdateInterrupt, UpdateInterruptHandler<T>> + 'd,
+        freq: Hertz,
+    ) -> Self {
+        let mut this = Self::new_inner(tim, freq);
+        this.set_trigger_input(Channel::Ch1, edge);
+        this.set_slave_sync(SlaveSync::Trigger(TriggerSource::TI1FP1));
+        this
+    }
+
+    /// Create a new one-pulse driver, triggered by an edge on the channel 2 pin.
+    ///
+    /// The delay and width of the pulse are counted in ticks of `freq`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_ch2_triggered(
+        tim: impl Peripheral<P = T> + 'd,
+        _trigger: TriggerPin<'d, T, Ch2>,
+        edge: TriggerEdge,
+        _ch1: Option<PwmPin<'d, T, Ch1>>,
+        _ch3: Option<PwmPin<'d, T, Ch3>>,
+        _ch4: Option<PwmPin<'d, T, Ch4>>,
+        _irq: impl Binding<T::UpdateInterrupt, UpdateInterruptHandler<T>> + 'd,
+        freq: Hertz,
+    ) -> Self {
+        let mut this = Self::new_inner(tim, freq);
+        this.set_trigger_input(Channel::Ch2, edge);
+        this.set_slave_sync(SlaveSync::Trigger(TriggerSource::TI2FP2));
+        this
+    }
+
+    fn new_inner(tim: impl Peripheral<P = T> + 'd, freq: Hertz) -> Self {
+        let mut this = Self { inner: Timer::new(tim) };
+
+        this.inner.set_counting_mode(CountingMode::EdgeAlignedUp);
+        this.inner.set_tick_freq(freq);
+        this.inner.set_one_pulse_mode(true);
+        this.inner.enable_outputs(); // Required for advanced timers, see GeneralInstance4Channel for details
+
+        // The output is inactive until the counter reaches the delay, and active until it reaches
+        // the auto-reload value, where the counter stops.
+        [Channel::Ch1, Channel::Ch2, Channel::Ch3, Channel::Ch4]
+            .iter()
+            .for_each(|&channel| {
+                this.inner.set_output_compare_mode(channel, OutputCompareMode::PwmMode2);
+                this.inner.set_output_compare_preload(channel, false);
+            });
+        this.set_pulse(1, 1);
+
+        // The prescaler update sets the update flag.
+        this.inner.clear_update_interrupt();
+
+        // enable NVIC interrupt
+        T::UpdateInterrupt::unpend();
+        unsafe { T::UpdateInterrupt::enable() };
+
+        this
+    }
+
+    fn set_trigger_input(&mut self, channel: Channel, edge: TriggerEdge) {
+        self.inner.set_input_ti_selection(channel, InputTISelection::Normal);
+        self.inner.set_input_capture_mode(
+            channel,
+            match edge {
+                TriggerEdge::Rising => InputCaptureMode::Rising,
+                TriggerEdge::Falling => InputCaptureMode::Falling,
+            },
+        );
+    }
+
+    /// Enable the given channel.
+    pub fn enable(&mut self, channel: Channel) {
+        self.inner.enable_channel(channel, true);
+    }
+
+    /// Disable the given channel.
+    pub fn disable(&mut self, channel: Channel) {
+        self.inner.enable_channel(channel, false);
+    }
+
+    /// Check whether given channel is enabled
+    pub fn is_enabled(&self, channel: Channel) -> bool {
+        self.inner.get_channel_enable_state(channel)
+    }
+
+    /// Set the output polarity for a given channel.
+    pub fn set_polarity(&mut self, channel: Channel, polarity: OutputPolarity) {
+        self.inner.set_output_polarity(channel, polarity);
+    }
+
+    /// Get the maximum value of `delay + width`, in ticks.
+    pub fn get_max_ticks(&self) -> u32 {
+        match T::BITS {
+            super::TimerBits::Bits16 => u16::MAX as u32 + 1,
+            #[cfg(not(stm32l0))]
+            super::TimerBits::Bits32 => u32::MAX,
+        }
+    }
+
+    /// Set the delay from the trigger to the start of the pulse, and the width of the pulse, in
+    /// ticks.
+    ///
+    /// Both must be at least 1 tick, and their sum at most [`get_max_ticks`](Self::get_max_ticks).
+    /// This must not be called while a pulse is being generated.
+    pub fn set_pulse(&mut self, delay: u32, width: u32) {
+        assert!(delay > 0 && width > 0);
+        let arr = unwrap!(delay.checked_add(width - 1));
+        assert!(arr < self.get_max_ticks());
+
+        [Channel::Ch1, Channel::Ch2, Channel::Ch3, Channel::Ch4]
+            .iter()
+            .for_each(|&channel| self.inner.set_compare_value(channel, delay));
+        self.inner.set_max_compare_value(arr);
+    }
+
+    /// Set the delay from the trigger to the start of the pulse for a given channel, in ticks.
+    ///
+    /// All pulses end together, so this lets the channels output pulses of different widths. The
+    /// delay must be at least 1 tick, and lower than the end of the pulse set with
+    /// [`set_pulse`](Self::set_pulse).
+    pub fn set_channel_delay(&mut self, channel: Channel, delay: u32) {
+        assert!(delay > 0 && delay <= self.inner.get_max_compare_value());
+        self.inner.set_compare_value(channel, delay);
+    }
+
+    /// Set the master mode, selecting the trigger output (TRGO) sent to slave timers.
+    pub fn set_master_mode(&mut self, mode: MasterMode) {
+        self.inner.set_master_mode(mode);
+    }
+
+    /// Set the slave synchronization mode.
+    ///
+    /// [`SlaveSync::Trigger`] starts a pulse on each trigger edge. [`SlaveSync::Disabled`] only
+    /// leaves the software [`trigger`](Self::trigger).
+    pub fn set_slave_sync(&mut self, sync: SlaveSync) {
+        self.inner.set_slave_sync(sync);
+    }
+
+    /// Check whether a pulse is being generated.
+    pub fn is_running(&self) -> bool {
+        self.inner.is_running()
+    }
+
+    /// Start a pulse.
+    ///
+    /// This does nothing if a pulse is already being generated.
+    pub fn trigger(&mut self) {
+        if !self.inner.is_running() {
+            self.inner.clear_update_interrupt();
+            self.inner.start();
+        }
+    }
+
+    /// Start a pulse, and wait until it ends.
+    pub async fn pulse(&mut self) {
+        self.trigger();
+        self.wait_for_pulse().await
+    }
+
+    /// Asynchronously wait until a pulse ends.
+    ///
+    /// This returns immediately if a pulse has ended since the last call to
+    /// [`trigger`](Self::trigger), [`pulse`](Self::pulse) or this method.
+    pub async fn wait_for_pulse(&mut self) {
+        PulseFuture::<T> { phantom: PhantomData }.await
+    }
+}
+
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+struct PulseFuture<T: CoreInstance> {
+    phantom: PhantomData<T>,
+}
+
+impl<T: CoreInstance> Drop for PulseFuture<T> {
+    fn drop(&mut self) {
+        critical_section::with(|_| {
+            let regs = unsafe { crate::pac::timer::TimCore::from_ptr(T::regs()) };
+
+            // disable interrupt enable
+            regs.dier().modify(|w| w.set_uie(false));
+        });
+    }
+}
+
+impl<T: CoreInstance> Future for PulseFuture<T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        T::state().up_waker.register(cx.waker());
+
+        let regs = unsafe { crate::pac::timer::TimCore::from_ptr(T::regs()) };
+
+        if regs.sr().read().uif() {
+            regs.sr().modify(|w| w.set_uif(false));
+            Poll::Ready(())
+        } else {
+            regs.dier().modify(|w| w.set_uie(true));
+            Poll::Pending
+        }
+    }
+}
@@ -4,7 +4,7 @@ use core::marker::PhantomData;
 
 use embassy_hal_internal::{into_ref, PeripheralRef};
 
-use super::low_level::{CountingMode, OutputCompareMode, OutputPolarity, Timer};
+use super::low_level::{CountingMode, MasterMode, OutputCompareMode, OutputPolarity, SlaveSync, Timer};
 use super::{Channel, Channel1Pin, Channel2Pin, Channel3Pin, Channel4Pin, GeneralInstance4Channel};
 use crate::gpio::{AfType, AnyPin, OutputType, Speed};
 use crate::time::Hertz;
@@ -149,6 +149,24 @@ impl<'d, T: GeneralInstance4Channel> SimplePwm<'d, T> {
         self.inner.set_output_compare_mode(channel, mode);
     }
 
+    /// Set the master mode, selecting the trigger output (TRGO) sent to slave timers.
+    pub fn set_master_mode(&mut self, mode: MasterMode) {
+        self.inner.set_master_mode(mode);
+    }
+
+    /// Set the slave synchronization mode.
+    ///
+    /// In [`SlaveSync::Trigger`] mode, the counter is stopped and reset, and only starts on the next
+    /// trigger edge. For example, slaves triggered by a master in [`MasterMode::UPDATE`] mode all
+    /// start counting in lockstep with the master at its next update event.
+    pub fn set_slave_sync(&mut self, sync: SlaveSync) {
+        if let SlaveSync::Trigger(_) = sync {
+            self.inner.stop();
+            self.inner.reset();
+        }
+        self.inner.set_slave_sync(sync);
+    }
+
     /// Generate a sequence of PWM waveform
     ///
     /// Note:  
@@ -217,6 +235,66 @@ impl<'d, T: GeneralInstance4Channel> SimplePwm<'d, T> {
     }
 }
 
+impl<'d, T: GeneralInstance4Channel> SimplePwm<'d, T> {
+    /// Generate a multichannel sequence of PWM waveforms using DMA bursts
+    ///
+    /// On each update event, the next `ending_channel - starting_channel + 1` values of `duty` are
+    /// written to the compare registers of `starting_channel` through `ending_channel`. `duty` is
+    /// thus interleaved, e.g. `[ch1, ch2, ch3, ch1, ch2, ch3, ...]`.
+    ///
+    /// Note:
+    /// you will need to provide corresponding TIMx_UP DMA channel to use this method.
+    pub async fn waveform_up_multi_channel(
+        &mut self,
+        dma: impl Peripheral<P = impl super::UpDma<T>>,
+        starting_channel: Channel,
+        ending_channel: Channel,
+        duty: &[u16],
+    ) {
+        let start_index = starting_channel.index();
+        let end_index = ending_channel.index();
+        assert!(start_index <= end_index);
+
+        let len = end_index - start_index + 1;
+        assert!(duty.len() % len == 0);
+
+        let regs = self.inner.regs_gp16();
+        let base = (regs.ccr(start_index).as_ptr() as usize - regs.cr1().as_ptr() as usize) / 4;
+        self.inner.set_dma_burst(base as u8, len as u8);
+
+        into_ref!(dma);
+
+        #[allow(clippy::let_unit_value)] // eg. stm32f334
+        let req = dma.request();
+
+        let original_update_dma_state = self.inner.get_update_dma_state();
+
+        if !original_update_dma_state {
+            self.inner.enable_update_dma(true);
+        }
+
+        unsafe {
+            #[cfg(not(any(bdma, gpdma)))]
+            use crate::dma::{Burst, FifoThreshold};
+            use crate::dma::{Transfer, TransferOptions};
+
+            let dma_transfer_option = TransferOptions {
+                #[cfg(not(any(bdma, gpdma)))]
+                fifo_threshold: Some(FifoThreshold::Full),
+                #[cfg(not(any(bdma, gpdma)))]
+                mburst: Burst::Incr4,
+                ..Default::default()
+            };
+
+            Transfer::new_write(&mut dma, req, duty, self.inner.dma_burst_address(), dma_transfer_option).await
+        };
+
+        if !original_update_dma_state {
+            self.inner.enable_update_dma(false);
+        }
+    }
+}
+
 macro_rules! impl_waveform_chx {
     ($fn_name:ident, $dma_ch:ident, $cc_ch:ident) => {
         impl<'d, T: GeneralInstance4Channel> SimplePwm<'d, T> {
@@ -0,0 +1,234 @@
+//! A synchronization primitive for telling many tasks to stop.
+use core::cell::RefCell;
+use core::future::{poll_fn, Future};
+use core::task::{Context, Poll};
+
+use embassy_futures::select::{select, Either};
+
+use crate::blocking_mutex::raw::RawMutex;
+use crate::blocking_mutex::Mutex;
+use crate::waitqueue::MultiWakerRegistration;
+
+/// A token that can be cancelled once, waking every task waiting on it.
+///
+/// Unlike a [`Signal`](crate::signal::Signal), which only wakes a single waiter, any number of
+/// tasks can wait for a token to be cancelled with [`CancellationToken::cancelled`]. This makes
+/// it suitable for shutting down a whole subsystem, for example before entering a low-power mode
+/// or before swapping firmware.
+///
+/// Tokens form a hierarchy: a child token created with [`CancellationToken::child_token`] is
+/// cancelled when its parent (or any further ancestor) is cancelled, but cancelling a child
+/// doesn't affect its parent. Children only borrow their parent, so both can be declared as
+/// `static`s or live on the stack.
+///
+/// `N` is the number of wakers that can be registered at once on a single token. If more tasks
+/// than that wait on the same token, all of them are woken and re-register, so any `N` greater
+/// than zero is correct, but a larger `N` avoids spurious wakeups.
+///
+/// ```
+/// use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+/// use embassy_sync::cancellation_token::CancellationToken;
+///
+/// static SHUTDOWN: CancellationToken<CriticalSectionRawMutex> = CancellationToken::new();
+/// static NETWORK_SHUTDOWN: CancellationToken<CriticalSectionRawMutex> = SHUTDOWN.child_token();
+///
+/// async fn network_task() {
+///     while NETWORK_SHUTDOWN.run_until_cancelled(handle_request()).await.is_some() {}
+///     // Clean up...
+/// }
+///
+/// async fn handle_request() {
+///     // ...
+/// }
+///
+/// // Stops `network_task`, along with everything else waiting on `SHUTDOWN`.
+/// SHUTDOWN.cancel();
+/// assert!(NETWORK_SHUTDOWN.is_cancelled());
+/// ```
+pub struct CancellationToken<'a, M: RawMutex, const N: usize = 4> {
+    parent: Option<&'a CancellationToken<'a, M, N>>,
+    state: Mutex<M, RefCell<State<N>>>,
+}
+
+struct State<const N: usize> {
+    cancelled: bool,
+    wakers: MultiWakerRegistration<N>,
+}
+
+impl<'a, M: RawMutex, const N: usize> CancellationToken<'a, M, N> {
+    /// Create a new root token, which is not cancelled.
+    pub const fn new() -> Self {
+        Self::with_parent(None)
+    }
+
+    /// Create a new child token of this token.
+    ///
+    /// The child is cancelled when this token is cancelled. Cancelling the child doesn't cancel this
+    /// token.
+    pub const fn child_token(&'a self) -> Self {
+        Self::with_parent(Some(self))
+    }
+
+    const fn with_parent(parent: Option<&'a CancellationToken<'a, M, N>>) -> Self {
+        Self {
+            parent,
+            state: Mutex::new(RefCell::new(State {
+                cancelled: false,
+                wakers: MultiWakerRegistration::new(),
+            })),
+        }
+    }
+
+    /// Cancel this token and all of its children, waking every task waiting on them.
+    ///
+    /// Cancelling an already cancelled token does nothing.
+    pub fn cancel(&self) {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.cancelled = true;
+            s.wakers.wake();
+        })
+    }
+
+    /// Check whether this token, or any of its ancestors, has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock(|s| s.borrow().cancelled) || self.parent.map_or(false, |p| p.is_cancelled())
+    }
+
+    fn poll_cancelled(&self, cx: &mut Context<'_>) -> Poll<()> {
+        // Register on every ancestor, since cancelling one of them only wakes its own waiters.
+        let mut token = Some(self);
+        while let Some(t) = token {
+            let cancelled = t.state.lock(|s| {
+                let mut s = s.borrow_mut();
+                if !s.cancelled {
+                    s.wakers.register(cx.waker());
+                }
+                s.cancelled
+            });
+            if cancelled {
+                return Poll::Ready(());
+            }
+            token = t.parent;
+        }
+        Poll::Pending
+    }
+
+    /// Future that completes when this token, or any of its ancestors, is cancelled.
+    ///
+    /// Completes immediately if the token is already cancelled.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| self.poll_cancelled(cx))
+    }
+
+    /// Run `fut` until it completes or this token is cancelled, whichever happens first.
+    ///
+    /// Returns `Some` with the output of `fut` if it completed, or `None` if the token was cancelled.
+    /// If both happen at the same time, cancellation takes precedence, and `fut` is dropped.
+    pub async fn run_until_cancelled<F: Future>(&self, fut: F) -> Option<F::Output> {
+        match select(self.cancelled(), fut).await {
+            Either::First(()) => None,
+            Either::Second(r) => Some(r),
+        }
+    }
+}
+
+impl<'a, M: RawMutex, const N: usize> Default for CancellationToken<'a, M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::pending;
+    use core::pin::pin;
+
+    use futures_executor::block_on;
+    use futures_util::poll;
+
+    use super::*;
+    use crate::blocking_mutex::raw::NoopRawMutex;
+
+    #[test]
+    fn cancel_wakes_all_waiters() {
+        let token = CancellationToken::<NoopRawMutex>::new();
+
+        let mut a = pin!(token.cancelled());
+        let mut b = pin!(token.cancelled());
+
+        block_on(async {
+            assert!(poll!(a.as_mut()).is_pending());
+            assert!(poll!(b.as_mut()).is_pending());
+            assert!(!token.is_cancelled());
+
+            token.cancel();
+            assert!(token.is_cancelled());
+            assert!(poll!(a.as_mut()).is_ready());
+            assert!(poll!(b.as_mut()).is_ready());
+
+            // Waiting on an already cancelled token completes immediately.
+            token.cancelled().await;
+        });
+    }
+
+    #[test]
+    fn cancel_parent_cancels_children() {
+        let root = CancellationToken::<NoopRawMutex>::new();
+        let child = root.child_token();
+        let grandchild = child.child_token();
+
+        let mut wait = pin!(grandchild.cancelled());
+
+        block_on(async {
+            assert!(poll!(wait.as_mut()).is_pending());
+
+            root.cancel();
+            assert!(child.is_cancelled());
+            assert!(grandchild.is_cancelled());
+            assert!(poll!(wait.as_mut()).is_ready());
+        });
+    }
+
+    #[test]
+    fn cancel_child_does_not_cancel_parent() {
+        let root = CancellationToken::<NoopRawMutex>::new();
+        let child = root.child_token();
+        let sibling = root.child_token();
+
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(!root.is_cancelled());
+        assert!(!sibling.is_cancelled());
+    }
+
+    #[test]
+    fn more_waiters_than_capacity() {
+        let token = CancellationToken::<NoopRawMutex, 1>::new();
+
+        let mut a = pin!(token.cancelled());
+        let mut b = pin!(token.cancelled());
+
+        block_on(async {
+            assert!(poll!(a.as_mut()).is_pending());
+            assert!(poll!(b.as_mut()).is_pending());
+
+            token.cancel();
+            assert!(poll!(a.as_mut()).is_ready());
+            assert!(poll!(b.as_mut()).is_ready());
+        });
+    }
+
+    #[test]
+    fn run_until_cancelled() {
+        let token = CancellationToken::<NoopRawMutex>::new();
+
+        block_on(async {
+            assert_eq!(token.run_until_cancelled(async { 42 }).await, Some(42));
+
+            token.cancel();
+            assert_eq!(token.run_until_cancelled(pending::<()>()).await, None);
+            assert_eq!(token.run_until_cancelled(async { 42 }).await, None);
+        });
+    }
+}
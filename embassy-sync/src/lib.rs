@@ -11,6 +11,7 @@ pub(crate) mod fmt;
 mod ring_buffer;
 
 pub mod blocking_mutex;
+pub mod cancellation_token;
 pub mod channel;
 pub mod mutex;
 pub mod once_lock;
@@ -14,6 +14,7 @@ mod delay;
 mod duration;
 mod instant;
 mod timer;
+mod wall_clock;
 
 #[cfg(feature = "mock-driver")]
 mod driver_mock;
@@ -32,7 +33,8 @@ pub use delay::{block_for, Delay};
 pub use duration::Duration;
 pub use embassy_time_driver::TICK_HZ;
 pub use instant::Instant;
-pub use timer::{with_deadline, with_timeout, Ticker, TimeoutError, Timer, WithTimeout};
+pub use timer::{with_deadline, with_timeout, MissedTickBehavior, Ticker, TimeoutError, Timer, WithTimeout};
+pub use wall_clock::{UtcTime, WallClock};
 
 const fn gcd(a: u64, b: u64) -> u64 {
     if b == 0 {
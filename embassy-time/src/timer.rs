@@ -200,22 +200,109 @@ impl Future for Timer {
 ///     }
 /// }
 /// ```
+///
+/// If the task falls behind by more than one period, what happens to the missed ticks is set by
+/// [`MissedTickBehavior`]. By default, they are all fired back-to-back to catch up.
+///
+/// A ticker created with [`Ticker::every_aligned`] is phase-locked: it ticks at absolute multiples
+/// of its period since boot, so several tickers with the same period tick at the same instants,
+/// no matter when they were created.
 pub struct Ticker {
     expires_at: Instant,
     duration: Duration,
+    behavior: MissedTickBehavior,
+    aligned: bool,
+    missed_ticks: u64,
+}
+
+/// What a [`Ticker`] does when one or more ticks have been missed.
+///
+/// A tick is missed when the ticker is polled more than one period after the tick's deadline,
+/// for example because the task was busy or blocked for too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MissedTickBehavior {
+    /// Fire all missed ticks back-to-back, as fast as possible, until the ticker has caught up.
+    ///
+    /// Ticks stay on the original schedule. This is the default.
+    #[default]
+    Burst,
+    /// Drop the missed ticks, and schedule the next tick one period after the late tick fired.
+    ///
+    /// All further ticks are shifted by the delay, so the ticker no longer keeps its original
+    /// schedule or alignment.
+    Delay,
+    /// Drop the missed ticks, and schedule the next tick at the next deadline of the original
+    /// schedule.
+    ///
+    /// The ticker keeps its original schedule and alignment, but fires less often than expected.
+    Skip,
 }
 
 impl Ticker {
     /// Creates a new ticker that ticks at the specified duration interval.
     pub fn every(duration: Duration) -> Self {
         let expires_at = Instant::now() + duration;
-        Self { expires_at, duration }
+        Self::new(expires_at, duration, false)
+    }
+
+    /// Creates a new phase-locked ticker that ticks at absolute multiples of `duration` since boot.
+    ///
+    /// The first tick happens at the next multiple of `duration`, which may be sooner than one full
+    /// period from now.
+    pub fn every_aligned(duration: Duration) -> Self {
+        Self::new(Self::next_aligned(Instant::now(), duration), duration, true)
+    }
+
+    fn new(expires_at: Instant, duration: Duration, aligned: bool) -> Self {
+        Self {
+            expires_at,
+            duration,
+            behavior: MissedTickBehavior::default(),
+            aligned,
+            missed_ticks: 0,
+        }
+    }
+
+    /// Returns the first multiple of `duration` since boot that is strictly after `now`.
+    fn next_aligned(now: Instant, duration: Duration) -> Instant {
+        let period = duration.as_ticks();
+        if period == 0 {
+            return now;
+        }
+        Instant::from_ticks((now.as_ticks() / period + 1) * period)
+    }
+
+    /// Sets what the ticker does when ticks are missed.
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.behavior = behavior;
+    }
+
+    /// Returns what the ticker does when ticks are missed.
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.behavior
+    }
+
+    /// Returns the number of ticks that were missed when the last tick fired.
+    ///
+    /// This is the number of further deadlines that had already passed at that point, i.e. how
+    /// many periods the ticker was behind. With [`MissedTickBehavior::Burst`], these ticks fire
+    /// immediately after; with the other behaviors, they are dropped.
+    pub fn missed_ticks(&self) -> u64 {
+        self.missed_ticks
     }
 
     /// Resets the ticker back to its original state.
     /// This causes the ticker to go back to zero, even if the current tick isn't over yet.
+    ///
+    /// A phase-locked ticker is re-aligned to the next multiple of its period instead.
     pub fn reset(&mut self) {
-        self.expires_at = Instant::now() + self.duration;
+        let now = Instant::now();
+        self.expires_at = if self.aligned {
+            Self::next_aligned(now, self.duration)
+        } else {
+            now + self.duration
+        };
     }
 
     /// Reset the ticker at the deadline.
@@ -232,16 +319,28 @@ impl Ticker {
 
     /// Waits for the next tick.
     pub fn next(&mut self) -> impl Future<Output = ()> + Send + Sync + '_ {
-        poll_fn(|cx| {
-            if self.expires_at <= Instant::now() {
-                let dur = self.duration;
-                self.expires_at += dur;
-                Poll::Ready(())
-            } else {
-                embassy_time_queue_driver::schedule_wake(self.expires_at.as_ticks(), cx.waker());
-                Poll::Pending
-            }
-        })
+        poll_fn(|cx| self.poll_tick(cx))
+    }
+
+    fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let now = Instant::now();
+        if self.expires_at <= now {
+            let period = self.duration.as_ticks();
+            let missed = match period {
+                0 => 0,
+                _ => (now - self.expires_at).as_ticks() / period,
+            };
+            self.missed_ticks = missed;
+            self.expires_at = match (missed, self.behavior) {
+                (0, _) | (_, MissedTickBehavior::Burst) => self.expires_at + self.duration,
+                (_, MissedTickBehavior::Delay) => now + self.duration,
+                (_, MissedTickBehavior::Skip) => self.expires_at + Duration::from_ticks(period * (missed + 1)),
+            };
+            Poll::Ready(())
+        } else {
+            embassy_time_queue_driver::schedule_wake(self.expires_at.as_ticks(), cx.waker());
+            Poll::Pending
+        }
     }
 }
 
@@ -250,14 +349,7 @@ impl Unpin for Ticker {}
 impl Stream for Ticker {
     type Item = ();
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if self.expires_at <= Instant::now() {
-            let dur = self.duration;
-            self.expires_at += dur;
-            Poll::Ready(Some(()))
-        } else {
-            embassy_time_queue_driver::schedule_wake(self.expires_at.as_ticks(), cx.waker());
-            Poll::Pending
-        }
+        self.poll_tick(cx).map(Some)
     }
 }
 
@@ -267,3 +359,72 @@ impl FusedStream for Ticker {
         false
     }
 }
+
+#[cfg(test)]
+#[cfg(all(feature = "mock-driver", feature = "generic-queue"))]
+mod tests {
+    use core::task::Waker;
+
+    use futures_util::task::noop_waker;
+    use serial_test::serial;
+
+    use super::*;
+    use crate::driver_mock::MockDriver;
+
+    fn poll_tick(ticker: &mut Ticker) -> bool {
+        let waker: Waker = noop_waker();
+        ticker.poll_tick(&mut Context::from_waker(&waker)).is_ready()
+    }
+
+    fn stalled_ticker(behavior: MissedTickBehavior) -> Ticker {
+        MockDriver::get().reset();
+        let mut ticker = Ticker::every(Duration::from_secs(1));
+        ticker.set_missed_tick_behavior(behavior);
+
+        // Stall for 3.5 periods.
+        MockDriver::get().advance(Duration::from_millis(3500));
+        assert!(poll_tick(&mut ticker));
+        assert_eq!(ticker.missed_ticks(), 2);
+        ticker
+    }
+
+    #[test]
+    #[serial]
+    fn test_burst() {
+        let mut ticker = stalled_ticker(MissedTickBehavior::Burst);
+
+        assert!(poll_tick(&mut ticker));
+        assert_eq!(ticker.missed_ticks(), 1);
+        assert!(poll_tick(&mut ticker));
+        assert_eq!(ticker.missed_ticks(), 0);
+        assert_eq!(ticker.expires_at, Instant::from_secs(4));
+    }
+
+    #[test]
+    #[serial]
+    fn test_delay() {
+        let ticker = stalled_ticker(MissedTickBehavior::Delay);
+        assert_eq!(ticker.expires_at, Instant::from_millis(4500));
+    }
+
+    #[test]
+    #[serial]
+    fn test_skip() {
+        let ticker = stalled_ticker(MissedTickBehavior::Skip);
+        assert_eq!(ticker.expires_at, Instant::from_secs(4));
+    }
+
+    #[test]
+    #[serial]
+    fn test_aligned() {
+        MockDriver::get().reset();
+        MockDriver::get().advance(Duration::from_millis(1300));
+
+        let mut ticker = Ticker::every_aligned(Duration::from_secs(1));
+        assert_eq!(ticker.expires_at, Instant::from_secs(2));
+
+        MockDriver::get().advance(Duration::from_millis(2000));
+        ticker.reset();
+        assert_eq!(ticker.expires_at, Instant::from_secs(4));
+    }
+}
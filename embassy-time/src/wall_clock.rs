@@ -0,0 +1,380 @@
+use core::cell::Cell;
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+use critical_section::Mutex as CsMutex;
+
+use crate::{Duration, Instant};
+
+/// A point in UTC time, represented as microseconds since the Unix epoch (1970-01-01T00:00:00Z).
+///
+/// Like Unix time, leap seconds are not counted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UtcTime {
+    micros: u64,
+}
+
+impl UtcTime {
+    /// The Unix epoch, 1970-01-01T00:00:00Z.
+    pub const UNIX_EPOCH: UtcTime = UtcTime { micros: 0 };
+
+    /// Create a `UtcTime` from a microsecond count since the Unix epoch.
+    pub const fn from_unix_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+
+    /// Create a `UtcTime` from a millisecond count since the Unix epoch.
+    pub const fn from_unix_millis(millis: u64) -> Self {
+        Self { micros: millis * 1000 }
+    }
+
+    /// Create a `UtcTime` from a second count since the Unix epoch.
+    pub const fn from_unix_secs(secs: u64) -> Self {
+        Self {
+            micros: secs * 1_000_000,
+        }
+    }
+
+    /// Microseconds since the Unix epoch.
+    pub const fn as_unix_micros(&self) -> u64 {
+        self.micros
+    }
+
+    /// Milliseconds since the Unix epoch, rounding down.
+    pub const fn as_unix_millis(&self) -> u64 {
+        self.micros / 1000
+    }
+
+    /// Seconds since the Unix epoch, rounding down.
+    pub const fn as_unix_secs(&self) -> u64 {
+        self.micros / 1_000_000
+    }
+
+    /// Microseconds elapsed since the last whole second.
+    pub const fn subsec_micros(&self) -> u32 {
+        (self.micros % 1_000_000) as u32
+    }
+
+    /// Duration between this time and an earlier one, or `None` if `earlier` is later than `self`.
+    pub fn checked_duration_since(&self, earlier: UtcTime) -> Option<Duration> {
+        self.micros.checked_sub(earlier.micros).map(Duration::from_micros)
+    }
+
+    /// Adds a duration, returning `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<UtcTime> {
+        self.micros
+            .checked_add(duration.as_micros())
+            .map(|micros| UtcTime { micros })
+    }
+
+    /// Subtracts a duration, returning `None` on underflow.
+    pub fn checked_sub(&self, duration: Duration) -> Option<UtcTime> {
+        self.micros
+            .checked_sub(duration.as_micros())
+            .map(|micros| UtcTime { micros })
+    }
+
+    fn offset_by(self, micros: i64) -> UtcTime {
+        UtcTime {
+            micros: self.micros.saturating_add_signed(micros),
+        }
+    }
+}
+
+impl Add<Duration> for UtcTime {
+    type Output = UtcTime;
+
+    fn add(self, rhs: Duration) -> UtcTime {
+        self.checked_add(rhs)
+            .expect("overflow when adding duration to UTC time")
+    }
+}
+
+impl AddAssign<Duration> for UtcTime {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for UtcTime {
+    type Output = UtcTime;
+
+    fn sub(self, rhs: Duration) -> UtcTime {
+        self.checked_sub(rhs)
+            .expect("overflow when subtracting duration from UTC time")
+    }
+}
+
+impl SubAssign<Duration> for UtcTime {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:06} s since epoch", self.as_unix_secs(), self.subsec_micros())
+    }
+}
+
+/// Default correction above which [`WallClock::synchronize`] steps the clock instead of slewing it.
+const DEFAULT_STEP_THRESHOLD: Duration = Duration::from_millis(128);
+/// Default maximum slew rate, in parts per million.
+const DEFAULT_MAX_SLEW_PPM: u32 = 500;
+
+#[derive(Copy, Clone)]
+struct State {
+    /// Instant of the last adjustment.
+    base: Instant,
+    /// UTC time at `base`.
+    base_utc: UtcTime,
+    /// Correction still to be applied after `base`, in microseconds.
+    slew: i64,
+}
+
+impl State {
+    fn utc_at(&self, instant: Instant, max_slew_ppm: u32) -> UtcTime {
+        match instant.checked_duration_since(self.base) {
+            Some(elapsed) => {
+                let elapsed = elapsed.as_micros();
+                let max_correction = (elapsed as u128 * max_slew_ppm as u128 / 1_000_000) as i64;
+                let correction = self.slew.clamp(-max_correction, max_correction);
+                self.base_utc.offset_by(elapsed as i64).offset_by(correction)
+            }
+            // Don't extrapolate slewing backwards.
+            None => self
+                .base_utc
+                .offset_by(-(self.base.duration_since(instant).as_micros() as i64)),
+        }
+    }
+}
+
+/// Wall-clock time derived from the monotonic [`Instant`] clock.
+///
+/// A `WallClock` stores the mapping between [`Instant`]s since boot and [`UtcTime`], obtained from an
+/// external time source such as SNTP or GPS. Until it is set for the first time, [`WallClock::now_utc`]
+/// returns `None`.
+///
+/// When synchronizing an already-set clock with [`WallClock::synchronize`], small corrections are
+/// slewed in gradually at a bounded rate, so the reported time neither jumps nor goes backwards.
+/// Corrections larger than the step threshold are applied immediately instead, so the reported time
+/// jumps, and goes backwards if the clock was ahead.
+///
+/// `WallClock::new()` is `const`, so a `WallClock` can be placed in a `static` and shared between tasks.
+pub struct WallClock {
+    step_threshold: Duration,
+    max_slew_ppm: u32,
+    state: CsMutex<Cell<Option<State>>>,
+}
+
+impl WallClock {
+    /// Create a new, unset wall clock.
+    ///
+    /// Corrections smaller than 128 ms are slewed in at up to 500 ppm.
+    pub const fn new() -> Self {
+        Self::with_slew(DEFAULT_STEP_THRESHOLD, DEFAULT_MAX_SLEW_PPM)
+    }
+
+    /// Create a new, unset wall clock with a custom slew configuration.
+    ///
+    /// Corrections smaller than `step_threshold` are slewed in at up to `max_slew_ppm` parts
+    /// per million. `max_slew_ppm` must be lower than 1_000_000 for the clock to stay monotonic.
+    /// A `step_threshold` of zero disables slewing.
+    pub const fn with_slew(step_threshold: Duration, max_slew_ppm: u32) -> Self {
+        core::assert!(max_slew_ppm < 1_000_000);
+        Self {
+            step_threshold,
+            max_slew_ppm,
+            state: CsMutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Returns whether the clock has been set.
+    pub fn is_set(&self) -> bool {
+        critical_section::with(|cs| self.state.borrow(cs).get().is_some())
+    }
+
+    /// Forget the current time. The clock reads as unset until it is set again.
+    pub fn reset(&self) {
+        critical_section::with(|cs| self.state.borrow(cs).set(None))
+    }
+
+    /// Set the clock to `utc`, as of now, without slewing.
+    pub fn set(&self, utc: UtcTime) {
+        self.set_at(Instant::now(), utc)
+    }
+
+    /// Set the clock so that the UTC time at `instant` is `utc`, without slewing.
+    pub fn set_at(&self, instant: Instant, utc: UtcTime) {
+        let state = State {
+            base: instant,
+            base_utc: utc,
+            slew: 0,
+        };
+        critical_section::with(|cs| self.state.borrow(cs).set(Some(state)))
+    }
+
+    /// Synchronize the clock with a UTC time measured at `instant`.
+    ///
+    /// If the clock is not set, or the correction exceeds the step threshold, the clock is set to
+    /// the new time immediately. Otherwise the correction is slewed in gradually.
+    ///
+    /// Returns the offset that was measured between the new time and the clock, in microseconds.
+    /// It is positive when the clock was behind. Returns `None` if the clock was not set.
+    pub fn synchronize(&self, instant: Instant, utc: UtcTime) -> Option<i64> {
+        self.synchronize_now(Instant::now(), instant, utc)
+    }
+
+    fn synchronize_now(&self, now: Instant, instant: Instant, utc: UtcTime) -> Option<i64> {
+        // Always apply the new state at `now`, so it never moves the base backwards in time.
+        let measured = match now.checked_duration_since(instant) {
+            Some(d) => utc + d,
+            None => utc - instant.duration_since(now),
+        };
+
+        critical_section::with(|cs| {
+            let cell = self.state.borrow(cs);
+            let Some(state) = cell.get() else {
+                cell.set(Some(State {
+                    base: now,
+                    base_utc: measured,
+                    slew: 0,
+                }));
+                return None;
+            };
+
+            let current = state.utc_at(now, self.max_slew_ppm);
+            let offset = measured.as_unix_micros() as i64 - current.as_unix_micros() as i64;
+            let new_state = if offset.unsigned_abs() >= self.step_threshold.as_micros() {
+                State {
+                    base: now,
+                    base_utc: measured,
+                    slew: 0,
+                }
+            } else {
+                State {
+                    base: now,
+                    base_utc: current,
+                    slew: offset,
+                }
+            };
+            cell.set(Some(new_state));
+            Some(offset)
+        })
+    }
+
+    /// Current UTC time, or `None` if the clock is not set.
+    pub fn now_utc(&self) -> Option<UtcTime> {
+        self.utc_at(Instant::now())
+    }
+
+    /// UTC time at `instant`, or `None` if the clock is not set.
+    pub fn utc_at(&self, instant: Instant) -> Option<UtcTime> {
+        critical_section::with(|cs| self.state.borrow(cs).get()).map(|s| s.utc_at(instant, self.max_slew_ppm))
+    }
+
+    /// Correction that hasn't been slewed in yet at `instant`, in microseconds.
+    ///
+    /// Returns `None` if the clock is not set.
+    pub fn pending_correction_at(&self, instant: Instant) -> Option<i64> {
+        critical_section::with(|cs| self.state.borrow(cs).get()).map(|s| {
+            let applied = s.utc_at(instant, self.max_slew_ppm).as_unix_micros() as i64
+                - s.base_utc.as_unix_micros() as i64
+                - instant.saturating_duration_since(s.base).as_micros() as i64;
+            s.slew - applied
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T0: UtcTime = UtcTime::from_unix_secs(1_700_000_000);
+
+    #[test]
+    fn test_unset() {
+        let clock = WallClock::new();
+        assert!(!clock.is_set());
+        assert_eq!(clock.utc_at(Instant::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_set() {
+        let clock = WallClock::new();
+        clock.set_at(Instant::from_secs(10), T0);
+        assert!(clock.is_set());
+        assert_eq!(clock.utc_at(Instant::from_secs(10)), Some(T0));
+        assert_eq!(clock.utc_at(Instant::from_secs(15)), Some(T0 + Duration::from_secs(5)));
+        assert_eq!(clock.utc_at(Instant::from_secs(5)), Some(T0 - Duration::from_secs(5)));
+
+        clock.reset();
+        assert!(!clock.is_set());
+    }
+
+    #[test]
+    fn test_synchronize_first() {
+        let clock = WallClock::new();
+        let now = Instant::from_secs(10);
+        // The measurement was taken 1s ago.
+        assert_eq!(clock.synchronize_now(now, Instant::from_secs(9), T0), None);
+        assert_eq!(clock.utc_at(now), Some(T0 + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_synchronize_step() {
+        let clock = WallClock::new();
+        clock.set_at(Instant::from_secs(0), T0);
+
+        let now = Instant::from_secs(10);
+        let utc = T0 + Duration::from_secs(12);
+        assert_eq!(clock.synchronize_now(now, now, utc), Some(2_000_000));
+        assert_eq!(clock.utc_at(now), Some(utc));
+        assert_eq!(clock.pending_correction_at(now), Some(0));
+    }
+
+    #[test]
+    fn test_synchronize_slew() {
+        let clock = WallClock::with_slew(Duration::from_millis(100), 1000);
+        clock.set_at(Instant::from_secs(0), T0);
+
+        // Clock is 10ms behind.
+        let now = Instant::from_secs(10);
+        let utc = T0 + Duration::from_millis(10_010);
+        assert_eq!(clock.synchronize_now(now, now, utc), Some(10_000));
+
+        // No jump.
+        assert_eq!(clock.utc_at(now), Some(T0 + Duration::from_secs(10)));
+        assert_eq!(clock.pending_correction_at(now), Some(10_000));
+
+        // At 1000ppm, 1ms of correction is applied per second.
+        let later = now + Duration::from_secs(4);
+        assert_eq!(clock.utc_at(later), Some(T0 + Duration::from_millis(14_004)));
+        assert_eq!(clock.pending_correction_at(later), Some(6_000));
+
+        // The correction is fully applied after 10s.
+        let later = now + Duration::from_secs(20);
+        assert_eq!(clock.utc_at(later), Some(T0 + Duration::from_millis(30_010)));
+        assert_eq!(clock.pending_correction_at(later), Some(0));
+    }
+
+    #[test]
+    fn test_synchronize_slew_backwards_is_monotonic() {
+        let clock = WallClock::with_slew(Duration::from_millis(100), 1000);
+        clock.set_at(Instant::from_secs(0), T0);
+
+        // Clock is 10ms ahead.
+        let now = Instant::from_secs(10);
+        let utc = T0 + Duration::from_millis(9_990);
+        assert_eq!(clock.synchronize_now(now, now, utc), Some(-10_000));
+
+        let mut prev = clock.utc_at(now).unwrap();
+        for ms in (100..20_000).step_by(100) {
+            let t = clock.utc_at(now + Duration::from_millis(ms)).unwrap();
+            assert!(t > prev);
+            prev = t;
+        }
+        assert_eq!(prev, T0 + Duration::from_millis(9_990 + 19_900));
+    }
+}
@@ -6,6 +6,12 @@ pub(crate) const DFU_PROTOCOL_DFU: u8 = 0x02;
 #[allow(unused)]
 pub(crate) const DFU_PROTOCOL_RT: u8 = 0x01;
 pub(crate) const DESC_DFU_FUNCTIONAL: u8 = 0x21;
+#[allow(unused)]
+pub(crate) const DFUSE_CMD_GET_COMMANDS: u8 = 0x00;
+#[allow(unused)]
+pub(crate) const DFUSE_CMD_SET_ADDRESS_POINTER: u8 = 0x21;
+#[allow(unused)]
+pub(crate) const DFUSE_CMD_ERASE: u8 = 0x41;
 
 #[cfg(feature = "defmt")]
 defmt::bitflags! {
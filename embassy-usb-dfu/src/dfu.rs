@@ -1,6 +1,6 @@
 use core::marker::PhantomData;
 
-use embassy_boot::{AlignedBuffer, BlockingFirmwareUpdater};
+use embassy_boot::{AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterError};
 use embassy_usb::control::{InResponse, OutResponse, Recipient, RequestType};
 use embassy_usb::driver::Driver;
 use embassy_usb::{Builder, Handler};
@@ -10,7 +10,7 @@ use crate::consts::{
     DfuAttributes, Request, State, Status, APPN_SPEC_SUBCLASS_DFU, DESC_DFU_FUNCTIONAL, DFU_PROTOCOL_DFU,
     USB_CLASS_APPN_SPEC,
 };
-use crate::Reset;
+use crate::{Region, Reset};
 
 /// Internal state for USB DFU
 pub struct Control<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> {
@@ -19,6 +19,7 @@ pub struct Control<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_S
     state: State,
     status: Status,
     offset: usize,
+    upload: Option<&'d mut dyn Region>,
     _rst: PhantomData<RST>,
 }
 
@@ -31,10 +32,27 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Co
             state: State::DfuIdle,
             status: Status::Ok,
             offset: 0,
+            upload: None,
             _rst: PhantomData,
         }
     }
 
+    /// Create a new DFU instance to handle DFU transfers, with uploads reading from `upload`.
+    ///
+    /// Uploads let the host read back the firmware, usually from the active partition, for
+    /// backup or verification. They are only accepted if `attrs` contains
+    /// [`DfuAttributes::CAN_UPLOAD`].
+    pub fn new_with_upload(
+        updater: BlockingFirmwareUpdater<'d, DFU, STATE>,
+        attrs: DfuAttributes,
+        upload: &'d mut dyn Region,
+    ) -> Self {
+        Self {
+            upload: Some(upload),
+            ..Self::new(updater, attrs)
+        }
+    }
+
     fn reset_state(&mut self) {
         self.offset = 0;
         self.state = State::DfuIdle;
@@ -75,15 +93,7 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                         }
                         Err(e) => {
                             self.state = State::Error;
-                            match e {
-                                embassy_boot::FirmwareUpdaterError::Flash(e) => match e {
-                                    NorFlashErrorKind::NotAligned => self.status = Status::ErrWrite,
-                                    NorFlashErrorKind::OutOfBounds => self.status = Status::ErrAddress,
-                                    _ => self.status = Status::ErrUnknown,
-                                },
-                                embassy_boot::FirmwareUpdaterError::Signature(_) => self.status = Status::ErrVerify,
-                                embassy_boot::FirmwareUpdaterError::BadState => self.status = Status::ErrUnknown,
-                            }
+                            self.status = updater_status(e);
                         }
                     }
                 } else {
@@ -101,15 +111,7 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                         }
                         Err(e) => {
                             self.state = State::Error;
-                            match e {
-                                embassy_boot::FirmwareUpdaterError::Flash(e) => match e {
-                                    NorFlashErrorKind::NotAligned => self.status = Status::ErrWrite,
-                                    NorFlashErrorKind::OutOfBounds => self.status = Status::ErrAddress,
-                                    _ => self.status = Status::ErrUnknown,
-                                },
-                                embassy_boot::FirmwareUpdaterError::Signature(_) => self.status = Status::ErrVerify,
-                                embassy_boot::FirmwareUpdaterError::BadState => self.status = Status::ErrUnknown,
-                            }
+                            self.status = updater_status(e);
                         }
                     }
                 }
@@ -150,18 +152,66 @@ impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize> Ha
                 Some(InResponse::Accepted(&buf[0..1]))
             }
             Ok(Request::Upload) if self.attrs.contains(DfuAttributes::CAN_UPLOAD) => {
-                //TODO: FirmwareUpdater does not provide a way of reading the active partition, can't upload.
-                Some(InResponse::Rejected)
+                let Some(upload) = self.upload.as_mut() else {
+                    return Some(InResponse::Rejected);
+                };
+                if req.value == 0 && self.state == State::DfuIdle {
+                    self.state = State::UploadIdle;
+                    self.offset = 0;
+                }
+                if self.state != State::UploadIdle {
+                    self.status = Status::ErrUnknown;
+                    self.state = State::Error;
+                    return Some(InResponse::Rejected);
+                }
+
+                // A block shorter than requested ends the upload.
+                let len = (req.length as usize)
+                    .min(buf.len())
+                    .min(upload.capacity().saturating_sub(self.offset));
+                match upload.read(self.offset as u32, &mut buf[..len]) {
+                    Ok(()) => {
+                        self.offset += len;
+                        if len < req.length as usize {
+                            self.state = State::DfuIdle;
+                        }
+                        Some(InResponse::Accepted(&buf[..len]))
+                    }
+                    Err(e) => {
+                        self.state = State::Error;
+                        self.status = flash_status(e);
+                        Some(InResponse::Rejected)
+                    }
+                }
             }
             _ => None,
         }
     }
 }
 
+/// Get the DFU status of a flash error.
+pub(crate) fn flash_status(e: NorFlashErrorKind) -> Status {
+    match e {
+        NorFlashErrorKind::NotAligned => Status::ErrWrite,
+        NorFlashErrorKind::OutOfBounds => Status::ErrAddress,
+        _ => Status::ErrUnknown,
+    }
+}
+
+/// Get the DFU status of a firmware updater error.
+pub(crate) fn updater_status(e: FirmwareUpdaterError) -> Status {
+    match e {
+        FirmwareUpdaterError::Flash(e) => flash_status(e),
+        FirmwareUpdaterError::Signature(_) | FirmwareUpdaterError::Digest => Status::ErrVerify,
+        FirmwareUpdaterError::BadState => Status::ErrUnknown,
+        _ => Status::ErrFile,
+    }
+}
+
 /// An implementation of the USB DFU 1.1 protocol
 ///
 /// This function will add a DFU interface descriptor to the provided Builder, and register the provided Control as a handler for the USB device
-/// The handler is responsive to DFU GetState, GetStatus, Abort, and ClrStatus commands, as well as Download if configured by the user.
+/// The handler is responsive to DFU GetState, GetStatus, Abort, and ClrStatus commands, as well as Download and Upload if configured by the user.
 ///
 /// Once the host has initiated a DFU download operation, the chunks sent by the host will be written to the DFU partition.
 /// Once the final sync in the manifestation phase has been received, the handler will trigger a system reset to swap the new firmware.
@@ -188,3 +238,140 @@ pub fn usb_dfu<'d, D: Driver<'d>, DFU: NorFlash, STATE: NorFlash, RST: Reset, co
     drop(func);
     builder.handler(handler);
 }
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use embassy_boot::FirmwareUpdaterConfig;
+    use embassy_embedded_hal::flash::partition::BlockingPartition;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+
+    use super::*;
+    use crate::test_usb::{control_in, flash, get_state, get_status, out, Flash, TestReset};
+
+    type TestControl<'d> = Control<
+        'd,
+        BlockingPartition<'d, NoopRawMutex, Flash>,
+        BlockingPartition<'d, NoopRawMutex, Flash>,
+        TestReset,
+        64,
+    >;
+
+    struct Memories {
+        // State partition followed by the DFU partition.
+        boot: Mutex<NoopRawMutex, RefCell<Flash>>,
+        active: Flash,
+        aligned: [u8; 4],
+    }
+
+    impl Memories {
+        fn new() -> Self {
+            let mut active = flash(4096);
+            let mut firmware = [0; 4096];
+            for (i, b) in firmware.iter_mut().enumerate() {
+                *b = (i * 7) as u8;
+            }
+            NorFlash::write(&mut active, 0, &firmware).unwrap();
+            Self {
+                boot: Mutex::new(RefCell::new(flash(12 * 1024))),
+                active,
+                aligned: [0; 4],
+            }
+        }
+
+        fn control(&mut self, attrs: DfuAttributes) -> TestControl<'_> {
+            let state = BlockingPartition::new(&self.boot, 0, 4096);
+            let dfu = BlockingPartition::new(&self.boot, 4096, 8192);
+            let updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut self.aligned);
+            Control::new_with_upload(updater, attrs, &mut self.active)
+        }
+    }
+
+    #[test]
+    fn can_upload() {
+        let mut memories = Memories::new();
+        let firmware = memories.active.contents().to_vec();
+        let mut control = memories.control(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::CAN_UPLOAD);
+        let mut buf = [0; 1000];
+
+        // Blocks are read one after the other, whatever their number after the first.
+        for (block, offset) in [(0, 0), (1, 1000), (7, 2000), (3, 3000)] {
+            assert_eq!(
+                Some(InResponse::Accepted(&firmware[offset..offset + 1000])),
+                control_in(&mut control, Request::Upload, block, &mut buf)
+            );
+            assert_eq!(State::UploadIdle as u8, get_state(&mut control));
+        }
+
+        // A block shorter than requested ends the upload.
+        assert_eq!(
+            Some(InResponse::Accepted(&firmware[4000..])),
+            control_in(&mut control, Request::Upload, 4, &mut buf)
+        );
+        assert_eq!((Status::Ok as u8, State::DfuIdle as u8), get_status(&mut control));
+
+        // Block 0 starts another upload.
+        assert_eq!(
+            Some(InResponse::Accepted(&firmware[..64])),
+            control_in(&mut control, Request::Upload, 0, &mut [0; 64])
+        );
+
+        // An aborted upload starts over.
+        assert_eq!(Some(OutResponse::Accepted), out(&mut control, Request::Abort, 0, &[]));
+        assert_eq!(State::DfuIdle as u8, get_state(&mut control));
+        assert_eq!(
+            Some(InResponse::Accepted(&firmware[..64])),
+            control_in(&mut control, Request::Upload, 0, &mut [0; 64])
+        );
+    }
+
+    #[test]
+    fn rejects_uploads() {
+        let mut memories = Memories::new();
+        let mut buf = [0; 64];
+
+        // Uploads are only handled with the attribute.
+        let mut control = memories.control(DfuAttributes::CAN_DOWNLOAD);
+        assert_eq!(None, control_in(&mut control, Request::Upload, 0, &mut buf));
+
+        // An upload must start with block 0.
+        let mut control = memories.control(DfuAttributes::CAN_DOWNLOAD | DfuAttributes::CAN_UPLOAD);
+        assert_eq!(
+            Some(InResponse::Rejected),
+            control_in(&mut control, Request::Upload, 1, &mut buf)
+        );
+        assert_eq!((Status::ErrUnknown as u8, State::Error as u8), get_status(&mut control));
+        assert_eq!(
+            Some(OutResponse::Accepted),
+            out(&mut control, Request::ClrStatus, 0, &[])
+        );
+
+        // Nor can it start during a download.
+        assert_eq!(
+            Some(OutResponse::Accepted),
+            out(&mut control, Request::Dnload, 0, &[0x11; 64])
+        );
+        assert_eq!((Status::Ok as u8, State::DlSync as u8), get_status(&mut control));
+        assert_eq!(
+            Some(InResponse::Rejected),
+            control_in(&mut control, Request::Upload, 0, &mut buf)
+        );
+        assert_eq!((Status::ErrUnknown as u8, State::Error as u8), get_status(&mut control));
+    }
+
+    #[test]
+    fn rejects_uploads_without_memory() {
+        let mut memories = Memories::new();
+        let state = BlockingPartition::new(&memories.boot, 0, 4096);
+        let dfu = BlockingPartition::new(&memories.boot, 4096, 8192);
+        let updater = BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut memories.aligned);
+        let mut control: TestControl<'_> = Control::new(updater, DfuAttributes::CAN_UPLOAD);
+        assert_eq!(
+            Some(InResponse::Rejected),
+            control_in(&mut control, Request::Upload, 0, &mut [0; 64])
+        );
+        assert_eq!(State::DfuIdle as u8, get_state(&mut control));
+    }
+}
@@ -0,0 +1,844 @@
+use core::fmt::Write;
+use core::marker::PhantomData;
+
+use embassy_boot::{AlignedBuffer, BlockingFirmwareUpdater};
+use embassy_usb::control::{InResponse, OutResponse, Recipient, RequestType};
+use embassy_usb::driver::Driver;
+use embassy_usb::types::{InterfaceNumber, StringIndex};
+use embassy_usb::{Builder, Handler};
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::consts::{
+    DfuAttributes, Request, State, Status, APPN_SPEC_SUBCLASS_DFU, DESC_DFU_FUNCTIONAL, DFUSE_CMD_ERASE,
+    DFUSE_CMD_GET_COMMANDS, DFUSE_CMD_SET_ADDRESS_POINTER, DFU_PROTOCOL_DFU, USB_CLASS_APPN_SPEC,
+};
+use crate::dfu::{flash_status, updater_status};
+use crate::{Region, Reset};
+
+/// Maximum length of the memory layout string of a region.
+const LAYOUT_LEN: usize = 128;
+
+/// Memory region exposed as a DfuSe alternate setting.
+pub struct DfuseRegion<'d> {
+    /// Name of the region, shown by the host.
+    pub name: &'d str,
+    /// Address of the region in the memory map of the device.
+    pub address: u32,
+    /// Memory of the region.
+    pub memory: &'d mut dyn Region,
+}
+
+impl<'d> DfuseRegion<'d> {
+    fn contains(&self, address: u32, len: usize) -> bool {
+        let end = self.address as u64 + self.memory.capacity() as u64;
+        address >= self.address && address as u64 + len as u64 <= end
+    }
+
+    /// Write the DfuSe memory layout string, such as `@Flash /0x08000000/64*002Kg`.
+    fn layout(&self, out: &mut impl Write) -> core::fmt::Result {
+        let erase_size = self.memory.erase_size();
+        let pages = self.memory.capacity() / erase_size;
+        let (size, unit) = if erase_size % 1024 == 0 {
+            (erase_size / 1024, 'K')
+        } else {
+            (erase_size, ' ')
+        };
+        // 'g' marks the pages readable, erasable and writable.
+        write!(out, "@{} /0x{:08X}/{:02}*{:03}{}g", self.name, self.address, pages, size, unit)
+    }
+}
+
+struct LayoutBuffer {
+    buf: [u8; LAYOUT_LEN],
+    len: usize,
+}
+
+impl Write for LayoutBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let buf = self.buf.get_mut(self.len..self.len + s.len()).ok_or(core::fmt::Error)?;
+        buf.copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// Internal state for USB DFU with the DfuSe extensions
+pub struct DfuseControl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize, const N: usize> {
+    updater: BlockingFirmwareUpdater<'d, DFU, STATE>,
+    firmware: DfuseRegion<'d>,
+    regions: [DfuseRegion<'d>; N],
+    attrs: DfuAttributes,
+    state: State,
+    status: Status,
+    iface: Option<InterfaceNumber>,
+    first_string: Option<StringIndex>,
+    alt: u8,
+    address: u32,
+    updated: bool,
+    layout: LayoutBuffer,
+    _rst: PhantomData<RST>,
+}
+
+impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize, const N: usize>
+    DfuseControl<'d, DFU, STATE, RST, BLOCK_SIZE, N>
+{
+    /// Create a new DfuSe instance to handle DFU transfers.
+    ///
+    /// The firmware is exposed as the first alternate setting, at the address of the active
+    /// partition given by `firmware`. Downloads to it are written to the DFU partition with the
+    /// `updater`, and marked updated when the host leaves DFU mode, unless it aborts or clears an
+    /// error, or selects another alternate setting first. Uploads read the active partition, from
+    /// the `memory` of `firmware`. The `regions` are exposed as the next alternate settings, and are
+    /// read, erased and written directly.
+    pub fn new(
+        updater: BlockingFirmwareUpdater<'d, DFU, STATE>,
+        firmware: DfuseRegion<'d>,
+        regions: [DfuseRegion<'d>; N],
+        attrs: DfuAttributes,
+    ) -> Self {
+        assert!(N < u8::MAX as usize);
+        assert_eq!(0, BLOCK_SIZE % DFU::WRITE_SIZE);
+        for region in core::iter::once(&firmware).chain(regions.iter()) {
+            assert_eq!(0, BLOCK_SIZE % region.memory.write_size());
+            let mut layout = LayoutBuffer {
+                buf: [0; LAYOUT_LEN],
+                len: 0,
+            };
+            assert!(region.layout(&mut layout).is_ok(), "region name too long");
+        }
+
+        Self {
+            address: firmware.address,
+            updater,
+            firmware,
+            regions,
+            attrs,
+            state: State::DfuIdle,
+            status: Status::Ok,
+            iface: None,
+            first_string: None,
+            alt: 0,
+            updated: false,
+            layout: LayoutBuffer {
+                buf: [0; LAYOUT_LEN],
+                len: 0,
+            },
+            _rst: PhantomData,
+        }
+    }
+
+    fn reset_state(&mut self) {
+        self.state = State::DfuIdle;
+        self.status = Status::Ok;
+        self.updated = false;
+    }
+
+    fn region(&mut self) -> &mut DfuseRegion<'d> {
+        match self.alt {
+            0 => &mut self.firmware,
+            alt => &mut self.regions[alt as usize - 1],
+        }
+    }
+
+    /// Address of a block, relative to the address pointer.
+    fn block_address(&self, block: u16) -> u32 {
+        self.address
+            .wrapping_add((block as u32 - 2).wrapping_mul(BLOCK_SIZE as u32))
+    }
+
+    fn command(&mut self, data: &[u8]) -> Result<(), Status> {
+        let alt = self.alt;
+        match *data {
+            [DFUSE_CMD_SET_ADDRESS_POINTER, a0, a1, a2, a3] => {
+                let address = u32::from_le_bytes([a0, a1, a2, a3]);
+                if !self.region().contains(address, 0) {
+                    return Err(Status::ErrTarget);
+                }
+                self.address = address;
+                Ok(())
+            }
+            // The firmware updater erases the DFU partition while writing.
+            [DFUSE_CMD_ERASE] if alt == 0 => Ok(()),
+            [DFUSE_CMD_ERASE] => {
+                let memory = &mut self.region().memory;
+                memory.erase(0, memory.capacity() as u32).map_err(flash_status)
+            }
+            [DFUSE_CMD_ERASE, a0, a1, a2, a3] => {
+                let address = u32::from_le_bytes([a0, a1, a2, a3]);
+                let region = self.region();
+                if !region.contains(address, 1) {
+                    return Err(Status::ErrTarget);
+                }
+                if alt == 0 {
+                    return Ok(());
+                }
+                let erase_size = region.memory.erase_size() as u32;
+                let page = (address - region.address) / erase_size * erase_size;
+                region.memory.erase(page, page + erase_size).map_err(flash_status)
+            }
+            _ => Err(Status::ErrStalledPkt),
+        }
+    }
+
+    fn download(&mut self, block: u16, data: &[u8]) -> Result<(), Status> {
+        let alt = self.alt;
+        let address = self.block_address(block);
+        let region = self.region();
+        if !region.contains(address, data.len()) {
+            return Err(Status::ErrAddress);
+        }
+        let offset = address - region.address;
+
+        // Pad the last block with erased bytes to a whole write.
+        let mut buf = AlignedBuffer([0xFF; BLOCK_SIZE]);
+        buf.as_mut()[..data.len()].copy_from_slice(data);
+
+        if alt == 0 {
+            let len = data.len().next_multiple_of(DFU::WRITE_SIZE);
+            self.updater
+                .write_firmware(offset as usize, &buf.as_ref()[..len])
+                .map_err(updater_status)?;
+            self.updated = true;
+            Ok(())
+        } else {
+            let len = data.len().next_multiple_of(region.memory.write_size());
+            region.memory.write(offset, &buf.as_ref()[..len]).map_err(flash_status)
+        }
+    }
+
+    fn upload(&mut self, block: u16, buf: &mut [u8]) -> Result<usize, Status> {
+        match block {
+            0 => {
+                let commands = [DFUSE_CMD_GET_COMMANDS, DFUSE_CMD_SET_ADDRESS_POINTER, DFUSE_CMD_ERASE];
+                buf[..commands.len()].copy_from_slice(&commands);
+                Ok(commands.len())
+            }
+            1 => Err(Status::ErrStalledPkt),
+            _ => {
+                let address = self.block_address(block);
+                let region = self.region();
+                if !region.contains(address, 0) {
+                    return Err(Status::ErrAddress);
+                }
+                let offset = address - region.address;
+                let len = buf.len().min(region.memory.capacity() - offset as usize);
+                region.memory.read(offset, &mut buf[..len]).map_err(flash_status)?;
+                Ok(len)
+            }
+        }
+    }
+}
+
+impl<'d, DFU: NorFlash, STATE: NorFlash, RST: Reset, const BLOCK_SIZE: usize, const N: usize> Handler
+    for DfuseControl<'d, DFU, STATE, RST, BLOCK_SIZE, N>
+{
+    fn set_alternate_setting(&mut self, iface: InterfaceNumber, alternate_setting: u8) {
+        if self.iface == Some(iface) && alternate_setting as usize <= N {
+            self.alt = alternate_setting;
+            self.address = self.region().address;
+            self.reset_state();
+        }
+    }
+
+    fn control_out(
+        &mut self,
+        req: embassy_usb::control::Request,
+        data: &[u8],
+    ) -> Option<embassy_usb::control::OutResponse> {
+        if (req.request_type, req.recipient) != (RequestType::Class, Recipient::Interface) {
+            return None;
+        }
+        match Request::try_from(req.request) {
+            Ok(Request::Abort) => {
+                self.reset_state();
+                Some(OutResponse::Accepted)
+            }
+            Ok(Request::Dnload) if self.attrs.contains(DfuAttributes::CAN_DOWNLOAD) => {
+                if !matches!(self.state, State::DfuIdle | State::Download) {
+                    // Unexpected DNLOAD while chip is waiting for a GETSTATUS
+                    self.status = Status::ErrUnknown;
+                    self.state = State::Error;
+                    return Some(OutResponse::Rejected);
+                }
+
+                let result = match (req.length, req.value) {
+                    // An empty download leaves DFU mode, installing the firmware if it was written.
+                    (0, _) if self.updated => self.updater.mark_updated().map_err(updater_status),
+                    (0, _) => Ok(()),
+                    (_, 0) => self.command(data),
+                    (_, 1) => Err(Status::ErrStalledPkt),
+                    (_, block) => self.download(block, data),
+                };
+                match result {
+                    Ok(()) if req.length == 0 => {
+                        self.status = Status::Ok;
+                        self.state = State::ManifestSync;
+                    }
+                    Ok(()) => {
+                        self.status = Status::Ok;
+                        self.state = State::DlSync;
+                    }
+                    Err(status) => {
+                        self.status = status;
+                        self.state = State::Error;
+                    }
+                }
+                Some(OutResponse::Accepted)
+            }
+            Ok(Request::Detach) => Some(OutResponse::Accepted), // Device is already in DFU mode
+            Ok(Request::ClrStatus) => {
+                self.reset_state();
+                Some(OutResponse::Accepted)
+            }
+            _ => None,
+        }
+    }
+
+    fn control_in<'a>(
+        &'a mut self,
+        req: embassy_usb::control::Request,
+        buf: &'a mut [u8],
+    ) -> Option<embassy_usb::control::InResponse<'a>> {
+        if (req.request_type, req.recipient) != (RequestType::Class, Recipient::Interface) {
+            return None;
+        }
+        match Request::try_from(req.request) {
+            Ok(Request::GetStatus) => {
+                // DfuSe hosts expect commands and writes to report dfuDNBUSY before completing.
+                let state = match self.state {
+                    State::DlSync => {
+                        self.state = State::Download;
+                        State::DlBusy
+                    }
+                    State::ManifestSync => RST::sys_reset(),
+                    state => state,
+                };
+                buf[0..6].copy_from_slice(&[self.status as u8, 0x32, 0x00, 0x00, state as u8, 0x00]);
+                Some(InResponse::Accepted(&buf[0..6]))
+            }
+            Ok(Request::GetState) => {
+                buf[0] = self.state as u8;
+                Some(InResponse::Accepted(&buf[0..1]))
+            }
+            Ok(Request::Upload) if self.attrs.contains(DfuAttributes::CAN_UPLOAD) => {
+                if !matches!(self.state, State::DfuIdle | State::UploadIdle) {
+                    self.status = Status::ErrUnknown;
+                    self.state = State::Error;
+                    return Some(InResponse::Rejected);
+                }
+
+                let len = (req.length as usize).min(buf.len());
+                match self.upload(req.value, &mut buf[..len]) {
+                    Ok(read) => {
+                        // A block shorter than requested ends the upload.
+                        self.state = if read < req.length as usize {
+                            State::DfuIdle
+                        } else {
+                            State::UploadIdle
+                        };
+                        Some(InResponse::Accepted(&buf[..read]))
+                    }
+                    Err(status) => {
+                        self.status = status;
+                        self.state = State::Error;
+                        Some(InResponse::Rejected)
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn get_string(&mut self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+        let alt = index.0.checked_sub(self.first_string?.0)?;
+        if alt as usize > N {
+            return None;
+        }
+        let region = match alt {
+            0 => &self.firmware,
+            alt => &self.regions[alt as usize - 1],
+        };
+        self.layout.len = 0;
+        region.layout(&mut self.layout).ok()?;
+        core::str::from_utf8(&self.layout.buf[..self.layout.len]).ok()
+    }
+}
+
+/// An implementation of the USB DFU 1.1 protocol with the ST DfuSe extensions
+///
+/// This function will add a DFU interface descriptor to the provided Builder, with an alternate setting for the
+/// firmware and for each region of the handler, and register the handler for the USB device. Each alternate setting
+/// is named with the DfuSe memory layout of its region, so that hosts such as `dfu-util` and STM32CubeProgrammer can
+/// address the regions by their address, for example with `dfu-util -a 0 -s 0x08000000:leave -D firmware.bin`.
+///
+/// Besides the requests handled by [`usb_dfu`](crate::usb_dfu), the handler supports the DfuSe set address pointer
+/// and erase commands. Once the host leaves DFU mode, the handler marks the firmware updated if it was downloaded,
+/// and triggers a system reset.
+pub fn usb_dfuse<
+    'd,
+    D: Driver<'d>,
+    DFU: NorFlash,
+    STATE: NorFlash,
+    RST: Reset,
+    const BLOCK_SIZE: usize,
+    const N: usize,
+>(
+    builder: &mut Builder<'d, D>,
+    handler: &'d mut DfuseControl<'d, DFU, STATE, RST, BLOCK_SIZE, N>,
+) {
+    let mut func = builder.function(0x00, 0x00, 0x00);
+    let mut iface = func.interface();
+    handler.iface = Some(iface.interface_number());
+    for alt in 0..=N {
+        let string = iface.string();
+        handler.first_string.get_or_insert(string);
+        let mut alt_setting = iface.alt_setting(
+            USB_CLASS_APPN_SPEC,
+            APPN_SPEC_SUBCLASS_DFU,
+            DFU_PROTOCOL_DFU,
+            Some(string),
+        );
+        // The functional descriptor follows the last alternate setting.
+        if alt == N {
+            alt_setting.descriptor(
+                DESC_DFU_FUNCTIONAL,
+                &[
+                    handler.attrs.bits(),
+                    0xc4,
+                    0x09, // 2500ms timeout, doesn't affect operation as DETACH not necessary in bootloader code
+                    (BLOCK_SIZE & 0xff) as u8,
+                    ((BLOCK_SIZE & 0xff00) >> 8) as u8,
+                    0x1a,
+                    0x01, // DfuSe 1.1a
+                ],
+            );
+        }
+    }
+
+    drop(func);
+    builder.handler(handler);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::RefCell;
+
+    use embassy_boot::FirmwareUpdaterConfig;
+    use embassy_embedded_hal::flash::partition::BlockingPartition;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+    use embassy_usb::control::InResponse;
+    use embassy_usb::Config;
+    use embedded_storage::nor_flash::NorFlashErrorKind;
+
+    use super::*;
+    use crate::test_usb::{control_in, flash, get_state, get_status, out, Flash, NoDriver, TestReset, IFACE};
+
+    const ACTIVE: u32 = 0x0800_0000;
+    const DATA: u32 = 0x0808_0000;
+    const OK: u8 = Status::Ok as u8;
+
+    type TestControl<'d> = DfuseControl<
+        'd,
+        BlockingPartition<'d, NoopRawMutex, Flash>,
+        BlockingPartition<'d, NoopRawMutex, Flash>,
+        TestReset,
+        64,
+        1,
+    >;
+
+    struct Memories {
+        // State partition followed by the DFU partition.
+        boot: Mutex<NoopRawMutex, RefCell<Flash>>,
+        active: Flash,
+        data: Flash,
+        aligned: [u8; 4],
+    }
+
+    impl Memories {
+        fn new() -> Self {
+            Self {
+                boot: Mutex::new(RefCell::new(flash(12 * 1024))),
+                active: flash(4096),
+                data: flash(2048),
+                aligned: [0; 4],
+            }
+        }
+
+        fn control(&mut self) -> TestControl<'_> {
+            let state = BlockingPartition::new(&self.boot, 0, 4096);
+            let dfu = BlockingPartition::new(&self.boot, 4096, 8192);
+            let mut control = DfuseControl::new(
+                BlockingFirmwareUpdater::new(FirmwareUpdaterConfig { dfu, state }, &mut self.aligned),
+                DfuseRegion {
+                    name: "Firmware",
+                    address: ACTIVE,
+                    memory: &mut self.active,
+                },
+                [DfuseRegion {
+                    name: "Data",
+                    address: DATA,
+                    memory: &mut self.data,
+                }],
+                DfuAttributes::CAN_DOWNLOAD | DfuAttributes::CAN_UPLOAD,
+            );
+            control.iface = Some(IFACE);
+            control
+        }
+
+        fn dfu(&self) -> std::vec::Vec<u8> {
+            self.boot.lock(|f| f.borrow().contents()[4096..].to_vec())
+        }
+    }
+
+    fn command(control: &mut TestControl<'_>, command: &[u8]) -> (u8, u8) {
+        assert_eq!(Some(OutResponse::Accepted), out(control, Request::Dnload, 0, command));
+        get_status(control)
+    }
+
+    fn set_address(control: &mut TestControl<'_>, address: u32) -> (u8, u8) {
+        let [a0, a1, a2, a3] = address.to_le_bytes();
+        command(control, &[DFUSE_CMD_SET_ADDRESS_POINTER, a0, a1, a2, a3])
+    }
+
+    fn download(control: &mut TestControl<'_>, block: u16, data: &[u8]) -> (u8, u8) {
+        assert_eq!(Some(OutResponse::Accepted), out(control, Request::Dnload, block, data));
+        get_status(control)
+    }
+
+    fn leave(control: &mut TestControl<'_>) {
+        assert_eq!(Some(OutResponse::Accepted), out(control, Request::Dnload, 2, &[]));
+        assert_eq!(State::ManifestSync as u8, get_state(control));
+    }
+
+    fn updated(control: &mut TestControl<'_>) -> bool {
+        control.updater.get_state().unwrap() == embassy_boot::State::Swap
+    }
+
+    #[test]
+    fn can_download_firmware() {
+        let mut memories = Memories::new();
+        let mut control = memories.control();
+        let busy = (OK, State::DlBusy as u8);
+
+        // As `dfu-util -a 0 -s 0x08000400:leave -D firmware.bin`, with a firmware of 74 bytes.
+        assert_eq!(busy, set_address(&mut control, ACTIVE + 1024));
+        assert_eq!(State::Download as u8, get_state(&mut control));
+        assert_eq!(busy, command(&mut control, &[DFUSE_CMD_ERASE, 0x00, 0x04, 0x00, 0x08]));
+        assert_eq!(busy, download(&mut control, 2, &[0x11; 64]));
+        assert_eq!(busy, download(&mut control, 3, &[0x22; 10]));
+        assert!(!updated(&mut control));
+        leave(&mut control);
+        assert!(updated(&mut control));
+
+        // Blocks are written from the address pointer, the last one padded to a whole write.
+        let dfu = memories.dfu();
+        assert!(dfu[..1024].iter().all(|b| *b == 0xFF));
+        let mut expected = [0xFF; 128];
+        expected[..64].fill(0x11);
+        expected[64..74].fill(0x22);
+        assert_eq!(expected, dfu[1024..1152]);
+    }
+
+    #[test]
+    #[should_panic(expected = "reset")]
+    fn resets_after_leaving() {
+        let mut memories = Memories::new();
+        let mut control = memories.control();
+        leave(&mut control);
+        // Leaving without downloading firmware doesn't install anything.
+        assert!(!updated(&mut control));
+        get_status(&mut control);
+    }
+
+    #[test]
+    fn aborts_firmware_download() {
+        let mut memories = Memories::new();
+        let mut control = memories.control();
+        download(&mut control, 2, &[0x11; 64]);
+        assert_eq!(Some(OutResponse::Accepted), out(&mut control, Request::Abort, 0, &[]));
+        assert_eq!((OK, State::DfuIdle as u8), get_status(&mut control));
+        leave(&mut control);
+        assert!(!updated(&mut control));
+
+        // Selecting another alternate setting also abandons the firmware.
+        let mut control = memories.control();
+        download(&mut control, 2, &[0x11; 64]);
+        control.set_alternate_setting(IFACE, 1);
+        leave(&mut control);
+        assert!(!updated(&mut control));
+    }
+
+    #[test]
+    fn can_write_regions() {
+        let mut memories = Memories::new();
+        NorFlash::write(&mut memories.data, 0, &[0; 8]).unwrap();
+        NorFlash::write(&mut memories.data, 1024, &[0; 8]).unwrap();
+        let mut control = memories.control();
+        let busy = (OK, State::DlBusy as u8);
+
+        // Another interface is ignored.
+        control.set_alternate_setting(InterfaceNumber(1), 1);
+        assert_eq!(
+            (Status::ErrTarget as u8, State::Error as u8),
+            set_address(&mut control, DATA)
+        );
+        assert_eq!(
+            Some(OutResponse::Accepted),
+            out(&mut control, Request::ClrStatus, 0, &[])
+        );
+
+        // The address pointer is reset to the region of the alternate setting.
+        control.set_alternate_setting(IFACE, 1);
+        assert_eq!(busy, command(&mut control, &[DFUSE_CMD_ERASE, 0x00, 0x04, 0x08, 0x08]));
+        assert_eq!(busy, set_address(&mut control, DATA + 960));
+        assert_eq!(busy, download(&mut control, 3, &[0x33; 6]));
+        leave(&mut control);
+        assert!(!updated(&mut control));
+
+        // The erase command erased the page at 0x08080400, which block 3 then wrote to, 64 bytes after
+        // the address pointer.
+        let data = memories.data.contents();
+        assert_eq!([0; 8], data[..8]);
+        assert_eq!([0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0xFF, 0xFF], data[1024..1032]);
+        assert!(data[1032..].iter().all(|b| *b == 0xFF));
+
+        // A mass erase erases the whole region.
+        let mut control = memories.control();
+        control.set_alternate_setting(IFACE, 1);
+        assert_eq!(busy, command(&mut control, &[DFUSE_CMD_ERASE]));
+        assert!(memories.data.contents().iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn rejects_addresses_outside_regions() {
+        let mut memories = Memories::new();
+        let mut control = memories.control();
+        let error = |status: Status| (status as u8, State::Error as u8);
+
+        assert_eq!(error(Status::ErrTarget), set_address(&mut control, DATA));
+        out(&mut control, Request::ClrStatus, 0, &[]);
+        assert_eq!(
+            error(Status::ErrTarget),
+            command(&mut control, &[DFUSE_CMD_ERASE, 0x00, 0x10, 0x00, 0x08])
+        );
+        out(&mut control, Request::ClrStatus, 0, &[]);
+
+        // The last block would end past the firmware.
+        set_address(&mut control, ACTIVE + 4096 - 32);
+        assert_eq!(error(Status::ErrAddress), download(&mut control, 2, &[0; 64]));
+        out(&mut control, Request::ClrStatus, 0, &[]);
+
+        // Block 1 is reserved, and unknown commands are stalled.
+        assert_eq!(error(Status::ErrStalledPkt), download(&mut control, 1, &[0; 64]));
+        out(&mut control, Request::ClrStatus, 0, &[]);
+        assert_eq!(error(Status::ErrStalledPkt), command(&mut control, &[0x42]));
+    }
+
+    #[test]
+    fn can_upload_regions() {
+        let mut memories = Memories::new();
+        let mut firmware = [0; 4096];
+        for (i, b) in firmware.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        NorFlash::write(&mut memories.active, 0, &firmware).unwrap();
+        NorFlash::write(&mut memories.data, 0, &[0x44; 8]).unwrap();
+        let mut control = memories.control();
+        let mut buf = [0; 64];
+
+        // Block 0 lists the supported commands.
+        assert_eq!(
+            Some(InResponse::Accepted(
+                &[DFUSE_CMD_GET_COMMANDS, DFUSE_CMD_SET_ADDRESS_POINTER, DFUSE_CMD_ERASE][..]
+            )),
+            control_in(&mut control, Request::Upload, 0, &mut buf)
+        );
+        assert_eq!(State::DfuIdle as u8, get_state(&mut control));
+
+        // Blocks are read from the address pointer.
+        set_address(&mut control, ACTIVE + 1024);
+        out(&mut control, Request::Abort, 0, &[]);
+        assert_eq!(
+            Some(InResponse::Accepted(&firmware[1024..1088])),
+            control_in(&mut control, Request::Upload, 2, &mut buf)
+        );
+        assert_eq!(State::UploadIdle as u8, get_state(&mut control));
+        assert_eq!(
+            Some(InResponse::Accepted(&firmware[1152..1216])),
+            control_in(&mut control, Request::Upload, 4, &mut buf)
+        );
+
+        // A block shorter than requested ends the upload.
+        assert_eq!(
+            Some(InResponse::Accepted(&firmware[4032..])),
+            control_in(&mut control, Request::Upload, 49, &mut [0; 128])
+        );
+        assert_eq!(State::DfuIdle as u8, get_state(&mut control));
+
+        // Block 1 is reserved.
+        assert_eq!(
+            Some(InResponse::Rejected),
+            control_in(&mut control, Request::Upload, 1, &mut buf)
+        );
+        assert_eq!(
+            (Status::ErrStalledPkt as u8, State::Error as u8),
+            get_status(&mut control)
+        );
+        out(&mut control, Request::ClrStatus, 0, &[]);
+
+        // Other regions are read from their own address.
+        control.set_alternate_setting(IFACE, 1);
+        assert_eq!(
+            Some(InResponse::Accepted(&[0x44; 8][..])),
+            control_in(&mut control, Request::Upload, 2, &mut [0; 8])
+        );
+    }
+
+    #[test]
+    fn rejects_uploads_while_downloading() {
+        let mut memories = Memories::new();
+        let mut control = memories.control();
+        let mut buf = [0; 64];
+
+        download(&mut control, 2, &[0x11; 64]);
+        assert_eq!(
+            Some(InResponse::Rejected),
+            control_in(&mut control, Request::Upload, 2, &mut buf)
+        );
+        assert_eq!((Status::ErrUnknown as u8, State::Error as u8), get_status(&mut control));
+
+        // Nor can a download start during an upload.
+        out(&mut control, Request::ClrStatus, 0, &[]);
+        control_in(&mut control, Request::Upload, 2, &mut buf);
+        assert_eq!(
+            Some(OutResponse::Rejected),
+            out(&mut control, Request::Dnload, 2, &[0x11; 64])
+        );
+        assert_eq!((Status::ErrUnknown as u8, State::Error as u8), get_status(&mut control));
+    }
+
+    #[test]
+    fn can_describe_regions() {
+        let mut memories = Memories::new();
+        let mut control = memories.control();
+        control.first_string = Some(StringIndex(4));
+        assert_eq!(None, control.get_string(StringIndex(3), 0));
+        assert_eq!(
+            Some("@Firmware /0x08000000/04*001Kg"),
+            control.get_string(StringIndex(4), 0)
+        );
+        assert_eq!(
+            Some("@Data /0x08080000/02*001Kg"),
+            control.get_string(StringIndex(5), 0)
+        );
+        assert_eq!(None, control.get_string(StringIndex(6), 0));
+
+        // Pages which aren't a multiple of 1K are listed in bytes.
+        let mut small = SmallPageFlash(flash(1024));
+        let region = DfuseRegion {
+            name: "Small",
+            address: 0x2000_0000,
+            memory: &mut small,
+        };
+        let mut layout = LayoutBuffer {
+            buf: [0; LAYOUT_LEN],
+            len: 0,
+        };
+        region.layout(&mut layout).unwrap();
+        assert_eq!(b"@Small /0x20000000/04*256 g", &layout.buf[..layout.len]);
+    }
+
+    /// Region with 256 byte pages.
+    struct SmallPageFlash(Flash);
+
+    impl Region for SmallPageFlash {
+        fn capacity(&self) -> usize {
+            Region::capacity(&self.0)
+        }
+
+        fn erase_size(&self) -> usize {
+            256
+        }
+
+        fn write_size(&self) -> usize {
+            Region::write_size(&self.0)
+        }
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+            Region::read(&mut self.0, offset, bytes)
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+            Region::write(&mut self.0, offset, bytes)
+        }
+
+        fn erase(&mut self, _from: u32, _to: u32) -> Result<(), NorFlashErrorKind> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn can_add_alternate_settings() {
+        let mut config_descriptor = [0; 256];
+        let mut bos_descriptor = [0; 256];
+        let mut control_buf = [0; 64];
+        let mut memories = Memories::new();
+        let mut control = memories.control();
+
+        let mut builder = Builder::new(
+            NoDriver,
+            Config::new(0xc0de, 0xcafe),
+            &mut config_descriptor,
+            &mut bos_descriptor,
+            &mut [],
+            &mut control_buf,
+        );
+        usb_dfuse(&mut builder, &mut control);
+        drop(builder);
+
+        // Walk the descriptors following the configuration descriptor.
+        let mut interfaces = 0;
+        let mut strings = [0; 2];
+        let mut functional = None;
+        let mut rest = &config_descriptor[9..];
+        while rest.first().is_some_and(|len| *len > 0) {
+            let (descriptor, next) = rest.split_at(rest[0] as usize);
+            match descriptor[1] {
+                0x04 => {
+                    // Interface number, alternate setting, endpoints, class, subclass and protocol.
+                    assert_eq!(
+                        [
+                            0,
+                            interfaces,
+                            0,
+                            USB_CLASS_APPN_SPEC,
+                            APPN_SPEC_SUBCLASS_DFU,
+                            DFU_PROTOCOL_DFU
+                        ],
+                        descriptor[2..8]
+                    );
+                    assert!(
+                        functional.is_none(),
+                        "functional descriptor before an alternate setting"
+                    );
+                    strings[interfaces as usize] = descriptor[8];
+                    interfaces += 1;
+                }
+                DESC_DFU_FUNCTIONAL => functional = Some(descriptor),
+                _ => {}
+            }
+            rest = next;
+        }
+
+        assert_eq!(2, interfaces);
+        assert_eq!(strings[0] + 1, strings[1]);
+        // Attributes, detach timeout, transfer size and DfuSe version.
+        assert_eq!(
+            Some(&[0x03, 0xc4, 0x09, 64, 0, 0x1a, 0x01][..]),
+            functional.map(|d| &d[2..])
+        );
+    }
+}
@@ -10,6 +10,19 @@ mod dfu;
 #[cfg(feature = "dfu")]
 pub use self::dfu::*;
 
+#[cfg(feature = "dfu")]
+mod dfuse;
+#[cfg(feature = "dfu")]
+pub use self::dfuse::*;
+
+#[cfg(feature = "dfu")]
+mod region;
+#[cfg(feature = "dfu")]
+pub use self::region::*;
+
+#[cfg(all(test, feature = "dfu"))]
+mod test_usb;
+
 #[cfg(feature = "application")]
 mod application;
 #[cfg(feature = "application")]
@@ -0,0 +1,79 @@
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
+
+/// Memory read by DFU uploads, or written by DfuSe downloads.
+///
+/// This is implemented for all [`NorFlash`] types, such as partitions, so that memories of
+/// different types can be exposed together.
+pub trait Region {
+    /// Size of the region.
+    fn capacity(&self) -> usize;
+
+    /// Size of an erase page.
+    fn erase_size(&self) -> usize;
+
+    /// Size of a write.
+    fn write_size(&self) -> usize;
+
+    /// Read from the region.
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind>;
+
+    /// Write to the region, in multiples of the write size.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind>;
+
+    /// Erase the pages of the region from `from` to `to`.
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind>;
+}
+
+impl<F: NorFlash> Region for F {
+    fn capacity(&self) -> usize {
+        F::capacity(self)
+    }
+
+    fn erase_size(&self) -> usize {
+        F::ERASE_SIZE
+    }
+
+    fn write_size(&self) -> usize {
+        F::WRITE_SIZE
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        F::read(self, offset, bytes).map_err(|e| e.kind())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        F::write(self, offset, bytes).map_err(|e| e.kind())
+    }
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        F::erase(self, from, to).map_err(|e| e.kind())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_usb::flash;
+
+    #[test]
+    fn can_access_flash() {
+        let mut flash = flash(2048);
+        let region: &mut dyn Region = &mut flash;
+        assert_eq!(2048, region.capacity());
+        assert_eq!(1024, region.erase_size());
+        assert_eq!(4, region.write_size());
+
+        let mut buf = [0; 8];
+        region.write(1028, &[1, 2, 3, 4]).unwrap();
+        region.read(1024, &mut buf).unwrap();
+        assert_eq!([0xFF, 0xFF, 0xFF, 0xFF, 1, 2, 3, 4], buf);
+        region.erase(1024, 2048).unwrap();
+        region.read(1024, &mut buf).unwrap();
+        assert_eq!([0xFF; 8], buf);
+
+        // Errors keep their kind, which DFU reports as a status.
+        assert_eq!(Err(NorFlashErrorKind::NotAligned), region.write(2, &[0; 4]));
+        assert_eq!(Err(NorFlashErrorKind::NotAligned), region.erase(0, 512));
+        assert_eq!(Err(NorFlashErrorKind::OutOfBounds), region.read(2044, &mut buf));
+    }
+}
@@ -0,0 +1,182 @@
+use embassy_embedded_hal::flash::sim_flash::{Config, SimFlash};
+use embassy_usb::control::{InResponse, OutResponse, Recipient, RequestType};
+use embassy_usb::driver::{
+    Bus, ControlPipe, Direction, Driver, Endpoint, EndpointAddress, EndpointAllocError, EndpointError, EndpointIn,
+    EndpointInfo, EndpointOut, EndpointType, Event, Unsupported,
+};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::Handler;
+
+use crate::consts::Request;
+use crate::Reset;
+
+pub(crate) type Flash = SimFlash<4, 1024>;
+
+/// Create an erased flash of `size` bytes.
+pub(crate) fn flash(size: usize) -> Flash {
+    Flash::new(size, Config::default())
+}
+
+/// Reset which panics, so that tests can check when the handlers reset.
+pub(crate) struct TestReset;
+
+impl Reset for TestReset {
+    fn sys_reset() -> ! {
+        panic!("reset")
+    }
+}
+
+/// Driver without endpoints, enough to build descriptors.
+pub(crate) struct NoDriver;
+
+pub(crate) enum NoEndpoint {}
+
+impl<'d> Driver<'d> for NoDriver {
+    type EndpointOut = NoEndpoint;
+    type EndpointIn = NoEndpoint;
+    type ControlPipe = NoEndpoint;
+    type Bus = NoEndpoint;
+
+    fn alloc_endpoint_out(&mut self, _: EndpointType, _: u16, _: u8) -> Result<NoEndpoint, EndpointAllocError> {
+        Err(EndpointAllocError)
+    }
+
+    fn alloc_endpoint_in(&mut self, _: EndpointType, _: u16, _: u8) -> Result<NoEndpoint, EndpointAllocError> {
+        Err(EndpointAllocError)
+    }
+
+    fn start(self, _: u16) -> (NoEndpoint, NoEndpoint) {
+        unreachable!()
+    }
+}
+
+impl Endpoint for NoEndpoint {
+    fn info(&self) -> &EndpointInfo {
+        match *self {}
+    }
+
+    async fn wait_enabled(&mut self) {
+        match *self {}
+    }
+}
+
+impl EndpointOut for NoEndpoint {
+    async fn read(&mut self, _: &mut [u8]) -> Result<usize, EndpointError> {
+        match *self {}
+    }
+}
+
+impl EndpointIn for NoEndpoint {
+    async fn write(&mut self, _: &[u8]) -> Result<(), EndpointError> {
+        match *self {}
+    }
+}
+
+impl ControlPipe for NoEndpoint {
+    fn max_packet_size(&self) -> usize {
+        match *self {}
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        match *self {}
+    }
+
+    async fn data_out(&mut self, _: &mut [u8], _: bool, _: bool) -> Result<usize, EndpointError> {
+        match *self {}
+    }
+
+    async fn data_in(&mut self, _: &[u8], _: bool, _: bool) -> Result<(), EndpointError> {
+        match *self {}
+    }
+
+    async fn accept(&mut self) {
+        match *self {}
+    }
+
+    async fn reject(&mut self) {
+        match *self {}
+    }
+
+    async fn accept_set_address(&mut self, _: u8) {
+        match *self {}
+    }
+}
+
+impl Bus for NoEndpoint {
+    async fn enable(&mut self) {
+        match *self {}
+    }
+
+    async fn disable(&mut self) {
+        match *self {}
+    }
+
+    async fn poll(&mut self) -> Event {
+        match *self {}
+    }
+
+    fn endpoint_set_enabled(&mut self, _: EndpointAddress, _: bool) {
+        match *self {}
+    }
+
+    fn endpoint_set_stalled(&mut self, _: EndpointAddress, _: bool) {
+        match *self {}
+    }
+
+    fn endpoint_is_stalled(&mut self, _: EndpointAddress) -> bool {
+        match *self {}
+    }
+
+    async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+        match *self {}
+    }
+}
+
+/// Interface number the handlers are registered on in tests.
+pub(crate) const IFACE: InterfaceNumber = InterfaceNumber(0);
+
+fn request(direction: Direction, request: Request, value: u16, length: u16) -> embassy_usb::control::Request {
+    embassy_usb::control::Request {
+        direction,
+        request_type: RequestType::Class,
+        recipient: Recipient::Interface,
+        request: request as u8,
+        value,
+        index: IFACE.0 as u16,
+        length,
+    }
+}
+
+/// Send a DFU request with `data`.
+pub(crate) fn out(handler: &mut impl Handler, req: Request, value: u16, data: &[u8]) -> Option<OutResponse> {
+    handler.control_out(request(Direction::Out, req, value, data.len() as u16), data)
+}
+
+/// Send a DFU request reading up to the length of `buf`.
+pub(crate) fn control_in<'a>(
+    handler: &'a mut impl Handler,
+    req: Request,
+    value: u16,
+    buf: &'a mut [u8],
+) -> Option<InResponse<'a>> {
+    let length = buf.len() as u16;
+    handler.control_in(request(Direction::In, req, value, length), buf)
+}
+
+/// Status and state reported by DFU_GETSTATUS.
+pub(crate) fn get_status(handler: &mut impl Handler) -> (u8, u8) {
+    let mut buf = [0; 6];
+    match control_in(handler, Request::GetStatus, 0, &mut buf) {
+        Some(InResponse::Accepted(status)) => (status[0], status[4]),
+        response => panic!("DFU_GETSTATUS not accepted: {:?}", response),
+    }
+}
+
+/// State reported by DFU_GETSTATE, which unlike DFU_GETSTATUS doesn't advance the state.
+pub(crate) fn get_state(handler: &mut impl Handler) -> u8 {
+    let mut buf = [0; 1];
+    match control_in(handler, Request::GetState, 0, &mut buf) {
+        Some(InResponse::Accepted(state)) => state[0],
+        response => panic!("DFU_GETSTATE not accepted: {:?}", response),
+    }
+}
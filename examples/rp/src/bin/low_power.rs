@@ -0,0 +1,37 @@
+//! This example shows how to use the low-power executor.
+//!
+//! No peripheral is active and the next timer is a second away, so the chip is in the sleep state
+//! between LED toggles, with `PLL_SYS` powered down.
+
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::low_power::{self, Executor, SleepMode};
+use embassy_time::Timer;
+use {defmt_rtt as _, panic_probe as _};
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    Executor::take().run(|spawner| {
+        unwrap!(spawner.spawn(async_main(spawner)));
+    })
+}
+
+#[embassy_executor::task]
+async fn async_main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+    let mut led = Output::new(p.PIN_25, Level::Low);
+
+    loop {
+        info!("led on! sleep ready: {}", low_power::sleep_ready(SleepMode::Sleep));
+        led.set_high();
+        Timer::after_secs(1).await;
+
+        info!("led off!");
+        led.set_low();
+        Timer::after_secs(1).await;
+    }
+}
@@ -0,0 +1,42 @@
+//! This example shows how to use the RP2040 as an SPI slave.
+//! The host reads back the bytes it wrote in the previous transaction, so connect it to pins 8 (MISO),
+//! 9 (CS), 10 (CLK) and 11 (MOSI).
+
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::spi_slave::{Config, SpiSlave};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+    info!("Hello World!");
+
+    let mut spi = SpiSlave::new(
+        p.SPI1,
+        p.PIN_10,
+        p.PIN_11,
+        p.PIN_8,
+        p.PIN_9,
+        p.DMA_CH0,
+        p.DMA_CH1,
+        Config::default(),
+    );
+
+    let mut tx_buf = [0_u8; 64];
+    let mut tx_len = 0;
+    loop {
+        let mut rx_buf = [0_u8; 64];
+        let (n_rx, n_tx) = spi.transfer(&mut rx_buf, &tx_buf[..tx_len]).await.unwrap();
+        info!("received {:x}, sent {} bytes", rx_buf[..n_rx], n_tx);
+        if spi.is_overflow() {
+            warn!("host sent more than {} bytes", rx_buf.len());
+        }
+
+        tx_buf[..n_rx].copy_from_slice(&rx_buf[..n_rx]);
+        tx_len = n_rx;
+    }
+}
@@ -0,0 +1,76 @@
+//! Echoes CAN frames received on a SocketCAN interface, with the ID incremented.
+//!
+//! Set up a virtual interface with:
+//!
+//! ```sh
+//! sudo ip link add dev vcan0 type vcan
+//! sudo ip link set up vcan0
+//! ```
+//!
+//! then send frames with `cansend vcan0 123#DEADBEEF` and watch them with `candump vcan0`.
+use clap::Parser;
+use embassy_can_std::driver::embedded_can::{Frame as _, Id, StandardId};
+use embassy_can_std::driver::{Can, Filter};
+use embassy_can_std::SocketCan;
+use embassy_executor::{Executor, Spawner};
+use embassy_time::{with_timeout, Duration};
+use log::*;
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// SocketCAN interface name
+    #[clap(short, long, default_value = "vcan0")]
+    interface: String,
+}
+
+/// Application code, independent of the CAN implementation.
+async fn echo<C: Can>(mut can: C) -> ! {
+    // Only echo standard frames, so the echoes aren't echoed again.
+    can.set_filters(&[Filter::new_standard(StandardId::ZERO, 0x400)])
+        .unwrap();
+    loop {
+        let frame = match with_timeout(Duration::from_secs(10), can.receive()).await {
+            Ok(Ok(frame)) => frame,
+            Err(_) => {
+                info!("no frames received, bus state {:?}", can.bus_state());
+                continue;
+            }
+            Ok(Err(e)) => {
+                warn!("rx error: {:?}", e);
+                continue;
+            }
+        };
+        info!("rx: {:?} {:02x?}", frame.id(), frame.data());
+
+        let Id::Standard(id) = frame.id() else { continue };
+        let id = StandardId::new(id.as_raw() + 0x400).unwrap();
+        let echo = C::Frame::new(id, frame.data()).unwrap();
+        if let Err(e) = can.transmit(&echo).await {
+            warn!("tx error: {:?}", e);
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn main_task(_spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+    let can = SocketCan::open(&opts.interface).unwrap();
+    echo(can).await
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
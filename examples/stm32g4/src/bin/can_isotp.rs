@@ -0,0 +1,80 @@
+//! ISO-TP echo server over CAN FD.
+//!
+//! Messages received on ID 0x7E0 are sent back on ID 0x7E8.
+#![no_std]
+#![no_main]
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::can::frame::FdFrame;
+use embassy_stm32::can::isotp::{self, IsoTp, StMin};
+use embassy_stm32::peripherals::*;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{bind_interrupts, can, Config};
+use embedded_can::StandardId;
+use {defmt_rtt as _, panic_probe as _};
+
+bind_interrupts!(struct Irqs {
+    FDCAN1_IT0 => can::IT0InterruptHandler<FDCAN1>;
+    FDCAN1_IT1 => can::IT1InterruptHandler<FDCAN1>;
+});
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut config = Config::default();
+    {
+        use embassy_stm32::rcc::*;
+        config.rcc.hse = Some(Hse {
+            freq: Hertz(24_000_000),
+            mode: HseMode::Oscillator,
+        });
+        config.rcc.pll = Some(Pll {
+            source: PllSource::HSE,
+            prediv: PllPreDiv::DIV6,
+            mul: PllMul::MUL85,
+            divp: None,
+            divq: Some(PllQDiv::DIV8), // 42.5 Mhz for fdcan.
+            divr: Some(PllRDiv::DIV2), // Main system clock at 170 MHz
+        });
+        config.rcc.mux.fdcansel = mux::Fdcansel::PLL1_Q;
+        config.rcc.sys = Sysclk::PLL1_R;
+    }
+    let peripherals = embassy_stm32::init(config);
+
+    let mut can = can::CanConfigurator::new(peripherals.FDCAN1, peripherals.PA11, peripherals.PA12, Irqs);
+
+    can.properties().set_standard_filter(
+        can::filter::StandardFilterSlot::_0,
+        can::filter::StandardFilter::accept_all_into_fifo0(),
+    );
+
+    // 500k bps, 2M bps data phase
+    can.set_bitrate(500_000);
+    can.set_fd_data_bitrate(2_000_000, false);
+
+    let can = can.start(can::OperatingMode::NormalOperationMode);
+
+    let mut config = isotp::Config::new(StandardId::new(0x7E8).unwrap(), StandardId::new(0x7E0).unwrap());
+    config.tx_dl = 64;
+    config.bit_rate_switching = true;
+    config.block_size = 8;
+    config.st_min = StMin::from_hundred_micros(5);
+    let mut isotp: IsoTp<_, FdFrame> = IsoTp::new(can, config);
+
+    info!("Configured");
+
+    let mut buf = [0u8; 4095];
+    loop {
+        let len = match isotp.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("rx error: {}", e);
+                continue;
+            }
+        };
+        info!("received {} bytes", len);
+
+        if let Err(e) = isotp.send(&buf[..len]).await {
+            warn!("tx error: {}", e);
+        }
+    }
+}
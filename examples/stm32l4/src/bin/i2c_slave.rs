@@ -0,0 +1,56 @@
+//! This example shows how to use the STM32 as an i2c slave.
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::i2c::slave::{Command, Config, I2cSlave, ReadStatus};
+use embassy_stm32::{bind_interrupts, i2c, peripherals};
+use {defmt_rtt as _, panic_probe as _};
+
+const DEV_ADDR: u8 = 0x42;
+
+bind_interrupts!(struct Irqs {
+    I2C2_EV => i2c::EventInterruptHandler<peripherals::I2C2>;
+    I2C2_ER => i2c::ErrorInterruptHandler<peripherals::I2C2>;
+});
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(Default::default());
+    info!("Hello World!");
+
+    let mut config = Config::default();
+    config.addr = DEV_ADDR.into();
+    let mut dev = I2cSlave::new(p.I2C2, p.PB10, p.PB11, Irqs, p.DMA1_CH4, p.DMA1_CH5, config);
+
+    let mut state = 0;
+
+    loop {
+        let mut buf = [0u8; 128];
+        match dev.listen(&mut buf).await {
+            Ok(Command::GeneralCall(len)) => info!("Device received general call write: {}", buf[..len]),
+            Ok(Command::Read) => match dev.respond_and_fill(&[state], 0x00).await {
+                Ok(read_status) => info!("response read status {}", read_status),
+                Err(e) => error!("error while responding {}", e),
+            },
+            Ok(Command::Write(len)) => info!("Device received write: {}", buf[..len]),
+            Ok(Command::WriteRead(len)) => {
+                info!("device received write read: {:x}", buf[..len]);
+                match buf[0] {
+                    // Set the state
+                    0xC2 => state = buf[1],
+                    // Reset State
+                    0xC8 => state = 0,
+                    x => error!("Invalid Write Read {:x}", x),
+                }
+                match dev.respond_and_fill(&[state], 0x00).await {
+                    Ok(ReadStatus::LeftoverBytes(x)) => info!("tried to write {} extra bytes", x),
+                    Ok(read_status) => info!("response read status {}", read_status),
+                    Err(e) => error!("error while responding {}", e),
+                }
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! This example shows how to use the STM32 as an SPI slave.
+//! The host reads back the bytes it wrote in the previous transaction.
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::spi::slave::{Config, SpiSlave};
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(Default::default());
+    info!("Hello World!");
+
+    let mut spi = SpiSlave::new(
+        p.SPI1,
+        p.PA5,
+        p.PA7,
+        p.PA6,
+        p.PA4,
+        p.EXTI4,
+        p.DMA1_CH3,
+        p.DMA1_CH2,
+        Config::default(),
+    );
+
+    let mut tx_buf = [0_u8; 64];
+    let mut tx_len = 0;
+    loop {
+        let mut rx_buf = [0_u8; 64];
+        let (n_rx, n_tx) = unwrap!(spi.transfer(&mut rx_buf, &tx_buf[..tx_len]).await);
+        info!("received {:x}, sent {} bytes", rx_buf[..n_rx], n_tx);
+        if spi.is_overflow() {
+            warn!("host sent more than {} bytes", rx_buf.len());
+        }
+
+        tx_buf[..n_rx].copy_from_slice(&rx_buf[..n_rx]);
+        tx_len = n_rx;
+    }
+}
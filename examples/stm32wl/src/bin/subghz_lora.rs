@@ -0,0 +1,133 @@
+//! This example sends a LoRa packet every few seconds, and listens for packets in between.
+//!
+//! It's written for the NUCLEO-WL55JC board, adapt the RF switch and the frequency to your board
+//! and region.
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::subghz::{
+    self, CodingRate, Error, LoRaBandwidth, LoRaConfig, LoRaHeader, LoRaModParams, LoRaPacketParams, PaConfig, PaSel,
+    RampTime, RegulatorMode, RfSwitch, SpreadingFactor, SubGhz, TcxoVoltage, Timeout, TxParams,
+};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{bind_interrupts, Config};
+use {defmt_rtt as _, panic_probe as _};
+
+bind_interrupts!(struct Irqs{
+    SUBGHZ_RADIO => subghz::InterruptHandler;
+});
+
+/// RF switch of the NUCLEO-WL55JC.
+struct NucleoRfSwitch<'d> {
+    ctrl1: Output<'d>,
+    ctrl2: Output<'d>,
+    ctrl3: Output<'d>,
+}
+
+impl<'d> NucleoRfSwitch<'d> {
+    fn set(&mut self, ctrl1: bool, ctrl2: bool, ctrl3: bool) {
+        self.ctrl1.set_level(ctrl1.into());
+        self.ctrl2.set_level(ctrl2.into());
+        self.ctrl3.set_level(ctrl3.into());
+    }
+}
+
+impl<'d> RfSwitch for NucleoRfSwitch<'d> {
+    fn set_off(&mut self) {
+        self.set(false, false, false);
+    }
+
+    fn set_rx(&mut self) {
+        self.set(true, false, true);
+    }
+
+    fn set_tx(&mut self, pa: PaSel) {
+        match pa {
+            PaSel::Lp => self.set(true, true, true),
+            PaSel::Hp => self.set(false, true, true),
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let mut config = Config::default();
+    {
+        use embassy_stm32::rcc::*;
+        // The radio is clocked by a TCXO on this board.
+        config.rcc.hse = Some(Hse {
+            freq: Hertz(32_000_000),
+            mode: HseMode::Bypass,
+            prescaler: HsePrescaler::DIV1,
+        });
+        config.rcc.sys = Sysclk::PLL1_R;
+        config.rcc.pll = Some(Pll {
+            source: PllSource::HSE,
+            prediv: PllPreDiv::DIV2,
+            mul: PllMul::MUL6,
+            divp: None,
+            divq: None,
+            divr: Some(PllRDiv::DIV2), // sysclk 48Mhz clock (32 / 2 * 6 / 2)
+        });
+    }
+    let p = embassy_stm32::init(config);
+
+    info!("Hello World!");
+
+    let rf_switch = NucleoRfSwitch {
+        ctrl1: Output::new(p.PC4, Level::Low, Speed::Low),
+        ctrl2: Output::new(p.PC5, Level::Low, Speed::Low),
+        ctrl3: Output::new(p.PC3, Level::Low, Speed::Low),
+    };
+
+    let mut radio_config = subghz::Config::default();
+    radio_config.regulator = RegulatorMode::Smps;
+    radio_config.tcxo = Some(TcxoVoltage::V1_7);
+    let mut radio = unwrap!(SubGhz::new(
+        p.SUBGHZSPI,
+        Irqs,
+        p.DMA1_CH1,
+        p.DMA1_CH2,
+        rf_switch,
+        radio_config
+    ));
+
+    unwrap!(
+        radio
+            .configure_lora(&LoRaConfig {
+                frequency: 868_100_000,
+                modulation: LoRaModParams::new(SpreadingFactor::Sf7, LoRaBandwidth::Khz125, CodingRate::Cr45),
+                packet: LoRaPacketParams {
+                    preamble_len: 8,
+                    header: LoRaHeader::Explicit,
+                    payload_len: 0,
+                    crc: true,
+                    invert_iq: false,
+                },
+                sync_word: 0x1424,
+                pa: PaConfig::HP_14,
+                tx: TxParams {
+                    power: 22,
+                    ramp_time: RampTime::Micros40,
+                },
+            })
+            .await
+    );
+
+    let mut counter: u32 = 0;
+    loop {
+        unwrap!(radio.tx(&counter.to_be_bytes(), Timeout::from_millis_sat(1000)).await);
+        info!("sent packet {}", counter);
+        counter = counter.wrapping_add(1);
+
+        let mut buf = [0u8; 255];
+        match radio.rx(&mut buf, Timeout::from_millis_sat(5000)).await {
+            Ok((len, status)) => info!("received {:02x}, {}", buf[..len], status),
+            Err(Error::Timeout) => info!("no packet received"),
+            Err(e) => warn!("rx error: {}", e),
+        }
+    }
+}